@@ -1,21 +1,122 @@
+//! A C API for sled.
+//!
+//! Ownership follows a few rules:
+//!
+//! * Every object returned as a pointer (`Config`, `Db`, `Tree`,
+//!   `Iter`, `Batch`) is owned by the caller, and must be freed
+//!   with its own `sled_free_*` function, or passed to a
+//!   function that is documented to consume it.
+//! * Buffers returned for keys and values are owned by the
+//!   caller, and must be freed with `sled_free_buf`, passing the
+//!   length that was returned with them.
+//! * Keys, values, names and paths passed in are only borrowed
+//!   for the duration of the call, and are copied if they are
+//!   kept, so the caller remains responsible for them.
+//! * The message returned by `sled_last_error_message` is owned
+//!   by sled.
+//!
+//! A `Tree` is cheap to get, and may be used from several
+//! threads at once, as long as it is freed only once.
+
 extern crate libc;
 extern crate sled;
 
-use std::ffi::CString;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
 use std::mem;
+use std::ops::Bound;
 use std::ptr;
 use std::slice;
 
 use libc::*;
 
-use sled::{Config, Db, Iter, Tree};
+use sled::{Batch, Config, Db, Error, Iter, Tree};
+
+/// The kind of error returned by the last failed call on
+/// the current thread. The values of these codes never
+/// change between versions, and new kinds of errors are
+/// only ever given new codes.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The last call succeeded.
+    Ok = 0,
+    /// The underlying collection no longer exists.
+    CollectionNotFound = 1,
+    /// The system has been used in an unsupported way.
+    Unsupported = 2,
+    /// An unexpected bug has happened.
+    ReportableBug = 3,
+    /// A read or write error has happened when interacting
+    /// with the file system.
+    Io = 4,
+    /// Corruption has been detected in the storage file.
+    Corruption = 5,
+    /// A write was attempted on a key that already exists
+    /// in a write-once tree.
+    ReadOnlyKey = 6,
+    /// An error that has no code of its own.
+    Other = 255,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<(ErrorCode, CString)>> =
+        RefCell::new(None);
+}
+
+fn clear_last_error() {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+}
+
+fn set_last_error(error: Error) -> ErrorCode {
+    #[allow(unreachable_patterns)]
+    let code = match error {
+        Error::CollectionNotFound(_) => ErrorCode::CollectionNotFound,
+        Error::Unsupported(_) => ErrorCode::Unsupported,
+        Error::ReportableBug(_) => ErrorCode::ReportableBug,
+        Error::Io(_) => ErrorCode::Io,
+        Error::Corruption { .. } => ErrorCode::Corruption,
+        Error::ReadOnlyKey(_) => ErrorCode::ReadOnlyKey,
+        _ => ErrorCode::Other,
+    };
+
+    // messages may contain the bytes of keys or tree names
+    let message = error.to_string().replace('\0', "\\0");
+    let message = CString::new(message).unwrap();
+
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some((code, message)));
+
+    code
+}
+
+/// Get the code of the error returned by the last failed
+/// call on this thread, or `Ok` if the last call succeeded.
+#[no_mangle]
+pub extern "C" fn sled_last_error_code() -> ErrorCode {
+    LAST_ERROR.with(|last| match *last.borrow() {
+        Some((code, _)) => code,
+        None => ErrorCode::Ok,
+    })
+}
+
+/// Get a description of the error returned by the last
+/// failed call on this thread, or null if the last call
+/// succeeded. The string is owned by sled, and is only
+/// valid until the next call into sled on this thread.
+#[no_mangle]
+pub extern "C" fn sled_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| match *last.borrow() {
+        Some((_, ref message)) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
 
 fn leak_buf(v: Vec<u8>, vallen: *mut size_t) -> *mut c_char {
     unsafe {
         *vallen = v.len();
     }
     let mut bsv = v.into_boxed_slice();
-    let val = bsv.as_mut_ptr() as *mut i8;
+    let val = bsv.as_mut_ptr() as *mut c_char;
     mem::forget(bsv);
     val
 }
@@ -35,15 +136,23 @@ pub unsafe extern "C" fn sled_free_config(config: *mut Config) {
 
 /// Set the configured file path. The caller is responsible for freeing the path
 /// string after calling this (it is copied in this function).
+/// Returns `Ok`, or `Unsupported` if the path is not valid UTF-8.
 #[no_mangle]
 pub unsafe extern "C" fn sled_config_set_path(
     config: *mut Config,
     path: *const c_char,
-) {
-    let c_str = CString::from_raw(path as *mut i8);
-    let value = c_str.into_string().unwrap();
-
-    *config = (*config).clone().path(value);
+) -> ErrorCode {
+    clear_last_error();
+    match CStr::from_ptr(path).to_str() {
+        Ok(value) => {
+            *config = (*config).clone().path(value);
+            ErrorCode::Ok
+        }
+        Err(_) => set_last_error(Error::Unsupported(
+            "the path passed to sled_config_set_path is not valid UTF-8"
+                .to_string(),
+        )),
+    }
 }
 
 /// Configure read-only mode.
@@ -84,20 +193,93 @@ pub unsafe extern "C" fn sled_config_flush_every_ms(
 }
 
 /// Open a sled lock-free log-structured tree. Consumes the passed-in config.
+/// Returns null on failure, which is described by `sled_last_error_code`
+/// and `sled_last_error_message`. Keys and values are read and written
+/// through a `Tree` from `sled_db_default_tree` or `sled_open_tree`.
 #[no_mangle]
 pub unsafe extern "C" fn sled_open_db(config: *mut Config) -> *mut Db {
+    clear_last_error();
     let conf_2 = (*config).clone();
     let conf_3 = conf_2;
     sled_free_config(config);
-    Box::into_raw(Box::new(conf_3.open().unwrap()))
+    match conf_3.open() {
+        Ok(db) => Box::into_raw(Box::new(db)),
+        Err(e) => {
+            set_last_error(e);
+            ptr::null_mut()
+        }
+    }
 }
 
-/// Close a sled lock-free log-structured tree.
+/// Close a sled lock-free log-structured tree. Trees that were
+/// opened from it stay usable until they are freed.
 #[no_mangle]
 pub unsafe extern "C" fn sled_close(db: *mut Db) {
     drop(Box::from_raw(db));
 }
 
+/// Get the default tree of a database.
+/// Caller is responsible for freeing the returned tree with `sled_free_tree`.
+#[no_mangle]
+pub unsafe extern "C" fn sled_db_default_tree(db: *mut Db) -> *mut Tree {
+    let tree: &Tree = &*db;
+    Box::into_raw(Box::new(tree.clone()))
+}
+
+/// Open or create the tree with the given name.
+/// Caller is responsible for freeing the returned tree with `sled_free_tree`.
+/// Returns null on failure, in which case `sled_last_error_code` returns
+/// something other than `Ok`.
+#[no_mangle]
+pub unsafe extern "C" fn sled_open_tree(
+    db: *mut Db,
+    name: *const c_uchar,
+    namelen: size_t,
+) -> *mut Tree {
+    clear_last_error();
+    let name = slice::from_raw_parts(name, namelen);
+    match (*db).open_tree(name) {
+        Ok(tree) => Box::into_raw(Box::new(tree)),
+        Err(e) => {
+            set_last_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Remove the tree with the given name and all of its contents.
+/// Trees that were already opened with this name must still be freed.
+/// Returns 1 if it existed and 0 if it didn't.
+/// Also returns 0 on failure, in which case `sled_last_error_code` returns
+/// something other than `Ok`.
+#[no_mangle]
+pub unsafe extern "C" fn sled_drop_tree(
+    db: *mut Db,
+    name: *const c_uchar,
+    namelen: size_t,
+) -> c_uchar {
+    clear_last_error();
+    let name = slice::from_raw_parts(name, namelen);
+    match (*db).drop_tree(name) {
+        Ok(existed) => existed as c_uchar,
+        Err(e) => {
+            set_last_error(e);
+            0
+        }
+    }
+}
+
+/// Make all previous writes to the database durable.
+/// Returns `Ok`, or the code of the error that occurred.
+#[no_mangle]
+pub unsafe extern "C" fn sled_flush(tree: *mut Tree) -> ErrorCode {
+    clear_last_error();
+    match (*tree).flush() {
+        Ok(_) => ErrorCode::Ok,
+        Err(e) => set_last_error(e),
+    }
+}
+
 /// Free a buffer originally allocated by sled.
 #[no_mangle]
 pub unsafe extern "C" fn sled_free_buf(buf: *mut c_char, sz: size_t) {
@@ -117,6 +299,7 @@ pub unsafe extern "C" fn sled_free_iter(iter: *mut Iter) {
 }
 
 /// Set a key to a value.
+/// Returns `Ok`, or the code of the error that occurred.
 #[no_mangle]
 pub unsafe extern "C" fn sled_set(
     db: *mut Tree,
@@ -124,15 +307,21 @@ pub unsafe extern "C" fn sled_set(
     keylen: size_t,
     val: *const c_uchar,
     vallen: size_t,
-) {
+) -> ErrorCode {
+    clear_last_error();
     let k = slice::from_raw_parts(key, keylen).to_vec();
     let v = slice::from_raw_parts(val, vallen).to_vec();
-    (*db).insert(k.clone(), v.clone()).unwrap();
+    match (*db).insert(k, v) {
+        Ok(_) => ErrorCode::Ok,
+        Err(e) => set_last_error(e),
+    }
 }
 
 /// Get the value of a key.
 /// Caller is responsible for freeing the returned value with `sled_free_buf` if
 /// it's non-null.
+/// Returns null if the key is not set, or on failure, in which case
+/// `sled_last_error_code` returns something other than `Ok`.
 #[no_mangle]
 pub unsafe extern "C" fn sled_get(
     db: *mut Tree,
@@ -140,25 +329,33 @@ pub unsafe extern "C" fn sled_get(
     keylen: size_t,
     vallen: *mut size_t,
 ) -> *mut c_char {
+    clear_last_error();
     let k = slice::from_raw_parts(key as *const u8, keylen);
     let res = (*db).get(k);
     match res {
         Ok(Some(v)) => leak_buf(v.to_vec(), vallen),
         Ok(None) => ptr::null_mut(),
-        // TODO proper error propagation
-        Err(e) => panic!("{:?}", e),
+        Err(e) => {
+            set_last_error(e);
+            ptr::null_mut()
+        }
     }
 }
 
 /// Delete the value of a key.
+/// Returns `Ok`, or the code of the error that occurred.
 #[no_mangle]
 pub unsafe extern "C" fn sled_del(
     db: *mut Tree,
     key: *const c_char,
     keylen: size_t,
-) {
+) -> ErrorCode {
+    clear_last_error();
     let k = slice::from_raw_parts(key as *const u8, keylen);
-    (*db).remove(k).unwrap();
+    match (*db).remove(k) {
+        Ok(_) => ErrorCode::Ok,
+        Err(e) => set_last_error(e),
+    }
 }
 
 /// Compare and swap.
@@ -167,6 +364,8 @@ pub unsafe extern "C" fn sled_del(
 /// which must be freed using `sled_free_buf` by the caller if non-null.
 /// `actual_val` will be null and `actual_vallen` 0 if the current value is not
 /// set.
+/// Also returns 0 on failure, in which case `sled_last_error_code` returns
+/// something other than `Ok`.
 #[no_mangle]
 pub unsafe extern "C" fn sled_compare_and_swap(
     db: *mut Tree,
@@ -179,6 +378,7 @@ pub unsafe extern "C" fn sled_compare_and_swap(
     actual_val: *mut *const c_uchar,
     actual_vallen: *mut size_t,
 ) -> c_uchar {
+    clear_last_error();
     let k = slice::from_raw_parts(key as *const u8, keylen).to_vec();
 
     let old = if old_vallen == 0 {
@@ -212,8 +412,10 @@ pub unsafe extern "C" fn sled_compare_and_swap(
             *actual_val = leak_buf(v.to_vec(), actual_vallen) as *const u8;
             0
         }
-        // TODO proper error propagation
-        Err(e) => panic!("{:?}", e),
+        Err(e) => {
+            set_last_error(e);
+            0
+        }
     }
 }
 
@@ -230,9 +432,43 @@ pub unsafe extern "C" fn sled_scan_prefix(
     Box::into_raw(Box::new((*db).scan_prefix(k)))
 }
 
+/// Iterate over all tuples.
+/// Caller is responsible for freeing the returned iterator with
+/// `sled_free_iter`.
+#[no_mangle]
+pub unsafe extern "C" fn sled_iter(db: *mut Tree) -> *mut Iter {
+    Box::into_raw(Box::new((*db).iter()))
+}
+
+/// Iterate over the tuples with keys from `start`, inclusive, to `end`,
+/// exclusive. A null `start` or `end` leaves that side of the range open.
+/// Caller is responsible for freeing the returned iterator with
+/// `sled_free_iter`.
+#[no_mangle]
+pub unsafe extern "C" fn sled_range(
+    db: *mut Tree,
+    start: *const c_uchar,
+    startlen: size_t,
+    end: *const c_uchar,
+    endlen: size_t,
+) -> *mut Iter {
+    let lo = if start.is_null() {
+        Bound::Unbounded
+    } else {
+        Bound::Included(slice::from_raw_parts(start, startlen))
+    };
+    let hi = if end.is_null() {
+        Bound::Unbounded
+    } else {
+        Bound::Excluded(slice::from_raw_parts(end, endlen))
+    };
+    Box::into_raw(Box::new((*db).range::<&[u8], _>((lo, hi))))
+}
+
 /// Get they next kv pair from an iterator.
 /// Caller is responsible for freeing the key and value with `sled_free_buf`.
-/// Returns 0 when exhausted.
+/// Returns 0 when exhausted, or on failure, in which case
+/// `sled_last_error_code` returns something other than `Ok`.
 #[no_mangle]
 pub unsafe extern "C" fn sled_iter_next(
     iter: *mut Iter,
@@ -241,14 +477,173 @@ pub unsafe extern "C" fn sled_iter_next(
     val: *mut *const c_char,
     vallen: *mut size_t,
 ) -> c_uchar {
+    clear_last_error();
     match (*iter).next() {
         Some(Ok((k, v))) => {
             *key = leak_buf(k.to_vec(), keylen);
             *val = leak_buf(v.to_vec(), vallen);
             1
         }
-        // TODO proper error propagation
-        Some(Err(e)) => panic!("{:?}", e),
+        Some(Err(e)) => {
+            set_last_error(e);
+            0
+        }
         None => 0,
     }
 }
+
+/// Get the previous kv pair from the end of an iterator, in the same way
+/// as `sled_iter_next`.
+#[no_mangle]
+pub unsafe extern "C" fn sled_iter_next_back(
+    iter: *mut Iter,
+    key: *mut *const c_char,
+    keylen: *mut size_t,
+    val: *mut *const c_char,
+    vallen: *mut size_t,
+) -> c_uchar {
+    clear_last_error();
+    match (*iter).next_back() {
+        Some(Ok((k, v))) => {
+            *key = leak_buf(k.to_vec(), keylen);
+            *val = leak_buf(v.to_vec(), vallen);
+            1
+        }
+        Some(Err(e)) => {
+            set_last_error(e);
+            0
+        }
+        None => 0,
+    }
+}
+
+/// Create a new batch of writes.
+#[no_mangle]
+pub unsafe extern "C" fn sled_create_batch() -> *mut Batch {
+    Box::into_raw(Box::new(Batch::default()))
+}
+
+/// Free a batch without applying it.
+#[no_mangle]
+pub unsafe extern "C" fn sled_free_batch(batch: *mut Batch) {
+    drop(Box::from_raw(batch));
+}
+
+/// Add setting a key to a value to a batch.
+#[no_mangle]
+pub unsafe extern "C" fn sled_batch_set(
+    batch: *mut Batch,
+    key: *const c_uchar,
+    keylen: size_t,
+    val: *const c_uchar,
+    vallen: size_t,
+) {
+    let k = slice::from_raw_parts(key, keylen).to_vec();
+    let v = slice::from_raw_parts(val, vallen).to_vec();
+    (*batch).insert(k, v);
+}
+
+/// Add deleting a key to a batch.
+#[no_mangle]
+pub unsafe extern "C" fn sled_batch_del(
+    batch: *mut Batch,
+    key: *const c_uchar,
+    keylen: size_t,
+) {
+    let k = slice::from_raw_parts(key, keylen).to_vec();
+    (*batch).remove(k);
+}
+
+/// Atomically apply all of the writes in a batch. Consumes the passed-in
+/// batch, whether or not it is applied.
+/// Returns `Ok`, or the code of the error that occurred.
+#[no_mangle]
+pub unsafe extern "C" fn sled_apply_batch(
+    db: *mut Tree,
+    batch: *mut Batch,
+) -> ErrorCode {
+    clear_last_error();
+    let batch = *Box::from_raw(batch);
+    match (*db).apply_batch(batch) {
+        Ok(()) => ErrorCode::Ok,
+        Err(e) => set_last_error(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe fn next(iter: *mut Iter, back: bool) -> Option<(Vec<u8>, Vec<u8>)> {
+        let mut key = ptr::null();
+        let mut keylen = 0;
+        let mut val = ptr::null();
+        let mut vallen = 0;
+        let found = if back {
+            sled_iter_next_back(
+                iter,
+                &mut key,
+                &mut keylen,
+                &mut val,
+                &mut vallen,
+            )
+        } else {
+            sled_iter_next(iter, &mut key, &mut keylen, &mut val, &mut vallen)
+        };
+        if found == 0 {
+            assert_eq!(sled_last_error_code(), ErrorCode::Ok);
+            return None;
+        }
+        let k = slice::from_raw_parts(key as *const u8, keylen).to_vec();
+        let v = slice::from_raw_parts(val as *const u8, vallen).to_vec();
+        sled_free_buf(key as *mut c_char, keylen);
+        sled_free_buf(val as *mut c_char, vallen);
+        Some((k, v))
+    }
+
+    #[test]
+    fn round_trip() {
+        unsafe {
+            let config = Box::into_raw(Box::new(Config::new().temporary(true)));
+            let db = sled_open_db(config);
+            assert!(!db.is_null());
+
+            let tree = sled_open_tree(db, b"t".as_ptr(), 1);
+            let default = sled_db_default_tree(db);
+
+            let batch = sled_create_batch();
+            for k in &[b"a", b"b", b"c", b"d"] {
+                sled_batch_set(batch, k.as_ptr(), 1, b"v".as_ptr(), 1);
+            }
+            sled_batch_del(batch, b"d".as_ptr(), 1);
+            assert_eq!(sled_apply_batch(tree, batch), ErrorCode::Ok);
+            assert_eq!(sled_flush(tree), ErrorCode::Ok);
+
+            // the default tree is separate from the named one
+            let mut vallen = 0;
+            assert!(
+                sled_get(default, b"a".as_ptr() as _, 1, &mut vallen).is_null()
+            );
+            let val = sled_get(tree, b"a".as_ptr() as _, 1, &mut vallen);
+            assert_eq!(slice::from_raw_parts(val as *const u8, vallen), b"v");
+            sled_free_buf(val, vallen);
+
+            let iter = sled_range(tree, b"b".as_ptr(), 1, ptr::null(), 0);
+            assert_eq!(next(iter, false).unwrap().0, b"b");
+            assert_eq!(next(iter, true).unwrap().0, b"c");
+            assert!(next(iter, false).is_none());
+            sled_free_iter(iter);
+
+            let iter = sled_iter(tree);
+            assert_eq!(next(iter, true).unwrap().0, b"c");
+            sled_free_iter(iter);
+
+            sled_free_tree(default);
+            sled_close(db);
+
+            // trees outlive the database they came from
+            assert_eq!(sled_del(tree, b"a".as_ptr() as _, 1), ErrorCode::Ok);
+            sled_free_tree(tree);
+        }
+    }
+}
@@ -6,7 +6,8 @@ set -e
 targets="wasm32-unknown-unknown aarch64-fuchsia aarch64-linux-android \
          i686-linux-android i686-unknown-linux-gnu \
          x86_64-linux-android x86_64-fuchsia \
-         aarch64-apple-ios"
+         aarch64-apple-ios armv7-unknown-linux-gnueabihf \
+         arm-unknown-linux-gnueabi"
 
 rustup update
 
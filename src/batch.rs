@@ -1,7 +1,5 @@
 #![allow(unused_results)]
 
-use std::collections::HashMap;
-
 use super::*;
 
 /// A batch of updates that will
@@ -32,7 +30,7 @@ use super::*;
 /// ```
 #[derive(Debug, Default, Clone)]
 pub struct Batch {
-    pub(crate) writes: HashMap<IVec, Option<IVec>>,
+    pub(crate) writes: FastMap8<IVec, Option<IVec>>,
 }
 
 impl Batch {
@@ -1,6 +1,9 @@
-use std::cmp::Ordering::{Equal, Greater, Less};
+use std::{
+    cmp::Ordering::{self, Equal, Greater, Less},
+    convert::TryFrom,
+};
 
-use crate::{fastcmp, IVec};
+use crate::{IVec, fastcmp};
 
 pub(crate) fn binary_search_lub<'a>(
     key: &[u8],
@@ -13,11 +16,20 @@ pub(crate) fn binary_search_lub<'a>(
     }
 }
 
+/// Searches the sorted keys of a node for `key`, with the
+/// same result as `binary_search_by` with `fastcmp`. Each
+/// probe compares the first 8 bytes of the keys as
+/// big-endian integers, which order like the bytes do, and
+/// only calls `fastcmp` when those are equal. The keys of a
+/// node have their common prefix truncated, so their heads
+/// usually differ, and the search is branchless apart from
+/// its fixed number of iterations.
 pub fn binary_search<'a>(key: &[u8], s: &'a [IVec]) -> Result<usize, usize> {
     let mut size = s.len();
-    if size == 0 || *key < *s[0] {
+    if size == 0 {
         return Err(0);
     }
+    let key_head = head(key);
     let mut base = 0_usize;
     while size > 1 {
         let half = size / 2;
@@ -27,18 +39,37 @@ pub fn binary_search<'a>(key: &[u8], s: &'a [IVec]) -> Result<usize, usize> {
         // mid < size: mid = size / 2 + size / 4 + size / 8 ...
         #[allow(unsafe_code)]
         let l = unsafe { s.get_unchecked(mid).as_ref() };
-        let cmp = fastcmp(l, key);
+        let cmp = head_cmp(l, key, key_head);
         base = if cmp == Greater { base } else { mid };
         size -= half;
     }
     // base is always in [0, size) because base <= mid.
     #[allow(unsafe_code)]
     let l = unsafe { s.get_unchecked(base).as_ref() };
-    let cmp = fastcmp(l, key);
-    if cmp == Equal {
-        Ok(base)
+    let cmp = head_cmp(l, key, key_head);
+    if cmp == Equal { Ok(base) } else { Err(base + (cmp == Less) as usize) }
+}
+
+// The first 8 bytes of a key, padded with zeroes. A padded
+// byte only differs from the other key's byte when the
+// shorter key is a prefix of the longer one, which also
+// orders it first.
+fn head(key: &[u8]) -> u64 {
+    if key.len() >= 8 {
+        u64::from_be_bytes(<[u8; 8]>::try_from(&key[..8]).unwrap())
     } else {
-        Err(base + (cmp == Less) as usize)
+        let mut head = 0;
+        for (i, byte) in key.iter().enumerate() {
+            head |= u64::from(*byte) << (56 - 8 * i);
+        }
+        head
+    }
+}
+
+fn head_cmp(l: &[u8], r: &[u8], r_head: u64) -> Ordering {
+    match head(l).cmp(&r_head) {
+        Equal => fastcmp(l, r),
+        unequal => unequal,
     }
 }
 
@@ -64,3 +95,28 @@ fn test_binary_search_lub() {
     s.clear();
     assert_eq!(binary_search_lub(&[8], &*s), None);
 }
+
+#[cfg(test)]
+mod qc {
+    use super::binary_search;
+    use crate::{IVec, fastcmp};
+
+    quickcheck::quickcheck! {
+        fn qc_binary_search_matches_std(
+            keys: Vec<Vec<u8>>,
+            key: Vec<u8>
+        ) -> bool {
+            let mut keys = keys;
+            keys.sort();
+            keys.dedup();
+            let s: Vec<IVec> =
+                keys.iter().map(|k| k.as_slice().into()).collect();
+            let expected = s.binary_search_by(|k| fastcmp(k, &key));
+            assert_eq!(binary_search(&key, &s), expected);
+            for (i, k) in keys.iter().enumerate() {
+                assert_eq!(binary_search(k, &s), Ok(i));
+            }
+            true
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    pagecache::{Update, constants::META_PID},
+    *,
+};
+
+/// The net change to a key between two log sequence
+/// numbers, as returned by `Db::changes_between`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// The name of the `Tree` that the key belongs to.
+    pub tree: IVec,
+    /// The key that changed.
+    pub key: IVec,
+    /// The value of the key at the first lsn, or `None`
+    /// if the key was absent.
+    pub before: Option<IVec>,
+    /// The value of the key at the second lsn, or `None`
+    /// if the key was removed.
+    pub after: Option<IVec>,
+}
+
+type Contents = BTreeMap<IVec, BTreeMap<IVec, IVec>>;
+
+pub(crate) fn changes_between(
+    pagecache: &PageCache,
+    from: Lsn,
+    to: Lsn,
+) -> Result<Vec<Change>> {
+    let (pages_at_from, pages_at_to) = pagecache.historical_pages(from, to)?;
+
+    let before = contents(&pages_at_from)?;
+    let after = contents(&pages_at_to)?;
+
+    let empty = BTreeMap::new();
+    let mut changes = vec![];
+
+    let names: BTreeSet<&IVec> = before.keys().chain(after.keys()).collect();
+    for name in names {
+        let tree_before = before.get(name).unwrap_or(&empty);
+        let tree_after = after.get(name).unwrap_or(&empty);
+
+        let keys: BTreeSet<&IVec> =
+            tree_before.keys().chain(tree_after.keys()).collect();
+        for key in keys {
+            let value_before = tree_before.get(key);
+            let value_after = tree_after.get(key);
+            if value_before != value_after {
+                changes.push(Change {
+                    tree: name.clone(),
+                    key: key.clone(),
+                    before: value_before.cloned(),
+                    after: value_after.cloned(),
+                });
+            }
+        }
+    }
+
+    Ok(changes)
+}
+
+// Reads every item of every tree rooted in the meta page,
+// by descending to the leftmost leaf of each tree and then
+// following its leaves' sibling links.
+fn contents(pages: &FastMap8<PageId, Update>) -> Result<Contents> {
+    let mut contents = Contents::new();
+
+    let meta = match pages.get(&META_PID) {
+        Some(Update::Meta(meta)) => meta,
+        // the log ends before the database was initialized
+        _ => return Ok(contents),
+    };
+
+    for (name, root) in &meta.inner {
+        let items = contents.entry(name.clone()).or_default();
+
+        let mut node = node_at(pages, *root)?;
+        while let Data::Index(index) = &node.data {
+            node = node_at(pages, index.pointers[0])?;
+        }
+
+        loop {
+            let leaf = node.data.leaf_ref().ok_or_else(|| {
+                Error::ReportableBug("index node found among leaves".to_owned())
+            })?;
+
+            for (encoded_key, stored) in leaf.keys.iter().zip(&leaf.values) {
+                let value = match stored {
+                    Value::Inline(inline) => inline.clone(),
+                    Value::Overflow(value_pid) => match pages.get(value_pid) {
+                        Some(Update::Value(overflow)) => overflow.clone(),
+                        _ => {
+                            return Err(Error::ReportableBug(format!(
+                                "missing value page {}",
+                                value_pid
+                            )));
+                        }
+                    },
+                };
+                items.insert(node.prefix_decode(encoded_key), value);
+            }
+
+            match node.next {
+                Some(next) => node = node_at(pages, next)?,
+                None => break,
+            }
+        }
+    }
+
+    Ok(contents)
+}
+
+fn node_at(pages: &FastMap8<PageId, Update>, pid: PageId) -> Result<&Node> {
+    match pages.get(&pid) {
+        Some(Update::Node(node)) => Ok(node),
+        _ => Err(Error::ReportableBug(format!("missing node page {}", pid))),
+    }
+}
@@ -6,10 +6,18 @@ use std::{
     io::{BufRead, BufReader, ErrorKind, Read, Seek, Write},
     ops::Deref,
     path::{Path, PathBuf},
-    sync::{atomic::AtomicUsize, Arc},
+    sync::{atomic::AtomicUsize, mpsc, Arc},
+    time::{Duration, Instant},
 };
 
-use crate::pagecache::{arr_to_u32, u32_to_arr, Lsn};
+use crate::pagecache::{
+    arr_to_u32,
+    constants::{
+        DIRECT_IO_BLOCK_SIZE, MAX_PAGE_CHAIN_LENGTH,
+        PAGE_CONSOLIDATION_THRESHOLD,
+    },
+    u32_to_arr, LogFile, LogStorage, Lsn,
+};
 use crate::*;
 
 const DEFAULT_PATH: &str = "default.sled";
@@ -31,6 +39,109 @@ pub enum Mode {
     HighThroughput,
 }
 
+/// Determines which operations complete the partial
+/// splits and merges that other threads leave behind
+/// while restructuring a `Tree`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepairPolicy {
+    /// Any operation that encounters a partial split or
+    /// merge completes it, including reads. This keeps
+    /// the tree tightly structured at the cost of
+    /// occasional read latency spikes.
+    Readers,
+    /// Only writes complete partial splits and merges,
+    /// and only writes split or merge nodes. Reads step
+    /// over incomplete structure without doing extra work.
+    Writers,
+    /// Like `Writers`, except that writes only help
+    /// complete merges, which they would otherwise be
+    /// blocked by. Partial splits are instead completed by
+    /// a background thread that sweeps each `Tree` every
+    /// `repair_every_ms` milliseconds.
+    Background,
+}
+
+/// Decides when the fragments linked onto a page, such as
+/// the inserts into a leaf, are consolidated into a new
+/// copy of the whole page. Only each fragment is written
+/// to the log until then, which makes long chains of them
+/// cheap to append to, but every fragment has to be read
+/// back in along with the page once it has been evicted
+/// from the cache.
+///
+/// A page is made up of at most 16 messages in the log,
+/// counting the page and the fragments linked onto it,
+/// whichever policy is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsolidationPolicy {
+    /// Consolidates a page once it is made up of this many
+    /// messages in the log. Defaults to 10.
+    Fixed(usize),
+    /// Consolidates a page once it is made up of `max_chain`
+    /// messages in the log, or once it is made up of at
+    /// least `min_chain` and either the fragments take up
+    /// more than `max_bytes` in the log, or the page has been
+    /// read at least `hot_reads` times since it was last
+    /// consolidated. This lets pages that are mostly
+    /// appended to grow long chains, while keeping the
+    /// chains of large or frequently read pages short.
+    Adaptive {
+        /// The length below which a page is never consolidated.
+        min_chain: usize,
+        /// The length at which a page is always consolidated.
+        max_chain: usize,
+        /// How many bytes of fragments a page may have in
+        /// the log before it is consolidated.
+        max_bytes: u64,
+        /// How many reads make a page hot enough to be
+        /// consolidated.
+        hot_reads: u32,
+    },
+}
+
+/// How durable a write must be before the call that
+/// made it returns, as chosen with methods like
+/// `Tree::insert_with_durability`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Durability {
+    /// Return as soon as the write is buffered in memory,
+    /// leaving it to be written out by the next flush,
+    /// which happens every `flush_every_ms` milliseconds
+    /// in the background. This is how `Tree::insert` and
+    /// other writes behave.
+    Buffered,
+    /// Write the buffered log out and `fdatasync` it, so
+    /// that the write survives a crash once the call
+    /// returns, without waiting for file metadata that
+    /// isn't needed to read it back.
+    DataSync,
+    /// Like `DataSync`, but uses a full `fsync` that also
+    /// waits for all of the file's metadata to be synced.
+    Sync,
+}
+
+/// How far recovery has gotten, as reported to the
+/// callback passed to `Config::open_with_deadline`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecoveryProgress {
+    /// The highest log sequence number that recovery has
+    /// replayed so far, including what was restored from
+    /// the last snapshot. This stops increasing once the
+    /// log has been replayed and the rest of the database
+    /// is being loaded.
+    pub recovered_lsn: Lsn,
+    /// How long the database has been opening for.
+    pub elapsed: Duration,
+}
+
+// Shared between the thread opening a database and the
+// caller of `Config::open_with_deadline` waiting on it.
+#[derive(Debug, Default)]
+pub(crate) struct Recovery {
+    pub(crate) recovered_lsn: AtomicLsn,
+    pub(crate) abandoned: AtomicBool,
+}
+
 /// A persisted configuration about high-level
 /// storage file information
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
@@ -165,6 +276,11 @@ impl StorageParameters {
 
 /// Top-level configuration for the system.
 ///
+/// `Config::open` is the way to open a database. It
+/// checks every option, and the combination of options,
+/// before touching the filesystem, returning
+/// `Error::Unsupported` for an invalid configuration.
+///
 /// # Examples
 ///
 /// ```
@@ -211,15 +327,65 @@ pub struct Inner {
     #[doc(hidden)]
     pub temporary: bool,
     #[doc(hidden)]
+    pub in_memory: bool,
+    #[doc(hidden)]
+    pub replica: bool,
+    #[doc(hidden)]
+    pub recover_until: Option<Lsn>,
+    #[doc(hidden)]
+    pub snapshot_after_ops: u64,
+    #[doc(hidden)]
     pub use_compression: bool,
     #[doc(hidden)]
+    pub use_direct_io: bool,
+    #[doc(hidden)]
     pub compression_factor: i32,
     #[doc(hidden)]
     pub print_profile_on_drop: bool,
     #[doc(hidden)]
     pub idgen_persist_interval: u64,
     #[doc(hidden)]
+    pub overflow_threshold: usize,
+    #[doc(hidden)]
+    pub inline_value_threshold: usize,
+    #[doc(hidden)]
+    pub repair_policy: RepairPolicy,
+    #[doc(hidden)]
+    pub repair_every_ms: u64,
+    #[doc(hidden)]
+    pub consolidation_policy: ConsolidationPolicy,
+    #[doc(hidden)]
+    pub prefetch_pages: usize,
+    #[doc(hidden)]
+    pub max_leaf_items: usize,
+    #[doc(hidden)]
+    pub max_index_items: usize,
+    #[doc(hidden)]
+    pub min_leaf_items: usize,
+    #[doc(hidden)]
+    pub min_index_items: usize,
+    #[doc(hidden)]
+    pub max_node_size: Option<u64>,
+    #[doc(hidden)]
+    pub leaf_filter_bits_per_key: usize,
+    #[doc(hidden)]
+    pub warm_cache_pages: usize,
+    #[doc(hidden)]
+    pub background_bytes_per_sec: Option<u64>,
+    #[doc(hidden)]
+    pub background_ops_per_sec: Option<u64>,
+    #[doc(hidden)]
+    pub scrub_every_ms: Option<u64>,
+    #[doc(hidden)]
     pub version: (usize, usize),
+    pub(crate) materializer: Option<Arc<dyn Materializer>>,
+    #[cfg(feature = "instrumentation")]
+    pub(crate) instrumentation: Option<Arc<dyn Instrumentation>>,
+    #[cfg(feature = "failpoints")]
+    pub(crate) fault_plan: fail::FaultPlan,
+    #[cfg(feature = "simulation")]
+    pub(crate) simulation: Option<Arc<simulation::Shared>>,
+    pub(crate) compressed_trees: Vec<IVec>,
     tmp_path: PathBuf,
     pub(crate) global_error: Arc<Atomic<Error>>,
     #[cfg(feature = "event_log")]
@@ -238,15 +404,65 @@ impl Default for Inner {
             cache_capacity: 1024 * 1024 * 1024, // 1gb
             mode: Mode::LowSpace,
             use_compression: false,
+            use_direct_io: false,
             compression_factor: 5,
             temporary: false,
+            in_memory: false,
+            replica: false,
+            recover_until: None,
+            snapshot_after_ops: 1_000_000,
             version: crate_version(),
+            materializer: None,
+            #[cfg(feature = "instrumentation")]
+            instrumentation: None,
+            #[cfg(feature = "failpoints")]
+            fault_plan: fail::FaultPlan::default(),
+            #[cfg(feature = "simulation")]
+            simulation: None,
+            compressed_trees: vec![],
 
             // useful in testing
             segment_size: 512 * 1024, // 512kb in bytes
             print_profile_on_drop: false,
             flush_every_ms: Some(500),
             idgen_persist_interval: 1_000_000,
+            overflow_threshold: 64 * 1024,
+            inline_value_threshold: 128,
+            repair_policy: RepairPolicy::Readers,
+            repair_every_ms: 100,
+            consolidation_policy: ConsolidationPolicy::Fixed(
+                PAGE_CONSOLIDATION_THRESHOLD,
+            ),
+            prefetch_pages: 0,
+            leaf_filter_bits_per_key: 0,
+            warm_cache_pages: 0,
+            background_bytes_per_sec: None,
+            background_ops_per_sec: None,
+            scrub_every_ms: None,
+
+            // tiny nodes make splits and merges happen
+            // often enough to be exercised by tests
+            max_leaf_items: if cfg!(any(test, feature = "lock_free_delays")) {
+                2
+            } else {
+                16
+            },
+            max_index_items: if cfg!(any(test, feature = "lock_free_delays")) {
+                2
+            } else {
+                256
+            },
+            min_leaf_items: if cfg!(any(test, feature = "lock_free_delays")) {
+                1
+            } else {
+                4
+            },
+            min_index_items: if cfg!(any(test, feature = "lock_free_delays")) {
+                1
+            } else {
+                64
+            },
+            max_node_size: None,
             global_error: Arc::new(Atomic::default()),
             #[cfg(feature = "event_log")]
             event_log: Arc::new(crate::event_log::EventLog::default()),
@@ -255,16 +471,67 @@ impl Default for Inner {
 }
 
 impl Inner {
+    /// The time since an arbitrary point in the past, which
+    /// is simulated for databases opened on a `Simulation`.
+    pub(crate) fn now(&self) -> Duration {
+        static START: Lazy<Instant, fn() -> Instant> = Lazy::new(Instant::now);
+
+        #[cfg(feature = "simulation")]
+        {
+            if let Some(simulation) = &self.simulation {
+                return simulation.now();
+            }
+        }
+
+        START.elapsed()
+    }
+
+    /// Whether the database is opened on a `Simulation`.
+    pub(crate) fn is_simulated(&self) -> bool {
+        #[cfg(feature = "simulation")]
+        {
+            self.simulation.is_some()
+        }
+
+        #[cfg(not(feature = "simulation"))]
+        {
+            false
+        }
+    }
+
+    /// Runs `f` once no thread can still be reading what it
+    /// frees or reuses. A simulated database is only used by
+    /// the thread driving its simulation, so `f` is run right
+    /// away to keep where it happens deterministic.
+    pub(crate) fn defer<F>(&self, guard: &Guard, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if self.is_simulated() {
+            f();
+        } else {
+            guard.defer(f);
+        }
+    }
+
     // Get the path of the database
     #[doc(hidden)]
     pub fn get_path(&self) -> PathBuf {
-        if self.temporary && self.path == PathBuf::from(DEFAULT_PATH) {
+        if self.in_memory
+            || (self.temporary && self.path == PathBuf::from(DEFAULT_PATH))
+        {
             self.tmp_path.clone()
         } else {
             self.path.clone()
         }
     }
 
+    // Whether the database is deleted on drop, in which
+    // case there's no point in syncing it to disk.
+    pub(crate) fn is_temporary(&self) -> bool {
+        self.temporary || self.in_memory
+    }
+
     pub(crate) fn blob_path(&self, id: Lsn) -> PathBuf {
         let mut path = self.get_path();
         path.push("blobs");
@@ -278,7 +545,7 @@ impl Inner {
         path
     }
 
-    fn config_path(&self) -> PathBuf {
+    pub(crate) fn config_path(&self) -> PathBuf {
         let mut path = self.get_path();
         path.push("conf");
         path
@@ -355,8 +622,208 @@ impl Config {
         self
     }
 
+    /// Sets the `Materializer` that consolidates the pages
+    /// allocated with `Db::allocate_page` (builder). It has
+    /// to be set whenever such a database is opened.
+    pub fn materializer<M: Materializer + 'static>(
+        mut self,
+        materializer: M,
+    ) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.materializer = Some(Arc::new(materializer));
+        self
+    }
+
+    /// Sets the `Instrumentation` that is told when the
+    /// database enters and exits its major internal code
+    /// paths (builder). Requires the `instrumentation` feature.
+    #[cfg(feature = "instrumentation")]
+    pub fn instrumentation<I: Instrumentation + 'static>(
+        mut self,
+        instrumentation: I,
+    ) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.instrumentation = Some(Arc::new(instrumentation));
+        self
+    }
+
+    /// Sets the `FaultPlan` that decides which failpoints
+    /// fail while the database is running (builder). By
+    /// default, none do. Requires the `failpoints` feature.
+    #[cfg(feature = "failpoints")]
+    pub fn fault_plan(mut self, fault_plan: fail::FaultPlan) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.fault_plan = fault_plan;
+        self
+    }
+
+    // Runs the database on a simulated machine.
+    #[cfg(feature = "simulation")]
+    pub(crate) fn simulation(
+        mut self,
+        simulation: Arc<simulation::Shared>,
+    ) -> Config {
+        let m = Arc::make_mut(&mut self.0);
+        m.simulation = Some(simulation);
+        self
+    }
+
+    /// Compresses the consolidated pages of the named tree
+    /// with zstd when they are written to the log, and
+    /// decompresses them when they are paged back in
+    /// (builder). The `Db`'s own keyspace is named
+    /// `__sled__default`. Requires the `compression` feature.
+    pub fn compress_tree<N: AsRef<[u8]>>(mut self, name: N) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.compressed_trees.push(IVec::from(name.as_ref()));
+        self
+    }
+
+    pub(crate) fn is_compressed_tree(&self, name: &[u8]) -> bool {
+        self.compressed_trees.iter().any(|tree| tree == name)
+    }
+
     /// Opens a `Db` based on the provided config.
     pub fn open(&self) -> Result<Db> {
+        self.open_inner(Arc::new(Recovery::default()))
+    }
+
+    /// Opens a `Db` on a background thread, waiting up to
+    /// `deadline` for it to finish recovering. Each time the
+    /// deadline passes, `progress` is called with how far
+    /// recovery has gotten, and returns `true` to keep
+    /// waiting for another `deadline`, or `false` to give up.
+    ///
+    /// After giving up, this waits for recovery to stop, so
+    /// that the database's files are no longer in use, and
+    /// returns an `Error::Io` of kind `TimedOut`. Recovery
+    /// only stops between log messages, so this may take a
+    /// moment once the log has been replayed.
+    ///
+    /// This lets a supervisor such as a readiness probe see
+    /// that a large log is still being replayed instead of
+    /// waiting on `open` without knowing whether it is stuck.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::time::Duration;
+    ///
+    /// let config = sled::Config::new().temporary(true);
+    /// let db = config.open_with_deadline(
+    ///     Duration::from_secs(10),
+    ///     |progress| {
+    ///         println!("recovered up to lsn {}", progress.recovered_lsn);
+    ///         progress.elapsed < Duration::from_secs(60)
+    ///     },
+    /// )?;
+    /// # Ok(()) }
+    /// ```
+    pub fn open_with_deadline<F>(
+        &self,
+        deadline: Duration,
+        mut progress: F,
+    ) -> Result<Db>
+    where
+        F: FnMut(RecoveryProgress) -> bool,
+    {
+        let start = Instant::now();
+        let recovery = Arc::new(Recovery::default());
+
+        let (tx, rx) = mpsc::channel();
+        let config = self.clone();
+        let opener_recovery = recovery.clone();
+        let opener = std::thread::Builder::new()
+            .name("sled open".to_owned())
+            .spawn(move || {
+                let _ = tx.send(config.open_inner(opener_recovery));
+            })?;
+
+        let opener_panicked = || {
+            Error::ReportableBug(
+                "the thread opening the database panicked".to_owned(),
+            )
+        };
+
+        let mut wait_until = start + deadline;
+        loop {
+            let now = Instant::now();
+            let timeout = if now < wait_until {
+                wait_until - now
+            } else {
+                Duration::from_millis(0)
+            };
+
+            match rx.recv_timeout(timeout) {
+                Ok(res) => {
+                    let _ = opener.join();
+                    return res;
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    let _ = opener.join();
+                    return Err(opener_panicked());
+                }
+            }
+
+            let report = RecoveryProgress {
+                recovered_lsn: recovery.recovered_lsn.load(Acquire),
+                elapsed: start.elapsed(),
+            };
+
+            if !progress(report) {
+                break;
+            }
+
+            wait_until += deadline;
+        }
+
+        recovery.abandoned.store(true, Release);
+
+        // the database is dropped here if it finished
+        // opening before it noticed that we gave up
+        let res = rx.recv();
+        let _ = opener.join();
+        if res.is_err() {
+            return Err(opener_panicked());
+        }
+
+        Err(Error::Io(io::Error::new(
+            ErrorKind::TimedOut,
+            "gave up waiting for the database to open",
+        )))
+    }
+
+    fn open_inner(&self, recovery: Arc<Recovery>) -> Result<Db> {
         // only validate, setup directory, and open file once
         self.validate()?;
 
@@ -364,9 +831,15 @@ impl Config {
         config.limit_cache_max_memory();
 
         let file = config.open_file()?;
+        let direct_file = config.open_direct_file()?;
 
         // seal config in a Config
-        let config = RunningConfig { inner: config, file: Arc::new(file) };
+        let config = RunningConfig {
+            inner: config,
+            file: Arc::new(file),
+            direct_file: direct_file.map(Arc::new),
+            recovery,
+        };
 
         Db::start_inner(config)
     }
@@ -389,15 +862,6 @@ impl Config {
         self
     }
 
-    #[doc(hidden)]
-    #[deprecated(
-        since = "0.31.0",
-        note = "this does nothing for now. maybe it will come back in the future."
-    )]
-    pub const fn snapshot_after_ops(self, _: u64) -> Self {
-        self
-    }
-
     #[doc(hidden)]
     #[deprecated(
         since = "0.31.0",
@@ -407,7 +871,12 @@ impl Config {
         self
     }
 
-    #[doc(hidden)]
+    /// Flushes dirty IO buffers and syncs the log from a
+    /// background thread every `every_ms` milliseconds, so
+    /// that writes become durable within about that long
+    /// without calling `Tree::flush`. `None` disables the
+    /// thread, leaving writes buffered until they fill an IO
+    /// buffer or are flushed explicitly. Defaults to 500.
     pub fn flush_every_ms(mut self, every_ms: Option<u64>) -> Self {
         if Arc::strong_count(&self.0) != 1 {
             error!(
@@ -454,12 +923,33 @@ impl Config {
         let file = self.open_file().unwrap_or_else(|e| {
             panic!("open file at {:?}: {}", self.db_path(), e);
         });
+        let direct_file = self.open_direct_file().unwrap_or_else(|e| {
+            panic!("open file at {:?} for direct IO: {}", self.db_path(), e);
+        });
 
         // seal config in a Config
-        RunningConfig { inner: self, file: Arc::new(file) }
+        RunningConfig {
+            inner: self,
+            file: Arc::new(file),
+            direct_file: direct_file.map(Arc::new),
+            recovery: Arc::new(Recovery::default()),
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) fn gen_temp_path() -> PathBuf {
+        // there is no clock, process id or temporary
+        // directory to salt the path with, and it is only
+        // used by `in_memory` databases that write blobs
+        static SALT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let seed = SALT_COUNTER.fetch_add(1, SeqCst);
+
+        format!("pagecache.tmp.{}", seed).into()
     }
 
-    fn gen_temp_path() -> PathBuf {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn gen_temp_path() -> PathBuf {
         use std::time::SystemTime;
 
         static SALT_COUNTER: AtomicUsize = AtomicUsize::new(0);
@@ -500,18 +990,44 @@ impl Config {
     }
 
     builder!(
-        (cache_capacity, u64, "maximum size in bytes for the system page cache"),
+        (cache_capacity, u64, "maximum size in bytes for the system page cache. once it is exceeded, the least recently used pages are dropped from memory, keeping only their locations in the log to read them back from. must be at least 256"),
         (mode, Mode, "specify whether the system should run in \"small\" or \"fast\" mode"),
-        (use_compression, bool, "whether to use zstd compression"),
+        (use_compression, bool, "whether to use zstd compression for new writes. may be changed across restarts, because each log message records whether it was compressed"),
+        (use_direct_io, bool, "writes the log with O_DIRECT, so that large sequential writes bypass the OS page cache instead of evicting the application's working set from it. each flush is padded out to a whole number of 4kb blocks. linux only"),
         (compression_factor, i32, "the compression factor to use with zstd compression. Ranges from 1 up to 22. 0 is 'default'. Levels >= 20 are 'ultra'."),
         (temporary, bool, "deletes the database after drop. if no path is set, uses /dev/shm on linux"),
+        (in_memory, bool, "keeps the database in memory only, for tests and caches. it is deleted after drop like a `temporary` one, but is never synced to disk and writes no snapshots or config file. its log is kept in a buffer in memory, and only values that are too large for the log are written to /dev/shm on linux, or to the system's temporary directory elsewhere. this is the mode to use on wasm32-unknown-unknown, where there is no file system, and writing a value that large returns an `Error::Io`"),
         (create_new, bool, "attempts to exclusively open the database, failing if it already exists"),
         (read_only, bool, "whether to run in read-only mode"),
-        (print_profile_on_drop, bool, "print a performance profile when the Config is dropped")
+        (replica, bool, "creates the database as a replica, which only accepts log entries shipped from a primary through `Db::apply_log_entries` until it is promoted. the database must not exist yet, and stays a replica across restarts"),
+        (recover_until, Option<Lsn>, "stops recovery at the given lsn, such as one returned by `Db::checkpoint`, `Db::backup_to`, or `LogEntry::lsn`, rolling the database back to the last atomic write at or before it. everything written after it is discarded for good, so if opening fails or the process crashes while opening, the database must be opened again with the same lsn. returns `Error::Unsupported` if the log segments that the state at the lsn was stored in have since been reused"),
+        (snapshot_after_ops, u64, "takes a snapshot of the page table from the periodic flush thread once this many messages have been written to the log since the last one, so that recovery only replays the log after it. has no effect when `flush_every_ms` is `None`"),
+        (print_profile_on_drop, bool, "print a performance profile when the Config is dropped"),
+        (overflow_threshold, usize, "values at least this many bytes long are stored in their own page instead of inline in a leaf"),
+        (inline_value_threshold, usize, "values of up to this many bytes are packed into a single buffer per leaf as it is read from the log or consolidated, instead of each taking an allocation of their own. values of up to 22 bytes never take one, and the threshold may be at most 65535. a value returned by `get`, an iterator, or a subscriber shares the buffer of every packed value in its leaf, keeping all of it alive until the value is dropped, even after the leaf is evicted or rewritten. values that are kept for a long time may be copied, such as with `to_vec`, to release the buffer. 0 disables packing"),
+        (repair_policy, RepairPolicy, "which operations complete partial splits and merges left behind by other threads"),
+        (repair_every_ms, u64, "how often the background thread sweeps each tree when using `RepairPolicy::Background`"),
+        (consolidation_policy, ConsolidationPolicy, "when the fragments linked onto a page are consolidated into a new copy of it. chains of at most 2 to 16 messages may be configured"),
+        (prefetch_pages, usize, "how many of the following leaves a forward scan reads from disk in the background once it reaches a leaf. 0 disables prefetching"),
+        (max_leaf_items, usize, "leaves holding more than this many items are split. Must be at least 2"),
+        (max_index_items, usize, "index nodes pointing to more than this many children are split. Must be at least 2"),
+        (min_leaf_items, usize, "leaves holding fewer than this many items are merged into their left sibling. Must be at most half of `max_leaf_items`"),
+        (min_index_items, usize, "index nodes pointing to fewer than this many children are merged into their left sibling. Must be at most half of `max_index_items`"),
+        (max_node_size, Option<u64>, "nodes taking up more than this many bytes in memory are split regardless of how many items they hold, which keeps nodes with large values from being rewritten as a whole on every consolidation"),
+        (leaf_filter_bits_per_key, usize, "builds a bloom filter with this many bits per key over each leaf as it is paged out, so that reading a key that is not in the tree doesn't read the leaf back from the log. about 10 bits per key rule out 99% of absent keys. the filters are kept in memory, and written to the database's directory along with snapshots and on shutdown. 0 disables them"),
+        (warm_cache_pages, usize, "writes the ids of up to this many of the most recently accessed pages to the database's directory on shutdown, and reads those pages back into the cache in the background when it is opened again, so that reads don't all go to disk for a while after a restart. `Db::warm_cache` does the same in the foreground. dropping the database waits for the warm-up to finish. 0 disables warming up"),
+        (background_bytes_per_sec, Option<u64>, "limits the bytes that the periodic flush thread writes, both when flushing IO buffers and when relocating pages for garbage collection, to about this many per second. once the budget is used up, the thread skips its flushes, leaving IO buffers to be written as they fill up, and stops collecting garbage until it has refilled. the limit is raised by up to 4 times as the share of the log that is dead space, or the bytes waiting to be flushed, grows. flushes on shutdown and calls to `flush` are never limited. `None` by default, for no limit"),
+        (background_ops_per_sec, Option<u64>, "limits the periodic flushes, the pages relocated for garbage collection and the trees swept by `RepairPolicy::Background` to about this many per second in total, raised along with `background_bytes_per_sec`. `None` by default, for no limit"),
+        (scrub_every_ms, Option<u64>, "runs `Db::scrub` from a background thread this long after the database is opened and after each scrub, charging the pages it reads to `background_bytes_per_sec` and `background_ops_per_sec` and waiting for them to refill. the outcome of the last one is returned by `Db::last_scrub`. has no effect on read-only databases. `None` by default, for no periodic scrubs")
     );
 
-    // panics if config options are outside of advised range
+    // returns an error if config options are outside of
+    // advised range or can't be combined
     fn validate(&self) -> Result<()> {
+        supported!(
+            self.cache_capacity >= 256,
+            "cache_capacity must be at least 256"
+        );
         supported!(
             self.segment_size.count_ones() == 1,
             "segment_size should be a power of 2"
@@ -542,10 +1058,148 @@ impl Config {
             self.idgen_persist_interval > 0,
             "idgen_persist_interval must be above 0"
         );
+        supported!(
+            self.overflow_threshold > 0,
+            "overflow_threshold must be above 0"
+        );
+        supported!(
+            self.background_bytes_per_sec != Some(0)
+                && self.background_ops_per_sec != Some(0),
+            "background_bytes_per_sec and background_ops_per_sec must be above 0"
+        );
+        supported!(
+            self.inline_value_threshold <= usize::from(u16::max_value()),
+            "inline_value_threshold must be at most 65535"
+        );
+        supported!(self.repair_every_ms > 0, "repair_every_ms must be above 0");
+        supported!(
+            self.scrub_every_ms != Some(0),
+            "scrub_every_ms must be above 0"
+        );
+        supported!(
+            self.snapshot_after_ops > 0,
+            "snapshot_after_ops must be above 0"
+        );
+        match self.consolidation_policy {
+            ConsolidationPolicy::Fixed(chain) => {
+                supported!(
+                    (2..=MAX_PAGE_CHAIN_LENGTH).contains(&chain),
+                    "ConsolidationPolicy::Fixed must be between 2 and 16"
+                );
+            }
+            ConsolidationPolicy::Adaptive { min_chain, max_chain, .. } => {
+                supported!(
+                    min_chain >= 2 && min_chain <= max_chain,
+                    "ConsolidationPolicy::Adaptive's min_chain must be \
+                     at least 2 and at most max_chain"
+                );
+                supported!(
+                    max_chain <= MAX_PAGE_CHAIN_LENGTH,
+                    "ConsolidationPolicy::Adaptive's max_chain must be \
+                     at most 16"
+                );
+            }
+        }
+        supported!(
+            self.max_leaf_items >= 2,
+            "max_leaf_items must be at least 2"
+        );
+        supported!(
+            self.max_index_items >= 2,
+            "max_index_items must be at least 2"
+        );
+        // otherwise merged nodes could need to be split right away
+        supported!(
+            self.min_leaf_items <= self.max_leaf_items / 2,
+            "min_leaf_items must be at most half of max_leaf_items"
+        );
+        supported!(
+            self.min_index_items <= self.max_index_items / 2,
+            "min_index_items must be at most half of max_index_items"
+        );
+        if !self.compressed_trees.is_empty() {
+            supported!(
+                cfg!(feature = "compression"),
+                "compress_tree requires the compression feature"
+            );
+        }
+        if self.use_direct_io {
+            supported!(
+                cfg!(target_os = "linux"),
+                "use_direct_io is only supported on linux"
+            );
+            supported!(
+                !cfg!(feature = "io_uring"),
+                "use_direct_io can't be used with the 'io_uring' feature"
+            );
+            supported!(
+                !self.in_memory,
+                "use_direct_io can't be used with in_memory, because \
+                 an in-memory database is never written to disk"
+            );
+            supported!(
+                self.segment_size % DIRECT_IO_BLOCK_SIZE == 0,
+                "segment_size must be a multiple of 4kb to use_direct_io"
+            );
+        }
+        if let Some(max_node_size) = self.max_node_size {
+            supported!(max_node_size > 0, "max_node_size must be above 0");
+        }
+        if let Some(flush_every_ms) = self.flush_every_ms {
+            supported!(
+                flush_every_ms > 0,
+                "flush_every_ms must be above 0, or None to disable \
+                 periodic flushing"
+            );
+        }
+        if self.read_only {
+            supported!(
+                !self.create_new,
+                "create_new can't be used with read_only, because \
+                 a read-only database can't be created"
+            );
+            supported!(
+                !self.temporary,
+                "temporary can't be used with read_only, because \
+                 a temporary database starts out empty"
+            );
+        }
+        if self.in_memory {
+            supported!(
+                !self.read_only && !self.create_new,
+                "in_memory can't be used with read_only or \
+                 create_new, because an in-memory database always \
+                 starts out empty"
+            );
+            supported!(
+                self.path == PathBuf::from(DEFAULT_PATH),
+                "in_memory can't be used with a path, because an \
+                 in-memory database is not stored there"
+            );
+        }
+        if self.replica {
+            supported!(
+                !self.read_only && !self.in_memory,
+                "replica can't be used with read_only or in_memory, \
+                 because a replica is written to and has to keep \
+                 its position in the primary's log across restarts"
+            );
+        }
+        if let Some(recover_until) = self.recover_until {
+            supported!(
+                recover_until >= 0,
+                "recover_until must not be negative"
+            );
+            supported!(
+                !self.read_only && !self.in_memory,
+                "recover_until can't be used with read_only or \
+                 in_memory, because rolling back the log writes to it"
+            );
+        }
         Ok(())
     }
 
-    fn open_file(&self) -> Result<File> {
+    fn open_file(&self) -> Result<LogFile> {
         let path = self.db_path();
 
         // panic if we can't parse the path
@@ -568,19 +1222,35 @@ impl Config {
             )));
         }
 
+        if self.in_memory {
+            // the blobs directory is only created once a blob
+            // is written, so that nothing is written to disk
+            // unless a value is too large for the log
+            let buf = RwLock::new(vec![]);
+            return Ok(LogFile::from(LogStorage::Memory(buf)));
+        }
+
         if !dir.exists() {
             fs::create_dir_all(dir)?;
         }
 
         self.verify_config()?;
 
+        #[cfg(feature = "simulation")]
+        {
+            if let Some(simulation) = &self.simulation {
+                let file = simulation.open();
+                return Ok(LogFile::from(LogStorage::Simulated(file)));
+            }
+        }
+
         // open the data file
         let mut options = fs::OpenOptions::new();
 
-        let _ = options.create(true);
         let _ = options.read(true);
 
         if !self.read_only {
+            let _ = options.create(true);
             let _ = options.write(true);
         }
 
@@ -588,37 +1258,68 @@ impl Config {
             options.create_new(true);
         }
 
-        self.try_lock(options.open(&path)?)
+        let file = options.open(&path)?;
+        self.try_lock(LogFile::from(LogStorage::Disk(file)))
     }
 
-    fn try_lock(&self, file: File) -> Result<File> {
-        #[cfg(any(windows, target_os = "linux", target_os = "macos"))]
-        {
-            use fs2::FileExt;
-
-            let try_lock = if self.read_only {
-                file.try_lock_shared()
-            } else if cfg!(feature = "testing") {
-                // we block here because during testing
-                // there are many filesystem race condition
-                // that happen, causing locks to be held
-                // for long periods of time, so we should
-                // block to wait on reopening files.
-                file.lock_exclusive()
+    // Opens a second handle to the data file that the log
+    // is written through when `use_direct_io` is set. It
+    // isn't locked, because the first handle already is.
+    #[cfg(target_os = "linux")]
+    fn open_direct_file(&self) -> Result<Option<File>> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        if !self.use_direct_io || self.read_only {
+            return Ok(None);
+        }
+
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(self.db_path())
+            .map_err(|e| {
+                Error::Unsupported(format!(
+                    "could not open {:?} for direct IO, which \
+                     its filesystem may not support: {}",
+                    self.db_path(),
+                    e
+                ))
+            })?;
+
+        Ok(Some(file))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn open_direct_file(&self) -> Result<Option<File>> {
+        Ok(None)
+    }
+
+    fn try_lock(&self, file: LogFile) -> Result<LogFile> {
+        // we block during testing because there are many
+        // filesystem race conditions that happen, causing
+        // locks to be held for long periods of time, so we
+        // should block to wait on reopening files.
+        let block = !self.read_only && cfg!(feature = "testing");
+
+        if let Err(e) = file.lock(self.read_only, block) {
+            // another process holding the lock would
+            // interleave its writes to the log with ours
+            let reason = if LogFile::is_lock_contended(&e) {
+                "the database is already open in another process"
             } else {
-                file.try_lock_exclusive()
+                "the file could not be locked"
             };
-
-            if let Err(e) = try_lock {
-                return Err(Error::Io(io::Error::new(
-                    ErrorKind::Other,
-                    format!(
-                        "could not acquire lock on {:?}: {:?}",
-                        self.db_path().to_string_lossy(),
-                        e
-                    ),
-                )));
-            }
+            return Err(Error::Io(io::Error::new(
+                e.kind(),
+                format!(
+                    "could not acquire {} lock on {:?}, \
+                     because {}: {}",
+                    if self.read_only { "a shared" } else { "an exclusive" },
+                    self.db_path().to_string_lossy(),
+                    reason,
+                    e
+                ),
+            )));
         }
 
         Ok(file)
@@ -627,16 +1328,9 @@ impl Config {
     fn verify_config(&self) -> Result<()> {
         match self.read_config() {
             Ok(Some(old)) => {
-                supported!(
-                    self.use_compression == old.use_compression,
-                    format!(
-                        "cannot change compression values across restarts. \
-                         old value of use_compression loaded from disk: {}, \
-                         currently set value: {}.",
-                        old.use_compression, self.use_compression,
-                    )
-                );
-
+                // use_compression may change across restarts,
+                // because every message records whether it
+                // was compressed
                 supported!(
                     self.segment_size == old.segment_size,
                     format!(
@@ -799,7 +1493,13 @@ impl Config {
 #[derive(Debug, Clone)]
 pub struct RunningConfig {
     inner: Config,
-    pub(crate) file: Arc<File>,
+    pub(crate) file: Arc<LogFile>,
+    // the file that the log is written through
+    // with O_DIRECT, if `use_direct_io` is set
+    pub(crate) direct_file: Option<Arc<File>>,
+    // how far recovery has gotten, and whether
+    // `Config::open_with_deadline` gave up on it
+    pub(crate) recovery: Arc<Recovery>,
 }
 
 #[allow(unsafe_code)]
@@ -822,7 +1522,7 @@ impl Drop for Inner {
             M.print_profile();
         }
 
-        if !self.temporary {
+        if !self.is_temporary() {
             return;
         }
 
@@ -0,0 +1,79 @@
+use crate::{
+    sha256::sha256,
+    transaction::{ConflictableTransactionResult, TransactionError},
+    *,
+};
+
+/// The SHA-256 digest of a blob's contents, which names
+/// it in `Db::put_blob`, `Db::get_blob`, and
+/// `Db::release_blob`.
+pub type BlobHash = [u8; 32];
+
+/// The `Tree` that content-addressed blobs are stored in.
+pub(crate) const BLOBS_TREE_ID: &[u8] = b"__sled__blobs";
+
+// each blob is stored under two keys, so that changing
+// its reference count doesn't rewrite its contents
+const CONTENTS: u8 = 0;
+const REFERENCES: u8 = 1;
+
+fn key(kind: u8, hash: &BlobHash) -> [u8; 33] {
+    let mut key = [kind; 33];
+    key[1..].copy_from_slice(hash);
+    key
+}
+
+fn references(count: Option<IVec>) -> u64 {
+    count.map_or(0, |bytes| {
+        u64::from_be_bytes(<[u8; 8]>::try_from(bytes.as_ref()).unwrap())
+    })
+}
+
+fn storage_error(transaction_error: TransactionError<()>) -> Error {
+    match transaction_error {
+        TransactionError::Storage(error) => error,
+        TransactionError::Abort(()) => unreachable!(),
+    }
+}
+
+pub(crate) fn put_blob(blobs: &Tree, bytes: &[u8]) -> Result<BlobHash> {
+    let hash = sha256(bytes);
+
+    blobs
+        .transaction(|tx| -> ConflictableTransactionResult<()> {
+            let old = references(tx.get(key(REFERENCES, &hash))?);
+            if old == 0 {
+                tx.insert(&key(CONTENTS, &hash)[..], bytes)?;
+            }
+            let new = (old + 1).to_be_bytes();
+            tx.insert(&key(REFERENCES, &hash)[..], &new[..])?;
+            Ok(())
+        })
+        .map_err(storage_error)?;
+
+    Ok(hash)
+}
+
+pub(crate) fn get_blob(blobs: &Tree, hash: &BlobHash) -> Result<Option<IVec>> {
+    blobs.get(key(CONTENTS, hash))
+}
+
+pub(crate) fn release_blob(blobs: &Tree, hash: &BlobHash) -> Result<bool> {
+    blobs
+        .transaction(|tx| -> ConflictableTransactionResult<bool> {
+            let old = references(tx.get(key(REFERENCES, hash))?);
+            match old {
+                0 => return Ok(false),
+                1 => {
+                    tx.remove(&key(REFERENCES, hash)[..])?;
+                    tx.remove(&key(CONTENTS, hash)[..])?;
+                }
+                _ => {
+                    let new = (old - 1).to_be_bytes();
+                    tx.insert(&key(REFERENCES, hash)[..], &new[..])?;
+                }
+            }
+            Ok(true)
+        })
+        .map_err(storage_error)
+}
@@ -18,6 +18,7 @@ pub struct Context {
     pub(crate) flusher: Arc<Mutex<Option<flusher::Flusher>>>,
     #[doc(hidden)]
     pub pagecache: Arc<PageCache>,
+    pub(crate) read_snapshots: Arc<ReadSnapshots>,
 }
 
 impl std::ops::Deref for Context {
@@ -32,7 +33,7 @@ impl Drop for Context {
     fn drop(&mut self) {
         loop {
             match self.pagecache.flush() {
-                Ok(0) => return,
+                Ok(0) => break,
                 Ok(_) => continue,
                 Err(e) => {
                     error!(
@@ -40,22 +41,64 @@ impl Drop for Context {
                          pagecache during drop: {:?}",
                         e
                     );
-                    return;
+                    break;
                 }
             }
         }
+
+        if let Err(e) = self.pagecache.persist_leaf_filters() {
+            error!("failed to write leaf filters during drop: {:?}", e);
+        }
+
+        if let Err(e) = self.pagecache.persist_warm_pages() {
+            error!("failed to write warm pages during drop: {:?}", e);
+        }
+
+        #[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+        {
+            if Arc::strong_count(&self.flusher) == 1 {
+                self.wait_for_background_work();
+            }
+        }
     }
 }
 
 impl Context {
+    /// Called when the last high-level reference is dropped.
+    /// Background threads and threadpool tasks hold the
+    /// pagecache or its io buffers, and with them the locked
+    /// file, so we wait for them to let go before returning.
+    /// That way the pagecache is dropped right after this,
+    /// and the same path may be opened again immediately.
+    #[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+    fn wait_for_background_work(&self) {
+        // joins the flusher thread
+        drop(self.flusher.lock().take());
+
+        let backoff = Backoff::new();
+        while Arc::strong_count(&self.pagecache) > 1
+            || self.pagecache.log.has_pending_writes()
+        {
+            backoff.snooze();
+        }
+
+        // run destructors deferred by this thread's epochs
+        pin().flush();
+    }
+
     pub(crate) fn start(config: RunningConfig) -> Result<Self> {
         trace!("starting context");
 
         let pagecache = Arc::new(PageCache::start(config.clone())?);
 
+        if config.warm_cache_pages > 0 {
+            PageCache::warm_up_in_background(&pagecache);
+        }
+
         Ok(Self {
             config,
             pagecache,
+            read_snapshots: Arc::new(ReadSnapshots::default()),
             #[cfg(any(windows, target_os = "linux", target_os = "macos"))]
             flusher: Arc::new(parking_lot::Mutex::new(None)),
         })
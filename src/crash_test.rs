@@ -0,0 +1,225 @@
+//! A regression suite for recovery, which runs a workload of
+//! batches on a `Simulation` and cuts the power partway
+//! through some of them. After each power loss the database
+//! is reopened and checked: it must open, the interrupted
+//! batch must have been applied completely or not at all,
+//! everything flushed before it must still be there, and
+//! `Db::verify` must find no violations.
+//!
+//! Requires the `simulation` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use sled::{crash_test::CrashTest, Batch};
+//!
+//! let report = CrashTest::new(42).rounds(50).run(|simulation| {
+//!     let mut batch = Batch::default();
+//!     for _ in 0..simulation.random(8) {
+//!         let key = simulation.random(32).to_be_bytes();
+//!         if simulation.random(4) == 0 {
+//!             batch.remove(&key);
+//!         } else {
+//!             batch.insert(&key, vec![0; 100]);
+//!         }
+//!     }
+//!     batch
+//! })?;
+//!
+//! assert!(report.is_ok(), "{:?}", report.violation);
+//! # Ok(()) }
+//! ```
+
+use std::{collections::BTreeMap, convert::TryFrom, time::Duration};
+
+use crate::{
+    simulation::{Damage, Simulation},
+    *,
+};
+
+/// Runs a workload on a `Simulation`, cutting the power
+/// during some of its batches, and checks that the database
+/// recovers from every power loss.
+#[derive(Debug, Clone)]
+pub struct CrashTest {
+    seed: u64,
+    rounds: usize,
+    segment_size: usize,
+    damage: Vec<Damage>,
+}
+
+/// The result of `CrashTest::run`.
+#[derive(Debug, Default)]
+pub struct CrashReport {
+    /// The number of batches that were applied.
+    pub rounds: usize,
+    /// The number of batches that the power was cut during.
+    pub power_losses: usize,
+    /// How recovery went wrong, if it did. The test stops
+    /// at the first violation.
+    pub violation: Option<CrashViolation>,
+}
+
+impl CrashReport {
+    /// Returns `true` if no violation was found.
+    pub fn is_ok(&self) -> bool {
+        self.violation.is_none()
+    }
+}
+
+/// A failure to recover from a power loss, found by
+/// `CrashTest::run`.
+#[derive(Debug)]
+pub enum CrashViolation {
+    /// The database failed to open after the power loss.
+    Reopen {
+        /// The round whose batch the power was cut during.
+        round: usize,
+        /// The error returned by `Config::open`.
+        error: Error,
+    },
+    /// The database didn't contain what it did either before
+    /// or after the batch that the power was cut during.
+    Inconsistent {
+        /// The round whose batch the power was cut during.
+        round: usize,
+    },
+    /// `Db::verify` found a violation after the power loss.
+    /// Orphaned pages don't count, because a crash between
+    /// allocating a page and linking it into a tree leaks
+    /// the page without losing any data.
+    Structure {
+        /// The round whose batch the power was cut during.
+        round: usize,
+        /// The violation that was found.
+        violation: Violation,
+    },
+}
+
+type Contents = BTreeMap<IVec, IVec>;
+
+fn contents(db: &Db) -> Result<Contents> {
+    db.iter().collect()
+}
+
+impl CrashTest {
+    /// Creates a test of 100 rounds, whose workload and power
+    /// losses are all drawn from a `Simulation` seeded with
+    /// `seed`, so that a failing test can be replayed.
+    pub fn new(seed: u64) -> CrashTest {
+        CrashTest {
+            seed,
+            rounds: 100,
+            segment_size: 1024,
+            damage: vec![Damage::Truncate, Damage::BitFlip, Damage::Reorder],
+        }
+    }
+
+    /// Sets the number of batches to apply.
+    pub fn rounds(mut self, rounds: usize) -> CrashTest {
+        self.rounds = rounds;
+        self
+    }
+
+    /// Sets the segment size of the database, which is 1024
+    /// bytes by default so that short workloads still fill,
+    /// clean and reuse segments.
+    pub fn segment_size(mut self, segment_size: usize) -> CrashTest {
+        self.segment_size = segment_size;
+        self
+    }
+
+    /// Sets the ways in which the write that is in progress
+    /// when the power is cut may be damaged, one of which is
+    /// chosen at random for each power loss.
+    pub fn damage(mut self, damage: &[Damage]) -> CrashTest {
+        assert!(!damage.is_empty(), "at least one kind of damage is needed");
+        self.damage = damage.to_vec();
+        self
+    }
+
+    /// Runs the test. Each round asks `workload` for a batch,
+    /// applies it to the default `Tree` and flushes it, and
+    /// about one in four rounds has the power cut during one
+    /// of the writes that this makes. Errors that aren't
+    /// caused by a power loss are returned.
+    pub fn run<F>(&self, mut workload: F) -> Result<CrashReport>
+    where
+        F: FnMut(&Simulation) -> Batch,
+    {
+        let simulation = Simulation::new(self.seed);
+        let config = simulation.config().segment_size(self.segment_size);
+
+        let mut report = CrashReport::default();
+        let mut db = config.open()?;
+        let mut before = Contents::new();
+
+        for round in 0..self.rounds {
+            let batch = workload(&simulation);
+
+            let mut after = before.clone();
+            for (key, write) in &batch.writes {
+                if let Some(value) = write {
+                    let _ = after.insert(key.clone(), value.clone());
+                } else {
+                    let _ = after.remove(key);
+                }
+            }
+
+            if simulation.random(4) == 0 {
+                let kinds = self.damage.len() as u64;
+                let kind = usize::try_from(simulation.random(kinds)).unwrap();
+                let damage = self.damage[kind];
+                simulation.power_loss_at(1 + simulation.random(4), damage);
+            }
+
+            let crashes = simulation.crashes();
+            let res = db.apply_batch(batch).and_then(|()| db.flush());
+            report.rounds += 1;
+
+            if simulation.crashes() == crashes {
+                simulation.cancel_power_loss();
+                let _ = res?;
+                before = after;
+                simulation.tick(&db, Duration::from_millis(10))?;
+                continue;
+            }
+
+            report.power_losses += 1;
+            drop(db);
+
+            db = match config.open() {
+                Ok(reopened) => reopened,
+                Err(error) => {
+                    let violation = CrashViolation::Reopen { round, error };
+                    report.violation = Some(violation);
+                    return Ok(report);
+                }
+            };
+
+            let recovered = contents(&db)?;
+            if recovered == after {
+                before = after;
+            } else if recovered != before {
+                let violation = CrashViolation::Inconsistent { round };
+                report.violation = Some(violation);
+                return Ok(report);
+            }
+
+            let verified = db.verify()?;
+            let structural =
+                verified.violations.into_iter().find(|v| match v {
+                    Violation::OrphanedPage { .. } => false,
+                    _ => true,
+                });
+            if let Some(violation) = structural {
+                report.violation =
+                    Some(CrashViolation::Structure { round, violation });
+                return Ok(report);
+            }
+        }
+
+        Ok(report)
+    }
+}
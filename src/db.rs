@@ -1,4 +1,4 @@
-use std::ops::Deref;
+use std::{iter::FromIterator, ops::Deref};
 
 use crate::*;
 
@@ -7,10 +7,16 @@ use crate::*;
 /// a default keyspace.
 #[derive(Clone)]
 pub struct Db {
+    // declared first so that a scrub under way is stopped
+    // before the context waits for background work to end
+    #[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+    _scrubber: Option<Arc<Periodic>>,
     #[doc(hidden)]
     pub context: Context,
     pub(crate) default: Tree,
     tenants: Arc<RwLock<FastMap8<IVec, Tree>>>,
+    #[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+    _repairer: Option<Arc<Periodic>>,
 }
 
 /// Opens a `Db` with a default configuration at the
@@ -39,6 +45,57 @@ impl Deref for Db {
     }
 }
 
+impl IntoIterator for &'_ Db {
+    type Item = Result<(IVec, IVec)>;
+    type IntoIter = Iter;
+
+    fn into_iter(self) -> Iter {
+        self.iter()
+    }
+}
+
+impl IntoIterator for Db {
+    type Item = Result<(IVec, IVec)>;
+    type IntoIter = Iter;
+
+    fn into_iter(self) -> Iter {
+        self.iter()
+    }
+}
+
+/// Inserts the pairs into the default `Tree`, like the
+/// `Extend` implementation of `Tree`.
+impl<K, V> Extend<(K, V)> for Db
+where
+    IVec: From<K> + From<V>,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        self.default.extend(iter);
+    }
+}
+
+/// Collects the pairs into the default `Tree` of a new
+/// temporary database, which is removed once every handle
+/// to it is dropped.
+///
+/// # Panics
+///
+/// Panics if the temporary database can't be created or
+/// written to.
+impl<K, V> FromIterator<(K, V)> for Db
+where
+    IVec: From<K> + From<V>,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Db {
+        let mut db = Config::new()
+            .temporary(true)
+            .open()
+            .expect("failed to open temporary database");
+        db.extend(iter);
+        db
+    }
+}
+
 impl Debug for Db {
     fn fmt(
         &self,
@@ -83,7 +140,8 @@ impl Db {
 
         #[cfg(any(windows, target_os = "linux", target_os = "macos"))]
         {
-            if !context.read_only {
+            // a simulation runs the flusher itself, in `tick`
+            if !context.read_only && !context.is_simulated() {
                 let flusher_pagecache = context.pagecache.clone();
                 let flusher = context.flush_every_ms.map(move |fem| {
                     flusher::Flusher::new(
@@ -101,10 +159,39 @@ impl Db {
         let default =
             meta::open_tree(&context, DEFAULT_TREE_ID.to_vec(), &guard)?;
 
+        let tenants = Arc::new(RwLock::new(FastMap8::default()));
+
+        #[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+        let repairer = if context.repair_policy == RepairPolicy::Background
+            && !context.read_only
+        {
+            Some(Arc::new(repairer::spawn(
+                Arc::downgrade(&tenants),
+                context.repair_every_ms,
+            )))
+        } else {
+            None
+        };
+
+        #[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+        let scrubber = match context.scrub_every_ms {
+            Some(sem) if !context.read_only && !context.is_simulated() => {
+                Some(Arc::new(scrubber::spawn(
+                    Arc::downgrade(&context.pagecache),
+                    sem,
+                )))
+            }
+            _ => None,
+        };
+
         let ret = Self {
+            #[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+            _scrubber: scrubber,
             context: context.clone(),
             default,
-            tenants: Arc::new(RwLock::new(FastMap8::default())),
+            tenants,
+            #[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+            _repairer: repairer,
         };
 
         let mut tenants = ret.tenants.write();
@@ -117,12 +204,20 @@ impl Db {
                 root: AtomicU64::new(root),
                 concurrency_control: ConcurrencyControl::default(),
                 merge_operator: RwLock::new(None),
+                dup_sort: AtomicBool::new(false),
+                write_once: AtomicBool::new(false),
+                stale_snapshot: StaleSnapshot::default(),
+                indexes: Indexes::default(),
             }));
             assert!(tenants.insert(id, tree).is_none());
         }
 
         drop(tenants);
 
+        ret.recover_pages(&guard)?;
+
+        ret.context.pagecache.start_following();
+
         #[cfg(feature = "event_log")]
         ret.context.event_log.verify();
 
@@ -158,7 +253,10 @@ impl Db {
 
     /// Remove a disk-backed collection.
     pub fn drop_tree(&self, name: &[u8]) -> Result<bool> {
-        if name == DEFAULT_TREE_ID {
+        if name == DEFAULT_TREE_ID
+            || name == PAGES_TREE_ID
+            || name == content_store::BLOBS_TREE_ID
+        {
             return Err(Error::Unsupported(
                 "cannot remove the core structures".into(),
             ));
@@ -203,7 +301,7 @@ impl Db {
             }
         }
 
-        tree.root.store(u64::max_value(), SeqCst);
+        tree.root.store(u64::max_value(), Release);
 
         // drop writer lock
         drop(tenants);
@@ -215,6 +313,109 @@ impl Db {
         Ok(true)
     }
 
+    /// Takes a `ReadSnapshot` of every `Tree`, which keeps
+    /// seeing their items as they are now while writers carry
+    /// on. A snapshot is never taken in the middle of a batch
+    /// or transaction, so long scans see a consistent view,
+    /// even across trees. See `ReadSnapshot` for its costs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use sled::transaction::TransactionResult;
+    /// # fn main() -> TransactionResult<()> {
+    /// use sled::{Config, IVec, Transactional};
+    ///
+    /// let config = Config::new().temporary(true);
+    /// let db = config.open()?;
+    /// let checking = db.open_tree("checking")?;
+    /// let savings = db.open_tree("savings")?;
+    /// checking.insert("alice", "100")?;
+    /// savings.insert("alice", "0")?;
+    ///
+    /// let snapshot = db.snapshot();
+    ///
+    /// (&checking, &savings).transaction(|(checking, savings)| {
+    ///     checking.insert("alice", "40")?;
+    ///     savings.insert("alice", "60")?;
+    ///     Ok(())
+    /// })?;
+    ///
+    /// // the snapshot still sees the balances before the transfer
+    /// let checking_then = snapshot.open_tree("checking").unwrap();
+    /// let savings_then = snapshot.open_tree("savings").unwrap();
+    /// assert_eq!(checking_then.get("alice")?, Some(IVec::from("100")));
+    /// assert_eq!(savings_then.get("alice")?, Some(IVec::from("0")));
+    /// # Ok(()) }
+    /// ```
+    pub fn snapshot(&self) -> ReadSnapshot {
+        ReadSnapshot::take(&self.context, &self.default, &self.tenants)
+    }
+
+    /// Runs an optimistic transaction over any of the `Tree`s
+    /// of this `Db`, which are passed to the reads and writes
+    /// of the `OptimisticTransaction` that `f` is called with.
+    /// Nothing is locked while `f` runs, and its writes are
+    /// buffered until it returns. They are then applied
+    /// atomically if no page that `f` read a key from has
+    /// been written to since, and otherwise `f` is run again
+    /// after a backoff that grows up to a cap, until it
+    /// commits. Returning `Err(ConflictableTransactionError::Conflict)`
+    /// from `f` also runs it again. A run that is retried may
+    /// have read a mix of older and newer values, so `f`
+    /// should have no effects other than its writes.
+    ///
+    /// Unlike `Tree::transaction`, the `Tree`s don't have to
+    /// be named up front, so `f` may decide which ones to use
+    /// based on what it reads. Conflicts are detected for
+    /// whole pages, so a write to a neighboring key may also
+    /// cause `f` to run again. Writes made outside of
+    /// transactions are not held off while a transaction
+    /// commits.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use sled::transaction::TransactionResult;
+    /// # fn main() -> TransactionResult<()> {
+    /// use sled::{Config, IVec};
+    ///
+    /// let config = Config::new().temporary(true);
+    /// let db = config.open()?;
+    /// let accounts = db.open_tree("accounts")?;
+    /// let ledger = db.open_tree("ledger")?;
+    /// accounts.insert("alice", &100_u64.to_be_bytes())?;
+    ///
+    /// let balance = |value: Option<IVec>| {
+    ///     let mut bytes = [0; 8];
+    ///     bytes.copy_from_slice(&value.unwrap());
+    ///     u64::from_be_bytes(bytes)
+    /// };
+    ///
+    /// db.transact(|tx| {
+    ///     let alice = balance(tx.get(&accounts, "alice")?);
+    ///     tx.insert(&accounts, "alice", &(alice - 30).to_be_bytes())?;
+    ///     tx.insert(&accounts, "bob", &30_u64.to_be_bytes())?;
+    ///     tx.insert(&ledger, "alice->bob", "30")?;
+    ///     Ok(())
+    /// })?;
+    ///
+    /// assert_eq!(balance(accounts.get("alice")?), 70);
+    /// assert_eq!(balance(accounts.get("bob")?), 30);
+    /// # Ok(()) }
+    /// ```
+    pub fn transact<F, A, E>(
+        &self,
+        f: F,
+    ) -> transaction::TransactionResult<A, E>
+    where
+        F: Fn(
+            &transaction::OptimisticTransaction<'_>,
+        ) -> transaction::ConflictableTransactionResult<A, E>,
+    {
+        transaction::OptimisticTransaction::run(&self.context, f)
+    }
+
     /// Returns the trees names saved in this Db.
     pub fn tree_names(&self) -> Vec<IVec> {
         let tenants = self.tenants.read();
@@ -381,6 +582,91 @@ impl Db {
         }
     }
 
+    /// Writes every collection in the `Db` to `writer` as a
+    /// portable dump, and returns the number of key/value
+    /// pairs written. Unlike the on-disk format, the dump
+    /// format is versioned and stays readable by
+    /// `import_from` in every later version, so it can be
+    /// used to migrate between versions with incompatible
+    /// on-disk formats, or to move data in from other
+    /// stores. Every record in it carries a checksum.
+    ///
+    /// The dump is a consistent view of the whole `Db`,
+    /// which locks all underlying Trees for the duration of
+    /// the export. Writes are not buffered, so `writer`
+    /// should usually be wrapped in a `BufWriter`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let old = sled::Config::new().temporary(true).open()?;
+    /// old.insert(b"k1", b"v1")?;
+    /// old.open_tree(b"other")?.insert(b"k2", b"v2")?;
+    ///
+    /// let mut dump = vec![];
+    /// assert_eq!(old.export_to(&mut dump)?, 2);
+    ///
+    /// let new = sled::Config::new().temporary(true).open()?;
+    /// assert_eq!(new.import_from(&mut &dump[..])?, 2);
+    ///
+    /// assert_eq!(old.checksum()?, new.checksum()?);
+    /// # Ok(()) }
+    /// ```
+    pub fn export_to<W: Write>(&self, mut writer: W) -> Result<u64> {
+        let tenants_mu = self.tenants.write();
+
+        // the order is part of the format, so that exporting
+        // the same data twice produces the same dump
+        // the pages tree holds page ids, which mean nothing
+        // to the database that the dump is imported into
+        let tenants: BTreeMap<_, _> = tenants_mu
+            .iter()
+            .filter(|(name, _)| &***name != PAGES_TREE_ID)
+            .collect();
+
+        let mut locks = vec![];
+        for tree in tenants.values() {
+            locks.push(tree.concurrency_control.write());
+        }
+
+        dump::export(&tenants, &mut writer)
+    }
+
+    /// Imports a dump written by `export_to`, possibly by an
+    /// earlier version, and returns the number of key/value
+    /// pairs imported. Reads are not buffered, so `reader`
+    /// should usually be wrapped in a `BufReader`.
+    ///
+    /// Pairs are inserted as they are read, and the dump is
+    /// only known to be complete once its end is reached.
+    /// If an error is returned, such as when a record's
+    /// checksum does not match, the pairs before it may
+    /// already have been imported, so the dump should be
+    /// imported into a new database that is thrown away on
+    /// failure.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Unsupported` if the dump is malformed,
+    /// truncated, from a later version, or holds a key that
+    /// already exists in this `Db`.
+    pub fn import_from<R: Read>(&self, mut reader: R) -> Result<u64> {
+        dump::import(
+            &mut reader,
+            |name| self.open_tree(name),
+            |tree, k, v| {
+                let absent: Option<&[u8]> = None;
+                match tree.compare_and_swap(k, absent, Some(v))? {
+                    Ok(()) => Ok(()),
+                    Err(_) => Err(Error::Unsupported(
+                        "import is overwriting existing data".to_owned(),
+                    )),
+                }
+            },
+        )
+    }
+
     /// Returns the CRC32 of all keys and values
     /// in this Db.
     ///
@@ -416,12 +702,929 @@ impl Db {
         Ok(hasher.finalize())
     }
 
+    /// Verifies every `Tree` in this `Db` like
+    /// `Tree::verify`, and additionally reports any
+    /// allocated page that no tree references, such as
+    /// pages left behind by a crash while dropping a tree.
+    ///
+    /// This is O(N) and locks all underlying Trees for
+    /// the duration of the entire walk.
+    pub fn verify(&self) -> Result<VerifyReport> {
+        let tenants = self.tenants.write();
+
+        let mut locks = vec![];
+        for tree in tenants.values() {
+            locks.push(tree.concurrency_control.write());
+        }
+
+        let mut report = VerifyReport::default();
+        let mut reachable = FastSet8::default();
+
+        for tree in tenants.values() {
+            let mut tree_report = VerifyReport::default();
+            reachable.extend(verify::verify_tree(tree, &mut tree_report)?);
+            report.absorb(tree_report);
+        }
+
+        if let Some(pages) = tenants.get(PAGES_TREE_ID) {
+            let mut iter = pages.iter();
+            while let Some(kv_res) = iter.next_inner() {
+                let (k, _v) = kv_res?;
+                reachable.insert(pid_from_key(&k));
+            }
+        }
+
+        let guard = pin();
+        for pid in self.context.pagecache.allocated_pids(&guard) {
+            if !reachable.contains(&pid) {
+                report.violations.push(Violation::OrphanedPage { pid });
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Reads every log message and blob that makes up a page
+    /// of any tree back from disk, and checks them against
+    /// their checksums, so that bit rot is found before a
+    /// read of the page fails with `Error::Corruption`.
+    ///
+    /// A corrupt page that is resident in the cache is
+    /// written out again from memory, repairing it. Pages
+    /// that are not resident can't be repaired, and are
+    /// only reported. Either way, the corruption is logged.
+    ///
+    /// Messages that have not been synced to disk yet are
+    /// skipped rather than forcing a sync, and are left for
+    /// a later scrub.
+    ///
+    /// Writes carry on during a scrub, which reads each page
+    /// once and is not limited by
+    /// `Config::background_bytes_per_sec`. To scrub from a
+    /// background thread instead, set
+    /// `Config::scrub_every_ms`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// db.insert(b"a", b"1")?;
+    ///
+    /// let report = db.scrub()?;
+    /// assert!(report.is_ok());
+    /// assert_eq!(db.last_scrub(), Some(report));
+    /// # Ok(()) }
+    /// ```
+    pub fn scrub(&self) -> Result<ScrubReport> {
+        self.context.pagecache.scrub(false, || false)
+    }
+
+    /// Returns the outcome of the last `scrub` since the
+    /// database was opened, whether it was called directly
+    /// or run by the background thread of
+    /// `Config::scrub_every_ms`.
+    pub fn last_scrub(&self) -> Option<ScrubReport> {
+        self.context.pagecache.last_scrub()
+    }
+
+    /// Flushes all pending writes, and returns the log
+    /// sequence number up to which the database has been
+    /// written. This may be passed to `changes_between` to
+    /// refer to the state of the database at this point.
+    pub fn checkpoint(&self) -> Result<Lsn> {
+        self.flush()?;
+        Ok(self.context.pagecache.stable_lsn())
+    }
+
+    /// Copies the database into a new directory at `path`
+    /// without blocking writes, returning the lsn that the
+    /// copy is consistent at, as if the database had crashed
+    /// right after `checkpoint` returned it. The copy is
+    /// opened like any other database, and holds only the
+    /// parts of the log that are still in use.
+    ///
+    /// Segments of the log and blobs are not reclaimed while
+    /// the copy is being made, so the database may use more
+    /// space than usual in the meantime. Returns
+    /// `Error::Unsupported` if a database already exists at
+    /// `path`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let _ = std::fs::remove_dir_all("backup_to_example");
+    /// let config = sled::Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// db.insert(b"a", b"1")?;
+    /// db.backup_to("backup_to_example")?;
+    /// db.insert(b"b", b"2")?;
+    ///
+    /// let backup = sled::open("backup_to_example")?;
+    /// assert_eq!(backup.get(b"a")?, Some(sled::IVec::from(b"1")));
+    /// assert_eq!(backup.get(b"b")?, None);
+    /// # drop(backup);
+    /// # std::fs::remove_dir_all("backup_to_example")?;
+    /// # Ok(()) }
+    /// ```
+    pub fn backup_to<P: AsRef<std::path::Path>>(&self, path: P) -> Result<Lsn> {
+        self.context.pagecache.backup_to(path.as_ref())
+    }
+
+    /// Returns the net change to every key of every `Tree`
+    /// between two lsns returned by `checkpoint`, sorted by
+    /// tree name and then key. Keys that were modified and
+    /// then restored to their original value in between are
+    /// not included.
+    ///
+    /// The changes are computed by replaying the log from
+    /// its beginning, so this is O(N) in the size of the log,
+    /// and requires that no segment written before `to`
+    /// has been reclaimed yet, which is the case for
+    /// databases that have not grown past a few segments
+    /// or that don't overwrite or remove data. Otherwise
+    /// this returns `Error::Unsupported`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// db.insert(b"a", b"1")?;
+    /// db.insert(b"b", b"2")?;
+    /// let from = db.checkpoint()?;
+    ///
+    /// db.insert(b"a", b"3")?;
+    /// db.remove(b"b")?;
+    /// let to = db.checkpoint()?;
+    ///
+    /// let changes = db.changes_between(from, to)?;
+    /// assert_eq!(changes.len(), 2);
+    /// assert_eq!(changes[0].key, b"a");
+    /// assert_eq!(changes[0].before, Some(sled::IVec::from(b"1")));
+    /// assert_eq!(changes[0].after, Some(sled::IVec::from(b"3")));
+    /// assert_eq!(changes[1].key, b"b");
+    /// assert_eq!(changes[1].after, None);
+    /// # Ok(()) }
+    /// ```
+    pub fn changes_between(&self, from: Lsn, to: Lsn) -> Result<Vec<Change>> {
+        changes::changes_between(&self.context.pagecache, from, to)
+    }
+
+    /// Returns an iterator over the raw entries of the log
+    /// whose lsns fall within the given range, for auditing,
+    /// replicating, or debugging a database from the outside.
+    /// Use `LogEntries::page` to only see a single page.
+    ///
+    /// Only entries that were stable when this is called
+    /// are returned, so call `flush` or `checkpoint` first
+    /// to include recent writes. Entries in segments that
+    /// have already been reclaimed are skipped, and if a
+    /// segment is reclaimed while being iterated over, the
+    /// iterator returns `Error::Unsupported` and stops.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// let from = db.checkpoint()?;
+    /// db.insert(b"a", b"1")?;
+    /// let to = db.checkpoint()?;
+    ///
+    /// for entry in db.log_entries(from..=to) {
+    ///     let entry = entry?;
+    ///     assert!(entry.lsn >= from && entry.lsn <= to);
+    /// }
+    /// # Ok(()) }
+    /// ```
+    pub fn log_entries<R: std::ops::RangeBounds<Lsn>>(
+        &self,
+        lsns: R,
+    ) -> LogEntries {
+        LogEntries::new(&self.context.pagecache, lsns)
+    }
+
+    /// Returns a blocking iterator over every entry of the
+    /// log from the lsn `from` onwards, which waits for each
+    /// entry to become stable before returning it, for
+    /// shipping the log to read replicas or backup daemons.
+    /// Entries are only made stable by the background
+    /// flusher or by calls to `flush`.
+    ///
+    /// To resume shipping after the last entry that was
+    /// processed, pass its lsn plus one, or its lsn if it
+    /// was a `LogEntryKind::Free` entry, as several of those
+    /// may share one lsn. Shipping ends once the `Db` is
+    /// dropped. If `from` falls in a segment that has been
+    /// reclaimed, or a consumer falls so far behind that the
+    /// segments it has yet to read are reclaimed, this
+    /// returns `Error::Unsupported`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// let from = db.checkpoint()?;
+    /// let mut shipper = db.ship_log(from + 1);
+    ///
+    /// db.insert(b"a", b"1")?;
+    /// db.flush()?;
+    ///
+    /// let entry = shipper.next().unwrap()?;
+    /// assert!(entry.lsn > from);
+    /// # Ok(()) }
+    /// ```
+    pub fn ship_log(&self, from: Lsn) -> LogShipper {
+        LogShipper::new(&self.context.pagecache, from)
+    }
+
+    /// Applies entries shipped from a primary's log to a
+    /// database opened with `Config::replica`, in one atomic
+    /// batch. Entries that were already applied are skipped,
+    /// so it is safe to apply overlapping ranges of the log,
+    /// and `replication_lsn` returns where the next range
+    /// has to start. Entries have to be applied in the order
+    /// that the primary wrote them, without gaps.
+    ///
+    /// Returns `Error::Unsupported` if this isn't a replica,
+    /// or if an entry is malformed, in which case the entries
+    /// before it are still applied.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let primary = sled::Config::new().temporary(true).open()?;
+    /// let replica =
+    ///     sled::Config::new().temporary(true).replica(true).open()?;
+    ///
+    /// primary.insert(b"a", b"1")?;
+    /// let to = primary.checkpoint()?;
+    ///
+    /// let from = replica.replication_lsn().unwrap();
+    /// let entries = primary.log_entries(from..=to);
+    /// replica.apply_log_entries(entries.collect::<sled::Result<Vec<_>>>()?)?;
+    ///
+    /// assert_eq!(replica.get(b"a")?, Some(sled::IVec::from(b"1")));
+    /// assert!(replica.insert(b"b", b"2").is_err());
+    /// # Ok(()) }
+    /// ```
+    pub fn apply_log_entries<I>(&self, entries: I) -> Result<()>
+    where
+        I: IntoIterator<Item = LogEntry>,
+    {
+        let res = self.context.pagecache.apply_replicated(entries);
+
+        // the entries may have created or dropped trees, or
+        // moved their roots, and even a failed batch applies
+        // the entries before the one that failed
+        self.refresh_tenants()?;
+
+        res
+    }
+
+    /// Returns the lsn in the primary's log that entries
+    /// have to be shipped from next, or `None` if this isn't
+    /// a replica.
+    pub fn replication_lsn(&self) -> Option<Lsn> {
+        self.context.pagecache.replication_lsn()
+    }
+
+    /// Turns a replica into a regular database that accepts
+    /// writes, for failing over once its primary is gone. A
+    /// promoted database stays one across restarts, and
+    /// no longer accepts shipped entries.
+    pub fn promote(&self) -> Result<()> {
+        self.context.pagecache.promote()
+    }
+
+    // Makes the open trees match the catalog after it was
+    // changed underneath them by `apply_log_entries`.
+    fn refresh_tenants(&self) -> Result<()> {
+        let guard = pin();
+        let roots = self.context.pagecache.get_meta(&guard)?.tenants();
+
+        let mut tenants = self.tenants.write();
+
+        tenants.retain(|name, tree| {
+            if roots.contains_key(name) {
+                true
+            } else {
+                tree.root.store(u64::max_value(), Release);
+                false
+            }
+        });
+
+        for (name, root) in roots {
+            if let Some(tree) = tenants.get(&name) {
+                tree.root.store(root, Release);
+                continue;
+            }
+
+            let tree = Tree(Arc::new(TreeInner {
+                tree_id: name.clone(),
+                subscribers: Subscribers::default(),
+                context: self.context.clone(),
+                root: AtomicU64::new(root),
+                concurrency_control: ConcurrencyControl::default(),
+                merge_operator: RwLock::new(None),
+                dup_sort: AtomicBool::new(false),
+                write_once: AtomicBool::new(false),
+                stale_snapshot: StaleSnapshot::default(),
+                indexes: Indexes::default(),
+            }));
+            assert!(tenants.insert(name, tree).is_none());
+        }
+
+        if let Some(tree) = tenants.get(DEFAULT_TREE_ID) {
+            self.default.root.store(tree.root.load(Acquire), Release);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the version number last stored with
+    /// `set_user_version`, or 0 if it has never been set.
+    pub fn get_user_version(&self) -> Result<u32> {
+        let guard = pin();
+        Ok(self.context.pagecache.get_meta(&guard)?.user_version)
+    }
+
+    /// Stores a version number for the application's own use,
+    /// such as tracking which schema migrations have been
+    /// applied, without setting aside a key in a `Tree`. Like
+    /// SQLite's `user_version`, sled never interprets it.
+    ///
+    /// The version is stored in the same page as the roots of
+    /// every `Tree`, which is replaced atomically, so after a
+    /// crash it is either the old or the new version. Like
+    /// other writes, it is durable once `flush` returns.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// if db.get_user_version()? < 1 {
+    ///     // migrate the data to the first schema version
+    ///     db.set_user_version(1)?;
+    /// }
+    /// assert_eq!(db.get_user_version()?, 1);
+    /// # Ok(()) }
+    /// ```
+    pub fn set_user_version(&self, user_version: u32) -> Result<()> {
+        if self.context.read_only {
+            return Err(Error::Unsupported(
+                "the database is in read-only mode".to_owned(),
+            ));
+        }
+        let guard = pin();
+        self.context.pagecache.set_user_version(user_version, &guard)
+    }
+
+    /// Stores `bytes` under their SHA-256 hash, which is
+    /// returned, and adds a reference to them. Storing the
+    /// same bytes again only adds a reference, so each
+    /// distinct blob is stored once however many times it
+    /// is put. Blobs longer than `Config::overflow_threshold`
+    /// are stored in their own pages.
+    ///
+    /// Blobs are kept in an internal `Tree` named
+    /// `__sled__blobs`, which is created by the first call
+    /// to `put_blob`, can't be dropped, and is exported and
+    /// checksummed along with the other trees.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// // two messages with the same attachment
+    /// let hash = db.put_blob(b"attachment")?;
+    /// db.insert(b"message 1", &hash)?;
+    /// assert_eq!(db.put_blob(b"attachment")?, hash);
+    /// db.insert(b"message 2", &hash)?;
+    ///
+    /// assert_eq!(db.get_blob(&hash)?, Some(sled::IVec::from(b"attachment")));
+    ///
+    /// // the blob is removed along with its last reference
+    /// db.remove(b"message 1")?;
+    /// db.release_blob(&hash)?;
+    /// assert!(db.get_blob(&hash)?.is_some());
+    ///
+    /// db.remove(b"message 2")?;
+    /// db.release_blob(&hash)?;
+    /// assert_eq!(db.get_blob(&hash)?, None);
+    /// # Ok(()) }
+    /// ```
+    pub fn put_blob<V: AsRef<[u8]>>(&self, bytes: V) -> Result<BlobHash> {
+        let blobs = self.open_tree(content_store::BLOBS_TREE_ID)?;
+        content_store::put_blob(&blobs, bytes.as_ref())
+    }
+
+    /// Retrieves the blob with the given hash, if any
+    /// references to it remain. See `Db::put_blob`.
+    pub fn get_blob(&self, hash: &BlobHash) -> Result<Option<IVec>> {
+        if let Some(blobs) = self.blobs() {
+            content_store::get_blob(&blobs, hash)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Removes a reference to the blob with the given hash
+    /// that was added by `Db::put_blob`, removing the blob
+    /// itself once no references remain. Returns `false` if
+    /// the blob did not exist.
+    pub fn release_blob(&self, hash: &BlobHash) -> Result<bool> {
+        if let Some(blobs) = self.blobs() {
+            content_store::release_blob(&blobs, hash)
+        } else {
+            Ok(false)
+        }
+    }
+
+    // the tree of blobs is only created by the first
+    // `Db::put_blob`, so that reads don't add it
+    fn blobs(&self) -> Option<Tree> {
+        self.tenants.read().get(content_store::BLOBS_TREE_ID).cloned()
+    }
+
+    /// Allocates a page outside of any `Tree`, holding `page`,
+    /// and returns its id. Such pages are consolidated by the
+    /// `Materializer` set with `Config::materializer`, and are
+    /// kept until they are removed with `Db::free_page`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// struct Concatenate;
+    ///
+    /// impl sled::Materializer for Concatenate {
+    ///     fn merge(&self, page: &[u8], frags: &[&[u8]]) -> Vec<u8> {
+    ///         let mut ret = page.to_vec();
+    ///         for frag in frags {
+    ///             ret.extend_from_slice(frag);
+    ///         }
+    ///         ret
+    ///     }
+    /// }
+    ///
+    /// let config =
+    ///     sled::Config::new().temporary(true).materializer(Concatenate);
+    /// let db = config.open()?;
+    ///
+    /// let pid = db.allocate_page(b"a")?;
+    /// db.link_page(pid, b"b")?;
+    /// db.link_page(pid, b"c")?;
+    /// assert_eq!(db.get_page(pid)?, Some(sled::IVec::from(b"abc")));
+    ///
+    /// assert!(db.free_page(pid)?);
+    /// assert_eq!(db.get_page(pid)?, None);
+    /// # Ok(()) }
+    /// ```
+    pub fn allocate_page<V: Into<IVec>>(&self, page: V) -> Result<u64> {
+        let pages = self.open_tree(PAGES_TREE_ID)?;
+
+        let guard = pin();
+        let pid =
+            self.context.pagecache.allocate_custom(page.into(), &guard)?;
+
+        pages.insert(pid.to_be_bytes(), vec![])?;
+
+        Ok(pid)
+    }
+
+    /// Retrieves a page allocated with `Db::allocate_page`,
+    /// with all of the fragments linked onto it applied.
+    /// Returns `None` if the page does not exist or was not
+    /// allocated with `Db::allocate_page`.
+    pub fn get_page(&self, pid: u64) -> Result<Option<IVec>> {
+        let guard = pin();
+        let view_opt = self.context.pagecache.get_custom(pid, &guard)?;
+        Ok(view_opt.map(|view| (*view).clone()))
+    }
+
+    /// Links `frag` onto a page allocated with `Db::allocate_page`.
+    /// The fragment is written to the log on its own, and is
+    /// applied with `Materializer::merge`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Unsupported` if the page does not exist,
+    /// or if `Config::materializer` is not set.
+    pub fn link_page<V: Into<IVec>>(&self, pid: u64, frag: V) -> Result<()> {
+        let pagecache = &self.context.pagecache;
+        let mut frag_ivec = frag.into();
+        let guard = pin();
+
+        loop {
+            let view_opt = pagecache.get_custom(pid, &guard)?;
+            let view = if let Some(view) = view_opt {
+                view
+            } else {
+                return Err(Error::Unsupported(format!(
+                    "page {} was not allocated with `Db::allocate_page`",
+                    pid
+                )));
+            };
+
+            match pagecache.link_custom(pid, view, frag_ivec, &guard)? {
+                Ok(_) => return Ok(()),
+                Err(Some((_, returned))) => frag_ivec = returned,
+                Err(None) => {
+                    return Err(Error::Unsupported(format!(
+                        "page {} was freed while being linked onto",
+                        pid
+                    )));
+                }
+            }
+        }
+    }
+
+    /// Frees a page allocated with `Db::allocate_page`. Returns
+    /// `false` if the page did not exist. The freed ID is
+    /// recorded in the log, and is handed out again by later
+    /// allocations, including after a restart.
+    pub fn free_page(&self, pid: u64) -> Result<bool> {
+        let pages = self.open_tree(PAGES_TREE_ID)?;
+        // the page is forgotten before its ID is freed, so a
+        // crash in between may leak the ID, but can't leave it
+        // recorded as a page after it was handed out again
+        if pages.remove(pid.to_be_bytes())?.is_none() {
+            return Ok(false);
+        }
+
+        let guard = pin();
+        while let Some(view) = self.context.pagecache.get_custom(pid, &guard)? {
+            if self.context.pagecache.free(pid, view.0, &guard)?.is_ok() {
+                break;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Returns the IDs of the pages, of trees as well as
+    /// those allocated with `Db::allocate_page`, that have
+    /// been written to since the log was last made stable,
+    /// in ascending order.
+    pub fn dirty_pages(&self) -> Vec<u64> {
+        self.context.pagecache.dirty_pages()
+    }
+
+    /// Makes every write to the page with the given ID
+    /// durable, without waiting for writes that were made to
+    /// other pages afterwards. Returns the number of bytes
+    /// written during this call, which is 0 if the page had
+    /// no pending writes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # struct Concatenate;
+    /// # impl sled::Materializer for Concatenate {
+    /// #     fn merge(&self, page: &[u8], frags: &[&[u8]]) -> Vec<u8> {
+    /// #         let mut ret = page.to_vec();
+    /// #         frags.iter().for_each(|frag| ret.extend_from_slice(frag));
+    /// #         ret
+    /// #     }
+    /// # }
+    /// let config = sled::Config::new()
+    ///     .temporary(true)
+    ///     .flush_every_ms(None)
+    ///     .materializer(Concatenate);
+    /// let db = config.open()?;
+    ///
+    /// let pid = db.allocate_page(b"a")?;
+    /// assert!(db.dirty_pages().contains(&pid));
+    ///
+    /// db.flush_page(pid)?;
+    /// assert!(!db.dirty_pages().contains(&pid));
+    /// # Ok(()) }
+    /// ```
+    pub fn flush_page(&self, pid: u64) -> Result<usize> {
+        self.context.pagecache.flush_page(pid)
+    }
+
+    /// Makes every write to the pages returned by
+    /// `Db::dirty_pages` durable, which leaves none of them
+    /// dirty. Returns the number of bytes written during
+    /// this call.
+    pub fn flush_dirty(&self) -> Result<usize> {
+        self.context.pagecache.flush_dirty()
+    }
+
+    // hands each page allocated with `Db::allocate_page` to
+    // `Materializer::recover` while the database is opened.
+    fn recover_pages(&self, guard: &Guard) -> Result<()> {
+        let pages = if let Some(pages) = self.tenants.read().get(PAGES_TREE_ID)
+        {
+            pages.clone()
+        } else {
+            return Ok(());
+        };
+
+        for key in pages.iter().keys() {
+            let pid = pid_from_key(&key?);
+            let materializer =
+                self.context.materializer.as_ref().ok_or_else(|| {
+                    Error::Unsupported(
+                        "this database contains pages allocated with \
+                         `Db::allocate_page`, and can only be opened \
+                         when `Config::materializer` is set"
+                            .into(),
+                    )
+                })?;
+
+            if let Some(view) = self.context.pagecache.get_custom(pid, guard)? {
+                materializer.recover(pid, &view);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Calls `on_violation` from a background thread at the
+    /// end of every `slo.window` in which the latency of
+    /// `slo.operation` at `slo.percentile` exceeded
+    /// `slo.threshold`, until the returned `SloMonitor` is
+    /// dropped.
+    ///
+    /// Latencies are taken from sled's metrics, which are
+    /// shared by every `Db` in this process, and are only
+    /// collected when sled is built with the `metrics`
+    /// feature. Otherwise this returns `Error::Unsupported`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::time::Duration;
+    ///
+    /// use sled::{LatencySlo, SloOperation};
+    ///
+    /// let db = sled::open("my_db")?;
+    ///
+    /// let slo = LatencySlo {
+    ///     operation: SloOperation::Get,
+    ///     percentile: 99.9,
+    ///     threshold: Duration::from_millis(5),
+    ///     window: Duration::from_secs(60),
+    /// };
+    ///
+    /// let _monitor = db.register_latency_slo(slo, |violation| {
+    ///     eprintln!(
+    ///         "p{} get latency was {:?} over the last minute",
+    ///         violation.slo.percentile, violation.observed,
+    ///     );
+    /// })?;
+    /// # Ok(()) }
+    /// ```
+    pub fn register_latency_slo<F>(
+        &self,
+        slo: LatencySlo,
+        on_violation: F,
+    ) -> Result<SloMonitor>
+    where
+        F: Fn(&SloViolation) + Send + 'static,
+    {
+        SloMonitor::start(slo, on_violation)
+    }
+
     /// Returns the on-disk size of the storage files
-    /// for this database.
+    /// for this database. On linux, segments of the log
+    /// that have been freed by garbage collection have
+    /// their space returned to the filesystem, and are
+    /// not counted here.
     pub fn size_on_disk(&self) -> Result<u64> {
         self.context.pagecache.size_on_disk()
     }
 
+    /// Returns an approximate breakdown of the memory held
+    /// by the database's cache and the structures around it,
+    /// for embedders that need to budget memory among
+    /// several caches in one process. This visits every
+    /// page, so it should not be called in a hot loop.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// db.insert(b"a", vec![0; 1024])?;
+    ///
+    /// let usage = db.memory_usage();
+    /// assert!(usage.resident_pages >= 1024);
+    /// assert!(usage.total() > usage.resident_pages);
+    /// # Ok(()) }
+    /// ```
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.context.pagecache.memory_usage()
+    }
+
+    /// Reads the pages that were the most recently accessed
+    /// when the database was last closed with
+    /// `Config::warm_cache_pages` set back into the cache,
+    /// and returns how many of them had to be read from
+    /// disk. With that option set, this already happens in
+    /// the background on open, and calling this blocks until
+    /// the cache is warm, such as before serving requests.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let _ = std::fs::remove_dir_all("warm_cache_example");
+    /// let config = sled::Config::new()
+    ///     .path("warm_cache_example")
+    ///     .warm_cache_pages(1024);
+    ///
+    /// let db = config.open()?;
+    /// db.insert(b"a", b"1")?;
+    /// drop(db);
+    ///
+    /// let db = config.open()?;
+    /// db.warm_cache()?;
+    /// assert_eq!(db.warm_cache()?, 0);
+    /// # drop(db);
+    /// # std::fs::remove_dir_all("warm_cache_example")?;
+    /// # Ok(()) }
+    /// ```
+    pub fn warm_cache(&self) -> Result<usize> {
+        self.context.pagecache.warm_up()
+    }
+
+    /// Returns a breakdown of the space used by the log,
+    /// blob and snapshot files, the live and dead bytes of
+    /// every segment of the log, and the resulting space and
+    /// write amplification, to help decide when garbage
+    /// collection should run and how large a disk needs to
+    /// be. This visits every page, so it should not be
+    /// called in a hot loop.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// db.insert(b"a", vec![0; 1024])?;
+    /// db.flush()?;
+    ///
+    /// let stats = db.space_stats()?;
+    /// assert!(stats.live_bytes >= 1024);
+    /// println!(
+    ///     "{} live and {} dead bytes in {} segments",
+    ///     stats.live_bytes,
+    ///     stats.dead_bytes,
+    ///     stats.segments.len(),
+    /// );
+    /// # Ok(()) }
+    /// ```
+    pub fn space_stats(&self) -> Result<SpaceStats> {
+        self.context.pagecache.space_stats()
+    }
+
+    /// Returns counters of the IO done since the database
+    /// was opened: the bytes written to the log, the pages
+    /// read back in from it, the fsyncs made and the time
+    /// spent on them, and the pages relocated by garbage
+    /// collection. Comparing two of these shows whether a
+    /// slowdown comes from write amplification, from reads
+    /// that miss the cache, or from waiting on syncs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let _ = std::fs::remove_dir_all("io_stats_example");
+    /// let db = sled::open("io_stats_example")?;
+    ///
+    /// let before = db.io_stats();
+    /// db.insert(b"a", vec![0; 1024])?;
+    /// db.flush()?;
+    /// let after = db.io_stats();
+    ///
+    /// assert!(after.log_bytes_written >= before.log_bytes_written + 1024);
+    /// assert!(after.fsyncs > before.fsyncs);
+    /// # drop(db);
+    /// # std::fs::remove_dir_all("io_stats_example")?;
+    /// # Ok(()) }
+    /// ```
+    pub fn io_stats(&self) -> IoStats {
+        self.context.pagecache.io_stats()
+    }
+
+    /// Synchronously runs garbage collection over every
+    /// segment of the log that is no longer being written to,
+    /// relocating and consolidating all of their pages, so
+    /// that heavy maintenance can be scheduled for quiet
+    /// periods instead of being left to the background
+    /// flusher. This is `compact_to(100)`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// db.insert(b"a", b"1")?;
+    ///
+    /// let stats = db.compact()?;
+    /// println!(
+    ///     "relocated {} pages, writing {} bytes",
+    ///     stats.pages_relocated, stats.bytes_relocated,
+    /// );
+    /// # Ok(()) }
+    /// ```
+    pub fn compact(&self) -> Result<CompactionStats> {
+        self.compact_to(100)
+    }
+
+    /// Synchronously runs garbage collection over every
+    /// segment of the log that is no longer being written to
+    /// and is at most `target_utilization` percent live, as
+    /// reported by `space_stats`. The log is flushed before
+    /// choosing the segments, so that recent writes are
+    /// considered, and again after relocating their pages.
+    /// The drained segments are reused once the segments
+    /// that their pages were relocated to fill up.
+    pub fn compact_to(
+        &self,
+        target_utilization: u8,
+    ) -> Result<CompactionStats> {
+        self.compact_inner(target_utilization, None)
+    }
+
+    /// Synchronously runs garbage collection over the single
+    /// segment at `offset` in the log file, as reported by
+    /// `space_stats`, however live it is. Segments that are
+    /// still being written to are left alone.
+    pub fn compact_segment(&self, offset: u64) -> Result<CompactionStats> {
+        self.compact_inner(u8::max_value(), Some(offset))
+    }
+
+    fn compact_inner(
+        &self,
+        target_utilization: u8,
+        only: Option<u64>,
+    ) -> Result<CompactionStats> {
+        let _ = self.context.pagecache.flush()?;
+        let stats = self.context.pagecache.compact(target_utilization, only)?;
+        let _ = self.context.pagecache.flush()?;
+        Ok(stats)
+    }
+
+    /// Returns the counts and latencies of gets, writes, page
+    /// faults, consolidations, fsyncs and garbage collection
+    /// relocations. These are collected for all databases in
+    /// the process together, and only with the `metrics`
+    /// feature. Without it, every count is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// db.insert(b"a", b"1")?;
+    /// db.get(b"a")?;
+    ///
+    /// let metrics = db.metrics();
+    /// println!(
+    ///     "{} gets with a p99 of {:?}",
+    ///     metrics.gets.count, metrics.gets.p99,
+    /// );
+    /// # Ok(()) }
+    /// ```
+    pub fn metrics(&self) -> MetricsSnapshot {
+        M.snapshot()
+    }
+
     /// Traverses all files and calculates their total physical
     /// size, then traverses all pages and calculates their
     /// total logical size, then divides the physical size
@@ -432,6 +1635,12 @@ impl Db {
     }
 }
 
+fn pid_from_key(key: &[u8]) -> PageId {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(key);
+    PageId::from_be_bytes(buf)
+}
+
 /// These types provide the information that allows an entire
 /// system to be exported and imported to facilitate
 /// major upgrades. It is comprised entirely
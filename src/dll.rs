@@ -182,6 +182,12 @@ impl DoublyLinkedList {
         }
     }
 
+    /// Iterates over the items from the head, which was
+    /// pushed or promoted last, to the tail.
+    pub(crate) const fn iter(&self) -> Iter<'_> {
+        Iter { cursor: self.head, _list: self }
+    }
+
     #[cfg(test)]
     pub(crate) fn into_vec(mut self) -> Vec<PageId> {
         let mut res = vec![];
@@ -192,6 +198,25 @@ impl DoublyLinkedList {
     }
 }
 
+pub(crate) struct Iter<'a> {
+    cursor: *mut Node,
+    _list: &'a DoublyLinkedList,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = PageId;
+
+    fn next(&mut self) -> Option<PageId> {
+        if self.cursor.is_null() {
+            return None;
+        }
+        // the list is borrowed, so its nodes can't be freed
+        let node = unsafe { &*self.cursor };
+        self.cursor = node.prev;
+        Some(node.inner)
+    }
+}
+
 #[allow(unused_results)]
 #[test]
 fn test_dll() {
@@ -206,5 +231,6 @@ fn test_dll() {
     dll.push_head(2);
     dll.push_head(1);
     assert_eq!(dll.len(), 9);
+    assert_eq!(dll.iter().collect::<Vec<_>>(), vec![1, 2, 3, 4, 5, 6, 7, 8, 9]);
     assert_eq!(dll.into_vec(), vec![1, 2, 3, 4, 5, 6, 7, 8, 9]);
 }
@@ -1,6 +1,6 @@
 //! ## Built-In Profiler
 //! 
-//! To get a summary of latency histograms relating to different operations you've used on a sled database, sled can print a nice table when the Db is dropped by disabling the `no_metrics` default feature and setting `print_profile_on_drop(true)` on a `ConfigBuilder`:
+//! To get a summary of latency histograms relating to different operations you've used on a sled database, sled can print a nice table when the Db is dropped by enabling the `metrics` feature and setting `print_profile_on_drop(true)` on a `ConfigBuilder`:
 //! 
 //! ```rust
 //! let config = sled::ConfigBuilder::new()
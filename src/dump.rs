@@ -0,0 +1,227 @@
+//! A portable format for the contents of a `Db`, written by
+//! `Db::export_to` and read back by `Db::import_from`.
+//!
+//! A dump starts with `MAGIC` and a little-endian `u32`
+//! version, followed by records. Each record is a kind
+//! byte, its fields, and a crc32 of the kind and fields.
+//! Byte string fields are prefixed with their length as a
+//! little-endian `u64`. A `TREE` record holds the name of
+//! the tree that the `KV` records after it belong to, and
+//! the `END` record holds the number of `KV` records in the
+//! dump, so that a truncated dump is detected.
+//!
+//! This format must be readable by every later version, so
+//! changes to it require bumping `VERSION` and keeping the
+//! ability to read the older versions.
+
+use std::convert::TryInto;
+
+use crate::*;
+
+const MAGIC: &[u8; 8] = b"rsdbdump";
+const VERSION: u32 = 1;
+
+const TREE: u8 = 1;
+const KV: u8 = 2;
+const END: u8 = 3;
+
+struct RecordWriter<'a, W: Write> {
+    writer: &'a mut W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<'a, W: Write> RecordWriter<'a, W> {
+    fn start(writer: &'a mut W, kind: u8) -> Result<RecordWriter<'a, W>> {
+        let mut record =
+            RecordWriter { writer, hasher: crc32fast::Hasher::new() };
+        record.write(&[kind])?;
+        Ok(record)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<()> {
+        self.hasher.update(buf);
+        self.writer.write_all(buf)?;
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.write(&(bytes.len() as u64).to_le_bytes())?;
+        self.write(bytes)
+    }
+
+    fn finish(self) -> Result<()> {
+        let crc = self.hasher.finalize();
+        self.writer.write_all(&crc.to_le_bytes())?;
+        Ok(())
+    }
+}
+
+struct RecordReader<'a, R: Read> {
+    reader: &'a mut R,
+    hasher: crc32fast::Hasher,
+}
+
+impl<'a, R: Read> RecordReader<'a, R> {
+    fn new(reader: &'a mut R) -> RecordReader<'a, R> {
+        RecordReader { reader, hasher: crc32fast::Hasher::new() }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<()> {
+        self.reader.read_exact(buf).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                malformed("it is truncated")
+            } else {
+                e.into()
+            }
+        })?;
+        self.hasher.update(buf);
+        Ok(())
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        let mut buf = [0; 8];
+        self.read(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn read_bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.read_u64()?;
+
+        // a corrupted length is only detected by the crc
+        // after it, so the buffer grows as bytes arrive
+        // instead of being allocated up front
+        let mut bytes = vec![];
+        let _ = self.reader.by_ref().take(len).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != len {
+            return Err(malformed("it is truncated"));
+        }
+
+        self.hasher.update(&bytes);
+        Ok(bytes)
+    }
+
+    fn finish(mut self) -> Result<()> {
+        let crc = self.hasher.clone().finalize();
+        let mut buf = [0; 4];
+        self.read(&mut buf)?;
+        if u32::from_le_bytes(buf) != crc {
+            return Err(malformed("a record has a bad checksum"));
+        }
+        Ok(())
+    }
+}
+
+fn malformed(why: &str) -> Error {
+    Error::Unsupported(format!("the dump can't be imported, because {}", why))
+}
+
+/// Writes every key and value of `trees` to `writer`,
+/// returning the number of pairs written.
+pub(crate) fn export<W: Write>(
+    trees: &BTreeMap<&IVec, &Tree>,
+    writer: &mut W,
+) -> Result<u64> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+
+    let mut count: u64 = 0;
+
+    for (name, tree) in trees {
+        let mut tree_record = RecordWriter::start(writer, TREE)?;
+        tree_record.write_bytes(name)?;
+        tree_record.finish()?;
+
+        let mut iter = tree.iter();
+        while let Some(kv_res) = iter.next_inner() {
+            let (k, v) = kv_res?;
+            let mut kv_record = RecordWriter::start(writer, KV)?;
+            kv_record.write_bytes(&k)?;
+            kv_record.write_bytes(&v)?;
+            kv_record.finish()?;
+            count += 1;
+        }
+    }
+
+    let mut end_record = RecordWriter::start(writer, END)?;
+    end_record.write(&count.to_le_bytes())?;
+    end_record.finish()?;
+
+    writer.flush()?;
+
+    Ok(count)
+}
+
+/// Reads a dump written by `export` from `reader`, passing
+/// each tree name and each key and value in it to
+/// `open_tree` and `insert` as soon as its record has been
+/// verified, and returns the number of pairs read once the
+/// end of the dump has been reached.
+pub(crate) fn import<R, O, I>(
+    reader: &mut R,
+    mut open_tree: O,
+    mut insert: I,
+) -> Result<u64>
+where
+    R: Read,
+    O: FnMut(Vec<u8>) -> Result<Tree>,
+    I: FnMut(&Tree, Vec<u8>, Vec<u8>) -> Result<()>,
+{
+    let mut header = [0; 12];
+    RecordReader::new(reader).read(&mut header)?;
+    if &header[..8] != MAGIC {
+        return Err(malformed("it does not start with the dump header"));
+    }
+
+    let version = u32::from_le_bytes(header[8..].try_into().unwrap());
+    if version > VERSION {
+        return Err(Error::Unsupported(format!(
+            "the dump has format version {}, but this version of \
+             the database only reads up to version {}",
+            version, VERSION
+        )));
+    }
+
+    let mut tree = None;
+    let mut count: u64 = 0;
+
+    loop {
+        let mut record = RecordReader::new(reader);
+
+        let mut kind = [0];
+        record.read(&mut kind)?;
+
+        match kind[0] {
+            TREE => {
+                let name = record.read_bytes()?;
+                record.finish()?;
+                tree = Some(open_tree(name)?);
+            }
+            KV => {
+                let k = record.read_bytes()?;
+                let v = record.read_bytes()?;
+                record.finish()?;
+                let current = tree.as_ref().ok_or_else(|| {
+                    malformed("a key comes before any tree name")
+                })?;
+                insert(current, k, v)?;
+                count += 1;
+            }
+            END => {
+                let expected = record.read_u64()?;
+                record.finish()?;
+                if expected != count {
+                    return Err(malformed(
+                        "it does not hold as many pairs as it should",
+                    ));
+                }
+                return Ok(count);
+            }
+            other => {
+                return Err(malformed(&format!(
+                    "it has a record of unknown kind {}",
+                    other
+                )));
+            }
+        }
+    }
+}
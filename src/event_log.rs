@@ -27,7 +27,7 @@ use std::collections::HashMap;
 use crate::pagecache::DiskPtr;
 use crate::*;
 
-use crate::stack::{Iter as StackIter, Stack};
+use crate::stack::Stack;
 
 /// A thing that happens at a certain time.
 #[derive(Debug, Clone)]
@@ -52,14 +52,9 @@ impl EventLog {
         while let Some(_) = self.inner.pop(&guard) {}
     }
 
-    fn iter<'a>(&self, guard: &'a Guard) -> StackIter<'a, Event> {
-        let head = self.inner.head(guard);
-        StackIter::from_ptr(head, guard)
-    }
-
     pub(crate) fn verify(&self) {
         let guard = pin();
-        let iter = self.iter(&guard);
+        let iter = self.inner.iter(&guard);
 
         // if we encounter a `PagesOnRecovery`, then we should
         // compare it to any subsequent `PagesOnShutdown`
@@ -1,29 +1,118 @@
-use std::collections::HashSet;
+use std::sync::Arc;
 
 use parking_lot::Mutex;
 
-use crate::Lazy;
+use crate::FastMap8;
 
-type HS =
-    HashSet<&'static str, std::hash::BuildHasherDefault<fxhash::FxHasher64>>;
+/// A plan for making the named failpoints of one database
+/// fail, set with `Config::fault_plan`. Clones share the
+/// same plan, so a test can keep one to change the plan
+/// while the database is running, and across restarts.
+///
+/// Probabilistic failpoints draw from a random number
+/// generator seeded by `FaultPlan::new`, so a test that
+/// performs the same operations in the same order fails in
+/// the same places every time. Because every database has
+/// its own plan, such tests can run concurrently.
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let plan = sled::fail::FaultPlan::new(42);
+/// let config =
+///     sled::Config::new().temporary(true).fault_plan(plan.clone());
+/// let db = config.open()?;
+///
+/// db.insert(b"a", b"1")?;
+/// plan.set("buffer write");
+/// assert_eq!(db.flush(), Err(sled::Error::FailPoint));
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FaultPlan(Arc<Mutex<Plan>>);
 
-static ACTIVE: Lazy<Mutex<HS>, fn() -> Mutex<HS>> = Lazy::new(init);
-
-fn init() -> Mutex<HS> {
-    Mutex::new(HS::default())
+#[derive(Debug, Default)]
+struct Plan {
+    // the state of a splitmix64 generator
+    rng: u64,
+    sites: FastMap8<&'static str, Site>,
 }
 
-/// Returns `true` if the given failpoint is active.
-pub fn is_active(name: &'static str) -> bool {
-    ACTIVE.lock().contains(&name)
+#[derive(Debug, Clone, Copy)]
+enum Site {
+    Probability(f64),
+    // the number of times the failpoint has been reached,
+    // and the number at which it fails
+    At { hits: u64, at: u64 },
 }
 
-/// Enable a particular failpoint
-pub fn set(name: &'static str) {
-    ACTIVE.lock().insert(name);
+impl Plan {
+    fn next_u64(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // a uniformly distributed number in [0, 1)
+    #[allow(clippy::cast_precision_loss)]
+    #[allow(clippy::float_arithmetic)]
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
 }
 
-/// Clear all active failpoints.
-pub fn reset() {
-    ACTIVE.lock().clear();
+impl FaultPlan {
+    /// Creates a plan with no active failpoints, whose
+    /// probabilistic failpoints are decided by a random
+    /// number generator seeded with `seed`.
+    pub fn new(seed: u64) -> FaultPlan {
+        FaultPlan(Arc::new(Mutex::new(Plan {
+            rng: seed,
+            sites: FastMap8::default(),
+        })))
+    }
+
+    /// Makes the named failpoint fail every time it is
+    /// reached.
+    pub fn set(&self, name: &'static str) {
+        self.set_probability(name, 1.);
+    }
+
+    /// Makes the named failpoint fail with the given
+    /// probability each time it is reached.
+    pub fn set_probability(&self, name: &'static str, probability: f64) {
+        let site = Site::Probability(probability);
+        let _ = self.0.lock().sites.insert(name, site);
+    }
+
+    /// Makes the named failpoint fail only the `n`th time
+    /// that it is reached after this call, counting from 1.
+    pub fn set_at(&self, name: &'static str, n: u64) {
+        let _ = self.0.lock().sites.insert(name, Site::At { hits: 0, at: n });
+    }
+
+    /// Clears all active failpoints.
+    pub fn reset(&self) {
+        self.0.lock().sites.clear();
+    }
+
+    /// Returns `true` if the given failpoint should fail
+    /// this time that it is reached.
+    pub(crate) fn is_active(&self, name: &str) -> bool {
+        let mut plan = self.0.lock();
+        match plan.sites.get_mut(name) {
+            None => false,
+            Some(Site::At { hits, at }) => {
+                *hits += 1;
+                hits == at
+            }
+            Some(Site::Probability(probability)) => {
+                let probability = *probability;
+                probability >= 1. || plan.next_f64() < probability
+            }
+        }
+    }
 }
@@ -72,15 +72,23 @@ fn run(
     let mut shutdown = shutdown.lock();
     let mut wrote_data = false;
     while shutdown.is_running() || wrote_data {
-        let before = std::time::Instant::now();
-        match pagecache.flush() {
+        let before = pagecache.config.now();
+        pagecache.update_maintenance_pressure();
+
+        // IO buffers are still written as they fill up while
+        // periodic flushes are throttled
+        let throttled =
+            shutdown.is_running() && pagecache.maintenance_throttled();
+        let flushed = if throttled { Ok(0) } else { pagecache.flush() };
+        match flushed {
             Ok(0) => {
                 wrote_data = false;
                 if !shutdown.is_running() {
                     break;
                 }
             }
-            Ok(_) => {
+            Ok(written) => {
+                pagecache.charge_maintenance(written as u64, 1);
                 wrote_data = true;
                 if !shutdown.is_running() {
                     // loop right away if we're in
@@ -114,39 +122,61 @@ fn run(
         // this looks weird because it's a rust-style do-while
         // where the conditional is the full body
         while {
-            let made_progress = match pagecache.attempt_gc() {
-                Err(e) => {
-                    error!(
-                        "failed to clean file from periodic flush thread: {}",
-                        e
-                    );
+            // nothing is relocated while throttled
+            let made_progress = !pagecache.maintenance_throttled()
+                && match pagecache.attempt_gc() {
+                    Err(e) => {
+                        error!(
+                            "failed to clean file from periodic flush thread: {}",
+                            e
+                        );
+
+                        #[cfg(feature = "failpoints")]
+                        pagecache.set_failpoint(e);
+
+                        *shutdown = ShutdownState::ShutDown;
+
+                        // having held the mutex makes this linearized
+                        // with the notify below.
+                        drop(shutdown);
+
+                        let _notified = sc.notify_all();
+                        return;
+                    }
+                    Ok(false) => false,
+                    Ok(true) => true,
+                };
+            made_progress
+                && shutdown.is_running()
+                && (pagecache.config.now() - before) < flush_every / 2
+        } {}
 
-                    #[cfg(feature = "failpoints")]
-                    pagecache.set_failpoint(e);
+        if shutdown.is_running() {
+            if let Err(e) = pagecache.snapshot_if_due() {
+                error!("failed to snapshot from periodic flush thread: {}", e);
 
-                    *shutdown = ShutdownState::ShutDown;
+                #[cfg(feature = "failpoints")]
+                pagecache.set_failpoint(e);
 
-                    // having held the mutex makes this linearized
-                    // with the notify below.
-                    drop(shutdown);
+                *shutdown = ShutdownState::ShutDown;
 
-                    let _notified = sc.notify_all();
-                    return;
-                }
-                Ok(false) => false,
-                Ok(true) => true,
-            };
-            made_progress
-                && shutdown.is_running()
-                && before.elapsed() < flush_every / 2
-        } {}
+                // having held the mutex makes this linearized
+                // with the notify below.
+                drop(shutdown);
+
+                let _notified = sc.notify_all();
+                return;
+            }
+        }
 
-        if let Err(e) = pagecache.config.file.sync_all() {
-            error!("failed to fsync from periodic flush thread: {}", e);
+        if !pagecache.config.in_memory && !throttled {
+            if let Err(e) = pagecache.config.file.sync_all() {
+                error!("failed to fsync from periodic flush thread: {}", e);
+            }
         }
 
         let sleep_duration = flush_every
-            .checked_sub(before.elapsed())
+            .checked_sub(pagecache.config.now() - before)
             .unwrap_or_else(|| Duration::from_millis(1));
 
         if shutdown.is_running() {
@@ -76,7 +76,7 @@ impl Histogram {
     /// Record a value.
     #[inline]
     pub fn measure(&self, raw_value: u64) {
-        #[cfg(not(feature = "no_metrics"))]
+        #[cfg(feature = "metrics")]
         {
             let value_float: f64 = raw_value as f64;
             self.sum.fetch_add(value_float.round() as usize, Ordering::Relaxed);
@@ -95,7 +95,7 @@ impl Histogram {
     /// Retrieve a percentile [0-100]. Returns NAN if no metrics have been
     /// collected yet.
     pub fn percentile(&self, p: f64) -> f64 {
-        #[cfg(not(feature = "no_metrics"))]
+        #[cfg(feature = "metrics")]
         {
             assert!(p <= 100., "percentiles must not exceed 100.0");
 
@@ -139,6 +139,66 @@ impl Histogram {
     pub fn count(&self) -> usize {
         self.count.load(Ordering::Acquire)
     }
+
+    /// Copy the current bucket counts, so that percentiles
+    /// can later be computed over only the observations
+    /// recorded after this point.
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            vals: self.vals.iter().map(|v| v.load(Ordering::Acquire)).collect(),
+        }
+    }
+}
+
+/// The bucket counts of a `Histogram` at one point in time.
+pub(crate) struct Snapshot {
+    vals: Vec<usize>,
+}
+
+impl Snapshot {
+    /// Return the count of observations recorded between
+    /// `earlier` and this snapshot.
+    pub(crate) fn count_since(&self, earlier: &Snapshot) -> usize {
+        self.vals
+            .iter()
+            .zip(&earlier.vals)
+            .map(|(now, then)| now.saturating_sub(*then))
+            .sum()
+    }
+
+    /// Retrieve a percentile [0-100] of the observations
+    /// recorded between `earlier` and this snapshot. Returns
+    /// NAN if nothing was recorded in between.
+    #[allow(clippy::cast_precision_loss)]
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn percentile_since(&self, earlier: &Snapshot, p: f64) -> f64 {
+        assert!(p <= 100., "percentiles must not exceed 100.0");
+
+        let count = self.count_since(earlier);
+
+        if count == 0 {
+            return std::f64::NAN;
+        }
+
+        let mut target = count as f64 * (p / 100.);
+        if target == 0. {
+            target = 1.;
+        }
+
+        let mut sum = 0.;
+
+        for (idx, (now, then)) in
+            self.vals.iter().zip(&earlier.vals).enumerate()
+        {
+            sum += now.saturating_sub(*then) as f64;
+
+            if sum >= target {
+                return decompress(idx as u16);
+            }
+        }
+
+        std::f64::NAN
+    }
 }
 
 // compress takes a value and lossily shrinks it to an u16 to facilitate
@@ -167,15 +227,15 @@ fn decompress(compressed: u16) -> f64 {
     (unboosted.exp() - 1.)
 }
 
-#[cfg(not(feature = "no_metrics"))]
+#[cfg(feature = "metrics")]
 #[test]
 fn it_works() {
     let c = Histogram::default();
-    assert_eq!(c.measure(2), 1);
-    assert_eq!(c.measure(2), 2);
-    assert_eq!(c.measure(3), 1);
-    assert_eq!(c.measure(3), 2);
-    assert_eq!(c.measure(4), 1);
+    c.measure(2);
+    c.measure(2);
+    c.measure(3);
+    c.measure(3);
+    c.measure(4);
     assert_eq!(c.percentile(0.).round() as usize, 2);
     assert_eq!(c.percentile(40.).round() as usize, 2);
     assert_eq!(c.percentile(40.1).round() as usize, 3);
@@ -185,7 +245,7 @@ fn it_works() {
     c.print_percentiles();
 }
 
-#[cfg(not(feature = "no_metrics"))]
+#[cfg(feature = "metrics")]
 #[test]
 fn high_percentiles() {
     let c = Histogram::default();
@@ -210,7 +270,7 @@ fn high_percentiles() {
     assert_eq!(c.percentile(100.).round() as usize, 502);
 }
 
-#[cfg(not(feature = "no_metrics"))]
+#[cfg(feature = "metrics")]
 #[test]
 fn multithreaded() {
     use std::sync::Arc;
@@ -232,3 +292,25 @@ fn multithreaded() {
 
     assert_eq!(h.percentile(50.).round() as usize, 20);
 }
+
+#[test]
+fn snapshot_percentiles() {
+    let mut earlier = Snapshot { vals: vec![0; BUCKETS] };
+    earlier.vals[compress(10) as usize] = 5;
+
+    let mut later = Snapshot { vals: earlier.vals.clone() };
+    for (value, count) in &[(10, 90), (100, 9), (1000, 1)] {
+        later.vals[compress(*value) as usize] += count;
+    }
+
+    let close_to = |p: f64, expected: f64| {
+        let actual = later.percentile_since(&earlier, p);
+        (actual - expected).abs() / expected < 0.01
+    };
+
+    assert_eq!(later.count_since(&earlier), 100);
+    assert!(later.percentile_since(&later, 50.).is_nan());
+    assert!(close_to(50., 10.));
+    assert!(close_to(99., 100.));
+    assert!(close_to(100., 1000.));
+}
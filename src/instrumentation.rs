@@ -0,0 +1,153 @@
+//! Hooks into the major internal code paths, which let the
+//! latency of calls be correlated with what the database was
+//! doing, for instance by forwarding them to `tracing` spans.
+//! They are only called with the `instrumentation` feature,
+//! and compile to nothing without it.
+
+#[cfg(feature = "instrumentation")]
+use std::{
+    fmt::{self, Debug},
+    sync::Arc,
+};
+
+use crate::Config;
+
+/// A stretch of internal work that an `Instrumentation`
+/// is told about when it is entered and exited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Span {
+    /// Descending from a tree's root to the leaf for a key.
+    TreeDescent,
+    /// Reserving space in the log's IO buffers for a write.
+    LogReservation,
+    /// Waiting for the log to become durable, as `flush` does.
+    Flush,
+    /// Reading the latest snapshot and the log written
+    /// after it when the database is opened.
+    RecoverSnapshot,
+    /// Rebuilding the state of the log's segments when the
+    /// database is opened.
+    RecoverSegments,
+    /// Loading the page table from the recovered snapshot
+    /// when the database is opened.
+    RecoverPages,
+}
+
+/// A point-in-time occurrence that an `Instrumentation` is
+/// told about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InstrumentationEvent {
+    /// A tree operation lost a race with a concurrent
+    /// operation, usually a failed page CAS, and retried.
+    TreeRetry,
+    /// A log reservation found the current IO buffer sealed
+    /// or full, or lost a race for space in it, and retried.
+    LogReservationRetry,
+}
+
+/// Receives the `Span`s and `InstrumentationEvent`s of a
+/// database, as set with `Config::instrumentation`. Spans
+/// are entered and exited on the thread that does the work,
+/// and nest within each other and within the caller's own
+/// spans. Requires the `instrumentation` feature.
+///
+/// # Examples
+///
+/// Forwarding to the `tracing` crate:
+///
+/// ```ignore
+/// use std::cell::RefCell;
+///
+/// use sled::{Instrumentation, InstrumentationEvent, Span};
+///
+/// thread_local! {
+///     static ENTERED: RefCell<Vec<tracing::span::EnteredSpan>> =
+///         RefCell::new(vec![]);
+/// }
+///
+/// struct Tracing;
+///
+/// impl Instrumentation for Tracing {
+///     fn enter(&self, span: Span) {
+///         let span = tracing::trace_span!("sled", span = ?span);
+///         ENTERED.with(|e| e.borrow_mut().push(span.entered()));
+///     }
+///
+///     fn exit(&self, _: Span) {
+///         ENTERED.with(|e| e.borrow_mut().pop());
+///     }
+///
+///     fn event(&self, event: InstrumentationEvent) {
+///         tracing::trace!(event = ?event, "sled");
+///     }
+/// }
+///
+/// let db = sled::Config::new().instrumentation(Tracing).open()?;
+/// ```
+#[cfg(feature = "instrumentation")]
+pub trait Instrumentation: Send + Sync {
+    /// Called when a `Span` is entered.
+    fn enter(&self, span: Span) {
+        let _ = span;
+    }
+
+    /// Called when a `Span` is exited, after any spans that
+    /// were entered within it have been exited.
+    fn exit(&self, span: Span) {
+        let _ = span;
+    }
+
+    /// Called when an `InstrumentationEvent` happens.
+    fn event(&self, event: InstrumentationEvent) {
+        let _ = event;
+    }
+}
+
+#[cfg(feature = "instrumentation")]
+impl Debug for dyn Instrumentation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Instrumentation")
+    }
+}
+
+/// Exits its `Span` when dropped.
+#[cfg(feature = "instrumentation")]
+pub(crate) struct Entered {
+    instrumentation: Arc<dyn Instrumentation>,
+    span: Span,
+}
+
+#[cfg(feature = "instrumentation")]
+impl Drop for Entered {
+    fn drop(&mut self) {
+        self.instrumentation.exit(self.span);
+    }
+}
+
+/// Enters `span` until the returned guard is dropped.
+#[cfg(feature = "instrumentation")]
+pub(crate) fn enter(config: &Config, span: Span) -> Option<Entered> {
+    let instrumentation = config.instrumentation.clone()?;
+    instrumentation.enter(span);
+    Some(Entered { instrumentation, span })
+}
+
+#[cfg(not(feature = "instrumentation"))]
+pub(crate) struct Entered;
+
+#[cfg(not(feature = "instrumentation"))]
+#[inline]
+pub(crate) const fn enter(_: &Config, _: Span) -> Entered {
+    Entered
+}
+
+#[cfg(feature = "instrumentation")]
+pub(crate) fn event(config: &Config, event: InstrumentationEvent) {
+    if let Some(instrumentation) = &config.instrumentation {
+        instrumentation.event(event);
+    }
+}
+
+#[cfg(not(feature = "instrumentation"))]
+#[inline]
+pub(crate) const fn event(_: &Config, _: InstrumentationEvent) {}
@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+/// Counters of the IO a `Db` has done since it was opened,
+/// returned by `Db::io_stats`. Comparing two of them shows
+/// whether a slowdown comes from writing more than the
+/// workload does, from reading pages back in from disk, or
+/// from waiting on the disk to make writes durable.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoStats {
+    /// The bytes written to the log, including the headers
+    /// of messages and the padding at the ends of segments,
+    /// but not the files that values too large for the log
+    /// are stored in.
+    pub log_bytes_written: u64,
+    /// How many times a page that was not resident in the
+    /// cache was read back in from the log.
+    pub page_ins: u64,
+    /// The bytes of the log messages read to do that.
+    pub page_in_bytes: u64,
+    /// How many times writes to the log were made durable,
+    /// whether by `flush`, the periodic flush thread, or
+    /// writes with a `Durability` other than `Buffered`.
+    pub fsyncs: u64,
+    /// The total time spent making writes to the log
+    /// durable.
+    pub fsync_time: Duration,
+    /// How many pages garbage collection and compaction
+    /// rewrote to the tip of the log to free the segments
+    /// they were in.
+    pub gc_relocated_pages: u64,
+    /// The bytes written to the log by those rewrites, which
+    /// are also counted in `log_bytes_written`.
+    pub gc_relocated_bytes: u64,
+}
@@ -1,3 +1,5 @@
+#[cfg(feature = "async")]
+use std::collections::VecDeque;
 use std::ops::{Bound, Deref};
 
 use crate::{Guard, Measure, M};
@@ -35,6 +37,30 @@ macro_rules! iter_try {
 }
 
 /// An iterator over keys and values in a `Tree`.
+///
+/// While the `Tree` is written to during a scan, in either
+/// direction:
+///
+/// * every key that is present from the start of the scan
+///   to its end is returned, with one of the values it had
+///   along the way,
+/// * no key is returned twice, and keys are returned in
+///   strictly ascending (or, from the back, descending)
+///   order,
+/// * keys that are inserted or removed during the scan may
+///   or may not be returned.
+///
+/// This holds across concurrent splits and merges of the
+/// nodes being read. The iterator keeps a copy of the leaf
+/// it is reading, along with the epoch guard it was read
+/// under, and otherwise only remembers the last key it
+/// returned. Whenever it reads another node, it checks the
+/// node's low and high keys to tell whether the node still
+/// covers the keys after that one, and looks the key up
+/// again from the root if it has been split or merged away.
+///
+/// Use `Db::snapshot` for a scan that sees a single point in
+/// time.
 pub struct Iter {
     pub(super) tree: Tree,
     pub(super) hi: Bound<IVec>,
@@ -54,6 +80,42 @@ impl Iter {
         self.map(|r| r.map(|(_k, v)| v))
     }
 
+    /// Turns this into an `AsyncIter` over the keys and
+    /// values that it has not returned yet.
+    #[cfg(feature = "async")]
+    pub fn into_async(self) -> AsyncIter {
+        AsyncIter {
+            tree: self.tree,
+            lo: self.lo,
+            hi: self.hi,
+            buffered: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    // Returns the items up to and including the first one
+    // in the leaf after the one that the first item is in,
+    // stopping early at an error, and whether the iterator
+    // was exhausted.
+    #[cfg(feature = "async")]
+    fn next_leaf(&mut self) -> (Vec<Result<(IVec, IVec)>>, bool) {
+        let mut items = vec![];
+        let mut leaf = None;
+
+        while let Some(item) = self.next() {
+            let failed = item.is_err();
+            items.push(item);
+
+            let pid = self.cached_node.as_ref().map(|(pid, _, _)| *pid);
+            if failed || (leaf.is_some() && pid != leaf) {
+                return (items, false);
+            }
+            leaf = pid;
+        }
+
+        (items, true)
+    }
+
     fn bounds_collapsed(&self) -> bool {
         match (&self.lo, &self.hi) {
             (Bound::Included(ref start), Bound::Included(ref end))
@@ -81,7 +143,17 @@ impl Iter {
         }
     }
 
+    // Doesn't take the `Tree`'s concurrency control, so that
+    // operations that hold it, like `bulk_load`, can iterate.
     pub(crate) fn next_inner(&mut self) -> Option<<Self as Iterator>::Item> {
+        self.step_forward(false)
+    }
+
+    fn step_forward(
+        &mut self,
+        protect: bool,
+    ) -> Option<<Self as Iterator>::Item> {
+        let cached_pid = self.cached_node.as_ref().map(|(pid, _, _)| *pid);
         let (mut pid, mut node, guard) =
             if let (true, Some((pid, node, guard))) =
                 (self.going_forward, self.cached_node.take())
@@ -89,10 +161,14 @@ impl Iter {
                 (pid, node, guard)
             } else {
                 let guard = pin();
-                let view =
-                    iter_try!(self.tree.view_for_key(self.low_key(), &guard));
+                let view = iter_try!(
+                    self.tree.read_view_for_key(self.low_key(), &guard)
+                );
                 (view.pid, view.deref().clone(), guard)
             };
+        if protect {
+            let _ = self.tree.concurrency_control.read(&guard);
+        }
 
         for _ in 0..MAX_LOOPS {
             if self.bounds_collapsed() {
@@ -104,11 +180,16 @@ impl Iter {
                 let next_pid = node.next?;
                 assert_ne!(pid, next_pid);
                 let view = if let Some(view) =
-                    iter_try!(self.tree.view_for_pid(next_pid, &guard))
-                {
+                    iter_try!(self.tree.view_for_pid_inner(
+                        next_pid,
+                        self.tree.read_repair(),
+                        &guard
+                    )) {
                     view
                 } else {
-                    iter_try!(self.tree.view_for_key(self.low_key(), &guard))
+                    iter_try!(
+                        self.tree.read_view_for_key(self.low_key(), &guard)
+                    )
                 };
 
                 pid = view.pid;
@@ -117,13 +198,32 @@ impl Iter {
             } else if !node.contains_lower_bound(&self.lo, true) {
                 // view too high (maybe split, maybe exhausted?)
                 let seek_key = possible_predecessor(&node.lo)?;
-                let view = iter_try!(self.tree.view_for_key(seek_key, &guard));
+                let view =
+                    iter_try!(self.tree.read_view_for_key(seek_key, &guard));
                 pid = view.pid;
                 node = view.deref().clone();
                 continue;
             }
 
-            if let Some((key, value)) = node.successor(&self.lo) {
+            if let Some((key, stored)) = node.successor(&self.lo) {
+                let value = if let Some(ivec) =
+                    iter_try!(self.tree.resolve_value(&stored, &guard))
+                {
+                    ivec
+                } else {
+                    // the value's overflow page was freed by a
+                    // concurrent write, so re-read its leaf
+                    let view =
+                        iter_try!(self.tree.read_view_for_key(&key, &guard));
+                    pid = view.pid;
+                    node = view.deref().clone();
+                    continue;
+                };
+
+                if cached_pid != Some(pid) || !self.going_forward {
+                    self.tree.prefetch_after(&node);
+                }
+
                 self.lo = Bound::Excluded(key.clone());
                 self.cached_node = Some((pid, node, guard));
                 self.going_forward = true;
@@ -158,9 +258,7 @@ impl Iterator for Iter {
 
     fn next(&mut self) -> Option<Self::Item> {
         let _measure = Measure::new(&M.tree_scan);
-        let guard = pin();
-        let _ = self.tree.concurrency_control.read(&guard);
-        self.next_inner()
+        self.step_forward(true)
     }
 
     fn last(mut self) -> Option<Self::Item> {
@@ -171,19 +269,22 @@ impl Iterator for Iter {
 impl DoubleEndedIterator for Iter {
     fn next_back(&mut self) -> Option<Self::Item> {
         let _measure = Measure::new(&M.tree_reverse_scan);
-        let guard = pin();
-        let _ = self.tree.concurrency_control.read(&guard);
 
+        // a cached node comes with the guard it was read
+        // under, so only pin when there isn't one
         let (mut pid, mut node, guard) =
             if let (false, Some((pid, node, guard))) =
                 (self.going_forward, self.cached_node.take())
             {
                 (pid, node, guard)
             } else {
-                let view =
-                    iter_try!(self.tree.view_for_key(self.high_key(), &guard));
+                let guard = pin();
+                let view = iter_try!(
+                    self.tree.read_view_for_key(self.high_key(), &guard)
+                );
                 (view.pid, view.deref().clone(), guard)
             };
+        let _ = self.tree.concurrency_control.read(&guard);
 
         for _ in 0..MAX_LOOPS {
             if self.bounds_collapsed() {
@@ -195,11 +296,16 @@ impl DoubleEndedIterator for Iter {
                 let next_pid = node.next?;
                 assert_ne!(pid, next_pid);
                 let view = if let Some(view) =
-                    iter_try!(self.tree.view_for_pid(next_pid, &guard))
-                {
+                    iter_try!(self.tree.view_for_pid_inner(
+                        next_pid,
+                        self.tree.read_repair(),
+                        &guard
+                    )) {
                     view
                 } else {
-                    iter_try!(self.tree.view_for_key(self.high_key(), &guard))
+                    iter_try!(
+                        self.tree.read_view_for_key(self.high_key(), &guard)
+                    )
                 };
 
                 pid = view.pid;
@@ -208,13 +314,28 @@ impl DoubleEndedIterator for Iter {
             } else if !node.contains_lower_bound(&self.hi, false) {
                 // view too high (maybe split, maybe exhausted?)
                 let seek_key = possible_predecessor(&node.lo)?;
-                let view = iter_try!(self.tree.view_for_key(seek_key, &guard));
+                let view =
+                    iter_try!(self.tree.read_view_for_key(seek_key, &guard));
                 pid = view.pid;
                 node = view.deref().clone();
                 continue;
             }
 
-            if let Some((key, value)) = node.predecessor(&self.hi) {
+            if let Some((key, stored)) = node.predecessor(&self.hi) {
+                let value = if let Some(ivec) =
+                    iter_try!(self.tree.resolve_value(&stored, &guard))
+                {
+                    ivec
+                } else {
+                    // the value's overflow page was freed by a
+                    // concurrent write, so re-read its leaf
+                    let view =
+                        iter_try!(self.tree.read_view_for_key(&key, &guard));
+                    pid = view.pid;
+                    node = view.deref().clone();
+                    continue;
+                };
+
                 self.hi = Bound::Excluded(key.clone());
                 self.cached_node = Some((pid, node, guard));
                 self.going_forward = false;
@@ -244,6 +365,70 @@ impl DoubleEndedIterator for Iter {
     }
 }
 
+/// An asynchronous iterator over keys and values in a
+/// `Tree`, created with `Iter::into_async`. Items are read
+/// a leaf at a time on sled's IO threadpool, so awaiting
+/// them never blocks the calling thread on reading the log.
+///
+/// # Examples
+///
+/// ```
+/// # async fn example() -> sled::Result<()> {
+/// let config = sled::Config::new().temporary(true);
+/// let t = config.open()?;
+///
+/// t.insert(&[1], vec![10])?;
+/// t.insert(&[2], vec![20])?;
+///
+/// let mut iter = t.iter().into_async();
+/// while let Some(item) = iter.next().await {
+///     let (key, value) = item?;
+///     assert_eq!(value[0], key[0] * 10);
+/// }
+/// # Ok(()) }
+/// ```
+#[cfg(feature = "async")]
+pub struct AsyncIter {
+    tree: Tree,
+    lo: Bound<IVec>,
+    hi: Bound<IVec>,
+    buffered: VecDeque<Result<(IVec, IVec)>>,
+    done: bool,
+}
+
+#[cfg(feature = "async")]
+impl AsyncIter {
+    /// Returns the next key and value, or `None` once the
+    /// iterator is exhausted. After an error, the next call
+    /// continues after the last key that was returned.
+    pub async fn next(&mut self) -> Option<Result<(IVec, IVec)>> {
+        if self.buffered.is_empty() && !self.done {
+            let (tree, lo, hi) =
+                (self.tree.clone(), self.lo.clone(), self.hi.clone());
+            let (items, done) = threadpool::spawn(move || {
+                let mut iter = Iter {
+                    tree,
+                    lo,
+                    hi,
+                    cached_node: None,
+                    going_forward: true,
+                };
+                iter.next_leaf()
+            })
+            .await;
+
+            self.buffered.extend(items);
+            self.done = done;
+        }
+
+        let item = self.buffered.pop_front()?;
+        if let Ok((ref key, _)) = item {
+            self.lo = Bound::Excluded(key.clone());
+        }
+        Some(item)
+    }
+}
+
 #[test]
 fn test_possible_predecessor() {
     assert_eq!(possible_predecessor(b""), None);
@@ -26,6 +26,10 @@ impl Default for IVec {
 enum IVecInner {
     Inline(u8, Inner),
     Remote(Arc<[u8]>),
+    // an offset and length into a buffer shared with other
+    // values, like those that a leaf packs together, which
+    // are narrow enough to keep an IVec at 24 bytes
+    Subslice(Arc<[u8]>, u32, u16),
 }
 
 impl Hash for IVec {
@@ -60,11 +64,40 @@ impl IVec {
         Self(IVecInner::Remote(arc))
     }
 
+    /// Refers to `len` bytes of `base` from `offset` on,
+    /// without copying them.
+    pub(crate) fn subslice(
+        base: &Arc<[u8]>,
+        offset: usize,
+        len: usize,
+    ) -> Self {
+        assert!(offset + len <= base.len());
+        Self(IVecInner::Subslice(
+            base.clone(),
+            u32::try_from(offset).unwrap(),
+            u16::try_from(len).unwrap(),
+        ))
+    }
+
+    /// Whether the bytes are in an allocation of their own.
+    pub(crate) fn is_remote(&self) -> bool {
+        if let IVecInner::Remote(_) = self.0 { true } else { false }
+    }
+
+    /// Whether the bytes are in a buffer shared with others.
+    pub(crate) fn is_subslice(&self) -> bool {
+        if let IVecInner::Subslice(..) = self.0 { true } else { false }
+    }
+
     fn make_mut(&mut self) {
         match self.0 {
             IVecInner::Remote(ref mut buf) if Arc::strong_count(buf) != 1 => {
                 self.0 = IVecInner::Remote(buf.to_vec().into());
             }
+            IVecInner::Subslice(..) => {
+                let copy: Arc<[u8]> = Arc::from(self.as_ref());
+                self.0 = IVecInner::Remote(copy);
+            }
             _ => {}
         }
     }
@@ -170,8 +203,8 @@ from_array!(
 impl Into<Arc<[u8]>> for IVec {
     fn into(self) -> Arc<[u8]> {
         match self.0 {
-            IVecInner::Inline(..) => Arc::from(self.as_ref()),
             IVecInner::Remote(arc) => arc,
+            _ => Arc::from(self.as_ref()),
         }
     }
 }
@@ -194,6 +227,10 @@ impl AsRef<[u8]> for IVec {
                 buf.get_unchecked(..*sz as usize)
             },
             IVecInner::Remote(buf) => buf,
+            IVecInner::Subslice(base, offset, len) => {
+                let start = *offset as usize;
+                &base[start..start + *len as usize]
+            }
         }
     }
 }
@@ -216,6 +253,9 @@ impl AsMut<[u8]> for IVec {
                 std::slice::from_raw_parts_mut(buf.as_mut_ptr(), *sz as usize)
             },
             IVecInner::Remote(ref mut buf) => Arc::get_mut(buf).unwrap(),
+            IVecInner::Subslice(..) => {
+                unreachable!("make_mut copies subslices out")
+            }
         }
     }
 }
@@ -259,3 +299,19 @@ fn ivec_usage() {
     let iv2 = IVec::from(&[4; 128][..]);
     assert_eq!(iv2, vec![4; 128]);
 }
+
+#[test]
+fn ivec_subslice() {
+    let base: Arc<[u8]> = Arc::from(&[1, 2, 3, 4, 5][..]);
+    let mut iv = IVec::subslice(&base, 1, 3);
+    assert_eq!(iv, vec![2, 3, 4]);
+    assert_eq!(iv, IVec::from(vec![2, 3, 4]));
+
+    // writing to it leaves the shared buffer alone
+    iv[0] = 9;
+    assert_eq!(iv, vec![9, 3, 4]);
+    assert_eq!(&*base, &[1, 2, 3, 4, 5]);
+
+    let arc: Arc<[u8]> = IVec::subslice(&base, 3, 2).into();
+    assert_eq!(&*arc, &[4, 5]);
+}
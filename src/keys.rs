@@ -0,0 +1,325 @@
+//! Keys that sort in the same order as the values they were
+//! encoded from.
+//!
+//! Keys are compared byte by byte, so integers written with
+//! `to_le_bytes`, or signed integers written with
+//! `to_be_bytes`, are scanned out of numeric order. The
+//! types here encode integers big-endian, with the sign bit
+//! of signed integers flipped so that negative numbers sort
+//! first, and `KeyBuilder` joins several of them, and byte
+//! strings, into a key that sorts like a tuple of them.
+
+use std::convert::TryFrom;
+
+use crate::IVec;
+
+/// A key that sorts in the numeric order of a `u64`.
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use sled::U64Key;
+///
+/// let config = sled::Config::new().temporary(true);
+/// let tree = config.open()?;
+///
+/// for i in &[256_u64, 1, 2] {
+///     tree.insert(U64Key::new(*i), vec![])?;
+/// }
+///
+/// let keys: Vec<u64> = tree
+///     .range(U64Key::new(2)..)
+///     .keys()
+///     .map(|k| U64Key::from_bytes(&k.unwrap()).unwrap().get())
+///     .collect();
+/// assert_eq!(keys, vec![2, 256]);
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct U64Key([u8; 8]);
+
+impl U64Key {
+    /// Encodes `n`.
+    pub fn new(n: u64) -> U64Key {
+        U64Key(n.to_be_bytes())
+    }
+
+    /// Returns the number that was encoded.
+    pub fn get(&self) -> u64 {
+        u64::from_be_bytes(self.0)
+    }
+
+    /// Reads back a key that was written as a `U64Key`, or
+    /// returns `None` if `bytes` is not 8 bytes long.
+    pub fn from_bytes(bytes: &[u8]) -> Option<U64Key> {
+        <[u8; 8]>::try_from(bytes).ok().map(U64Key)
+    }
+}
+
+/// A key that sorts in the numeric order of an `i64`, with
+/// negative numbers first.
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use sled::I64Key;
+///
+/// let config = sled::Config::new().temporary(true);
+/// let tree = config.open()?;
+///
+/// for i in &[1_i64, -1, 0, i64::min_value()] {
+///     tree.insert(I64Key::new(*i), vec![])?;
+/// }
+///
+/// let keys: Vec<i64> = tree
+///     .iter()
+///     .keys()
+///     .map(|k| I64Key::from_bytes(&k.unwrap()).unwrap().get())
+///     .collect();
+/// assert_eq!(keys, vec![i64::min_value(), -1, 0, 1]);
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct I64Key([u8; 8]);
+
+const SIGN_BIT: u64 = 1 << 63;
+
+#[allow(clippy::cast_sign_loss, clippy::cast_possible_wrap)]
+impl I64Key {
+    /// Encodes `n`.
+    pub fn new(n: i64) -> I64Key {
+        I64Key((n as u64 ^ SIGN_BIT).to_be_bytes())
+    }
+
+    /// Returns the number that was encoded.
+    pub fn get(&self) -> i64 {
+        (u64::from_be_bytes(self.0) ^ SIGN_BIT) as i64
+    }
+
+    /// Reads back a key that was written as an `I64Key`, or
+    /// returns `None` if `bytes` is not 8 bytes long.
+    pub fn from_bytes(bytes: &[u8]) -> Option<I64Key> {
+        <[u8; 8]>::try_from(bytes).ok().map(I64Key)
+    }
+}
+
+macro_rules! impl_int_key {
+    ($key:ident, $int:ty) => {
+        impl From<$int> for $key {
+            fn from(n: $int) -> $key {
+                $key::new(n)
+            }
+        }
+
+        impl From<$key> for $int {
+            fn from(key: $key) -> $int {
+                key.get()
+            }
+        }
+
+        impl AsRef<[u8]> for $key {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl From<$key> for IVec {
+            fn from(key: $key) -> IVec {
+                IVec::from(&key.0[..])
+            }
+        }
+    };
+}
+
+impl_int_key!(U64Key, u64);
+impl_int_key!(I64Key, i64);
+
+/// Builds a key out of several parts, which sorts by the
+/// first part, then by the second, and so on. A key built
+/// from only the first few parts is a prefix of every key
+/// that starts with them, so it can be passed to
+/// `Tree::scan_prefix`. `KeyReader` reads the parts back.
+///
+/// Byte strings have their zero bytes escaped and are
+/// terminated by two zero bytes, so that they can be
+/// followed by other parts and still sort before any longer
+/// byte string they are a prefix of.
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use sled::{KeyBuilder, KeyReader};
+///
+/// let config = sled::Config::new().temporary(true);
+/// let tree = config.open()?;
+///
+/// // (user, timestamp) -> event
+/// let key = |user: &str, ts| KeyBuilder::new().bytes(user).i64(ts).build();
+/// tree.insert(key("bob", 5), "login")?;
+/// tree.insert(key("bob", -3), "signup")?;
+/// tree.insert(key("bobby", 1), "login")?;
+///
+/// let bobs_prefix = KeyBuilder::new().bytes("bob").build();
+/// let bobs: Vec<i64> = tree
+///     .scan_prefix(bobs_prefix)
+///     .keys()
+///     .map(|k| {
+///         let k = k.unwrap();
+///         let mut reader = KeyReader::new(&k);
+///         assert_eq!(reader.bytes().unwrap(), b"bob");
+///         reader.i64().unwrap()
+///     })
+///     .collect();
+/// assert_eq!(bobs, vec![-3, 5]);
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyBuilder {
+    encoded: Vec<u8>,
+}
+
+impl KeyBuilder {
+    /// Returns a builder of an empty key.
+    pub fn new() -> KeyBuilder {
+        KeyBuilder::default()
+    }
+
+    /// Appends a `u64`, encoded as with `U64Key`.
+    pub fn u64(mut self, n: u64) -> KeyBuilder {
+        self.encoded.extend_from_slice(U64Key::new(n).as_ref());
+        self
+    }
+
+    /// Appends an `i64`, encoded as with `I64Key`.
+    pub fn i64(mut self, n: i64) -> KeyBuilder {
+        self.encoded.extend_from_slice(I64Key::new(n).as_ref());
+        self
+    }
+
+    /// Appends a byte string.
+    pub fn bytes<B: AsRef<[u8]>>(mut self, bytes: B) -> KeyBuilder {
+        for byte in bytes.as_ref() {
+            self.encoded.push(*byte);
+            if *byte == 0 {
+                self.encoded.push(u8::max_value());
+            }
+        }
+        self.encoded.extend_from_slice(&[0, 0]);
+        self
+    }
+
+    /// Returns the key.
+    pub fn build(self) -> IVec {
+        IVec::from(self.encoded)
+    }
+}
+
+/// Reads the parts of a key that was built by `KeyBuilder`,
+/// in the order they were appended. Each method returns
+/// `None` if the rest of the key is too short to hold the
+/// part, or if a byte string is not terminated.
+#[derive(Debug, Clone)]
+pub struct KeyReader<'a> {
+    remaining: &'a [u8],
+}
+
+impl<'a> KeyReader<'a> {
+    /// Starts reading from the beginning of `key`.
+    pub fn new(key: &'a [u8]) -> KeyReader<'a> {
+        KeyReader { remaining: key }
+    }
+
+    fn take_8(&mut self) -> Option<&'a [u8]> {
+        if self.remaining.len() < 8 {
+            return None;
+        }
+        let (part, rest) = self.remaining.split_at(8);
+        self.remaining = rest;
+        Some(part)
+    }
+
+    /// Reads a part appended with `KeyBuilder::u64`.
+    pub fn u64(&mut self) -> Option<u64> {
+        self.take_8().and_then(U64Key::from_bytes).map(|k| k.get())
+    }
+
+    /// Reads a part appended with `KeyBuilder::i64`.
+    pub fn i64(&mut self) -> Option<i64> {
+        self.take_8().and_then(I64Key::from_bytes).map(|k| k.get())
+    }
+
+    /// Reads a part appended with `KeyBuilder::bytes`.
+    pub fn bytes(&mut self) -> Option<Vec<u8>> {
+        let mut bytes = vec![];
+        let mut i = 0;
+        while i + 1 < self.remaining.len() {
+            match (self.remaining[i], self.remaining[i + 1]) {
+                (0, 0) => {
+                    self.remaining = &self.remaining[i + 2..];
+                    return Some(bytes);
+                }
+                (0, _) => {
+                    bytes.push(0);
+                    i += 2;
+                }
+                (byte, _) => {
+                    bytes.push(byte);
+                    i += 1;
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns `true` once every part has been read.
+    pub fn is_empty(&self) -> bool {
+        self.remaining.is_empty()
+    }
+}
+
+#[test]
+fn int_keys_sort_numerically() {
+    let ints = [i64::min_value(), -256, -1, 0, 1, 255, 256, i64::max_value()];
+    for pair in ints.windows(2) {
+        assert!(I64Key::new(pair[0]).as_ref() < I64Key::new(pair[1]).as_ref());
+        assert_eq!(I64Key::new(pair[0]).get(), pair[0]);
+    }
+
+    let uints = [0, 1, 255, 256, u64::max_value()];
+    for pair in uints.windows(2) {
+        assert!(U64Key::new(pair[0]).as_ref() < U64Key::new(pair[1]).as_ref());
+        assert_eq!(U64Key::new(pair[0]).get(), pair[0]);
+    }
+
+    assert_eq!(U64Key::from_bytes(&[0; 7]), None);
+    assert_eq!(KeyReader::new(&[1, 0]).bytes(), None);
+}
+
+#[cfg(test)]
+mod qc {
+    use super::{KeyBuilder, KeyReader};
+
+    fn build(key: &(i64, Vec<u8>, u64)) -> Vec<u8> {
+        KeyBuilder::new().i64(key.0).bytes(&key.1).u64(key.2).build().to_vec()
+    }
+
+    quickcheck::quickcheck! {
+        fn qc_built_keys_sort_like_tuples(
+            a: (i64, Vec<u8>, u64),
+            b: (i64, Vec<u8>, u64)
+        ) -> bool {
+            let (encoded_a, encoded_b) = (build(&a), build(&b));
+            assert_eq!(a.cmp(&b), encoded_a.cmp(&encoded_b));
+
+            let mut reader = KeyReader::new(&encoded_a);
+            assert_eq!(reader.i64(), Some(a.0));
+            assert_eq!(reader.bytes(), Some(a.1.clone()));
+            assert_eq!(reader.u64(), Some(a.2));
+            assert!(reader.is_empty());
+            true
+        }
+    }
+}
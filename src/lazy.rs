@@ -2,15 +2,26 @@
 //! be very unhappy. We rely heavily on TSAN for finding
 //! races, so we don't use `lazy_static`.
 
-use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering::SeqCst};
+use std::{
+    marker::PhantomData,
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering::SeqCst},
+};
 
 /// A lazily initialized value
 pub struct Lazy<T, F> {
     value: AtomicPtr<T>,
     init_mu: AtomicBool,
     init: F,
+    // `AtomicPtr<T>` is `Send` and `Sync` for any `T`, so
+    // without this the auto traits would not depend on `T`.
+    _owns: PhantomData<Box<T>>,
 }
 
+// The value may be initialized by whichever thread first
+// dereferences a shared `Lazy`, and is dropped by the owner.
+#[allow(unsafe_code)]
+unsafe impl<T: Send + Sync, F: Sync> Sync for Lazy<T, F> {}
+
 impl<T, F> Lazy<T, F> {
     /// Create a new Lazy
     pub const fn new(init: F) -> Self
@@ -21,6 +32,7 @@ impl<T, F> Lazy<T, F> {
             value: AtomicPtr::new(std::ptr::null_mut()),
             init_mu: AtomicBool::new(false),
             init,
+            _owns: PhantomData,
         }
     }
 }
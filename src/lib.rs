@@ -38,6 +38,16 @@
 //! allow any sort of data structure to be built
 //! using merges as an atomic high-level operation.
 //!
+//! `sled` needs the standard library. Its `Tree`s are
+//! built on the page cache, whose page table holds the
+//! log locations of every page, and the page cache relies
+//! on threads, `parking_lot` locks and the global epoch
+//! of `crossbeam-epoch`, so there is no part of it that
+//! works with only `core` and `alloc`. The closest
+//! setup is `Config::in_memory`, which keeps the log in
+//! memory, with the `portable_io` feature to avoid any
+//! platform-specific IO.
+//!
 //! `sled` is built by experienced database engineers
 //! who think users should spend less time tuning and
 //! working against high-friction APIs. Expect
@@ -160,7 +170,7 @@ macro_rules! io_fail {
     ($config:expr, $e:expr) => {
         #[cfg(feature = "failpoints")]
         {
-            if fail::is_active($e) {
+            if $config.fault_plan.is_active($e) {
                 $config.set_global_error(Error::FailPoint);
                 return Err(Error::FailPoint).into();
             }
@@ -177,37 +187,64 @@ macro_rules! testing_assert {
 
 mod batch;
 mod binary_search;
+mod changes;
 mod concurrency_control;
 mod config;
+mod content_store;
 mod context;
 mod db;
 mod dll;
+mod dump;
 mod fastcmp;
 mod fastlock;
 mod histogram;
+mod instrumentation;
+mod io_stats;
 mod iter;
 mod ivec;
+mod keys;
 mod lazy;
+mod log_entries;
 mod lru;
+mod memory_usage;
 mod meta;
 mod metrics;
 mod node;
 mod oneshot;
 mod pagecache;
+mod periodic;
+mod pinned_value;
 mod prefix;
+mod read_snapshot;
 mod result;
+mod scrub;
+mod secondary_index;
 mod serialization;
+mod sha256;
+mod slo;
+mod space_stats;
 mod stack;
 mod stackvec;
+mod structure;
 mod subscriber;
 mod sys_limits;
+mod throttle;
 pub mod transaction;
 mod tree;
+mod value_reader;
+mod verify;
+mod versions;
 
 /// Functionality for conditionally triggering failpoints under test.
 #[cfg(feature = "failpoints")]
 pub mod fail;
 
+#[cfg(feature = "simulation")]
+pub mod simulation;
+
+#[cfg(feature = "simulation")]
+pub mod crash_test;
+
 #[cfg(feature = "docs")]
 pub mod doc;
 
@@ -233,6 +270,12 @@ mod threadpool;
 #[cfg(any(windows, target_os = "linux", target_os = "macos"))]
 mod flusher;
 
+#[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+mod repairer;
+
+#[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+mod scrubber;
+
 #[cfg(feature = "event_log")]
 /// The event log helps debug concurrency issues.
 pub mod event_log;
@@ -245,8 +288,24 @@ mod measure_allocs;
 static ALLOCATOR: measure_allocs::TrackingAllocator =
     measure_allocs::TrackingAllocator;
 
+#[cfg(feature = "measure_allocs")]
+pub use measure_allocs::thread_allocations;
+
+#[cfg(feature = "async")]
+pub use iter::AsyncIter;
+
+#[cfg(feature = "instrumentation")]
+pub use instrumentation::{Instrumentation, InstrumentationEvent, Span};
+
+#[cfg(not(feature = "instrumentation"))]
+use instrumentation::{InstrumentationEvent, Span};
+
 const DEFAULT_TREE_ID: &[u8] = b"__sled__default";
 
+// the tree that records the pages allocated with
+// `Db::allocate_page`, so that they can be recovered
+const PAGES_TREE_ID: &[u8] = b"__sled__pages";
+
 /// hidden re-export of items for testing purposes
 #[doc(hidden)]
 pub use {
@@ -268,32 +327,54 @@ pub use {
 
 pub use self::{
     batch::Batch,
-    config::{Config, Mode},
+    changes::Change,
+    config::{
+        Config, ConsolidationPolicy, Durability, Mode, RecoveryProgress,
+        RepairPolicy,
+    },
+    content_store::BlobHash,
     db::{open, Db},
+    io_stats::IoStats,
     iter::Iter,
     ivec::IVec,
+    keys::{I64Key, KeyBuilder, KeyReader, U64Key},
+    log_entries::{LogEntries, LogEntry, LogEntryKind, LogShipper},
+    memory_usage::MemoryUsage,
+    metrics::{LatencyStats, MetricsSnapshot},
+    pinned_value::PinnedValue,
+    read_snapshot::{ReadSnapshot, SnapshotIter, SnapshotTree},
     result::{Error, Result},
+    scrub::{CorruptPage, ScrubReport},
+    secondary_index::{IndexIter, SecondaryIndex},
+    slo::{LatencySlo, SloMonitor, SloOperation, SloViolation},
+    space_stats::{CompactionStats, SegmentSpace, SegmentState, SpaceStats},
     subscriber::{Event, Subscriber},
     transaction::Transactional,
     tree::{CompareAndSwapError, Tree},
+    value_reader::ValueReader,
+    verify::{VerifyReport, Violation},
 };
 
 use {
     self::{
-        binary_search::binary_search_lub,
+        binary_search::{binary_search, binary_search_lub},
         concurrency_control::{ConcurrencyControl, Protector},
         context::Context,
         fastcmp::fastcmp,
         histogram::Histogram,
         lru::Lru,
         meta::Meta,
-        metrics::{clock, Measure, M},
-        node::{Data, Node},
+        metrics::{M, Measure, clock, clock_ticks_per_ns},
+        node::{Data, Node, Value},
         oneshot::{OneShot, OneShotFiller},
+        periodic::Periodic,
+        read_snapshot::ReadSnapshots,
         result::CasResult,
+        secondary_index::Indexes,
         stackvec::StackVec,
         subscriber::Subscribers,
-        tree::TreeInner,
+        throttle::Throttle,
+        tree::{escape_dup_key, StaleSnapshot, TreeInner},
     },
     crossbeam_utils::{Backoff, CachePadded},
     log::{debug, error, trace, warn},
@@ -306,8 +387,9 @@ use {
         io::{Read, Write},
         sync::{
             atomic::{
-                AtomicI64 as AtomicLsn, AtomicU64, AtomicUsize,
-                Ordering::{Acquire, Relaxed, Release, SeqCst},
+                AtomicBool, AtomicI64 as AtomicLsn, AtomicU32, AtomicU64,
+                AtomicUsize,
+                Ordering::{AcqRel, Acquire, Relaxed, Release, SeqCst},
             },
             Arc,
         },
@@ -345,7 +427,7 @@ const fn debug_delay() {}
 #[derive(Clone, Debug, PartialEq)]
 pub(crate) enum Link {
     /// A new value is set for a given key
-    Set(IVec, IVec),
+    Set(IVec, Value),
     /// The associated value is removed for a given key
     Del(IVec),
     /// A child of this Index node is marked as mergable
@@ -381,3 +463,28 @@ impl<F> MergeOperator for F where
     F: Fn(&[u8], Option<&[u8]>, &[u8]) -> Option<Vec<u8>>
 {
 }
+
+/// Consolidates the pages allocated with `Db::allocate_page`,
+/// which lets data structures other than trees be built on
+/// the same pages and log.
+pub trait Materializer: Send + Sync {
+    /// Returns the page that results from applying `frags`,
+    /// oldest first, to `page`. Called whenever a page is
+    /// linked onto, read back in from the log, or has
+    /// enough fragments to be consolidated.
+    fn merge(&self, page: &[u8], frags: &[&[u8]]) -> Vec<u8>;
+
+    /// Called once for every page allocated with
+    /// `Db::allocate_page` when the database is opened,
+    /// so that in-memory state such as the location of a
+    /// structure's root can be rebuilt.
+    fn recover(&self, pid: u64, page: &[u8]) {
+        let _ = (pid, page);
+    }
+}
+
+impl Debug for dyn Materializer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Materializer")
+    }
+}
@@ -0,0 +1,271 @@
+use std::{
+    ops::{Bound, RangeBounds},
+    sync::Weak,
+    time::Duration,
+};
+
+use crate::{
+    pagecache::{LogIter, LogKind, LogRead},
+    *,
+};
+
+/// The kind of a raw log entry, as returned by
+/// `Db::log_entries`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogEntryKind {
+    /// The entry replaces the whole page.
+    Replace,
+    /// The entry is a delta applied on top of the page's
+    /// previous entries.
+    Link,
+    /// The entry frees the page.
+    Free,
+}
+
+/// A single entry in the log, as returned by
+/// `Db::log_entries`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    /// The log sequence number that the entry was written at.
+    pub lsn: Lsn,
+    /// The page that the entry belongs to.
+    pub pid: PageId,
+    /// Whether the entry replaces, links onto, or frees
+    /// the page.
+    pub kind: LogEntryKind,
+    /// The kind of the page update followed by the update
+    /// itself, which is empty for `LogEntryKind::Free`
+    /// entries. The format of this is not stable across
+    /// versions.
+    pub payload: Vec<u8>,
+}
+
+/// An iterator over the entries of the log, created by
+/// `Db::log_entries`.
+pub struct LogEntries {
+    // the `Db` waits for every other reference to the
+    // pagecache to be dropped when it shuts down, so this
+    // must not keep it alive
+    pagecache: Weak<PageCache>,
+    iter: LogIter,
+    start: Lsn,
+    pid: Option<PageId>,
+    done: bool,
+}
+
+impl LogEntries {
+    pub(crate) fn new<R: RangeBounds<Lsn>>(
+        pagecache: &Arc<PageCache>,
+        lsns: R,
+    ) -> LogEntries {
+        let start = match lsns.start_bound() {
+            Bound::Included(lsn) => *lsn,
+            Bound::Excluded(lsn) => lsn.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+
+        // iteration has to begin at a message boundary, so
+        // it starts from the beginning of the segment and
+        // skips anything before `start`
+        let segment_size =
+            Lsn::try_from(pagecache.config.segment_size).unwrap();
+        let mut iter =
+            pagecache.log.iter_from(start.max(0) / segment_size * segment_size);
+
+        let end = match lsns.end_bound() {
+            Bound::Included(lsn) => Some(*lsn),
+            Bound::Excluded(lsn) => Some(lsn.saturating_sub(1)),
+            Bound::Unbounded => None,
+        };
+        if let Some(last) = end {
+            iter.max_lsn = iter.max_lsn.min(last);
+        }
+
+        LogEntries {
+            pagecache: Arc::downgrade(pagecache),
+            iter,
+            start,
+            pid: None,
+            done: false,
+        }
+    }
+
+    /// Only yield entries that belong to the given page.
+    pub fn page(mut self, pid: PageId) -> LogEntries {
+        self.pid = Some(pid);
+        self
+    }
+}
+
+impl Debug for LogEntries {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogEntries")
+            .field("start", &self.start)
+            .field("max_lsn", &self.iter.max_lsn)
+            .field("pid", &self.pid)
+            .finish()
+    }
+}
+
+impl Iterator for LogEntries {
+    type Item = Result<LogEntry>;
+
+    fn next(&mut self) -> Option<Result<LogEntry>> {
+        if self.done {
+            return None;
+        }
+
+        loop {
+            let (kind, pid, lsn, ptr, _sz) = self.iter.next()?;
+
+            let other_page = self.pid.is_some() && self.pid != Some(pid);
+            if lsn < self.start || other_page {
+                continue;
+            }
+
+            let entry_kind = match kind {
+                LogKind::Replace => LogEntryKind::Replace,
+                LogKind::Link => LogEntryKind::Link,
+                LogKind::Free => {
+                    return Some(Ok(LogEntry {
+                        lsn,
+                        pid,
+                        kind: LogEntryKind::Free,
+                        payload: vec![],
+                    }));
+                }
+                LogKind::Skip | LogKind::Corrupted => continue,
+            };
+
+            // the segment may be reused between being iterated
+            // over and being read here, in which case the rest
+            // of the requested range is gone too
+            let pagecache = self.pagecache.upgrade()?;
+            let read = match pagecache.log.read(pid, lsn, ptr) {
+                Ok(read) => read,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            };
+
+            let (header, bytes) = match read {
+                LogRead::Inline(header, bytes, _)
+                | LogRead::Blob(header, bytes, _, _) => (header, bytes),
+                _ => {
+                    self.done = true;
+                    return Some(Err(Error::Unsupported(format!(
+                        "the log at lsn {} is no longer retained",
+                        lsn
+                    ))));
+                }
+            };
+
+            let mut payload = Vec::with_capacity(1 + bytes.len());
+            payload.push(header.kind.into());
+            payload.extend_from_slice(&bytes);
+
+            return Some(Ok(LogEntry { lsn, pid, kind: entry_kind, payload }));
+        }
+    }
+}
+
+/// A blocking iterator that ships every entry of the log
+/// once it is stable, created by `Db::ship_log`.
+pub struct LogShipper {
+    pagecache: Weak<PageCache>,
+    entries: Option<LogEntries>,
+    // every entry at or below this lsn has been shipped
+    shipped: Lsn,
+    // the stable offset that the log has been read up to.
+    // This may be past `shipped`, because reading stops
+    // early at batches that are only partially stable.
+    scanned: Lsn,
+    // set once the log is stable past `waiting_on`
+    wakeup: Arc<(Mutex<bool>, Condvar)>,
+    waiting_on: Option<Lsn>,
+}
+
+impl LogShipper {
+    pub(crate) fn new(pagecache: &Arc<PageCache>, from: Lsn) -> LogShipper {
+        let shipped = from.max(0) - 1;
+        LogShipper {
+            pagecache: Arc::downgrade(pagecache),
+            entries: None,
+            shipped,
+            scanned: shipped,
+            wakeup: Arc::new((Mutex::new(false), Condvar::new())),
+            waiting_on: None,
+        }
+    }
+}
+
+impl Debug for LogShipper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogShipper").field("shipped", &self.shipped).finish()
+    }
+}
+
+impl Iterator for LogShipper {
+    type Item = Result<LogEntry>;
+
+    fn next(&mut self) -> Option<Result<LogEntry>> {
+        loop {
+            if let Some(entries) = &mut self.entries {
+                match entries.next() {
+                    Some(Ok(entry)) => {
+                        self.shipped = entry.lsn;
+                        return Some(Ok(entry));
+                    }
+                    Some(Err(e)) => {
+                        self.entries = None;
+                        return Some(Err(e));
+                    }
+                    None => self.entries = None,
+                }
+            }
+
+            let pagecache = self.pagecache.upgrade()?;
+
+            if let Err(e) = pagecache.config.global_error() {
+                return Some(Err(e));
+            }
+
+            let stable = pagecache.log.stable_offset();
+            if stable <= self.scanned {
+                if self.waiting_on != Some(self.scanned) {
+                    self.waiting_on = Some(self.scanned);
+                    let wakeup = self.wakeup.clone();
+                    pagecache.log.on_stable(self.scanned + 1, move || {
+                        *wakeup.0.lock() = true;
+                        wakeup.1.notify_all();
+                    });
+                }
+
+                // see `LogEntries::pagecache`
+                drop(pagecache);
+
+                let mut woken = self.wakeup.0.lock();
+                if !*woken {
+                    let _ = self
+                        .wakeup
+                        .1
+                        .wait_for(&mut woken, Duration::from_millis(100));
+                }
+                *woken = false;
+                continue;
+            }
+
+            let from = self.shipped + 1;
+            if !pagecache.history_retained(from, stable) {
+                return Some(Err(Error::Unsupported(format!(
+                    "the log at lsn {} is no longer retained",
+                    from
+                ))));
+            }
+
+            self.scanned = stable;
+            self.entries = Some(LogEntries::new(&pagecache, from..=stable));
+        }
+    }
+}
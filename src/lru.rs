@@ -1,6 +1,7 @@
 #![allow(unsafe_code)]
 
 use std::convert::TryFrom;
+use std::mem::size_of;
 use std::ptr;
 use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
 
@@ -8,7 +9,7 @@ use crate::{
     debug_delay,
     dll::{DoublyLinkedList, Node},
     fastlock::FastLock,
-    Guard, PageId,
+    Backoff, Guard, PageId,
 };
 
 #[cfg(any(test, feature = "lock_free_delays"))]
@@ -63,8 +64,10 @@ impl AccessQueue {
             let head = self.writing.load(Ordering::Acquire);
             let block = unsafe { &*head };
 
+            // the count only hands out slots, and each slot's
+            // item is published by its own Release store
             debug_delay();
-            let offset = block.len.fetch_add(1, Ordering::SeqCst);
+            let offset = block.len.fetch_add(1, Ordering::Relaxed);
 
             if offset < MAX_QUEUE_ITEMS {
                 debug_delay();
@@ -72,7 +75,7 @@ impl AccessQueue {
                     block
                         .block
                         .get_unchecked(offset)
-                        .store(item.0, Ordering::SeqCst);
+                        .store(item.0, Ordering::Release);
                 }
                 return filled;
             } else {
@@ -80,7 +83,7 @@ impl AccessQueue {
                 let new = Box::into_raw(Box::new(AccessBlock::default()));
                 debug_delay();
                 let prev =
-                    self.writing.compare_and_swap(head, new, Ordering::SeqCst);
+                    self.writing.compare_and_swap(head, new, Ordering::AcqRel);
                 if prev != head {
                     // we lost the CAS, free the new item that was
                     // never published to other threads
@@ -95,12 +98,13 @@ impl AccessQueue {
                 let mut full_list_ptr = self.full_list.load(Ordering::Acquire);
                 while {
                     // we loop because maybe other threads are pushing stuff too
-                    block.next.store(full_list_ptr, Ordering::SeqCst);
+                    // and the CAS below releases `next` along with the block
+                    block.next.store(full_list_ptr, Ordering::Relaxed);
                     debug_delay();
                     ret = self.full_list.compare_and_swap(
                         full_list_ptr,
                         head,
-                        Ordering::SeqCst,
+                        Ordering::AcqRel,
                     );
                     ret != full_list_ptr
                 } {
@@ -113,7 +117,7 @@ impl AccessQueue {
 
     fn take<'a>(&self, guard: &'a Guard) -> CacheAccessIter<'a> {
         debug_delay();
-        let ptr = self.full_list.swap(std::ptr::null_mut(), Ordering::SeqCst);
+        let ptr = self.full_list.swap(std::ptr::null_mut(), Ordering::Acquire);
 
         CacheAccessIter { guard, current_offset: 0, current_block: ptr }
     }
@@ -266,6 +270,65 @@ impl Lru {
         }
         ret
     }
+
+    /// Returns up to `n` of the most recently accessed
+    /// items, taking them from the head of every shard in
+    /// turn, so that the items of all shards are ordered
+    /// by how recently they were accessed within their own
+    /// shard. Accesses still waiting in the access queues
+    /// are not counted.
+    pub fn hottest(&self, n: usize) -> Vec<PageId> {
+        let shards = self.shards.len();
+        let per_shard = (n + shards - 1) / shards;
+
+        let mut by_shard = Vec::with_capacity(shards);
+        for (_queue, shard_mu) in &self.shards {
+            let backoff = Backoff::new();
+            let shard = loop {
+                if let Some(shard) = shard_mu.try_lock() {
+                    break shard;
+                }
+                backoff.snooze();
+            };
+            let positions: Vec<PageId> =
+                shard.list.iter().take(per_shard).collect();
+            by_shard.push(positions);
+        }
+
+        let mut ret = Vec::with_capacity(n);
+        for rank in 0..per_shard {
+            for (shard_idx, positions) in by_shard.iter().enumerate() {
+                if let Some(pos) = positions.get(rank) {
+                    ret.push(pos * shards as u64 + shard_idx as u64);
+                }
+            }
+        }
+        ret.truncate(n);
+        ret
+    }
+
+    /// Approximates the bytes held by the access queues
+    /// and the shards. Full access blocks that are waiting
+    /// to be applied to a shard are not counted.
+    pub fn rss(&self) -> u64 {
+        let mut rss = (self.shards.capacity()
+            * size_of::<(AccessQueue, FastLock<Shard>)>())
+            as u64;
+        for (_queue, shard_mu) in &self.shards {
+            let backoff = Backoff::new();
+            let shard = loop {
+                if let Some(shard) = shard_mu.try_lock() {
+                    break shard;
+                }
+                backoff.snooze();
+            };
+            rss += (size_of::<AccessBlock>()
+                + shard.entries.capacity() * size_of::<Entry>()
+                + shard.list.len() * size_of::<Node>())
+                as u64;
+        }
+        rss
+    }
 }
 
 #[derive(Clone)]
@@ -345,3 +408,62 @@ impl Shard {
 fn safe_usize(value: PageId) -> usize {
     usize::try_from(value).unwrap()
 }
+
+#[test]
+fn access_queue_hands_off_every_item_once() {
+    use std::{
+        collections::HashSet,
+        sync::{atomic::AtomicBool, Arc},
+        thread,
+    };
+
+    const N_WRITERS: u64 = 4;
+    const N_ITEMS: u64 = 1000;
+
+    let queue = Arc::new(AccessQueue::default());
+    let done = Arc::new(AtomicBool::new(false));
+
+    // takes concurrently with the pushes, which is where
+    // weaker orderings than SeqCst could lose or tear items
+    let taker = {
+        let queue = queue.clone();
+        let done = done.clone();
+        thread::spawn(move || {
+            let mut taken = vec![];
+            loop {
+                let finished = done.load(Ordering::Acquire);
+                let guard = crossbeam_epoch::pin();
+                taken.extend(queue.take(&guard).map(|access| access.0));
+                if finished {
+                    return taken;
+                }
+            }
+        })
+    };
+
+    let writers: Vec<_> = (0..N_WRITERS)
+        .map(|writer| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                for i in 0..N_ITEMS {
+                    // 0 marks a slot that hasn't been written yet
+                    let _ = queue.push(CacheAccess(writer * N_ITEMS + i + 1));
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    done.store(true, Ordering::Release);
+    let taken = taker.join().unwrap();
+
+    // the last block stays in `writing` until a push finds
+    // it full, so it is the only one that isn't handed off
+    let total = N_WRITERS * N_ITEMS;
+    assert_eq!(total % MAX_QUEUE_ITEMS as u64, 0);
+    assert_eq!(taken.len() as u64, total - MAX_QUEUE_ITEMS as u64);
+    let distinct: HashSet<u64> = taken.iter().copied().collect();
+    assert_eq!(distinct.len(), taken.len());
+    assert!(taken.iter().all(|item| (1..=total).contains(item)));
+}
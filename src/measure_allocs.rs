@@ -1,5 +1,6 @@
 #![allow(unsafe_code)]
 
+use std::cell::Cell;
 use std::sync::atomic::{AtomicUsize, Ordering::Release};
 
 // define a passthrough allocator that tracks alloc calls.
@@ -11,10 +12,34 @@ pub(crate) struct TrackingAllocator;
 pub static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
 pub static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
 
+// Cell<usize> needs no destructor, so accessing these
+// never allocates, even for the first time on a thread.
+thread_local! {
+    static THREAD_ALLOCATIONS: Cell<usize> = Cell::new(0);
+    static THREAD_ALLOCATED_BYTES: Cell<usize> = Cell::new(0);
+}
+
+/// Returns the number of heap allocations made by the
+/// current thread so far, and the number of bytes they
+/// requested. Comparing these before and after a call
+/// measures the allocations that the call made, without
+/// counting those of other threads.
+pub fn thread_allocations() -> (usize, usize) {
+    let allocations = THREAD_ALLOCATIONS.with(Cell::get);
+    let bytes = THREAD_ALLOCATED_BYTES.with(Cell::get);
+    (allocations, bytes)
+}
+
 unsafe impl GlobalAlloc for TrackingAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         ALLOCATIONS.fetch_add(1, Release);
         ALLOCATED_BYTES.fetch_add(layout.size(), Release);
+
+        // the thread's locals are gone while it shuts down
+        let _ = THREAD_ALLOCATIONS.try_with(|a| a.set(a.get() + 1));
+        let _ = THREAD_ALLOCATED_BYTES
+            .try_with(|b| b.set(b.get() + layout.size()));
+
         System.alloc(layout)
     }
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
@@ -0,0 +1,38 @@
+/// An approximate breakdown of the memory held by a `Db`,
+/// returned by `Db::memory_usage`. Every field is a number
+/// of bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// The nodes, values and metadata of the pages that are
+    /// currently resident in the cache. This is what
+    /// `Config::cache_capacity` bounds, though it is
+    /// measured in the size of their log messages there.
+    pub resident_pages: u64,
+    /// The per-page records of where each message in a
+    /// page's chain of updates lives in the log. These are
+    /// kept for every page, whether it is resident or not.
+    pub delta_chains: u64,
+    /// The lock-free radix tree that maps page IDs to pages.
+    pub page_table: u64,
+    /// The access queues and recency lists of the cache's
+    /// LRU shards.
+    pub lru: u64,
+    /// The buffer that log messages are currently being
+    /// written into.
+    pub io_buffer: u64,
+    /// The filters over the keys of paged out leaves, with
+    /// `Config::leaf_filter_bits_per_key`.
+    pub leaf_filters: u64,
+}
+
+impl MemoryUsage {
+    /// The sum of all of the components.
+    pub fn total(&self) -> u64 {
+        self.resident_pages
+            + self.delta_chains
+            + self.page_table
+            + self.lru
+            + self.io_buffer
+            + self.leaf_filters
+    }
+}
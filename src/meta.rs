@@ -5,6 +5,7 @@ use crate::*;
 #[derive(Clone, Debug, Eq, PartialEq, Default)]
 pub struct Meta {
     pub(crate) inner: BTreeMap<IVec, PageId>,
+    pub(crate) user_version: u32,
 }
 
 impl Meta {
@@ -34,7 +35,8 @@ impl Meta {
             .map(|(k, _pid)| {
                 k.len() as u64 + std::mem::size_of::<PageId>() as u64
             })
-            .sum()
+            .sum::<u64>()
+            + std::mem::size_of::<u32>() as u64
     }
 }
 
@@ -62,6 +64,10 @@ where
                     root: AtomicU64::new(root_id),
                     concurrency_control: ConcurrencyControl::default(),
                     merge_operator: RwLock::new(None),
+                    dup_sort: AtomicBool::new(false),
+                    write_once: AtomicBool::new(false),
+                    stale_snapshot: StaleSnapshot::default(),
+                    indexes: Indexes::default(),
                 })));
             }
             Err(Error::CollectionNotFound(_)) => {}
@@ -69,8 +75,10 @@ where
         }
 
         // set up empty leaf
+        let compress = context.is_compressed_tree(&name);
         let leaf = Node::default();
-        let (leaf_id, leaf_ptr) = context.pagecache.allocate(leaf, guard)?;
+        let (leaf_id, leaf_ptr) =
+            context.pagecache.allocate(leaf, compress, guard)?;
 
         trace!(
             "allocated pid {} for leaf in new_tree for namespace {:?}",
@@ -82,7 +90,8 @@ where
 
         // vec![0] represents a prefix-encoded empty prefix
         let root = Node::new_root(leaf_id);
-        let (root_id, root_ptr) = context.pagecache.allocate(root, guard)?;
+        let (root_id, root_ptr) =
+            context.pagecache.allocate(root, compress, guard)?;
 
         debug!("allocated pid {} for root of new_tree {:?}", root_id, name);
 
@@ -114,6 +123,10 @@ where
             root: AtomicU64::new(root_id),
             concurrency_control: ConcurrencyControl::default(),
             merge_operator: RwLock::new(None),
+            dup_sort: AtomicBool::new(false),
+            write_once: AtomicBool::new(false),
+            stale_snapshot: StaleSnapshot::default(),
+            indexes: Indexes::default(),
         })));
     }
 }
@@ -9,7 +9,10 @@ pub struct Node {
     pub(crate) hi: IVec,
     pub(crate) merging_child: Option<PageId>,
     pub(crate) merging: bool,
-    pub(crate) prefix_len: u8,
+    /// The length of the prefix shared by `lo` and `hi`, and
+    /// therefore by every key in this node, which is omitted
+    /// from the stored keys.
+    pub(crate) prefix_len: u16,
     pub(crate) data: Data,
 }
 
@@ -45,7 +48,15 @@ impl Node {
             + self.data.rss()
     }
 
-    fn prefix_decode(&self, key: &[u8]) -> IVec {
+    /// Packs the small values of a leaf into one buffer,
+    /// see `Leaf::pack_values`.
+    pub(crate) fn pack_values(&mut self, threshold: usize) {
+        if let Data::Leaf(leaf) = &mut self.data {
+            leaf.pack_values(threshold);
+        }
+    }
+
+    pub(crate) fn prefix_decode(&self, key: &[u8]) -> IVec {
         prefix::decode(self.prefix(), key)
     }
 
@@ -102,12 +113,12 @@ impl Node {
         }
     }
 
-    pub(crate) fn set_leaf(&mut self, key: IVec, val: IVec) {
+    pub(crate) fn set_leaf(&mut self, key: IVec, val: Value) {
         if !self.hi.is_empty() {
             assert!(*key < self.hi[self.prefix_len as usize..]);
         }
         if let Data::Leaf(ref mut leaf) = self.data {
-            let search = leaf.keys.binary_search_by(|k| fastcmp(k, &key));
+            let search = binary_search(&key, &leaf.keys);
             match search {
                 Ok(idx) => leaf.values[idx] = val,
                 Err(idx) => {
@@ -123,7 +134,7 @@ impl Node {
 
     pub(crate) fn del_leaf(&mut self, key: &IVec) {
         if let Data::Leaf(ref mut leaf) = self.data {
-            let search = leaf.keys.binary_search_by(|k| fastcmp(k, key));
+            let search = binary_search(key, &leaf.keys);
             if let Ok(idx) = search {
                 leaf.keys.remove(idx);
                 leaf.values.remove(idx);
@@ -137,7 +148,7 @@ impl Node {
     pub(crate) fn parent_split(&mut self, at: &[u8], to: PageId) -> bool {
         if let Data::Index(ref mut index) = self.data {
             let encoded_sep = &at[self.prefix_len as usize..];
-            match index.keys.binary_search_by(|k| fastcmp(k, encoded_sep)) {
+            match binary_search(encoded_sep, &index.keys) {
                 Ok(_) => {
                     debug!(
                         "parent_split skipped because \
@@ -166,7 +177,7 @@ impl Node {
             old_prefix: &[u8],
             old_hi: &[u8],
             suffix_truncation: bool,
-        ) -> (IVec, u8, Vec<IVec>, Vec<T>)
+        ) -> (IVec, u16, Vec<IVec>, Vec<T>)
         where
             T: Clone + Ord,
         {
@@ -185,12 +196,7 @@ impl Node {
                 // otherwise ranges would be permanently
                 // inaccessible by falling into the gap
                 // during a split.
-                right_min
-                    .iter()
-                    .zip(left_max.iter())
-                    .take_while(|(a, b)| a == b)
-                    .count()
-                    + 1
+                shortest_separator(left_max, right_min).len()
             } else {
                 right_min.len()
             };
@@ -204,7 +210,7 @@ impl Node {
                 .iter()
                 .zip(split_point.iter())
                 .take_while(|(a, b)| a == b)
-                .take(u8::max_value() as usize)
+                .take(u16::max_value() as usize)
                 .count();
 
             assert!(
@@ -231,7 +237,7 @@ impl Node {
 
             (
                 split_point,
-                u8::try_from(new_prefix_len).unwrap(),
+                u16::try_from(new_prefix_len).unwrap(),
                 right_keys_data,
                 right_values,
             )
@@ -294,7 +300,7 @@ impl Node {
             .iter()
             .zip(self.lo.iter())
             .take_while(|(a, b)| a == b)
-            .take(u8::max_value() as usize)
+            .take(u16::max_value() as usize)
             .count();
 
         if new_prefix_len != self.prefix_len as usize {
@@ -312,7 +318,7 @@ impl Node {
             }
         }
 
-        self.prefix_len = u8::try_from(new_prefix_len).unwrap();
+        self.prefix_len = u16::try_from(new_prefix_len).unwrap();
 
         // intentionally make this the end to make
         // any issues pop out with setting it
@@ -381,7 +387,7 @@ impl Node {
             .iter()
             .zip(self.lo.iter())
             .take_while(|(a, b)| a == b)
-            .take(u8::max_value() as usize)
+            .take(u16::max_value() as usize)
             .count();
 
         if new_prefix_len != merged.prefix_len as usize {
@@ -399,7 +405,7 @@ impl Node {
             }
         }
 
-        merged.prefix_len = u8::try_from(new_prefix_len).unwrap();
+        merged.prefix_len = u16::try_from(new_prefix_len).unwrap();
 
         match (&mut merged.data, &right.data) {
             (Data::Index(ref mut left_index), Data::Index(ref right_index)) => {
@@ -458,7 +464,7 @@ impl Node {
     pub(crate) fn successor(
         &self,
         bound: &Bound<IVec>,
-    ) -> Option<(IVec, IVec)> {
+    ) -> Option<(IVec, Value)> {
         assert!(!self.data.is_index());
 
         // This encoding happens this way because
@@ -473,7 +479,7 @@ impl Node {
 
         let leaf = self.data.leaf_ref().unwrap();
         let search =
-            leaf.keys.binary_search_by(|k| fastcmp(k, predecessor_key));
+            binary_search(predecessor_key, &leaf.keys);
 
         let start = match search {
             Ok(start) => start,
@@ -500,7 +506,7 @@ impl Node {
     pub(crate) fn predecessor(
         &self,
         bound: &Bound<IVec>,
-    ) -> Option<(IVec, IVec)> {
+    ) -> Option<(IVec, Value)> {
         assert!(!self.data.is_index());
 
         // This encoding happens this way because
@@ -527,7 +533,7 @@ impl Node {
 
         let leaf = self.data.leaf_ref().unwrap();
         let search = if let Some(successor_key) = successor_key {
-            leaf.keys.binary_search_by(|k| fastcmp(k, &successor_key))
+            binary_search(&successor_key, &leaf.keys)
         } else if leaf.keys.is_empty() {
             Err(0)
         } else {
@@ -563,7 +569,7 @@ impl Node {
     pub(crate) fn leaf_pair_for_key(
         &self,
         key: &[u8],
-    ) -> Option<(&IVec, &IVec)> {
+    ) -> Option<(&IVec, &Value)> {
         let leaf = self
             .data
             .leaf_ref()
@@ -571,14 +577,14 @@ impl Node {
 
         let suffix = &key[self.prefix_len as usize..];
 
-        let search = leaf.keys.binary_search_by(|k| fastcmp(k, suffix)).ok();
+        let search = binary_search(suffix, &leaf.keys).ok();
 
         search.map(|idx| (&leaf.keys[idx], &leaf.values[idx]))
     }
 
     /// `node_kv_pair` returns either existing (node/key, value) pair or
     /// (node/key, none) where a node/key is node level encoded key.
-    pub fn node_kv_pair(&self, key: &[u8]) -> (IVec, Option<IVec>) {
+    pub(crate) fn node_kv_pair(&self, key: &[u8]) -> (IVec, Option<Value>) {
         assert!(key >= self.lo.as_ref());
         if !self.hi.is_empty() {
             assert!(key < self.hi.as_ref());
@@ -592,28 +598,30 @@ impl Node {
         }
     }
 
-    pub(crate) fn should_split(&self) -> bool {
-        let threshold = if cfg!(any(test, feature = "lock_free_delays")) {
-            2
-        } else if self.data.is_index() {
-            256
-        } else {
-            16
-        };
+    /// The number of items above which a node is split.
+    pub(crate) fn split_threshold(config: &Config, is_index: bool) -> usize {
+        if is_index { config.max_index_items } else { config.max_leaf_items }
+    }
+
+    pub(crate) fn should_split(&self, config: &Config) -> bool {
+        let threshold = Node::split_threshold(config, self.data.is_index());
+
+        // both halves of a split need at least one item
+        let too_large = config.max_node_size.map_or(false, |max_node_size| {
+            self.rss() > max_node_size && self.data.len() > 2
+        });
 
-        let size_checks = self.data.len() > threshold;
+        let size_checks = self.data.len() > threshold || too_large;
         let safety_checks = self.merging_child.is_none() && !self.merging;
 
         size_checks && safety_checks
     }
 
-    pub(crate) fn should_merge(&self) -> bool {
-        let threshold = if cfg!(any(test, feature = "lock_free_delays")) {
-            1
-        } else if self.data.is_index() {
-            64
+    pub(crate) fn should_merge(&self, config: &Config) -> bool {
+        let threshold = if self.data.is_index() {
+            config.min_index_items
         } else {
-            4
+            config.min_leaf_items
         };
 
         let size_checks = self.data.len() < threshold;
@@ -662,7 +670,7 @@ impl Data {
                 .keys
                 .iter()
                 .zip(leaf.values.iter())
-                .map(|(k, v)| k.len() + v.len())
+                .map(|(k, v)| k.len() + v.rss())
                 .sum::<usize>() as u64,
         }
     }
@@ -715,7 +723,95 @@ impl Data {
 #[derive(Clone, Debug, PartialEq, Default)]
 pub(crate) struct Leaf {
     pub(crate) keys: Vec<IVec>,
-    pub(crate) values: Vec<IVec>,
+    pub(crate) values: Vec<Value>,
+}
+
+impl Leaf {
+    /// Returns `true` if any of the values in this leaf
+    /// live in their own overflow page.
+    pub(crate) fn has_overflow(&self) -> bool {
+        self.values.iter().any(Value::is_overflow)
+    }
+
+    /// Copies the values that are at most `threshold` bytes
+    /// long, but too long to be stored inline in an `IVec`,
+    /// into a single buffer that they then refer to, so that
+    /// they take one allocation and sit next to each other
+    /// instead of each being behind a pointer of their own.
+    /// Does nothing unless some of them aren't packed yet,
+    /// and otherwise repacks the ones that already are too,
+    /// so that the leaf never holds on to more than one
+    /// buffer along with the removed values left in it.
+    pub(crate) fn pack_values(&mut self, threshold: usize) {
+        let threshold = threshold.min(usize::from(u16::max_value()));
+        let packable = |v: &IVec| {
+            (v.is_remote() || v.is_subslice()) && v.len() <= threshold
+        };
+        let unpacked = |value: &Value| match value {
+            Value::Inline(v) => v.is_remote() && v.len() <= threshold,
+            Value::Overflow(_) => false,
+        };
+        if !self.values.iter().any(unpacked) {
+            return;
+        }
+
+        let mut buf = vec![];
+        for value in &self.values {
+            match value {
+                Value::Inline(v) if packable(v) => buf.extend_from_slice(v),
+                _ => {}
+            }
+        }
+        if u32::try_from(buf.len()).is_err() {
+            return;
+        }
+        let buf: Arc<[u8]> = Arc::from(buf);
+
+        let mut offset = 0;
+        for value in &mut self.values {
+            if let Value::Inline(v) = value {
+                if packable(v) {
+                    let len = v.len();
+                    *v = IVec::subslice(&buf, offset, len);
+                    offset += len;
+                }
+            }
+        }
+    }
+}
+
+/// A value stored in a leaf. Values that are at least
+/// `Config::overflow_threshold` bytes long are written
+/// to a dedicated page and only referenced from the
+/// leaf, so that large values don't inflate the cost of
+/// consolidating, splitting, or paging in their leaf.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Value {
+    Inline(IVec),
+    Overflow(PageId),
+}
+
+impl Value {
+    pub(crate) fn is_overflow(&self) -> bool {
+        if let Value::Overflow(_) = self {
+            true
+        } else {
+            false
+        }
+    }
+
+    fn rss(&self) -> usize {
+        match self {
+            Value::Inline(value) => value.len(),
+            Value::Overflow(_) => std::mem::size_of::<PageId>(),
+        }
+    }
+}
+
+impl From<IVec> for Value {
+    fn from(value: IVec) -> Value {
+        Value::Inline(value)
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Default)]
@@ -724,6 +820,24 @@ pub(crate) struct Index {
     pub(crate) pointers: Vec<PageId>,
 }
 
+/// The shortest prefix of `right_min` that sorts after
+/// `left_max`, which can be used as the bound between the
+/// nodes holding them in place of all of `right_min`.
+pub(crate) fn shortest_separator<'a>(
+    left_max: &[u8],
+    right_min: &'a [u8],
+) -> &'a [u8] {
+    assert!(left_max < right_min);
+
+    let shared_len = right_min
+        .iter()
+        .zip(left_max.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    &right_min[..=shared_len]
+}
+
 #[cfg(feature = "lock_free_delays")]
 fn is_sorted<T: PartialOrd>(xs: &[T]) -> bool {
     xs.windows(2).all(|pair| pair[0] <= pair[1])
@@ -734,7 +848,7 @@ fn merge_uneven_nodes() {
     let left = Node {
         data: Data::Leaf(Leaf {
             keys: vec![vec![230, 126, 1, 0].into()],
-            values: vec![vec![].into()],
+            values: vec![IVec::from(vec![]).into()],
         }),
         next: Some(1),
         lo: vec![230, 125, 1, 0].into(),
@@ -747,7 +861,7 @@ fn merge_uneven_nodes() {
     let right = Node {
         data: Data::Leaf(Leaf {
             keys: vec![vec![134, 0, 0].into()],
-            values: vec![vec![].into()],
+            values: vec![IVec::from(vec![]).into()],
         }),
         next: None,
         lo: vec![230, 134, 0, 0].into(),
@@ -759,3 +873,63 @@ fn merge_uneven_nodes() {
 
     left.receive_merge(&right);
 }
+
+#[test]
+fn split_long_prefix() {
+    let prefix = vec![7; 1000];
+    let key = |suffix: u8| {
+        let mut key = prefix.clone();
+        key.push(suffix);
+        IVec::from(key)
+    };
+
+    let node = Node {
+        data: Data::Leaf(Leaf {
+            keys: (1..=4).map(|i| key(i)[1000..].into()).collect(),
+            values: (1..=4).map(|_| IVec::default().into()).collect(),
+        }),
+        next: None,
+        lo: key(0),
+        hi: key(10),
+        merging_child: None,
+        merging: false,
+        prefix_len: 1000,
+    };
+
+    let (left, right) = node.split();
+
+    assert_eq!(left.prefix_len, 1000);
+    assert_eq!(right.prefix_len, 1000);
+    assert_eq!(right.lo, key(4));
+
+    let merged = left.receive_merge(&right);
+    assert_eq!(merged.prefix_len, 1000);
+    for i in 1..=4 {
+        assert_eq!(merged.leaf_pair_for_key(&key(i)).unwrap().0, &[i]);
+    }
+}
+
+#[test]
+fn split_truncates_separator() {
+    assert_eq!(shortest_separator(b"ab", b"abc"), b"abc");
+    assert_eq!(shortest_separator(b"abc", b"abd"), b"abd");
+    assert_eq!(shortest_separator(b"abc", b"b"), b"b");
+    assert_eq!(shortest_separator(b"aaaa", b"abcdef"), b"ab");
+    assert_eq!(shortest_separator(b"", b"xyz"), b"x");
+
+    let key = |i: u8| IVec::from(vec![i, 0, 0, 0, 0, 0, 0, 0]);
+
+    let node = Node {
+        data: Data::Leaf(Leaf {
+            keys: (1..=4).map(key).collect(),
+            values: (1..=4).map(|_| IVec::default().into()).collect(),
+        }),
+        ..Node::default()
+    };
+
+    let (left, right) = node.split();
+
+    assert_eq!(left.hi, &[4]);
+    assert_eq!(right.lo, &[4]);
+    assert_eq!(right.leaf_pair_for_key(&key(4)).unwrap().0, &key(4));
+}
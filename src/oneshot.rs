@@ -55,6 +55,26 @@ impl<T> OneShot<T> {
         }
         inner.item.take()
     }
+
+    /// Poll for the `OneShot`'s completion or dropping of
+    /// the `OneShotFiller`, returning `None` for the latter
+    /// like `wait` does.
+    pub(crate) fn poll_filled(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<T>> {
+        let mut state = self.mu.lock();
+        if state.fused {
+            return Poll::Pending;
+        }
+        if state.filled {
+            state.fused = true;
+            Poll::Ready(state.item.take())
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
 }
 
 impl<T> Future for OneShot<T> {
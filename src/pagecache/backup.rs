@@ -0,0 +1,101 @@
+use std::{
+    collections::BTreeSet,
+    fs::{self, OpenOptions},
+    path::Path,
+};
+
+use super::{DiskPtr, LogOffset, Lsn, PageState, Snapshot, pwrite_all};
+use crate::*;
+
+/// Copies the segments that `snapshot` refers to into a new
+/// log file in `dir`, at the same offsets, leaving out every
+/// message written after `cut`. `segment_lsns` maps the
+/// offset of each segment in use to the lsn that it starts
+/// at, and none of them may be reused while this runs.
+pub(crate) fn copy_log(
+    config: &RunningConfig,
+    snapshot: &Snapshot,
+    segment_lsns: &FastMap8<LogOffset, Lsn>,
+    cut: Lsn,
+    dir: &Path,
+) -> Result<()> {
+    let segment_size = config.segment_size as LogOffset;
+    let segment_of = |lid: LogOffset| lid / segment_size * segment_size;
+
+    let mut lids: BTreeSet<LogOffset> = snapshot
+        .pt
+        .iter()
+        .filter_map(|state| match state {
+            PageState::Present(items) => Some(items),
+            _ => None,
+        })
+        .flatten()
+        .map(|(_lsn, ptr, _sz)| segment_of(ptr.lid()))
+        .collect();
+
+    // recovery replays the log from the segment holding
+    // the last message that the snapshot includes
+    let _ = lids.insert(segment_of(snapshot.last_lid));
+
+    let file =
+        OpenOptions::new().write(true).create_new(true).open(dir.join("db"))?;
+
+    let mut buf = vec![0; config.segment_size];
+
+    for lid in &lids {
+        let lsn = match segment_lsns.get(lid) {
+            Some(lsn) if *lsn <= cut => *lsn,
+            _ => {
+                return Err(Error::ReportableBug(format!(
+                    "the segment at {} was reused while being backed up",
+                    lid
+                )));
+            }
+        };
+
+        let len =
+            usize::try_from(cut - lsn + 1).unwrap().min(config.segment_size);
+
+        io_fail!(config, "backup copy segment");
+        config.file.pread_exact(&mut buf[..len], *lid)?;
+        pwrite_all(&file, &buf[..len], *lid)?;
+    }
+
+    // the rest of the last segment reads as zeroes, which
+    // recovery treats as the end of the log
+    if let Some(last) = lids.iter().next_back() {
+        file.set_len(last + segment_size)?;
+    }
+
+    file.sync_all()?;
+
+    Ok(())
+}
+
+/// Copies the blobs that `snapshot` refers to into `dir`.
+pub(crate) fn copy_blobs(
+    config: &RunningConfig,
+    snapshot: &Snapshot,
+    dir: &Path,
+) -> Result<()> {
+    let blobs_dir = dir.join("blobs");
+    fs::create_dir_all(&blobs_dir)?;
+
+    for state in &snapshot.pt {
+        let items = if let PageState::Present(items) = state {
+            items
+        } else {
+            continue;
+        };
+
+        for (_lsn, ptr, _sz) in items {
+            if let DiskPtr::Blob(_, blob_ptr) = ptr {
+                let to = blobs_dir.join(format!("{}", blob_ptr));
+                let _copied = fs::copy(config.blob_path(*blob_ptr), &to)?;
+                OpenOptions::new().write(true).open(&to)?.sync_all()?;
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -52,9 +52,9 @@ pub(crate) fn read_blob(
     let crc_actual = hasher.finalize();
 
     if crc_expected == crc_actual {
-        let buf =
-            if config.use_compression { maybe_decompress(buf)? } else { buf };
-        Ok((MessageKind::from(kind_byte[0]), buf))
+        let compressed = kind_byte[0] & COMPRESSED_KIND_FLAG != 0;
+        let buf = if compressed { decompress(buf)? } else { buf };
+        Ok((MessageKind::from(kind_byte[0] & !COMPRESSED_KIND_FLAG), buf))
     } else {
         warn!("blob {} failed crc check!", blob_ptr);
 
@@ -62,17 +62,140 @@ pub(crate) fn read_blob(
     }
 }
 
+/// Reads only the `MessageKind` of a blob, without
+/// reading or verifying the rest of its contents.
+pub(crate) fn read_blob_kind(
+    blob_ptr: Lsn,
+    config: &Config,
+) -> Result<MessageKind> {
+    let path = config.blob_path(blob_ptr);
+    let mut f = std::fs::OpenOptions::new().read(true).open(&path)?;
+
+    let mut prefix = [0_u8; std::mem::size_of::<u32>() + 1];
+    f.read_exact(&mut prefix)?;
+
+    let kind_byte = prefix[std::mem::size_of::<u32>()];
+
+    Ok(MessageKind::from(kind_byte & !COMPRESSED_KIND_FLAG))
+}
+
+/// Incrementally reads the `IVec` stored in a `BlobValue`
+/// blob, verifying its CRC once the last byte has been read.
+#[derive(Debug)]
+pub(crate) struct BlobReader {
+    file: std::io::BufReader<std::fs::File>,
+    blob_ptr: Lsn,
+    hasher: crc32fast::Hasher,
+    crc_expected: u32,
+    remaining: u64,
+}
+
+impl BlobReader {
+    /// Opens the blob and consumes its header and the
+    /// serialized length prefix of the value it holds.
+    /// Compressed blobs can't be read incrementally, and
+    /// return `Error::Unsupported`.
+    pub(crate) fn open(blob_ptr: Lsn, config: &Config) -> Result<BlobReader> {
+        let path = config.blob_path(blob_ptr);
+        let mut file = std::io::BufReader::new(
+            std::fs::OpenOptions::new().read(true).open(&path)?,
+        );
+
+        let mut prefix = [0_u8; std::mem::size_of::<u32>() + 1];
+        file.read_exact(&mut prefix)?;
+
+        let crc_expected = arr_to_u32(&prefix[..std::mem::size_of::<u32>()]);
+        let kind_byte = prefix[std::mem::size_of::<u32>()];
+
+        if kind_byte & COMPRESSED_KIND_FLAG != 0 {
+            return Err(Error::Unsupported(
+                "compressed blobs can't be read incrementally".to_owned(),
+            ));
+        }
+
+        if MessageKind::from(kind_byte) != MessageKind::BlobValue {
+            return Err(Error::Corruption { at: DiskPtr::Blob(0, blob_ptr) });
+        }
+
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&[kind_byte]);
+
+        // the value is serialized as a varint length
+        // followed by its bytes, and the first byte of
+        // the varint determines how many follow it.
+        let mut len_buf = [0_u8; 9];
+        file.read_exact(&mut len_buf[..1])?;
+        let len_len = match len_buf[0] {
+            0..=240 => 1,
+            241..=248 => 2,
+            other => usize::from(other - 246),
+        };
+        file.read_exact(&mut len_buf[1..len_len])?;
+        hasher.update(&len_buf[..len_len]);
+
+        let remaining = u64::deserialize(&mut &len_buf[..len_len])?;
+
+        Ok(BlobReader { file, blob_ptr, hasher, crc_expected, remaining })
+    }
+
+    /// The number of bytes of the value that have not been read yet.
+    pub(crate) const fn remaining(&self) -> u64 {
+        self.remaining
+    }
+}
+
+impl Read for BlobReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let max = usize::try_from(self.remaining)
+            .map_or(buf.len(), |remaining| remaining.min(buf.len()));
+
+        let read = self.file.read(&mut buf[..max])?;
+
+        if read == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+
+        self.hasher.update(&buf[..read]);
+        self.remaining -= read as u64;
+
+        if self.remaining == 0 && self.hasher.clone().finalize() != self.crc_expected
+        {
+            warn!("blob {} failed crc check!", self.blob_ptr);
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                Error::Corruption { at: DiskPtr::Blob(0, self.blob_ptr) },
+            ));
+        }
+
+        Ok(read)
+    }
+}
+
 pub(crate) fn write_blob<T: Serialize>(
     config: &Config,
     kind: MessageKind,
+    compressed: bool,
     id: Lsn,
     item: &T,
 ) -> Result<()> {
     let path = config.blob_path(id);
+    if config.in_memory {
+        // `in_memory` databases create their blobs
+        // directory when the first blob is written
+        std::fs::create_dir_all(path.parent().unwrap())?;
+    }
     let mut f =
         std::fs::OpenOptions::new().write(true).create_new(true).open(&path)?;
 
-    let kind_buf = &[kind.into()];
+    let kind_buf = if compressed {
+        &[kind.into() | COMPRESSED_KIND_FLAG]
+    } else {
+        &[kind.into()]
+    };
 
     let mut hasher = crc32fast::Hasher::new();
     hasher.update(kind_buf);
@@ -99,6 +222,11 @@ pub(crate) fn write_blob<T: Serialize>(
 }
 
 pub(crate) fn gc_blobs(config: &Config, stable_lsn: Lsn) -> Result<()> {
+    if config.in_memory {
+        // nothing survives from a previous run
+        return Ok(());
+    }
+
     let stable = config.blob_path(stable_lsn);
     let blob_dir = stable.parent().unwrap();
     let blobs = std::fs::read_dir(blob_dir)?;
@@ -11,6 +11,10 @@ pub const MAX_MSG_HEADER_LEN: usize = 32;
 /// Log segments have a header of this length.
 pub const SEG_HEADER_LEN: usize = 20;
 
+/// With `Config::use_direct_io`, log writes start and
+/// end on multiples of this many bytes.
+pub(crate) const DIRECT_IO_BLOCK_SIZE: usize = 4096;
+
 /// The minimum number of items per segment.
 /// Items larger than this fraction of an `io_buf`
 /// will be stored as an off-log blob.
@@ -23,19 +27,13 @@ pub const MAX_SPACE_AMPLIFICATION: f64 = 5.;
 pub(crate) const META_PID: PageId = 0;
 pub(crate) const COUNTER_PID: PageId = 1;
 pub(crate) const BATCH_MANIFEST_PID: PageId = PageId::max_value() - 666;
+pub(crate) const FREE_BATCH_PID: PageId = PageId::max_value() - 667;
 
 pub(crate) const PAGE_CONSOLIDATION_THRESHOLD: usize = 10;
+/// The most messages in the log that a page can be made up
+/// of before it has to be consolidated, whatever the
+/// `ConsolidationPolicy` says.
+pub(crate) const MAX_PAGE_CHAIN_LENGTH: usize = 16;
+/// How many prefetches of leaves may be in flight at once.
+pub(crate) const PREFETCH_QUEUE_CAPACITY: usize = 8;
 pub(crate) const SEGMENT_CLEANUP_THRESHOLD: usize = 50;
-
-// Allows for around 1 trillion items to be stored
-// 2^37 * (assuming 50% node fill, 8 items per leaf)
-// and well below 1% of nodes being non-leaf nodes.
-#[cfg(target_pointer_width = "64")]
-pub(crate) const MAX_PID_BITS: usize = 37;
-
-// Allows for around 32 billion items to be stored
-// 2^32 * (assuming 50% node fill of 8 items per leaf)
-// and well below 1% of nodes being non-leaf nodes.
-// Assumed to be enough for a 32-bit system.
-#[cfg(target_pointer_width = "32")]
-pub(crate) const MAX_PID_BITS: usize = 32;
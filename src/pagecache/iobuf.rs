@@ -17,7 +17,7 @@ macro_rules! io_fail {
     ($self:expr, $e:expr) => {
         #[cfg(feature = "failpoints")]
         {
-            if crate::fail::is_active($e) {
+            if $self.config.fault_plan.is_active($e) {
                 $self.config.set_global_error(Error::FailPoint);
                 // wake up any waiting threads so they don't stall forever
                 let _mu = $self.intervals.lock();
@@ -118,6 +118,24 @@ impl IoBuf {
         }
     }
 
+    // With direct IO, writes must start on a block boundary,
+    // so they also cover the part of the first block that
+    // comes before `base`, which either belongs to an earlier
+    // write from the same segment's buffer or was read back
+    // in from the log during startup.
+    fn get_mut_block_range(&self, len: usize) -> &'static mut [u8] {
+        let buf_ptr = self.buf.get();
+        let prefix = self.base % DIRECT_IO_BLOCK_SIZE;
+
+        unsafe {
+            assert!((*buf_ptr).1 >= self.base + len);
+            std::slice::from_raw_parts_mut(
+                (*buf_ptr).0.add(self.base - prefix),
+                prefix + len,
+            )
+        }
+    }
+
     // use this for operations on an `IoBuf` that must be
     // linearized together, and can't fit in the header!
     pub(crate) fn linearized<F, B>(&self, f: F) -> B
@@ -164,6 +182,11 @@ impl IoBuf {
         self.set_header(bumped);
     }
 
+    // `maxed`, the header and the pointer to the current
+    // iobuf stay SeqCst: writers reserve space by CASing
+    // the header of the iobuf that they loaded while
+    // rotation seals it and swaps in the next one, and both
+    // sides need to agree on a single order of those writes.
     pub(crate) fn set_maxed(&self, maxed: bool) {
         debug_delay();
         self.maxed.store(maxed, SeqCst);
@@ -223,9 +246,17 @@ pub(crate) struct IoBufs {
     pub stable_lsn: AtomicLsn,
     pub max_reserved_lsn: AtomicLsn,
     pub max_header_stable_lsn: Arc<AtomicLsn>,
+    // The bytes written to the log since startup, for
+    // calculating write amplification.
+    pub written_bytes: AtomicU64,
     pub segment_accountant: Mutex<SegmentAccountant>,
     pub segment_cleaner: SegmentCleaner,
     deferred_segment_ops: stack::Stack<SegmentOp>,
+
+    // Callbacks waiting for an lsn to become stable, which
+    // are run by whichever thread makes it stable.
+    stable_waiters: Mutex<Vec<(Lsn, Box<dyn FnOnce() + Send>)>>,
+
     #[cfg(feature = "io_uring")]
     pub submission_mutex: Mutex<()>,
     #[cfg(feature = "io_uring")]
@@ -251,8 +282,6 @@ impl IoBufs {
 
         let segment_size = config.segment_size;
 
-        let snapshot_last_lsn = snapshot.last_lsn;
-        let snapshot_last_lid = snapshot.last_lid;
         let snapshot_max_header_stable_lsn = snapshot.max_header_stable_lsn;
 
         let segment_cleaner = SegmentCleaner::default();
@@ -264,39 +293,7 @@ impl IoBufs {
                 segment_cleaner.clone(),
             )?;
 
-        let (next_lsn, next_lid) =
-            if snapshot_last_lsn % segment_size as Lsn == 0 {
-                (snapshot_last_lsn, snapshot_last_lid)
-            } else {
-                let width = match read_message(
-                    &**file,
-                    snapshot_last_lid,
-                    SegmentNumber(
-                        u64::try_from(snapshot_last_lsn).unwrap()
-                            / u64::try_from(config.segment_size).unwrap(),
-                    ),
-                    &config,
-                ) {
-                    Ok(LogRead::Canceled(inline_len))
-                    | Ok(LogRead::Inline(_, _, inline_len)) => inline_len,
-                    Ok(LogRead::Blob(_header, _buf, _blob_ptr, inline_len)) => {
-                        inline_len
-                    }
-                    other => {
-                        // we can overwrite this non-flush
-                        debug!(
-                            "got non-flush tip while recovering at {}: {:?}",
-                            snapshot_last_lid, other
-                        );
-                        0
-                    }
-                };
-
-                (
-                    snapshot_last_lsn + Lsn::from(width),
-                    snapshot_last_lid + LogOffset::from(width),
-                )
-            };
+        let (next_lsn, next_lid) = recovered_tip(&config, snapshot)?;
 
         trace!(
             "starting IoBufs with next_lsn: {} \
@@ -349,7 +346,7 @@ impl IoBufs {
                 next_lid, next_lsn
             );
 
-            IoBuf {
+            let iobuf = IoBuf {
                 buf: Arc::new(UnsafeCell::new(AlignedBuf::new(segment_size))),
                 header: CachePadded::new(AtomicU64::new(0)),
                 base,
@@ -359,7 +356,17 @@ impl IoBufs {
                 maxed: AtomicBool::new(false),
                 linearizer: Mutex::new(()),
                 stored_max_stable_lsn: -1,
+            };
+
+            if config.direct_file.is_some() {
+                // direct writes will rewrite the start of
+                // the block that the tip is in
+                let prefix = iobuf.get_mut_block_range(0);
+                let prefix_offset = next_lid - prefix.len() as LogOffset;
+                config.file.pread_exact(prefix, prefix_offset)?;
             }
+
+            iobuf
         };
 
         // remove all blob files larger than our stable offset
@@ -378,9 +385,11 @@ impl IoBufs {
             max_header_stable_lsn: Arc::new(AtomicLsn::new(
                 snapshot_max_header_stable_lsn,
             )),
+            written_bytes: AtomicU64::new(0),
             segment_accountant: Mutex::new(segment_accountant),
             segment_cleaner,
             deferred_segment_ops: stack::Stack::default(),
+            stable_waiters: Mutex::new(vec![]),
             #[cfg(feature = "io_uring")]
             submission_mutex: Mutex::new(()),
             #[cfg(feature = "io_uring")]
@@ -408,6 +417,26 @@ impl IoBufs {
         self.deferred_segment_ops.push(op, guard);
     }
 
+    /// Like `sa_mark_replace`, but always leaves the accounting
+    /// to whoever next holds the segment accountant, so that
+    /// replacing many pages at once doesn't hold it up.
+    pub(in crate::pagecache) fn sa_defer_replace(
+        &self,
+        pid: PageId,
+        lsn: Lsn,
+        old_cache_infos: &StackVec,
+        new_cache_info: CacheInfo,
+        guard: &Guard,
+    ) {
+        let op = SegmentOp::Replace {
+            pid,
+            lsn,
+            old_cache_infos: *old_cache_infos,
+            new_cache_info,
+        };
+        self.deferred_segment_ops.push(op, guard);
+    }
+
     pub(in crate::pagecache) fn sa_mark_replace(
         &self,
         pid: PageId,
@@ -493,13 +522,19 @@ impl IoBufs {
             cur_lsn: corrected_lsn,
             segment_base: None,
             segment_iter,
+            dangling_blobs: 0,
+            pending_frees: vec![],
+            pending_free_location: (0, DiskPtr::Inline(0), 0),
+            incomplete_batch: false,
         }
     }
 
     /// Returns the last stable offset in storage.
     pub(in crate::pagecache) fn stable(&self) -> Lsn {
         debug_delay();
-        self.stable_lsn.load(SeqCst)
+        // pairs with the swap in `mark_interval`, which is only
+        // made after the writes below it are durable
+        self.stable_lsn.load(Acquire)
     }
 
     // Adds a header to the front of the buffer
@@ -523,7 +558,13 @@ impl IoBufs {
         if let Some(blob_id) = blob_id {
             // write blob to file
             io_fail!(self, "blob blob write");
-            write_blob(&self.config, header.kind, blob_id, item)?;
+            write_blob(
+                &self.config,
+                header.kind,
+                header.compressed,
+                blob_id,
+                item,
+            )?;
 
             let _ = Measure::new(&M.serialize);
             blob_id.serialize_into(out_buf_ref);
@@ -595,6 +636,7 @@ impl IoBufs {
 
             let header = MessageHeader {
                 kind: MessageKind::Cap,
+                compressed: false,
                 pid: PageId::max_value(),
                 segment_number,
                 len: u64::try_from(pad_len).unwrap(),
@@ -653,7 +695,34 @@ impl IoBufs {
             }
         }
 
-        let total_len = if maxed { capacity } else { bytes_to_write };
+        let direct_io_len = if maxed || self.config.direct_file.is_none() {
+            bytes_to_write
+        } else {
+            direct_io_len(iobuf.base, bytes_to_write)
+        };
+
+        // with direct IO, a cancelled message fills the rest of the
+        // last block so that the next buffer starts on a block boundary
+        if direct_io_len > bytes_to_write {
+            let gap = direct_io_len - bytes_to_write;
+            let data = iobuf.get_mut_range(bytes_to_write, gap);
+
+            let segment_number = SegmentNumber(
+                u64::try_from(base_lsn).unwrap()
+                    / u64::try_from(self.config.segment_size).unwrap(),
+            );
+
+            let mut pad_header = direct_io_pad_header(gap, segment_number);
+            let header_bytes = pad_header.serialize();
+            let body = vec![0; usize::try_from(pad_header.len).unwrap()];
+            pad_header.crc32 = calculate_message_crc32(&header_bytes, &body);
+
+            let mut cursor = &mut *data;
+            pad_header.serialize_into(&mut cursor);
+            cursor.copy_from_slice(&body);
+        }
+
+        let total_len = if maxed { capacity } else { direct_io_len };
 
         let data = iobuf.get_mut_range(0, total_len);
         let stored_max_stable_lsn = iobuf.stored_max_stable_lsn;
@@ -661,79 +730,67 @@ impl IoBufs {
         io_fail!(self, "buffer write");
         #[cfg(feature = "io_uring")]
         {
-            let mut wrote = 0;
-            while wrote < total_len {
-                let to_write = &data[wrote..];
-                let offset = log_offset + wrote as u64;
-
-                // we take out this mutex to guarantee
-                // that our `Link` write operation below
-                // is serialized with the following sync.
-                // we don't put the `Rio` instance into
-                // the `Mutex` because we want to drop the
-                // `Mutex` right after beginning the async
-                // submission.
-                let link_mu = self.submission_mutex.lock();
-
-                // using the `Link` ordering, we specify
-                // that `io_uring` should not begin
-                // the following `sync_file_range`
-                // until the previous write is
-                // complete.
-                let wrote_completion = self.io_uring.write_at_ordered(
-                    &*self.config.file,
-                    &to_write,
-                    offset,
-                    rio::Ordering::Link,
-                );
+            if let Some(file) = self.config.file.as_disk() {
+                let mut wrote = 0;
+                while wrote < total_len {
+                    let to_write = &data[wrote..];
+                    let offset = log_offset + wrote as u64;
+
+                    // we take out this mutex to guarantee
+                    // that our `Link` write operation below
+                    // is serialized with the following sync.
+                    // we don't put the `Rio` instance into
+                    // the `Mutex` because we want to drop the
+                    // `Mutex` right after beginning the async
+                    // submission.
+                    let link_mu = self.submission_mutex.lock();
+
+                    // using the `Link` ordering, we specify
+                    // that `io_uring` should not begin
+                    // the following `sync_file_range`
+                    // until the previous write is
+                    // complete.
+                    let wrote_completion = self.io_uring.write_at_ordered(
+                        file,
+                        &to_write,
+                        offset,
+                        rio::Ordering::Link,
+                    );
 
-                let sync_completion = self.io_uring.sync_file_range(
-                    &*self.config.file,
-                    offset,
-                    to_write.len(),
-                );
+                    let sync_completion = self.io_uring.sync_file_range(
+                        file,
+                        offset,
+                        to_write.len(),
+                    );
 
-                sync_completion.wait()?;
+                    sync_completion.wait()?;
 
-                // TODO we want to move this above the previous `wait`
-                // but there seems to be an issue in `rio` that is
-                // triggered when multiple threads are submitting
-                // events while events from other threads are in play.
-                drop(link_mu);
+                    // TODO we want to move this above the previous `wait`
+                    // but there seems to be an issue in `rio` that is
+                    // triggered when multiple threads are submitting
+                    // events while events from other threads are in play.
+                    drop(link_mu);
 
-                wrote += wrote_completion.wait()?;
+                    wrote += wrote_completion.wait()?;
+                }
+            } else {
+                // the log of an `in_memory` database is not a
+                // file that io_uring could write to
+                self.config.file.pwrite_all(data, log_offset)?;
             }
         }
         #[cfg(not(feature = "io_uring"))]
         {
             let f = &self.config.file;
-            pwrite_all(f, data, log_offset)?;
-            if !self.config.temporary {
-                #[cfg(target_os = "linux")]
-                {
-                    use std::os::unix::io::AsRawFd;
-                    let ret = unsafe {
-                        libc::sync_file_range(
-                            f.as_raw_fd(),
-                            i64::try_from(log_offset).unwrap(),
-                            i64::try_from(total_len).unwrap(),
-                            libc::SYNC_FILE_RANGE_WAIT_BEFORE
-                                | libc::SYNC_FILE_RANGE_WRITE
-                                | libc::SYNC_FILE_RANGE_WAIT_AFTER,
-                        )
-                    };
-                    if ret < 0 {
-                        let err = std::io::Error::last_os_error();
-                        if let Some(libc::ENOSYS) = err.raw_os_error() {
-                            f.sync_all()?;
-                        } else {
-                            return Err(err.into());
-                        }
-                    }
-                }
-
-                #[cfg(not(target_os = "linux"))]
-                f.sync_all()?;
+            if let Some(direct_file) = &self.config.direct_file {
+                let block = iobuf.get_mut_block_range(total_len);
+                let prefix = block.len() - total_len;
+                pwrite_all(direct_file, block, log_offset - prefix as u64)?;
+            } else {
+                f.pwrite_all(data, log_offset)?;
+            }
+            if !self.config.is_temporary() {
+                f.sync_range(log_offset, total_len as u64)?;
             }
         }
         io_fail!(self, "buffer write post");
@@ -760,6 +817,7 @@ impl IoBufs {
         }
 
         M.written_bytes.measure(total_len as u64);
+        let _ = self.written_bytes.fetch_add(total_len as u64, Relaxed);
 
         // NB the below deferred logic is important to ensure
         // that we never actually free a segment until all threads
@@ -767,7 +825,7 @@ impl IoBufs {
         // have completed their (crossbeam-epoch)-pinned operations.
         let guard = pin();
         let max_header_stable_lsn = self.max_header_stable_lsn.clone();
-        guard.defer(move || {
+        self.config.defer(&guard, move || {
             trace!("bumping atomic header lsn to {}", stored_max_stable_lsn);
             bump_atomic_lsn(&max_header_stable_lsn, stored_max_stable_lsn)
         });
@@ -849,6 +907,46 @@ impl IoBufs {
             drop(intervals);
 
             let _notified = self.interval_updated.notify_all();
+
+            self.run_stable_waiters();
+        }
+    }
+
+    /// Calls `f` once `lsn` is stable, which may be
+    /// immediately on this thread.
+    pub(in crate::pagecache) fn on_stable<F>(&self, lsn: Lsn, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let mut waiters = self.stable_waiters.lock();
+
+        // `mark_interval` runs waiters after advancing
+        // the stable lsn, so checking it while holding
+        // the lock can't miss an update.
+        if self.stable() >= lsn {
+            drop(waiters);
+            f();
+        } else {
+            waiters.push((lsn, Box::new(f)));
+        }
+    }
+
+    fn run_stable_waiters(&self) {
+        let stable = self.stable();
+
+        let ready: Vec<_> = {
+            let mut waiters = self.stable_waiters.lock();
+            if waiters.is_empty() {
+                return;
+            }
+            let (ready, waiting) =
+                waiters.drain(..).partition(|(lsn, _)| *lsn <= stable);
+            *waiters = waiting;
+            ready
+        };
+
+        for (_lsn, f) in ready {
+            f();
         }
     }
 
@@ -872,6 +970,7 @@ pub(in crate::pagecache) fn make_stable(
     lsn: Lsn,
 ) -> Result<usize> {
     let _measure = Measure::new(&M.make_stable);
+    let _span = instrumentation::enter(&iobufs.config, Span::Flush);
 
     // NB before we write the 0th byte of the file, stable  is -1
     let first_stable = iobufs.stable();
@@ -898,6 +997,29 @@ pub(in crate::pagecache) fn make_stable(
         if offset(header) == 0 || is_sealed(header) || iobuf.lsn > lsn {
             // nothing to write, don't bother sealing
             // current IO buffer.
+        } else if iobufs.stable() + 1 < iobuf.lsn {
+            // group commit: an earlier buffer is still being
+            // written, so rather than sealing this one behind
+            // it, we wait for that write to finish. Writers that
+            // reserve space in the meantime join this buffer,
+            // and the first of us to wake up seals and writes it
+            // with a single sync for all of them.
+            let mut waiter = iobufs.intervals.lock();
+            if iobufs.stable() + 1 < iobuf.lsn
+                && iobufs.config.global_error().is_ok()
+            {
+                trace!("waiting for an earlier write in make_stable({})", lsn);
+
+                // a failed write notifies before it sets the global
+                // error, so we don't wait for long without checking
+                let _ = iobufs.interval_updated.wait_for(
+                    &mut waiter,
+                    std::time::Duration::from_millis(10),
+                );
+            }
+            drop(waiter);
+            stable = iobufs.stable();
+            continue;
         } else {
             maybe_seal_and_write_iobuf(iobufs, &iobuf, header, false)?;
             stable = iobufs.stable();
@@ -983,9 +1105,18 @@ pub(in crate::pagecache) fn maybe_seal_and_write_iobuf(
     }
 
     let sealed = mk_sealed(header);
-    let res_len = offset(sealed);
 
-    let maxed = from_reserve || capacity - res_len < MAX_MSG_HEADER_LEN;
+    let padded_len = if iobufs.config.direct_file.is_some() {
+        direct_io_len(iobuf.base, offset(sealed))
+    } else {
+        offset(sealed)
+    };
+
+    let maxed = from_reserve || capacity < padded_len + MAX_MSG_HEADER_LEN;
+
+    // with direct IO, the next buffer starts at a block boundary,
+    // while a maxed buffer is padded out to the end of its segment
+    let res_len = if maxed { offset(sealed) } else { padded_len };
 
     let worked = iobuf.linearized(|| {
         if iobuf.cas_header(header, sealed).is_err() {
@@ -1164,6 +1295,88 @@ pub(in crate::pagecache) fn maybe_seal_and_write_iobuf(
     }
 }
 
+/// Returns the lsn and offset right after the last message
+/// that `snapshot` includes, where writing resumes after
+/// recovery.
+pub(crate) fn recovered_tip(
+    config: &RunningConfig,
+    snapshot: &Snapshot,
+) -> Result<(Lsn, LogOffset)> {
+    if snapshot.last_lsn % Lsn::try_from(config.segment_size).unwrap() == 0 {
+        return Ok((snapshot.last_lsn, snapshot.last_lid));
+    }
+
+    let width = match read_message(
+        &*config.file,
+        snapshot.last_lid,
+        SegmentNumber(
+            u64::try_from(snapshot.last_lsn).unwrap()
+                / u64::try_from(config.segment_size).unwrap(),
+        ),
+        config,
+    ) {
+        Ok(LogRead::Canceled(inline_len))
+        | Ok(LogRead::Inline(_, _, inline_len)) => inline_len,
+        Ok(LogRead::Blob(_header, _buf, _blob_ptr, inline_len)) => inline_len,
+        other => {
+            // we can overwrite this non-flush
+            debug!(
+                "got non-flush tip while recovering at {}: {:?}",
+                snapshot.last_lid, other
+            );
+            0
+        }
+    };
+
+    Ok((
+        snapshot.last_lsn + Lsn::from(width),
+        snapshot.last_lid + LogOffset::from(width),
+    ))
+}
+
+/// Returns the number of bytes that a direct write of `len`
+/// bytes starting at `base` in its segment takes up, after
+/// rounding up to the next block boundary. The gap that this
+/// leaves is always large enough to hold a `Canceled` message.
+fn direct_io_len(base: usize, len: usize) -> usize {
+    let end = base + len;
+    let mut aligned_end = end;
+    if end % DIRECT_IO_BLOCK_SIZE != 0 {
+        aligned_end += DIRECT_IO_BLOCK_SIZE - end % DIRECT_IO_BLOCK_SIZE;
+    }
+    if aligned_end != end && aligned_end - end < MAX_MSG_HEADER_LEN {
+        aligned_end += DIRECT_IO_BLOCK_SIZE;
+    }
+    aligned_end - base
+}
+
+/// Finds a `Canceled` message header whose serialized length plus
+/// the length of its body is exactly `gap` bytes.
+fn direct_io_pad_header(
+    gap: usize,
+    segment_number: SegmentNumber,
+) -> MessageHeader {
+    // the length of a varint can jump by more than one byte
+    // at a time, so some gaps can't be hit with a single pid
+    for &pid in &[0, PageId::max_value()] {
+        for header_len in 0..=MAX_MSG_HEADER_LEN.min(gap) {
+            let header = MessageHeader {
+                kind: MessageKind::Canceled,
+                compressed: false,
+                pid,
+                segment_number,
+                len: u64::try_from(gap - header_len).unwrap(),
+                crc32: 0,
+            };
+            if usize::try_from(header.serialized_size()).unwrap() == header_len
+            {
+                return header;
+            }
+        }
+    }
+    panic!("no padding message fits a gap of {} bytes", gap);
+}
+
 impl Debug for IoBufs {
     fn fmt(
         &self,
@@ -1,24 +1,40 @@
 use std::{collections::BTreeMap, io};
 
 use super::{
-    pread_exact_or_eof, read_message, read_segment_header, BasedBuf, DiskPtr,
-    LogKind, LogOffset, LogRead, Lsn, SegmentHeader, SegmentNumber,
-    MAX_MSG_HEADER_LEN, SEG_HEADER_LEN,
+    BasedBuf, DiskPtr, FreeBatch, LogKind, LogOffset, LogRead, Lsn,
+    MAX_MSG_HEADER_LEN, MessageKind, SEG_HEADER_LEN, SegmentHeader,
+    SegmentNumber, read_message, read_segment_header,
 };
 use crate::*;
 
 pub struct LogIter {
     pub config: RunningConfig,
-    pub segment_iter: Box<dyn Iterator<Item = (Lsn, LogOffset)>>,
+    pub segment_iter: Box<dyn Iterator<Item = (Lsn, LogOffset)> + Send>,
     pub segment_base: Option<BasedBuf>,
     pub max_lsn: Lsn,
     pub cur_lsn: Lsn,
+    /// The number of messages skipped because the blob
+    /// they pointed to had already been removed.
+    pub dangling_blobs: usize,
+    /// The remaining pages freed by the last `FreeBatch`
+    /// message, which are returned one at a time along
+    /// with the message's lsn, location, and size.
+    pub pending_frees: Vec<PageId>,
+    pub pending_free_location: (Lsn, DiskPtr, u64),
+    /// Whether the iterator stopped at a batch that ends
+    /// after `max_lsn`, leaving the rest of the log unread.
+    pub incomplete_batch: bool,
 }
 
 impl Iterator for LogIter {
     type Item = (LogKind, PageId, Lsn, DiskPtr, u64);
 
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(pid) = self.pending_frees.pop() {
+            let (lsn, ptr, sz) = self.pending_free_location;
+            return Some((LogKind::Free, pid, lsn, ptr, sz));
+        }
+
         // If segment is None, get next on segment_iter, panic
         // if we can't read something we expect to be able to,
         // return None if there are no more remaining segments.
@@ -37,7 +53,6 @@ impl Iterator for LogIter {
                          that contain the initial cur_lsn value or higher"
                     );
 
-                    #[cfg(target_os = "linux")]
                     self.fadvise_willneed(next_lid);
 
                     if let Err(e) = self.read_segment(next_lsn, next_lid) {
@@ -95,7 +110,7 @@ impl Iterator for LogIter {
                         u64::from(inline_len),
                     ));
                 }
-                Ok(LogRead::Inline(header, _buf, inline_len)) => {
+                Ok(LogRead::Inline(header, buf, inline_len)) => {
                     trace!(
                         "read inline flush with header {:?} in LogIter::next",
                         header,
@@ -103,6 +118,28 @@ impl Iterator for LogIter {
                     let lsn = self.cur_lsn;
                     self.cur_lsn += Lsn::from(inline_len);
 
+                    if header.kind == MessageKind::FreeBatch {
+                        // every page in the batch shares this
+                        // message's lsn and location
+                        let mut batch =
+                            match FreeBatch::deserialize(&mut buf.as_slice()) {
+                                Ok(batch) => batch.0,
+                                Err(e) => {
+                                    debug!(
+                                        "failed to deserialize free batch \
+                                     at lsn {}: {}",
+                                        lsn, e
+                                    );
+                                    return None;
+                                }
+                            };
+                        batch.reverse();
+                        self.pending_free_location =
+                            (lsn, DiskPtr::Inline(lid), u64::from(inline_len));
+                        self.pending_frees = batch;
+                        return self.next();
+                    }
+
                     return Some((
                         LogKind::from(header.kind),
                         header.pid,
@@ -113,6 +150,7 @@ impl Iterator for LogIter {
                 }
                 Ok(LogRead::BatchManifest(last_lsn_in_batch, inline_len)) => {
                     if last_lsn_in_batch > self.max_lsn {
+                        self.incomplete_batch = true;
                         return None;
                     } else {
                         self.cur_lsn += Lsn::from(inline_len);
@@ -143,6 +181,7 @@ impl Iterator for LogIter {
                         self.cur_lsn, blob_ptr
                     );
                     self.cur_lsn += Lsn::from(inline_len);
+                    self.dangling_blobs += 1;
                     continue;
                 }
                 Err(e) => {
@@ -204,7 +243,7 @@ impl LogIter {
         self.cur_lsn = segment_header.lsn + SEG_HEADER_LEN as Lsn;
 
         let mut buf = vec![0; self.config.segment_size];
-        let size = pread_exact_or_eof(f, &mut buf, offset)?;
+        let size = f.pread_exact_or_eof(&mut buf, offset)?;
 
         trace!("setting stored segment buffer length to {} after read", size);
         buf.truncate(size);
@@ -213,25 +252,10 @@ impl LogIter {
         Ok(())
     }
 
-    #[cfg(target_os = "linux")]
     fn fadvise_willneed(&self, lid: LogOffset) {
-        use std::os::unix::io::AsRawFd;
-
-        let f = &self.config.file;
-        #[allow(unsafe_code)]
-        let ret = unsafe {
-            libc::posix_fadvise(
-                f.as_raw_fd(),
-                libc::off_t::try_from(lid).unwrap(),
-                libc::off_t::try_from(self.config.segment_size).unwrap(),
-                libc::POSIX_FADV_WILLNEED,
-            )
-        };
-        if ret != 0 {
-            panic!(
-                "failed to call fadvise: {}",
-                std::io::Error::from_raw_os_error(ret)
-            );
+        let len = LogOffset::try_from(self.config.segment_size).unwrap();
+        if let Err(e) = self.config.file.prefetch(lid, len) {
+            panic!("failed to call fadvise: {}", e);
         }
     }
 }
@@ -284,7 +308,7 @@ fn scan_segment_lsns(
     let segment_len = LogOffset::try_from(config.segment_size).unwrap();
 
     let f = &config.file;
-    let file_len = f.metadata()?.len();
+    let file_len = f.len()?;
     let segments = (file_len / segment_len)
         + if file_len % segment_len
             < LogOffset::try_from(SEG_HEADER_LEN).unwrap()
@@ -404,6 +428,10 @@ fn clean_tail_tears(
         segment_base: None,
         max_lsn: missing_item_in_tail.unwrap_or(Lsn::max_value()),
         cur_lsn: 0,
+        dangling_blobs: 0,
+        pending_frees: vec![],
+        pending_free_location: (0, DiskPtr::Inline(0), 0),
+        incomplete_batch: false,
     };
 
     let tip: (Lsn, LogOffset) =
@@ -418,15 +446,19 @@ fn clean_tail_tears(
             |(_, _, lsn, ptr, _)| (lsn, ptr.lid()),
         );
 
+    // recovering to an earlier point tears off everything
+    // after it as if it had never been written
+    let tip_lsn = config.recover_until.map_or(tip.0, |until| tip.0.min(until));
+
     debug!(
         "filtering out segments after detected tear at lsn {} lid {}",
-        tip.0, tip.1
+        tip_lsn, tip.1
     );
 
     let mut to_zero_after_snap_write = vec![];
 
     for (lsn, lid) in ordering
-        .range((std::ops::Bound::Excluded(tip.0), std::ops::Bound::Unbounded))
+        .range((std::ops::Bound::Excluded(tip_lsn), std::ops::Bound::Unbounded))
     {
         debug!(
             "marking torn segment with lsn {} at lid {} \
@@ -437,7 +469,7 @@ fn clean_tail_tears(
     }
 
     ordering =
-        ordering.into_iter().filter(|&(lsn, _lid)| lsn <= tip.0).collect();
+        ordering.into_iter().filter(|&(lsn, _lid)| lsn <= tip_lsn).collect();
 
     Ok((ordering, to_zero_after_snap_write))
 }
@@ -473,6 +505,10 @@ pub fn raw_segment_iter_from(
         cur_lsn: 0,
         segment_base: None,
         segment_iter: tip_segment_iter,
+        dangling_blobs: 0,
+        pending_frees: vec![],
+        pending_free_location: (0, DiskPtr::Inline(0), 0),
+        incomplete_batch: false,
     };
 
     // run the iterator to the end so
@@ -482,7 +518,13 @@ pub fn raw_segment_iter_from(
     // in the actual iterator.
     while let Some(_) = tip_iter.next() {}
 
-    let tip = tip_iter.cur_lsn;
+    // the iterator stopped at the start of the first message
+    // that it couldn't read, so the tip is just before it. a
+    // batch whose last message starts there is incomplete.
+    let last_readable = tip_iter.cur_lsn - 1;
+    let tip = config
+        .recover_until
+        .map_or(last_readable, |until| last_readable.min(until));
 
     trace!("found max stable tip: {}", tip);
 
@@ -503,6 +545,10 @@ pub fn raw_segment_iter_from(
             cur_lsn: 0,
             segment_base: None,
             segment_iter,
+            dangling_blobs: 0,
+            pending_frees: vec![],
+            pending_free_location: (0, DiskPtr::Inline(0), 0),
+            incomplete_batch: false,
         },
         max_header_stable_lsn,
         to_zero_after_snap_write,
@@ -0,0 +1,250 @@
+use std::{
+    fs::{self, OpenOptions},
+    io,
+    path::PathBuf,
+};
+
+use crate::pagecache::*;
+use crate::*;
+
+const FILE_NAME: &str = "leaf_filters";
+
+// more probes than this only slow reads down, for any
+// sensible number of bits per key
+const MAX_HASHES: u8 = 30;
+
+/// A bloom filter over the keys of a leaf that has been
+/// paged out, which lets point reads of keys that it
+/// doesn't hold skip reading the leaf back from the log.
+///
+/// The filter describes the leaf as of the last message
+/// in its chain, at `lsn`, and only for keys between its
+/// `lo` and `hi` bounds. Any write to the leaf pages it
+/// back in and adds a message with a later `lsn`, so a
+/// filter whose `lsn` is not the last one of the page it
+/// was built for is simply ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LeafFilter {
+    pub(crate) lsn: Lsn,
+    pub(crate) lo: IVec,
+    pub(crate) hi: IVec,
+    pub(crate) prefix_len: u16,
+    pub(crate) hashes: u8,
+    pub(crate) bits: IVec,
+}
+
+// FNV-1a, which unlike std's hashers is guaranteed to
+// hash the same way after a persisted filter is reloaded
+// by another build. Its multiplications only carry low
+// bits upwards, so it is finished with murmur3's mix to
+// spread every byte over the low bits too.
+fn hash(key: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in key {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ hash >> 33
+}
+
+// the bit positions that `key` sets in a filter of `len`
+// bits, derived from the two halves of a single hash
+fn probes(key: &[u8], hashes: u8, len: u64) -> impl Iterator<Item = u64> {
+    let hash = hash(key);
+    let h1 = hash & 0xffff_ffff;
+    let h2 = hash >> 32 | 1;
+    (0..u64::from(hashes))
+        .map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % len)
+}
+
+impl LeafFilter {
+    /// Builds a filter over the keys of `node`, or returns
+    /// `None` if it is an index or is being merged away.
+    pub(crate) fn new(
+        node: &Node,
+        lsn: Lsn,
+        bits_per_key: usize,
+    ) -> Option<LeafFilter> {
+        let keys = match &node.data {
+            Data::Leaf(leaf) if !node.merging => &leaf.keys,
+            _ => return None,
+        };
+
+        // whole bytes, because readers go by the length of `bits`
+        let bytes = ((keys.len() * bits_per_key).max(64) + 7) / 8;
+        let len = bytes as u64 * 8;
+        // ln(2) hashes per bit per key is optimal
+        #[allow(clippy::cast_possible_truncation)]
+        let hashes =
+            (bits_per_key * 69 / 100).max(1).min(usize::from(MAX_HASHES)) as u8;
+
+        let mut bits = vec![0_u8; bytes];
+        for key in keys {
+            // leaves store their keys without the prefix
+            // shared by their bounds
+            for bit in probes(key, hashes, len) {
+                bits[usize::try_from(bit / 8).unwrap()] |= 1 << (bit % 8);
+            }
+        }
+
+        Some(LeafFilter {
+            lsn,
+            lo: node.lo.clone(),
+            hi: node.hi.clone(),
+            prefix_len: node.prefix_len,
+            hashes,
+            bits: IVec::from(bits),
+        })
+    }
+
+    /// Returns `true` if `key` is between the leaf's bounds
+    /// but certainly not in it.
+    pub(crate) fn excludes(&self, key: &[u8]) -> bool {
+        if key < &*self.lo || (!self.hi.is_empty() && key >= &*self.hi) {
+            return false;
+        }
+        let suffix = &key[usize::from(self.prefix_len)..];
+        let len = self.bits.len() as u64 * 8;
+        probes(suffix, self.hashes, len).any(|bit| {
+            self.bits[usize::try_from(bit / 8).unwrap()] & (1 << (bit % 8))
+                == 0
+        })
+    }
+
+    pub(crate) fn rss(&self) -> u64 {
+        (std::mem::size_of::<LeafFilter>()
+            + self.lo.len()
+            + self.hi.len()
+            + self.bits.len()) as u64
+    }
+}
+
+/// The filters of the leaves that are paged out, by pid.
+#[derive(Debug, Default)]
+pub(crate) struct LeafFilters {
+    filters: RwLock<FastMap8<PageId, LeafFilter>>,
+}
+
+impl LeafFilters {
+    /// Reads back the filters persisted by the last run.
+    /// They are checked against the pages that they were
+    /// built for as they are used, so they may be stale.
+    pub(crate) fn open(config: &RunningConfig) -> Result<LeafFilters> {
+        let mut filters = LeafFilters::default();
+        if config.in_memory || config.leaf_filter_bits_per_key == 0 {
+            return Ok(filters);
+        }
+
+        let buf = match fs::read(filters_path(config)) {
+            Ok(buf) => buf,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(filters);
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        if buf.len() < 4 {
+            warn!("empty/corrupt leaf filter file found");
+            return Ok(filters);
+        }
+        let (mut items, crc_bytes) = buf.split_at(buf.len() - 4);
+        if crc32(items) != arr_to_u32(crc_bytes) {
+            warn!("leaf filter file has a bad checksum");
+            return Ok(filters);
+        }
+
+        while !items.is_empty() {
+            let (pid, filter) =
+                <(PageId, LeafFilter)>::deserialize(&mut items)?;
+            let _ = filters.filters.get_mut().insert(pid, filter);
+        }
+        debug!("read {} leaf filters", filters.filters.get_mut().len());
+
+        Ok(filters)
+    }
+
+    pub(crate) fn insert(&self, pid: PageId, filter: LeafFilter) {
+        let _ = self.filters.write().insert(pid, filter);
+    }
+
+    pub(crate) fn retain<F>(&self, mut f: F)
+    where
+        F: FnMut(PageId, Lsn) -> bool,
+    {
+        self.filters.write().retain(|pid, filter| f(*pid, filter.lsn));
+    }
+
+    pub(crate) fn remove(&self, pid: PageId) {
+        if self.filters.read().contains_key(&pid) {
+            let _ = self.filters.write().remove(&pid);
+        }
+    }
+
+    /// Returns `true` if the filter of `pid` was built at
+    /// `lsn` and excludes `key`.
+    pub(crate) fn excludes(&self, pid: PageId, lsn: Lsn, key: &[u8]) -> bool {
+        match self.filters.read().get(&pid) {
+            Some(filter) if filter.lsn == lsn => filter.excludes(key),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn rss(&self) -> u64 {
+        self.filters.read().values().map(LeafFilter::rss).sum()
+    }
+
+    /// Writes the filters of pages whose last message is
+    /// stable at `stable_lsn` into the database's directory,
+    /// in place of the previous ones. Later messages may be
+    /// lost in a crash, and their lsns reused by others.
+    pub(crate) fn persist(
+        &self,
+        config: &RunningConfig,
+        stable_lsn: Lsn,
+    ) -> Result<()> {
+        if config.in_memory
+            || config.read_only
+            || config.leaf_filter_bits_per_key == 0
+        {
+            return Ok(());
+        }
+
+        let mut buf = vec![];
+        for (pid, filter) in self.filters.read().iter() {
+            if filter.lsn <= stable_lsn {
+                buf.extend_from_slice(&(*pid, filter.clone()).serialize());
+            }
+        }
+        let crc = u32_to_arr(crc32(&buf));
+        buf.extend_from_slice(&crc);
+
+        let path = filters_path(config);
+        let tmp_path = path.with_extension("generating");
+        let mut f = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+
+        io_fail!(config, "leaf filters write");
+        f.write_all(&buf)?;
+        if !config.is_temporary() {
+            f.sync_all()?;
+        }
+
+        io_fail!(config, "leaf filters write mv");
+        fs::rename(&tmp_path, &path)?;
+
+        Ok(())
+    }
+}
+
+fn filters_path(config: &RunningConfig) -> PathBuf {
+    let mut path = config.get_path();
+    path.push(FILE_NAME);
+    path
+}
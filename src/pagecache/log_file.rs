@@ -0,0 +1,300 @@
+//! The file that the log is written to, which is either a
+//! file on disk that is accessed through the positioned IO
+//! of the platform's `parallel_io` module, a buffer in
+//! memory for databases opened with `Config::in_memory`, or
+//! the disk of a `Simulation`.
+
+use std::{
+    convert::TryFrom,
+    fmt::{self, Debug},
+    fs::File,
+    io,
+    sync::atomic::{AtomicU64, Ordering::Relaxed},
+    time::{Duration, Instant},
+};
+
+use parking_lot::RwLock;
+
+use crate::{M, Measure};
+
+use super::{
+    LogOffset, allocated_len, assert_usize, is_lock_contended, lock,
+    pread_exact, pread_exact_or_eof, prefetch, punch_hole, pwrite_all,
+    sync_range,
+};
+
+pub(crate) struct LogFile {
+    storage: LogStorage,
+    // how many times writes to the log were made durable,
+    // and the nanoseconds that took in total
+    syncs: AtomicU64,
+    sync_nanos: AtomicU64,
+}
+
+pub(crate) enum LogStorage {
+    Disk(File),
+    /// The log of an `in_memory` database, which is never
+    /// written to disk.
+    Memory(RwLock<Vec<u8>>),
+    /// The log of a database opened on a `Simulation`'s
+    /// simulated machine.
+    #[cfg(feature = "simulation")]
+    Simulated(crate::simulation::SimulatedFile),
+}
+
+impl From<LogStorage> for LogFile {
+    fn from(storage: LogStorage) -> LogFile {
+        LogFile {
+            storage,
+            syncs: AtomicU64::new(0),
+            sync_nanos: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Debug for LogFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.storage {
+            LogStorage::Disk(file) => write!(f, "LogFile::Disk({:?})", file),
+            LogStorage::Memory(buf) => {
+                write!(f, "LogFile::Memory({} bytes)", buf.read().len())
+            }
+            #[cfg(feature = "simulation")]
+            LogStorage::Simulated(file) => write!(f, "LogFile::{:?}", file),
+        }
+    }
+}
+
+fn unexpected_eof() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer")
+}
+
+impl LogFile {
+    /// Returns the file on disk, unless the log is kept in
+    /// memory.
+    #[cfg(feature = "io_uring")]
+    pub(crate) fn as_disk(&self) -> Option<&File> {
+        match &self.storage {
+            LogStorage::Disk(file) => Some(file),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn pread_exact(
+        &self,
+        buf: &mut [u8],
+        offset: LogOffset,
+    ) -> io::Result<()> {
+        match &self.storage {
+            LogStorage::Disk(file) => pread_exact(file, buf, offset),
+            _ => {
+                if self.pread_exact_or_eof(buf, offset)? == buf.len() {
+                    Ok(())
+                } else {
+                    Err(unexpected_eof())
+                }
+            }
+        }
+    }
+
+    pub(crate) fn pread_exact_or_eof(
+        &self,
+        buf: &mut [u8],
+        offset: LogOffset,
+    ) -> io::Result<usize> {
+        match &self.storage {
+            LogStorage::Disk(file) => pread_exact_or_eof(file, buf, offset),
+            LogStorage::Memory(mem) => {
+                let data = mem.read();
+                let start = std::cmp::min(assert_usize(offset), data.len());
+                let src = &data[start..];
+                let len = std::cmp::min(src.len(), buf.len());
+                buf[..len].copy_from_slice(&src[..len]);
+                Ok(len)
+            }
+            #[cfg(feature = "simulation")]
+            LogStorage::Simulated(file) => file.pread_exact_or_eof(buf, offset),
+        }
+    }
+
+    pub(crate) fn pwrite_all(
+        &self,
+        buf: &[u8],
+        offset: LogOffset,
+    ) -> io::Result<()> {
+        match &self.storage {
+            LogStorage::Disk(file) => pwrite_all(file, buf, offset),
+            LogStorage::Memory(mem) => {
+                let mut data = mem.write();
+                let start = assert_usize(offset);
+                let end = start + buf.len();
+                if data.len() < end {
+                    data.resize(end, 0);
+                }
+                data[start..end].copy_from_slice(buf);
+                Ok(())
+            }
+            #[cfg(feature = "simulation")]
+            LogStorage::Simulated(file) => file.pwrite_all(buf, offset),
+        }
+    }
+
+    pub(crate) fn sync_all(&self) -> io::Result<()> {
+        match &self.storage {
+            LogStorage::Disk(file) => self.timed_sync(|| file.sync_all()),
+            LogStorage::Memory(_) => Ok(()),
+            #[cfg(feature = "simulation")]
+            LogStorage::Simulated(file) => self.timed_sync(|| file.sync_all()),
+        }
+    }
+
+    pub(crate) fn sync_data(&self) -> io::Result<()> {
+        match &self.storage {
+            LogStorage::Disk(file) => self.timed_sync(|| file.sync_data()),
+            LogStorage::Memory(_) => Ok(()),
+            #[cfg(feature = "simulation")]
+            LogStorage::Simulated(file) => self.timed_sync(|| file.sync_all()),
+        }
+    }
+
+    /// Makes the writes to the given range durable.
+    pub(crate) fn sync_range(
+        &self,
+        offset: LogOffset,
+        len: u64,
+    ) -> io::Result<()> {
+        match &self.storage {
+            LogStorage::Disk(file) => {
+                self.timed_sync(|| sync_range(file, offset, len))
+            }
+            LogStorage::Memory(_) => Ok(()),
+            #[cfg(feature = "simulation")]
+            LogStorage::Simulated(file) => {
+                self.timed_sync(|| file.sync_range(offset, len))
+            }
+        }
+    }
+
+    fn timed_sync<F>(&self, f: F) -> io::Result<()>
+    where
+        F: FnOnce() -> io::Result<()>,
+    {
+        let _measure = Measure::new(&M.fsync);
+        let start = Instant::now();
+        let ret = f();
+        let nanos = u64::try_from(start.elapsed().as_nanos()).unwrap();
+        let _ = self.syncs.fetch_add(1, Relaxed);
+        let _ = self.sync_nanos.fetch_add(nanos, Relaxed);
+        ret
+    }
+
+    /// Returns how many times writes to the log were made
+    /// durable, and how long that took in total.
+    pub(crate) fn syncs(&self) -> (u64, Duration) {
+        (
+            self.syncs.load(Relaxed),
+            Duration::from_nanos(self.sync_nanos.load(Relaxed)),
+        )
+    }
+
+    /// Returns the space used by the given range without
+    /// changing the length, after which it reads as zeroes.
+    /// Returns `false` if that isn't possible, in which case
+    /// the range is left as it was.
+    pub(crate) fn punch_hole(
+        &self,
+        offset: LogOffset,
+        len: u64,
+    ) -> io::Result<bool> {
+        match &self.storage {
+            LogStorage::Disk(file) => punch_hole(file, offset, len),
+            LogStorage::Memory(_) => Ok(false),
+            #[cfg(feature = "simulation")]
+            LogStorage::Simulated(file) => file.punch_hole(offset, len),
+        }
+    }
+
+    /// Asks for the given range to be read ahead of time.
+    pub(crate) fn prefetch(
+        &self,
+        offset: LogOffset,
+        len: u64,
+    ) -> io::Result<()> {
+        match &self.storage {
+            LogStorage::Disk(file) => prefetch(file, offset, len),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn len(&self) -> io::Result<u64> {
+        match &self.storage {
+            LogStorage::Disk(file) => Ok(file.metadata()?.len()),
+            LogStorage::Memory(data) => Ok(data.read().len() as u64),
+            #[cfg(feature = "simulation")]
+            LogStorage::Simulated(file) => file.len(),
+        }
+    }
+
+    pub(crate) fn set_len(&self, len: u64) -> io::Result<()> {
+        match &self.storage {
+            LogStorage::Disk(file) => file.set_len(len),
+            LogStorage::Memory(data) => {
+                data.write().resize(assert_usize(len), 0);
+                Ok(())
+            }
+            #[cfg(feature = "simulation")]
+            LogStorage::Simulated(file) => file.set_len(len),
+        }
+    }
+
+    /// Returns the space that the log takes up, which is less
+    /// than its length if holes were punched in it.
+    pub(crate) fn allocated_len(&self) -> io::Result<u64> {
+        match &self.storage {
+            LogStorage::Disk(file) => allocated_len(file),
+            _ => self.len(),
+        }
+    }
+
+    /// Takes an advisory lock on the file, which is shared if
+    /// `shared` is set and exclusive otherwise, and waits for
+    /// it if `block` is set. The log in memory can't be seen by
+    /// other processes, so it is never locked.
+    pub(crate) fn lock(&self, shared: bool, block: bool) -> io::Result<()> {
+        match &self.storage {
+            LogStorage::Disk(file) => lock(file, shared, block),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether `err` was returned by `lock` because another
+    /// process holds a conflicting lock.
+    pub(crate) fn is_lock_contended(err: &io::Error) -> bool {
+        is_lock_contended(err)
+    }
+}
+
+#[test]
+fn memory_log_file() {
+    let file = LogFile::from(LogStorage::Memory(RwLock::new(vec![])));
+
+    file.pwrite_all(b"hello", 4).unwrap();
+    assert_eq!(file.len().unwrap(), 9);
+
+    let mut buf = [1; 9];
+    file.pread_exact(&mut buf, 0).unwrap();
+    assert_eq!(&buf, b"\0\0\0\0hello");
+
+    let mut buf = [1; 4];
+    assert_eq!(file.pread_exact_or_eof(&mut buf, 7).unwrap(), 2);
+    assert_eq!(&buf, b"lo\x01\x01");
+    assert_eq!(file.pread_exact_or_eof(&mut buf, 20).unwrap(), 0);
+    assert_eq!(
+        file.pread_exact(&mut buf, 7).unwrap_err().kind(),
+        io::ErrorKind::UnexpectedEof
+    );
+
+    file.set_len(6).unwrap();
+    assert_eq!(file.len().unwrap(), 6);
+    assert!(!file.punch_hole(0, 6).unwrap());
+}
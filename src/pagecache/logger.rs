@@ -1,13 +1,12 @@
-use std::fs::File;
 use std::sync::Arc;
 
 use super::{
-    arr_to_lsn, arr_to_u32, assert_usize, bump_atomic_lsn, iobuf, lsn_to_arr,
-    maybe_decompress, pread_exact, pread_exact_or_eof, read_blob, u32_to_arr,
-    BasedBuf, BlobPointer, DiskPtr, IoBuf, IoBufs, LogKind, LogOffset, Lsn,
-    MessageKind, Reservation, Serialize, Snapshot, BATCH_MANIFEST_PID,
-    COUNTER_PID, MAX_MSG_HEADER_LEN, META_PID, MINIMUM_ITEMS_PER_SEGMENT,
-    SEG_HEADER_LEN,
+    BATCH_MANIFEST_PID, BasedBuf, BlobPointer, COUNTER_PID, DiskPtr,
+    FREE_BATCH_PID, FreeBatch, IoBuf, IoBufs, LogFile, LogKind, LogOffset, Lsn,
+    MAX_MSG_HEADER_LEN, META_PID, MINIMUM_ITEMS_PER_SEGMENT, MessageKind,
+    PageKind, Reservation, SEG_HEADER_LEN, Serialize, Snapshot, arr_to_lsn,
+    arr_to_u32, assert_usize, bump_atomic_lsn, decompress, iobuf, lsn_to_arr,
+    read_blob, u32_to_arr,
 };
 
 use crate::*;
@@ -21,15 +20,24 @@ pub struct Log {
     /// iobufs is the underlying lock-free IO write buffer.
     pub(super) iobufs: Arc<IoBufs>,
     pub(crate) config: RunningConfig,
+    /// The number of messages reserved since the page
+    /// table was last snapshotted.
+    pub(super) ops_since_snapshot: AtomicU64,
 }
 
 impl Log {
+    /// Returns `true` while threadpool tasks that write io
+    /// buffers to the log still hold references to them.
+    pub(crate) fn has_pending_writes(&self) -> bool {
+        Arc::strong_count(&self.iobufs) > 1
+    }
+
     /// Start the log, open or create the configured file,
     /// and optionally start the periodic buffer flush thread.
     pub fn start(config: RunningConfig, snapshot: &Snapshot) -> Result<Self> {
         let iobufs = Arc::new(IoBufs::start(config.clone(), snapshot)?);
 
-        Ok(Self { iobufs, config })
+        Ok(Self { iobufs, config, ops_since_snapshot: AtomicU64::new(0) })
     }
 
     /// Flushes any pending IO buffers to disk to ensure durability.
@@ -49,6 +57,7 @@ impl Log {
         trace!("reading log lsn {} ptr {}", lsn, ptr);
 
         let _wrote = self.make_stable(lsn)?;
+        io_fail!(self.config, "read page");
         let expected_segment_number = SegmentNumber(
             u64::try_from(lsn).unwrap()
                 / u64::try_from(self.config.segment_size).unwrap(),
@@ -65,6 +74,7 @@ impl Log {
             read_blob(blob_ptr, &self.config).map(|(kind, buf)| {
                 let header = MessageHeader {
                     kind,
+                    compressed: false,
                     pid,
                     segment_number: expected_segment_number,
                     crc32: 0,
@@ -75,11 +85,156 @@ impl Log {
         }
     }
 
-    /// returns the current stable offset written to disk
+    /// read several buffers of one page from the disk
+    #[cfg(not(feature = "io_uring"))]
+    pub(crate) fn read_many(
+        &self,
+        pid: PageId,
+        locations: &[(Lsn, DiskPtr, u64)],
+    ) -> Result<Vec<LogRead>> {
+        locations
+            .iter()
+            .map(|&(lsn, ptr, _)| self.read(pid, lsn, ptr))
+            .collect()
+    }
+
+    /// read several buffers of one page from the disk, submitting
+    /// the reads of all inline messages to io_uring before waiting
+    /// for any of them to complete. The length of each read is taken
+    /// from the size of the message that was logged, and any message
+    /// that can't be parsed from what was read is read again with
+    /// `Log::read`.
+    #[cfg(feature = "io_uring")]
+    pub(crate) fn read_many(
+        &self,
+        pid: PageId,
+        locations: &[(Lsn, DiskPtr, u64)],
+    ) -> Result<Vec<LogRead>> {
+        if let Some(max_lsn) = locations.iter().map(|&(lsn, _, _)| lsn).max() {
+            let _wrote = self.make_stable(max_lsn)?;
+        }
+        io_fail!(self.config, "read page");
+
+        let segment_size = self.config.segment_size as LogOffset;
+
+        let bufs: Vec<Option<Vec<u8>>> = locations
+            .iter()
+            .map(|&(_, ptr, log_size)| {
+                if !ptr.is_inline() {
+                    return None;
+                }
+                let lid = ptr.lid();
+                let segment_end = (lid / segment_size + 1) * segment_size;
+                let len = std::cmp::min(
+                    std::cmp::max(log_size, MAX_MSG_HEADER_LEN as u64),
+                    segment_end - lid,
+                );
+                Some(vec![0; usize::try_from(len).unwrap()])
+            })
+            .collect();
+
+        let f = if let Some(f) = self.config.file.as_disk() {
+            f
+        } else {
+            return locations
+                .iter()
+                .map(|&(lsn, ptr, _)| self.read(pid, lsn, ptr))
+                .collect();
+        };
+
+        // see the comment in `IoBufs::write_to_log` on why
+        // submissions are serialized with this mutex
+        let submission_mu = self.iobufs.submission_mutex.lock();
+        let completions: Vec<_> = bufs
+            .iter()
+            .zip(locations)
+            .map(|(buf, &(_, ptr, _))| {
+                buf.as_ref()
+                    .map(|buf| self.iobufs.io_uring.read_at(f, buf, ptr.lid()))
+            })
+            .collect();
+        drop(submission_mu);
+
+        let mut read_lens = Vec::with_capacity(completions.len());
+        for completion in completions {
+            read_lens.push(match completion {
+                Some(completion) => Some(completion.wait()?),
+                None => None,
+            });
+        }
+
+        let mut reads = Vec::with_capacity(locations.len());
+        for ((buf, read_len), &(lsn, ptr, _)) in
+            bufs.into_iter().zip(read_lens).zip(locations)
+        {
+            let read = if let (Some(mut buf), Some(read_len)) = (buf, read_len)
+            {
+                buf.truncate(read_len);
+                let expected_segment_number = SegmentNumber(
+                    u64::try_from(lsn).unwrap()
+                        / u64::try_from(self.config.segment_size).unwrap(),
+                );
+                let based = BasedBuf(buf, ptr.lid());
+                match read_message(
+                    &based,
+                    ptr.lid(),
+                    expected_segment_number,
+                    &self.config,
+                ) {
+                    Ok(read @ LogRead::Inline(..)) => read,
+                    _ => self.read(pid, lsn, ptr)?,
+                }
+            } else {
+                self.read(pid, lsn, ptr)?
+            };
+            reads.push(read);
+        }
+
+        Ok(reads)
+    }
+
+    /// read the message that was written at a particular
+    /// log sequence number, such as one returned by
+    /// `Reservation::complete`, without needing to know its
+    /// page or where it was written. Blocks until `lsn` is
+    /// stable. Returns `None` if no successfully written
+    /// message begins at `lsn`, including when the segment
+    /// that held it has since been reclaimed.
+    pub fn read_lsn(&self, lsn: Lsn) -> Result<Option<LogRead>> {
+        trace!("reading log lsn {}", lsn);
+
+        let _wrote = self.make_stable(lsn)?;
+
+        for (_, pid, item_lsn, ptr, _) in self.iter_from(lsn) {
+            if item_lsn < lsn {
+                continue;
+            }
+            if item_lsn > lsn {
+                break;
+            }
+            return self.read(pid, lsn, ptr).map(Some);
+        }
+
+        Ok(None)
+    }
+
+    /// returns the current stable offset written to disk.
+    /// Every message at or below this log sequence number
+    /// has been written and fsynced, so a write is durable
+    /// once `stable_offset` is at least its `Lsn`.
     pub fn stable_offset(&self) -> Lsn {
         self.iobufs.stable()
     }
 
+    /// Calls `f` once `lsn` is stable, which may be
+    /// immediately on this thread.
+    pub(crate) fn on_stable<F>(&self, lsn: Lsn, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.iobufs.on_stable(lsn, f)
+    }
+
     /// blocks until the specified log sequence number has
     /// been made stable on disk. Returns the number of
     /// bytes written during this call.
@@ -94,6 +249,7 @@ impl Log {
         &self,
         pid: PageId,
         blob_pointer: BlobPointer,
+        page_kind: PageKind,
         guard: &Guard,
     ) -> Result<Reservation<'_>> {
         self.reserve_inner(
@@ -101,6 +257,10 @@ impl Log {
             pid,
             &blob_pointer,
             Some(blob_pointer),
+            page_kind,
+            // only the blob's own kind byte says whether
+            // its contents are compressed
+            false,
             guard,
         )
     }
@@ -117,10 +277,119 @@ impl Log {
         pid: PageId,
         item: &T,
         guard: &Guard,
+    ) -> Result<Reservation<'_>> {
+        self.reserve_maybe_compressed(
+            log_kind,
+            pid,
+            item,
+            PageKind::Node,
+            false,
+            guard,
+        )
+    }
+
+    /// Reserve space for a consolidated node, which is
+    /// compressed if `compress` is set even when
+    /// `use_compression` is not.
+    pub(super) fn reserve_node(
+        &self,
+        log_kind: LogKind,
+        pid: PageId,
+        node: &Node,
+        compress: bool,
+        guard: &Guard,
+    ) -> Result<Reservation<'_>> {
+        self.reserve_maybe_compressed(
+            log_kind,
+            pid,
+            node,
+            PageKind::Node,
+            compress,
+            guard,
+        )
+    }
+
+    /// Reserve space for a value that overflowed its leaf
+    /// and is being stored in its own page.
+    pub(super) fn reserve_value(
+        &self,
+        pid: PageId,
+        value: &IVec,
+        guard: &Guard,
+    ) -> Result<Reservation<'_>> {
+        self.reserve_maybe_compressed(
+            LogKind::Replace,
+            pid,
+            value,
+            PageKind::Value,
+            false,
+            guard,
+        )
+    }
+
+    /// Reserve space for a page allocated with
+    /// `Db::allocate_page`, or for a fragment linked onto
+    /// one.
+    pub(super) fn reserve_custom(
+        &self,
+        log_kind: LogKind,
+        pid: PageId,
+        bytes: &IVec,
+        guard: &Guard,
+    ) -> Result<Reservation<'_>> {
+        self.reserve_maybe_compressed(
+            log_kind,
+            pid,
+            bytes,
+            PageKind::Custom,
+            false,
+            guard,
+        )
+    }
+
+    /// Reserve space for a single message that frees every
+    /// page in the batch. The batch must fit inline, see
+    /// `Log::max_free_batch_size`.
+    pub(super) fn reserve_free_batch(
+        &self,
+        batch: &FreeBatch,
+        guard: &Guard,
+    ) -> Result<Reservation<'_>> {
+        self.reserve_inner(
+            LogKind::Free,
+            FREE_BATCH_PID,
+            batch,
+            None,
+            PageKind::Node,
+            false,
+            guard,
+        )
+    }
+
+    /// The largest serialized `FreeBatch` that is written
+    /// inline rather than as a blob.
+    pub(super) fn max_free_batch_size(&self) -> u64 {
+        let max_buf_size = (self.config.segment_size
+            / MINIMUM_ITEMS_PER_SEGMENT)
+            - SEG_HEADER_LEN;
+
+        u64::try_from(max_buf_size - MAX_MSG_HEADER_LEN).unwrap()
+    }
+
+    fn reserve_maybe_compressed<T: Serialize + Debug>(
+        &self,
+        log_kind: LogKind,
+        pid: PageId,
+        item: &T,
+        page_kind: PageKind,
+        always_compress: bool,
+        guard: &Guard,
     ) -> Result<Reservation<'_>> {
         #[cfg(feature = "compression")]
         {
-            if self.config.use_compression && pid != BATCH_MANIFEST_PID {
+            if (self.config.use_compression || always_compress)
+                && pid != BATCH_MANIFEST_PID
+            {
                 use zstd::block::compress;
 
                 let buf = item.serialize();
@@ -135,12 +404,17 @@ impl Log {
                     pid,
                     &IVec::from(compressed_buf),
                     None,
+                    page_kind,
+                    true,
                     guard,
                 );
             }
         }
 
-        self.reserve_inner(log_kind, pid, item, None, guard)
+        #[cfg(not(feature = "compression"))]
+        let _ = always_compress;
+
+        self.reserve_inner(log_kind, pid, item, None, page_kind, false, guard)
     }
 
     fn reserve_inner<T: Serialize + Debug>(
@@ -149,9 +423,14 @@ impl Log {
         pid: PageId,
         item: &T,
         blob_rewrite: Option<Lsn>,
+        page_kind: PageKind,
+        compressed: bool,
         _: &Guard,
     ) -> Result<Reservation<'_>> {
         let _measure = Measure::new(&M.reserve_lat);
+        let _span = instrumentation::enter(&self.config, Span::LogReservation);
+
+        let _ = self.ops_since_snapshot.fetch_add(1, Relaxed);
 
         let serialized_len = item.serialized_size();
         let max_buf_len =
@@ -191,7 +470,26 @@ impl Log {
             (BATCH_MANIFEST_PID, LogKind::Skip, false) => {
                 MessageKind::BatchManifest
             }
+            (FREE_BATCH_PID, LogKind::Free, false) => MessageKind::FreeBatch,
             (_, LogKind::Free, false) => MessageKind::Free,
+            (_, LogKind::Replace, true) if page_kind == PageKind::Value => {
+                MessageKind::BlobValue
+            }
+            (_, LogKind::Replace, false) if page_kind == PageKind::Value => {
+                MessageKind::InlineValue
+            }
+            (_, LogKind::Replace, true) if page_kind == PageKind::Custom => {
+                MessageKind::BlobCustom
+            }
+            (_, LogKind::Replace, false) if page_kind == PageKind::Custom => {
+                MessageKind::InlineCustom
+            }
+            (_, LogKind::Link, true) if page_kind == PageKind::Custom => {
+                MessageKind::BlobFrag
+            }
+            (_, LogKind::Link, false) if page_kind == PageKind::Custom => {
+                MessageKind::InlineFrag
+            }
             (_, LogKind::Replace, true) => MessageKind::BlobNode,
             (_, LogKind::Replace, false) => MessageKind::InlineNode,
             (_, LogKind::Link, true) => MessageKind::BlobLink,
@@ -203,8 +501,16 @@ impl Log {
             ),
         };
 
+        let mut first_attempt = true;
         loop {
             M.log_reservation_attempted();
+            if !first_attempt {
+                instrumentation::event(
+                    &self.config,
+                    InstrumentationEvent::LogReservationRetry,
+                );
+            }
+            first_attempt = false;
 
             // don't continue if the system
             // has encountered an issue.
@@ -243,6 +549,7 @@ impl Log {
             let message_header = MessageHeader {
                 crc32: 0,
                 kind,
+                compressed,
                 segment_number: SegmentNumber(
                     u64::try_from(iobuf.lsn).unwrap()
                         / u64::try_from(self.config.segment_size).unwrap(),
@@ -456,8 +763,10 @@ impl Drop for Log {
             error!("failed to flush from IoBufs::drop: {}", e);
         }
 
-        if !self.config.temporary {
-            self.config.file.sync_all().unwrap();
+        if !self.config.is_temporary() {
+            if let Err(e) = self.config.file.sync_all() {
+                error!("failed to sync from IoBufs::drop: {}", e);
+            }
         }
 
         debug!("IoBufs dropped");
@@ -467,8 +776,15 @@ impl Drop for Log {
 /// All log messages are prepended with this header
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct MessageHeader {
+    /// covers the rest of the header and the message body.
+    /// Recovery stops at the first message that fails this
+    /// check, such as one torn by a crash, and new messages
+    /// are written over it and everything after it
     pub crc32: u32,
     pub kind: MessageKind,
+    /// whether the message was compressed with zstd, which is
+    /// stored in the high bit of the kind byte
+    pub compressed: bool,
     pub segment_number: SegmentNumber,
     pub pid: PageId,
     pub len: u64,
@@ -605,13 +921,13 @@ impl Into<[u8; SEG_HEADER_LEN]> for SegmentHeader {
 }
 
 pub(crate) fn read_segment_header(
-    file: &File,
+    file: &LogFile,
     lid: LogOffset,
 ) -> Result<SegmentHeader> {
     trace!("reading segment header at {}", lid);
 
     let mut seg_header_buf = [0; SEG_HEADER_LEN];
-    pread_exact(file, &mut seg_header_buf, lid)?;
+    file.pread_exact(&mut seg_header_buf, lid)?;
     let segment_header = SegmentHeader::from(seg_header_buf);
 
     if segment_header.lsn < Lsn::try_from(lid).unwrap() {
@@ -635,9 +951,9 @@ pub(crate) trait ReadAt {
     ) -> std::io::Result<usize>;
 }
 
-impl ReadAt for File {
+impl ReadAt for LogFile {
     fn pread_exact(&self, dst: &mut [u8], at: u64) -> std::io::Result<()> {
-        pread_exact(self, dst, at)
+        LogFile::pread_exact(self, dst, at)
     }
 
     fn pread_exact_or_eof(
@@ -645,7 +961,7 @@ impl ReadAt for File {
         dst: &mut [u8],
         at: u64,
     ) -> std::io::Result<usize> {
-        pread_exact_or_eof(self, dst, at)
+        LogFile::pread_exact_or_eof(self, dst, at)
     }
 }
 
@@ -785,7 +1101,10 @@ pub(crate) fn read_message<R: ReadAt>(
         }
         MessageKind::BlobLink
         | MessageKind::BlobNode
-        | MessageKind::BlobMeta => {
+        | MessageKind::BlobMeta
+        | MessageKind::BlobValue
+        | MessageKind::BlobCustom
+        | MessageKind::BlobFrag => {
             let id = arr_to_lsn(&buf);
 
             match read_blob(id, config) {
@@ -818,14 +1137,14 @@ pub(crate) fn read_message<R: ReadAt>(
         MessageKind::InlineLink
         | MessageKind::InlineNode
         | MessageKind::InlineMeta
+        | MessageKind::InlineValue
+        | MessageKind::InlineCustom
+        | MessageKind::InlineFrag
         | MessageKind::Free
+        | MessageKind::FreeBatch
         | MessageKind::Counter => {
             trace!("read a successful inline message");
-            let buf = if config.use_compression {
-                maybe_decompress(buf)?
-            } else {
-                buf
-            };
+            let buf = if header.compressed { decompress(buf)? } else { buf };
 
             Ok(LogRead::Inline(header, buf, inline_len))
         }
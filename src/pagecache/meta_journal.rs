@@ -0,0 +1,233 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Seek},
+    path::{Path, PathBuf},
+};
+
+use parking_lot::MutexGuard;
+
+use crate::pagecache::*;
+use crate::*;
+
+// each record is a crc32 of the rest of the record, the
+// lsn that the `Meta` was written to the log at, the length
+// of the serialized `Meta`, and the `Meta` itself
+const RECORD_HEADER_LEN: usize = 20;
+
+// once this many records have been appended, the journal
+// is rewritten to hold only the latest one
+const COMPACT_AFTER_RECORDS: usize = 64;
+
+const FILE_NAME: &str = "meta_journal";
+
+/// A `Meta` from the journal, with the `Lsn` that it was
+/// written to the log at.
+pub(crate) type JournaledMeta = (Lsn, Meta);
+
+/// A small journal of every version of the `Meta` page,
+/// which maps `Tree` names to their roots. Changes to the
+/// catalog are rare, but losing one loses a whole `Tree`,
+/// so each one is appended and synced here along with the
+/// `Lsn` that it was written to the log at. Recovery takes
+/// the catalog from the last record whose `Lsn` was
+/// recovered, unless the log recovered a newer `Meta` page.
+#[derive(Debug)]
+pub(crate) struct MetaJournal {
+    file: Mutex<JournalFile>,
+}
+
+#[derive(Debug)]
+pub(crate) struct JournalFile {
+    config: RunningConfig,
+    file: File,
+    records: usize,
+}
+
+impl MetaJournal {
+    /// Opens the journal, creating it if needed, and returns
+    /// the last `Meta` in it that was written to the log at
+    /// or before `recovered_lsn`, along with that `Lsn`. Later records, which were
+    /// never made stable in the log, and any torn record at
+    /// the end, are cut off. There is no journal for
+    /// `in_memory` databases, or for read-only ones that
+    /// were written before it existed.
+    pub(crate) fn open(
+        config: &RunningConfig,
+        recovered_lsn: Lsn,
+    ) -> Result<(Option<Self>, Option<JournaledMeta>)> {
+        if config.in_memory {
+            return Ok((None, None));
+        }
+
+        let path = journal_path(config);
+
+        let mut options = OpenOptions::new();
+        options.read(true);
+        if !config.read_only {
+            options.write(true).create(true);
+        }
+
+        let mut file = match options.open(&path) {
+            Ok(file) => file,
+            Err(ref e)
+                if config.read_only && e.kind() == io::ErrorKind::NotFound =>
+            {
+                debug!("no meta journal found at {:?}", path);
+                return Ok((None, None));
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut buf = vec![];
+        let _ = file.read_to_end(&mut buf)?;
+
+        let mut last = None;
+        let mut records = 0;
+        let mut valid_len = 0;
+        let mut remaining = &buf[..];
+
+        while remaining.len() >= RECORD_HEADER_LEN {
+            let crc_expected = arr_to_u32(&remaining[..4]);
+            let lsn = arr_to_lsn(&remaining[4..12]);
+            let len = usize::try_from(u64::from_le_bytes(
+                remaining[12..RECORD_HEADER_LEN].try_into().unwrap(),
+            ))
+            .unwrap();
+
+            if remaining.len() - RECORD_HEADER_LEN < len {
+                debug!("meta journal ends with a torn record");
+                break;
+            }
+
+            let record = &remaining[4..RECORD_HEADER_LEN + len];
+            if crc32(record) != crc_expected {
+                warn!("meta journal record has a bad checksum");
+                break;
+            }
+
+            if lsn > recovered_lsn {
+                debug!(
+                    "ignoring meta journal records from lsn {} on, \
+                     beyond the recovered lsn {}",
+                    lsn, recovered_lsn
+                );
+                break;
+            }
+
+            let mut meta_buf = &record[RECORD_HEADER_LEN - 4..];
+            last = Some((lsn, Meta::deserialize(&mut meta_buf)?));
+            records += 1;
+            valid_len += RECORD_HEADER_LEN + len;
+            remaining = &remaining[RECORD_HEADER_LEN + len..];
+        }
+
+        if valid_len != buf.len() && !config.read_only {
+            debug!(
+                "truncating meta journal from {} to {} bytes",
+                buf.len(),
+                valid_len
+            );
+            file.set_len(valid_len as u64)?;
+        }
+
+        let journal = MetaJournal {
+            file: Mutex::new(JournalFile {
+                config: config.clone(),
+                file,
+                records,
+            }),
+        };
+
+        Ok((Some(journal), last))
+    }
+
+    /// Locks the journal. Replacements of the `Meta` page
+    /// are made while holding this lock, so that they are
+    /// appended in the same order that they are installed.
+    pub(crate) fn lock(&self) -> MutexGuard<'_, JournalFile> {
+        self.file.lock()
+    }
+}
+
+impl JournalFile {
+    /// Appends a new version of the `Meta` page that was
+    /// written to the log at `lsn`, syncing it unless the
+    /// database is temporary.
+    pub(crate) fn append(&mut self, lsn: Lsn, meta: &Meta) -> Result<()> {
+        if self.records >= COMPACT_AFTER_RECORDS {
+            return self.compact(lsn, meta);
+        }
+
+        io_fail!(self.config, "meta journal append");
+        self.file.seek(io::SeekFrom::End(0))?;
+        self.file.write_all(&record(lsn, meta))?;
+        if !self.config.is_temporary() {
+            self.file.sync_all()?;
+        }
+        io_fail!(self.config, "meta journal append post");
+
+        self.records += 1;
+
+        Ok(())
+    }
+
+    /// Copies the journal into the directory `dir`, for a
+    /// backup of the database.
+    pub(crate) fn copy_to(&self, dir: &Path) -> Result<()> {
+        let _copied = fs::copy(journal_path(&self.config), dir.join(FILE_NAME))?;
+        Ok(())
+    }
+
+    // Replaces the journal with one holding only `meta`,
+    // written to the side and then renamed into place.
+    fn compact(&mut self, lsn: Lsn, meta: &Meta) -> Result<()> {
+        let path = journal_path(&self.config);
+        let mut tmp_path = path.clone();
+        tmp_path.set_extension("generating");
+
+        trace!("compacting meta journal of {} records", self.records);
+
+        let mut tmp = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+
+        io_fail!(self.config, "meta journal compact");
+        tmp.write_all(&record(lsn, meta))?;
+        if !self.config.is_temporary() {
+            tmp.sync_all()?;
+        }
+
+        io_fail!(self.config, "meta journal compact mv");
+        fs::rename(&tmp_path, &path)?;
+        io_fail!(self.config, "meta journal compact mv post");
+
+        self.file = tmp;
+        self.records = 1;
+
+        Ok(())
+    }
+}
+
+fn journal_path(config: &RunningConfig) -> PathBuf {
+    let mut path = config.get_path();
+    path.push(FILE_NAME);
+    path
+}
+
+fn record(lsn: Lsn, meta: &Meta) -> Vec<u8> {
+    let meta_bytes = meta.serialize();
+
+    let mut buf = Vec::with_capacity(RECORD_HEADER_LEN + meta_bytes.len());
+    buf.extend_from_slice(&[0; 4]);
+    buf.extend_from_slice(&lsn_to_arr(lsn));
+    buf.extend_from_slice(&u64_to_arr(meta_bytes.len() as u64));
+    buf.extend_from_slice(&meta_bytes);
+
+    let crc = u32_to_arr(crc32(&buf[4..]));
+    buf[..4].copy_from_slice(&crc);
+
+    buf
+}
@@ -5,52 +5,78 @@
 pub mod constants;
 pub mod logger;
 
+mod backup;
 mod blob_io;
 mod disk_pointer;
 mod iobuf;
 mod iterator;
+mod leaf_filter;
+mod log_file;
+mod meta_journal;
 mod pagetable;
-#[cfg(all(not(unix), not(windows)))]
+#[cfg(any(feature = "portable_io", all(not(unix), not(windows))))]
 mod parallel_io_polyfill;
-#[cfg(unix)]
+#[cfg(all(unix, not(feature = "portable_io")))]
 mod parallel_io_unix;
-#[cfg(windows)]
+#[cfg(all(windows, not(feature = "portable_io")))]
 mod parallel_io_windows;
+mod replica;
 mod reservation;
 mod segment;
 mod snapshot;
+mod warm_pages;
 
 use crate::*;
-use std::{collections::BinaryHeap, ops::Deref};
+use parking_lot::MutexGuard;
+use std::{collections::BinaryHeap, ops::Deref, path::Path};
 
-#[cfg(all(not(unix), not(windows)))]
-use parallel_io_polyfill::{pread_exact, pread_exact_or_eof, pwrite_all};
+#[cfg(any(feature = "portable_io", all(not(unix), not(windows))))]
+use parallel_io_polyfill::{
+    allocated_len, is_lock_contended, lock, pread_exact, pread_exact_or_eof,
+    prefetch, punch_hole, pwrite_all, sync_range,
+};
 
-#[cfg(unix)]
-use parallel_io_unix::{pread_exact, pread_exact_or_eof, pwrite_all};
+#[cfg(all(unix, not(feature = "portable_io")))]
+use parallel_io_unix::{
+    allocated_len, is_lock_contended, lock, pread_exact, pread_exact_or_eof,
+    prefetch, punch_hole, pwrite_all, sync_range,
+};
 
-#[cfg(windows)]
-use parallel_io_windows::{pread_exact, pread_exact_or_eof, pwrite_all};
+#[cfg(all(windows, not(feature = "portable_io")))]
+use parallel_io_windows::{
+    allocated_len, is_lock_contended, lock, pread_exact, pread_exact_or_eof,
+    prefetch, punch_hole, pwrite_all, sync_range,
+};
 
 use self::{
-    blob_io::{gc_blobs, read_blob, remove_blob, write_blob},
+    blob_io::{gc_blobs, read_blob, read_blob_kind, remove_blob, write_blob},
     constants::{
-        BATCH_MANIFEST_PID, COUNTER_PID, META_PID,
-        PAGE_CONSOLIDATION_THRESHOLD, SEGMENT_CLEANUP_THRESHOLD,
+        BATCH_MANIFEST_PID, COUNTER_PID, DIRECT_IO_BLOCK_SIZE, FREE_BATCH_PID,
+        META_PID, PREFETCH_QUEUE_CAPACITY, SEGMENT_CLEANUP_THRESHOLD,
     },
     iobuf::{IoBuf, IoBufs},
-    iterator::{raw_segment_iter_from, LogIter},
+    iterator::raw_segment_iter_from,
+    leaf_filter::LeafFilters,
+    meta_journal::{JournalFile, JournaledMeta, MetaJournal},
     pagetable::PageTable,
+    replica::{replicated_update, Position, ReplicaJournal},
     segment::{SegmentAccountant, SegmentCleaner, SegmentOp},
 };
 
 pub(crate) use self::{
+    blob_io::BlobReader,
+    iterator::LogIter,
+    leaf_filter::LeafFilter,
+    log_file::{LogFile, LogStorage},
     logger::{
         read_message, read_segment_header, MessageHeader, SegmentHeader,
         SegmentNumber,
     },
     reservation::Reservation,
-    snapshot::{read_snapshot_or_default, PageState, Snapshot},
+    snapshot::{
+        read_last_snapshot, read_snapshot_or_default, snapshot_files,
+        write_snapshot, write_snapshot_in, PageState, Snapshot,
+    },
 };
 
 pub use self::{
@@ -83,6 +109,10 @@ pub type PageId = u64;
 #[repr(transparent)]
 pub struct BatchManifest(pub Lsn);
 
+/// The pages freed together by a single log message.
+#[derive(Default, Clone, Eq, PartialEq, Debug)]
+pub struct FreeBatch(pub Vec<PageId>);
+
 /// A buffer with an associated offset. Useful for
 /// batching many reads over a file segment.
 pub struct BasedBuf(pub Vec<u8>, pub u64);
@@ -123,8 +153,32 @@ pub enum MessageKind {
     InlineLink = 10,
     /// A partial page update, stored blobly
     BlobLink = 11,
+    /// A large value that overflowed its leaf, stored inline
+    InlineValue = 12,
+    /// A large value that overflowed its leaf, stored blobly
+    BlobValue = 13,
+    /// Indicates that every page in the following list
+    /// was freed from the pagetable.
+    FreeBatch = 14,
+    /// A consolidated page allocated with
+    /// `Db::allocate_page`, stored inline
+    InlineCustom = 15,
+    /// A consolidated page allocated with
+    /// `Db::allocate_page`, stored blobly
+    BlobCustom = 16,
+    /// A fragment linked onto a page allocated with
+    /// `Db::allocate_page`, stored inline
+    InlineFrag = 17,
+    /// A fragment linked onto a page allocated with
+    /// `Db::allocate_page`, stored blobly
+    BlobFrag = 18,
 }
 
+/// Set in the kind byte of messages and blobs whose payload
+/// was compressed with zstd, so that they can be read back
+/// regardless of whether compression is currently enabled.
+pub(crate) const COMPRESSED_KIND_FLAG: u8 = 0b1000_0000;
+
 impl MessageKind {
     pub(crate) const fn into(self) -> u8 {
         self as u8
@@ -147,6 +201,13 @@ impl From<u8> for MessageKind {
             9 => BlobNode,
             10 => InlineLink,
             11 => BlobLink,
+            12 => InlineValue,
+            13 => BlobValue,
+            14 => FreeBatch,
+            15 => InlineCustom,
+            16 => BlobCustom,
+            17 => InlineFrag,
+            18 => BlobFrag,
             other => {
                 debug!("encountered unexpected message kind byte {}", other);
                 Corrupted
@@ -155,6 +216,18 @@ impl From<u8> for MessageKind {
     }
 }
 
+/// What a page holds, which determines the kinds of
+/// the messages that its updates are logged as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PageKind {
+    /// A tree node, or one of the system pages
+    Node,
+    /// A value that overflowed its leaf
+    Value,
+    /// A page allocated with `Db::allocate_page`
+    Custom,
+}
+
 /// The high-level types of stored information
 /// about pages and their mutations
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -174,23 +247,32 @@ pub enum LogKind {
 fn log_kind_from_update(update: &Update) -> LogKind {
     match update {
         Update::Free => LogKind::Free,
-        Update::Link(..) => LogKind::Link,
-        Update::Node(..) | Update::Counter(..) | Update::Meta(..) => {
-            LogKind::Replace
-        }
+        Update::Link(..) | Update::Frag(..) => LogKind::Link,
+        Update::Node(..)
+        | Update::Counter(..)
+        | Update::Meta(..)
+        | Update::Value(..)
+        | Update::Custom(..) => LogKind::Replace,
     }
 }
 
 impl From<MessageKind> for LogKind {
     fn from(kind: MessageKind) -> Self {
         match kind {
-            MessageKind::Free => LogKind::Free,
+            MessageKind::Free | MessageKind::FreeBatch => LogKind::Free,
             MessageKind::InlineNode
             | MessageKind::Counter
             | MessageKind::BlobNode
             | MessageKind::InlineMeta
-            | MessageKind::BlobMeta => LogKind::Replace,
-            MessageKind::InlineLink | MessageKind::BlobLink => LogKind::Link,
+            | MessageKind::BlobMeta
+            | MessageKind::InlineValue
+            | MessageKind::BlobValue
+            | MessageKind::InlineCustom
+            | MessageKind::BlobCustom => LogKind::Replace,
+            MessageKind::InlineLink
+            | MessageKind::BlobLink
+            | MessageKind::InlineFrag
+            | MessageKind::BlobFrag => LogKind::Link,
             MessageKind::Canceled
             | MessageKind::Cap
             | MessageKind::BatchManifest => LogKind::Skip,
@@ -202,6 +284,33 @@ impl From<MessageKind> for LogKind {
     }
 }
 
+fn deserialize_update(kind: MessageKind, bytes: &[u8]) -> Update {
+    use MessageKind::*;
+
+    // We create this &mut &[u8] to assist the `Serializer`
+    // implementation that incrementally consumes bytes
+    // without taking ownership of them.
+    let buf = &mut &*bytes;
+
+    let _deserialize_latency = Measure::new(&M.deserialize);
+
+    let update_res = match kind {
+        Counter => u64::deserialize(buf).map(Update::Counter),
+        BlobMeta | InlineMeta => Meta::deserialize(buf).map(Update::Meta),
+        BlobLink | InlineLink => Link::deserialize(buf).map(Update::Link),
+        BlobNode | InlineNode => Node::deserialize(buf).map(Update::Node),
+        BlobValue | InlineValue => IVec::deserialize(buf).map(Update::Value),
+        BlobCustom | InlineCustom => IVec::deserialize(buf).map(Update::Custom),
+        BlobFrag | InlineFrag => IVec::deserialize(buf).map(Update::Frag),
+        Free | FreeBatch => Ok(Update::Free),
+        Corrupted | Canceled | Cap | BatchManifest => {
+            panic!("unexpected pull: {:?}", kind)
+        }
+    };
+
+    update_res.expect("failed to deserialize data")
+}
+
 fn assert_usize<T>(from: T) -> usize
 where
     usize: TryFrom<T, Error = std::num::TryFromIntError>,
@@ -211,12 +320,12 @@ where
 
 // TODO remove this when atomic fetch_max stabilizes in #48655
 fn bump_atomic_lsn(atomic_lsn: &AtomicLsn, to: Lsn) {
-    let mut current = atomic_lsn.load(SeqCst);
+    let mut current = atomic_lsn.load(Acquire);
     loop {
         if current >= to {
             return;
         }
-        let last = atomic_lsn.compare_and_swap(current, to, SeqCst);
+        let last = atomic_lsn.compare_and_swap(current, to, AcqRel);
         if last == current {
             // we succeeded.
             return;
@@ -242,6 +351,11 @@ pub(crate) fn u64_to_arr(number: u64) -> [u8; 8] {
     number.to_le_bytes()
 }
 
+#[inline]
+pub(crate) fn arr_to_u64(arr: &[u8]) -> u64 {
+    u64::from_le_bytes(arr.try_into().unwrap())
+}
+
 #[inline]
 pub(crate) fn arr_to_u32(arr: &[u8]) -> u32 {
     u32::from_le_bytes(arr.try_into().unwrap())
@@ -253,7 +367,7 @@ pub(crate) fn u32_to_arr(number: u32) -> [u8; 4] {
 }
 
 #[allow(clippy::needless_pass_by_value)]
-pub(crate) fn maybe_decompress(in_buf: Vec<u8>) -> std::io::Result<Vec<u8>> {
+pub(crate) fn decompress(in_buf: Vec<u8>) -> Result<Vec<u8>> {
     #[cfg(feature = "compression")]
     {
         use zstd::stream::decode_all;
@@ -273,7 +387,14 @@ pub(crate) fn maybe_decompress(in_buf: Vec<u8>) -> std::io::Result<Vec<u8>> {
     }
 
     #[cfg(not(feature = "compression"))]
-    Ok(in_buf)
+    {
+        drop(in_buf);
+        Err(Error::Unsupported(
+            "the 'compression' feature must be enabled to read \
+             data that was written with use_compression"
+                .to_owned(),
+        ))
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -302,6 +423,32 @@ impl<'g> Deref for MetaView<'g> {
 unsafe impl<'g> Send for MetaView<'g> {}
 unsafe impl<'g> Sync for MetaView<'g> {}
 
+#[derive(Debug, Clone, Copy)]
+pub struct ValueView<'g>(pub(crate) PageView<'g>);
+
+impl<'g> Deref for ValueView<'g> {
+    type Target = IVec;
+    fn deref(&self) -> &IVec {
+        self.0.as_value()
+    }
+}
+
+unsafe impl<'g> Send for ValueView<'g> {}
+unsafe impl<'g> Sync for ValueView<'g> {}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CustomView<'g>(pub(crate) PageView<'g>);
+
+impl<'g> Deref for CustomView<'g> {
+    type Target = IVec;
+    fn deref(&self) -> &IVec {
+        self.0.as_custom()
+    }
+}
+
+unsafe impl<'g> Send for CustomView<'g> {}
+unsafe impl<'g> Sync for CustomView<'g> {}
+
 #[derive(Debug, Clone, Copy)]
 pub struct PageView<'g> {
     pub(crate) read: Shared<'g, Page>,
@@ -350,6 +497,9 @@ pub(crate) enum Update {
     Free,
     Counter(u64),
     Meta(Meta),
+    Value(IVec),
+    Custom(IVec),
+    Frag(IVec),
 }
 
 impl Update {
@@ -389,6 +539,33 @@ impl Update {
             panic!("called as_counter on {:?}", self)
         }
     }
+
+    fn as_value(&self) -> &IVec {
+        if let Update::Value(value) = self {
+            value
+        } else {
+            panic!("called as_value on {:?}", self)
+        }
+    }
+
+    fn as_custom(&self) -> &IVec {
+        if let Update::Custom(page) = self {
+            page
+        } else {
+            panic!("called as_custom on {:?}", self)
+        }
+    }
+
+    fn rss(&self) -> u64 {
+        match self {
+            Update::Node(node) => node.rss(),
+            Update::Meta(meta) => meta.rss(),
+            Update::Value(ivec) | Update::Custom(ivec) | Update::Frag(ivec) => {
+                ivec.len() as u64
+            }
+            Update::Link(_) | Update::Free | Update::Counter(_) => 0,
+        }
+    }
 }
 
 /// Ensures that any operations that are written to disk between the
@@ -409,6 +586,13 @@ pub struct RecoveryGuard<'a> {
 }
 
 impl<'a> RecoveryGuard<'a> {
+    /// Returns the LSN of the reservation that starts the
+    /// batch, which orders it before any batch that is
+    /// pinned after it.
+    pub(crate) fn lsn(&self) -> Lsn {
+        self.batch_res.lsn
+    }
+
     /// Writes the last LSN for a batch into an earlier
     /// reservation, releasing it.
     pub fn seal_batch(mut self, guard: &Guard) -> Result<()> {
@@ -421,10 +605,16 @@ impl<'a> RecoveryGuard<'a> {
 
 /// A page consists of a sequence of state transformations
 /// with associated storage parameters like disk pos, lsn, time.
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Page {
     pub(crate) update: Option<Update>,
     pub(crate) cache_infos: StackVec,
+    // only counted with `ConsolidationPolicy::Adaptive`
+    pub(crate) reads: AtomicU32,
+    // whether the page belongs to a tree passed to
+    // `Config::compress_tree`, which has its consolidated
+    // images compressed
+    pub(crate) compress: bool,
 }
 
 impl Page {
@@ -440,10 +630,34 @@ impl Page {
         self.update.as_ref().unwrap().as_counter()
     }
 
+    pub(crate) fn as_value(&self) -> &IVec {
+        self.update.as_ref().unwrap().as_value()
+    }
+
+    pub(crate) fn as_custom(&self) -> &IVec {
+        self.update.as_ref().unwrap().as_custom()
+    }
+
     pub(crate) fn is_free(&self) -> bool {
         self.update == Some(Update::Free) || self.cache_infos.is_empty()
     }
 
+    pub(crate) fn is_value(&self) -> bool {
+        if let Some(Update::Value(_)) = self.update {
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn is_custom(&self) -> bool {
+        if let Some(Update::Custom(_)) = self.update {
+            true
+        } else {
+            false
+        }
+    }
+
     pub(crate) fn last_lsn(&self) -> Lsn {
         self.cache_infos.last().map(|ci| ci.lsn).unwrap()
     }
@@ -479,6 +693,35 @@ pub struct PageCache {
     idgen: Arc<AtomicU64>,
     idgen_persists: Arc<AtomicU64>,
     idgen_persist_mu: Arc<Mutex<()>>,
+    meta_journal: Option<Arc<MetaJournal>>,
+    replica: Mutex<Option<ReplicaJournal>>,
+    follower: AtomicBool,
+    // the first pids of the prefetches in flight
+    prefetching: Mutex<Vec<PageId>>,
+    // the pages written to since they were last known to
+    // be stable, with the lsn of their latest message
+    dirty: Mutex<BTreeMap<PageId, Lsn>>,
+    // the bytes of the links written since startup, for
+    // calculating write amplification
+    linked_bytes: AtomicU64,
+    // filters over the keys of paged out leaves, with
+    // `Config::leaf_filter_bits_per_key`
+    leaf_filters: LeafFilters,
+    // the pages read back in from the log and the bytes of
+    // their messages, since startup
+    page_ins: AtomicU64,
+    page_in_bytes: AtomicU64,
+    // the pages that garbage collection relocated and the
+    // bytes it wrote doing so, since startup
+    relocated_pages: AtomicU64,
+    relocated_bytes: AtomicU64,
+    // budgets for the flusher, repairer and scrubber, with
+    // `Config::background_bytes_per_sec` and
+    // `Config::background_ops_per_sec`
+    maintenance: Throttle,
+    // the outcome of the last scrub that was not stopped
+    // part way through
+    last_scrub: Mutex<Option<ScrubReport>>,
     was_recovered: bool,
 }
 
@@ -548,12 +791,23 @@ impl PageCache {
         // try to pull any existing snapshot off disk, and
         // apply any new data to it to "catch-up" the
         // snapshot before loading it.
-        let snapshot = read_snapshot_or_default(&config)?;
+        let snapshot = {
+            let _span = instrumentation::enter(&config, Span::RecoverSnapshot);
+            read_snapshot_or_default(&config)?
+        };
 
         let _measure = Measure::new(&M.start_pagecache);
 
+        let (meta_journal, journaled_meta) =
+            MetaJournal::open(&config, snapshot.last_lsn)?;
+
         let cache_capacity = config.cache_capacity;
         let lru = Lru::new(cache_capacity);
+        let leaf_filters = LeafFilters::open(&config)?;
+        let maintenance = Throttle::new(
+            config.background_bytes_per_sec,
+            config.background_ops_per_sec,
+        );
 
         let mut pc = Self {
             config: config.clone(),
@@ -565,12 +819,33 @@ impl PageCache {
             idgen_persist_mu: Arc::new(Mutex::new(())),
             idgen: Arc::new(AtomicU64::new(0)),
             idgen_persists: Arc::new(AtomicU64::new(0)),
+            meta_journal: meta_journal.map(Arc::new),
+            replica: Mutex::new(None),
+            follower: AtomicBool::new(false),
+            prefetching: Mutex::new(vec![]),
+            dirty: Mutex::new(BTreeMap::new()),
+            linked_bytes: AtomicU64::new(0),
+            leaf_filters,
+            page_ins: AtomicU64::new(0),
+            page_in_bytes: AtomicU64::new(0),
+            relocated_pages: AtomicU64::new(0),
+            relocated_bytes: AtomicU64::new(0),
+            maintenance,
+            last_scrub: Mutex::new(None),
             was_recovered: false,
         };
 
         // now we read it back in
         pc.load_snapshot(&snapshot)?;
 
+        // the filters of leaves written to since they were
+        // persisted will never be used again
+        {
+            let guard = pin();
+            pc.leaf_filters
+                .retain(|pid, lsn| pc.paged_out_lsn(pid, &guard) == Some(lsn));
+        }
+
         #[cfg(feature = "event_log")]
         {
             use std::collections::HashMap;
@@ -608,7 +883,8 @@ impl PageCache {
 
                 let meta_update = Update::Meta(Meta::default());
 
-                let (meta_id, _) = pc.allocate_inner(meta_update, &guard)?;
+                let (meta_id, _) =
+                    pc.allocate_inner(meta_update, false, &guard)?;
 
                 assert_eq!(
                     meta_id, META_PID,
@@ -617,6 +893,8 @@ impl PageCache {
                 );
             }
 
+            pc.recover_meta(journaled_meta, &guard)?;
+
             if let Err(Error::ReportableBug(..)) = pc.get_idgen(&guard) {
                 // set up idgen
                 was_recovered = false;
@@ -624,7 +902,7 @@ impl PageCache {
                 let counter_update = Update::Counter(0);
 
                 let (counter_id, _) =
-                    pc.allocate_inner(counter_update, &guard)?;
+                    pc.allocate_inner(counter_update, false, &guard)?;
 
                 assert_eq!(
                     counter_id, COUNTER_PID,
@@ -648,6 +926,9 @@ impl PageCache {
 
         pc.was_recovered = was_recovered;
 
+        *pc.replica.get_mut() =
+            ReplicaJournal::open(&pc.config, snapshot.last_lsn, was_recovered)?;
+
         #[cfg(feature = "event_log")]
         {
             let guard = pin();
@@ -671,6 +952,17 @@ impl PageCache {
         self.log.flush()
     }
 
+    /// Calls `f` once every write that has been made so
+    /// far is stable, which may be immediately on this thread.
+    pub(crate) fn on_stable<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let iobufs = &self.log.iobufs;
+        let max_reserved_lsn = iobufs.max_reserved_lsn.load(Acquire);
+        iobufs.on_stable(max_reserved_lsn, f);
+    }
+
     /// Create a new page, trying to reuse old freed pages if possible
     /// to maximize underlying `PageTable` pointer density. Returns
     /// the page ID and its pointer for use in future atomic `replace`
@@ -678,16 +970,45 @@ impl PageCache {
     pub fn allocate<'g>(
         &self,
         new: Node,
+        compress: bool,
         guard: &'g Guard,
     ) -> Result<(PageId, PageView<'g>)> {
-        self.allocate_inner(Update::Node(new), guard)
+        self.allocate_inner(Update::Node(new), compress, guard)
+    }
+
+    /// Store a value in a page of its own, returning the
+    /// `PageId` that it may be retrieved from with
+    /// `get_value`. The page may be released using `free`.
+    pub(crate) fn allocate_value(
+        &self,
+        value: IVec,
+        guard: &Guard,
+    ) -> Result<PageId> {
+        self.allocate_inner(Update::Value(value), false, guard)
+            .map(|(pid, _)| pid)
+    }
+
+    /// Store a page that is consolidated by the configured
+    /// `Materializer`, returning the `PageId` that it may be
+    /// retrieved from with `get_custom`. The page may be
+    /// released using `free`.
+    pub(crate) fn allocate_custom(
+        &self,
+        page: IVec,
+        guard: &Guard,
+    ) -> Result<PageId> {
+        self.allocate_inner(Update::Custom(page), false, guard)
+            .map(|(pid, _)| pid)
     }
 
     fn allocate_inner<'g>(
         &self,
         new: Update,
+        compress: bool,
         guard: &'g Guard,
     ) -> Result<(PageId, PageView<'g>)> {
+        self.check_writable()?;
+
         let (pid, page_view) = if let Some(pid) = self.free.lock().pop() {
             trace!("re-allocating pid {}", pid);
 
@@ -713,8 +1034,12 @@ impl PageCache {
 
             trace!("allocating pid {} for the first time", pid);
 
-            let new_page =
-                Page { update: None, cache_infos: StackVec::default() };
+            let new_page = Page {
+                update: None,
+                cache_infos: StackVec::default(),
+                reads: AtomicU32::new(0),
+                compress: false,
+            };
 
             let page_view = self.inner.insert(pid, new_page, guard);
 
@@ -722,7 +1047,7 @@ impl PageCache {
         };
 
         let new_pointer = self
-            .cas_page(pid, page_view, new, false, guard)?
+            .cas_page(pid, page_view, new, false, compress, guard)?
             .unwrap_or_else(|e| {
                 panic!(
                     "should always be able to install \
@@ -748,8 +1073,12 @@ impl PageCache {
         let guard = pin();
         let to_clean = self.log.iobufs.segment_cleaner.pop();
         let ret = if let Some((pid_to_clean, segment_to_clean)) = to_clean {
-            self.rewrite_page(pid_to_clean, segment_to_clean, &guard)
-                .map(|_| true)
+            self.rewrite_page(pid_to_clean, segment_to_clean, &guard).map(
+                |rewritten| {
+                    self.charge_maintenance(rewritten, 1);
+                    true
+                },
+            )
         } else {
             Ok(false)
         };
@@ -757,6 +1086,77 @@ impl PageCache {
         ret
     }
 
+    /// Returns `true` if background maintenance has used up
+    /// its budget of `Config::background_bytes_per_sec` or
+    /// `Config::background_ops_per_sec` for now.
+    pub(crate) fn maintenance_throttled(&self) -> bool {
+        self.maintenance.exhausted(self.config.now())
+    }
+
+    /// Deducts maintenance work from its budgets.
+    pub(crate) fn charge_maintenance(&self, bytes: u64, ops: u64) {
+        self.maintenance.charge(self.config.now(), bytes, ops);
+    }
+
+    /// Raises the maintenance budgets along with the larger
+    /// of the share of the bytes in the log's segments that
+    /// are dead, where half of them being dead counts as the
+    /// most pressure, and the bytes written since the last
+    /// flush, where a whole IO buffer of them does.
+    pub(crate) fn update_maintenance_pressure(&self) {
+        if self.config.background_bytes_per_sec.is_none()
+            && self.config.background_ops_per_sec.is_none()
+        {
+            return;
+        }
+
+        let segments = self.log.iobufs.with_sa(|sa| sa.segment_space());
+        let dead: u64 = segments.iter().map(|s| s.dead_bytes).sum();
+        let total: u64 =
+            dead + segments.iter().map(|s| s.live_bytes).sum::<u64>();
+        let dead_pressure = if total == 0 { 0 } else { dead * 200 / total };
+
+        let unflushed = self.log.iobufs.max_reserved_lsn.load(Acquire)
+            - self.stable_lsn();
+        let dirty_pressure = u64::try_from(unflushed.max(0)).unwrap() * 100
+            / self.config.segment_size as u64;
+
+        self.maintenance.set_pressure(dead_pressure.max(dirty_pressure));
+    }
+
+    /// Starts draining every inactive segment that is at most
+    /// `target_utilization` percent live, or only the one at
+    /// `only`, then relocates the pages of every draining
+    /// segment before returning, consolidating them along
+    /// the way.
+    pub(crate) fn compact(
+        &self,
+        target_utilization: u8,
+        only: Option<LogOffset>,
+    ) -> Result<CompactionStats> {
+        let mut stats = CompactionStats::default();
+        if self.config.read_only {
+            return Ok(stats);
+        }
+
+        stats.segments_drained = self.log.iobufs.with_sa(|sa| {
+            sa.drain_segments(usize::from(target_utilization), only)
+        })?;
+
+        let guard = pin();
+        while let Some((pid, segment)) = self.log.iobufs.segment_cleaner.pop()
+        {
+            let relocated = self.rewrite_page(pid, segment, &guard)?;
+            if relocated > 0 {
+                stats.pages_relocated += 1;
+                stats.bytes_relocated += relocated;
+            }
+        }
+        guard.flush();
+
+        Ok(stats)
+    }
+
     /// Initiate an atomic sequence of writes to the
     /// underlying log. Returns a `RecoveryGuard` which,
     /// when dropped, will record the current max reserved
@@ -806,6 +1206,8 @@ impl PageCache {
     ) -> Result<CasResult<'g, ()>> {
         trace!("attempting to free pid {}", pid);
 
+        self.check_writable()?;
+
         if pid == COUNTER_PID || pid == META_PID || pid == BATCH_MANIFEST_PID {
             return Err(Error::Unsupported(
                 "you are not able to free the first \
@@ -816,11 +1218,11 @@ impl PageCache {
         }
 
         let new_pointer =
-            self.cas_page(pid, old, Update::Free, false, guard)?;
+            self.cas_page(pid, old, Update::Free, false, false, guard)?;
 
         if new_pointer.is_ok() {
             let free = self.free.clone();
-            guard.defer(move || {
+            self.config.defer(guard, move || {
                 let mut free = free.lock();
                 // panic if we double-freed a page
                 if free.iter().any(|e| e == &pid) {
@@ -834,15 +1236,156 @@ impl PageCache {
         Ok(new_pointer.map_err(|o| o.map(|(pointer, _)| (pointer, ()))))
     }
 
+    /// Free many pages at once, writing a single log message
+    /// for as many of them as fit into one, and leaving their
+    /// segment accounting to be done later in the background.
+    /// Returns the pages that changed since they were viewed,
+    /// which must be freed individually with `free` instead.
+    ///
+    /// The `Guard` must have been held since the pages were
+    /// viewed, which keeps any page freed concurrently from
+    /// being reused while its batch is written.
+    pub(crate) fn free_batch<'g>(
+        &self,
+        pages: Vec<(PageId, PageView<'g>)>,
+        guard: &'g Guard,
+    ) -> Result<Vec<PageId>> {
+        self.check_writable()?;
+
+        for (pid, _) in &pages {
+            if *pid == COUNTER_PID
+                || *pid == META_PID
+                || *pid == BATCH_MANIFEST_PID
+            {
+                return Err(Error::Unsupported(
+                    "you are not able to free the first \
+                     couple pages, which are allocated \
+                     for system internal purposes"
+                        .into(),
+                ));
+            }
+        }
+
+        let max_batch_size = self.log.max_free_batch_size();
+        let mut freed = vec![];
+        let mut changed = vec![];
+        let mut remaining = pages.into_iter().peekable();
+
+        while remaining.peek().is_some() {
+            let mut chunk = vec![];
+            let mut batch = FreeBatch::default();
+            let mut batch_size = 0;
+            while let Some((pid, _)) = remaining.peek() {
+                batch_size += pid.serialized_size();
+                if batch_size > max_batch_size {
+                    break;
+                }
+                batch.0.push(*pid);
+                chunk.push(remaining.next().unwrap());
+            }
+
+            trace!("freeing batch of pids {:?}", batch.0);
+
+            let log_reservation = self.log.reserve_free_batch(&batch, guard)?;
+            let lsn = log_reservation.lsn();
+
+            // pages are accounted for as if they each had
+            // their own share of the message
+            let reservation_len =
+                u64::try_from(log_reservation.reservation_len()).unwrap();
+            let pages_in_batch = u64::try_from(chunk.len()).unwrap();
+            let log_size =
+                (reservation_len + pages_in_batch - 1) / pages_in_batch;
+
+            for (pid, old) in chunk {
+                let cache_info = CacheInfo {
+                    ts: old.ts() + 1,
+                    lsn,
+                    pointer: log_reservation.pointer(),
+                    log_size,
+                };
+
+                let new_page = Owned::new(Page {
+                    update: Some(Update::Free),
+                    cache_infos: StackVec::single(cache_info),
+                    reads: AtomicU32::new(0),
+                    compress: false,
+                });
+
+                debug_delay();
+                let result = old
+                    .entry
+                    .compare_and_set(old.read, new_page, AcqRel, guard);
+
+                if result.is_ok() {
+                    unsafe {
+                        guard.defer_destroy(old.read);
+                    }
+
+                    self.log.iobufs.sa_defer_replace(
+                        pid,
+                        lsn,
+                        &old.cache_infos,
+                        cache_info,
+                        guard,
+                    );
+                    self.mark_dirty(pid, lsn);
+
+                    freed.push(pid);
+                } else {
+                    // the batch still lists this page, which is
+                    // harmless because it was either freed already
+                    // or the caller's `free` of it gets a higher lsn
+                    trace!("batched free of pid {} failed", pid);
+                    changed.push(pid);
+                }
+            }
+
+            // NB complete must happen AFTER calls to SA, because
+            // when the iobuf's n_writers hits 0, we may transition
+            // the segment to inactive, resulting in a race otherwise.
+            let _pointer = log_reservation.complete()?;
+        }
+
+        let free = self.free.clone();
+        self.config.defer(guard, move || {
+            let mut free = free.lock();
+            // panic if we double-freed a page
+            let already_free: FastSet8<PageId> = free.iter().copied().collect();
+            for pid in freed {
+                if already_free.contains(&pid) {
+                    panic!("pid {} was double-freed", pid);
+                }
+
+                free.push(pid);
+            }
+        });
+
+        Ok(changed)
+    }
+
     /// Try to atomically add a `PageLink` to the page.
     /// Returns `Ok(new_key)` if the operation was successful. Returns
     /// `Err(None)` if the page no longer exists. Returns
     /// `Err(Some(actual_key))` if the atomic link fails.
     pub(crate) fn link<'g>(
+        &'g self,
+        pid: PageId,
+        old: PageView<'g>,
+        new: Link,
+        compress: bool,
+        guard: &'g Guard,
+    ) -> Result<CasResult<'g, Link>> {
+        self.check_writable()?;
+        self.link_inner(pid, old, new, compress, guard)
+    }
+
+    fn link_inner<'g>(
         &'g self,
         pid: PageId,
         mut old: PageView<'g>,
         new: Link,
+        compress: bool,
         guard: &'g Guard,
     ) -> Result<CasResult<'g, Link>> {
         let _measure = Measure::new(&M.link_page);
@@ -854,14 +1397,12 @@ impl PageCache {
         #[cfg(any(test, feature = "lock_free_delays"))]
         {
             use std::cell::RefCell;
-            use std::time::{SystemTime, UNIX_EPOCH};
 
             thread_local! {
                 pub static COUNT: RefCell<u32> = RefCell::new(1);
             }
 
-            let time_now =
-                SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+            let time_now = self.config.now();
 
             #[allow(clippy::cast_possible_truncation)]
             let fail_seed = std::cmp::max(3, time_now.as_nanos() as u32 % 128);
@@ -877,11 +1418,9 @@ impl PageCache {
                     "injecting a randomized failure in the link of pid {}",
                     pid
                 );
-                if let Some(current_pointer) = self.get(pid, guard)? {
-                    return Ok(Err(Some((current_pointer.0, new))));
-                } else {
-                    return Ok(Err(None));
-                }
+                // the page is not read again, which could wait
+                // on a log write that the caller holds back
+                return Ok(Err(Some((old, new))));
             }
         }
 
@@ -889,21 +1428,150 @@ impl PageCache {
         node.apply(&new);
 
         // see if we should short-circuit replace
-        if old.cache_infos.len() >= PAGE_CONSOLIDATION_THRESHOLD {
-            let short_circuit = self.replace(pid, old, node, guard)?;
+        if self.should_consolidate(&old) {
+            let _consolidation = Measure::new(&M.consolidate);
+            let short_circuit =
+                self.replace_inner(pid, old, node, compress, guard)?;
             return Ok(short_circuit.map_err(|a| a.map(|b| (b.0, new))));
         }
 
+        let frag = Update::Link(new);
+        let res = self.link_frag(
+            pid,
+            old,
+            Update::Node(node),
+            &frag,
+            compress,
+            guard,
+        )?;
+        Ok(res.map_err(|current| {
+            if let Update::Link(rejected_new) = frag {
+                current.map(|page_view| (page_view, rejected_new))
+            } else {
+                unreachable!();
+            }
+        }))
+    }
+
+    // Whether the page should be consolidated instead of
+    // having another fragment linked onto it.
+    fn should_consolidate(&self, page: &Page) -> bool {
+        let chain = page.cache_infos.len();
+        match self.config.consolidation_policy {
+            ConsolidationPolicy::Fixed(max_chain) => chain >= max_chain,
+            ConsolidationPolicy::Adaptive {
+                min_chain,
+                max_chain,
+                max_bytes,
+                hot_reads,
+            } => {
+                let frag_bytes: u64 =
+                    page.cache_infos.iter().skip(1).map(|ci| ci.log_size).sum();
+                chain >= max_chain
+                    || (chain >= min_chain
+                        && (frag_bytes > max_bytes
+                            || page.reads.load(Relaxed) >= hot_reads))
+            }
+        }
+    }
+
+    fn mark_dirty(&self, pid: PageId, lsn: Lsn) {
+        let mut dirty = self.dirty.lock();
+        let max_lsn = dirty.entry(pid).or_insert(lsn);
+        *max_lsn = std::cmp::max(*max_lsn, lsn);
+    }
+
+    // forgets the pages whose messages are all stable by now
+    fn prune_dirty(&self, dirty: &mut BTreeMap<PageId, Lsn>) {
+        let stable = self.stable_lsn();
+        let clean: Vec<PageId> = dirty
+            .iter()
+            .filter(|(_pid, lsn)| **lsn <= stable)
+            .map(|(pid, _lsn)| *pid)
+            .collect();
+        for pid in clean {
+            let _ = dirty.remove(&pid);
+        }
+    }
+
+    /// Returns the pages that were written to since the log
+    /// was last made stable, in ascending order.
+    pub(crate) fn dirty_pages(&self) -> Vec<PageId> {
+        let mut dirty = self.dirty.lock();
+        self.prune_dirty(&mut dirty);
+        dirty.keys().copied().collect()
+    }
+
+    /// Makes every message written for `pid` so far stable,
+    /// returning the number of bytes written during this
+    /// call, which is 0 if the page was not dirty.
+    pub(crate) fn flush_page(&self, pid: PageId) -> Result<usize> {
+        let lsn = if let Some(lsn) = self.dirty.lock().get(&pid) {
+            *lsn
+        } else {
+            return Ok(0);
+        };
+        let written = self.make_stable(lsn)?;
+        self.prune_dirty(&mut self.dirty.lock());
+        Ok(written)
+    }
+
+    /// Makes the messages of every dirty page stable,
+    /// writing the log only up to the last of them rather
+    /// than every write that was reserved since. Returns the
+    /// number of bytes written during this call.
+    pub(crate) fn flush_dirty(&self) -> Result<usize> {
+        let max_lsn = self.dirty.lock().values().copied().max();
+        let written =
+            if let Some(lsn) = max_lsn { self.make_stable(lsn)? } else { 0 };
+        self.prune_dirty(&mut self.dirty.lock());
+        Ok(written)
+    }
+
+    /// Counts a read of the page by a read-only operation,
+    /// which `ConsolidationPolicy::Adaptive` consolidates
+    /// frequently read pages sooner for.
+    pub(crate) fn record_read(&self, page_view: PageView<'_>) {
+        if let ConsolidationPolicy::Adaptive { .. } =
+            self.config.consolidation_policy
+        {
+            let _ = page_view.reads.fetch_add(1, Relaxed);
+        }
+    }
+
+    // Logs `frag` as a link onto the page, and installs
+    // `materialized`, the page with `frag` applied to it,
+    // as its new in-memory state. Retries if the page was
+    // only moved on disk in the meantime, and otherwise
+    // returns the page as it is now.
+    fn link_frag<'g>(
+        &'g self,
+        pid: PageId,
+        mut old: PageView<'g>,
+        materialized: Update,
+        frag: &Update,
+        compress: bool,
+        guard: &'g Guard,
+    ) -> Result<std::result::Result<PageView<'g>, Option<PageView<'g>>>> {
         let mut new_page = Some(Owned::new(Page {
-            update: Some(Update::Node(node)),
+            update: Some(materialized),
             cache_infos: StackVec::default(),
+            reads: AtomicU32::new(0),
+            compress,
         }));
 
         loop {
             // TODO handle replacement on threshold here instead
 
-            let log_reservation =
-                self.log.reserve(LogKind::Link, pid, &new, guard)?;
+            let log_reservation = match frag {
+                Update::Link(link) => {
+                    self.log.reserve(LogKind::Link, pid, link, guard)?
+                }
+                Update::Frag(bytes) => {
+                    self.log.reserve_custom(LogKind::Link, pid, bytes, guard)?
+                }
+                other => panic!("non-link used in link_frag: {:?}", other),
+            };
             let lsn = log_reservation.lsn();
             let pointer = log_reservation.pointer();
 
@@ -936,10 +1604,15 @@ impl PageCache {
 
             let mut page_ptr = new_page.take().unwrap();
             page_ptr.cache_infos = new_cache_infos;
+            page_ptr.reads = AtomicU32::new(old.reads.load(Relaxed));
 
+            // like every install into the page table, this
+            // releases the new page to readers, who load it
+            // with Acquire in `PageTable::get`. Nothing else
+            // needs a total order with it, so no SeqCst.
             debug_delay();
             let result =
-                old.entry.compare_and_set(old.read, page_ptr, SeqCst, guard);
+                old.entry.compare_and_set(old.read, page_ptr, AcqRel, guard);
 
             match result {
                 Ok(new_shared) => {
@@ -959,6 +1632,10 @@ impl PageCache {
                     // FIXME can result in deadlock if a node that holds SA
                     // is waiting to acquire a new reservation blocked by this?
                     log_reservation.complete()?;
+                    self.mark_dirty(pid, cache_info.lsn);
+                    let _ = self
+                        .linked_bytes
+                        .fetch_add(cache_info.log_size, Relaxed);
 
                     // possibly evict an item now that our cache has grown
                     let total_page_size =
@@ -994,13 +1671,78 @@ impl PageCache {
                         trace!("link of pid {} failed due to new update", pid);
                         let mut page_view = old;
                         page_view.read = actual;
-                        return Ok(Err(Some((page_view, new))));
+                        return Ok(Err(Some(page_view)));
                     }
                 }
             }
         }
     }
 
+    /// Try to atomically link a fragment onto a page that
+    /// was stored with `allocate_custom`, consolidating it
+    /// with the configured `Materializer` once it has enough
+    /// fragments. Returns `Err(None)` if the page no longer
+    /// exists, and `Err(Some(actual_key))` if it changed.
+    pub(crate) fn link_custom<'g>(
+        &'g self,
+        pid: PageId,
+        old: CustomView<'g>,
+        frag: IVec,
+        guard: &'g Guard,
+    ) -> Result<CasResult<'g, IVec>> {
+        self.check_writable()?;
+        self.link_custom_inner(pid, old, frag, guard)
+    }
+
+    fn link_custom_inner<'g>(
+        &'g self,
+        pid: PageId,
+        old: CustomView<'g>,
+        frag: IVec,
+        guard: &'g Guard,
+    ) -> Result<CasResult<'g, IVec>> {
+        let _measure = Measure::new(&M.link_page);
+
+        trace!("linking pid {} with a fragment of {:?}", pid, frag);
+
+        let merged = self.materializer()?.merge(&old, &[&frag]);
+        let materialized = Update::Custom(IVec::from(merged));
+
+        if self.should_consolidate(&old.0) {
+            let _consolidation = Measure::new(&M.consolidate);
+            let res =
+                self.cas_page(pid, old.0, materialized, false, false, guard)?;
+            return Ok(res.map_err(|fail| fail.map(|(view, _)| (view, frag))));
+        }
+
+        let update = Update::Frag(frag);
+        let res =
+            self.link_frag(pid, old.0, materialized, &update, false, guard)?;
+        Ok(res.map_err(|current| {
+            if let Update::Frag(rejected_frag) = update {
+                current.map(|page_view| (page_view, rejected_frag))
+            } else {
+                unreachable!();
+            }
+        }))
+    }
+
+    // Returns the configured `Materializer`, which pages
+    // stored with `allocate_custom` can't be read back or
+    // linked onto without.
+    fn materializer(&self) -> Result<&dyn Materializer> {
+        if let Some(materializer) = &self.config.materializer {
+            Ok(&**materializer)
+        } else {
+            Err(Error::Unsupported(
+                "pages allocated with `Db::allocate_page` can only be \
+                 linked onto or read back in when \
+                 `Config::materializer` is set"
+                    .into(),
+            ))
+        }
+    }
+
     /// Node an existing page with a different set of `PageLink`s.
     /// Returns `Ok(new_key)` if the operation was successful. Returns
     /// `Err(None)` if the page no longer exists. Returns
@@ -1010,10 +1752,26 @@ impl PageCache {
         pid: PageId,
         old: PageView<'g>,
         new: Node,
+        compress: bool,
+        guard: &'g Guard,
+    ) -> Result<CasResult<'g, Node>> {
+        self.check_writable()?;
+        self.replace_inner(pid, old, new, compress, guard)
+    }
+
+    fn replace_inner<'g>(
+        &self,
+        pid: PageId,
+        old: PageView<'g>,
+        mut new: Node,
+        compress: bool,
         guard: &'g Guard,
     ) -> Result<CasResult<'g, Node>> {
         let _measure = Measure::new(&M.replace_page);
 
+        // consolidations, splits and merges rebuild the page
+        new.pack_values(self.config.inline_value_threshold);
+
         trace!("replacing pid {} with {:?}", pid, new);
 
         // A failure injector that fails replace calls randomly
@@ -1021,14 +1779,12 @@ impl PageCache {
         #[cfg(any(test, feature = "lock_free_delays"))]
         {
             use std::cell::RefCell;
-            use std::time::{SystemTime, UNIX_EPOCH};
 
             thread_local! {
                 pub static COUNT: RefCell<u32> = RefCell::new(1);
             }
 
-            let time_now =
-                SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+            let time_now = self.config.now();
 
             #[allow(clippy::cast_possible_truncation)]
             let fail_seed = std::cmp::max(3, time_now.as_nanos() as u32 % 128);
@@ -1044,16 +1800,14 @@ impl PageCache {
                     "injecting a randomized failure in the replace of pid {}",
                     pid
                 );
-                if let Some(current_pointer) = self.get(pid, guard)? {
-                    return Ok(Err(Some((current_pointer.0, new))));
-                } else {
-                    return Ok(Err(None));
-                }
+                // the page is not read again, which could wait
+                // on a log write that the caller holds back
+                return Ok(Err(Some((old, new))));
             }
         }
 
         let result =
-            self.cas_page(pid, old, Update::Node(new), false, guard)?;
+            self.cas_page(pid, old, Update::Node(new), false, compress, guard)?;
 
         if let Some((pid_to_clean, segment_to_clean)) =
             self.log.iobufs.segment_cleaner.pop()
@@ -1075,12 +1829,32 @@ impl PageCache {
     // (at least partially) located in. This happens when a
     // segment has had enough resident page replacements moved
     // away to trigger the `segment_cleanup_threshold`.
+    // Returns the number of bytes written to the log, which
+    // is 0 if the page had already been moved.
+    // Relocates `pid` out of the segment being cleaned,
+    // returning the bytes written, or 0 if it had already
+    // moved.
     fn rewrite_page(
         &self,
         pid: PageId,
         segment_to_purge: LogOffset,
         guard: &Guard,
-    ) -> Result<()> {
+    ) -> Result<u64> {
+        let rewritten =
+            self.rewrite_page_inner(pid, segment_to_purge, guard)?;
+        if rewritten > 0 {
+            let _ = self.relocated_pages.fetch_add(1, Relaxed);
+            let _ = self.relocated_bytes.fetch_add(rewritten, Relaxed);
+        }
+        Ok(rewritten)
+    }
+
+    fn rewrite_page_inner(
+        &self,
+        pid: PageId,
+        segment_to_purge: LogOffset,
+        guard: &Guard,
+    ) -> Result<u64> {
         let _measure = Measure::new(&M.rewrite_page);
 
         trace!("rewriting pid {}", pid);
@@ -1104,7 +1878,7 @@ impl PageCache {
                         == purge_segment_id
                 });
             if already_moved {
-                return Ok(());
+                return Ok(0);
             }
 
             // if the page is just a single blob pointer, rewrite it.
@@ -1112,9 +1886,24 @@ impl PageCache {
                 trace!("rewriting blob with pid {}", pid);
                 let blob_pointer = disk_pointer.blob().1;
 
-                let log_reservation =
-                    self.log.rewrite_blob_pointer(pid, blob_pointer, guard)?;
-
+                let page_kind = match page_view.update {
+                    Some(Update::Value(_)) => PageKind::Value,
+                    Some(Update::Custom(_)) => PageKind::Custom,
+                    Some(_) => PageKind::Node,
+                    None => match read_blob_kind(blob_pointer, &self.config)? {
+                        MessageKind::BlobValue => PageKind::Value,
+                        MessageKind::BlobCustom => PageKind::Custom,
+                        _ => PageKind::Node,
+                    },
+                };
+
+                let log_reservation = self.log.rewrite_blob_pointer(
+                    pid,
+                    blob_pointer,
+                    page_kind,
+                    guard,
+                )?;
+
                 let cache_info = CacheInfo {
                     ts: page_view.ts(),
                     lsn: log_reservation.lsn,
@@ -1126,13 +1915,15 @@ impl PageCache {
                 let new_page = Owned::new(Page {
                     update: page_view.update.clone(),
                     cache_infos: StackVec::single(cache_info),
+                    reads: AtomicU32::new(0),
+                    compress: page_view.compress,
                 });
 
                 debug_delay();
                 let result = page_view.entry.compare_and_set(
                     page_view.read,
                     new_page,
-                    SeqCst,
+                    AcqRel,
                     guard,
                 );
 
@@ -1155,6 +1946,7 @@ impl PageCache {
                     // when the iobuf's n_writers hits 0, we may transition
                     // the segment to inactive, resulting in a race otherwise.
                     let _pointer = log_reservation.complete()?;
+                    self.mark_dirty(pid, lsn);
 
                     // possibly evict an item now that our cache has grown
                     let total_page_size =
@@ -1172,7 +1964,7 @@ impl PageCache {
 
                     trace!("rewriting pid {} succeeded", pid);
 
-                    return Ok(());
+                    return Ok(cache_info.log_size);
                 } else {
                     let _pointer = log_reservation.abort()?;
 
@@ -1190,6 +1982,10 @@ impl PageCache {
                     (key, Update::Counter(counter))
                 } else if let Some(node_view) = self.get(pid, guard)? {
                     (node_view.0, Update::Node(node_view.deref().clone()))
+                } else if let Some(value_view) = self.get_value(pid, guard)? {
+                    (value_view.0, Update::Value(value_view.deref().clone()))
+                } else if let Some(custom_view) = self.get_custom(pid, guard)? {
+                    (custom_view.0, Update::Custom(custom_view.deref().clone()))
                 } else {
                     let page_view = match self.inner.get(pid, guard) {
                         None => panic!("expected page missing in rewrite"),
@@ -1209,23 +2005,23 @@ impl PageCache {
                              the Free was replace'd",
                                 pid, other
                             );
-                            return Ok(());
+                            return Ok(0);
                         }
                     }
                 };
 
-                let res = self.cas_page(pid, key, update, true, guard).map(
-                    |res| {
+                let res = self
+                    .cas_page(pid, key, update, true, key.compress, guard)
+                    .map(|res| {
                         trace!(
                             "rewriting pid {} success: {}",
                             pid,
                             res.is_ok()
                         );
                         res
-                    },
-                )?;
-                if res.is_ok() {
-                    return Ok(());
+                    })?;
+                if let Ok(new_view) = res {
+                    return Ok(new_view.log_size());
                 }
             }
         }
@@ -1247,14 +2043,104 @@ impl PageCache {
         Ok(on_disk_bytes / logical_size)
     }
 
+    /// Approximates the memory held by resident pages, the
+    /// records of every page's chain of log messages, the
+    /// page table, the LRU, the current IO buffer, and the
+    /// filters of paged out leaves.
+    pub(crate) fn memory_usage(&self) -> MemoryUsage {
+        let guard = pin();
+        let mut usage = MemoryUsage {
+            page_table: self.inner.rss(&guard),
+            lru: self.lru.rss(),
+            io_buffer: self.config.segment_size as u64,
+            leaf_filters: self.leaf_filters.rss(),
+            ..MemoryUsage::default()
+        };
+
+        for pid in 0..self.next_pid_to_allocate.load(Acquire) {
+            if let Some(page_view) = self.inner.get(pid, &guard) {
+                usage.delta_chains += std::mem::size_of::<Page>() as u64;
+                if let Some(update) = &page_view.update {
+                    usage.resident_pages += update.rss();
+                }
+            }
+        }
+
+        usage
+    }
+
+    /// Breaks down the space used by the log, blob and
+    /// snapshot files, and the live and dead bytes of every
+    /// segment that is not free.
+    #[allow(clippy::cast_precision_loss)]
+    #[allow(clippy::float_arithmetic)]
+    pub(crate) fn space_stats(&self) -> Result<SpaceStats> {
+        let segments = self.log.iobufs.with_sa(|sa| sa.segment_space());
+        let log_bytes = self.config.file.allocated_len()?;
+        let on_disk_bytes = self.size_on_disk()?;
+
+        let mut snapshot_bytes = 0;
+        for path in snapshot_files(&self.config)? {
+            // older snapshots may be removed concurrently
+            snapshot_bytes +=
+                std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        }
+
+        let written_bytes = self.log.iobufs.written_bytes.load(Relaxed);
+        let linked_bytes = self.linked_bytes.load(Relaxed);
+        let write_amplification = if linked_bytes == 0 {
+            0.
+        } else {
+            written_bytes as f64 / linked_bytes as f64
+        };
+
+        Ok(SpaceStats {
+            log_bytes,
+            blob_bytes: on_disk_bytes.saturating_sub(log_bytes),
+            snapshot_bytes,
+            live_bytes: segments.iter().map(|s| s.live_bytes).sum(),
+            dead_bytes: segments.iter().map(|s| s.dead_bytes).sum(),
+            segments,
+            space_amplification: self.space_amplification()?,
+            write_amplification,
+        })
+    }
+
+    /// Counts the IO done since startup.
+    pub(crate) fn io_stats(&self) -> IoStats {
+        let (fsyncs, fsync_time) = self.config.file.syncs();
+        IoStats {
+            log_bytes_written: self.log.iobufs.written_bytes.load(Relaxed),
+            page_ins: self.page_ins.load(Relaxed),
+            page_in_bytes: self.page_in_bytes.load(Relaxed),
+            fsyncs,
+            fsync_time,
+            gc_relocated_pages: self.relocated_pages.load(Relaxed),
+            gc_relocated_bytes: self.relocated_bytes.load(Relaxed),
+        }
+    }
+
     pub(crate) fn size_on_disk(&self) -> Result<u64> {
-        let mut size = self.config.file.metadata()?.len();
+        // free segments in the middle of the log have
+        // holes punched in them, which don't use any
+        // space even though they count toward its length
+        let mut size = self.config.file.allocated_len()?;
 
         let stable = self.config.blob_path(0);
         let blob_dir = stable.parent().expect(
             "should be able to determine the parent for the blob directory",
         );
-        let blob_files = std::fs::read_dir(blob_dir)?;
+        let blob_files = match std::fs::read_dir(blob_dir) {
+            Ok(blob_files) => blob_files,
+            Err(ref e)
+                if self.config.in_memory
+                    && e.kind() == std::io::ErrorKind::NotFound =>
+            {
+                // no blobs have been written yet
+                return Ok(size);
+            }
+            Err(e) => return Err(e.into()),
+        };
 
         for blob_file in blob_files {
             let blob_file = if let Ok(bf) = blob_file {
@@ -1282,6 +2168,10 @@ impl PageCache {
         for pid in min_pid..next_pid_to_allocate {
             if let Some(node_cell) = self.get(pid, &guard)? {
                 ret += node_cell.rss();
+            } else if let Some(value_cell) = self.get_value(pid, &guard)? {
+                ret += value_cell.len() as u64;
+            } else if let Some(custom_cell) = self.get_custom(pid, &guard)? {
+                ret += custom_cell.len() as u64;
             }
         }
         Ok(ret)
@@ -1293,6 +2183,7 @@ impl PageCache {
         mut old: PageView<'g>,
         update: Update,
         is_rewrite: bool,
+        compress: bool,
         guard: &'g Guard,
     ) -> Result<CasResult<'g, Update>> {
         trace!(
@@ -1308,6 +2199,8 @@ impl PageCache {
         let mut new_page = Some(Owned::new(Page {
             update: Some(update),
             cache_infos: StackVec::default(),
+            reads: AtomicU32::new(0),
+            compress,
         }));
 
         loop {
@@ -1318,8 +2211,14 @@ impl PageCache {
                 }
                 Update::Meta(m) => self.log.reserve(log_kind, pid, m, guard)?,
                 Update::Free => self.log.reserve(log_kind, pid, &(), guard)?,
-                Update::Node(node) => {
-                    self.log.reserve(log_kind, pid, node, guard)?
+                Update::Node(node) => self
+                    .log
+                    .reserve_node(log_kind, pid, node, compress, guard)?,
+                Update::Value(value) => {
+                    self.log.reserve_value(pid, value, guard)?
+                }
+                Update::Custom(page) => {
+                    self.log.reserve_custom(log_kind, pid, page, guard)?
                 }
                 other => {
                     panic!("non-replacement used in cas_page: {:?}", other)
@@ -1359,7 +2258,7 @@ impl PageCache {
 
             debug_delay();
             let result =
-                old.entry.compare_and_set(old.read, page_ptr, SeqCst, guard);
+                old.entry.compare_and_set(old.read, page_ptr, AcqRel, guard);
 
             match result {
                 Ok(new_shared) => {
@@ -1380,6 +2279,7 @@ impl PageCache {
                     // when the iobuf's n_writers hits 0, we may transition
                     // the segment to inactive, resulting in a race otherwise.
                     let _pointer = log_reservation.complete()?;
+                    self.mark_dirty(pid, lsn);
 
                     // possibly evict an item now that our cache has grown
                     let total_page_size =
@@ -1486,270 +2386,1173 @@ impl PageCache {
         }
     }
 
-    /// Try to retrieve a page by its logical ID.
-    pub fn get<'g>(
+    /// Returns the IDs of all pages that are currently
+    /// allocated, other than the meta and counter pages.
+    pub(crate) fn allocated_pids(&self, guard: &Guard) -> Vec<PageId> {
+        let next_pid_to_allocate = self.next_pid_to_allocate.load(Acquire);
+        (COUNTER_PID + 1..next_pid_to_allocate)
+            .filter(|pid| {
+                self.inner
+                    .get(*pid, guard)
+                    .map_or(false, |page_view| !page_view.is_free())
+            })
+            .collect()
+    }
+
+    /// Replays the log from its beginning, returning the
+    /// contents that every page had once the messages up to
+    /// and including `from`, and then `to`, were applied.
+    /// Fails with `Error::Unsupported` if any part of that
+    /// history has been reclaimed by segment cleaning.
+    pub(crate) fn historical_pages(
         &self,
-        pid: PageId,
-        guard: &'g Guard,
-    ) -> Result<Option<NodeView<'g>>> {
-        trace!("getting page iterator for pid {}", pid);
-        let _measure = Measure::new(&M.get_page);
+        from: Lsn,
+        to: Lsn,
+    ) -> Result<(FastMap8<PageId, Update>, FastMap8<PageId, Update>)> {
+        if from > to {
+            return Err(Error::Unsupported(format!(
+                "lsn {} is after lsn {}",
+                from, to
+            )));
+        }
 
-        if pid == COUNTER_PID || pid == META_PID || pid == BATCH_MANIFEST_PID {
-            return Err(Error::Unsupported(
-                "you are not able to iterate over \
-                 the first couple pages, which are \
-                 reserved for storing metadata and \
-                 monotonic ID generator info"
-                    .into(),
-            ));
+        if to > self.log.stable_offset() {
+            self.flush()?;
         }
 
-        let page_view = match self.inner.get(pid, guard) {
-            None => return Ok(None),
-            Some(p) => p,
+        if to > self.log.stable_offset() {
+            return Err(Error::Unsupported(format!(
+                "lsn {} has not been written yet",
+                to
+            )));
+        }
+
+        let reclaimed = || {
+            Error::Unsupported(format!(
+                "the log before lsn {} is no longer retained",
+                to
+            ))
         };
 
-        if page_view.is_free() {
-            return Ok(None);
+        if !self.history_retained(0, to) {
+            return Err(reclaimed());
         }
 
-        if page_view.update.is_some() {
-            // possibly evict an item now that our cache has grown
-            let total_page_size = page_view.log_size();
-            let to_evict = self.lru.accessed(pid, total_page_size, guard);
-            trace!("accessed pid {} -> paging out pids {:?}", pid, to_evict);
-            if !to_evict.is_empty() {
-                self.page_out(to_evict, guard)?;
+        let segment_size = self.config.segment_size as Lsn;
+        let mut pages = FastMap8::default();
+        let mut pages_at_from = None;
+
+        let mut iter = self.log.iter_from(0);
+        iter.max_lsn = to;
+
+        while let Some((kind, pid, lsn, ptr, _sz)) = iter.next() {
+            if lsn > from && pages_at_from.is_none() {
+                pages_at_from = Some(pages.clone());
             }
-            return Ok(Some(NodeView(page_view)));
-        }
 
-        // need to page-in
-        let updates_result: Result<Vec<Update>> = page_view
-            .cache_infos
-            .iter()
-            .map(|ci| self.pull(pid, ci.lsn, ci.pointer))
-            .collect();
+            if kind == LogKind::Free {
+                pages.remove(&pid);
+                continue;
+            } else if kind != LogKind::Replace && kind != LogKind::Link {
+                continue;
+            }
 
-        let mut updates: Vec<Update> = updates_result?;
+            // the segment may be reused between being iterated
+            // over and being read here, which we detect the same
+            // way that the log iterator does.
+            let (header, bytes) = match self.log.read(pid, lsn, ptr)? {
+                LogRead::Inline(header, bytes, _)
+                | LogRead::Blob(header, bytes, _, _) => (header, bytes),
+                _ => return Err(reclaimed()),
+            };
 
-        let (base_slice, links) = updates.split_at_mut(1);
+            let expected_segment_number =
+                SegmentNumber(u64::try_from(lsn / segment_size).unwrap());
+            if header.pid != pid
+                || header.segment_number != expected_segment_number
+            {
+                return Err(reclaimed());
+            }
 
-        let base: &mut Node = base_slice[0].as_node_mut();
+            match deserialize_update(header.kind, &bytes) {
+                Update::Link(link) => match pages.get_mut(&pid) {
+                    Some(Update::Node(node)) => node.apply(&link),
+                    _ => {
+                        return Err(Error::ReportableBug(format!(
+                            "link to pid {} at lsn {} without a node \
+                             to apply it to",
+                            pid, lsn
+                        )));
+                    }
+                },
+                update => {
+                    pages.insert(pid, update);
+                }
+            }
+        }
 
-        for link_update in links {
-            let link: &Link = link_update.as_link();
-            base.apply(link);
+        // removed blobs and reused segments can't be read,
+        // so the iterator skips over them.
+        if iter.dangling_blobs > 0 || !self.history_retained(0, to) {
+            return Err(reclaimed());
         }
 
-        updates.truncate(1);
-        let base = updates.pop().unwrap();
+        let pages_at_from = pages_at_from.unwrap_or_else(|| pages.clone());
 
-        let page = Owned::new(Page {
-            update: Some(base),
-            cache_infos: page_view.cache_infos,
-        });
+        Ok((pages_at_from, pages))
+    }
 
-        debug_delay();
-        let result = page_view.entry.compare_and_set(
-            page_view.read,
-            page,
-            SeqCst,
-            guard,
-        );
+    // Segments are only ever freed as a whole, after which
+    // they are reused with a higher lsn, so the history from
+    // `from` up to `to` is intact if no segment in between
+    // is missing.
+    pub(crate) fn history_retained(&self, from: Lsn, to: Lsn) -> bool {
+        let segment_size = self.config.segment_size as Lsn;
+        let mut expected_segment_lsn = from / segment_size * segment_size;
 
-        if let Ok(new_shared) = result {
-            trace!("fix-up for pid {} succeeded", pid);
+        let segments =
+            self.log.iobufs.with_sa(|sa| sa.segment_snapshot_iter_from(from));
 
-            unsafe {
-                guard.defer_destroy(page_view.read);
+        for (segment_lsn, _offset) in segments {
+            if segment_lsn > to {
+                break;
             }
-
-            // possibly evict an item now that our cache has grown
-            let total_page_size = unsafe { new_shared.deref().log_size() };
-            let to_evict = self.lru.accessed(pid, total_page_size, guard);
-            trace!("accessed pid {} -> paging out pids {:?}", pid, to_evict);
-            if !to_evict.is_empty() {
-                self.page_out(to_evict, guard)?;
+            if segment_lsn != expected_segment_lsn {
+                return false;
             }
+            expected_segment_lsn += segment_size;
+        }
 
-            let mut page_view = page_view;
-            page_view.read = new_shared;
-
-            Ok(Some(NodeView(page_view)))
-        } else {
-            trace!("fix-up for pid {} failed", pid);
+        expected_segment_lsn > to
+    }
 
-            self.get(pid, guard)
+    /// Copies the database into the directory at `path`
+    /// while writes continue, returning the stable lsn that
+    /// the copy was cut at. Only the snapshot, log segments,
+    /// and blobs that the state at the cut refers to are
+    /// copied.
+    pub(crate) fn backup_to(&self, path: &Path) -> Result<Lsn> {
+        if path.join("db").exists() {
+            return Err(Error::Unsupported(format!(
+                "a database already exists at {:?}",
+                path
+            )));
         }
-    }
+        std::fs::create_dir_all(path)?;
 
-    /// The highest known stable Lsn on disk.
-    pub fn stable_lsn(&self) -> Lsn {
-        self.log.stable_offset()
-    }
+        // whatever the state at the cut refers to has to stay
+        // in place until it is copied, even if it is replaced
+        // in the meantime
+        self.log.iobufs.with_sa(SegmentAccountant::pause_freeing);
+        let res = self.backup_to_inner(path);
+        let resumed =
+            self.log.iobufs.with_sa(SegmentAccountant::resume_freeing);
 
-    /// Blocks until the provided Lsn is stable on disk,
-    /// triggering necessary flushes in the process.
-    /// Returns the number of bytes written during
-    /// this call.
-    pub fn make_stable(&self, lsn: Lsn) -> Result<usize> {
-        self.log.make_stable(lsn)
-    }
+        let cut = res?;
+        resumed?;
 
-    /// Returns `true` if the database was
-    /// recovered from a previous process.
-    /// Note that database state is only
-    /// guaranteed to be present up to the
-    /// last call to `flush`! Otherwise state
-    /// is synced to disk periodically if the
-    /// `sync_every_ms` configuration option
-    /// is set to `Some(number_of_ms_between_syncs)`
-    /// or if the IO buffer gets filled to
-    /// capacity before being rotated.
-    pub const fn was_recovered(&self) -> bool {
-        self.was_recovered
+        Ok(cut)
     }
 
-    /// Generate a monotonic ID. Not guaranteed to be
-    /// contiguous. Written to disk every `idgen_persist_interval`
-    /// operations, followed by a blocking flush. During recovery, we
-    /// take the last recovered generated ID and add 2x
-    /// the `idgen_persist_interval` to it. While persisting, if the
-    /// previous persisted counter wasn't synced to disk yet, we will do
-    /// a blocking flush to fsync the latest counter, ensuring
-    /// that we will never give out the same counter twice.
-    pub fn generate_id(&self) -> Result<u64> {
-        let ret = self.idgen.fetch_add(1, Relaxed);
+    fn backup_to_inner(&self, path: &Path) -> Result<Lsn> {
+        // the journals are copied before anything else can be
+        // appended to them, so that every record they hold was
+        // written to the log before the cut
+        let replica = self.replica.lock();
+        let meta_journal = self.meta_journal.as_ref().map(|j| j.lock());
 
-        let interval = self.config.idgen_persist_interval;
-        let necessary_persists = ret / interval * interval;
-        let mut persisted = self.idgen_persists.load(Acquire);
+        let _written = self.flush()?;
+        let cut = self.stable_lsn();
 
-        while persisted < necessary_persists {
-            let _mu = self.idgen_persist_mu.lock();
-            persisted = self.idgen_persists.load(Acquire);
-            if persisted < necessary_persists {
-                // it's our responsibility to persist up to our ID
-                let guard = pin();
-                let (key, current) = self.get_idgen(&guard)?;
+        if let Some(journal) = &meta_journal {
+            journal.copy_to(path)?;
+        }
+        if let Some(journal) = &*replica {
+            journal.copy_to(path)?;
+        }
 
-                assert_eq!(current, persisted);
+        drop(meta_journal);
+        drop(replica);
 
-                let counter_update = Update::Counter(necessary_persists);
+        let mut snapshot = read_last_snapshot(&self.config)?;
+        let mut iter = self.log.iter_from(snapshot.last_lsn);
+        iter.max_lsn = iter.max_lsn.min(cut);
+        snapshot.advance(iter);
 
-                let old = self.idgen_persists.swap(necessary_persists, Release);
-                assert_eq!(old, persisted);
+        let segment_lsns: FastMap8<LogOffset, Lsn> = self
+            .log
+            .iobufs
+            .with_sa(|sa| sa.segment_snapshot_iter_from(0))
+            .map(|(lsn, lid)| (lid, lsn))
+            .collect();
 
-                if self
-                    .cas_page(
-                        COUNTER_PID,
-                        key.clone(),
-                        counter_update,
-                        false,
-                        &guard,
-                    )?
-                    .is_err()
-                {
-                    // CAS failed
-                    continue;
-                }
+        backup::copy_log(&self.config, &snapshot, &segment_lsns, cut, path)?;
+        backup::copy_blobs(&self.config, &snapshot, path)?;
 
-                // during recovery we add 2x the interval. we only
-                // need to block if the last one wasn't stable yet.
-                let gap = (necessary_persists - persisted) / interval;
-                if gap > 1 {
-                    // this is the most pessimistic case, hopefully
-                    // we only ever hit this on the first ID generation
-                    // of a process's lifetime
-                    let _written = self.flush()?;
-                } else if key.last_lsn() > self.stable_lsn() {
-                    let _written = self.make_stable(key.last_lsn())?;
-                }
-            }
+        let config_path = self.config.config_path();
+        if config_path.exists() {
+            let _copied = std::fs::copy(config_path, path.join("conf"))?;
         }
 
-        Ok(ret)
+        let _snapshot_path = write_snapshot_in(&self.config, path, &snapshot)?;
+
+        Ok(cut)
     }
 
-    /// Look up a `PageId` for a given identifier in the `Meta`
-    /// mapping. This is pretty cheap, but in some cases
-    /// you may prefer to maintain your own atomic references
-    /// to collection roots instead of relying on this. See
-    /// sled's `Tree` root tracking for an example of
-    /// avoiding this in a lock-free way that handles
-    /// various race conditions.
-    pub fn meta_pid_for_name(
-        &self,
-        name: &[u8],
-        guard: &Guard,
-    ) -> Result<PageId> {
-        let m = self.get_meta(guard)?;
-        if let Some(root) = m.get_root(name) {
-            Ok(root)
-        } else {
-            Err(Error::CollectionNotFound(name.into()))
+    /// Takes a snapshot of the page table if at least
+    /// `Config::snapshot_after_ops` messages have been
+    /// written to the log since the last one.
+    pub(crate) fn snapshot_if_due(&self) -> Result<()> {
+        let ops = self.log.ops_since_snapshot.load(Relaxed);
+        if ops < self.config.snapshot_after_ops || self.config.in_memory {
+            return Ok(());
         }
+        self.log.ops_since_snapshot.store(0, Relaxed);
+
+        // the segments that the last snapshot and the log
+        // after it refer to must not be reused before the
+        // log has been replayed on top of the snapshot
+        self.log.iobufs.with_sa(SegmentAccountant::pause_freeing);
+        let res = self.take_snapshot();
+        let resumed =
+            self.log.iobufs.with_sa(SegmentAccountant::resume_freeing);
+
+        res.and(resumed)
     }
 
-    /// Compare-and-swap the `Meta` mapping for a given
-    /// identifier.
-    pub fn cas_root_in_meta<'g>(
-        &self,
-        name: &[u8],
-        old: Option<PageId>,
-        new: Option<PageId>,
-        guard: &'g Guard,
-    ) -> Result<std::result::Result<(), Option<PageId>>> {
-        loop {
-            let meta_view = self.get_meta(guard)?;
+    // Advances the last snapshot written to disk up to the
+    // stable lsn, and writes it in place of the old one.
+    fn take_snapshot(&self) -> Result<()> {
+        let _written = self.flush()?;
+        let cut = self.stable_lsn();
 
-            let actual = meta_view.get_root(name);
-            if actual != old {
-                return Ok(Err(actual));
-            }
+        let mut snapshot = read_last_snapshot(&self.config)?;
+        if snapshot.last_lsn >= cut {
+            return Ok(());
+        }
 
-            let mut new_meta = meta_view.deref().clone();
-            if let Some(new) = new {
-                new_meta.set_root(name.into(), new);
-            } else {
-                new_meta.del_root(name);
-            }
+        let mut iter = self.log.iter_from(snapshot.last_lsn);
+        iter.max_lsn = iter.max_lsn.min(cut);
+        snapshot.advance(iter);
 
-            let new_meta_link = Update::Meta(new_meta);
+        debug!("taking a snapshot of the page table at lsn {}", cut);
 
-            let res = self.cas_page(
-                META_PID,
-                meta_view.0,
-                new_meta_link,
-                false,
-                guard,
-            )?;
+        write_snapshot(&self.config, &snapshot)?;
+        self.leaf_filters.persist(&self.config, cut)
+    }
 
-            match res {
-                Ok(_worked) => return Ok(Ok(())),
-                Err(Some((_current_pointer, _rejected))) => {}
-                Err(None) => {
-                    return Err(Error::ReportableBug(
-                        "replacing the META page has failed because \
-                         the pagecache does not think it currently exists."
-                            .into(),
-                    ));
+    /// Writes the filters of the leaves that are paged out
+    /// to disk, so that they are used again after a restart.
+    pub(crate) fn persist_leaf_filters(&self) -> Result<()> {
+        self.leaf_filters.persist(&self.config, self.stable_lsn())
+    }
+
+    /// Writes the pids of the most recently accessed pages
+    /// to disk, for `warm_up` to read them back in after a
+    /// restart, if `Config::warm_cache_pages` is set.
+    pub(crate) fn persist_warm_pages(&self) -> Result<()> {
+        let pages = self.config.warm_cache_pages;
+        if pages == 0 || self.config.in_memory || self.config.read_only {
+            return Ok(());
+        }
+        warm_pages::persist(&self.config, &self.lru.hottest(pages))
+    }
+
+    /// Reads the pages that were the most recently accessed
+    /// when the database was last closed back into the
+    /// cache, coldest first so that the hottest ones are the
+    /// last to be evicted again. Returns how many of them
+    /// had to be read from disk.
+    pub(crate) fn warm_up(&self) -> Result<usize> {
+        let pids = warm_pages::read(&self.config)?;
+        let guard = pin();
+        let mut paged_in = 0;
+        for pid in pids.into_iter().rev() {
+            match self.inner.get(pid, &guard) {
+                Some(page_view)
+                    if !page_view.is_free() && page_view.update.is_none() =>
+                {
+                    if self.page_in(pid, &guard)?.is_some() {
+                        paged_in += 1;
+                    }
                 }
+                _ => {}
             }
         }
+        Ok(paged_in)
     }
 
-    fn page_out(&self, to_evict: Vec<PageId>, guard: &Guard) -> Result<()> {
-        let _measure = Measure::new(&M.page_out);
-        for pid in to_evict {
-            if pid == COUNTER_PID
-                || pid == META_PID
-                || pid == BATCH_MANIFEST_PID
-            {
-                // should not page these suckas out
-                continue;
+    /// Runs `warm_up` on the IO threadpool.
+    pub(crate) fn warm_up_in_background(pagecache: &Arc<Self>) {
+        let pc = pagecache.clone();
+        let _result = threadpool::spawn(move || match pc.warm_up() {
+            Ok(paged_in) => debug!("warmed up {} pages", paged_in),
+            Err(e) => debug!("failed to warm up the cache: {:?}", e),
+        });
+    }
+
+    /// Reads every message and blob that makes up a page
+    /// back from disk and checks it against its checksum.
+    /// Corrupt pages that are resident are written out again
+    /// from memory, so that the corrupt copy is no longer
+    /// part of them. Messages above the stable lsn are left
+    /// for a later scrub instead of syncing the log to read
+    /// them back. With `background` set, the work is
+    /// charged to the maintenance budgets and waits for them
+    /// to refill, and the scrub returns what it has checked
+    /// so far, without recording it as the last scrub, as
+    /// soon as `stop` returns `true`.
+    pub(crate) fn scrub<F: Fn() -> bool>(
+        &self,
+        background: bool,
+        stop: F,
+    ) -> Result<ScrubReport> {
+        let mut report = ScrubReport::default();
+        let next_pid_to_allocate = self.next_pid_to_allocate.load(Acquire);
+
+        for pid in 0..next_pid_to_allocate {
+            while background && self.maintenance_throttled() && !stop() {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            if stop() {
+                return Ok(report);
+            }
+
+            let guard = pin();
+            let page_view = match self.inner.get(pid, &guard) {
+                Some(page_view) if !page_view.is_free() => page_view,
+                _ => continue,
+            };
+
+            let stable_lsn = self.stable_lsn();
+            let mut checked = false;
+            let mut corrupt_at = None;
+            for cache_info in page_view.cache_infos.iter() {
+                if cache_info.lsn > stable_lsn {
+                    continue;
+                }
+                checked = true;
+                report.bytes += cache_info.log_size;
+                if !self.message_is_intact(pid, cache_info)? {
+                    corrupt_at = Some(cache_info.pointer);
+                    break;
+                }
+            }
+            if !checked {
+                continue;
+            }
+            report.pages += 1;
+            if background {
+                self.charge_maintenance(page_view.log_size(), 1);
+            }
+
+            let at = if let Some(at) = corrupt_at {
+                at
+            } else {
+                continue;
+            };
+
+            // the page may have been rewritten since it was
+            // read, and the segment the message was in reused
+            if let Some(repaired) = self.repair_from_memory(pid, at, &guard)? {
+                error!(
+                    "scrub found pid {} corrupt at {}, repaired: {}",
+                    pid, at, repaired
+                );
+                report.corrupt.push(CorruptPage { pid, at, repaired });
+            }
+        }
+
+        *self.last_scrub.lock() = Some(report.clone());
+
+        Ok(report)
+    }
+
+    /// Returns the outcome of the last scrub that was not
+    /// stopped part way through.
+    pub(crate) fn last_scrub(&self) -> Option<ScrubReport> {
+        self.last_scrub.lock().clone()
+    }
+
+    // reads a stable message of a page back from disk, along
+    // with the blob it points to if it has one
+    fn message_is_intact(
+        &self,
+        pid: PageId,
+        cache_info: &CacheInfo,
+    ) -> Result<bool> {
+        let expected_segment_number = SegmentNumber(
+            u64::try_from(cache_info.lsn).unwrap()
+                / u64::try_from(self.config.segment_size).unwrap(),
+        );
+
+        let read = read_message(
+            &*self.config.file,
+            cache_info.pointer.lid(),
+            expected_segment_number,
+            &self.config,
+        );
+
+        match read {
+            Ok(LogRead::Inline(header, ..)) | Ok(LogRead::Blob(header, ..)) => {
+                Ok(header.pid == pid)
+            }
+            Ok(_) | Err(Error::Corruption { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    // writes a page out again from memory in place of its
+    // messages on disk if it still includes the one at `at`,
+    // returning whether it was resident to do so
+    fn repair_from_memory(
+        &self,
+        pid: PageId,
+        at: DiskPtr,
+        guard: &Guard,
+    ) -> Result<Option<bool>> {
+        loop {
+            let page_view = match self.inner.get(pid, guard) {
+                Some(page_view)
+                    if !page_view.is_free()
+                        && page_view
+                            .cache_infos
+                            .iter()
+                            .any(|cache_info| cache_info.pointer == at) =>
+                {
+                    page_view
+                }
+                _ => return Ok(None),
+            };
+
+            let update = if let Some(update) = &page_view.update {
+                update.clone()
+            } else {
+                return Ok(Some(false));
+            };
+
+            let compress = page_view.compress;
+            let result =
+                self.cas_page(pid, page_view, update, true, compress, guard)?;
+            if result.is_ok() {
+                return Ok(Some(true));
+            }
+        }
+    }
+
+    /// Returns `true` if `pid` is a paged out leaf whose
+    /// filter shows that `key` is not in it, which spares
+    /// reading it back from the log to find that out. Only
+    /// keys between the leaf's bounds can be excluded.
+    pub(crate) fn leaf_filter_excludes(
+        &self,
+        pid: PageId,
+        key: &[u8],
+        guard: &Guard,
+    ) -> bool {
+        if self.config.leaf_filter_bits_per_key == 0 {
+            return false;
+        }
+        match self.paged_out_lsn(pid, guard) {
+            Some(lsn) => self.leaf_filters.excludes(pid, lsn, key),
+            None => false,
+        }
+    }
+
+    // the lsn of the last message of a page that is not
+    // resident, which changes with every write to it
+    fn paged_out_lsn(&self, pid: PageId, guard: &Guard) -> Option<Lsn> {
+        match self.inner.get(pid, guard) {
+            Some(page_view)
+                if page_view.update.is_none() && !page_view.is_free() =>
+            {
+                page_view.cache_infos.last().map(|ci| ci.lsn)
+            }
+            _ => None,
+        }
+    }
+
+    /// Try to retrieve a page by its logical ID.
+    pub fn get<'g>(
+        &self,
+        pid: PageId,
+        guard: &'g Guard,
+    ) -> Result<Option<NodeView<'g>>> {
+        trace!("getting page iterator for pid {}", pid);
+        let _measure = Measure::new(&M.get_page);
+
+        if pid == COUNTER_PID || pid == META_PID || pid == BATCH_MANIFEST_PID {
+            return Err(Error::Unsupported(
+                "you are not able to iterate over \
+                 the first couple pages, which are \
+                 reserved for storing metadata and \
+                 monotonic ID generator info"
+                    .into(),
+            ));
+        }
+
+        match self.page_in(pid, guard)? {
+            Some(page_view)
+                if !page_view.is_value() && !page_view.is_custom() =>
+            {
+                Ok(Some(NodeView(page_view)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Retrieve a value that was stored with `allocate_value`.
+    pub(crate) fn get_value<'g>(
+        &self,
+        pid: PageId,
+        guard: &'g Guard,
+    ) -> Result<Option<ValueView<'g>>> {
+        trace!("getting overflow value for pid {}", pid);
+        let _measure = Measure::new(&M.get_page);
+
+        match self.page_in(pid, guard)? {
+            Some(page_view) if page_view.is_value() => {
+                Ok(Some(ValueView(page_view)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Retrieve a page that was stored with `allocate_custom`,
+    /// merging any fragments linked onto it.
+    pub(crate) fn get_custom<'g>(
+        &self,
+        pid: PageId,
+        guard: &'g Guard,
+    ) -> Result<Option<CustomView<'g>>> {
+        trace!("getting custom page for pid {}", pid);
+        let _measure = Measure::new(&M.get_page);
+
+        // unlike the other pids, these come from users
+        if pid >= self.next_pid_to_allocate.load(Acquire) {
+            return Ok(None);
+        }
+
+        match self.page_in(pid, guard)? {
+            Some(page_view) if page_view.is_custom() => {
+                Ok(Some(CustomView(page_view)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns a reader over a value that was stored with
+    /// `allocate_value`. If the value is not resident and
+    /// lives in an uncompressed blob, it is streamed from the
+    /// blob file instead of being paged in.
+    pub(crate) fn value_reader(
+        &self,
+        pid: PageId,
+        guard: &Guard,
+    ) -> Result<Option<ValueReader>> {
+        let page_view = match self.inner.get(pid, guard) {
+            None => return Ok(None),
+            Some(p) => p,
+        };
+
+        if page_view.is_free() {
+            return Ok(None);
+        }
+
+        if page_view.update.is_none() && page_view.cache_infos.len() == 1 {
+            if let DiskPtr::Blob(_, blob_pointer) =
+                page_view.cache_infos[0].pointer
+            {
+                // the blob may have been concurrently
+                // rewritten elsewhere, or be compressed,
+                // in which case we fall back to paging
+                // the value in.
+                if let Ok(blob_reader) =
+                    BlobReader::open(blob_pointer, &self.config)
+                {
+                    return Ok(Some(ValueReader::from_blob(blob_reader)));
+                }
+            }
+        }
+
+        Ok(self
+            .get_value(pid, guard)?
+            .map(|value_view| ValueReader::from_memory((*value_view).clone())))
+    }
+
+    /// Reads up to `pages` nodes into the cache in the
+    /// background, starting with the first one that is not
+    /// resident along the `next` pointers from `pid`. The
+    /// kernel is asked to read that node's log messages
+    /// right away, while the IO threadpool pages it and the
+    /// nodes after it in. Nothing happens if too many
+    /// prefetches are already in flight.
+    pub(crate) fn prefetch(pagecache: &Arc<Self>, pid: PageId, pages: usize) {
+        let guard = pin();
+        let mut cursor = pid;
+        let mut remaining = pages;
+
+        while remaining > 0 {
+            let page_view = match pagecache.inner.get(cursor, &guard) {
+                Some(page_view) if !page_view.is_free() => page_view,
+                _ => return,
+            };
+            match page_view.update {
+                Some(Update::Node(ref node)) => {
+                    cursor = if let Some(next) = node.next {
+                        next
+                    } else {
+                        return;
+                    };
+                    remaining -= 1;
+                }
+                Some(_) => return,
+                None => {
+                    for cache_info in page_view.cache_infos.iter() {
+                        if cache_info.pointer.is_inline() {
+                            let _ = pagecache.config.file.prefetch(
+                                cache_info.pointer.lid(),
+                                cache_info.log_size,
+                            );
+                        }
+                    }
+                    break;
+                }
+            }
+        }
+
+        if remaining == 0 {
+            return;
+        }
+
+        {
+            let mut prefetching = pagecache.prefetching.lock();
+            if prefetching.len() >= PREFETCH_QUEUE_CAPACITY
+                || prefetching.contains(&cursor)
+            {
+                return;
+            }
+            prefetching.push(cursor);
+        }
+
+        trace!("prefetching {} pages starting at pid {}", remaining, cursor);
+
+        let pc = pagecache.clone();
+        let _result = threadpool::spawn(move || {
+            pc.page_in_chain(cursor, remaining);
+            pc.prefetching.lock().retain(|queued| *queued != cursor);
+        });
+    }
+
+    // Pages in up to `pages` nodes, following their `next`
+    // pointers from `pid`.
+    fn page_in_chain(&self, pid: PageId, pages: usize) {
+        let guard = pin();
+        let mut next = Some(pid);
+        for _ in 0..pages {
+            let current =
+                if let Some(current) = next { current } else { break };
+            next = match self.get(current, &guard) {
+                Ok(Some(node_view)) => node_view.next,
+                Ok(None) => None,
+                Err(e) => {
+                    debug!("failed to prefetch pid {}: {:?}", current, e);
+                    None
+                }
+            };
+        }
+    }
+
+    /// Like `get`, but returns `None` instead of pulling
+    /// the page from the log if it has been paged out.
+    #[cfg(feature = "async")]
+    pub(crate) fn get_resident<'g>(
+        &self,
+        pid: PageId,
+        guard: &'g Guard,
+    ) -> Result<Option<NodeView<'g>>> {
+        match self.resident(pid, guard)? {
+            Some(page_view)
+                if !page_view.is_value() && !page_view.is_custom() =>
+            {
+                Ok(Some(NodeView(page_view)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Like `get_value`, but returns `None` instead of
+    /// pulling the value from the log if it has been paged
+    /// out.
+    #[cfg(feature = "async")]
+    pub(crate) fn get_value_resident<'g>(
+        &self,
+        pid: PageId,
+        guard: &'g Guard,
+    ) -> Result<Option<ValueView<'g>>> {
+        match self.resident(pid, guard)? {
+            Some(page_view) if page_view.is_value() => {
+                Ok(Some(ValueView(page_view)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    #[cfg(feature = "async")]
+    fn resident<'g>(
+        &self,
+        pid: PageId,
+        guard: &'g Guard,
+    ) -> Result<Option<PageView<'g>>> {
+        match self.inner.get(pid, guard) {
+            Some(page_view)
+                if !page_view.is_free() && page_view.update.is_some() =>
+            {
+                self.accessed(pid, page_view.log_size(), guard)?;
+                Ok(Some(page_view))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // Records an access to a resident page, possibly
+    // evicting others now that our cache has grown.
+    fn accessed(&self, pid: PageId, size: u64, guard: &Guard) -> Result<()> {
+        let to_evict = self.lru.accessed(pid, size, guard);
+        trace!("accessed pid {} -> paging out pids {:?}", pid, to_evict);
+        if !to_evict.is_empty() {
+            self.page_out(to_evict, guard)?;
+        }
+        Ok(())
+    }
+
+    // Returns a resident view of a page, pulling its
+    // fragments from the log if it has been paged out.
+    fn page_in<'g>(
+        &self,
+        pid: PageId,
+        guard: &'g Guard,
+    ) -> Result<Option<PageView<'g>>> {
+        let page_view = match self.inner.get(pid, guard) {
+            None => return Ok(None),
+            Some(p) => p,
+        };
+
+        if page_view.is_free() {
+            return Ok(None);
+        }
+
+        if page_view.update.is_some() {
+            self.accessed(pid, page_view.log_size(), guard)?;
+            return Ok(Some(page_view));
+        }
+
+        // need to page-in
+        let (mut updates, base_compressed) =
+            self.pull_fragments(pid, &page_view.cache_infos)?;
+        let _ = self.page_ins.fetch_add(1, Relaxed);
+        let _ = self.page_in_bytes.fetch_add(page_view.log_size(), Relaxed);
+
+        let (base_slice, links) = updates.split_at_mut(1);
+
+        if let Update::Value(_) = base_slice[0] {
+            // values are only ever replaced or freed
+            assert!(links.is_empty());
+        } else if let Update::Custom(page) = &base_slice[0] {
+            if !links.is_empty() {
+                let frags: Vec<&[u8]> = links
+                    .iter()
+                    .map(|link| {
+                        if let Update::Frag(frag) = link {
+                            &**frag
+                        } else {
+                            panic!("non-Frag linked onto pid {}", pid)
+                        }
+                    })
+                    .collect();
+                let merged = self.materializer()?.merge(page, &frags);
+                base_slice[0] = Update::Custom(IVec::from(merged));
+            }
+        } else {
+            let base: &mut Node = base_slice[0].as_node_mut();
+
+            for link_update in links {
+                let link: &Link = link_update.as_link();
+                base.apply(link);
+            }
+            base.pack_values(self.config.inline_value_threshold);
+        }
+
+        updates.truncate(1);
+        let base = updates.pop().unwrap();
+
+        let page = Owned::new(Page {
+            update: Some(base),
+            cache_infos: page_view.cache_infos,
+            reads: AtomicU32::new(page_view.reads.load(Relaxed)),
+            // after recovery, only the log knows whether the
+            // page belongs to a compressed tree
+            compress: page_view.compress || base_compressed,
+        });
+
+        debug_delay();
+        let result = page_view.entry.compare_and_set(
+            page_view.read,
+            page,
+            AcqRel,
+            guard,
+        );
+
+        if let Ok(new_shared) = result {
+            trace!("fix-up for pid {} succeeded", pid);
+
+            unsafe {
+                guard.defer_destroy(page_view.read);
+            }
+
+            self.leaf_filters.remove(pid);
+
+            let total_page_size = unsafe { new_shared.deref().log_size() };
+            self.accessed(pid, total_page_size, guard)?;
+
+            let mut page_view = page_view;
+            page_view.read = new_shared;
+
+            Ok(Some(page_view))
+        } else {
+            trace!("fix-up for pid {} failed", pid);
+
+            self.page_in(pid, guard)
+        }
+    }
+
+    /// The highest known stable Lsn on disk.
+    pub fn stable_lsn(&self) -> Lsn {
+        self.log.stable_offset()
+    }
+
+    /// Blocks until the provided Lsn is stable on disk,
+    /// triggering necessary flushes in the process.
+    /// Returns the number of bytes written during
+    /// this call.
+    pub fn make_stable(&self, lsn: Lsn) -> Result<usize> {
+        self.log.make_stable(lsn)
+    }
+
+    /// Returns `true` if the database was
+    /// recovered from a previous process.
+    /// Note that database state is only
+    /// guaranteed to be present up to the
+    /// last call to `flush`! Otherwise state
+    /// is synced to disk periodically if the
+    /// `sync_every_ms` configuration option
+    /// is set to `Some(number_of_ms_between_syncs)`
+    /// or if the IO buffer gets filled to
+    /// capacity before being rotated.
+    pub const fn was_recovered(&self) -> bool {
+        self.was_recovered
+    }
+
+    /// Generate a monotonic ID. Not guaranteed to be
+    /// contiguous. Written to disk every `idgen_persist_interval`
+    /// operations, followed by a blocking flush. During recovery, we
+    /// take the last recovered generated ID and add 2x
+    /// the `idgen_persist_interval` to it. While persisting, if the
+    /// previous persisted counter wasn't synced to disk yet, we will do
+    /// a blocking flush to fsync the latest counter, ensuring
+    /// that we will never give out the same counter twice.
+    pub fn generate_id(&self) -> Result<u64> {
+        self.check_writable()?;
+
+        let ret = self.idgen.fetch_add(1, Relaxed);
+
+        let interval = self.config.idgen_persist_interval;
+        let necessary_persists = ret / interval * interval;
+        let mut persisted = self.idgen_persists.load(Acquire);
+
+        while persisted < necessary_persists {
+            let _mu = self.idgen_persist_mu.lock();
+            persisted = self.idgen_persists.load(Acquire);
+            if persisted < necessary_persists {
+                // it's our responsibility to persist up to our ID
+                let guard = pin();
+                let (key, current) = self.get_idgen(&guard)?;
+
+                assert_eq!(current, persisted);
+
+                let counter_update = Update::Counter(necessary_persists);
+
+                if self
+                    .cas_page(
+                        COUNTER_PID,
+                        key.clone(),
+                        counter_update,
+                        false,
+                        false,
+                        &guard,
+                    )?
+                    .is_err()
+                {
+                    // CAS failed, most likely because the page was
+                    // relocated, so the counter is still at
+                    // `persisted` and we try again
+                    continue;
+                }
+
+                let old = self.idgen_persists.swap(necessary_persists, Release);
+                assert_eq!(old, persisted);
+
+                // during recovery we add 2x the interval. we only
+                // need to block if the last one wasn't stable yet.
+                let gap = (necessary_persists - persisted) / interval;
+                if gap > 1 {
+                    // this is the most pessimistic case, hopefully
+                    // we only ever hit this on the first ID generation
+                    // of a process's lifetime
+                    let _written = self.flush()?;
+                } else if key.last_lsn() > self.stable_lsn() {
+                    let _written = self.make_stable(key.last_lsn())?;
+                }
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Look up a `PageId` for a given identifier in the `Meta`
+    /// mapping. This is pretty cheap, but in some cases
+    /// you may prefer to maintain your own atomic references
+    /// to collection roots instead of relying on this. See
+    /// sled's `Tree` root tracking for an example of
+    /// avoiding this in a lock-free way that handles
+    /// various race conditions.
+    pub fn meta_pid_for_name(
+        &self,
+        name: &[u8],
+        guard: &Guard,
+    ) -> Result<PageId> {
+        let m = self.get_meta(guard)?;
+        if let Some(root) = m.get_root(name) {
+            Ok(root)
+        } else {
+            Err(Error::CollectionNotFound(name.into()))
+        }
+    }
+
+    /// Atomically replace the application-defined version
+    /// number that is stored in the `Meta` page.
+    pub(crate) fn set_user_version(
+        &self,
+        user_version: u32,
+        guard: &Guard,
+    ) -> Result<()> {
+        self.check_writable()?;
+
+        let mut journal = self.meta_journal.as_ref().map(|j| j.lock());
+
+        loop {
+            let meta_view = self.get_meta(guard)?;
+
+            let mut new_meta = meta_view.deref().clone();
+            new_meta.user_version = user_version;
+
+            let res = self.cas_page(
+                META_PID,
+                meta_view.0,
+                Update::Meta(new_meta),
+                false,
+                false,
+                guard,
+            )?;
+
+            match res {
+                Ok(worked) => return self.journal_meta(&mut journal, &worked),
+                Err(Some((_current_pointer, _rejected))) => {}
+                Err(None) => {
+                    return Err(Error::ReportableBug(
+                        "replacing the META page has failed because \
+                         the pagecache does not think it currently exists."
+                            .into(),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Compare-and-swap the `Meta` mapping for a given
+    /// identifier.
+    pub fn cas_root_in_meta<'g>(
+        &self,
+        name: &[u8],
+        old: Option<PageId>,
+        new: Option<PageId>,
+        guard: &'g Guard,
+    ) -> Result<std::result::Result<(), Option<PageId>>> {
+        self.check_writable()?;
+
+        let mut journal = self.meta_journal.as_ref().map(|j| j.lock());
+
+        loop {
+            let meta_view = self.get_meta(guard)?;
+
+            let actual = meta_view.get_root(name);
+            if actual != old {
+                return Ok(Err(actual));
+            }
+
+            let mut new_meta = meta_view.deref().clone();
+            if let Some(new) = new {
+                new_meta.set_root(name.into(), new);
+            } else {
+                new_meta.del_root(name);
+            }
+
+            let new_meta_link = Update::Meta(new_meta);
+
+            let res = self.cas_page(
+                META_PID,
+                meta_view.0,
+                new_meta_link,
+                false,
+                false,
+                guard,
+            )?;
+
+            match res {
+                Ok(worked) => {
+                    return self.journal_meta(&mut journal, &worked).map(Ok);
+                }
+                Err(Some((_current_pointer, _rejected))) => {}
+                Err(None) => {
+                    return Err(Error::ReportableBug(
+                        "replacing the META page has failed because \
+                         the pagecache does not think it currently exists."
+                            .into(),
+                    ));
+                }
+            }
+        }
+    }
+
+    // Appends a newly installed version of the `Meta` page
+    // to the meta journal. This doesn't wait for the log to
+    // make it stable, because a batch may be holding the log
+    // back, and recovery ignores journaled versions that
+    // never made it into the log. A concurrent flush may make
+    // the new version stable before it is journaled, which
+    // recovery handles by keeping the newer of the two.
+    fn journal_meta(
+        &self,
+        journal: &mut Option<MutexGuard<'_, JournalFile>>,
+        new_meta: &PageView<'_>,
+    ) -> Result<()> {
+        if let Some(journal) = journal {
+            journal.append(new_meta.last_lsn(), new_meta.as_meta())?;
+        }
+        Ok(())
+    }
+
+    // Makes the catalog match the newest version of the
+    // `Meta` page that was recovered, from either the log or
+    // the meta journal. The log holds a newer one if we
+    // crashed after it was made stable but before it was
+    // journaled, and as other threads may have used the new
+    // catalog by then, it is kept and journaled now. The
+    // journal holds a newer one if the log lost the message
+    // that it was written in. A journal that is still empty
+    // is started off with the `Meta` page from the log.
+    fn recover_meta(
+        &self,
+        journaled: Option<JournaledMeta>,
+        guard: &Guard,
+    ) -> Result<()> {
+        let mut journal =
+            if let Some(journal) = self.meta_journal.as_ref().map(|j| j.lock())
+            {
+                journal
+            } else {
+                return Ok(());
+            };
+
+        let meta_view = self.get_meta(guard)?;
+
+        let logged_lsn = meta_view.0.last_lsn();
+        let (journaled_lsn, journaled_meta) = match journaled {
+            Some(last) => last,
+            None if self.config.read_only => return Ok(()),
+            None => return journal.append(logged_lsn, &meta_view),
+        };
+
+        if *meta_view == journaled_meta {
+            return Ok(());
+        }
+
+        if logged_lsn > journaled_lsn {
+            debug!(
+                "journaling meta page {:?} from lsn {}, which is newer \
+                 than the journaled version from lsn {}",
+                *meta_view, logged_lsn, journaled_lsn
+            );
+            if self.config.read_only {
+                return Ok(());
+            }
+            return journal.append(logged_lsn, &meta_view);
+        }
+
+        debug!(
+            "restoring meta page {:?} to journaled version {:?}",
+            *meta_view, journaled_meta
+        );
+
+        if self.config.read_only {
+            // nothing may be written to the log, and the meta
+            // page is never paged out or relocated, so it is
+            // only replaced in memory
+            let new_page = Owned::new(Page {
+                update: Some(Update::Meta(journaled_meta)),
+                cache_infos: meta_view.0.cache_infos,
+                reads: AtomicU32::new(0),
+                compress: false,
+            });
+            let old = meta_view.0;
+            if old
+                .entry
+                .compare_and_set(old.read, new_page, AcqRel, guard)
+                .is_ok()
+            {
+                unsafe {
+                    guard.defer_destroy(old.read);
+                }
+            }
+            return Ok(());
+        }
+
+        match self.cas_page(
+            META_PID,
+            meta_view.0,
+            Update::Meta(journaled_meta),
+            false,
+            false,
+            guard,
+        )? {
+            Ok(_worked) => Ok(()),
+            Err(_) => Err(Error::ReportableBug(
+                "failed to restore the META page from the meta journal \
+                 while starting up"
+                    .into(),
+            )),
+        }
+    }
+
+    fn page_out(&self, to_evict: Vec<PageId>, guard: &Guard) -> Result<()> {
+        let _measure = Measure::new(&M.page_out);
+        for pid in to_evict {
+            if pid == COUNTER_PID
+                || pid == META_PID
+                || pid == BATCH_MANIFEST_PID
+            {
+                // should not page these suckas out
+                continue;
             }
             loop {
                 if let Some(page_view) = self.inner.get(pid, guard) {
@@ -1757,9 +3560,12 @@ impl PageCache {
                         // don't page-out Freed suckas
                         break;
                     }
+                    let filter = self.leaf_filter(&page_view);
                     let new_page = Owned::new(Page {
                         update: None,
                         cache_infos: page_view.cache_infos,
+                        reads: AtomicU32::new(page_view.reads.load(Relaxed)),
+                        compress: page_view.compress,
                     });
                     debug_delay();
                     if page_view
@@ -1767,7 +3573,7 @@ impl PageCache {
                         .compare_and_set(
                             page_view.read,
                             new_page,
-                            SeqCst,
+                            AcqRel,
                             guard,
                         )
                         .is_ok()
@@ -1776,6 +3582,10 @@ impl PageCache {
                             guard.defer_destroy(page_view.read);
                         }
 
+                        if let Some(filter) = filter {
+                            self.leaf_filters.insert(pid, filter);
+                        }
+
                         break;
                     }
                     // keep looping until we page this sucka out
@@ -1785,19 +3595,79 @@ impl PageCache {
         Ok(())
     }
 
-    fn pull(&self, pid: PageId, lsn: Lsn, pointer: DiskPtr) -> Result<Update> {
-        use MessageKind::*;
+    // builds the filter that a leaf is paged out with
+    fn leaf_filter(&self, page_view: &PageView<'_>) -> Option<LeafFilter> {
+        let bits_per_key = self.config.leaf_filter_bits_per_key;
+        match (&page_view.update, page_view.cache_infos.last()) {
+            (Some(Update::Node(node)), Some(ci)) if bits_per_key > 0 => {
+                LeafFilter::new(node, ci.lsn, bits_per_key)
+            }
+            _ => None,
+        }
+    }
 
+    fn pull(&self, pid: PageId, lsn: Lsn, pointer: DiskPtr) -> Result<Update> {
         trace!("pulling pid {} lsn {} pointer {} from disk", pid, lsn, pointer);
         let _measure = Measure::new(&M.pull);
 
+        let read = self.log.read(pid, lsn, pointer).map_err(|e| {
+            debug!("failed to read page: {:?}", e);
+            e
+        })?;
+
+        self.update_from_read(pid, lsn, pointer, read)
+            .map(|(update, _compressed)| update)
+    }
+
+    // Pulls all of a page's fragments, which with the `io_uring`
+    // feature are read from the log together, along with
+    // whether the first of them was compressed.
+    fn pull_fragments(
+        &self,
+        pid: PageId,
+        cache_infos: &[CacheInfo],
+    ) -> Result<(Vec<Update>, bool)> {
+        trace!("pulling {} fragments of pid {}", cache_infos.len(), pid);
+        let _measure = Measure::new(&M.pull);
+
+        let locations: Vec<(Lsn, DiskPtr, u64)> = cache_infos
+            .iter()
+            .map(|ci| (ci.lsn, ci.pointer, ci.log_size))
+            .collect();
+
+        let reads = self.log.read_many(pid, &locations).map_err(|e| {
+            debug!("failed to read page: {:?}", e);
+            e
+        })?;
+
+        let mut base_compressed = false;
+        let mut updates = Vec::with_capacity(reads.len());
+        for (read, ci) in reads.into_iter().zip(cache_infos) {
+            let (update, compressed) =
+                self.update_from_read(pid, ci.lsn, ci.pointer, read)?;
+            if updates.is_empty() {
+                base_compressed = compressed;
+            }
+            updates.push(update);
+        }
+
+        Ok((updates, base_compressed))
+    }
+
+    fn update_from_read(
+        &self,
+        pid: PageId,
+        lsn: Lsn,
+        pointer: DiskPtr,
+        read: LogRead,
+    ) -> Result<(Update, bool)> {
         let expected_segment_number: SegmentNumber = SegmentNumber(
             u64::try_from(lsn).unwrap()
                 / u64::try_from(self.config.segment_size).unwrap(),
         );
 
-        let (header, bytes) = match self.log.read(pid, lsn, pointer) {
-            Ok(LogRead::Inline(header, buf, _len)) => {
+        let (header, bytes) = match read {
+            LogRead::Inline(header, buf, _len) => {
                 assert_eq!(
                     header.pid, pid,
                     "expected pid {} on pull of pointer {}, \
@@ -1812,7 +3682,7 @@ impl PageCache {
                 );
                 Ok((header, buf))
             }
-            Ok(LogRead::Blob(header, buf, _blob_pointer, _inline_len)) => {
+            LogRead::Blob(header, buf, _blob_pointer, _inline_len) => {
                 assert_eq!(
                     header.pid, pid,
                     "expected pid {} on pull of pointer {}, \
@@ -1828,43 +3698,13 @@ impl PageCache {
 
                 Ok((header, buf))
             }
-            Ok(other) => {
+            other => {
                 debug!("read unexpected page: {:?}", other);
                 Err(Error::Corruption { at: pointer })
             }
-            Err(e) => {
-                debug!("failed to read page: {:?}", e);
-                Err(e)
-            }
         }?;
 
-        // We create this &mut &[u8] to assist the `Serializer`
-        // implementation that incrementally consumes bytes
-        // without taking ownership of them.
-        let buf = &mut bytes.as_slice();
-
-        let update_res = {
-            let _deserialize_latency = Measure::new(&M.deserialize);
-
-            match header.kind {
-                Counter => u64::deserialize(buf).map(Update::Counter),
-                BlobMeta | InlineMeta => {
-                    Meta::deserialize(buf).map(Update::Meta)
-                }
-                BlobLink | InlineLink => {
-                    Link::deserialize(buf).map(Update::Link)
-                }
-                BlobNode | InlineNode => {
-                    Node::deserialize(buf).map(Update::Node)
-                }
-                Free => Ok(Update::Free),
-                Corrupted | Canceled | Cap | BatchManifest => {
-                    panic!("unexpected pull: {:?}", header.kind)
-                }
-            }
-        };
-
-        let update = update_res.expect("failed to deserialize data");
+        let update = deserialize_update(header.kind, &bytes);
 
         // TODO this feels racy, test it better?
         if let Update::Free = update {
@@ -1873,11 +3713,13 @@ impl PageCache {
                 pid
             )))
         } else {
-            Ok(update)
+            Ok((update, header.compressed))
         }
     }
 
     fn load_snapshot(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let _span = instrumentation::enter(&self.config, Span::RecoverPages);
+
         let next_pid_to_allocate = snapshot.pt.len() as PageId;
 
         self.next_pid_to_allocate = AtomicU64::from(next_pid_to_allocate);
@@ -1937,11 +3779,281 @@ impl PageCache {
             } else {
                 None
             };
-            let page = Page { update, cache_infos };
+            let page = Page {
+                update,
+                cache_infos,
+                reads: AtomicU32::new(0),
+                compress: false,
+            };
 
             self.inner.insert(pid, page, &guard);
         }
 
         Ok(())
     }
+
+    /// Makes a replica reject every write other than the
+    /// entries shipped to it. This happens once the database
+    /// has been opened, which may create its default `Tree`.
+    pub(crate) fn start_following(&self) {
+        if self.replica.lock().is_some() {
+            self.follower.store(true, Release);
+        }
+    }
+
+    /// Returns `true` for a replica that hasn't been
+    /// promoted yet.
+    pub(crate) fn is_follower(&self) -> bool {
+        self.follower.load(Acquire)
+    }
+
+    pub(crate) fn check_writable(&self) -> Result<()> {
+        if self.is_follower() {
+            Err(Error::Unsupported(
+                "the database is a replica, which only accepts log \
+                 entries shipped from its primary until it is promoted"
+                    .into(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the lsn in the primary's log that shipping
+    /// has to resume from, or `None` if this isn't a replica.
+    pub(crate) fn replication_lsn(&self) -> Option<Lsn> {
+        self.replica.lock().as_ref().map(|journal| journal.position().lsn)
+    }
+
+    /// Turns a replica into a regular database that accepts
+    /// writes, and stops accepting shipped entries.
+    pub(crate) fn promote(&self) -> Result<()> {
+        let mut replica = self.replica.lock();
+        if replica.is_none() {
+            return Err(Error::Unsupported(
+                "only a replica can be promoted".into(),
+            ));
+        }
+
+        // everything applied so far has to survive a crash
+        // once the journal of the position is gone
+        let _written = self.flush()?;
+        replica.as_ref().unwrap().remove()?;
+
+        *replica = None;
+        self.follower.store(false, Release);
+
+        Ok(())
+    }
+
+    /// Applies entries shipped from the primary's log in
+    /// one atomic batch, skipping any that were already
+    /// applied.
+    pub(crate) fn apply_replicated<I>(&self, entries: I) -> Result<()>
+    where
+        I: IntoIterator<Item = LogEntry>,
+    {
+        let mut replica = self.replica.lock();
+        let journal = if let Some(journal) = replica.as_mut() {
+            journal
+        } else {
+            return Err(Error::Unsupported(
+                "only a replica accepts log entries shipped from \
+                 a primary"
+                    .into(),
+            ));
+        };
+
+        let guard = pin();
+        let mut meta_journal = self.meta_journal.as_ref().map(|j| j.lock());
+        let recovery_guard = self.pin_log(&guard)?;
+
+        let mut position = journal.position();
+        let mut already_applied = position.applied_at_lsn;
+        let mut last_written = None;
+        let mut res = Ok(());
+
+        for entry in entries {
+            if entry.lsn < position.lsn {
+                continue;
+            }
+            if entry.lsn == position.lsn && already_applied > 0 {
+                already_applied -= 1;
+                continue;
+            }
+            if entry.lsn > position.lsn {
+                position = Position { lsn: entry.lsn, applied_at_lsn: 0 };
+                already_applied = 0;
+            }
+
+            match self.apply_entry(&entry, &mut meta_journal, &guard) {
+                Ok(lsn) => {
+                    last_written = Some(lsn);
+                    position.applied_at_lsn += 1;
+                }
+                Err(e) => {
+                    res = Err(e);
+                    break;
+                }
+            }
+        }
+
+        if let Some(lsn) = last_written {
+            let stable_lsn = self.log.stable_offset();
+            if let Err(e) = journal.append(lsn, position, stable_lsn) {
+                // the batch must not be recovered without its
+                // record, so nothing more may reach the log
+                self.config.set_global_error(e.clone());
+                return Err(e);
+            }
+        }
+
+        recovery_guard.seal_batch(&guard)?;
+
+        res
+    }
+
+    // Applies a single shipped entry, returning the lsn that
+    // it was written to our own log at.
+    fn apply_entry<'g>(
+        &'g self,
+        entry: &LogEntry,
+        meta_journal: &mut Option<MutexGuard<'_, JournalFile>>,
+        guard: &'g Guard,
+    ) -> Result<Lsn> {
+        let pid = entry.pid;
+
+        if entry.kind == LogEntryKind::Free {
+            return self.apply_free(pid, guard);
+        }
+
+        let mut new = match replicated_update(entry)? {
+            Update::Link(link) => return self.apply_link(pid, link, guard),
+            Update::Frag(frag) => return self.apply_frag(pid, frag, guard),
+            other => other,
+        };
+        let counter = if let Update::Counter(counter) = new {
+            Some(counter)
+        } else {
+            None
+        };
+
+        let mut old = self.replicated_page(pid, guard);
+        let view = loop {
+            match self.cas_page(pid, old, new, false, old.compress, guard)? {
+                Ok(view) => break view,
+                Err(Some((current, rejected))) => {
+                    old = current;
+                    new = rejected;
+                }
+                Err(None) => return Err(missing_page(pid)),
+            }
+        };
+
+        if pid == META_PID {
+            self.journal_meta(meta_journal, &view)?;
+        }
+
+        if let Some(persisted) = counter {
+            // the replica generates ids from where the primary
+            // would after recovering, in case it is promoted
+            let _mu = self.idgen_persist_mu.lock();
+            self.idgen_persists.store(persisted, Release);
+            self.idgen.store(
+                persisted + 2 * self.config.idgen_persist_interval,
+                Release,
+            );
+        }
+
+        Ok(view.last_lsn())
+    }
+
+    fn apply_link<'g>(
+        &'g self,
+        pid: PageId,
+        mut link: Link,
+        guard: &'g Guard,
+    ) -> Result<Lsn> {
+        loop {
+            let old = self.get(pid, guard)?.ok_or_else(|| missing_page(pid))?;
+            match self.link_inner(pid, old.0, link, old.0.compress, guard)? {
+                Ok(view) => return Ok(view.last_lsn()),
+                Err(Some((_, rejected))) => link = rejected,
+                Err(None) => return Err(missing_page(pid)),
+            }
+        }
+    }
+
+    fn apply_frag<'g>(
+        &'g self,
+        pid: PageId,
+        mut frag: IVec,
+        guard: &'g Guard,
+    ) -> Result<Lsn> {
+        loop {
+            let old = self
+                .get_custom(pid, guard)?
+                .ok_or_else(|| missing_page(pid))?;
+            match self.link_custom_inner(pid, old, frag, guard)? {
+                Ok(view) => return Ok(view.last_lsn()),
+                Err(Some((_, rejected))) => frag = rejected,
+                Err(None) => return Err(missing_page(pid)),
+            }
+        }
+    }
+
+    fn apply_free(&self, pid: PageId, guard: &Guard) -> Result<Lsn> {
+        let mut old =
+            self.inner.get(pid, guard).ok_or_else(|| missing_page(pid))?;
+        loop {
+            match self.cas_page(pid, old, Update::Free, false, false, guard)? {
+                Ok(view) => {
+                    // nothing else allocates pages on a replica,
+                    // so unlike `free` this doesn't have to wait
+                    // for readers of the page to finish first
+                    self.free.lock().push(pid);
+                    return Ok(view.last_lsn());
+                }
+                Err(Some((current, _))) => old = current,
+                Err(None) => return Err(missing_page(pid)),
+            }
+        }
+    }
+
+    // Returns the page that a shipped replacement goes into,
+    // which the primary may have only just allocated.
+    fn replicated_page<'g>(
+        &self,
+        pid: PageId,
+        guard: &'g Guard,
+    ) -> PageView<'g> {
+        if let Some(view) = self.inner.get(pid, guard) {
+            if view.is_free() {
+                let mut free = self.free.lock();
+                *free =
+                    free.drain().filter(|free_pid| *free_pid != pid).collect();
+            }
+            return view;
+        }
+
+        if self.next_pid_to_allocate.load(Acquire) <= pid {
+            self.next_pid_to_allocate.store(pid + 1, Release);
+        }
+
+        let page = Page {
+            update: None,
+            cache_infos: StackVec::default(),
+            reads: AtomicU32::new(0),
+            compress: false,
+        };
+        self.inner.insert(pid, page, guard)
+    }
+}
+
+fn missing_page(pid: PageId) -> Error {
+    Error::ReportableBug(format!(
+        "a shipped log entry refers to pid {}, which the replica \
+         doesn't have",
+        pid
+    ))
 }
@@ -1,18 +1,36 @@
-//! A simple wait-free, grow-only pagetable, assumes a dense keyspace.
+//! A simple lock-free, grow-only pagetable, assumes a dense keyspace.
+//!
+//! The keyspace stays dense because freed pids are reused
+//! before new ones are allocated, so dropping or clearing
+//! trees leaves no holes that later allocations skip over.
+//! Entries for free pids are never pruned, even when a whole
+//! `Node2` is free: each free page still records where its
+//! free marker was logged, which the segment cleaner needs
+//! in order to rewrite that marker before reclaiming its
+//! segment, and which the next snapshot records.
+//!
+//! The table starts out with a single level of `Node1`s above
+//! the `Node2`s that hold the pages, and adds another level
+//! above its root whenever a pid is inserted that the current
+//! levels can't reach, so every `u64` can be used as a pid.
 #![allow(unsafe_code)]
 
 use std::{
     alloc::{alloc_zeroed, Layout},
     convert::TryFrom,
     mem::{align_of, size_of},
-    sync::atomic::Ordering::{Acquire, Relaxed, Release},
+    ptr,
+    sync::atomic::{
+        AtomicPtr,
+        Ordering::{AcqRel, Acquire, Relaxed, Release},
+    },
 };
 
-use crossbeam_epoch::{pin, Atomic, Guard, Owned, Shared};
+use crossbeam_epoch::{pin, Atomic, Guard, Owned};
 
 use crate::{
     debug_delay,
-    pagecache::{constants::MAX_PID_BITS, Page, PageView},
+    pagecache::{Page, PageView},
     Measure, M,
 };
 
@@ -20,15 +38,22 @@ use crate::{
 #[doc(hidden)]
 pub const PAGETABLE_NODE_SZ: usize = size_of::<Node1>();
 
+const NODE1_FAN_FACTOR: usize = 10;
 const NODE2_FAN_FACTOR: usize = 18;
-const NODE1_FAN_OUT: usize = 1 << (MAX_PID_BITS - NODE2_FAN_FACTOR);
+const NODE1_FAN_OUT: usize = 1 << NODE1_FAN_FACTOR;
 const NODE2_FAN_OUT: usize = 1 << NODE2_FAN_FACTOR;
-const FAN_MASK: u64 = (NODE2_FAN_OUT - 1) as u64;
+const NODE1_FAN_MASK: u64 = (NODE1_FAN_OUT - 1) as u64;
+const NODE2_FAN_MASK: u64 = (NODE2_FAN_OUT - 1) as u64;
 
 pub type PageId = u64;
 
+/// An interior node, whose children are `Node2`s if its
+/// height is 1, and `Node1`s of one less height otherwise.
+/// Interior nodes are only freed when the whole table is
+/// dropped, so they are reached through plain pointers.
 struct Node1 {
-    children: [Atomic<Node2>; NODE1_FAN_OUT],
+    height: usize,
+    children: [AtomicPtr<()>; NODE1_FAN_OUT],
 }
 
 struct Node2 {
@@ -36,7 +61,7 @@ struct Node2 {
 }
 
 impl Node1 {
-    fn new() -> Owned<Self> {
+    fn new(height: usize) -> *mut Self {
         let size = size_of::<Self>();
         let align = align_of::<Self>();
 
@@ -46,13 +71,86 @@ impl Node1 {
             #[allow(clippy::cast_ptr_alignment)]
             let ptr = alloc_zeroed(layout) as *mut Self;
 
-            Owned::from_raw(ptr)
+            (*ptr).height = height;
+
+            ptr
+        }
+    }
+
+    /// The number of low bits of a pid that the subtree below
+    /// a `Node1` of this height spans.
+    fn span_bits(height: usize) -> usize {
+        NODE2_FAN_FACTOR + height * NODE1_FAN_FACTOR
+    }
+
+    fn covers(&self, pid: PageId) -> bool {
+        let bits = Self::span_bits(self.height);
+        bits >= 64 || pid >> bits == 0
+    }
+
+    fn child_idx(&self, pid: PageId) -> usize {
+        let shift = Self::span_bits(self.height - 1);
+        safe_usize((pid >> shift) & NODE1_FAN_MASK)
+    }
+
+    /// Returns the child at `idx`, installing a new one if it
+    /// is missing.
+    fn child(&self, idx: usize) -> *mut () {
+        debug_delay();
+        let slot = &self.children[idx];
+        let child = slot.load(Acquire);
+        if !child.is_null() {
+            return child;
+        }
+
+        let next_child = if self.height == 1 {
+            Node2::new() as *mut ()
+        } else {
+            Node1::new(self.height - 1) as *mut ()
+        };
+
+        debug_delay();
+        match slot.compare_exchange(
+            ptr::null_mut(),
+            next_child,
+            AcqRel,
+            Acquire,
+        ) {
+            Ok(_) => next_child,
+            Err(current) => {
+                unsafe { self.drop_child(next_child) };
+                current
+            }
+        }
+    }
+
+    unsafe fn drop_child(&self, child: *mut ()) {
+        if self.height == 1 {
+            drop(Box::from_raw(child as *mut Node2));
+        } else {
+            drop(Box::from_raw(child as *mut Node1));
+        }
+    }
+
+    fn rss(&self) -> u64 {
+        let mut rss = size_of::<Self>() as u64;
+        for child in &self.children[..] {
+            let child_ptr = child.load(Acquire);
+            if child_ptr.is_null() {
+                continue;
+            }
+            rss += if self.height == 1 {
+                size_of::<Node2>() as u64
+            } else {
+                unsafe { (*(child_ptr as *const Node1)).rss() }
+            };
         }
+        rss
     }
 }
 
 impl Node2 {
-    fn new() -> Owned<Node2> {
+    fn new() -> *mut Node2 {
         let size = size_of::<Self>();
         let align = align_of::<Self>();
 
@@ -62,14 +160,23 @@ impl Node2 {
             #[allow(clippy::cast_ptr_alignment)]
             let ptr = alloc_zeroed(layout) as *mut Self;
 
-            Owned::from_raw(ptr)
+            ptr
         }
     }
 }
 
 impl Drop for Node1 {
     fn drop(&mut self) {
-        drop_iter(self.children.iter());
+        for child in &self.children[..] {
+            let child_ptr = child.load(Relaxed);
+            if child_ptr.is_null() {
+                // unlike the pages of a `Node2`, the children
+                // of a new root are sparse until the pids in
+                // between are allocated
+                continue;
+            }
+            unsafe { self.drop_child(child_ptr) };
+        }
     }
 }
 
@@ -96,13 +203,12 @@ fn drop_iter<T>(iter: core::slice::Iter<'_, Atomic<T>>) {
 
 /// A simple lock-free radix tree.
 pub struct PageTable {
-    head: Atomic<Node1>,
+    head: AtomicPtr<Node1>,
 }
 
 impl Default for PageTable {
     fn default() -> Self {
-        let head = Node1::new();
-        Self { head: Atomic::from(head) }
+        Self { head: AtomicPtr::new(Node1::new(1)) }
     }
 }
 
@@ -120,7 +226,7 @@ impl PageTable {
         guard: &'g Guard,
     ) -> PageView<'g> {
         debug_delay();
-        let tip = self.traverse(pid, guard);
+        let tip = self.traverse(pid, self.grow_to(pid));
 
         let shared = Owned::new(item).into_shared(guard);
         let old = tip.swap(shared, Release, guard);
@@ -137,7 +243,11 @@ impl PageTable {
     ) -> Option<PageView<'g>> {
         let _measure = Measure::new(&M.get_pagetable);
         debug_delay();
-        let tip = self.traverse(pid, guard);
+        let head = unsafe { &*self.head.load(Acquire) };
+        if !head.covers(pid) {
+            return None;
+        }
+        let tip = self.traverse(pid, head);
 
         debug_delay();
         let res = tip.load(Acquire, guard);
@@ -150,61 +260,58 @@ impl PageTable {
         }
     }
 
-    fn traverse<'g>(&self, k: PageId, guard: &'g Guard) -> &'g Atomic<Page> {
-        let (l1k, l2k) = split_fanout(k);
-
-        debug_delay();
-        let head = self.head.load(Acquire, guard);
-
+    /// The bytes allocated for the table's own nodes, not
+    /// counting the pages they point to.
+    pub fn rss(&self, _guard: &Guard) -> u64 {
         debug_delay();
-        let l1 = unsafe { &head.deref().children };
+        unsafe { (*self.head.load(Acquire)).rss() }
+    }
 
-        debug_delay();
-        let mut l2_ptr = l1[l1k].load(Acquire, guard);
+    /// Returns the root, after adding levels above it until
+    /// it covers `pid`. The old root becomes the first child
+    /// of each new one, so concurrent traversals that started
+    /// from it still find the same pages.
+    fn grow_to<'g>(&self, pid: PageId) -> &'g Node1 {
+        loop {
+            debug_delay();
+            let head_ptr = self.head.load(Acquire);
+            let head = unsafe { &*head_ptr };
+            if head.covers(pid) {
+                return head;
+            }
 
-        if l2_ptr.is_null() {
-            let next_child = Node2::new();
+            let next_head = Node1::new(head.height + 1);
+            unsafe {
+                (*next_head).children[0].store(head_ptr as *mut (), Relaxed);
+            }
 
             debug_delay();
-            let ret = l1[l1k].compare_and_set(
-                Shared::null(),
-                next_child,
-                Release,
-                guard,
-            );
-
-            l2_ptr = match ret {
-                Ok(next_child) => next_child,
-                Err(returned) => {
-                    drop(returned.new);
-                    returned.current
+            if self
+                .head
+                .compare_exchange(head_ptr, next_head, AcqRel, Acquire)
+                .is_err()
+            {
+                unsafe {
+                    (*next_head).children[0].store(ptr::null_mut(), Relaxed);
+                    drop(Box::from_raw(next_head));
                 }
-            };
+            }
         }
+    }
 
-        debug_delay();
-        let l2 = unsafe { &l2_ptr.deref().children };
+    fn traverse<'g>(&self, k: PageId, head: &'g Node1) -> &'g Atomic<Page> {
+        let mut node = head;
+        loop {
+            let child = node.child(node.child_idx(k));
 
-        &l2[l2k]
-    }
-}
+            if node.height == 1 {
+                let l2 = unsafe { &(*(child as *const Node2)).children };
+                return &l2[safe_usize(k & NODE2_FAN_MASK)];
+            }
 
-#[inline]
-fn split_fanout(id: PageId) -> (usize, usize) {
-    // right shift 32 on 32-bit pointer systems panics
-    #[cfg(target_pointer_width = "64")]
-    assert!(
-        id <= 1 << MAX_PID_BITS,
-        "trying to access key of {}, which is \
-         higher than 2 ^ {}",
-        id,
-        MAX_PID_BITS,
-    );
-
-    let left = id >> NODE2_FAN_FACTOR;
-    let right = id & FAN_MASK;
-
-    (safe_usize(left), safe_usize(right))
+            node = unsafe { &*(child as *const Node1) };
+        }
+    }
 }
 
 #[inline]
@@ -214,22 +321,36 @@ fn safe_usize(value: PageId) -> usize {
 
 impl Drop for PageTable {
     fn drop(&mut self) {
-        let guard = pin();
-        let head = self.head.load(Relaxed, &guard);
         unsafe {
-            drop(head.into_owned());
+            drop(Box::from_raw(self.head.load(Relaxed)));
         }
     }
 }
 
 #[test]
-fn test_split_fanout() {
-    assert_eq!(
-        split_fanout(0b11_1111_1111_1111_1111),
-        (0, 0b11_1111_1111_1111_1111)
-    );
-    assert_eq!(
-        split_fanout(0b111_1111_1111_1111_1111),
-        (0b1, 0b11_1111_1111_1111_1111)
-    );
+fn test_growth() {
+    let table = PageTable::default();
+    let guard = pin();
+
+    // pids past the first of their `Node2` would be leaked
+    // when the table is dropped, as it is assumed to be dense
+    let pids = [0, 1 << 28, 1 << 40, u64::max_value() & !NODE2_FAN_MASK, 1];
+    for (i, pid) in pids.iter().enumerate() {
+        let page = Page {
+            update: Some(crate::pagecache::Update::Counter(i as u64)),
+            cache_infos: Default::default(),
+            reads: Default::default(),
+            compress: false,
+        };
+        table.insert(*pid, page, &guard);
+    }
+
+    for (i, pid) in pids.iter().enumerate() {
+        let view = table.get(*pid, &guard).unwrap();
+        assert_eq!(
+            view.update,
+            Some(crate::pagecache::Update::Counter(i as u64))
+        );
+    }
+    assert!(table.get(2, &guard).is_none());
 }
@@ -99,3 +99,45 @@ pub(crate) fn pwrite_all(
     }
     Ok(())
 }
+
+pub(crate) fn prefetch(
+    _file: &File,
+    _offset: LogOffset,
+    _len: u64,
+) -> io::Result<()> {
+    Ok(())
+}
+
+pub(crate) fn sync_range(
+    file: &File,
+    _offset: LogOffset,
+    _len: u64,
+) -> io::Result<()> {
+    file.sync_all()
+}
+
+pub(crate) fn punch_hole(
+    _file: &File,
+    _offset: LogOffset,
+    _len: u64,
+) -> io::Result<bool> {
+    Ok(false)
+}
+
+pub(crate) fn allocated_len(file: &File) -> io::Result<u64> {
+    Ok(file.metadata()?.len())
+}
+
+// the standard library can't lock files, so a database
+// that is opened by two processes at once may be corrupted
+pub(crate) fn lock(
+    _file: &File,
+    _shared: bool,
+    _block: bool,
+) -> io::Result<()> {
+    Ok(())
+}
+
+pub(crate) fn is_lock_contended(_err: &io::Error) -> bool {
+    false
+}
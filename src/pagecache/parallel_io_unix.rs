@@ -41,3 +41,177 @@ pub(crate) fn pwrite_all(
 ) -> io::Result<()> {
     file.write_all_at(buf, offset)
 }
+
+/// Asks the kernel to start reading the given range into
+/// its page cache in the background.
+#[cfg(target_os = "linux")]
+pub(crate) fn prefetch(
+    file: &File,
+    offset: LogOffset,
+    len: u64,
+) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let (start, count) =
+        match (libc::off_t::try_from(offset), libc::off_t::try_from(len)) {
+            (Ok(start), Ok(count)) => (start, count),
+            // past the 2gb that a 32-bit `off_t` can address
+            _ => return Ok(()),
+        };
+
+    let ret = unsafe {
+        libc::posix_fadvise(
+            file.as_raw_fd(),
+            start,
+            count,
+            libc::POSIX_FADV_WILLNEED,
+        )
+    };
+    if ret == 0 { Ok(()) } else { Err(io::Error::from_raw_os_error(ret)) }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn prefetch(
+    _file: &File,
+    _offset: LogOffset,
+    _len: u64,
+) -> io::Result<()> {
+    Ok(())
+}
+
+/// Makes the writes to the given range of the file durable.
+/// On linux this skips the metadata that `File::sync_all`
+/// also syncs, unless the kernel can't sync ranges.
+#[cfg(target_os = "linux")]
+pub(crate) fn sync_range(
+    file: &File,
+    offset: LogOffset,
+    len: u64,
+) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let ret = unsafe {
+        libc::sync_file_range(
+            file.as_raw_fd(),
+            i64::try_from(offset).unwrap(),
+            i64::try_from(len).unwrap(),
+            libc::SYNC_FILE_RANGE_WAIT_BEFORE
+                | libc::SYNC_FILE_RANGE_WRITE
+                | libc::SYNC_FILE_RANGE_WAIT_AFTER,
+        )
+    };
+    if ret < 0 {
+        let err = io::Error::last_os_error();
+        if let Some(libc::ENOSYS) = err.raw_os_error() {
+            file.sync_all()
+        } else {
+            Err(err)
+        }
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn sync_range(
+    file: &File,
+    _offset: LogOffset,
+    _len: u64,
+) -> io::Result<()> {
+    file.sync_all()
+}
+
+/// Returns the disk space of the given range of the file to
+/// the filesystem without changing the file's length, after
+/// which the range reads as zeroes. Returns `false` if the
+/// filesystem can't do that.
+#[cfg(target_os = "linux")]
+pub(crate) fn punch_hole(
+    file: &File,
+    offset: LogOffset,
+    len: u64,
+) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    let (start, count) =
+        match (libc::off_t::try_from(offset), libc::off_t::try_from(len)) {
+            (Ok(start), Ok(count)) => (start, count),
+            // past the 2gb that a 32-bit `off_t` can address
+            _ => return Ok(false),
+        };
+
+    let ret = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            start,
+            count,
+        )
+    };
+
+    if ret < 0 {
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::EOPNOTSUPP) | Some(libc::ENOSYS) => Ok(false),
+            _ => Err(err),
+        }
+    } else {
+        Ok(true)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn punch_hole(
+    _file: &File,
+    _offset: LogOffset,
+    _len: u64,
+) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// Returns the disk space that the file takes up, which is
+/// less than its length if holes were punched in it.
+pub(crate) fn allocated_len(file: &File) -> io::Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = file.metadata()?;
+    Ok(std::cmp::min(metadata.len(), metadata.blocks() * 512))
+}
+
+/// Takes an advisory lock on the file, which is shared if
+/// `shared` is set, and exclusive otherwise. Returns an
+/// error of the kind of `fs2::lock_contended_error` if
+/// another process holds a conflicting lock and `block` is
+/// not set.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub(crate) fn lock(file: &File, shared: bool, block: bool) -> io::Result<()> {
+    use fs2::FileExt;
+
+    match (shared, block) {
+        (true, true) => FileExt::lock_shared(file),
+        (true, false) => FileExt::try_lock_shared(file),
+        (false, true) => FileExt::lock_exclusive(file),
+        (false, false) => FileExt::try_lock_exclusive(file),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub(crate) fn lock(
+    _file: &File,
+    _shared: bool,
+    _block: bool,
+) -> io::Result<()> {
+    Ok(())
+}
+
+/// Whether `err` was returned by `lock` because another
+/// process holds a conflicting lock.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub(crate) fn is_lock_contended(err: &io::Error) -> bool {
+    err.kind() == fs2::lock_contended_error().kind()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub(crate) fn is_lock_contended(_err: &io::Error) -> bool {
+    false
+}
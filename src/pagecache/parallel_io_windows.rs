@@ -94,3 +94,47 @@ pub(crate) fn pwrite_all(
     let mut f = file.try_clone()?;
     seek_write_all(&mut f, buf, offset)
 }
+
+pub(crate) fn prefetch(
+    _file: &File,
+    _offset: LogOffset,
+    _len: u64,
+) -> io::Result<()> {
+    Ok(())
+}
+
+pub(crate) fn sync_range(
+    file: &File,
+    _offset: LogOffset,
+    _len: u64,
+) -> io::Result<()> {
+    // `FlushFileBuffers` can't be limited to a range
+    file.sync_all()
+}
+
+pub(crate) fn punch_hole(
+    _file: &File,
+    _offset: LogOffset,
+    _len: u64,
+) -> io::Result<bool> {
+    Ok(false)
+}
+
+pub(crate) fn allocated_len(file: &File) -> io::Result<u64> {
+    Ok(file.metadata()?.len())
+}
+
+pub(crate) fn lock(file: &File, shared: bool, block: bool) -> io::Result<()> {
+    use fs2::FileExt;
+
+    match (shared, block) {
+        (true, true) => FileExt::lock_shared(file),
+        (true, false) => FileExt::try_lock_shared(file),
+        (false, true) => FileExt::lock_exclusive(file),
+        (false, false) => FileExt::try_lock_exclusive(file),
+    }
+}
+
+pub(crate) fn is_lock_contended(err: &io::Error) -> bool {
+    err.kind() == fs2::lock_contended_error().kind()
+}
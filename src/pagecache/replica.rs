@@ -0,0 +1,284 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Seek},
+    path::{Path, PathBuf},
+};
+
+use crate::pagecache::*;
+use crate::*;
+
+// each record is a crc32 of the rest of the record, the lsn
+// of the last message that a batch of shipped entries was
+// written to our own log at, and the position in the
+// primary's log that the batch brought the replica up to
+const RECORD_LEN: usize = 28;
+
+// once this many records have been appended, the journal is
+// rewritten without the ones that recovery no longer needs
+const COMPACT_AFTER_RECORDS: usize = 1024;
+
+const FILE_NAME: &str = "replica";
+
+/// How far a replica has applied its primary's log: every
+/// entry before `lsn`, and the first `applied_at_lsn` of the
+/// entries at it, of which there are several when a batch of
+/// pages was freed together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Position {
+    pub(crate) lsn: Lsn,
+    pub(crate) applied_at_lsn: u64,
+}
+
+/// The journal of a replica's position in its primary's
+/// log. Each batch of shipped entries is written to our own
+/// log between a batch manifest and its seal, and its record
+/// is appended here before the seal, so recovery finds the
+/// position of the last batch that it recovered by taking
+/// the last record whose `Lsn` was recovered.
+#[derive(Debug)]
+pub(crate) struct ReplicaJournal {
+    config: RunningConfig,
+    file: File,
+    // records that recovery may still need, oldest first
+    records: Vec<(Lsn, Position)>,
+    position: Position,
+}
+
+impl ReplicaJournal {
+    /// Opens the journal if the database is a replica,
+    /// creating it if the database is being created as one,
+    /// and finds the position that recovery brought the
+    /// replica up to. Records of batches that were never
+    /// made stable in the log, and any torn record at the
+    /// end, are cut off.
+    pub(crate) fn open(
+        config: &RunningConfig,
+        recovered_lsn: Lsn,
+        was_recovered: bool,
+    ) -> Result<Option<Self>> {
+        if config.in_memory {
+            return Ok(None);
+        }
+
+        let path = journal_path(config);
+
+        if !path.exists() {
+            if !config.replica {
+                return Ok(None);
+            }
+            if was_recovered {
+                return Err(Error::Unsupported(
+                    "an existing database can't be turned into a \
+                     replica, which has to start out empty"
+                        .into(),
+                ));
+            }
+        }
+
+        let mut options = OpenOptions::new();
+        options.read(true);
+        if !config.read_only {
+            options.write(true).create(true);
+        }
+
+        let mut file = options.open(&path)?;
+
+        let mut buf = vec![];
+        let _ = file.read_to_end(&mut buf)?;
+
+        let mut records = vec![];
+        let mut remaining = &buf[..];
+
+        while remaining.len() >= RECORD_LEN {
+            let crc_expected = arr_to_u32(&remaining[..4]);
+            if crc32(&remaining[4..RECORD_LEN]) != crc_expected {
+                warn!("replica journal record has a bad checksum");
+                break;
+            }
+
+            let lsn = arr_to_lsn(&remaining[4..12]);
+            if lsn > recovered_lsn {
+                debug!(
+                    "ignoring replica journal records from lsn {} on, \
+                     beyond the recovered lsn {}",
+                    lsn, recovered_lsn
+                );
+                break;
+            }
+
+            let position = Position {
+                lsn: arr_to_lsn(&remaining[12..20]),
+                applied_at_lsn: u64::from_le_bytes(
+                    remaining[20..RECORD_LEN].try_into().unwrap(),
+                ),
+            };
+
+            records.push((lsn, position));
+            remaining = &remaining[RECORD_LEN..];
+        }
+
+        let valid_len = records.len() * RECORD_LEN;
+        if valid_len != buf.len() && !config.read_only {
+            debug!(
+                "truncating replica journal from {} to {} bytes",
+                buf.len(),
+                valid_len
+            );
+            file.set_len(valid_len as u64)?;
+        }
+
+        let position = records
+            .last()
+            .map_or(Position { lsn: 0, applied_at_lsn: 0 }, |r| r.1);
+
+        Ok(Some(ReplicaJournal {
+            config: config.clone(),
+            file,
+            records,
+            position,
+        }))
+    }
+
+    /// Returns the position that the replica has applied
+    /// its primary's log up to.
+    pub(crate) fn position(&self) -> Position {
+        self.position
+    }
+
+    /// Removes the journal, after which the database is
+    /// opened as a regular one.
+    pub(crate) fn remove(&self) -> Result<()> {
+        fs::remove_file(journal_path(&self.config))?;
+        Ok(())
+    }
+
+    /// Copies the journal into the directory `dir`, for a
+    /// backup of the database.
+    pub(crate) fn copy_to(&self, dir: &Path) -> Result<()> {
+        let _copied =
+            fs::copy(journal_path(&self.config), dir.join(FILE_NAME))?;
+        Ok(())
+    }
+
+    /// Appends the position reached by a batch whose last
+    /// message was written to our log at `lsn`, syncing it
+    /// unless the database is temporary.
+    pub(crate) fn append(
+        &mut self,
+        lsn: Lsn,
+        position: Position,
+        stable_lsn: Lsn,
+    ) -> Result<()> {
+        if self.records.len() >= COMPACT_AFTER_RECORDS {
+            // recovery never goes back past the stable offset,
+            // so it needs at most the last record below it
+            let keep_from = self
+                .records
+                .iter()
+                .rposition(|(record_lsn, _)| *record_lsn <= stable_lsn)
+                .unwrap_or(0);
+            let mut records = self.records.split_off(keep_from);
+            records.push((lsn, position));
+            self.compact(records)?;
+        } else {
+            io_fail!(self.config, "replica journal append");
+            self.file.seek(io::SeekFrom::End(0))?;
+            self.file.write_all(&record(lsn, position))?;
+            if !self.config.is_temporary() {
+                self.file.sync_all()?;
+            }
+            self.records.push((lsn, position));
+        }
+
+        self.position = position;
+
+        Ok(())
+    }
+
+    // Replaces the journal with one holding only `records`,
+    // written to the side and then renamed into place.
+    fn compact(&mut self, records: Vec<(Lsn, Position)>) -> Result<()> {
+        let path = journal_path(&self.config);
+        let mut tmp_path = path.clone();
+        tmp_path.set_extension("generating");
+
+        trace!("compacting replica journal of {} records", self.records.len());
+
+        let mut tmp = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+
+        io_fail!(self.config, "replica journal compact");
+        for (lsn, position) in &records {
+            tmp.write_all(&record(*lsn, *position))?;
+        }
+        if !self.config.is_temporary() {
+            tmp.sync_all()?;
+        }
+
+        fs::rename(&tmp_path, &path)?;
+
+        self.file = tmp;
+        self.records = records;
+
+        Ok(())
+    }
+}
+
+// Deserializes the update carried by a shipped entry, which
+// unlike one read back from our own log may be malformed.
+pub(crate) fn replicated_update(entry: &LogEntry) -> Result<Update> {
+    use MessageKind::*;
+
+    let malformed = || {
+        Error::Unsupported(format!(
+            "the shipped log entry for pid {} at lsn {} is malformed",
+            entry.pid, entry.lsn
+        ))
+    };
+
+    let (kind, bytes) = entry.payload.split_first().ok_or_else(malformed)?;
+    let buf = &mut &*bytes;
+
+    let update_res = match MessageKind::from(*kind) {
+        Counter => u64::deserialize(buf).map(Update::Counter),
+        BlobMeta | InlineMeta => Meta::deserialize(buf).map(Update::Meta),
+        BlobLink | InlineLink => Link::deserialize(buf).map(Update::Link),
+        BlobNode | InlineNode => Node::deserialize(buf).map(Update::Node),
+        BlobValue | InlineValue => IVec::deserialize(buf).map(Update::Value),
+        BlobCustom | InlineCustom => IVec::deserialize(buf).map(Update::Custom),
+        BlobFrag | InlineFrag => IVec::deserialize(buf).map(Update::Frag),
+        _ => return Err(malformed()),
+    };
+    let update = update_res.map_err(|_| malformed())?;
+
+    match (&update, entry.kind) {
+        (Update::Link(_), LogEntryKind::Link)
+        | (Update::Frag(_), LogEntryKind::Link) => Ok(update),
+        (Update::Link(_), _)
+        | (Update::Frag(_), _)
+        | (_, LogEntryKind::Link) => Err(malformed()),
+        _ => Ok(update),
+    }
+}
+
+fn journal_path(config: &RunningConfig) -> PathBuf {
+    let mut path = config.get_path();
+    path.push(FILE_NAME);
+    path
+}
+
+fn record(lsn: Lsn, position: Position) -> [u8; RECORD_LEN] {
+    let mut buf = [0; RECORD_LEN];
+    buf[4..12].copy_from_slice(&lsn_to_arr(lsn));
+    buf[12..20].copy_from_slice(&lsn_to_arr(position.lsn));
+    buf[20..].copy_from_slice(&u64_to_arr(position.applied_at_lsn));
+
+    let crc = u32_to_arr(crc32(&buf[4..]));
+    buf[..4].copy_from_slice(&crc);
+
+    buf
+}
@@ -30,6 +30,14 @@ impl<'a> Drop for Reservation<'a> {
 impl<'a> Reservation<'a> {
     /// Cancel the reservation, placing a failed flush on disk, returning
     /// the (cancelled) log sequence number and file offset.
+    ///
+    /// The reserved space keeps its length and checksum but is
+    /// marked as `Canceled`, so readers get `LogRead::Canceled`
+    /// and recovery steps over it to the next message instead
+    /// of treating the log as torn there. This is what callers
+    /// should do when the CAS that the reservation was made for
+    /// fails, and it is also done when a reservation is dropped
+    /// without being completed.
     pub fn abort(mut self) -> Result<(Lsn, DiskPtr)> {
         if self.pointer.is_blob() && !self.is_blob_rewrite {
             // we don't want to remove this blob if something
@@ -100,6 +100,11 @@ pub(crate) struct SegmentAccountant {
     segment_cleaner: SegmentCleaner,
     ordering: BTreeMap<Lsn, LogOffset>,
     async_truncations: BTreeMap<LogOffset, OneShot<Result<()>>>,
+    // while backups are being copied, segments and blobs are
+    // not freed, so that everything they copy stays intact
+    backups: usize,
+    deferred_frees: Vec<LogOffset>,
+    deferred_blob_removals: Vec<BlobPointer>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -256,27 +261,22 @@ impl Segment {
 
     /// Transitions a segment to being in the `Inactive` state.
     /// Returns the set of page replacements that happened
-    /// while this Segment was Active
+    /// while this Segment was Active, and the blobs whose
+    /// removal was deferred until now.
     fn active_to_inactive(
         &mut self,
         lsn: Lsn,
-        config: &Config,
-    ) -> Result<FastSet8<Lsn>> {
+    ) -> (FastSet8<Lsn>, FastSet8<BlobPointer>) {
         trace!("setting Segment with lsn {:?} to Inactive", self.lsn());
 
         let (inactive, ret) = if let Segment::Active(active) = self {
             assert!(lsn >= active.lsn);
 
             // now we can push any deferred blob removals to the removed set
-            for ptr in &active.deferred_rm_blob {
-                trace!(
-                    "removing blob {} while transitioning \
-                 segment lsn {:?} to Inactive",
-                    ptr,
-                    active.lsn,
-                );
-                remove_blob(*ptr, config)?;
-            }
+            let rm_blob = mem::replace(
+                &mut active.deferred_rm_blob,
+                Default::default(),
+            );
 
             let inactive = Segment::Inactive(Inactive {
                 lsn: active.lsn,
@@ -295,13 +295,13 @@ impl Segment {
                 Default::default(),
             );
 
-            (inactive, can_free)
+            (inactive, (can_free, rm_blob))
         } else {
             panic!("called active_to_inactive on {:?}", self);
         };
 
         *self = inactive;
-        Ok(ret)
+        ret
     }
 
     fn inactive_to_draining(&mut self, lsn: Lsn) -> BTreeSet<PageId> {
@@ -452,17 +452,15 @@ impl Segment {
         }
     }
 
-    fn remove_blob(
-        &mut self,
-        blob_ptr: BlobPointer,
-        config: &Config,
-    ) -> Result<()> {
+    /// Returns `true` if the blob can be removed right away.
+    fn remove_blob(&mut self, blob_ptr: BlobPointer) -> bool {
         match self {
             Segment::Active(active) => {
                 // we have received a removal before
                 // transferring this segment to Inactive, so
                 // we defer this pid's removal until the transfer.
                 active.deferred_rm_blob.insert(blob_ptr);
+                false
             }
             Segment::Inactive(_) | Segment::Draining(_) => {
                 trace!(
@@ -471,12 +469,10 @@ impl Segment {
                      or Draining.",
                     blob_ptr,
                 );
-                remove_blob(blob_ptr, config)?;
+                true
             }
             Segment::Free(_) => panic!("remove_blob called on a Free Segment"),
         }
-
-        Ok(())
     }
 
     fn can_free(&self) -> bool {
@@ -507,6 +503,7 @@ impl SegmentAccountant {
         segment_cleaner: SegmentCleaner,
     ) -> Result<Self> {
         let _measure = Measure::new(&M.start_segment_accountant);
+        let _span = instrumentation::enter(&config, Span::RecoverSegments);
         let mut ret = Self {
             config,
             segments: vec![],
@@ -516,6 +513,9 @@ impl SegmentAccountant {
             segment_cleaner,
             ordering: BTreeMap::default(),
             async_truncations: BTreeMap::default(),
+            backups: 0,
+            deferred_frees: vec![],
+            deferred_blob_removals: vec![],
         };
 
         ret.initialize_from_snapshot(snapshot)?;
@@ -525,7 +525,7 @@ impl SegmentAccountant {
 
     fn initial_segments(&self, snapshot: &Snapshot) -> Result<Vec<Segment>> {
         let segment_size = self.config.segment_size;
-        let file_len = self.config.file.metadata()?.len();
+        let file_len = self.config.file.len()?;
         let empty_snapshot = snapshot.pt.is_empty();
         let number_of_segments =
             usize::try_from(file_len / segment_size as u64).unwrap()
@@ -684,6 +684,12 @@ impl SegmentAccountant {
     }
 
     fn free_segment(&mut self, lid: LogOffset) -> Result<()> {
+        if self.backups > 0 {
+            debug!("deferring free of segment {} until backups finish", lid);
+            self.deferred_frees.push(lid);
+            return Ok(());
+        }
+
         debug!("freeing segment {}", lid);
         debug!("free list before free {:?}", self.free);
         self.segment_cleaner.remove_pids(lid);
@@ -728,6 +734,64 @@ impl SegmentAccountant {
             }
         }
 
+        if self.free.contains(&lid) {
+            self.punch_hole(lid)?;
+        }
+
+        Ok(())
+    }
+
+    // Returns the space used by a free segment that could not be
+    // truncated off the end of the file to the filesystem. This
+    // is done synchronously, unlike truncation, so that it can't
+    // race with the segment being reused by `next`. Its header
+    // becomes zeroes, which recovery ignores like any other
+    // segment without a valid header.
+    fn punch_hole(&self, lid: LogOffset) -> Result<()> {
+        trace!("punching a hole in free segment {}", lid);
+
+        let len = LogOffset::try_from(self.config.segment_size).unwrap();
+        if !self.config.file.punch_hole(lid, len)? {
+            // the space is reclaimed when the
+            // segment is reused instead
+            debug!("can't punch a hole at log offset {}", lid);
+        }
+
+        Ok(())
+    }
+
+    fn remove_blob(&mut self, blob_ptr: BlobPointer) -> Result<()> {
+        if self.backups > 0 {
+            self.deferred_blob_removals.push(blob_ptr);
+            Ok(())
+        } else {
+            remove_blob(blob_ptr, &self.config)
+        }
+    }
+
+    /// Stops segments from being freed and blobs from being
+    /// removed until `resume_freeing` is called, so that a
+    /// backup can copy them while writes continue.
+    pub(super) fn pause_freeing(&mut self) {
+        self.backups += 1;
+    }
+
+    /// Frees whatever was left alone since the matching call
+    /// to `pause_freeing`, once no other backup is running.
+    pub(super) fn resume_freeing(&mut self) -> Result<()> {
+        self.backups -= 1;
+        if self.backups > 0 {
+            return Ok(());
+        }
+
+        for lid in mem::replace(&mut self.deferred_frees, vec![]) {
+            self.free_segment(lid)?;
+        }
+        for blob_ptr in mem::replace(&mut self.deferred_blob_removals, vec![])
+        {
+            remove_blob(blob_ptr, &self.config)?;
+        }
+
         Ok(())
     }
 
@@ -790,9 +854,12 @@ impl SegmentAccountant {
         // Do we need to schedule any blob cleanups?
         // Not if we just moved the pointer without changing
         // the underlying blob, as is the case with a single Blob
-        // with nothing else.
+        // with nothing else being rewritten to a new location.
         let schedule_rm_blob = !(old_cache_infos.len() == 1
-            && old_cache_infos[0].pointer.is_blob());
+            && old_cache_infos[0].pointer.is_blob()
+            && new_cache_info.pointer.is_blob()
+            && old_cache_infos[0].pointer.blob().1
+                == new_cache_info.pointer.blob().1);
 
         let mut removals = FastMap8::default();
 
@@ -805,8 +872,10 @@ impl SegmentAccountant {
                     "queueing blob removal for {} in our own segment",
                     old_ptr
                 );
-                self.segments[new_idx]
-                    .remove_blob(old_ptr.blob().1, &self.config)?;
+                let blob_ptr = old_ptr.blob().1;
+                if self.segments[new_idx].remove_blob(blob_ptr) {
+                    self.remove_blob(blob_ptr)?;
+                }
             }
 
             let old_idx = self.segment_id(old_lid);
@@ -862,8 +931,18 @@ impl SegmentAccountant {
         idx: usize,
         lsn: Lsn,
     ) -> Result<()> {
-        let cleanup_threshold = SEGMENT_CLEANUP_THRESHOLD;
+        self.clean_or_free_segment(idx, lsn, SEGMENT_CLEANUP_THRESHOLD)
+    }
 
+    // Starts draining the segment if it is inactive and at
+    // most `cleanup_threshold` percent live, and frees it if
+    // it has been completely drained.
+    fn clean_or_free_segment(
+        &mut self,
+        idx: usize,
+        lsn: Lsn,
+        cleanup_threshold: usize,
+    ) -> Result<()> {
         let segment_start = (idx * self.config.segment_size) as LogOffset;
 
         if let Segment::Inactive(inactive) = &mut self.segments[idx] {
@@ -971,11 +1050,22 @@ impl SegmentAccountant {
             self.segments[idx]
         );
 
-        let freeable_segments = if self.segments[idx].is_active() {
-            self.segments[idx].active_to_inactive(lsn, &self.config)?
-        } else {
-            Default::default()
-        };
+        let (freeable_segments, removable_blobs) =
+            if self.segments[idx].is_active() {
+                self.segments[idx].active_to_inactive(lsn)
+            } else {
+                Default::default()
+            };
+
+        for blob_ptr in removable_blobs {
+            trace!(
+                "removing blob {} while transitioning \
+                 segment lsn {:?} to Inactive",
+                blob_ptr,
+                lsn,
+            );
+            self.remove_blob(blob_ptr)?;
+        }
 
         for lsn in freeable_segments {
             let segment_start = self.ordering[&lsn];
@@ -1078,7 +1168,7 @@ impl SegmentAccountant {
     pub(super) fn segment_snapshot_iter_from(
         &mut self,
         lsn: Lsn,
-    ) -> Box<dyn Iterator<Item = (Lsn, LogOffset)>> {
+    ) -> Box<dyn Iterator<Item = (Lsn, LogOffset)> + Send> {
         assert!(
             !self.ordering.is_empty(),
             "expected ordering to have been initialized already"
@@ -1101,6 +1191,77 @@ impl SegmentAccountant {
         )
     }
 
+    /// Returns the live and dead bytes of every segment that
+    /// is not free, in the order of their offsets.
+    pub(super) fn segment_space(&self) -> Vec<SegmentSpace> {
+        let segment_size = self.config.segment_size;
+
+        self.segments
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, segment)| {
+                let (state, lsn, live, dead) = match segment {
+                    Segment::Free(_) => return None,
+                    Segment::Active(active) => (
+                        SegmentState::Active,
+                        active.lsn,
+                        active.rss.saturating_sub(active.deferred_replaced_rss),
+                        active.deferred_replaced_rss,
+                    ),
+                    Segment::Inactive(inactive) => (
+                        SegmentState::Inactive,
+                        inactive.lsn,
+                        inactive.rss,
+                        segment_size.saturating_sub(inactive.rss),
+                    ),
+                    Segment::Draining(draining) => {
+                        (SegmentState::Draining, draining.lsn, 0, segment_size)
+                    }
+                };
+
+                Some(SegmentSpace {
+                    offset: (idx * segment_size) as LogOffset,
+                    lsn,
+                    state,
+                    live_bytes: live as u64,
+                    dead_bytes: dead as u64,
+                })
+            })
+            .collect()
+    }
+
+    /// Starts draining every inactive segment that is at most
+    /// `target_utilization` percent live, or only the one at
+    /// `only` if it is given, so that their pages can be
+    /// relocated. Segments that have nothing left to relocate
+    /// are freed right away. Returns the number of segments
+    /// that were drained or freed.
+    pub(super) fn drain_segments(
+        &mut self,
+        target_utilization: usize,
+        only: Option<LogOffset>,
+    ) -> Result<usize> {
+        let segment_size = self.config.segment_size as LogOffset;
+        let mut drained = 0;
+
+        for idx in 0..self.segments.len() {
+            let skipped =
+                only.map_or(false, |lid| lid / segment_size != idx as u64);
+            if skipped || !self.segments[idx].is_inactive() {
+                continue;
+            }
+
+            let lsn = self.segments[idx].lsn();
+            self.clean_or_free_segment(idx, lsn, target_utilization)?;
+
+            if !self.segments[idx].is_inactive() {
+                drained += 1;
+            }
+        }
+
+        Ok(drained)
+    }
+
     // truncate the file to the desired length
     fn truncate(&mut self, at: LogOffset) -> Result<()> {
         trace!("asynchronously truncating file to length {}", at);
@@ -1124,8 +1285,18 @@ impl SegmentAccountant {
             let res = config
                 .file
                 .set_len(at)
-                .and_then(|_| config.file.sync_all())
+                .and_then(|_| {
+                    if config.in_memory {
+                        Ok(())
+                    } else {
+                        config.file.sync_all()
+                    }
+                })
                 .map_err(|e| e.into());
+
+            // release the file before waking a dropping
+            // SegmentAccountant, so that it can be reopened
+            drop(config);
             completer.fill(res);
         });
 
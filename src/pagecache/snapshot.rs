@@ -1,11 +1,14 @@
+use std::path::{Path, PathBuf};
+
 #[cfg(feature = "zstd")]
 use zstd::block::{compress, decompress};
 
 use crate::*;
 
 use super::{
-    arr_to_u32, pwrite_all, raw_segment_iter_from, u32_to_arr, u64_to_arr,
-    DiskPtr, LogIter, LogKind, LogOffset, Lsn, MessageKind, MAX_MSG_HEADER_LEN,
+    arr_to_u32, iobuf::recovered_tip, raw_segment_iter_from,
+    read_segment_header, u32_to_arr, u64_to_arr, DiskPtr, LogIter, LogKind,
+    LogOffset, Lsn, MessageKind, MAX_MSG_HEADER_LEN,
 };
 
 /// A snapshot of the state required to quickly restart
@@ -60,6 +63,24 @@ impl PageState {
 }
 
 impl Snapshot {
+    /// Applies the messages from `iter` that come after
+    /// the snapshot's `last_lsn`. Unlike `advance_snapshot`,
+    /// this is not part of recovery, so it neither reports
+    /// progress nor writes the result to disk.
+    pub(crate) fn advance(&mut self, iter: LogIter) {
+        let old_lsn = self.last_lsn;
+
+        for (log_kind, pid, lsn, ptr, sz) in iter {
+            if lsn <= old_lsn || lsn < self.last_lsn {
+                continue;
+            }
+
+            self.last_lsn = lsn;
+            self.last_lid = ptr.lid();
+            self.apply(log_kind, pid, lsn, ptr, sz);
+        }
+    }
+
     fn apply(
         &mut self,
         log_kind: LogKind,
@@ -142,7 +163,7 @@ impl Snapshot {
 }
 
 pub(crate) fn advance_snapshot(
-    iter: LogIter,
+    iter: &mut LogIter,
     mut snapshot: Snapshot,
     config: &RunningConfig,
 ) -> Result<Snapshot> {
@@ -151,17 +172,26 @@ pub(crate) fn advance_snapshot(
     trace!("building on top of old snapshot: {:?}", snapshot);
 
     let old_lsn = snapshot.last_lsn;
+    config.recovery.recovered_lsn.store(old_lsn, Release);
 
     for (log_kind, pid, lsn, ptr, sz) in iter {
+        if config.recovery.abandoned.load(Acquire) {
+            return Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "recovery was abandoned",
+            )));
+        }
+
         trace!(
             "in advance_snapshot looking at item with lsn {} ptr {}",
             lsn,
             ptr
         );
 
-        if lsn <= snapshot.last_lsn {
+        if lsn <= old_lsn || lsn < snapshot.last_lsn {
             // don't process already-processed Lsn's. last_lsn is for the last
-            // item ALREADY INCLUDED lsn in the snapshot.
+            // item ALREADY INCLUDED lsn in the snapshot. Pages freed by the
+            // same batch share an lsn, so it may repeat within this pass.
             trace!(
                 "continuing in advance_snapshot, lsn {} ptr {} last_lsn {}",
                 lsn,
@@ -171,17 +201,14 @@ pub(crate) fn advance_snapshot(
             continue;
         }
 
-        assert!(lsn > snapshot.last_lsn);
+        assert!(lsn >= snapshot.last_lsn);
         snapshot.last_lsn = lsn;
         snapshot.last_lid = ptr.lid();
+        config.recovery.recovered_lsn.store(lsn, Release);
 
         snapshot.apply(log_kind, pid, lsn, ptr, sz);
     }
 
-    if snapshot.last_lsn != old_lsn {
-        write_snapshot(config, &snapshot)?;
-    }
-
     trace!("generated new snapshot: {:?}", snapshot);
 
     #[cfg(feature = "event_log")]
@@ -196,12 +223,54 @@ pub fn read_snapshot_or_default(config: &RunningConfig) -> Result<Snapshot> {
     let mut last_snap =
         read_snapshot(config)?.unwrap_or_else(Snapshot::default);
 
-    let (log_iter, max_header_stable_lsn, to_zero) =
+    // a snapshot that is past the point being recovered to
+    // can't be rolled back, so the log is replayed from the
+    // beginning instead
+    let discarded_snapshot = match config.recover_until {
+        Some(until) if last_snap.last_lsn > until => {
+            debug!(
+                "ignoring snapshot at lsn {} to recover until lsn {}",
+                last_snap.last_lsn, until
+            );
+            last_snap = Snapshot::default();
+            true
+        }
+        _ => false,
+    };
+
+    let (mut log_iter, max_header_stable_lsn, mut to_zero) =
         raw_segment_iter_from(last_snap.last_lsn, config)?;
 
-    last_snap.max_header_stable_lsn = max_header_stable_lsn;
+    last_snap.max_header_stable_lsn =
+        config.recover_until.map_or(max_header_stable_lsn, |until| {
+            max_header_stable_lsn.min(until)
+        });
+
+    let old_lsn = last_snap.last_lsn;
 
-    let res = advance_snapshot(log_iter, last_snap, config)?;
+    let res = advance_snapshot(&mut log_iter, last_snap, config)?;
+
+    if let Some(until) = config.recover_until {
+        check_retained(config, &res, log_iter.dangling_blobs, until)?;
+    }
+
+    if (res.last_lsn != old_lsn || discarded_snapshot) && !config.in_memory {
+        write_snapshot(config, &res)?;
+    }
+
+    // a batch that was torn by a crash is discarded along
+    // with everything after it, so the segments after the
+    // last recovered message are zeroed like torn ones
+    let incomplete_batch = log_iter.incomplete_batch;
+    if incomplete_batch {
+        let segment_size = config.segment_size as Lsn;
+        if log_iter.cur_lsn / segment_size != res.last_lsn / segment_size {
+            if let Some(segment_base) = &log_iter.segment_base {
+                to_zero.push(segment_base.1);
+            }
+        }
+        to_zero.extend(log_iter.segment_iter.by_ref().map(|(_lsn, lid)| lid));
+    }
 
     for lid in to_zero {
         debug!("zeroing torn segment at lid {}", lid);
@@ -210,12 +279,30 @@ pub fn read_snapshot_or_default(config: &RunningConfig) -> Result<Snapshot> {
         // from being allocated which would duplicate its LSN, messing
         // up recovery in the future.
         io_fail!(config, "segment initial free zero");
-        pwrite_all(
-            &config.file,
+        config.file.pwrite_all(
             &*vec![MessageKind::Corrupted.into(); SEG_HEADER_LEN],
             lid,
         )?;
-        if !config.temporary {
+        if !config.is_temporary() {
+            config.file.sync_all()?;
+        }
+    }
+
+    if config.recover_until.is_some() || incomplete_batch {
+        // messages after the tip in its own segment are
+        // discarded too, because the log would otherwise be
+        // read past the tip into them the next time it is
+        // recovered
+        let (_tip_lsn, tip_lid) = recovered_tip(config, &res)?;
+        let segment_size = config.segment_size as LogOffset;
+        let segment_end = (tip_lid / segment_size + 1) * segment_size;
+        let tail_len = usize::try_from(segment_end - tip_lid).unwrap();
+
+        debug!("zeroing {} bytes after the tip at lid {}", tail_len, tip_lid);
+
+        io_fail!(config, "recover until zero tail");
+        config.file.pwrite_all(&vec![0; tail_len], tip_lid)?;
+        if !config.is_temporary() {
             config.file.sync_all()?;
         }
     }
@@ -223,28 +310,122 @@ pub fn read_snapshot_or_default(config: &RunningConfig) -> Result<Snapshot> {
     Ok(res)
 }
 
-/// Read a `Snapshot` from disk.
-fn read_snapshot(config: &RunningConfig) -> std::io::Result<Option<Snapshot>> {
-    let mut f = loop {
-        let mut candidates = config.get_snapshot_files()?;
-        if candidates.is_empty() {
-            debug!("no previous snapshot found");
-            return Ok(None);
+// Checks that the state that the log was recovered up to
+// `until` is intact, which it is not if a segment that it
+// refers to has been reused, or a blob that the log pointed
+// to has been removed, since.
+fn check_retained(
+    config: &RunningConfig,
+    snapshot: &Snapshot,
+    dangling_blobs: usize,
+    until: Lsn,
+) -> Result<()> {
+    let reused = || {
+        Error::Unsupported(format!(
+            "can't recover until lsn {}, because the log that the \
+             state at that point was stored in has since been reused",
+            until
+        ))
+    };
+
+    if dangling_blobs > 0 {
+        return Err(reused());
+    }
+
+    let segment_size = config.segment_size as LogOffset;
+    let segment_lsn_size = Lsn::try_from(config.segment_size).unwrap();
+    let mut segment_lsns = FastMap8::default();
+
+    for state in &snapshot.pt {
+        let items = match state {
+            PageState::Present(items) => items,
+            PageState::Free(..) => continue,
+            // the messages that allocated this page are gone
+            PageState::Uninitialized => return Err(reused()),
+        };
+
+        for (lsn, ptr, _sz) in items {
+            let segment_lid = ptr.lid() / segment_size * segment_size;
+            let segment_lsn = if let Some(segment_lsn) =
+                segment_lsns.get(&segment_lid)
+            {
+                *segment_lsn
+            } else {
+                let header = read_segment_header(&config.file, segment_lid)?;
+                let segment_lsn = if header.ok { header.lsn } else { -1 };
+                let _ = segment_lsns.insert(segment_lid, segment_lsn);
+                segment_lsn
+            };
+
+            if segment_lsn != lsn / segment_lsn_size * segment_lsn_size {
+                return Err(reused());
+            }
         }
+    }
 
-        candidates.sort();
+    Ok(())
+}
 
-        let path = candidates.pop().unwrap();
+/// Read the last `Snapshot` written to disk, or a default
+/// one, without advancing it.
+pub(crate) fn read_last_snapshot(config: &RunningConfig) -> Result<Snapshot> {
+    Ok(read_snapshot(config)?.unwrap_or_else(Snapshot::default))
+}
 
-        match std::fs::OpenOptions::new().read(true).open(&path) {
-            Ok(f) => break f,
+/// Set in the length that follows a snapshot's bytes if
+/// they were compressed, so that snapshots can be read
+/// after `use_compression` has been changed.
+const COMPRESSED_SNAPSHOT_FLAG: u64 = 1 << 63;
+
+/// Read the newest `Snapshot` from disk. If it is corrupt,
+/// none is returned and recovery scans the whole log.
+fn read_snapshot(config: &RunningConfig) -> std::io::Result<Option<Snapshot>> {
+    if config.in_memory {
+        // `in_memory` databases never write snapshots, and
+        // shouldn't create a directory looking for them
+        return Ok(None);
+    }
+
+    loop {
+        let path = if let Some(path) = snapshot_files(config)?.pop() {
+            path
+        } else {
+            debug!("no previous snapshot found");
+            return Ok(None);
+        };
+
+        match read_snapshot_file(&path) {
+            Ok(Some(snapshot)) => return Ok(Some(snapshot)),
+            Ok(None) => {
+                warn!(
+                    "snapshot file {:?} is corrupt, falling back to \
+                     a full scan of the log",
+                    path
+                );
+                return Ok(None);
+            }
             Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
                 // this can happen if there's a race
                 continue;
             }
             Err(other) => return Err(other),
         }
-    };
+    }
+}
+
+// Returns the paths of the snapshots that have been
+// completely written, oldest first.
+pub(crate) fn snapshot_files(
+    config: &RunningConfig,
+) -> std::io::Result<Vec<PathBuf>> {
+    let mut candidates = config.get_snapshot_files()?;
+    candidates.retain(|path| path.extension() != Some("generating".as_ref()));
+    candidates.sort();
+    Ok(candidates)
+}
+
+fn read_snapshot_file(path: &Path) -> std::io::Result<Option<Snapshot>> {
+    let mut f = std::fs::OpenOptions::new().read(true).open(path)?;
 
     if f.metadata()?.len() <= 12 {
         warn!("empty/corrupt snapshot file found");
@@ -269,55 +450,104 @@ fn read_snapshot(config: &RunningConfig) -> std::io::Result<Option<Snapshot>> {
         return Ok(None);
     }
 
-    #[cfg(feature = "zstd")]
-    let bytes = if config.use_compression {
-        use std::convert::TryInto;
-
-        let len_expected: u64 =
-            u64::from_le_bytes(len_expected_bytes.as_ref().try_into().unwrap());
+    let len_expected = u64::from_le_bytes(len_expected_bytes);
+    let compressed = len_expected & COMPRESSED_SNAPSHOT_FLAG != 0;
 
-        decompress(&*buf, usize::try_from(len_expected).unwrap()).unwrap()
+    #[cfg(feature = "zstd")]
+    let bytes = if compressed {
+        let len_expected = len_expected & !COMPRESSED_SNAPSHOT_FLAG;
+        decompress(&*buf, usize::try_from(len_expected).unwrap())?
     } else {
         buf
     };
 
     #[cfg(not(feature = "zstd"))]
-    let bytes = buf;
+    let bytes = if compressed {
+        // recover from the log instead
+        warn!("found a compressed snapshot without the compression feature");
+        return Ok(None);
+    } else {
+        buf
+    };
 
     Ok(Snapshot::deserialize(&mut bytes.as_slice()).ok())
 }
 
-fn write_snapshot(config: &RunningConfig, snapshot: &Snapshot) -> Result<()> {
+/// Writes a `Snapshot` into the database's directory in
+/// place of the previous one.
+pub(crate) fn write_snapshot(
+    config: &RunningConfig,
+    snapshot: &Snapshot,
+) -> Result<()> {
+    let path_2 = write_snapshot_in(config, &config.get_path(), snapshot)?;
+
+    // clean up any old snapshots, and any that were left
+    // half-written by a crash
+    let candidates = config.get_snapshot_files()?;
+    for path in candidates {
+        if path.file_name() != path_2.file_name() {
+            debug!("removing old snapshot file {:?}", path);
+
+            io_fail!(config, "snap write rm old");
+
+            if let Err(e) = std::fs::remove_file(&path) {
+                // TODO should this just be a try return?
+                warn!(
+                    "failed to remove old snapshot file, maybe snapshot race? {}",
+                    e
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes a `Snapshot` into the given directory, returning
+/// the path of the new snapshot file.
+pub(crate) fn write_snapshot_in(
+    config: &RunningConfig,
+    dir: &Path,
+    snapshot: &Snapshot,
+) -> Result<PathBuf> {
     let raw_bytes = snapshot.serialize();
     let decompressed_len = raw_bytes.len();
 
     #[cfg(feature = "zstd")]
-    let bytes = if config.use_compression {
-        compress(&*raw_bytes, config.compression_factor).unwrap()
+    let (bytes, compressed) = if config.use_compression {
+        (compress(&*raw_bytes, config.compression_factor).unwrap(), true)
     } else {
-        raw_bytes
+        (raw_bytes, false)
     };
 
     #[cfg(not(feature = "zstd"))]
-    let bytes = raw_bytes;
+    let (bytes, compressed) = (raw_bytes, false);
+
+    let len = if compressed {
+        decompressed_len as u64 | COMPRESSED_SNAPSHOT_FLAG
+    } else {
+        decompressed_len as u64
+    };
 
     let crc32: [u8; 4] = u32_to_arr(crc32(&bytes));
-    let len_bytes: [u8; 8] = u64_to_arr(decompressed_len as u64);
+    let len_bytes: [u8; 8] = u64_to_arr(len);
 
     let path_1_suffix = format!("snap.{:016X}.generating", snapshot.last_lsn);
 
-    let mut path_1 = config.get_path();
+    let mut path_1 = dir.to_path_buf();
     path_1.push(path_1_suffix);
 
     let path_2_suffix = format!("snap.{:016X}", snapshot.last_lsn);
 
-    let mut path_2 = config.get_path();
+    let mut path_2 = dir.to_path_buf();
     path_2.push(path_2_suffix);
 
     let parent = path_1.parent().unwrap();
     std::fs::create_dir_all(parent)?;
-    let mut f =
-        std::fs::OpenOptions::new().write(true).create(true).open(&path_1)?;
+    let mut f = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path_1)?;
 
     // write the snapshot bytes, followed by a crc64 checksum at the end
     io_fail!(config, "snap write");
@@ -328,6 +558,12 @@ fn write_snapshot(config: &RunningConfig, snapshot: &Snapshot) -> Result<()> {
     f.write_all(&crc32)?;
     io_fail!(config, "snap write post");
 
+    // the snapshot has to be durable before it replaces
+    // the old one
+    if !config.is_temporary() {
+        f.sync_all()?;
+    }
+
     trace!("wrote snapshot to {}", path_1.to_string_lossy());
 
     io_fail!(config, "snap write mv");
@@ -336,23 +572,5 @@ fn write_snapshot(config: &RunningConfig, snapshot: &Snapshot) -> Result<()> {
 
     trace!("renamed snapshot to {}", path_2.to_string_lossy());
 
-    // clean up any old snapshots
-    let candidates = config.get_snapshot_files()?;
-    for path in candidates {
-        let path_str = path.file_name().unwrap().to_str().unwrap();
-        if !path_2.to_string_lossy().ends_with(&*path_str) {
-            debug!("removing old snapshot file {:?}", path);
-
-            io_fail!(config, "snap write rm old");
-
-            if let Err(e) = std::fs::remove_file(&path) {
-                // TODO should this just be a try return?
-                warn!(
-                    "failed to remove old snapshot file, maybe snapshot race? {}",
-                    e
-                );
-            }
-        }
-    }
-    Ok(())
+    Ok(path_2)
 }
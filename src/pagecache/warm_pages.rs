@@ -0,0 +1,78 @@
+//! The pages that were resident when the database was last
+//! closed, which are read back in when it is opened again so
+//! that reads don't all go to disk for a while after a
+//! restart.
+use std::{
+    fs::{self, OpenOptions},
+    io,
+    path::PathBuf,
+};
+
+use crate::pagecache::*;
+use crate::*;
+
+const FILE_NAME: &str = "warm_pages";
+
+/// Reads back the pids written by `persist`, hottest first.
+/// The pages may have been freed or reused since.
+pub(crate) fn read(config: &RunningConfig) -> Result<Vec<PageId>> {
+    if config.in_memory {
+        return Ok(vec![]);
+    }
+
+    let buf = match fs::read(warm_pages_path(config)) {
+        Ok(buf) => buf,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(vec![]);
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if buf.len() < 4 || (buf.len() - 4) % 8 != 0 {
+        warn!("empty/corrupt warm pages file found");
+        return Ok(vec![]);
+    }
+    let (items, crc_bytes) = buf.split_at(buf.len() - 4);
+    if crc32(items) != arr_to_u32(crc_bytes) {
+        warn!("warm pages file has a bad checksum");
+        return Ok(vec![]);
+    }
+
+    Ok(items.chunks(8).map(arr_to_u64).collect())
+}
+
+/// Writes `pids` into the database's directory, in place of
+/// the previous ones.
+pub(crate) fn persist(config: &RunningConfig, pids: &[PageId]) -> Result<()> {
+    let mut buf = Vec::with_capacity(pids.len() * 8 + 4);
+    for pid in pids {
+        buf.extend_from_slice(&u64_to_arr(*pid));
+    }
+    let crc = u32_to_arr(crc32(&buf));
+    buf.extend_from_slice(&crc);
+
+    let path = warm_pages_path(config);
+    let tmp_path = path.with_extension("generating");
+    let mut f = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&tmp_path)?;
+
+    io_fail!(config, "warm pages write");
+    f.write_all(&buf)?;
+    if !config.is_temporary() {
+        f.sync_all()?;
+    }
+
+    io_fail!(config, "warm pages write mv");
+    fs::rename(&tmp_path, &path)?;
+
+    Ok(())
+}
+
+fn warm_pages_path(config: &RunningConfig) -> PathBuf {
+    let mut path = config.get_path();
+    path.push(FILE_NAME);
+    path
+}
@@ -0,0 +1,77 @@
+use std::thread;
+use std::time::Duration;
+
+use parking_lot::MutexGuard;
+
+use super::*;
+
+/// A background thread that runs a task once every
+/// interval, until it is dropped or the task returns
+/// `false`. Dropping it waits for a run that is under way.
+#[derive(Debug)]
+pub(crate) struct Periodic {
+    name: &'static str,
+    shutdown: Arc<(Mutex<bool>, Condvar)>,
+    join_handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Periodic {
+    /// Spawns a thread called `name` that waits for
+    /// `interval` before each run of `task`. The task is
+    /// passed a function that returns `true` once the
+    /// `Periodic` is being dropped, so that long runs can
+    /// stop early.
+    pub(crate) fn spawn<F>(
+        name: &'static str,
+        interval: Duration,
+        task: F,
+    ) -> std::io::Result<Self>
+    where
+        F: FnMut(&dyn Fn() -> bool) -> bool + Send + 'static,
+    {
+        let shutdown = Arc::new((Mutex::new(false), Condvar::new()));
+
+        let join_handle =
+            thread::Builder::new().name(name.to_owned()).spawn({
+                let signal = shutdown.clone();
+                move || run(&signal, interval, task)
+            })?;
+
+        Ok(Self { name, shutdown, join_handle: Some(join_handle) })
+    }
+}
+
+fn run<F>(shutdown: &(Mutex<bool>, Condvar), interval: Duration, mut task: F)
+where
+    F: FnMut(&dyn Fn() -> bool) -> bool,
+{
+    let is_stopping = || *shutdown.0.lock();
+    let mut is_shut_down = shutdown.0.lock();
+
+    while !*is_shut_down {
+        let _ = shutdown.1.wait_for(&mut is_shut_down, interval);
+
+        if *is_shut_down {
+            return;
+        }
+
+        let keep_going =
+            MutexGuard::unlocked(&mut is_shut_down, || task(&is_stopping));
+        if !keep_going {
+            return;
+        }
+    }
+}
+
+impl Drop for Periodic {
+    fn drop(&mut self) {
+        *self.shutdown.0.lock() = true;
+        let _notified = self.shutdown.1.notify_all();
+
+        if let Some(join_handle) = self.join_handle.take() {
+            if let Err(e) = join_handle.join() {
+                error!("error joining {} thread: {:?}", self.name, e);
+            }
+        }
+    }
+}
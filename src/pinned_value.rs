@@ -0,0 +1,67 @@
+use std::{fmt, ops::Deref};
+
+use crate::{Guard, IVec};
+
+/// A value returned by `Tree::get_pinned`, which borrows the
+/// value from the page cache instead of copying it out.
+///
+/// While it is alive, the current thread stays pinned to
+/// the epoch it was read in, so the page it points into is
+/// not reclaimed even if the key is overwritten in the
+/// meantime. The same pinning delays the reclamation of
+/// every other page replaced during that time, so it should
+/// be dropped soon, and converted with `to_owned` if it has
+/// to be kept around or sent to another thread.
+pub struct PinnedValue {
+    // keeps the page that `value` lives in from being freed
+    _guard: Guard,
+    value: *const IVec,
+}
+
+impl PinnedValue {
+    /// `value` must point into a page that was read while
+    /// `guard` was pinned.
+    pub(crate) fn new(guard: Guard, value: *const IVec) -> PinnedValue {
+        PinnedValue { _guard: guard, value }
+    }
+
+    /// Returns an owned copy of the value, which for values
+    /// that are not stored inline only increments a
+    /// reference count.
+    pub fn to_owned(&self) -> IVec {
+        self.ivec().clone()
+    }
+
+    fn ivec(&self) -> &IVec {
+        #[allow(unsafe_code)]
+        unsafe {
+            &*self.value
+        }
+    }
+}
+
+impl Deref for PinnedValue {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.ivec()
+    }
+}
+
+impl AsRef<[u8]> for PinnedValue {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl<T: AsRef<[u8]>> PartialEq<T> for PinnedValue {
+    fn eq(&self, other: &T) -> bool {
+        self.as_ref() == other.as_ref()
+    }
+}
+
+impl fmt::Debug for PinnedValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_ref().fmt(f)
+    }
+}
@@ -0,0 +1,480 @@
+use std::{
+    collections::BTreeMap,
+    ops::{Bound, Deref, RangeBounds},
+    sync::{Arc, Weak},
+};
+
+use parking_lot::RwLockReadGuard;
+
+use super::*;
+
+type Overlay = BTreeMap<IVec, Option<IVec>>;
+
+/// The read snapshots of a database that are still alive.
+/// Writers save the value of each key they are about to
+/// overwrite into every one of them that doesn't have a
+/// value for that key yet, so a snapshot sees the values
+/// from when it was taken by checking what was saved into
+/// it after reading the current value.
+#[derive(Default)]
+pub(crate) struct ReadSnapshots {
+    // held for reading by writers, for the whole of a batch
+    // or transaction, and for writing while a snapshot is
+    // taken, so that a snapshot never sees part of a write
+    gate: RwLock<()>,
+    live: AtomicUsize,
+    registry: Mutex<Vec<Weak<Frozen>>>,
+}
+
+impl Debug for ReadSnapshots {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReadSnapshots")
+            .field("live", &self.live.load(Acquire))
+            .finish()
+    }
+}
+
+impl ReadSnapshots {
+    /// Must be held while writing, so that no snapshot is
+    /// taken in the middle of the write. May be taken again
+    /// by a thread that already holds it.
+    pub(crate) fn gate(&self) -> RwLockReadGuard<'_, ()> {
+        self.gate.read_recursive()
+    }
+
+    /// Saves the value that a key had before the write that
+    /// is about to be made to it into every live snapshot.
+    /// Must be called while holding the gate, before the
+    /// write becomes visible.
+    pub(crate) fn preserve(
+        &self,
+        tree_id: &IVec,
+        key: &[u8],
+        value: Option<&IVec>,
+    ) {
+        if self.live.load(Acquire) == 0 {
+            return;
+        }
+
+        let registry = self.registry.lock();
+        for frozen in registry.iter().filter_map(Weak::upgrade) {
+            let mut overlays = frozen.overlays.lock();
+            let overlay = overlays.entry(tree_id.clone()).or_default();
+            if !overlay.contains_key(key) {
+                let _ = overlay.insert(key.into(), value.cloned());
+            }
+        }
+    }
+
+    fn register(self: &Arc<Self>) -> Arc<Frozen> {
+        let _gate = self.gate.write();
+
+        let frozen = Arc::new(Frozen {
+            snapshots: self.clone(),
+            overlays: Mutex::new(FastMap8::default()),
+        });
+
+        let mut registry = self.registry.lock();
+        registry.retain(|weak| weak.strong_count() > 0);
+        registry.push(Arc::downgrade(&frozen));
+        let _ = self.live.fetch_add(1, Release);
+
+        frozen
+    }
+}
+
+// The values that were overwritten since a snapshot was
+// taken, by tree and key. `None` means that the key was
+// not present.
+struct Frozen {
+    snapshots: Arc<ReadSnapshots>,
+    overlays: Mutex<FastMap8<IVec, Overlay>>,
+}
+
+impl Drop for Frozen {
+    fn drop(&mut self) {
+        let _ = self.snapshots.live.fetch_sub(1, Release);
+    }
+}
+
+impl Frozen {
+    fn get(&self, tree_id: &IVec, key: &[u8]) -> Option<Option<IVec>> {
+        let overlays = self.overlays.lock();
+        overlays.get(tree_id)?.get(key).cloned()
+    }
+
+    // Returns the first (or last) saved entry of a tree
+    // within the given bounds.
+    fn first_in(
+        &self,
+        tree_id: &IVec,
+        lo: Bound<&IVec>,
+        hi: Bound<&IVec>,
+        back: bool,
+    ) -> Option<(IVec, Option<IVec>)> {
+        if bounds_are_empty(lo, hi) {
+            return None;
+        }
+
+        let overlays = self.overlays.lock();
+        let mut range = overlays.get(tree_id)?.range::<IVec, _>((lo, hi));
+        let entry = if back { range.next_back() } else { range.next() };
+        entry.map(|(k, v)| (k.clone(), v.clone()))
+    }
+}
+
+fn bounds_are_empty(lo: Bound<&IVec>, hi: Bound<&IVec>) -> bool {
+    match (lo, hi) {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end))
+        | (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+        _ => false,
+    }
+}
+
+/// A consistent, read-only view of every `Tree` of a `Db` as
+/// it was when `Db::snapshot` was called, which writers
+/// don't disturb. Dereferences to the view of the default
+/// `Tree`.
+///
+/// A snapshot doesn't stop writers. Instead, every write
+/// that is made while it is alive saves the value that it
+/// overwrites into it, so it uses memory in proportion to
+/// the number of distinct keys written since it was taken,
+/// and should be dropped once it is no longer needed. It is
+/// not persisted, and doesn't see through `Db::drop_tree`.
+#[derive(Clone)]
+pub struct ReadSnapshot {
+    default: SnapshotTree,
+    trees: FastMap8<IVec, Tree>,
+}
+
+impl Debug for ReadSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReadSnapshot")
+            .field("trees", &self.trees.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Deref for ReadSnapshot {
+    type Target = SnapshotTree;
+
+    fn deref(&self) -> &SnapshotTree {
+        &self.default
+    }
+}
+
+impl ReadSnapshot {
+    pub(crate) fn take(
+        context: &Context,
+        default: &Tree,
+        tenants: &RwLock<FastMap8<IVec, Tree>>,
+    ) -> ReadSnapshot {
+        let frozen = context.read_snapshots.register();
+
+        // trees opened after this are not part of the
+        // snapshot, and were empty when it was taken
+        let trees = tenants.read().clone();
+
+        ReadSnapshot {
+            default: SnapshotTree { tree: default.clone(), frozen },
+            trees,
+        }
+    }
+
+    /// Returns the view of the `Tree` with the given name,
+    /// or `None` if it was not open when the snapshot was
+    /// taken.
+    pub fn open_tree<V: AsRef<[u8]>>(&self, name: V) -> Option<SnapshotTree> {
+        let tree = self.trees.get(name.as_ref())?;
+        Some(SnapshotTree {
+            tree: tree.clone(),
+            frozen: self.default.frozen.clone(),
+        })
+    }
+
+    /// Returns the names of the trees in the snapshot.
+    pub fn tree_names(&self) -> Vec<IVec> {
+        self.trees.keys().cloned().collect()
+    }
+}
+
+/// A read-only view of one `Tree` as it was when a
+/// `ReadSnapshot` was taken.
+#[derive(Clone)]
+pub struct SnapshotTree {
+    tree: Tree,
+    frozen: Arc<Frozen>,
+}
+
+impl Debug for SnapshotTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SnapshotTree").field("name", &self.tree.name()).finish()
+    }
+}
+
+impl SnapshotTree {
+    /// Retrieve the value of a key as of the snapshot.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, IVec};
+    ///
+    /// let config = Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// db.insert("balance", "100")?;
+    /// let snapshot = db.snapshot();
+    /// db.insert("balance", "50")?;
+    /// db.insert("overdraft", "0")?;
+    ///
+    /// assert_eq!(snapshot.get("balance")?, Some(IVec::from("100")));
+    /// assert_eq!(snapshot.get("overdraft")?, None);
+    /// assert_eq!(db.get("balance")?, Some(IVec::from("50")));
+    /// # Ok(()) }
+    /// ```
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<IVec>> {
+        // the current value must be read before checking the
+        // saved one, because a write saves the value that it
+        // overwrites before it becomes visible
+        let current = self.tree.get(key.as_ref())?;
+        if let Some(saved) = self.frozen.get(&self.tree.tree_id, key.as_ref()) {
+            Ok(saved)
+        } else {
+            Ok(current)
+        }
+    }
+
+    /// Returns `true` if the key was present when the
+    /// snapshot was taken.
+    pub fn contains_key<K: AsRef<[u8]>>(&self, key: K) -> Result<bool> {
+        self.get(key).map(|v| v.is_some())
+    }
+
+    /// Create a double-ended iterator over the items of the
+    /// snapshot, in the lexicographic order of their keys.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// for i in 0..10_u8 {
+    ///     db.insert([i], vec![i])?;
+    /// }
+    ///
+    /// let snapshot = db.snapshot();
+    /// let mut scan = snapshot.iter();
+    /// let first = scan.next().unwrap()?;
+    ///
+    /// // writes made during the scan are not seen by it
+    /// db.clear()?;
+    /// assert_eq!(scan.count(), 9);
+    /// assert_eq!(first.0, [0]);
+    /// # Ok(()) }
+    /// ```
+    pub fn iter(&self) -> SnapshotIter {
+        self.range::<&[u8], _>(..)
+    }
+
+    /// Create a double-ended iterator over the items of the
+    /// snapshot whose keys fall within the given range.
+    pub fn range<K, R>(&self, range: R) -> SnapshotIter
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        let to_ivec = |bound: Bound<&K>| match bound {
+            Bound::Included(k) => Bound::Included(IVec::from(k.as_ref())),
+            Bound::Excluded(k) => Bound::Excluded(IVec::from(k.as_ref())),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let lo = to_ivec(range.start_bound());
+        let hi = to_ivec(range.end_bound());
+
+        SnapshotIter {
+            iter: self.tree.range::<IVec, _>((lo.clone(), hi.clone())),
+            tree_id: self.tree.tree_id.clone(),
+            frozen: self.frozen.clone(),
+            lo,
+            hi,
+            peeked_front: None,
+            peeked_back: None,
+            front: None,
+            back: None,
+            done: false,
+        }
+    }
+
+    /// Create a double-ended iterator over the items of the
+    /// snapshot whose keys start with the given prefix.
+    pub fn scan_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> SnapshotIter {
+        let lower = prefix.as_ref();
+        let mut upper = lower.to_vec();
+
+        // the first key after every key with the prefix is
+        // the prefix with its last byte below 255 incremented
+        while let Some(last) = upper.pop() {
+            if last < u8::max_value() {
+                upper.push(last + 1);
+                return self.range(lower..&*upper);
+            }
+        }
+
+        self.range(lower..)
+    }
+
+    /// Returns the number of items in the snapshot.
+    ///
+    /// Beware: performs a full O(n) scan under the hood.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Returns `true` if the snapshot has no items.
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Returns the name of the tree.
+    pub fn name(&self) -> IVec {
+        self.tree.name()
+    }
+}
+
+/// A double-ended iterator over the items of a
+/// `SnapshotTree`.
+pub struct SnapshotIter {
+    iter: Iter,
+    tree_id: IVec,
+    frozen: Arc<Frozen>,
+    lo: Bound<IVec>,
+    hi: Bound<IVec>,
+    // current items that were read from each end but not
+    // returned yet, because a saved value came before them
+    peeked_front: Option<(IVec, IVec)>,
+    peeked_back: Option<(IVec, IVec)>,
+    // the last keys returned from each end
+    front: Option<IVec>,
+    back: Option<IVec>,
+    done: bool,
+}
+
+impl SnapshotIter {
+    /// Iterate over the keys of the snapshot.
+    pub fn keys(self) -> impl DoubleEndedIterator<Item = Result<IVec>> {
+        self.map(|r| r.map(|(k, _v)| k))
+    }
+
+    /// Iterate over the values of the snapshot.
+    pub fn values(self) -> impl DoubleEndedIterator<Item = Result<IVec>> {
+        self.map(|r| r.map(|(_k, v)| v))
+    }
+
+    fn read_current(&mut self, back: bool) -> Result<Option<(IVec, IVec)>> {
+        let (peeked, other) = if back {
+            (&mut self.peeked_back, &mut self.peeked_front)
+        } else {
+            (&mut self.peeked_front, &mut self.peeked_back)
+        };
+        if let Some(current) = peeked.take() {
+            return Ok(Some(current));
+        }
+
+        let read = if back { self.iter.next_back() } else { self.iter.next() };
+        match read {
+            Some(current) => current.map(Some),
+            // the other end may still hold the last item
+            None => Ok(other.take()),
+        }
+    }
+
+    // Merges the current items with the saved values, from
+    // the front or the back. Keys that have a saved value
+    // are returned with it, or skipped if it is `None`.
+    fn next_inner(&mut self, back: bool) -> Option<Result<(IVec, IVec)>> {
+        while !self.done {
+            let current = match self.read_current(back) {
+                Ok(current) => current,
+                Err(e) => return Some(Err(e)),
+            };
+
+            // the saved values are looked up after reading the
+            // current item, so a write that made it visible
+            // has already saved the value that it overwrote
+            let lo =
+                self.front.as_ref().map_or(self.lo.as_ref(), Bound::Excluded);
+            let hi =
+                self.back.as_ref().map_or(self.hi.as_ref(), Bound::Excluded);
+            let saved = self.frozen.first_in(&self.tree_id, lo, hi, back);
+
+            let (key, value) = match (current, saved) {
+                (None, None) => {
+                    self.done = true;
+                    return None;
+                }
+                (Some((key, value)), None) => (key, Some(value)),
+                (None, Some(entry)) => entry,
+                (Some((key, value)), Some(entry)) => {
+                    let current_first =
+                        if back { key > entry.0 } else { key < entry.0 };
+                    if current_first {
+                        (key, Some(value))
+                    } else {
+                        if key != entry.0 {
+                            // returned after the saved value
+                            if back {
+                                self.peeked_back = Some((key, value));
+                            } else {
+                                self.peeked_front = Some((key, value));
+                            }
+                        }
+                        entry
+                    }
+                }
+            };
+
+            let crossed = if back {
+                self.front.as_ref().map_or(false, |last| key <= *last)
+            } else {
+                self.back.as_ref().map_or(false, |last| key >= *last)
+            };
+            if crossed {
+                self.done = true;
+                return None;
+            }
+
+            if back {
+                self.back = Some(key.clone());
+            } else {
+                self.front = Some(key.clone());
+            }
+
+            if let Some(present) = value {
+                return Some(Ok((key, present)));
+            }
+        }
+
+        None
+    }
+}
+
+impl Iterator for SnapshotIter {
+    type Item = Result<(IVec, IVec)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_inner(false)
+    }
+}
+
+impl DoubleEndedIterator for SnapshotIter {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.next_inner(true)
+    }
+}
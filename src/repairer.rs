@@ -0,0 +1,50 @@
+use std::sync::Weak;
+use std::time::Duration;
+
+use parking_lot::RwLock;
+
+use super::*;
+
+/// The trees of a `Db`, which the repairer only holds
+/// weakly so that it does not keep them alive.
+pub(crate) type Tenants = RwLock<FastMap8<IVec, Tree>>;
+
+/// Spawns a thread that completes partial splits and merges
+/// in every tree of a `Db` every `repair_every_ms` when
+/// running with `RepairPolicy::Background`, until the
+/// returned `Periodic` is dropped.
+pub(crate) fn spawn(tenants: Weak<Tenants>, repair_every_ms: u64) -> Periodic {
+    Periodic::spawn(
+        "structure repairer",
+        Duration::from_millis(repair_every_ms),
+        move |_is_stopping| {
+            let trees: Vec<Tree> = if let Some(live) = tenants.upgrade() {
+                live.read().values().cloned().collect()
+            } else {
+                return false;
+            };
+
+            for tree in trees {
+                // the rest are swept once the budget refills
+                let pagecache = &tree.context.pagecache;
+                if pagecache.maintenance_throttled() {
+                    break;
+                }
+                pagecache.charge_maintenance(0, 1);
+
+                match tree.repair_structure() {
+                    Ok(()) | Err(Error::CollectionNotFound(_)) => {}
+                    Err(e) => {
+                        error!(
+                            "failed to repair tree structure from \
+                             background repair thread: {}",
+                            e
+                        );
+                    }
+                }
+            }
+            true
+        },
+    )
+    .unwrap()
+}
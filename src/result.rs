@@ -29,6 +29,9 @@ pub enum Error {
     CollectionNotFound(IVec),
     /// The system has been used in an unsupported way.
     Unsupported(String),
+    /// A write was attempted on a key that already exists
+    /// in a write-once `Tree`. See `Tree::set_write_once`.
+    ReadOnlyKey(IVec),
     /// An unexpected bug has happened. Please open an issue on github!
     ReportableBug(String),
     /// A read or write error has happened when interacting with the file
@@ -53,6 +56,7 @@ impl Clone for Error {
             Io(ioe) => Io(io::Error::new(ioe.kind(), format!("{:?}", ioe))),
             CollectionNotFound(name) => CollectionNotFound(name.clone()),
             Unsupported(why) => Unsupported(why.clone()),
+            ReadOnlyKey(key) => ReadOnlyKey(key.clone()),
             ReportableBug(what) => ReportableBug(what.clone()),
             Corruption { at } => Corruption { at: *at },
             #[cfg(feature = "failpoints")]
@@ -82,6 +86,13 @@ impl PartialEq for Error {
                     false
                 }
             }
+            ReadOnlyKey(ref l) => {
+                if let ReadOnlyKey(ref r) = *other {
+                    l == r
+                } else {
+                    false
+                }
+            }
             ReportableBug(ref l) => {
                 if let ReportableBug(ref r) = *other {
                     l == r
@@ -130,6 +141,9 @@ impl Display for Error {
                 write!(f, "Collection {:?} does not exist", name,)
             }
             Unsupported(ref e) => write!(f, "Unsupported: {}", e),
+            ReadOnlyKey(ref key) => {
+                write!(f, "Key {:?} is write-once and already exists", key)
+            }
             ReportableBug(ref e) => write!(
                 f,
                 "Unexpected bug has happened: {}. \
@@ -0,0 +1,38 @@
+use crate::DiskPtr;
+
+/// The outcome of re-reading every live page of a `Db` from
+/// disk, returned by `Db::scrub` and `Db::last_scrub`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScrubReport {
+    /// How many pages were checked. Pages that were only
+    /// written to since the log was last synced are not.
+    pub pages: u64,
+    /// The bytes of the log messages and blobs that were
+    /// read to check them.
+    pub bytes: u64,
+    /// Every page that had a message or blob that failed its
+    /// checksum, or could not be read at all.
+    pub corrupt: Vec<CorruptPage>,
+}
+
+impl ScrubReport {
+    /// Returns `true` if no page was corrupt.
+    pub fn is_ok(&self) -> bool {
+        self.corrupt.is_empty()
+    }
+}
+
+/// A page that `Db::scrub` found to be corrupt on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CorruptPage {
+    /// The page.
+    pub pid: u64,
+    /// The first of its messages or blobs that was corrupt.
+    pub at: DiskPtr,
+    /// Whether the page was resident in the cache, and so
+    /// was written out again from memory. The corrupt copy
+    /// on disk is no longer part of a repaired page. Reading
+    /// a page that was not repaired fails with
+    /// `Error::Corruption`.
+    pub repaired: bool,
+}
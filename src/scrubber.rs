@@ -0,0 +1,31 @@
+use std::sync::Weak;
+use std::time::Duration;
+
+use super::*;
+
+/// Spawns a thread that checks every page of a `Db` against
+/// the checksums of its messages on disk every
+/// `Config::scrub_every_ms`, until the returned `Periodic`
+/// is dropped, which stops a scrub that is under way.
+pub(crate) fn spawn(
+    pagecache: Weak<PageCache>,
+    scrub_every_ms: u64,
+) -> Periodic {
+    Periodic::spawn(
+        "scrubber",
+        Duration::from_millis(scrub_every_ms),
+        move |is_stopping| {
+            let pc = if let Some(pc) = pagecache.upgrade() {
+                pc
+            } else {
+                return false;
+            };
+
+            if let Err(e) = pc.scrub(true, is_stopping) {
+                error!("failed to scrub from background scrub thread: {}", e);
+            }
+            true
+        },
+    )
+    .unwrap()
+}
@@ -0,0 +1,403 @@
+use std::{
+    ops::{Bound, RangeBounds},
+    sync::Arc,
+};
+
+use parking_lot::{MutexGuard, RwLockReadGuard};
+
+use crate::{
+    pagecache::RecoveryGuard,
+    versions::{self, History},
+    *,
+};
+
+type Extract = Arc<dyn Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync>;
+
+struct IndexDef {
+    index: Tree,
+    extract: Extract,
+}
+
+/// The secondary indexes of a `Tree`, declared with
+/// `Tree::add_index`, and its version history, enabled with
+/// `Tree::keep_versions`.
+#[derive(Default)]
+pub(crate) struct Indexes {
+    defs: RwLock<Vec<IndexDef>>,
+    pub(crate) history: RwLock<Option<History>>,
+    // serializes the writes to an indexed tree, so that the
+    // index entries of a key are always removed and added in
+    // the same order as the values they were extracted from
+    write_lock: Mutex<()>,
+}
+
+/// A write to an indexed `Tree` that is in progress. It pins
+/// the log before the primary write is made, so that the
+/// primary write and the index and version writes that
+/// `commit` makes are recovered atomically.
+pub(crate) struct IndexWrite<'a> {
+    defs: RwLockReadGuard<'a, Vec<IndexDef>>,
+    history: RwLockReadGuard<'a, Option<History>>,
+    _write_lock: MutexGuard<'a, ()>,
+    peg: RecoveryGuard<'a>,
+}
+
+impl Indexes {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.defs.read().is_empty() && self.history.read().is_none()
+    }
+
+    /// Returns `None` without taking any locks if the `Tree`
+    /// has no indexes and keeps no versions.
+    pub(crate) fn begin<'a>(
+        &'a self,
+        context: &'a Context,
+        guard: &Guard,
+    ) -> Result<Option<IndexWrite<'a>>> {
+        let defs = self.defs.read();
+        let history = self.history.read();
+        if defs.is_empty() && history.is_none() {
+            return Ok(None);
+        }
+
+        let write_lock = self.write_lock.lock();
+        let peg = context.pin_log(guard)?;
+
+        Ok(Some(IndexWrite { defs, history, _write_lock: write_lock, peg }))
+    }
+
+    /// Holds off writes to the `Tree` while the watermark of
+    /// its history is raised.
+    pub(crate) fn set_version_watermark(&self, ts: Lsn) -> Result<()> {
+        let kept = self.history.read();
+        let _write_lock = self.write_lock.lock();
+        if let Some(history) = &*kept {
+            history.set_watermark(ts)
+        } else {
+            Err(versions::disabled())
+        }
+    }
+}
+
+impl<'a> IndexWrite<'a> {
+    /// Moves the index entries of `key` from the index keys
+    /// extracted from its `old` value to those extracted from
+    /// its `new` value, adds its `new` version to the history,
+    /// and seals the batch.
+    pub(crate) fn commit(
+        self,
+        key: &[u8],
+        old: Option<&[u8]>,
+        new: Option<&[u8]>,
+        guard: &Guard,
+    ) -> Result<()> {
+        for def in &*self.defs {
+            let old_index_key = old.and_then(|value| (def.extract)(value));
+            let new_index_key = new.and_then(|value| (def.extract)(value));
+            if old_index_key == new_index_key {
+                continue;
+            }
+            if let Some(index_key) = old_index_key {
+                let _ = def.index.remove_dup_inner(&index_key, key, guard)?;
+            }
+            if let Some(index_key) = new_index_key {
+                let _ = def.index.insert_dup_inner(&index_key, key, guard)?;
+            }
+        }
+
+        if let Some(history) = &*self.history {
+            history.record(self.peg.lsn(), key, new, guard)?;
+        }
+
+        self.peg.seal_batch(guard)
+    }
+}
+
+/// A secondary index over the values of a `Tree`, returned
+/// by `Tree::add_index`. Its entries map the index key that
+/// was extracted from each value to the key of that value,
+/// and are kept in a dup-sort `Tree`. Lookups return the
+/// matching key-value pairs of the indexed `Tree`, ordered
+/// by index key first and by key second.
+#[derive(Clone)]
+pub struct SecondaryIndex {
+    primary: Tree,
+    index: Tree,
+    extract: Extract,
+}
+
+impl Debug for SecondaryIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecondaryIndex")
+            .field("primary", &self.primary.name())
+            .field("index", &self.index.name())
+            .finish()
+    }
+}
+
+impl SecondaryIndex {
+    pub(crate) fn add<F>(
+        primary: &Tree,
+        index: &Tree,
+        extract: F,
+    ) -> Result<SecondaryIndex>
+    where
+        F: Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync + 'static,
+    {
+        if primary.context.read_only {
+            return Err(Error::Unsupported(
+                "the database is in read-only mode".to_owned(),
+            ));
+        }
+
+        let mut defs = primary.indexes.defs.write();
+
+        if index.tree_id == primary.tree_id
+            || defs.iter().any(|def| def.index.tree_id == index.tree_id)
+        {
+            return Err(Error::Unsupported(
+                "a Tree can't be indexed into itself, or into \
+                 the same index Tree twice"
+                    .to_owned(),
+            ));
+        }
+
+        index.set_dup_sort(true);
+
+        let secondary_index = SecondaryIndex {
+            primary: primary.clone(),
+            index: index.clone(),
+            extract: Arc::new(extract),
+        };
+
+        // an index tree that already has entries was filled
+        // when the index was declared in an earlier run
+        if index.is_empty() {
+            secondary_index.fill()?;
+        }
+
+        defs.push(IndexDef {
+            index: index.clone(),
+            extract: secondary_index.extract.clone(),
+        });
+
+        Ok(secondary_index)
+    }
+
+    /// Returns the dup-sort `Tree` that holds the entries of
+    /// this index.
+    pub fn index_tree(&self) -> &Tree {
+        &self.index
+    }
+
+    /// Create a double-ended iterator over the key-value
+    /// pairs of the indexed `Tree` whose values have the
+    /// given index key, in the order of their keys.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, IVec};
+    ///
+    /// let config = Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// // fruits are stored as "color:name", and indexed by color
+    /// let fruits = db.open_tree("fruits")?;
+    /// let by_color = fruits.add_index(&db.open_tree("by_color")?, |v| {
+    ///     v.iter().position(|b| *b == b':').map(|i| v[..i].to_vec())
+    /// })?;
+    ///
+    /// fruits.insert("1", "red:apple")?;
+    /// fruits.insert("2", "yellow:banana")?;
+    /// fruits.insert("3", "red:cherry")?;
+    ///
+    /// let red: Vec<IVec> =
+    ///     by_color.get("red").keys().collect::<Result<_, _>>()?;
+    /// assert_eq!(red, vec![IVec::from("1"), IVec::from("3")]);
+    ///
+    /// // the index is updated with every write to the fruits
+    /// fruits.insert("1", "green:apple")?;
+    /// assert_eq!(by_color.get("red").count(), 1);
+    /// assert_eq!(by_color.get("green").count(), 1);
+    /// # Ok(()) }
+    /// ```
+    pub fn get<K: AsRef<[u8]>>(&self, index_key: K) -> IndexIter {
+        let mut prefix = vec![];
+        escape_dup_key(index_key.as_ref(), &mut prefix);
+        prefix.extend_from_slice(&[0, 0]);
+        self.resolve(self.index.scan_prefix(prefix))
+    }
+
+    /// Create a double-ended iterator over the key-value
+    /// pairs of the indexed `Tree` whose values have an index
+    /// key that falls within the given range.
+    pub fn range<K, R>(&self, range: R) -> IndexIter
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        // every index key is followed by [0, 0], and no
+        // encoded key continues with [0, 1] after it
+        let bound = |key: &K, terminator: u8| {
+            let mut encoded = vec![];
+            escape_dup_key(key.as_ref(), &mut encoded);
+            encoded.extend_from_slice(&[0, terminator]);
+            encoded
+        };
+
+        let start = match range.start_bound() {
+            Bound::Included(key) => Bound::Included(bound(key, 0)),
+            Bound::Excluded(key) => Bound::Included(bound(key, 1)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(key) => Bound::Excluded(bound(key, 1)),
+            Bound::Excluded(key) => Bound::Excluded(bound(key, 0)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+
+        self.resolve(self.index.range::<Vec<u8>, _>((start, end)))
+    }
+
+    /// Create a double-ended iterator over the key-value
+    /// pairs of the indexed `Tree` whose values have an index
+    /// key that starts with the given prefix.
+    pub fn scan_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> IndexIter {
+        // escaping preserves prefixes, so the escaped prefix
+        // is a prefix of the entries of every matching key
+        let mut encoded = vec![];
+        escape_dup_key(prefix.as_ref(), &mut encoded);
+        self.resolve(self.index.scan_prefix(encoded))
+    }
+
+    /// Clears the index and fills it again from the current
+    /// contents of the indexed `Tree`, atomically. This is
+    /// needed if the indexed `Tree` was written to while the
+    /// index was not declared, or if the extraction function
+    /// has changed since the index was filled.
+    pub fn rebuild(&self) -> Result<()> {
+        let _write_lock = self.primary.indexes.write_lock.lock();
+        self.fill()
+    }
+
+    // Replaces the entries of the index with those extracted
+    // from every value of the indexed tree, in one batch.
+    fn fill(&self) -> Result<()> {
+        let guard = pin();
+        let peg = self.primary.context.pin_log(&guard)?;
+
+        for key_res in self.index.iter().keys() {
+            let _ = self.index.remove_inner(key_res?, &guard)?;
+        }
+
+        for kv_res in &self.primary {
+            let (key, value) = kv_res?;
+            if let Some(index_key) = (self.extract)(&value) {
+                let _ = self.index.insert_dup(index_key, key)?;
+            }
+        }
+
+        peg.seal_batch(&guard)
+    }
+
+    fn resolve(&self, iter: Iter) -> IndexIter {
+        IndexIter {
+            primary: self.primary.clone(),
+            extract: self.extract.clone(),
+            iter,
+        }
+    }
+}
+
+/// A double-ended iterator over the key-value pairs that a
+/// `SecondaryIndex` lookup matched.
+///
+/// Entries are checked against the current value of their
+/// key as they are returned, so a pair is skipped if its
+/// value was changed or removed after the lookup began.
+pub struct IndexIter {
+    primary: Tree,
+    extract: Extract,
+    iter: Iter,
+}
+
+impl IndexIter {
+    /// Iterate over the keys of the matched pairs.
+    pub fn keys(self) -> impl DoubleEndedIterator<Item = Result<IVec>> {
+        self.map(|r| r.map(|(k, _v)| k))
+    }
+
+    /// Iterate over the values of the matched pairs.
+    pub fn values(self) -> impl DoubleEndedIterator<Item = Result<IVec>> {
+        self.map(|r| r.map(|(_k, v)| v))
+    }
+
+    fn resolve(
+        &self,
+        entry: Result<(IVec, IVec)>,
+    ) -> Option<Result<(IVec, IVec)>> {
+        let (encoded, _) = match entry {
+            Ok(kv) => kv,
+            Err(e) => return Some(Err(e)),
+        };
+        let (index_key, key) = unescape_dup_key(&encoded);
+
+        match self.primary.get(&key) {
+            Ok(Some(value)) => {
+                let current = (self.extract)(&value);
+                if current.as_ref().map(AsRef::as_ref) == Some(&*index_key) {
+                    Some(Ok((key, value)))
+                } else {
+                    None
+                }
+            }
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+impl Iterator for IndexIter {
+    type Item = Result<(IVec, IVec)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = self.iter.next()?;
+            if let Some(res) = self.resolve(entry) {
+                return Some(res);
+            }
+        }
+    }
+}
+
+impl DoubleEndedIterator for IndexIter {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = self.iter.next_back()?;
+            if let Some(res) = self.resolve(entry) {
+                return Some(res);
+            }
+        }
+    }
+}
+
+// Splits an entry that was encoded by `Tree::dup_key` into
+// its index key and the key of the indexed value.
+fn unescape_dup_key(encoded: &[u8]) -> (Vec<u8>, IVec) {
+    let mut index_key = vec![];
+    let mut i = 0;
+    while i < encoded.len() {
+        if encoded[i] != 0 {
+            index_key.push(encoded[i]);
+            i += 1;
+        } else if encoded.get(i + 1) == Some(&0) {
+            return (index_key, IVec::from(&encoded[i + 2..]));
+        } else {
+            index_key.push(0);
+            i += 2;
+        }
+    }
+    panic!("index entry {:?} has no terminator", encoded);
+}
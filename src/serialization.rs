@@ -6,9 +6,10 @@ use std::{
 };
 
 use crate::{
-    node::{Index, Leaf},
+    node::{Index, Leaf, Value},
     pagecache::{
-        BatchManifest, MessageHeader, PageState, SegmentNumber, Snapshot,
+        BatchManifest, COMPRESSED_KIND_FLAG, FreeBatch, LeafFilter,
+        MessageHeader, PageState, SegmentNumber, Snapshot,
     },
     Data, DiskPtr, Error, IVec, Link, Meta, Node, Result,
 };
@@ -77,6 +78,22 @@ impl Serialize for BatchManifest {
     }
 }
 
+impl Serialize for FreeBatch {
+    fn serialized_size(&self) -> u64 {
+        self.0.iter().map(Serialize::serialized_size).sum()
+    }
+
+    fn serialize_into(&self, buf: &mut &mut [u8]) {
+        for pid in &self.0 {
+            pid.serialize_into(buf);
+        }
+    }
+
+    fn deserialize(buf: &mut &[u8]) -> Result<Self> {
+        Ok(FreeBatch(deserialize_sequence(buf)?))
+    }
+}
+
 impl Serialize for () {
     fn serialized_size(&self) -> u64 {
         0
@@ -100,16 +117,24 @@ impl Serialize for MessageHeader {
     fn serialize_into(&self, buf: &mut &mut [u8]) {
         crate::trace!("serializing {:?}", self);
         self.crc32.serialize_into(buf);
-        self.kind.into().serialize_into(buf);
+        let kind_byte = if self.compressed {
+            self.kind.into() | COMPRESSED_KIND_FLAG
+        } else {
+            self.kind.into()
+        };
+        kind_byte.serialize_into(buf);
         self.len.serialize_into(buf);
         self.segment_number.serialize_into(buf);
         self.pid.serialize_into(buf);
     }
 
     fn deserialize(buf: &mut &[u8]) -> Result<MessageHeader> {
+        let crc32 = u32::deserialize(buf)?;
+        let kind_byte = u8::deserialize(buf)?;
         Ok(MessageHeader {
-            crc32: u32::deserialize(buf)?,
-            kind: u8::deserialize(buf)?.into(),
+            crc32,
+            kind: (kind_byte & !COMPRESSED_KIND_FLAG).into(),
+            compressed: kind_byte & COMPRESSED_KIND_FLAG != 0,
             len: u64::deserialize(buf)?,
             segment_number: SegmentNumber(u64::deserialize(buf)?),
             pid: u64::deserialize(buf)?,
@@ -316,34 +341,79 @@ impl Serialize for u8 {
 
 impl Serialize for Meta {
     fn serialized_size(&self) -> u64 {
-        self.inner
-            .iter()
-            .map(|(k, v)| {
-                (k.len() as u64).serialized_size()
-                    + u64::try_from(k.len()).unwrap()
-                    + v.serialized_size()
-            })
-            .sum()
+        u64::from(self.user_version).serialized_size()
+            + self
+                .inner
+                .iter()
+                .map(|(k, v)| {
+                    (k.len() as u64).serialized_size()
+                        + u64::try_from(k.len()).unwrap()
+                        + v.serialized_size()
+                })
+                .sum::<u64>()
     }
 
     fn serialize_into(&self, buf: &mut &mut [u8]) {
+        u64::from(self.user_version).serialize_into(buf);
         serialize_2tuple_sequence(self.inner.iter(), buf);
     }
 
     fn deserialize(buf: &mut &[u8]) -> Result<Self> {
-        Ok(Meta { inner: deserialize_sequence(buf)? })
+        let user_version = u32::try_from(u64::deserialize(buf)?)
+            .map_err(|_| Error::Corruption { at: DiskPtr::Inline(94) })?;
+        Ok(Meta { inner: deserialize_sequence(buf)?, user_version })
+    }
+}
+
+impl Serialize for Value {
+    fn serialized_size(&self) -> u64 {
+        match self {
+            Value::Inline(value) => 1 + value.serialized_size(),
+            Value::Overflow(pid) => 1 + pid.serialized_size(),
+        }
+    }
+
+    fn serialize_into(&self, buf: &mut &mut [u8]) {
+        match self {
+            Value::Inline(value) => {
+                0_u8.serialize_into(buf);
+                value.serialize_into(buf);
+            }
+            Value::Overflow(pid) => {
+                1_u8.serialize_into(buf);
+                pid.serialize_into(buf);
+            }
+        }
+    }
+
+    fn deserialize(buf: &mut &[u8]) -> Result<Self> {
+        if buf.is_empty() {
+            return Err(Error::Corruption { at: DiskPtr::Inline(225) });
+        }
+        let discriminant = buf[0];
+        *buf = &buf[1..];
+        Ok(match discriminant {
+            0 => Value::Inline(IVec::deserialize(buf)?),
+            1 => Value::Overflow(u64::deserialize(buf)?),
+            _ => return Err(Error::Corruption { at: DiskPtr::Inline(226) }),
+        })
     }
 }
 
 impl Serialize for Link {
     fn serialized_size(&self) -> u64 {
         match self {
-            Link::Set(key, value) => {
+            Link::Set(key, Value::Inline(value)) => {
                 1 + (key.len() as u64).serialized_size()
                     + (value.len() as u64).serialized_size()
                     + u64::try_from(key.len()).unwrap()
                     + u64::try_from(value.len()).unwrap()
             }
+            Link::Set(key, Value::Overflow(pid)) => {
+                1 + (key.len() as u64).serialized_size()
+                    + u64::try_from(key.len()).unwrap()
+                    + pid.serialized_size()
+            }
             Link::Del(key) => {
                 1 + (key.len() as u64).serialized_size()
                     + u64::try_from(key.len()).unwrap()
@@ -355,11 +425,16 @@ impl Serialize for Link {
 
     fn serialize_into(&self, buf: &mut &mut [u8]) {
         match self {
-            Link::Set(key, value) => {
+            Link::Set(key, Value::Inline(value)) => {
                 0_u8.serialize_into(buf);
                 key.serialize_into(buf);
                 value.serialize_into(buf);
             }
+            Link::Set(key, Value::Overflow(pid)) => {
+                5_u8.serialize_into(buf);
+                key.serialize_into(buf);
+                pid.serialize_into(buf);
+            }
             Link::Del(key) => {
                 1_u8.serialize_into(buf);
                 key.serialize_into(buf);
@@ -384,11 +459,18 @@ impl Serialize for Link {
         let discriminant = buf[0];
         *buf = &buf[1..];
         Ok(match discriminant {
-            0 => Link::Set(IVec::deserialize(buf)?, IVec::deserialize(buf)?),
+            0 => Link::Set(
+                IVec::deserialize(buf)?,
+                Value::Inline(IVec::deserialize(buf)?),
+            ),
             1 => Link::Del(IVec::deserialize(buf)?),
             2 => Link::ParentMergeIntention(u64::deserialize(buf)?),
             3 => Link::ParentMergeConfirm,
             4 => Link::ChildMergeCap,
+            5 => Link::Set(
+                IVec::deserialize(buf)?,
+                Value::Overflow(u64::deserialize(buf)?),
+            ),
             _ => return Err(Error::Corruption { at: DiskPtr::Inline(220) }),
         })
     }
@@ -400,8 +482,9 @@ impl Serialize for Node {
         let merging_child_sz =
             self.merging_child.unwrap_or(0_u64).serialized_size();
 
-        2 + next_sz
+        1 + next_sz
             + merging_child_sz
+            + u64::from(self.prefix_len).serialized_size()
             + self.lo.serialized_size()
             + self.hi.serialized_size()
             + self.data.serialized_size()
@@ -411,7 +494,7 @@ impl Serialize for Node {
         self.next.unwrap_or(0_u64).serialize_into(buf);
         self.merging_child.unwrap_or(0_u64).serialize_into(buf);
         self.merging.serialize_into(buf);
-        self.prefix_len.serialize_into(buf);
+        u64::from(self.prefix_len).serialize_into(buf);
         self.lo.serialize_into(buf);
         self.hi.serialize_into(buf);
         self.data.serialize_into(buf);
@@ -428,7 +511,8 @@ impl Serialize for Node {
                 Some(merging_child)
             },
             merging: bool::deserialize(buf)?,
-            prefix_len: u8::deserialize(buf)?,
+            prefix_len: u16::try_from(u64::deserialize(buf)?)
+                .map_err(|_| Error::Corruption { at: DiskPtr::Inline(227) })?,
             lo: IVec::deserialize(buf)?,
             hi: IVec::deserialize(buf)?,
             data: Data::deserialize(buf)?,
@@ -436,6 +520,42 @@ impl Serialize for Node {
     }
 }
 
+impl Serialize for LeafFilter {
+    fn serialized_size(&self) -> u64 {
+        self.lsn.serialized_size()
+            + self.lo.serialized_size()
+            + self.hi.serialized_size()
+            + u64::from(self.prefix_len).serialized_size()
+            + self.hashes.serialized_size()
+            + self.bits.serialized_size()
+    }
+
+    fn serialize_into(&self, buf: &mut &mut [u8]) {
+        self.lsn.serialize_into(buf);
+        self.lo.serialize_into(buf);
+        self.hi.serialize_into(buf);
+        u64::from(self.prefix_len).serialize_into(buf);
+        self.hashes.serialize_into(buf);
+        self.bits.serialize_into(buf);
+    }
+
+    fn deserialize(buf: &mut &[u8]) -> Result<Self> {
+        let filter = LeafFilter {
+            lsn: i64::deserialize(buf)?,
+            lo: IVec::deserialize(buf)?,
+            hi: IVec::deserialize(buf)?,
+            prefix_len: u16::try_from(u64::deserialize(buf)?)
+                .map_err(|_| Error::Corruption { at: DiskPtr::Inline(228) })?,
+            hashes: u8::deserialize(buf)?,
+            bits: IVec::deserialize(buf)?,
+        };
+        if filter.bits.is_empty() {
+            return Err(Error::Corruption { at: DiskPtr::Inline(229) });
+        }
+        Ok(filter)
+    }
+}
+
 impl Serialize for Snapshot {
     fn serialized_size(&self) -> u64 {
         self.last_lsn.serialized_size()
@@ -470,19 +590,36 @@ impl Serialize for Snapshot {
 impl Serialize for Data {
     fn serialized_size(&self) -> u64 {
         match self {
+            Data::Leaf(ref leaf) if leaf.has_overflow() => {
+                1_u64
+                    + (leaf.keys.len() as u64).serialized_size()
+                    + leaf
+                        .keys
+                        .iter()
+                        .zip(leaf.values.iter())
+                        .map(|(k, v)| {
+                            (k.len() as u64).serialized_size()
+                                + k.len() as u64
+                                + v.serialized_size()
+                        })
+                        .sum::<u64>()
+            }
             Data::Leaf(ref leaf) => {
                 1_u64
                     + (leaf.keys.len() as u64).serialized_size()
                     + leaf
                         .keys
                         .iter()
-                        .enumerate()
-                        .map(|(idx, k)| {
-                            let v = &leaf.values[idx];
+                        .zip(leaf.values.iter())
+                        .map(|(k, v)| {
+                            let v_len = match v {
+                                Value::Inline(v) => v.len() as u64,
+                                Value::Overflow(_) => unreachable!(),
+                            };
                             (k.len() as u64).serialized_size()
-                                + (v.len() as u64).serialized_size()
+                                + v_len.serialized_size()
                                 + k.len() as u64
-                                + v.len() as u64
+                                + v_len
                         })
                         .sum::<u64>()
             }
@@ -507,13 +644,25 @@ impl Serialize for Data {
     fn serialize_into(&self, buf: &mut &mut [u8]) {
         match self {
             Data::Leaf(leaf) => {
-                0_u8.serialize_into(buf);
+                // leaves without overflow values keep the
+                // original encoding, where each value is a
+                // plain length-prefixed byte string.
+                let has_overflow = leaf.has_overflow();
+                if has_overflow {
+                    2_u8.serialize_into(buf);
+                } else {
+                    0_u8.serialize_into(buf);
+                }
                 (leaf.keys.len() as u64).serialize_into(buf);
                 for key in &leaf.keys {
                     key.serialize_into(buf);
                 }
                 for value in &leaf.values {
-                    value.serialize_into(buf);
+                    if has_overflow {
+                        value.serialize_into(buf);
+                    } else if let Value::Inline(value) = value {
+                        value.serialize_into(buf);
+                    }
                 }
             }
             Data::Index(index) => {
@@ -539,12 +688,21 @@ impl Serialize for Data {
         Ok(match discriminant {
             0 => Data::Leaf(Leaf {
                 keys: deserialize_bounded_sequence(buf, len)?,
-                values: deserialize_bounded_sequence(buf, len)?,
+                values: deserialize_bounded_sequence::<IVec, Vec<IVec>>(
+                    buf, len,
+                )?
+                .into_iter()
+                .map(Value::Inline)
+                .collect(),
             }),
             1 => Data::Index(Index {
                 keys: deserialize_bounded_sequence(buf, len)?,
                 pointers: deserialize_bounded_sequence(buf, len)?,
             }),
+            2 => Data::Leaf(Leaf {
+                keys: deserialize_bounded_sequence(buf, len)?,
+                values: deserialize_bounded_sequence(buf, len)?,
+            }),
             _ => return Err(Error::Corruption { at: DiskPtr::Inline(115) }),
         })
     }
@@ -763,6 +921,7 @@ mod qc {
                 crc32: g.gen(),
                 len: g.gen(),
                 kind: MessageKind::arbitrary(g),
+                compressed: g.gen(),
                 segment_number: SegmentNumber(SpreadU64::arbitrary(g).0),
                 pid: g.gen(),
             }
@@ -771,7 +930,7 @@ mod qc {
 
     impl Arbitrary for MessageKind {
         fn arbitrary<G: Gen>(g: &mut G) -> MessageKind {
-            g.gen_range(0, 12).into()
+            g.gen_range(0, 15).into()
         }
     }
 
@@ -840,7 +999,7 @@ mod qc {
                 next,
                 merging_child,
                 merging: bool::arbitrary(g),
-                prefix_len: u8::arbitrary(g),
+                prefix_len: u16::arbitrary(g),
                 lo: IVec::arbitrary(g),
                 hi: IVec::arbitrary(g),
                 data: Data::arbitrary(g),
@@ -871,7 +1030,7 @@ mod qc {
         fn arbitrary<G: Gen>(g: &mut G) -> Link {
             let discriminant = g.gen_range(0, 5);
             match discriminant {
-                0 => Link::Set(IVec::arbitrary(g), IVec::arbitrary(g)),
+                0 => Link::Set(IVec::arbitrary(g), Value::arbitrary(g)),
                 1 => Link::Del(IVec::arbitrary(g)),
                 2 => Link::ParentMergeIntention(u64::arbitrary(g)),
                 3 => Link::ParentMergeConfirm,
@@ -881,6 +1040,16 @@ mod qc {
         }
     }
 
+    impl Arbitrary for Value {
+        fn arbitrary<G: Gen>(g: &mut G) -> Value {
+            if g.gen() {
+                Value::Inline(IVec::arbitrary(g))
+            } else {
+                Value::Overflow(g.gen())
+            }
+        }
+    }
+
     impl Arbitrary for IVec {
         fn arbitrary<G: Gen>(g: &mut G) -> IVec {
             let v: Vec<u8> = Arbitrary::arbitrary(g);
@@ -895,11 +1064,19 @@ mod qc {
 
     impl Arbitrary for Meta {
         fn arbitrary<G: Gen>(g: &mut G) -> Meta {
-            Meta { inner: Arbitrary::arbitrary(g) }
+            Meta {
+                inner: Arbitrary::arbitrary(g),
+                user_version: Arbitrary::arbitrary(g),
+            }
         }
 
         fn shrink(&self) -> Box<dyn Iterator<Item = Meta>> {
-            Box::new(self.inner.shrink().map(|inner| Meta { inner }))
+            let user_version = self.user_version;
+            Box::new(
+                self.inner
+                    .shrink()
+                    .map(move |inner| Meta { inner, user_version }),
+            )
         }
     }
 
@@ -1032,6 +1209,10 @@ mod qc {
             prop_serialize(item)
         }
 
+        fn value(item: Value) -> bool {
+            prop_serialize(item)
+        }
+
         fn msg_header(item: MessageHeader) -> bool {
             prop_serialize(item)
         }
@@ -0,0 +1,480 @@
+//! A harness for running a database deterministically, in
+//! the style of the simulation testing done by `FoundationDB`.
+//! The log is written to a simulated disk that loses or
+//! tears the writes that weren't synced when the simulated
+//! machine crashes, the flusher is run by the harness on a
+//! simulated clock instead of on its own thread, and the
+//! work that would be sent to the IO threadpool is run on
+//! the calling thread. Every random decision is drawn from
+//! one seeded generator, so a history that finds a bug can
+//! be replayed from its seed.
+//!
+//! A simulated database must only be used from the thread
+//! that drives its simulation, which lets it reclaim pages
+//! and segments as soon as they are freed instead of
+//! waiting for other threads to be done with them.
+//!
+//! Only the log is simulated. The config file, snapshots
+//! and blobs are written to a temporary directory, which
+//! is removed when the `Simulation` is dropped, and survive
+//! crashes intact.
+//!
+//! Requires the `simulation` feature, which runs the work
+//! of the IO threadpool on the calling thread for every
+//! database in the process, so it should only be enabled
+//! for tests.
+//!
+//! # Examples
+//!
+//! ```
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use std::time::Duration;
+//!
+//! use sled::simulation::Simulation;
+//!
+//! let simulation = Simulation::new(42);
+//! let config = simulation.config().segment_size(1024);
+//! let db = config.open()?;
+//!
+//! for i in 0..simulation.random(100) {
+//!     db.insert(i.to_be_bytes(), vec![0; 64])?;
+//!     simulation.tick(&db, Duration::from_millis(10))?;
+//! }
+//! db.flush()?;
+//!
+//! // whatever wasn't flushed may be lost or torn
+//! db.insert(b"unflushed", b"")?;
+//! simulation.crash();
+//! drop(db);
+//!
+//! let db = config.open()?;
+//! assert!(db.len() >= 1);
+//! # Ok(()) }
+//! ```
+
+use std::{
+    convert::TryFrom,
+    fmt::{self, Debug},
+    io,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+
+use crate::{pagecache::LogOffset, *};
+
+/// A seeded simulation of the machine that a database runs
+/// on. Every database opened with its `config` shares its
+/// disk, clock and random number generator.
+#[derive(Debug)]
+pub struct Simulation {
+    shared: Arc<Shared>,
+    config: Config,
+    path: PathBuf,
+}
+
+// What the databases opened with a `Simulation`'s config
+// share with it.
+pub(crate) struct Shared {
+    seed: u64,
+    rng: Mutex<u64>,
+    now: Mutex<Duration>,
+    last_flush: Mutex<Duration>,
+    disk: Mutex<Disk>,
+}
+
+impl Debug for Shared {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Simulation {{ seed: {} }}", self.seed)
+    }
+}
+
+#[derive(Default)]
+struct Disk {
+    // what reads see
+    visible: Vec<u8>,
+    // what survives a crash
+    durable: Vec<u8>,
+    // the writes since the last sync, in the order in which
+    // they happened
+    pending: Vec<(LogOffset, Vec<u8>)>,
+    // bumped by every crash, to stop the files that were
+    // opened before it from being used
+    generation: u64,
+    // the number of writes to let through before the power
+    // is cut during the next one, and how that one is
+    // damaged
+    power_loss: Option<(u64, Damage)>,
+}
+
+/// How the write to the log that is in progress when
+/// `Simulation::power_loss_at` cuts the power reaches the
+/// disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Damage {
+    /// Only a prefix of the write, of a random length that
+    /// may be zero, reaches the disk.
+    Truncate,
+    /// All of the write reaches the disk, with one random
+    /// bit flipped.
+    BitFlip,
+    /// A random subset of the write's 512 byte sectors
+    /// reaches the disk, so that a later part of it may
+    /// survive without an earlier one.
+    Reorder,
+}
+
+const SECTOR_SIZE: usize = 512;
+
+fn to_usize(n: u64) -> usize {
+    usize::try_from(n).unwrap()
+}
+
+fn write_at(data: &mut Vec<u8>, offset: LogOffset, buf: &[u8]) {
+    let start = to_usize(offset);
+    let end = start + buf.len();
+    if data.len() < end {
+        data.resize(end, 0);
+    }
+    data[start..end].copy_from_slice(buf);
+}
+
+impl Shared {
+    // Returns a number in `0..n`, drawn from a splitmix64
+    // generator.
+    fn random(&self, n: u64) -> u64 {
+        let mut rng = self.rng.lock();
+        *rng = rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = *rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        if n == 0 { 0 } else { z % n }
+    }
+
+    pub(crate) fn now(&self) -> Duration {
+        *self.now.lock()
+    }
+
+    // Keeps, loses or tears each write that hadn't been
+    // synced, and stops the files that are open from being
+    // used.
+    fn crash(&self, disk: &mut Disk) {
+        let pending = std::mem::replace(&mut disk.pending, vec![]);
+        let mut image = disk.durable.clone();
+
+        for (offset, buf) in pending {
+            match self.random(3) {
+                0 => write_at(&mut image, offset, &buf),
+                1 => {}
+                _ => {
+                    let len = to_usize(self.random(buf.len() as u64));
+                    write_at(&mut image, offset, &buf[..len]);
+                }
+            }
+        }
+
+        disk.visible = image.clone();
+        disk.durable = image;
+        disk.generation += 1;
+        disk.power_loss = None;
+    }
+
+    // Writes the parts of `buf` that `damage` lets through
+    // to both what reads see and what survives a crash.
+    fn write_damaged(
+        &self,
+        disk: &mut Disk,
+        offset: LogOffset,
+        buf: &[u8],
+        damage: Damage,
+    ) {
+        let mut write = |start: usize, part: &[u8]| {
+            let at = offset + start as LogOffset;
+            write_at(&mut disk.visible, at, part);
+            write_at(&mut disk.durable, at, part);
+        };
+
+        match damage {
+            Damage::Truncate => {
+                let len = to_usize(self.random(buf.len() as u64 + 1));
+                write(0, &buf[..len]);
+            }
+            Damage::BitFlip => {
+                let mut flipped = buf.to_vec();
+                if !flipped.is_empty() {
+                    let bits = flipped.len() as u64 * 8;
+                    let bit = to_usize(self.random(bits));
+                    flipped[bit / 8] ^= 1 << (bit % 8);
+                }
+                write(0, &flipped);
+            }
+            Damage::Reorder => {
+                for (i, sector) in buf.chunks(SECTOR_SIZE).enumerate() {
+                    if self.random(2) == 0 {
+                        write(i * SECTOR_SIZE, sector);
+                    }
+                }
+            }
+        }
+    }
+
+    pub(crate) fn open(self: &Arc<Self>) -> SimulatedFile {
+        let generation = self.disk.lock().generation;
+        SimulatedFile { shared: self.clone(), generation }
+    }
+}
+
+impl Simulation {
+    /// Creates a simulation whose random decisions are all
+    /// drawn from a generator seeded with `seed`.
+    pub fn new(seed: u64) -> Simulation {
+        let shared = Arc::new(Shared {
+            seed,
+            rng: Mutex::new(seed),
+            now: Mutex::new(Duration::from_secs(0)),
+            last_flush: Mutex::new(Duration::from_secs(0)),
+            disk: Mutex::new(Disk::default()),
+        });
+
+        let path = Config::gen_temp_path();
+
+        let config = Config::new()
+            .path(&path)
+            .flush_every_ms(Some(500))
+            .simulation(shared.clone());
+
+        Simulation { shared, config, path }
+    }
+
+    /// The seed that the simulation was created with.
+    pub fn seed(&self) -> u64 {
+        self.shared.seed
+    }
+
+    /// Returns a `Config` for opening a database on the
+    /// simulated machine. Its `flush_every_ms` is how often
+    /// `tick` runs the flusher, which never gets a thread of
+    /// its own. The rest of it may be changed as usual.
+    pub fn config(&self) -> Config {
+        self.config.clone()
+    }
+
+    /// Returns a number in `0..n` from the simulation's
+    /// seeded generator, for choosing what a test does next.
+    /// Returns 0 if `n` is 0.
+    pub fn random(&self, n: u64) -> u64 {
+        self.shared.random(n)
+    }
+
+    /// How much simulated time has passed.
+    pub fn now(&self) -> Duration {
+        self.shared.now()
+    }
+
+    /// Advances the simulated clock by `elapsed`, then does
+    /// a round of the flusher's work for `db` if at least
+    /// `flush_every_ms` has passed since the last round: it
+    /// flushes the log, relocates pages for garbage
+    /// collection, and takes a snapshot if one is due.
+    pub fn tick(&self, db: &Db, elapsed: Duration) -> Result<()> {
+        let now = {
+            let mut now = self.shared.now.lock();
+            *now += elapsed;
+            *now
+        };
+
+        let flush_every_ms = if let Some(fem) = db.context.flush_every_ms {
+            fem
+        } else {
+            return Ok(());
+        };
+        let flush_every = Duration::from_millis(flush_every_ms);
+
+        {
+            let mut last_flush = self.shared.last_flush.lock();
+            if now - *last_flush < flush_every {
+                return Ok(());
+            }
+            *last_flush = now;
+        }
+
+        let pagecache = &db.context.pagecache;
+        let _ = pagecache.flush()?;
+        while pagecache.attempt_gc()? {}
+        pagecache.snapshot_if_due()?;
+        pagecache.config.file.sync_all()?;
+
+        Ok(())
+    }
+
+    /// Crashes the simulated machine. Each write to the log
+    /// that hadn't been synced is randomly kept, lost, or
+    /// torn partway through, and every database that was
+    /// open returns errors from then on, so it should be
+    /// dropped and opened again.
+    pub fn crash(&self) {
+        let mut disk = self.shared.disk.lock();
+        self.shared.crash(&mut disk);
+    }
+
+    /// Arranges for the power of the simulated machine to be
+    /// cut during the `n`th write to the log from now,
+    /// counting from 1. The writes before it that hadn't
+    /// been synced are treated as by `crash`, the write
+    /// itself reaches the disk as `damage` describes, and the
+    /// write and every later use of a database that was open
+    /// returns an error. Replaces any power loss that was
+    /// arranged before and hasn't happened yet.
+    pub fn power_loss_at(&self, n: u64, damage: Damage) {
+        assert!(n > 0, "writes are counted from 1");
+        self.shared.disk.lock().power_loss = Some((n, damage));
+    }
+
+    /// Cancels the power loss arranged by `power_loss_at`, if
+    /// it hasn't happened yet.
+    pub fn cancel_power_loss(&self) {
+        self.shared.disk.lock().power_loss = None;
+    }
+
+    /// The number of times that the simulated machine has
+    /// crashed or lost power.
+    pub fn crashes(&self) -> u64 {
+        self.shared.disk.lock().generation
+    }
+
+    /// Returns a checksum of the log as it is currently seen
+    /// by reads, for checking that two runs with the same
+    /// seed wrote exactly the same bytes.
+    pub fn log_checksum(&self) -> u32 {
+        crc32(&self.shared.disk.lock().visible)
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// The log of a database opened on a simulated machine.
+pub(crate) struct SimulatedFile {
+    shared: Arc<Shared>,
+    generation: u64,
+}
+
+impl Debug for SimulatedFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SimulatedFile({})", self.generation)
+    }
+}
+
+impl SimulatedFile {
+    // Locks the disk, unless the machine has crashed since
+    // the file was opened.
+    fn disk(&self) -> io::Result<parking_lot::MutexGuard<'_, Disk>> {
+        let disk = self.shared.disk.lock();
+        if disk.generation == self.generation {
+            Ok(disk)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "the simulated machine crashed",
+            ))
+        }
+    }
+
+    pub(crate) fn pread_exact_or_eof(
+        &self,
+        buf: &mut [u8],
+        offset: LogOffset,
+    ) -> io::Result<usize> {
+        let disk = self.disk()?;
+        let start = std::cmp::min(to_usize(offset), disk.visible.len());
+        let src = &disk.visible[start..];
+        let len = std::cmp::min(src.len(), buf.len());
+        buf[..len].copy_from_slice(&src[..len]);
+        Ok(len)
+    }
+
+    pub(crate) fn pwrite_all(
+        &self,
+        buf: &[u8],
+        offset: LogOffset,
+    ) -> io::Result<()> {
+        let mut disk = self.disk()?;
+
+        if let Some((n, damage)) = disk.power_loss {
+            if n > 1 {
+                disk.power_loss = Some((n - 1, damage));
+            } else {
+                self.shared.crash(&mut disk);
+                self.shared.write_damaged(&mut disk, offset, buf, damage);
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "the simulated machine lost power",
+                ));
+            }
+        }
+
+        write_at(&mut disk.visible, offset, buf);
+        disk.pending.push((offset, buf.to_vec()));
+        Ok(())
+    }
+
+    pub(crate) fn sync_all(&self) -> io::Result<()> {
+        let mut disk = self.disk()?;
+        disk.durable = disk.visible.clone();
+        disk.pending.clear();
+        Ok(())
+    }
+
+    pub(crate) fn sync_range(
+        &self,
+        offset: LogOffset,
+        len: u64,
+    ) -> io::Result<()> {
+        let mut disk = self.disk()?;
+        let start = std::cmp::min(to_usize(offset), disk.visible.len());
+        let end = std::cmp::min(to_usize(offset + len), disk.visible.len());
+        let synced = disk.visible[start..end].to_vec();
+        write_at(&mut disk.durable, start as LogOffset, &synced);
+        disk.pending.retain(|(pending_offset, buf)| {
+            *pending_offset < offset
+                || pending_offset + buf.len() as LogOffset > offset + len
+        });
+        Ok(())
+    }
+
+    pub(crate) fn len(&self) -> io::Result<u64> {
+        Ok(self.disk()?.visible.len() as u64)
+    }
+
+    // like changes to the length, holes are durable right
+    // away, as if the file's metadata was always synced
+    pub(crate) fn punch_hole(
+        &self,
+        offset: LogOffset,
+        len: u64,
+    ) -> io::Result<bool> {
+        let mut guard = self.disk()?;
+        let disk = &mut *guard;
+        for data in &mut [&mut disk.visible, &mut disk.durable] {
+            let start = std::cmp::min(to_usize(offset), data.len());
+            let end = std::cmp::min(to_usize(offset + len), data.len());
+            data[start..end].iter_mut().for_each(|byte| *byte = 0);
+        }
+        Ok(true)
+    }
+
+    // changes to the length are durable right away, as if
+    // the file's metadata was always synced
+    pub(crate) fn set_len(&self, len: u64) -> io::Result<()> {
+        let mut disk = self.disk()?;
+        disk.visible.resize(to_usize(len), 0);
+        disk.durable.resize(to_usize(len), 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+use super::{histogram::Snapshot, *};
+
+/// An operation whose latency may be covered by a `LatencySlo`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SloOperation {
+    /// Point reads, including `get_lt` and `get_gt`.
+    Get,
+    /// `insert`.
+    Insert,
+    /// `remove`.
+    Remove,
+    /// `compare_and_swap`.
+    CompareAndSwap,
+    /// `merge`.
+    Merge,
+    /// Each step of a forward iterator.
+    Scan,
+    /// Each step of a reverse iterator.
+    ReverseScan,
+    /// Making writes durable, as `flush` does.
+    Flush,
+}
+
+impl SloOperation {
+    fn histogram(self) -> &'static Histogram {
+        match self {
+            SloOperation::Get => &M.tree_get,
+            SloOperation::Insert => &M.tree_set,
+            SloOperation::Remove => &M.tree_del,
+            SloOperation::CompareAndSwap => &M.tree_cas,
+            SloOperation::Merge => &M.tree_merge,
+            SloOperation::Scan => &M.tree_scan,
+            SloOperation::ReverseScan => &M.tree_reverse_scan,
+            SloOperation::Flush => &M.make_stable,
+        }
+    }
+}
+
+/// A latency objective, such as "the 99.9th percentile of
+/// `get` latency stays below 5ms in every minute".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencySlo {
+    /// The operation whose latency is checked.
+    pub operation: SloOperation,
+    /// The percentile [0-100] that is checked, such as 99.9.
+    pub percentile: f64,
+    /// The latency that the percentile may not exceed.
+    pub threshold: Duration,
+    /// The length of the windows that the percentile is
+    /// computed over.
+    pub window: Duration,
+}
+
+/// A window in which a `LatencySlo` was not met.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SloViolation {
+    /// The objective that was violated.
+    pub slo: LatencySlo,
+    /// The latency at the objective's percentile.
+    pub observed: Duration,
+    /// The number of operations measured in the window.
+    pub operations: usize,
+}
+
+/// Checks a `LatencySlo` at the end of each window from a
+/// background thread, until dropped. Returned by
+/// `Db::register_latency_slo`.
+#[derive(Debug)]
+pub struct SloMonitor {
+    _worker: Periodic,
+}
+
+impl SloMonitor {
+    pub(crate) fn start<F>(slo: LatencySlo, on_violation: F) -> Result<Self>
+    where
+        F: Fn(&SloViolation) + Send + 'static,
+    {
+        if cfg!(not(feature = "metrics")) {
+            return Err(Error::Unsupported(
+                "latency SLOs require metrics, which are only \
+                 collected with the metrics feature"
+                    .to_owned(),
+            ));
+        }
+        if !(slo.percentile > 0. && slo.percentile <= 100.) {
+            return Err(Error::Unsupported(
+                "SLO percentile must be above 0 and at most 100".to_owned(),
+            ));
+        }
+        if slo.window == Duration::from_secs(0) {
+            return Err(Error::Unsupported(
+                "SLO window must be above 0".to_owned(),
+            ));
+        }
+
+        let histogram = slo.operation.histogram();
+        let mut window_start = histogram.snapshot();
+
+        let worker =
+            Periodic::spawn("slo monitor", slo.window, move |_is_stopping| {
+                let window_end = histogram.snapshot();
+                if let Some(violation) =
+                    check_window(slo, &window_start, &window_end)
+                {
+                    on_violation(&violation);
+                }
+                window_start = window_end;
+                true
+            })?;
+
+        Ok(Self { _worker: worker })
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+#[allow(clippy::float_arithmetic)]
+fn check_window(
+    slo: LatencySlo,
+    window_start: &Snapshot,
+    window_end: &Snapshot,
+) -> Option<SloViolation> {
+    let ticks_per_ns = clock_ticks_per_ns();
+    let threshold = slo.threshold.as_nanos() as f64 * ticks_per_ns;
+
+    // NAN when nothing was measured, which never exceeds
+    // the threshold
+    let observed = window_end.percentile_since(window_start, slo.percentile);
+
+    if observed > threshold {
+        Some(SloViolation {
+            slo,
+            observed: Duration::from_nanos((observed / ticks_per_ns) as u64),
+            operations: window_end.count_since(window_start),
+        })
+    } else {
+        None
+    }
+}
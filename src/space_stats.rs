@@ -0,0 +1,87 @@
+use crate::Lsn;
+
+/// A breakdown of the space a `Db` uses on disk, returned
+/// by `Db::space_stats`. Every field but the amplification
+/// ratios is a number of bytes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpaceStats {
+    /// The space used by the log file. Segments that have
+    /// been freed by garbage collection have their space
+    /// returned to the filesystem on linux, and are not
+    /// counted here.
+    pub log_bytes: u64,
+    /// The space used by the files that hold values too
+    /// large to be stored inline in the log.
+    pub blob_bytes: u64,
+    /// The space used by the snapshot files that speed up
+    /// recovery.
+    pub snapshot_bytes: u64,
+    /// The sum of the `live_bytes` of every segment.
+    pub live_bytes: u64,
+    /// The sum of the `dead_bytes` of every segment.
+    pub dead_bytes: u64,
+    /// Every segment of the log that is not free, in the
+    /// order of their offsets in the log file.
+    pub segments: Vec<SegmentSpace>,
+    /// The space used by the log and blob files divided by
+    /// the logical size of every page, as with
+    /// `Db::space_amplification`.
+    pub space_amplification: f64,
+    /// The bytes written to the log since the `Db` was
+    /// opened, divided by the bytes of the updates that were
+    /// linked onto pages in that time. The rest is spent
+    /// consolidating pages, relocating them during garbage
+    /// collection, and padding the ends of segments. This is
+    /// 0 until an update has been linked.
+    pub write_amplification: f64,
+}
+
+/// The space used by a single segment of the log, as part
+/// of `SpaceStats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentSpace {
+    /// The offset of the segment in the log file.
+    pub offset: u64,
+    /// The LSN of the start of the segment.
+    pub lsn: Lsn,
+    /// Where the segment is in its lifecycle.
+    pub state: SegmentState,
+    /// The bytes of the messages in the segment that still
+    /// make up part of a page. This is 0 for draining
+    /// segments, whose pages are being relocated.
+    pub live_bytes: u64,
+    /// The bytes of the segment that garbage collection
+    /// could reclaim. For active segments this is only the
+    /// messages that have been replaced, while for the rest
+    /// it is everything that is not live, including the
+    /// padding at the end of the segment.
+    pub dead_bytes: u64,
+}
+
+/// Where a segment of the log is in its lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentState {
+    /// The segment is being written to.
+    Active,
+    /// The segment is no longer being written to, and will
+    /// be drained once enough of it is dead.
+    Inactive,
+    /// The pages in the segment are being relocated so that
+    /// it can be freed.
+    Draining,
+}
+
+/// What a compaction did, returned by `Db::compact` and
+/// `Db::compact_segment`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// The number of segments that compaction started
+    /// draining or freed.
+    pub segments_drained: usize,
+    /// The number of pages that were rewritten to the tip of
+    /// the log, including those of segments that garbage
+    /// collection had already started draining.
+    pub pages_relocated: usize,
+    /// The bytes written to the log by those rewrites.
+    pub bytes_relocated: u64,
+}
@@ -65,8 +65,7 @@ where
         formatter: &mut fmt::Formatter<'_>,
     ) -> Result<(), fmt::Error> {
         let guard = crossbeam_epoch::pin();
-        let head = self.head(&guard);
-        let iter = Iter::from_ptr(head, &guard);
+        let iter = self.iter(&guard);
 
         formatter.write_str("Stack [")?;
         let mut written = false;
@@ -129,10 +128,18 @@ impl<T: Send + Sync + 'static> Stack<T> {
         iter
     }
 
+    /// Iterate over the items currently in the stack, from
+    /// the most recently pushed one.
+    pub fn iter<'a>(&self, guard: &'a Guard) -> Iter<'a, T> {
+        Iter { inner: self.head(guard), guard }
+    }
+
     /// Pop the next item off the stack. Returns None if nothing is there.
     #[cfg(any(test, feature = "event_log"))]
-    pub(crate) fn pop(&self, guard: &Guard) -> Option<T> {
-        use std::ptr;
+    pub(crate) fn pop(&self, guard: &Guard) -> Option<T>
+    where
+        T: Clone,
+    {
         use std::sync::atomic::Ordering::SeqCst;
         debug_delay();
         let mut head = self.head(guard);
@@ -144,10 +151,13 @@ impl<T: Send + Sync + 'static> Stack<T> {
                     {
                         Ok(_) => unsafe {
                             // we unset the next pointer before destruction
-                            // to avoid double-frees.
+                            // to avoid double-frees. The item is cloned
+                            // rather than read out, because the node still
+                            // owns it and drops it when it is destroyed.
                             h.next.store(Shared::default(), SeqCst);
+                            let inner = h.inner.clone();
                             guard.defer_destroy(head);
-                            return Some(ptr::read(&h.inner));
+                            return Some(inner);
                         },
                         Err(h) => head = h.current,
                     }
@@ -157,9 +167,8 @@ impl<T: Send + Sync + 'static> Stack<T> {
         }
     }
 
-    /// Returns the current head pointer of the stack, which can
-    /// later be used as the key for cas and cap operations.
-    pub fn head<'g>(&self, guard: &'g Guard) -> Shared<'g, Node<T>> {
+    /// Returns the current head pointer of the stack.
+    pub(crate) fn head<'g>(&self, guard: &'g Guard) -> Shared<'g, Node<T>> {
         self.head.load(Acquire, guard)
     }
 }
@@ -173,19 +182,6 @@ where
     guard: &'a Guard,
 }
 
-impl<'a, T> Iter<'a, T>
-where
-    T: 'a + Send + 'static + Sync,
-{
-    /// Creates a `Iter` from a pointer to one.
-    pub fn from_ptr<'b>(
-        ptr: Shared<'b, Node<T>>,
-        guard: &'b Guard,
-    ) -> Iter<'b, T> {
-        Iter { inner: ptr, guard }
-    }
-}
-
 impl<'a, T> Iterator for Iter<'a, T>
 where
     T: Send + 'static + Sync,
@@ -263,3 +259,79 @@ fn basic_functionality() {
     guard.flush();
     drop(guard);
 }
+
+#[test]
+fn iter_and_pop_own_items() {
+    use crossbeam_epoch::Collector;
+    use std::sync::Arc;
+
+    let item = Arc::new(());
+
+    // a private collector destroys all deferred garbage
+    // once it and its handles are dropped
+    let collector = Collector::new();
+    let handle = collector.register();
+    let guard = handle.pin();
+
+    let stack = Stack::default();
+    stack.push(Arc::clone(&item), &guard);
+    stack.push(Arc::clone(&item), &guard);
+    assert_eq!(stack.iter(&guard).count(), 2);
+
+    let popped = stack.pop(&guard).unwrap();
+    assert_eq!(stack.iter(&guard).count(), 1);
+
+    drop(popped);
+    drop(stack);
+    drop(guard);
+    drop(handle);
+    drop(collector);
+
+    // every item pushed must be dropped exactly once
+    assert_eq!(Arc::strong_count(&item), 1);
+}
+
+#[test]
+fn contended_push_and_pop() {
+    use crossbeam_epoch::pin;
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    const N_THREADS: usize = 8;
+    const N_PER_THREAD: usize = 1000;
+
+    // every thread pushes its own items while popping
+    // whatever is on top, so that pushes and pops race on
+    // the same head
+    let stack = Arc::new(Stack::default());
+    let barrier = Arc::new(Barrier::new(N_THREADS));
+    let threads: Vec<_> = (0..N_THREADS)
+        .map(|t| {
+            let stack2 = Arc::clone(&stack);
+            let barrier2 = Arc::clone(&barrier);
+            thread::spawn(move || {
+                barrier2.wait();
+                let mut popped = vec![];
+                for i in 0..N_PER_THREAD {
+                    let guard = pin();
+                    stack2.push(t * N_PER_THREAD + i, &guard);
+                    if i % 2 == 0 {
+                        popped.extend(stack2.pop(&guard));
+                    }
+                }
+                popped
+            })
+        })
+        .collect();
+
+    let mut seen: Vec<usize> =
+        threads.into_iter().flat_map(|t| t.join().unwrap()).collect();
+
+    let guard = pin();
+    seen.extend(stack.take_iter(&guard).cloned());
+    assert_eq!(stack.iter(&guard).count(), 0);
+
+    // each item is popped or left behind exactly once
+    seen.sort_unstable();
+    assert_eq!(seen, (0..N_THREADS * N_PER_THREAD).collect::<Vec<_>>());
+}
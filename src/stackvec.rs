@@ -1,10 +1,10 @@
 use std::{convert::TryFrom, fmt, mem::MaybeUninit};
 
-use crate::pagecache::{constants::PAGE_CONSOLIDATION_THRESHOLD, CacheInfo};
+use crate::pagecache::{constants::MAX_PAGE_CHAIN_LENGTH, CacheInfo};
 
 #[derive(Clone, Copy)]
 pub(crate) struct StackVec {
-    items: [MaybeUninit<CacheInfo>; PAGE_CONSOLIDATION_THRESHOLD],
+    items: [MaybeUninit<CacheInfo>; MAX_PAGE_CHAIN_LENGTH],
     len: u8,
 }
 
@@ -19,7 +19,7 @@ impl fmt::Debug for StackVec {
 impl Default for StackVec {
     fn default() -> StackVec {
         StackVec {
-            items: [MaybeUninit::uninit(); PAGE_CONSOLIDATION_THRESHOLD],
+            items: [MaybeUninit::uninit(); MAX_PAGE_CHAIN_LENGTH],
             len: 0,
         }
     }
@@ -60,7 +60,7 @@ impl StackVec {
 
     pub(crate) fn extend_from_slice(&mut self, other: &[CacheInfo]) {
         assert!(
-            self.len as usize + other.len() <= PAGE_CONSOLIDATION_THRESHOLD,
+            self.len as usize + other.len() <= MAX_PAGE_CHAIN_LENGTH,
             "tried to extend_from_slice into StackVec past max capacity"
         );
 
@@ -77,7 +77,7 @@ impl StackVec {
 
     pub(crate) fn _insert(&mut self, idx: usize, item: CacheInfo) {
         assert_ne!(
-            self.len as usize, PAGE_CONSOLIDATION_THRESHOLD,
+            self.len as usize, MAX_PAGE_CHAIN_LENGTH,
             "tried to insert into StackVec already at max capacity"
         );
 
@@ -100,7 +100,7 @@ impl StackVec {
 
     pub(crate) fn push(&mut self, item: CacheInfo) {
         assert_ne!(
-            self.len as usize, PAGE_CONSOLIDATION_THRESHOLD,
+            self.len as usize, MAX_PAGE_CHAIN_LENGTH,
             "tried to push into StackVec already at max capacity"
         );
         self.items[self.len as usize] = MaybeUninit::new(item);
@@ -126,7 +126,7 @@ mod qc {
     use quickcheck::{Arbitrary, Gen};
     use rand::Rng;
 
-    use super::{CacheInfo, StackVec, PAGE_CONSOLIDATION_THRESHOLD};
+    use super::{CacheInfo, StackVec, MAX_PAGE_CHAIN_LENGTH};
 
     #[derive(Clone, Debug)]
     enum Op {
@@ -140,12 +140,12 @@ mod qc {
         fn arbitrary<G: Gen>(g: &mut G) -> Op {
             match g.gen_range(0, 4) {
                 0 => {
-                    let len = g.gen_range(0, PAGE_CONSOLIDATION_THRESHOLD);
+                    let len = g.gen_range(0, MAX_PAGE_CHAIN_LENGTH);
                     let items = vec![CacheInfo::arbitrary(g); len];
                     Op::Extend(items)
                 }
                 1 => Op::Insert(
-                    g.gen_range(0, PAGE_CONSOLIDATION_THRESHOLD),
+                    g.gen_range(0, MAX_PAGE_CHAIN_LENGTH),
                     CacheInfo::arbitrary(g),
                 ),
                 2 => Op::Push(CacheInfo::arbitrary(g)),
@@ -163,19 +163,19 @@ mod qc {
             for op in ops {
                 match op {
                     Op::Extend(items) => {
-                        if items.len() + v.len() < PAGE_CONSOLIDATION_THRESHOLD {
+                        if items.len() + v.len() < MAX_PAGE_CHAIN_LENGTH {
                             sv.extend_from_slice(&*items);
                             v.extend_from_slice(&items);
                         }
                     }
                     Op::Insert(at, item) => {
-                        if at <= v.len() && v.len() < PAGE_CONSOLIDATION_THRESHOLD {
+                        if at <= v.len() && v.len() < MAX_PAGE_CHAIN_LENGTH {
                             sv._insert(at, item);
                             v.insert(at, item);
                         }
                     }
                     Op::Push(item) => {
-                        if v.len() < PAGE_CONSOLIDATION_THRESHOLD {
+                        if v.len() < MAX_PAGE_CHAIN_LENGTH {
                             sv.push(item);
                             v.push(item);
                         }
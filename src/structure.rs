@@ -0,0 +1,227 @@
+use std::fmt::Write;
+
+use crate::{tree::Repair, *};
+
+// What `Tree::dump_structure` shows of a node.
+struct NodeSummary {
+    pid: PageId,
+    // the number of log messages making up the page, which
+    // is one more than the length of its delta chain
+    chain_len: usize,
+    lo: IVec,
+    hi: IVec,
+    next: Option<PageId>,
+    merging_child: Option<PageId>,
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    // the separator keys and the children they point to
+    Index(Vec<(IVec, PageId)>),
+    // the number of items
+    Leaf(usize),
+}
+
+// Walks the tree one level at a time from the root,
+// following sibling links, without repairing any partial
+// splits or merges along the way. Returns the root's ID
+// and the nodes of each level.
+fn collect(tree: &Tree) -> Result<(PageId, Vec<Vec<NodeSummary>>)> {
+    let guard = pin();
+
+    let root_pid =
+        tree.context.pagecache.meta_pid_for_name(&tree.tree_id, &guard)?;
+
+    let mut visited = FastSet8::default();
+    let mut levels = vec![];
+    let mut leftmost = Some(root_pid);
+
+    while let Some(mut pid) = leftmost.take() {
+        let mut level = vec![];
+
+        while visited.insert(pid) {
+            let view = if let Some(view) =
+                tree.view_for_pid_inner(pid, Repair::Nothing, &guard)?
+            {
+                view
+            } else {
+                break;
+            };
+
+            let kind = match &view.data {
+                Data::Index(index) => {
+                    if leftmost.is_none() {
+                        leftmost = index.pointers.first().copied();
+                    }
+                    NodeKind::Index(
+                        index
+                            .keys
+                            .iter()
+                            .map(|key| view.prefix_decode(key))
+                            .zip(index.pointers.iter().copied())
+                            .collect(),
+                    )
+                }
+                Data::Leaf(leaf) => NodeKind::Leaf(leaf.keys.len()),
+            };
+
+            level.push(NodeSummary {
+                pid,
+                chain_len: view.node_view.0.cache_infos.len(),
+                lo: view.lo.clone(),
+                hi: view.hi.clone(),
+                next: view.next,
+                merging_child: view.merging_child,
+                kind,
+            });
+
+            if let Some(next_pid) = view.next {
+                pid = next_pid;
+            } else {
+                break;
+            }
+        }
+
+        levels.push(level);
+    }
+
+    Ok((root_pid, levels))
+}
+
+// Renders a key with printable ASCII as is, and other
+// bytes as escapes.
+fn escape(key: &[u8]) -> String {
+    key.iter()
+        .flat_map(|byte| std::ascii::escape_default(*byte))
+        .map(char::from)
+        .collect()
+}
+
+pub(crate) fn text(tree: &Tree) -> Result<String> {
+    let (root_pid, levels) = collect(tree)?;
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "tree \"{}\" with root {}",
+        escape(&tree.tree_id),
+        root_pid
+    );
+
+    for (depth, level) in levels.iter().enumerate() {
+        let _ = writeln!(out, "level {}:", depth);
+
+        for node in level {
+            let _ = write!(
+                out,
+                "  {} {} chain {} lo \"{}\" hi \"{}\"",
+                match node.kind {
+                    NodeKind::Index(_) => "index",
+                    NodeKind::Leaf(_) => "leaf",
+                },
+                node.pid,
+                node.chain_len,
+                escape(&node.lo),
+                escape(&node.hi),
+            );
+            if let Some(next) = node.next {
+                let _ = write!(out, " next {}", next);
+            }
+            if let Some(merging_child) = node.merging_child {
+                let _ = write!(out, " merging {}", merging_child);
+            }
+            match &node.kind {
+                NodeKind::Index(children) => {
+                    for (separator, child) in children {
+                        let _ = write!(
+                            out,
+                            "\n    \"{}\" -> {}",
+                            escape(separator),
+                            child
+                        );
+                    }
+                }
+                NodeKind::Leaf(items) => {
+                    let _ = write!(out, " items {}", items);
+                }
+            }
+            out.push('\n');
+        }
+    }
+
+    Ok(out)
+}
+
+// Escapes a string for use within a quoted DOT string.
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+pub(crate) fn dot(tree: &Tree) -> Result<String> {
+    let (_, levels) = collect(tree)?;
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "digraph \"{}\" {{\n  node [shape=box];",
+        dot_escape(&escape(&tree.tree_id))
+    );
+
+    for level in &levels {
+        let mut rank = String::from("  { rank=same;");
+
+        for node in level {
+            let (kind, contents) = match &node.kind {
+                NodeKind::Index(children) => {
+                    ("index", format!("{} children", children.len()))
+                }
+                NodeKind::Leaf(items) => ("leaf", format!("{} items", items)),
+            };
+            let label = format!(
+                "{} {}\\nchain {}\\nlo \"{}\"\\nhi \"{}\"\\n{}",
+                kind,
+                node.pid,
+                node.chain_len,
+                dot_escape(&escape(&node.lo)),
+                dot_escape(&escape(&node.hi)),
+                contents,
+            );
+            let _ = writeln!(out, "  p{} [label=\"{}\"];", node.pid, label);
+            let _ = write!(rank, " p{};", node.pid);
+
+            if let NodeKind::Index(children) = &node.kind {
+                for (separator, child) in children {
+                    let _ = writeln!(
+                        out,
+                        "  p{} -> p{} [label=\"{}\"];",
+                        node.pid,
+                        child,
+                        dot_escape(&escape(separator))
+                    );
+                }
+            }
+            if let Some(next) = node.next {
+                let _ = writeln!(
+                    out,
+                    "  p{} -> p{} [style=dashed];",
+                    node.pid, next
+                );
+            }
+            if let Some(merging_child) = node.merging_child {
+                let _ = writeln!(
+                    out,
+                    "  p{} -> p{} [style=dotted, label=\"merging\"];",
+                    node.pid, merging_child
+                );
+            }
+        }
+
+        rank.push_str(" }");
+        out.push_str(&rank);
+        out.push('\n');
+    }
+
+    out.push_str("}\n");
+
+    Ok(out)
+}
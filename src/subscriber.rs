@@ -3,8 +3,10 @@ use std::{
     future::Future,
     pin::Pin,
     sync::{
-        atomic::{AtomicBool, Ordering::Relaxed},
-        mpsc::{sync_channel, Receiver, SyncSender, TryRecvError},
+        atomic::{AtomicBool, AtomicU64, Ordering::Relaxed},
+        mpsc::{
+            sync_channel, Receiver, SyncSender, TryRecvError, TrySendError,
+        },
     },
     task::{Context, Poll, Waker},
 };
@@ -39,8 +41,23 @@ impl Event {
     }
 }
 
-type Senders =
-    HashMap<usize, (Option<Waker>, SyncSender<OneShot<Option<Event>>>)>;
+// the flag is set for subscribers that only receive
+// events once their writes are stable, and the counter
+// is set for bounded subscribers, which drop events
+// instead of blocking writers when their buffer is full
+type Senders = HashMap<
+    usize,
+    (
+        Option<Waker>,
+        SyncSender<OneShot<Option<Event>>>,
+        bool,
+        Option<Arc<AtomicU64>>,
+    ),
+>;
+
+// the number of events buffered by subscribers created
+// with `Tree::watch_prefix`, after which writers block
+const DEFAULT_CAPACITY: usize = 1024;
 
 /// A subscriber listening on a specified prefix
 ///
@@ -82,12 +99,69 @@ type Senders =
 /// `Subscription` implements `Future<Output=Option<Event>>`.
 ///
 /// `while let Some(event) = (&mut subscriber).await { /* use it */ }`
+///
+/// `Subscriber::poll_next` has the same signature and
+/// contract as `futures::Stream::poll_next`, so it can be
+/// turned into a `Stream` with
+/// `futures::stream::poll_fn(move |cx| subscriber.poll_next(cx))`.
 pub struct Subscriber {
     id: usize,
     rx: Receiver<OneShot<Option<Event>>>,
+    // an event that was reserved but not yet completed
+    // the last time that we were polled
+    pending: Option<OneShot<Option<Event>>>,
+    lagged: Arc<AtomicU64>,
     home: Arc<RwLock<Senders>>,
 }
 
+impl Subscriber {
+    /// Returns the total number of events that were dropped
+    /// because the buffer of a subscriber created with
+    /// `Tree::watch_prefix_bounded` was full. Subscribers
+    /// created with `Tree::watch_prefix` never drop events,
+    /// so this is always 0 for them.
+    pub fn lagged(&self) -> u64 {
+        self.lagged.load(Relaxed)
+    }
+
+    /// Attempts to pull out the next `Event`, registering the
+    /// current task for wakeup if none is available yet.
+    /// Returns `Poll::Ready(None)` once the `Tree` is dropped.
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        let mut registered = false;
+        loop {
+            if let Some(future_rx) = &mut self.pending {
+                match future_rx.poll_filled(cx) {
+                    Poll::Ready(Some(Some(event))) => {
+                        self.pending = None;
+                        return Poll::Ready(Some(event));
+                    }
+                    // the reservation was aborted
+                    Poll::Ready(_) => self.pending = None,
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            match self.rx.try_recv() {
+                Ok(future_rx) => self.pending = Some(future_rx),
+                Err(TryRecvError::Empty) if registered => {
+                    return Poll::Pending;
+                }
+                Err(TryRecvError::Empty) => {
+                    // writers clone the waker while holding the
+                    // read lock, so anything sent before we
+                    // registered it is caught by trying again
+                    let mut home = self.home.write();
+                    let entry = home.get_mut(&self.id).unwrap();
+                    entry.0 = Some(cx.waker().clone());
+                    registered = true;
+                }
+                Err(TryRecvError::Disconnected) => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
 impl Drop for Subscriber {
     fn drop(&mut self) {
         let mut w_senders = self.home.write();
@@ -98,34 +172,11 @@ impl Drop for Subscriber {
 impl Future for Subscriber {
     type Output = Option<Event>;
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        loop {
-            match self.rx.try_recv() {
-                Ok(mut future_rx) => {
-                    #[allow(unsafe_code)]
-                    let future_rx =
-                        unsafe { std::pin::Pin::new_unchecked(&mut future_rx) };
-
-                    match Future::poll(future_rx, cx) {
-                        Poll::Ready(Some(event)) => {
-                            return Poll::Ready(Some(event));
-                        }
-                        Poll::Ready(None) => {
-                            continue;
-                        }
-                        Poll::Pending => {
-                            return Poll::Pending;
-                        }
-                    }
-                }
-                Err(TryRecvError::Empty) => break,
-                Err(TryRecvError::Disconnected) => return Poll::Ready(None),
-            }
-        }
-        let mut home = self.home.write();
-        let entry = home.get_mut(&self.id).unwrap();
-        entry.0 = Some(cx.waker().clone());
-        Poll::Pending
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Self::Output> {
+        self.poll_next(cx)
     }
 }
 
@@ -134,7 +185,10 @@ impl Iterator for Subscriber {
 
     fn next(&mut self) -> Option<Event> {
         loop {
-            let future_rx = self.rx.recv().ok()?;
+            let future_rx = match self.pending.take() {
+                Some(future_rx) => future_rx,
+                None => self.rx.recv().ok()?,
+            };
             match future_rx.wait() {
                 Some(Some(event)) => return Some(event),
                 Some(None) => return None,
@@ -156,7 +210,7 @@ impl Drop for Subscribers {
 
         for senders in watched.values() {
             let mut senders = senders.write();
-            for (_, (waker, sender)) in senders.drain() {
+            for (_, (waker, sender, _, _)) in senders.drain() {
                 drop(sender);
                 if let Some(waker) = waker {
                     waker.wake();
@@ -168,6 +222,27 @@ impl Drop for Subscribers {
 
 impl Subscribers {
     pub(crate) fn register(&self, prefix: &[u8]) -> Subscriber {
+        self.register_inner(prefix, false, None)
+    }
+
+    pub(crate) fn register_durable_only(&self, prefix: &[u8]) -> Subscriber {
+        self.register_inner(prefix, true, None)
+    }
+
+    pub(crate) fn register_bounded(
+        &self,
+        prefix: &[u8],
+        capacity: usize,
+    ) -> Subscriber {
+        self.register_inner(prefix, false, Some(capacity))
+    }
+
+    fn register_inner(
+        &self,
+        prefix: &[u8],
+        durable_only: bool,
+        capacity: Option<usize>,
+    ) -> Subscriber {
         self.ever_used.store(true, Relaxed);
         let r_mu = {
             let r_mu = self.watched.read();
@@ -188,16 +263,18 @@ impl Subscribers {
             }
         };
 
-        let (tx, rx) = sync_channel(1024);
+        let (tx, rx) = sync_channel(capacity.unwrap_or(DEFAULT_CAPACITY));
+        let lagged = Arc::new(AtomicU64::new(0));
 
         let arc_senders = &r_mu[prefix];
         let mut w_senders = arc_senders.write();
 
         let id = ID_GEN.fetch_add(1, Relaxed);
 
-        w_senders.insert(id, (None, tx));
+        let bounded_lagged = capacity.map(|_| lagged.clone());
+        w_senders.insert(id, (None, tx, durable_only, bounded_lagged));
 
-        Subscriber { id, rx, home: arc_senders.clone() }
+        Subscriber { id, rx, pending: None, lagged, home: arc_senders.clone() }
     }
 
     pub(crate) fn reserve<R: AsRef<[u8]>>(
@@ -216,12 +293,21 @@ impl Subscribers {
         for (_, subs_rwl) in prefixes {
             let subs = subs_rwl.read();
 
-            for (_id, (waker, sender)) in subs.iter() {
+            for (_id, (waker, sender, durable_only, lag)) in subs.iter() {
                 let (tx, rx) = OneShot::pair();
-                if sender.send(rx).is_err() {
+                if let Some(lagged) = lag {
+                    match sender.try_send(rx) {
+                        Ok(()) => {}
+                        Err(TrySendError::Full(_)) => {
+                            lagged.fetch_add(1, Relaxed);
+                            continue;
+                        }
+                        Err(TrySendError::Disconnected(_)) => continue,
+                    }
+                } else if sender.send(rx).is_err() {
                     continue;
                 }
-                subscribers.push((waker.clone(), tx));
+                subscribers.push((waker.clone(), tx, *durable_only));
             }
         }
 
@@ -234,14 +320,30 @@ impl Subscribers {
 }
 
 pub(crate) struct ReservedBroadcast {
-    subscribers: Vec<(Option<Waker>, OneShotFiller<Option<Event>>)>,
+    subscribers: Vec<(Option<Waker>, OneShotFiller<Option<Event>>, bool)>,
 }
 
 impl ReservedBroadcast {
+    /// Splits off the subscribers that only receive
+    /// events once their writes are stable, if any.
+    pub fn take_durable_only(&mut self) -> Option<ReservedBroadcast> {
+        let (durable_only, subscribers) = self
+            .subscribers
+            .drain(..)
+            .partition(|(_, _, durable_only)| *durable_only);
+        self.subscribers = subscribers;
+
+        if durable_only.is_empty() {
+            None
+        } else {
+            Some(ReservedBroadcast { subscribers: durable_only })
+        }
+    }
+
     pub fn complete(self, event: &Event) {
         let iter = self.subscribers.into_iter();
 
-        for (waker, tx) in iter {
+        for (waker, tx, _) in iter {
             tx.fill(Some(event.clone()));
             if let Some(waker) = waker {
                 waker.wake();
@@ -319,3 +421,73 @@ fn basic_subscriber() {
 
     assert_eq!(s4.next().unwrap().key(), &*k8);
 }
+
+#[test]
+fn bounded_subscriber_reports_lag() {
+    let subs = Subscribers::default();
+
+    let mut bounded = subs.register_bounded(&[], 2);
+    let mut unbounded = subs.register(&[]);
+
+    let keys: Vec<IVec> = (0..4_u8).map(|i| vec![i].into()).collect();
+    for key in &keys {
+        let reservation = subs.reserve(key).unwrap();
+        reservation.complete(&Event::Remove { key: key.clone() });
+    }
+
+    assert_eq!(bounded.next().unwrap().key(), &keys[0]);
+    assert_eq!(bounded.next().unwrap().key(), &keys[1]);
+    assert_eq!(bounded.lagged(), 2);
+
+    // once there's room again, events are delivered
+    let reservation = subs.reserve(&keys[0]).unwrap();
+    reservation.complete(&Event::Remove { key: keys[0].clone() });
+    assert_eq!(bounded.next().unwrap().key(), &keys[0]);
+
+    for key in &keys {
+        assert_eq!(unbounded.next().unwrap().key(), key);
+    }
+    assert_eq!(unbounded.lagged(), 0);
+}
+
+#[test]
+fn poll_next_keeps_pending_events() {
+    use std::task::{RawWaker, RawWakerVTable};
+
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            noop_raw_waker()
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable =
+            RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+
+    #[allow(unsafe_code)]
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+    let mut cx = Context::from_waker(&waker);
+
+    let subs = Subscribers::default();
+    let mut subscriber = subs.register(&[]);
+
+    assert_eq!(subscriber.poll_next(&mut cx), Poll::Pending);
+
+    let k1: IVec = vec![1].into();
+    let k2: IVec = vec![2].into();
+
+    // an aborted reservation is skipped
+    drop(subs.reserve(&k1).unwrap());
+
+    // a reserved but incomplete event is kept across polls
+    let r2 = subs.reserve(&k2).unwrap();
+    assert_eq!(subscriber.poll_next(&mut cx), Poll::Pending);
+    r2.complete(&Event::Remove { key: k2.clone() });
+    assert_eq!(
+        subscriber.poll_next(&mut cx),
+        Poll::Ready(Some(Event::Remove { key: k2 }))
+    );
+
+    drop(subs);
+    assert_eq!(subscriber.poll_next(&mut cx), Poll::Ready(None));
+}
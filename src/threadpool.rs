@@ -147,6 +147,14 @@ where
     R: Send + 'static,
 {
     let (promise_filler, promise) = OneShot::pair();
+
+    // simulations run everything on the calling thread, so
+    // that they are deterministic
+    if cfg!(feature = "simulation") {
+        promise_filler.fill((work)());
+        return promise;
+    }
+
     let task = move || {
         let result = (work)();
         promise_filler.fill(result);
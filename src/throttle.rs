@@ -0,0 +1,144 @@
+use std::time::Duration;
+
+use super::*;
+
+// how many times their configured rates background work
+// may run at once the log is under as much pressure as it
+// can be
+const MAX_BOOST: u64 = 4;
+
+/// A token bucket that refills at `rate` units per second,
+/// up to one second's worth. Work that is charged to it may
+/// take it into debt, which later refills pay off first.
+#[derive(Debug)]
+struct Bucket {
+    rate: u64,
+    budget: i64,
+    refilled_at: Duration,
+}
+
+impl Bucket {
+    fn new(rate: u64) -> Bucket {
+        Bucket {
+            rate,
+            budget: i64::try_from(rate).unwrap_or(i64::max_value()),
+            refilled_at: Duration::default(),
+        }
+    }
+
+    fn refill(&mut self, now: Duration, rate: u64) {
+        let elapsed = now.checked_sub(self.refilled_at).unwrap_or_default();
+        let earned = u128::from(rate) * elapsed.as_nanos() / 1_000_000_000;
+        if earned == 0 {
+            // let the time add up to at least one unit
+            return;
+        }
+        let burst = i64::try_from(rate).unwrap_or(i64::max_value());
+        self.budget = self
+            .budget
+            .saturating_add(i64::try_from(earned).unwrap_or(i64::max_value()))
+            .min(burst);
+        self.refilled_at = now;
+    }
+}
+
+/// Limits background maintenance to the bytes and
+/// operations per second of `Config::background_bytes_per_sec`
+/// and `Config::background_ops_per_sec`. The limits are
+/// raised by up to `MAX_BOOST` times along with the pressure
+/// that the flusher measures on every tick, so that dead
+/// space and unflushed writes can't build up for good.
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    bytes: Option<Mutex<Bucket>>,
+    ops: Option<Mutex<Bucket>>,
+    // [0-100]
+    pressure: AtomicU64,
+}
+
+impl Throttle {
+    pub(crate) fn new(
+        bytes_per_sec: Option<u64>,
+        ops_per_sec: Option<u64>,
+    ) -> Throttle {
+        Throttle {
+            bytes: bytes_per_sec.map(|rate| Mutex::new(Bucket::new(rate))),
+            ops: ops_per_sec.map(|rate| Mutex::new(Bucket::new(rate))),
+            pressure: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets how urgently maintenance is needed, as a
+    /// percentage.
+    pub(crate) fn set_pressure(&self, pressure: u64) {
+        self.pressure.store(pressure.min(100), Relaxed);
+    }
+
+    fn boosted(&self, rate: u64) -> u64 {
+        let pressure = self.pressure.load(Relaxed);
+        rate.saturating_add(
+            rate.saturating_mul((MAX_BOOST - 1) * pressure) / 100,
+        )
+    }
+
+    fn refill(&self, bucket: &mut Bucket, now: Duration) {
+        let rate = self.boosted(bucket.rate);
+        bucket.refill(now, rate);
+    }
+
+    fn deduct(&self, bucket_mu: &Mutex<Bucket>, now: Duration, units: u64) {
+        let mut bucket = bucket_mu.lock();
+        self.refill(&mut bucket, now);
+        let debt = i64::try_from(units).unwrap_or(i64::max_value());
+        bucket.budget = bucket.budget.saturating_sub(debt);
+    }
+
+    /// Deducts the work done by the time `now` from the
+    /// budgets.
+    pub(crate) fn charge(&self, now: Duration, bytes: u64, ops: u64) {
+        if let Some(bucket_mu) = &self.bytes {
+            self.deduct(bucket_mu, now, bytes);
+        }
+        if let Some(bucket_mu) = &self.ops {
+            self.deduct(bucket_mu, now, ops);
+        }
+    }
+
+    /// Returns `true` if either budget is used up at `now`,
+    /// in which case background work should wait for a
+    /// later tick.
+    pub(crate) fn exhausted(&self, now: Duration) -> bool {
+        self.bytes.iter().chain(self.ops.iter()).any(|bucket_mu| {
+            let mut bucket = bucket_mu.lock();
+            self.refill(&mut bucket, now);
+            bucket.budget <= 0
+        })
+    }
+}
+
+#[test]
+fn throttle_refills_and_boosts() {
+    let secs = Duration::from_secs;
+    let throttle = Throttle::new(Some(1000), None);
+    assert!(!throttle.exhausted(secs(0)));
+
+    // a burst of one second's worth, then debt
+    throttle.charge(secs(0), 1500, 1);
+    assert!(throttle.exhausted(secs(0)));
+    assert!(throttle.exhausted(Duration::from_millis(400)));
+    assert!(!throttle.exhausted(Duration::from_millis(600)));
+
+    // idle time only ever refills one second's worth
+    throttle.charge(secs(10), 1000, 1);
+    assert!(throttle.exhausted(secs(10)));
+
+    // full pressure refills at 4 times the rate
+    throttle.set_pressure(100);
+    throttle.charge(secs(20), 4000, 1);
+    assert!(throttle.exhausted(secs(20)));
+    assert!(!throttle.exhausted(Duration::from_millis(20_300)));
+
+    let unlimited = Throttle::new(None, None);
+    unlimited.charge(secs(0), u64::max_value(), u64::max_value());
+    assert!(!unlimited.exhausted(secs(0)));
+}
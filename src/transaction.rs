@@ -71,9 +71,17 @@
 //! assert_eq!(&processed.get(b"k3").unwrap().unwrap(), b"yappin' ligers");
 //! ```
 #![allow(clippy::module_name_repetitions)]
-use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+use std::{
+    cell::RefCell, collections::HashMap, fmt, rc::Rc,
+    sync::{atomic::Ordering::Acquire, Arc},
+};
 
-use crate::{pin, Batch, Error, Guard, IVec, Protector, Result, Tree};
+use crossbeam_utils::Backoff;
+
+use crate::{
+    debug_delay, pin, pagecache::PageId, tree::View, Batch, Context, Error,
+    Guard, IVec, Lsn, Protector, Result, Tree,
+};
 
 /// A transaction that will
 /// be applied atomically to the
@@ -253,6 +261,7 @@ impl TransactionalTree {
         K: AsRef<[u8]>,
     {
         let old = self.get(key.as_ref())?;
+        self.check_write_once(key.as_ref(), &old)?;
         let mut writes = self.writes.borrow_mut();
         let _last_write =
             writes.insert(IVec::from(key), Some(IVec::from(value)));
@@ -268,10 +277,27 @@ impl TransactionalTree {
         IVec: From<K>,
         K: AsRef<[u8]>,
     {
-        let old = self.get(key.as_ref());
+        let old = self.get(key.as_ref())?;
+        self.check_write_once(key.as_ref(), &old)?;
         let mut writes = self.writes.borrow_mut();
         let _last_write = writes.insert(IVec::from(key), None);
-        old
+        Ok(old)
+    }
+
+    // fails the transaction before it writes to a key that
+    // already exists in a write-once tree
+    fn check_write_once(
+        &self,
+        key: &[u8],
+        old: &Option<IVec>,
+    ) -> UnabortableTransactionResult<()> {
+        if old.is_some() && self.tree.write_once.load(Acquire) {
+            Err(UnabortableTransactionError::Storage(Error::ReadOnlyKey(
+                key.into(),
+            )))
+        } else {
+            Ok(())
+        }
     }
 
     /// Get the value associated with a key
@@ -325,14 +351,13 @@ impl TransactionalTree {
         true
     }
 
-    fn commit(&self) -> Result<()> {
+    fn commit(&self, guard: &Guard) -> Result<()> {
         let writes = self.writes.borrow();
-        let guard = pin();
         for (k, v_opt) in &*writes {
             if let Some(v) = v_opt {
-                let _old = self.tree.insert_inner(k, v, &guard)?;
+                let _old = self.tree.insert_inner(k, v, guard)?;
             } else {
-                let _old = self.tree.remove_inner(k, &guard)?;
+                let _old = self.tree.remove_inner(k, guard)?;
             }
         }
         Ok(())
@@ -389,9 +414,11 @@ impl TransactionalTrees {
     }
 
     fn commit(&self, guard: &Guard) -> Result<()> {
-        let peg = self.inner[0].tree.context.pin_log(guard)?;
+        let context = &self.inner[0].tree.context;
+        let _gate = context.read_snapshots.gate();
+        let peg = context.pin_log(guard)?;
         for tree in &self.inner {
-            tree.commit()?;
+            tree.commit(guard)?;
         }
 
         // when the peg drops, it ensures all updates
@@ -401,6 +428,221 @@ impl TransactionalTrees {
     }
 }
 
+/// An optimistic transaction over any of the `Tree`s of a
+/// `Db`, run by `Db::transact`. It takes no locks while the
+/// closure runs. Instead, it records the page and the lsn of
+/// the latest write to that page for every key that it
+/// reads, and checks at commit time that none of those pages
+/// has been written to since, in which case the closure is
+/// run again.
+///
+/// Every `Tree` that it reads or writes must belong to the
+/// `Db` that it was started from, or an
+/// `Error::Unsupported` is returned.
+pub struct OptimisticTransaction<'a> {
+    context: &'a Context,
+    trees: RefCell<HashMap<IVec, Tree>>,
+    reads: RefCell<HashMap<(IVec, IVec), Read>>,
+    writes: RefCell<HashMap<(IVec, IVec), Option<IVec>>>,
+}
+
+// a key as it was read, and where it was read from
+struct Read {
+    pid: PageId,
+    lsn: Lsn,
+    value: Option<IVec>,
+}
+
+// Reads a key along with the page that it was read from and
+// the lsn of that page's latest write.
+fn read_versioned(
+    tree: &Tree,
+    key: &[u8],
+    guard: &Guard,
+) -> Result<(PageId, Lsn, Option<IVec>)> {
+    loop {
+        let View { node_view, pid, .. } = tree.read_view_for_key(key, guard)?;
+        let lsn = node_view.0.last_lsn();
+        let value = match node_view.leaf_pair_for_key(key) {
+            Some((_, value)) => value,
+            None => return Ok((pid, lsn, None)),
+        };
+        if let Some(ivec) = tree.resolve_value(value, guard)? {
+            return Ok((pid, lsn, Some(ivec)));
+        }
+        tree.looped();
+    }
+}
+
+impl<'a> OptimisticTransaction<'a> {
+    pub(crate) fn run<F, A, E>(
+        context: &'a Context,
+        f: F,
+    ) -> TransactionResult<A, E>
+    where
+        F: Fn(
+            &OptimisticTransaction<'a>,
+        ) -> ConflictableTransactionResult<A, E>,
+    {
+        let backoff = Backoff::new();
+        loop {
+            let tx = OptimisticTransaction {
+                context,
+                trees: RefCell::new(HashMap::new()),
+                reads: RefCell::new(HashMap::new()),
+                writes: RefCell::new(HashMap::new()),
+            };
+            match f(&tx) {
+                Ok(r) => {
+                    if tx.commit()? {
+                        return Ok(r);
+                    }
+                }
+                Err(ConflictableTransactionError::Abort(e)) => {
+                    return Err(TransactionError::Abort(e));
+                }
+                Err(ConflictableTransactionError::Conflict) => {}
+                Err(ConflictableTransactionError::Storage(other)) => {
+                    return Err(TransactionError::Storage(other));
+                }
+            }
+            backoff.snooze();
+        }
+    }
+
+    /// Get the value associated with a key in `tree`
+    pub fn get<K: AsRef<[u8]>>(
+        &self,
+        tree: &Tree,
+        key: K,
+    ) -> UnabortableTransactionResult<Option<IVec>> {
+        // trees are told apart by their ids, which are only
+        // unique within a single `Db`
+        if !Arc::ptr_eq(&tree.context.pagecache, &self.context.pagecache) {
+            return Err(UnabortableTransactionError::Storage(
+                Error::Unsupported(
+                    "a Tree from a different Db can't be used \
+                     in this transaction"
+                        .to_owned(),
+                ),
+            ));
+        }
+
+        let id = (tree.tree_id.clone(), IVec::from(key.as_ref()));
+        if let Some(written) = self.writes.borrow().get(&id) {
+            return Ok(written.clone());
+        }
+        if let Some(read) = self.reads.borrow().get(&id) {
+            return Ok(read.value.clone());
+        }
+
+        let guard = pin();
+        let (pid, lsn, value) = read_versioned(tree, key.as_ref(), &guard)?;
+        let _ = self
+            .trees
+            .borrow_mut()
+            .entry(tree.tree_id.clone())
+            .or_insert_with(|| tree.clone());
+        let read = Read { pid, lsn, value: value.clone() };
+        let _ = self.reads.borrow_mut().insert(id, read);
+        Ok(value)
+    }
+
+    /// Set a key in `tree` to a new value
+    pub fn insert<K, V>(
+        &self,
+        tree: &Tree,
+        key: K,
+        value: V,
+    ) -> UnabortableTransactionResult<Option<IVec>>
+    where
+        IVec: From<K> + From<V>,
+        K: AsRef<[u8]>,
+    {
+        let old = self.get(tree, key.as_ref())?;
+        if old.is_some() && tree.write_once.load(Acquire) {
+            return Err(UnabortableTransactionError::Storage(
+                Error::ReadOnlyKey(key.as_ref().into()),
+            ));
+        }
+        let id = (tree.tree_id.clone(), IVec::from(key));
+        let _ = self.writes.borrow_mut().insert(id, Some(IVec::from(value)));
+        Ok(old)
+    }
+
+    /// Remove a key from `tree`
+    pub fn remove<K>(
+        &self,
+        tree: &Tree,
+        key: K,
+    ) -> UnabortableTransactionResult<Option<IVec>>
+    where
+        IVec: From<K>,
+        K: AsRef<[u8]>,
+    {
+        let old = self.get(tree, key.as_ref())?;
+        let id = (tree.tree_id.clone(), IVec::from(key));
+        let _ = self.writes.borrow_mut().insert(id, None);
+        Ok(old)
+    }
+
+    // Returns `false` without writing anything if a page
+    // that was read from has been written to since.
+    fn commit(&self) -> Result<bool> {
+        let trees = self.trees.borrow();
+        let writes = self.writes.borrow();
+        let guard = pin();
+        if writes.is_empty() {
+            return self.validate(&trees, &guard);
+        }
+
+        // lock the trees in the order of their ids, like
+        // `TransactionalTrees::stage`, so that concurrent
+        // commits can't deadlock
+        let mut ids: Vec<&IVec> = trees.keys().collect();
+        ids.sort_unstable();
+        let _locks: Vec<Protector<'_>> = ids
+            .iter()
+            .map(|id| trees[*id].concurrency_control.write())
+            .collect();
+
+        if !self.validate(&trees, &guard)? {
+            return Ok(false);
+        }
+
+        debug_delay();
+
+        let _gate = self.context.read_snapshots.gate();
+        let peg = self.context.pin_log(&guard)?;
+        for ((tree_id, key), write) in &*writes {
+            let tree = &trees[tree_id];
+            if let Some(value) = write {
+                let _ = tree.insert_inner(key, value, &guard)?;
+            } else {
+                let _ = tree.remove_inner(key, &guard)?;
+            }
+        }
+        peg.seal_batch(&guard)?;
+        Ok(true)
+    }
+
+    fn validate(
+        &self,
+        trees: &HashMap<IVec, Tree>,
+        guard: &Guard,
+    ) -> Result<bool> {
+        for ((tree_id, key), read) in &*self.reads.borrow() {
+            let tree = &trees[tree_id];
+            let View { node_view, pid, .. } =
+                tree.read_view_for_key(key, guard)?;
+            if pid != read.pid || node_view.0.last_lsn() != read.lsn {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
 /// A simple constructor for `Err(TransactionError::Abort(_))`
 pub fn abort<A, T>(t: T) -> ConflictableTransactionResult<A, T> {
     Err(ConflictableTransactionError::Abort(t))
@@ -1,16 +1,26 @@
 use std::{
     borrow::Cow,
     fmt::{self, Debug},
+    iter::FromIterator,
     ops::{self, Deref, RangeBounds},
     sync::{
-        atomic::{AtomicU64, Ordering::SeqCst},
+        atomic::{
+            AtomicBool, AtomicU64,
+            Ordering::{AcqRel, Acquire, Release},
+        },
         Arc,
     },
+    time::{Duration, Instant},
 };
 
 use parking_lot::RwLock;
 
-use crate::pagecache::NodeView;
+use crate::{
+    node::{Index, Leaf, shortest_separator},
+    pagecache::NodeView,
+    subscriber::ReservedBroadcast,
+    versions::{self, History},
+};
 
 use super::*;
 
@@ -29,6 +39,20 @@ impl<'g> Deref for View<'g> {
     }
 }
 
+/// How much structural work a traversal performs
+/// on behalf of other threads, as determined by the
+/// configured `RepairPolicy`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Repair {
+    /// Complete partial splits and merges, and begin
+    /// splitting or merging nodes that call for it.
+    All,
+    /// Like `All`, but step over partial splits.
+    SkipSplits,
+    /// Perform no structural work at all.
+    Nothing,
+}
+
 impl IntoIterator for &'_ Tree {
     type Item = Result<(IVec, IVec)>;
     type IntoIter = Iter;
@@ -38,6 +62,79 @@ impl IntoIterator for &'_ Tree {
     }
 }
 
+impl IntoIterator for Tree {
+    type Item = Result<(IVec, IVec)>;
+    type IntoIter = Iter;
+
+    fn into_iter(self) -> Iter {
+        self.iter()
+    }
+}
+
+// how many pairs `Extend` writes in each of its batches
+const EXTEND_BATCH_LEN: usize = 1024;
+
+/// Inserts the pairs in batches of up to 1024, each of
+/// which is applied atomically. Later pairs overwrite
+/// earlier ones with the same key.
+///
+/// # Panics
+///
+/// Panics if a batch can't be applied, for instance because
+/// the `Tree` is read-only. Use `apply_batch` to handle
+/// such errors instead.
+impl<K, V> Extend<(K, V)> for Tree
+where
+    IVec: From<K> + From<V>,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        let mut batch = Batch::default();
+        let mut len = 0;
+        for (k, v) in iter {
+            batch.insert(k, v);
+            len += 1;
+            if len == EXTEND_BATCH_LEN {
+                let full = std::mem::replace(&mut batch, Batch::default());
+                self.apply_batch(full).expect("failed to extend tree");
+                len = 0;
+            }
+        }
+        if len > 0 {
+            self.apply_batch(batch).expect("failed to extend tree");
+        }
+    }
+}
+
+/// Collects the pairs into the default `Tree` of a new
+/// temporary database, which is removed once the `Tree`
+/// and every handle to it are dropped.
+///
+/// # Panics
+///
+/// Panics if the temporary database can't be created or
+/// written to.
+///
+/// # Examples
+///
+/// ```
+/// let tree: sled::Tree = (0..10_u8).map(|i| (vec![i], vec![i; 2])).collect();
+///
+/// assert_eq!(tree.len(), 10);
+/// for (i, kv) in tree.into_iter().enumerate() {
+///     let (k, v) = kv.unwrap();
+///     assert_eq!(k, vec![i as u8]);
+///     assert_eq!(v, vec![i as u8; 2]);
+/// }
+/// ```
+impl<K, V> FromIterator<(K, V)> for Tree
+where
+    IVec: From<K> + From<V>,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Tree {
+        Db::from_iter(iter).default
+    }
+}
+
 /// A flash-sympathetic persistent lock-free B+ tree
 ///
 /// # Examples
@@ -80,9 +177,40 @@ pub struct TreeInner {
     pub(crate) tree_id: IVec,
     pub(crate) context: Context,
     pub(crate) subscribers: Subscribers,
+    // the pid of the root node, cached from the meta page so
+    // that traversals never need to read it. Root hoists
+    // update the meta page first and then this, and a
+    // dropped tree's root is set to `u64::max_value()`.
     pub(crate) root: AtomicU64,
     pub(crate) concurrency_control: ConcurrencyControl,
     pub(crate) merge_operator: RwLock<Option<Box<dyn MergeOperator>>>,
+    pub(crate) dup_sort: AtomicBool,
+    pub(crate) write_once: AtomicBool,
+    pub(crate) stale_snapshot: StaleSnapshot,
+    pub(crate) indexes: Indexes,
+}
+
+/// A copy of every item in a `Tree`, which `Tree::get_stale`
+/// serves reads from for as long as it is recent enough.
+#[derive(Default)]
+pub(crate) struct StaleSnapshot {
+    taken: RwLock<Option<(Instant, Arc<BTreeMap<IVec, IVec>>)>>,
+    // held by the one thread that is taking a new snapshot
+    refreshing: Mutex<()>,
+}
+
+/// A value that a write has stored but not yet linked into
+/// its leaf. An overflow page is written under a log peg, so
+/// that recovery keeps the page only along with the link,
+/// and it is freed again if the link fails or errors. The
+/// peg holds back log writes, so nothing may be read from
+/// the log until the value is linked or dropped.
+struct PendingValue<'a> {
+    tree: &'a Tree,
+    value: Value,
+    peg: Option<RecoveryGuard<'a>>,
+    linked: bool,
+    guard: &'a Guard,
 }
 
 impl Deref for Tree {
@@ -131,10 +259,43 @@ impl Tree {
         IVec: From<V>,
     {
         let guard = pin();
-        let _ = self.concurrency_control.read(&guard);
+        let _cc = self.concurrency_control.read(&guard);
         self.insert_inner(key, value, &guard)
     }
 
+    /// Insert a key to a new value like `insert`, only
+    /// returning once the write is as durable as requested.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, Durability};
+    /// let config = Config::new().temporary(true);
+    /// let t = config.open()?;
+    ///
+    /// // a bulk load can leave its writes to the background flusher
+    /// t.insert_with_durability(b"imported", b"row", Durability::Buffered)?;
+    ///
+    /// // while a ledger entry must survive a crash once it's written
+    /// t.insert_with_durability(b"balance", b"100", Durability::Sync)?;
+    /// # Ok(()) }
+    /// ```
+    pub fn insert_with_durability<K, V>(
+        &self,
+        key: K,
+        value: V,
+        durability: Durability,
+    ) -> Result<Option<IVec>>
+    where
+        K: AsRef<[u8]>,
+        IVec: From<V>,
+    {
+        let last = self.insert(key, value)?;
+        self.make_durable(durability)?;
+        Ok(last)
+    }
+
     pub(crate) fn insert_inner<K, V>(
         &self,
         key: K,
@@ -154,37 +315,75 @@ impl Tree {
             ));
         }
 
+        let _gate = self.context.read_snapshots.gate();
         let value = IVec::from(value);
+        let mut indexing = self.indexes.begin(&self.context, guard)?;
 
         loop {
             let View { node_view, pid, .. } =
                 self.view_for_key(key.as_ref(), guard)?;
 
-            let mut subscriber_reservation = self.subscribers.reserve(&key);
-
             let (encoded_key, last_value) =
                 node_view.node_kv_pair(key.as_ref());
-            let frag = Link::Set(encoded_key, value.clone());
+
+            if last_value.is_some() && self.write_once.load(Acquire) {
+                return Err(Error::ReadOnlyKey(key.as_ref().into()));
+            }
+
+            let last_ivec = if let Some(last) = &last_value {
+                if let Some(ivec) = self.resolve_value(last, guard)? {
+                    Some(ivec)
+                } else {
+                    self.looped();
+                    continue;
+                }
+            } else {
+                None
+            };
+
+            let mut subscriber_reservation = self.subscribers.reserve(&key);
+
+            self.context.read_snapshots.preserve(
+                &self.tree_id,
+                key.as_ref(),
+                last_ivec.as_ref(),
+            );
+
+            // stored for each attempt, as nothing may be paged
+            // in while the peg of an overflow page is held
+            let stored_value = PendingValue::store(self, &value, guard)?;
+            let frag = Link::Set(encoded_key, stored_value.value.clone());
             let link = self.context.pagecache.link(
                 pid,
                 node_view.0,
                 frag.clone(),
+                self.compress_pages(),
                 guard,
             )?;
             if let Ok(_new_cas_key) = link {
                 // success
+                stored_value.linked();
+                if let Some(last) = &last_value {
+                    self.free_replaced_value(last, guard);
+                }
+
+                if let Some(write) = indexing.take() {
+                    let old = last_ivec.as_ref().map(AsRef::as_ref);
+                    write.commit(key.as_ref(), old, Some(&value), guard)?;
+                }
+
                 if let Some(res) = subscriber_reservation.take() {
                     let event = subscriber::Event::Insert {
                         key: key.as_ref().into(),
                         value,
                     };
 
-                    res.complete(&event);
+                    self.complete_subscribers(res, event);
                 }
 
-                return Ok(last_value);
+                return Ok(last_ivec);
             }
-            M.tree_looped();
+            self.looped();
         }
     }
 
@@ -340,7 +539,7 @@ impl Tree {
     /// # Ok(()) }
     /// ```
     pub fn apply_batch(&self, batch: Batch) -> Result<()> {
-        let _ = self.concurrency_control.write();
+        let _cc = self.concurrency_control.write();
         let guard = pin();
         self.apply_batch_inner(batch, &guard)
     }
@@ -350,6 +549,17 @@ impl Tree {
         batch: Batch,
         guard: &Guard,
     ) -> Result<()> {
+        let _gate = self.context.read_snapshots.gate();
+
+        // reject the whole batch before writing any of it
+        if self.write_once.load(Acquire) {
+            for k in batch.writes.keys() {
+                if self.get_inner(k, guard)?.is_some() {
+                    return Err(Error::ReadOnlyKey(k.clone()));
+                }
+            }
+        }
+
         let peg = self.context.pin_log(guard)?;
         for (k, v_opt) in batch.writes {
             if let Some(v) = v_opt {
@@ -365,6 +575,323 @@ impl Tree {
         peg.seal_batch(guard)
     }
 
+    /// Load an empty `Tree` from key-value pairs that are
+    /// sorted by key in strictly ascending order. Nodes are
+    /// built bottom-up from the input, which is much faster
+    /// than calling `insert` for each pair.
+    ///
+    /// Subscribers are not notified of the loaded pairs,
+    /// and the `Tree` must not be written to through other
+    /// handles while it is being loaded. If a key is not
+    /// greater than the one before it, the pages written so
+    /// far are freed, the `Tree` is left empty, and
+    /// `Error::Unsupported` is returned.
+    ///
+    /// A crash during the load leaves the `Tree` empty too,
+    /// but the pages written before the crash are not freed
+    /// on recovery. Nothing links to them, so their space in
+    /// the log is leaked for good, and `Db::verify` reports
+    /// each of them as a `Violation::OrphanedPage`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    ///
+    /// t.bulk_load((0..1000_u32).map(|i| (i.to_be_bytes(), vec![0; 8])))?;
+    ///
+    /// assert_eq!(t.len(), 1000);
+    /// assert!(t.get(7_u32.to_be_bytes())?.is_some());
+    ///
+    /// // unsorted input is rejected
+    /// let unsorted = vec![(b"b", b"x".to_vec()), (b"a", b"x".to_vec())];
+    /// assert!(t.open_tree("other")?.bulk_load(unsorted).is_err());
+    /// # Ok(()) }
+    /// ```
+    pub fn bulk_load<I, K, V>(&self, iter: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<[u8]>,
+        IVec: From<V>,
+    {
+        if self.context.read_only {
+            return Err(Error::Unsupported(
+                "the database is in read-only mode".to_owned(),
+            ));
+        }
+
+        if !self.indexes.is_empty() {
+            return Err(Error::Unsupported(
+                "bulk_load can't maintain the secondary indexes \
+                 or versions of a Tree"
+                    .to_owned(),
+            ));
+        }
+
+        let _cc = self.concurrency_control.write();
+        let _gate = self.context.read_snapshots.gate();
+
+        if let Some(first) = self.iter().next_inner() {
+            first?;
+            return Err(Error::Unsupported(
+                "bulk_load may only be used on an empty Tree".to_owned(),
+            ));
+        }
+
+        let guard = pin();
+
+        // remember the current structure so we can
+        // free it after installing the loaded one
+        let root_pid = self.root.load(Acquire);
+        let mut old_leftmost_chain = vec![];
+        let mut cursor = root_pid;
+        while let Some(view) = self.view_for_pid(cursor, &guard)? {
+            if let Some(index) = view.data.index_ref() {
+                cursor = index.pointers[0];
+                old_leftmost_chain.push(cursor);
+            } else {
+                break;
+            }
+        }
+
+        drop(guard);
+
+        let mut leaves = vec![];
+        let mut pending_values = vec![];
+
+        if let Err(e) =
+            self.bulk_load_leaves(iter, &mut leaves, &mut pending_values)
+        {
+            let guard = pin();
+            for value in &pending_values {
+                self.free_value(value, &guard)?;
+            }
+            drop(guard);
+            if let Some((_, first_leaf_pid)) = leaves.first() {
+                self.gc_pages(vec![*first_leaf_pid])?;
+            }
+            return Err(e);
+        }
+
+        if leaves.is_empty() {
+            return Ok(());
+        }
+
+        // build index levels until a single node
+        // remains, which becomes the new root
+        let mut level = leaves;
+        let root = loop {
+            let mut nodes = Tree::bulk_load_index_level(
+                &level,
+                Node::split_threshold(&self.context, true),
+            );
+
+            if nodes.len() == 1 {
+                break nodes.pop().unwrap();
+            }
+
+            let guard = pin();
+            let mut next = None;
+            let mut allocated = Vec::with_capacity(nodes.len());
+            for mut node in nodes.into_iter().rev() {
+                node.next = next;
+                let lo = node.lo.clone();
+                let (pid, _) = self.context.pagecache.allocate(
+                    node,
+                    self.compress_pages(),
+                    &guard,
+                )?;
+                next = Some(pid);
+                allocated.push((lo, pid));
+            }
+            allocated.reverse();
+            level = allocated;
+        };
+
+        self.replace_bulk_loaded(root_pid, root)?;
+
+        self.gc_pages(old_leftmost_chain)
+    }
+
+    fn bulk_load_leaves<I, K, V>(
+        &self,
+        iter: I,
+        leaves: &mut Vec<(IVec, PageId)>,
+        values: &mut Vec<Value>,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<[u8]>,
+        IVec: From<V>,
+    {
+        let fanout = Node::split_threshold(&self.context, false);
+        let mut keys: Vec<IVec> = Vec::with_capacity(fanout);
+        let mut last_key: Option<IVec> = None;
+
+        for (k, v) in iter {
+            let key = <IVec as From<&[u8]>>::from(k.as_ref());
+
+            if let Some(last) = &last_key {
+                if *last >= key {
+                    return Err(Error::Unsupported(format!(
+                        "bulk_load requires keys in strictly ascending \
+                         order, but {:?} followed {:?}",
+                        key, last
+                    )));
+                }
+            }
+            last_key = Some(key.clone());
+
+            // snapshots taken before the load saw an empty tree
+            self.context.read_snapshots.preserve(&self.tree_id, &key, None);
+
+            let guard = pin();
+
+            if leaves.is_empty() || keys.len() == fanout {
+                // the next leaf's page is allocated before
+                // writing out the current one, so that the
+                // current one can point to it.
+                let (pid, _) = self.context.pagecache.allocate(
+                    Node::default(),
+                    self.compress_pages(),
+                    &guard,
+                )?;
+
+                // like a split, the bound between two leaves only
+                // needs to be long enough to tell their keys apart.
+                let lo = keys.last().map_or_else(IVec::default, |left_max| {
+                    <IVec as From<&[u8]>>::from(shortest_separator(
+                        left_max, &key,
+                    ))
+                });
+
+                if let Some((last_lo, last_pid)) = leaves.last() {
+                    let leaf = Tree::bulk_load_leaf(
+                        last_lo.clone(),
+                        lo.clone(),
+                        Some(pid),
+                        std::mem::replace(
+                            &mut keys,
+                            Vec::with_capacity(fanout),
+                        ),
+                        std::mem::replace(values, Vec::with_capacity(fanout)),
+                    );
+                    self.replace_bulk_loaded(*last_pid, leaf)?;
+                }
+
+                leaves.push((lo, pid));
+            }
+
+            values.push(self.store_value(&IVec::from(v), &guard)?);
+            keys.push(key);
+        }
+
+        if let Some((lo, last_pid)) = leaves.last() {
+            let leaf = Tree::bulk_load_leaf(
+                lo.clone(),
+                IVec::default(),
+                None,
+                keys,
+                std::mem::replace(values, vec![]),
+            );
+            self.replace_bulk_loaded(*last_pid, leaf)?;
+        }
+
+        Ok(())
+    }
+
+    fn bulk_load_leaf(
+        lo: IVec,
+        hi: IVec,
+        next: Option<PageId>,
+        keys: Vec<IVec>,
+        values: Vec<Value>,
+    ) -> Node {
+        let prefix_len = shared_prefix_len(&lo, &hi);
+
+        Node {
+            next,
+            lo,
+            hi,
+            merging_child: None,
+            merging: false,
+            prefix_len,
+            data: Data::Leaf(Leaf {
+                keys: keys
+                    .iter()
+                    .map(|k| IVec::from(&k[prefix_len as usize..]))
+                    .collect(),
+                values,
+            }),
+        }
+    }
+
+    // Groups the children of a level into index nodes,
+    // without linking them to their right siblings.
+    fn bulk_load_index_level(
+        children: &[(IVec, PageId)],
+        fanout: usize,
+    ) -> Vec<Node> {
+        let groups: Vec<&[(IVec, PageId)]> = children.chunks(fanout).collect();
+
+        groups
+            .iter()
+            .enumerate()
+            .map(|(i, group)| {
+                let lo = group[0].0.clone();
+                let hi = groups
+                    .get(i + 1)
+                    .map_or_else(IVec::default, |next| next[0].0.clone());
+                let prefix_len = shared_prefix_len(&lo, &hi);
+
+                Node {
+                    next: None,
+                    lo,
+                    hi,
+                    merging_child: None,
+                    merging: false,
+                    prefix_len,
+                    data: Data::Index(Index {
+                        keys: group
+                            .iter()
+                            .map(|(k, _)| IVec::from(&k[prefix_len as usize..]))
+                            .collect(),
+                        pointers: group.iter().map(|(_, pid)| *pid).collect(),
+                    }),
+                }
+            })
+            .collect()
+    }
+
+    // Nobody else links to pages written during a bulk load,
+    // so replacing them can only fail due to concurrent
+    // maintenance like page rewrites, and we can retry.
+    fn replace_bulk_loaded(&self, pid: PageId, node: Node) -> Result<()> {
+        let guard = pin();
+        let mut new = node;
+        loop {
+            let view = self
+                .context
+                .pagecache
+                .get(pid, &guard)?
+                .expect("bulk loaded page should not be freed");
+
+            match self.context.pagecache.replace(
+                pid,
+                view.0,
+                new,
+                self.compress_pages(),
+                &guard,
+            )? {
+                Ok(_) => return Ok(()),
+                Err(Some((_, returned))) => new = returned,
+                Err(None) => panic!("bulk loaded page should not be freed"),
+            }
+        }
+    }
+
     /// Retrieve a value from the `Tree` if it exists.
     ///
     /// # Examples
@@ -395,80 +922,481 @@ impl Tree {
 
         trace!("getting key {:?}", key.as_ref());
 
-        let View { node_view, .. } = self.view_for_key(key.as_ref(), guard)?;
-
-        let pair = node_view.leaf_pair_for_key(key.as_ref());
-        let val = pair.map(|kv| kv.1.clone());
+        loop {
+            let View { node_view, .. } =
+                match self.filtered_read_view_for_key(key.as_ref(), guard)? {
+                    Some(view) => view,
+                    None => return Ok(None),
+                };
 
-        Ok(val)
-    }
+            let value = match node_view.leaf_pair_for_key(key.as_ref()) {
+                Some((_, value)) => value,
+                None => return Ok(None),
+            };
 
-    #[doc(hidden)]
-    #[deprecated(since = "0.24.2", note = "replaced by `Tree::remove`")]
-    pub fn del<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<IVec>> {
-        self.remove(key)
+            if let Some(ivec) = self.resolve_value(value, guard)? {
+                return Ok(Some(ivec));
+            }
+            self.looped();
+        }
     }
 
-    /// Delete a value, returning the old value if it existed.
+    /// Retrieve a value from the `Tree` without blocking on
+    /// IO. If the nodes on the way to the key, and the value
+    /// itself, are in the cache, the returned future is
+    /// ready right away. Otherwise they are paged in on
+    /// sled's IO threadpool, so that the calling thread is
+    /// never blocked reading the log.
     ///
     /// # Examples
     ///
     /// ```
-    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # async fn example() -> sled::Result<()> {
     /// let config = sled::Config::new().temporary(true);
     /// let t = config.open()?;
-    /// t.insert(&[1], vec![1]);
-    /// assert_eq!(t.remove(&[1]), Ok(Some(sled::IVec::from(vec![1]))));
-    /// assert_eq!(t.remove(&[1]), Ok(None));
+    ///
+    /// t.insert(&[0], vec![0])?;
+    /// assert_eq!(t.get_async(&[0]).await?, Some(sled::IVec::from(vec![0])));
+    /// assert_eq!(t.get_async(&[1]).await?, None);
     /// # Ok(()) }
     /// ```
-    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<IVec>> {
-        let guard = pin();
-        let _ = self.concurrency_control.read(&guard);
-        self.remove_inner(key, &guard)
-    }
-
-    pub(crate) fn remove_inner<K: AsRef<[u8]>>(
+    #[cfg(feature = "async")]
+    pub fn get_async<K: AsRef<[u8]>>(
         &self,
         key: K,
-        guard: &Guard,
-    ) -> Result<Option<IVec>> {
-        let _measure = Measure::new(&M.tree_del);
-
-        trace!("removing key {:?}", key.as_ref());
+    ) -> impl std::future::Future<Output = Result<Option<IVec>>> {
+        let resident = {
+            let guard = pin();
+            let _ = self.concurrency_control.read(&guard);
+            self.get_resident(key.as_ref(), &guard)
+        };
 
-        if self.context.read_only {
-            return Ok(None);
-        }
+        let ready = match resident {
+            Ok(Some(value)) => Ok(value),
+            Err(e) => Err(e),
+            Ok(None) => {
+                let tree = self.clone();
+                let owned_key = IVec::from(key.as_ref());
+                return threadpool::spawn(move || tree.get(owned_key));
+            }
+        };
 
-        loop {
-            let View { pid, node_view, .. } =
-                self.view_for_key(key.as_ref(), guard)?;
+        let (filler, promise) = OneShot::pair();
+        filler.fill(ready);
+        promise
+    }
 
-            let mut subscriber_reservation = self.subscribers.reserve(&key);
+    // Looks a key up without paging anything in or
+    // repairing the tree's structure, returning `None` if
+    // a node or value on the way is not resident, or if a
+    // concurrent split or merge got in the way.
+    #[cfg(feature = "async")]
+    fn get_resident(
+        &self,
+        key: &[u8],
+        guard: &Guard,
+    ) -> Result<Option<Option<IVec>>> {
+        let pagecache = &self.context.pagecache;
+        let mut cursor = self.root.load(Acquire);
 
-            let (encoded_key, existing_val) =
-                node_view.node_kv_pair(key.as_ref());
+        // the root of a dropped tree is never resident, which
+        // leaves reporting it to `get`
+        loop {
+            let node_view = match pagecache.get_resident(cursor, guard)? {
+                Some(node_view) => node_view,
+                None => return Ok(None),
+            };
 
-            if existing_val.is_none() {
+            if key < node_view.lo.as_ref() {
                 return Ok(None);
             }
 
-            let frag = Link::Del(encoded_key);
-            let link =
-                self.context.pagecache.link(pid, node_view.0, frag, guard)?;
+            if key >= node_view.hi.as_ref() && !node_view.hi.is_empty() {
+                // follow a split that the parent doesn't know of yet
+                cursor = if let Some(next) = node_view.next {
+                    next
+                } else {
+                    return Ok(None);
+                };
+                continue;
+            }
 
-            if link.is_ok() {
-                // success
-                if let Some(res) = subscriber_reservation.take() {
-                    let event =
-                        subscriber::Event::Remove { key: key.as_ref().into() };
+            if node_view.data.is_index() {
+                cursor = node_view.index_next_node(key).1;
+                continue;
+            }
 
-                    res.complete(&event);
-                }
+            pagecache.record_read(node_view.0);
 
-                return Ok(existing_val);
-            }
+            return match node_view.leaf_pair_for_key(key) {
+                None => Ok(Some(None)),
+                Some((_, Value::Inline(ivec))) => Ok(Some(Some(ivec.clone()))),
+                Some((_, Value::Overflow(value_pid))) => Ok(pagecache
+                    .get_value_resident(*value_pid, guard)?
+                    .map(|value_view| Some((*value_view).clone()))),
+            };
+        }
+    }
+
+    /// Retrieve a value from the `Tree` if it exists, without
+    /// copying it. The returned `PinnedValue` borrows the value
+    /// from the page cache, and keeps the current thread pinned
+    /// until it is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    ///
+    /// t.insert(&[0], vec![0])?;
+    ///
+    /// let pinned = t.get_pinned(&[0])?.unwrap();
+    /// assert_eq!(&*pinned, &[0]);
+    ///
+    /// // overwriting the key does not change a pinned value
+    /// t.insert(&[0], vec![1])?;
+    /// assert_eq!(pinned.to_owned(), sled::IVec::from(vec![0]));
+    /// drop(pinned);
+    ///
+    /// assert_eq!(t.get_pinned(&[1])?, None);
+    /// # Ok(()) }
+    /// ```
+    pub fn get_pinned<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+    ) -> Result<Option<PinnedValue>> {
+        let _measure = Measure::new(&M.tree_get);
+
+        let guard = pin();
+        let _ = self.concurrency_control.read(&guard);
+
+        let value = loop {
+            let View { node_view, .. } =
+                self.read_view_for_key(key.as_ref(), &guard)?;
+
+            let pair = node_view.leaf_pair_for_key(key.as_ref());
+            let value: *const IVec = match pair {
+                Some((_, Value::Inline(ivec))) => ivec,
+                Some((_, Value::Overflow(value_pid))) => {
+                    if let Some(value_view) =
+                        self.context.pagecache.get_value(*value_pid, &guard)?
+                    {
+                        &*value_view
+                    } else {
+                        // the value was overwritten and its
+                        // page freed since the leaf was read
+                        self.looped();
+                        continue;
+                    }
+                }
+                None => return Ok(None),
+            };
+            break value;
+        };
+
+        Ok(Some(PinnedValue::new(guard, value)))
+    }
+
+    /// Retrieve a reader over a value in the `Tree` if it
+    /// exists. Unlike `get`, this avoids reading a large value
+    /// into memory all at once when it is not already cached,
+    /// by streaming it from the file it was written to.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::io::Read;
+    ///
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    ///
+    /// t.insert(&[0], vec![0; 1024 * 1024])?;
+    ///
+    /// let mut reader = t.get_reader(&[0])?.unwrap();
+    /// let mut buf = vec![];
+    /// reader.read_to_end(&mut buf)?;
+    /// assert_eq!(buf, vec![0; 1024 * 1024]);
+    /// assert!(t.get_reader(&[1])?.is_none());
+    /// # Ok(()) }
+    /// ```
+    pub fn get_reader<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+    ) -> Result<Option<ValueReader>> {
+        let _measure = Measure::new(&M.tree_get);
+
+        trace!("getting reader for key {:?}", key.as_ref());
+
+        let guard = pin();
+        let _ = self.concurrency_control.read(&guard);
+
+        loop {
+            let View { node_view, .. } =
+                self.read_view_for_key(key.as_ref(), &guard)?;
+
+            let value = match node_view.leaf_pair_for_key(key.as_ref()) {
+                Some((_, value)) => value,
+                None => return Ok(None),
+            };
+
+            match value {
+                Value::Inline(ivec) => {
+                    return Ok(Some(ValueReader::from_memory(ivec.clone())));
+                }
+                Value::Overflow(value_pid) => {
+                    let reader_opt = self
+                        .context
+                        .pagecache
+                        .value_reader(*value_pid, &guard)?;
+                    if reader_opt.is_some() {
+                        return Ok(reader_opt);
+                    }
+                }
+            }
+            self.looped();
+        }
+    }
+
+    /// Retrieve a value from the `Tree` if it exists, possibly
+    /// as it was up to `max_staleness` ago. Reads are served
+    /// from a copy of the whole `Tree` that is taken again, by
+    /// iterating over it, once it is older than the bound that
+    /// a reader asks for. This avoids traversing the `Tree` for
+    /// each read, for dashboards and caches of small `Tree`s
+    /// that are read far more often than they change, but keeps
+    /// every item in memory, so it isn't a good fit for large
+    /// ones. While one thread takes a new copy, others read the
+    /// current state of the `Tree` with `get` instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::IVec;
+    /// use std::time::Duration;
+    ///
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    ///
+    /// t.insert(b"hits", b"1")?;
+    /// let minute = Duration::from_secs(60);
+    /// assert_eq!(t.get_stale(b"hits", minute)?, Some(IVec::from(b"1")));
+    ///
+    /// // this is likely to still be served from the earlier copy
+    /// t.insert(b"hits", b"2")?;
+    /// let hits = t.get_stale(b"hits", minute)?.unwrap();
+    /// assert!(hits == b"1" || hits == b"2");
+    ///
+    /// // a zero bound always reads the current value
+    /// let now = Duration::from_secs(0);
+    /// assert_eq!(t.get_stale(b"hits", now)?, Some(IVec::from(b"2")));
+    /// # Ok(()) }
+    /// ```
+    pub fn get_stale<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        max_staleness: Duration,
+    ) -> Result<Option<IVec>> {
+        if let Some(items) = self.stale_snapshot.fresh(max_staleness) {
+            return Ok(items.get(key.as_ref()).cloned());
+        }
+
+        let refreshing = if let Some(refreshing) =
+            self.stale_snapshot.refreshing.try_lock()
+        {
+            refreshing
+        } else {
+            return self.get(key);
+        };
+
+        // the snapshot's age is counted from before any of its
+        // items are read, so that it bounds all of them
+        let taken_at = Instant::now();
+        let items: BTreeMap<IVec, IVec> = self.iter().collect::<Result<_>>()?;
+        let items = Arc::new(items);
+        let ret = items.get(key.as_ref()).cloned();
+
+        *self.stale_snapshot.taken.write() = Some((taken_at, items));
+        drop(refreshing);
+
+        if taken_at.elapsed() > max_staleness {
+            // taking the snapshot took longer than the bound
+            return self.get(key);
+        }
+
+        Ok(ret)
+    }
+
+    #[doc(hidden)]
+    #[deprecated(since = "0.24.2", note = "replaced by `Tree::remove`")]
+    pub fn del<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<IVec>> {
+        self.remove(key)
+    }
+
+    /// Delete a value, returning the old value if it existed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    /// t.insert(&[1], vec![1]);
+    /// assert_eq!(t.remove(&[1]), Ok(Some(sled::IVec::from(vec![1]))));
+    /// assert_eq!(t.remove(&[1]), Ok(None));
+    /// # Ok(()) }
+    /// ```
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<IVec>> {
+        let guard = pin();
+        let _cc = self.concurrency_control.read(&guard);
+        self.remove_inner(key, &guard)
+    }
+
+    /// Delete a value like `remove`, only returning once
+    /// the removal is as durable as requested.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::Durability;
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    /// t.insert(&[1], vec![1])?;
+    /// assert_eq!(
+    ///     t.remove_with_durability(&[1], Durability::DataSync),
+    ///     Ok(Some(sled::IVec::from(vec![1])))
+    /// );
+    /// # Ok(()) }
+    /// ```
+    pub fn remove_with_durability<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        durability: Durability,
+    ) -> Result<Option<IVec>> {
+        let last = self.remove(key)?;
+        self.make_durable(durability)?;
+        Ok(last)
+    }
+
+    // Delivers the event of a completed write, holding it back
+    // from `durable_only` subscribers until the write is stable.
+    fn complete_subscribers(
+        &self,
+        mut reservation: ReservedBroadcast,
+        event: Event,
+    ) {
+        if let Some(durable_only) = reservation.take_durable_only() {
+            let durable_event = event.clone();
+            self.context
+                .pagecache
+                .on_stable(move || durable_only.complete(&durable_event));
+        }
+
+        reservation.complete(&event);
+    }
+
+    // Waits until every write made so far, including the
+    // caller's, is as durable as requested.
+    fn make_durable(&self, durability: Durability) -> Result<()> {
+        if durability == Durability::Buffered {
+            return Ok(());
+        }
+
+        let _ = self.context.pagecache.flush()?;
+
+        // temporary files are never synced,
+        // just like the log writes themselves
+        if !self.context.is_temporary() {
+            let file = &self.context.file;
+            if durability == Durability::Sync {
+                file.sync_all()?;
+            } else {
+                file.sync_data()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn remove_inner<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        guard: &Guard,
+    ) -> Result<Option<IVec>> {
+        let _measure = Measure::new(&M.tree_del);
+
+        trace!("removing key {:?}", key.as_ref());
+
+        if self.context.read_only {
+            return Ok(None);
+        }
+
+        let _gate = self.context.read_snapshots.gate();
+        let mut indexing = self.indexes.begin(&self.context, guard)?;
+
+        loop {
+            let View { pid, node_view, .. } =
+                self.view_for_key(key.as_ref(), guard)?;
+
+            let (encoded_key, existing_opt) =
+                node_view.node_kv_pair(key.as_ref());
+
+            let existing_val = if let Some(existing) = existing_opt {
+                existing
+            } else {
+                return Ok(None);
+            };
+
+            if self.write_once.load(Acquire) {
+                return Err(Error::ReadOnlyKey(key.as_ref().into()));
+            }
+
+            let existing_ivec =
+                if let Some(ivec) = self.resolve_value(&existing_val, guard)? {
+                    ivec
+                } else {
+                    continue;
+                };
+
+            let mut subscriber_reservation = self.subscribers.reserve(&key);
+
+            self.context.read_snapshots.preserve(
+                &self.tree_id,
+                key.as_ref(),
+                Some(&existing_ivec),
+            );
+
+            let frag = Link::Del(encoded_key);
+            let link = self.context.pagecache.link(
+                pid,
+                node_view.0,
+                frag,
+                self.compress_pages(),
+                guard,
+            )?;
+
+            if link.is_ok() {
+                // success
+                self.free_replaced_value(&existing_val, guard);
+
+                if let Some(write) = indexing.take() {
+                    let old = Some(&*existing_ivec);
+                    write.commit(key.as_ref(), old, None, guard)?;
+                }
+
+                if let Some(res) = subscriber_reservation.take() {
+                    let event =
+                        subscriber::Event::Remove { key: key.as_ref().into() };
+
+                    self.complete_subscribers(res, event);
+                }
+
+                return Ok(Some(existing_ivec));
+            }
         }
     }
 
@@ -539,7 +1467,7 @@ impl Tree {
         let _measure = Measure::new(&M.tree_cas);
 
         let guard = pin();
-        let _ = self.concurrency_control.read(&guard);
+        let _cc = self.concurrency_control.read(&guard);
 
         if self.context.read_only {
             return Err(Error::Unsupported(
@@ -548,6 +1476,8 @@ impl Tree {
         }
 
         let new = new.map(IVec::from);
+        let _gate = self.context.read_snapshots.gate();
+        let mut indexing = self.indexes.begin(&self.context, &guard)?;
 
         // we need to retry caps until old != cur, since just because
         // cap fails it doesn't mean our value was changed.
@@ -555,8 +1485,20 @@ impl Tree {
             let View { pid, node_view, .. } =
                 self.view_for_key(key.as_ref(), &guard)?;
 
-            let (encoded_key, current_value) =
+            let (encoded_key, current_stored) =
                 node_view.node_kv_pair(key.as_ref());
+
+            let current_value = if let Some(current) = &current_stored {
+                if let Some(ivec) = self.resolve_value(current, &guard)? {
+                    Some(ivec)
+                } else {
+                    self.looped();
+                    continue;
+                }
+            } else {
+                None
+            };
+
             let matches = match (old.as_ref(), &current_value) {
                 (None, None) => true,
                 (Some(o), Some(ref c)) => o.as_ref() == &**c,
@@ -570,17 +1512,54 @@ impl Tree {
                 }));
             }
 
+            if current_value.is_some() && self.write_once.load(Acquire) {
+                return Err(Error::ReadOnlyKey(key.as_ref().into()));
+            }
+
             let mut subscriber_reservation = self.subscribers.reserve(&key);
 
-            let frag = if let Some(ref new) = new {
-                Link::Set(encoded_key, new.clone())
+            let new_stored = if let Some(ref new) = new {
+                Some(PendingValue::store(self, new, &guard)?)
+            } else {
+                None
+            };
+
+            self.context.read_snapshots.preserve(
+                &self.tree_id,
+                key.as_ref(),
+                current_value.as_ref(),
+            );
+
+            let frag = if let Some(stored) = &new_stored {
+                Link::Set(encoded_key, stored.value.clone())
             } else {
                 Link::Del(encoded_key)
             };
-            let link =
-                self.context.pagecache.link(pid, node_view.0, frag, &guard)?;
+            let link = self.context.pagecache.link(
+                pid,
+                node_view.0,
+                frag,
+                self.compress_pages(),
+                &guard,
+            )?;
 
             if link.is_ok() {
+                if let Some(stored) = new_stored {
+                    stored.linked();
+                }
+                if let Some(current) = &current_stored {
+                    self.free_replaced_value(current, &guard);
+                }
+
+                if let Some(write) = indexing.take() {
+                    write.commit(
+                        key.as_ref(),
+                        current_value.as_ref().map(AsRef::as_ref),
+                        new.as_ref().map(AsRef::as_ref),
+                        &guard,
+                    )?;
+                }
+
                 if let Some(res) = subscriber_reservation.take() {
                     let event = if let Some(new) = new {
                         subscriber::Event::Insert {
@@ -591,12 +1570,12 @@ impl Tree {
                         subscriber::Event::Remove { key: key.as_ref().into() }
                     };
 
-                    res.complete(&event);
+                    self.complete_subscribers(res, event);
                 }
 
                 return Ok(Ok(()));
             }
-            M.tree_looped();
+            self.looped();
         }
     }
 
@@ -745,19 +1724,113 @@ impl Tree {
     ) -> Result<Option<IVec>>
     where
         K: AsRef<[u8]>,
-        F: FnMut(Option<&[u8]>) -> Option<V>,
-        IVec: From<V>,
+        F: FnMut(Option<&[u8]>) -> Option<V>,
+        IVec: From<V>,
+    {
+        let key_ref = key.as_ref();
+        let mut current = self.get(key_ref)?;
+
+        loop {
+            let tmp = current.as_ref().map(AsRef::as_ref);
+            let next = f(tmp);
+            match self.compare_and_swap(key_ref, tmp, next)? {
+                Ok(()) => return Ok(current),
+                Err(CompareAndSwapError { current: cur, .. }) => {
+                    current = cur;
+                }
+            }
+        }
+    }
+
+    /// Returns the value of `key`, first inserting the value
+    /// returned by `default` if there isn't one. If another
+    /// thread inserts a value for `key` at the same time, that
+    /// value is returned instead of `default`'s, which is only
+    /// ever called once and only if the key was missing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let tree = config.open()?;
+    ///
+    /// assert_eq!(tree.get_or_insert_with("k", || vec![1])?, vec![1]);
+    /// assert_eq!(tree.get_or_insert_with("k", || vec![2])?, vec![1]);
+    /// # Ok(()) }
+    /// ```
+    pub fn get_or_insert_with<K, V, F>(
+        &self,
+        key: K,
+        default: F,
+    ) -> Result<IVec>
+    where
+        K: AsRef<[u8]>,
+        F: FnOnce() -> V,
+        IVec: From<V>,
+    {
+        let key_ref = key.as_ref();
+        if let Some(current) = self.get(key_ref)? {
+            return Ok(current);
+        }
+
+        let new = IVec::from(default());
+        loop {
+            match self.compare_and_swap::<_, &[u8], IVec>(
+                key_ref,
+                None,
+                Some(new.clone()),
+            )? {
+                Ok(()) => return Ok(new),
+                Err(CompareAndSwapError { current: Some(cur), .. }) => {
+                    return Ok(cur);
+                }
+                // removed again before the cas could see it
+                Err(CompareAndSwapError { current: None, .. }) => {}
+            }
+        }
+    }
+
+    /// Removes `key` if it has a value for which `pred`
+    /// returns `true`, and returns the value that was removed.
+    /// The value is never removed if it was changed after
+    /// `pred` checked it. Instead, `pred` is called again
+    /// with the value that replaced it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let tree = config.open()?;
+    ///
+    /// tree.insert("k", vec![1])?;
+    /// assert_eq!(tree.remove_if("k", |v| v == [2])?, None);
+    /// assert_eq!(tree.remove_if("k", |v| v == [1])?, Some(vec![1].into()));
+    /// assert_eq!(tree.get("k")?, None);
+    /// # Ok(()) }
+    /// ```
+    pub fn remove_if<K, F>(&self, key: K, mut pred: F) -> Result<Option<IVec>>
+    where
+        K: AsRef<[u8]>,
+        F: FnMut(&[u8]) -> bool,
     {
         let key_ref = key.as_ref();
         let mut current = self.get(key_ref)?;
 
         loop {
-            let tmp = current.as_ref().map(AsRef::as_ref);
-            let next = f(tmp);
-            match self.compare_and_swap(key_ref, tmp, next)? {
+            let cur = match current {
+                Some(ref cur) if pred(cur) => cur,
+                _ => return Ok(None),
+            };
+            match self.compare_and_swap::<_, _, IVec>(
+                key_ref,
+                Some(cur),
+                None,
+            )? {
                 Ok(()) => return Ok(current),
-                Err(CompareAndSwapError { current: cur, .. }) => {
-                    current = cur;
+                Err(CompareAndSwapError { current: next, .. }) => {
+                    current = next;
                 }
             }
         }
@@ -776,6 +1849,12 @@ impl Tree {
     /// `Subscriber` implements both `Iterator<Item = Event>`
     /// and `Future<Output=Option<Event>>`
     ///
+    /// Events are delivered as soon as their writes complete
+    /// in memory, which may be before those writes are durable.
+    /// A subscriber may therefore act on a write that is lost
+    /// if the system crashes before the next flush. Use
+    /// `watch_prefix_durable_only` if that is not acceptable.
+    ///
     /// # Examples
     ///
     /// Synchronous, blocking subscriber:
@@ -814,6 +1893,77 @@ impl Tree {
         self.subscribers.register(prefix.as_ref())
     }
 
+    /// Like `watch_prefix`, except that each `Event` is only
+    /// delivered once its write is durable, after the next
+    /// flush, so that a subscriber never acts on a write that
+    /// could be lost in a crash. Events for particular keys
+    /// are still delivered in the order that they happened.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, Event};
+    /// let config = Config::new().temporary(true).flush_every_ms(None);
+    ///
+    /// let tree = config.open()?;
+    /// let mut subscriber = tree.watch_prefix_durable_only(vec![]);
+    ///
+    /// tree.insert(vec![0], vec![1])?;
+    ///
+    /// // the insert is only delivered after it's flushed
+    /// tree.flush()?;
+    /// match subscriber.next() {
+    ///     Some(Event::Insert { key, value }) => assert_eq!(key, vec![0]),
+    ///     other => panic!("unexpected event {:?}", other),
+    /// }
+    /// # Ok(()) }
+    /// ```
+    pub fn watch_prefix_durable_only<P: AsRef<[u8]>>(
+        &self,
+        prefix: P,
+    ) -> Subscriber {
+        self.subscribers.register_durable_only(prefix.as_ref())
+    }
+
+    /// Like `watch_prefix`, except that at most `capacity`
+    /// events are buffered for the `Subscriber`. Once its
+    /// buffer is full, writers drop further events for it
+    /// instead of blocking until it catches up, and
+    /// `Subscriber::lagged` reports how many were dropped.
+    /// This suits async consumers that would rather skip
+    /// events than stall writers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    ///
+    /// let tree = config.open()?;
+    /// let mut subscriber = tree.watch_prefix_bounded(vec![], 1);
+    ///
+    /// tree.insert(vec![0], vec![0])?;
+    /// tree.insert(vec![1], vec![1])?;
+    ///
+    /// // the second insert didn't fit in the buffer
+    /// assert_eq!(subscriber.next().unwrap().key(), &[0]);
+    /// assert_eq!(subscriber.lagged(), 1);
+    /// # Ok(()) }
+    /// ```
+    pub fn watch_prefix_bounded<P: AsRef<[u8]>>(
+        &self,
+        prefix: P,
+        capacity: usize,
+    ) -> Subscriber {
+        assert!(capacity > 0, "bounded subscribers need a capacity above 0");
+        self.subscribers.register_bounded(prefix.as_ref(), capacity)
+    }
+
     /// Synchronously flushes all dirty IO buffers and calls
     /// fsync. If this succeeds, it is guaranteed that all
     /// previous writes will be recovered if the system
@@ -905,8 +2055,6 @@ impl Tree {
         K: AsRef<[u8]>,
     {
         let _measure = Measure::new(&M.tree_get);
-        let guard = pin();
-        let _ = self.concurrency_control.read(&guard);
         self.range(..key).next_back().transpose()
     }
 
@@ -962,8 +2110,6 @@ impl Tree {
         K: AsRef<[u8]>,
     {
         let _measure = Measure::new(&M.tree_get);
-        let guard = pin();
-        let _ = self.concurrency_control.read(&guard);
         self.range((ops::Bound::Excluded(key), ops::Bound::Unbounded))
             .next()
             .transpose()
@@ -1031,14 +2177,15 @@ impl Tree {
         V: AsRef<[u8]>,
     {
         let guard = pin();
-        let _ = self.concurrency_control.read(&guard);
-        self.merge_inner(key, value)
+        let _cc = self.concurrency_control.read(&guard);
+        self.merge_inner(key, value, &guard)
     }
 
     pub(crate) fn merge_inner<K, V>(
         &self,
         key: K,
         value: V,
+        guard: &Guard,
     ) -> Result<Option<IVec>>
     where
         K: AsRef<[u8]>,
@@ -1066,28 +2213,79 @@ impl Tree {
 
         let merge_operator = merge_operator_opt.as_ref().unwrap();
 
+        let _gate = self.context.read_snapshots.gate();
+        let mut indexing = self.indexes.begin(&self.context, guard)?;
+
         loop {
-            let guard = pin();
             let View { pid, node_view, .. } =
-                self.view_for_key(key.as_ref(), &guard)?;
+                self.view_for_key(key.as_ref(), guard)?;
 
-            let (encoded_key, current_value) =
+            let (encoded_key, current_stored) =
                 node_view.node_kv_pair(key.as_ref());
+
+            let current_value = if let Some(current) = &current_stored {
+                if let Some(ivec) = self.resolve_value(current, guard)? {
+                    Some(ivec)
+                } else {
+                    self.looped();
+                    continue;
+                }
+            } else {
+                None
+            };
+
+            if current_value.is_some() && self.write_once.load(Acquire) {
+                return Err(Error::ReadOnlyKey(key.as_ref().into()));
+            }
+
             let tmp = current_value.as_ref().map(AsRef::as_ref);
             let new = merge_operator(key.as_ref(), tmp, value.as_ref())
                 .map(IVec::from);
 
             let mut subscriber_reservation = self.subscribers.reserve(&key);
 
-            let frag = if let Some(ref new) = new {
-                Link::Set(encoded_key, new.clone())
+            let new_stored = if let Some(ref new) = new {
+                Some(PendingValue::store(self, new, guard)?)
+            } else {
+                None
+            };
+
+            self.context.read_snapshots.preserve(
+                &self.tree_id,
+                key.as_ref(),
+                current_value.as_ref(),
+            );
+
+            let frag = if let Some(stored) = &new_stored {
+                Link::Set(encoded_key, stored.value.clone())
             } else {
                 Link::Del(encoded_key)
             };
-            let link =
-                self.context.pagecache.link(pid, node_view.0, frag, &guard)?;
+            let link = self.context.pagecache.link(
+                pid,
+                node_view.0,
+                frag,
+                self.compress_pages(),
+                guard,
+            )?;
 
             if link.is_ok() {
+                if let Some(stored) = new_stored {
+                    stored.linked();
+                }
+                if let Some(current) = &current_stored {
+                    self.free_replaced_value(current, guard);
+                }
+
+                if let Some(write) = indexing.take() {
+                    write.commit(
+                        key.as_ref(),
+                        current_value.as_ref().map(AsRef::as_ref),
+                        new.as_ref().map(AsRef::as_ref),
+                        guard,
+                    )?;
+                }
+
                 if let Some(res) = subscriber_reservation.take() {
                     let event = if let Some(new) = &new {
                         subscriber::Event::Insert {
@@ -1098,12 +2296,12 @@ impl Tree {
                         subscriber::Event::Remove { key: key.as_ref().into() }
                     };
 
-                    res.complete(&event);
+                    self.complete_subscribers(res, event);
                 }
 
                 return Ok(new);
             }
-            M.tree_looped();
+            self.looped();
         }
     }
 
@@ -1140,42 +2338,418 @@ impl Tree {
     ///   Some(ret)
     /// }
     ///
-    /// let config = Config::new()
-    ///   .temporary(true);
+    /// let config = Config::new()
+    ///   .temporary(true);
+    ///
+    /// let tree = config.open()?;
+    /// tree.set_merge_operator(concatenate_merge);
+    ///
+    /// let k = b"k1";
+    ///
+    /// tree.insert(k, vec![0]);
+    /// tree.merge(k, vec![1]);
+    /// tree.merge(k, vec![2]);
+    /// assert_eq!(tree.get(k), Ok(Some(IVec::from(vec![0, 1, 2]))));
+    ///
+    /// // Replace previously merged data. The merge function will not be called.
+    /// tree.insert(k, vec![3]);
+    /// assert_eq!(tree.get(k), Ok(Some(IVec::from(vec![3]))));
+    ///
+    /// // Merges on non-present values will cause the merge function to be called
+    /// // with `old_value == None`. If the merge function returns something (which it
+    /// // does, in this case) a new value will be inserted.
+    /// tree.remove(k);
+    /// tree.merge(k, vec![4]);
+    /// assert_eq!(tree.get(k), Ok(Some(IVec::from(vec![4]))));
+    /// # Ok(()) }
+    /// ```
+    pub fn set_merge_operator(
+        &self,
+        merge_operator: impl MergeOperator + 'static,
+    ) {
+        let mut mo_write = self.merge_operator.write();
+        *mo_write = Some(Box::new(merge_operator));
+    }
+
+    /// Enables or disables the "dup-sort" mode of this `Tree`,
+    /// in which a key maps to a sorted set of values that are
+    /// added with `insert_dup`, removed with `remove_dup` and
+    /// scanned with `scan_dups`. This is the natural way to
+    /// store secondary indexes, where many items share the
+    /// same indexed value.
+    ///
+    /// Each key-value pair is stored as a single key that
+    /// encodes both, so a dup-sort `Tree` should only be
+    /// accessed through these methods. Like the merge
+    /// operator, this mode is not persisted, and must be
+    /// enabled again every time the `Tree` is opened.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, IVec};
+    ///
+    /// let config = Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// // an index from colors to the ids of fruits
+    /// let by_color = db.open_tree("fruits_by_color")?;
+    /// by_color.set_dup_sort(true);
+    ///
+    /// assert!(by_color.insert_dup("red", "apple")?);
+    /// assert!(by_color.insert_dup("red", "cherry")?);
+    /// assert!(by_color.insert_dup("yellow", "banana")?);
+    ///
+    /// // values are only stored once per key
+    /// assert!(!by_color.insert_dup("red", "apple")?);
+    ///
+    /// let red: Vec<IVec> = by_color.scan_dups("red").collect::<Result<_, _>>()?;
+    /// assert_eq!(red, vec![IVec::from("apple"), IVec::from("cherry")]);
+    ///
+    /// assert!(by_color.remove_dup("red", "apple")?);
+    /// assert_eq!(by_color.scan_dups("red").count(), 1);
+    /// # Ok(()) }
+    /// ```
+    pub fn set_dup_sort(&self, dup_sort: bool) {
+        self.dup_sort.store(dup_sort, Release);
+    }
+
+    /// Adds a value to the set of values of a key in a
+    /// dup-sort `Tree`, returning `false` if the value was
+    /// already present. See `Tree::set_dup_sort`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Unsupported` if dup-sort mode has
+    /// not been enabled with `Tree::set_dup_sort`.
+    pub fn insert_dup<K, V>(&self, key: K, value: V) -> Result<bool>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let guard = pin();
+        let _cc = self.concurrency_control.read(&guard);
+        self.insert_dup_inner(key.as_ref(), value.as_ref(), &guard)
+    }
+
+    pub(crate) fn insert_dup_inner(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        guard: &Guard,
+    ) -> Result<bool> {
+        let encoded = self.dup_key(key, value)?;
+        Ok(self.insert_inner(encoded, IVec::default(), guard)?.is_none())
+    }
+
+    /// Removes a value from the set of values of a key in
+    /// a dup-sort `Tree`, returning `false` if the value was
+    /// not present. See `Tree::set_dup_sort`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Unsupported` if dup-sort mode has
+    /// not been enabled with `Tree::set_dup_sort`.
+    pub fn remove_dup<K, V>(&self, key: K, value: V) -> Result<bool>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let guard = pin();
+        let _cc = self.concurrency_control.read(&guard);
+        self.remove_dup_inner(key.as_ref(), value.as_ref(), &guard)
+    }
+
+    pub(crate) fn remove_dup_inner(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        guard: &Guard,
+    ) -> Result<bool> {
+        let encoded = self.dup_key(key, value)?;
+        Ok(self.remove_inner(encoded, guard)?.is_some())
+    }
+
+    /// Create a double-ended iterator over the values of a
+    /// key in a dup-sort `Tree`, in the lexicographic order
+    /// of their bytes. See `Tree::set_dup_sort`.
+    ///
+    /// If dup-sort mode has not been enabled with
+    /// `Tree::set_dup_sort`, the iterator only returns an
+    /// `Error::Unsupported`.
+    pub fn scan_dups<K>(
+        &self,
+        key: K,
+    ) -> impl DoubleEndedIterator<Item = Result<IVec>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let (error, iter, prefix_len) = match self.dup_key(key.as_ref(), &[]) {
+            Ok(prefix) => (None, Some(self.scan_prefix(&prefix)), prefix.len()),
+            Err(e) => (Some(Err(e)), None, 0),
+        };
+
+        error.into_iter().chain(iter.into_iter().flatten().map(move |kv_res| {
+            kv_res.map(|(k, _)| IVec::from(&k[prefix_len..]))
+        }))
+    }
+
+    // Encodes a key and one of its values as a single key
+    // that sorts by the key first, and then by the value.
+    fn dup_key(&self, key: &[u8], value: &[u8]) -> Result<Vec<u8>> {
+        if !self.dup_sort.load(Acquire) {
+            return Err(Error::Unsupported(
+                "must enable dup-sort mode on this Tree \
+                 by calling Tree::set_dup_sort"
+                    .to_owned(),
+            ));
+        }
+
+        let mut encoded = Vec::with_capacity(key.len() + value.len() + 2);
+        escape_dup_key(key, &mut encoded);
+        encoded.extend_from_slice(&[0, 0]);
+        encoded.extend_from_slice(value);
+
+        Ok(encoded)
+    }
+
+    /// Makes the entries of this `Tree` write-once. While
+    /// enabled, any write that would overwrite, remove or
+    /// merge into a key that already exists returns
+    /// `Error::ReadOnlyKey`, including those made by batches
+    /// and transactions, and nothing is written. This is
+    /// useful for content-addressed data, where overwriting
+    /// an entry always indicates a bug. Keys may still be
+    /// created with `insert` or `compare_and_swap`, which
+    /// returns the usual `CompareAndSwapError` if the key
+    /// exists and the expected value was `None`. Like the
+    /// merge operator, this mode is not persisted, and must
+    /// be enabled again every time the `Tree` is opened.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, Error};
+    ///
+    /// let config = Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// let by_hash = db.open_tree("objects_by_hash")?;
+    /// by_hash.set_write_once(true);
+    ///
+    /// by_hash.insert("3a7bd3e2", "hello")?;
+    ///
+    /// assert_eq!(
+    ///     by_hash.insert("3a7bd3e2", "goodbye"),
+    ///     Err(Error::ReadOnlyKey("3a7bd3e2".into()))
+    /// );
+    /// assert_eq!(
+    ///     by_hash.remove("3a7bd3e2"),
+    ///     Err(Error::ReadOnlyKey("3a7bd3e2".into()))
+    /// );
+    /// assert_eq!(by_hash.get("3a7bd3e2")?, Some("hello".into()));
+    /// # Ok(()) }
+    /// ```
+    pub fn set_write_once(&self, write_once: bool) {
+        self.write_once.store(write_once, Release);
+    }
+
+    /// Declares a secondary index of this `Tree`, whose
+    /// entries are kept in the `index` `Tree`. `extract` is
+    /// called with each value that is written to this `Tree`,
+    /// and returns the index key that the value should be
+    /// found under, if any. Every write to this `Tree` updates
+    /// the index in the same atomic batch, including those
+    /// made by batches, transactions and merges, so a crash
+    /// never leaves the two out of step.
+    ///
+    /// If `index` is empty, it is filled from the current
+    /// contents of this `Tree`. Like the merge operator, an
+    /// index is not persisted, and must be declared again
+    /// every time the `Tree` is opened, before it is written
+    /// to. Writes made while it isn't declared are not
+    /// indexed, and `SecondaryIndex::rebuild` must be called
+    /// to catch up with them.
+    ///
+    /// `index` is switched to dup-sort mode, and should not
+    /// be written to directly. Writes to an indexed `Tree`
+    /// are serialized with each other.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Unsupported` if `index` is this
+    /// `Tree`, or is already one of its indexes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, IVec};
+    ///
+    /// let config = Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// // users are stored by id, with their email as the value
+    /// let users = db.open_tree("users")?;
+    /// let by_email =
+    ///     users.add_index(&db.open_tree("users_by_email")?, |email| {
+    ///         Some(email.to_ascii_lowercase())
+    ///     })?;
+    ///
+    /// users.insert("1", "Alice@example.com")?;
+    /// users.insert("2", "bob@example.com")?;
+    ///
+    /// let found = by_email.get("alice@example.com").next().unwrap()?;
+    /// assert_eq!(found, (IVec::from("1"), IVec::from("Alice@example.com")));
+    ///
+    /// users.remove("1")?;
+    /// assert!(by_email.get("alice@example.com").next().is_none());
+    /// # Ok(()) }
+    /// ```
+    pub fn add_index<F>(
+        &self,
+        index: &Tree,
+        extract: F,
+    ) -> Result<SecondaryIndex>
+    where
+        F: Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync + 'static,
+    {
+        SecondaryIndex::add(self, index, extract)
+    }
+
+    /// Keeps the versions of the values of this `Tree` in the
+    /// `history` `Tree`, so that they can be read as of an
+    /// earlier time with `get_at`. Every write adds a version
+    /// to the history in the same atomic batch, whose
+    /// timestamp is the lsn that the batch starts at, so the
+    /// lsn returned by `Db::checkpoint` after a write is a
+    /// timestamp that sees it. Versions are kept for every timestamp since
+    /// the watermark, which starts when versions were first
+    /// kept and is raised with `set_version_watermark`.
+    ///
+    /// Like the merge operator, this is not persisted, and
+    /// must be enabled again every time the `Tree` is opened,
+    /// before it is written to. `history` should not be
+    /// written to directly.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Unsupported` if `history` is this
+    /// `Tree`, or versions are already being kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, IVec};
+    ///
+    /// let config = Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// let prices = db.open_tree("prices")?;
+    /// prices.keep_versions(&db.open_tree("price_history")?)?;
+    ///
+    /// prices.insert("apple", "1.00")?;
+    /// let monday = db.checkpoint()?;
+    /// prices.insert("apple", "1.25")?;
+    /// let tuesday = db.checkpoint()?;
+    /// prices.remove("apple")?;
+    ///
+    /// assert_eq!(prices.get_at("apple", monday)?, Some(IVec::from("1.00")));
+    /// assert_eq!(prices.get_at("apple", tuesday)?, Some(IVec::from("1.25")));
+    /// assert_eq!(prices.get("apple")?, None);
+    ///
+    /// // versions before the watermark are collected
+    /// prices.set_version_watermark(tuesday)?;
+    /// assert!(prices.get_at("apple", monday).is_err());
+    /// assert_eq!(prices.get_at("apple", tuesday)?, Some(IVec::from("1.25")));
+    /// # Ok(()) }
+    /// ```
+    pub fn keep_versions(&self, history: &Tree) -> Result<()> {
+        if self.context.read_only {
+            return Err(Error::Unsupported(
+                "the database is in read-only mode".to_owned(),
+            ));
+        }
+
+        let mut current = self.indexes.history.write();
+        if current.is_some() || history.tree_id == self.tree_id {
+            return Err(Error::Unsupported(
+                "a Tree can't keep its versions in itself, or \
+                 keep them twice"
+                    .to_owned(),
+            ));
+        }
+
+        *current = Some(History::open(self, history)?);
+        Ok(())
+    }
+
+    /// Retrieve the value that a key had at the given
+    /// timestamp, from the versions kept since
+    /// `Tree::keep_versions` was called.
+    ///
+    /// # Errors
     ///
-    /// let tree = config.open()?;
-    /// tree.set_merge_operator(concatenate_merge);
+    /// Returns `Error::Unsupported` if versions are not
+    /// being kept, or if `ts` is before the watermark.
+    pub fn get_at<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        ts: Lsn,
+    ) -> Result<Option<IVec>> {
+        let kept = self.indexes.history.read();
+        let history = kept.as_ref().ok_or_else(versions::disabled)?;
+        history.get_at(key.as_ref(), ts)
+    }
+
+    /// Returns the timestamp of the latest write to a key,
+    /// including a removal, or `None` if it hasn't been
+    /// written to since the watermark. Comparing this with
+    /// the timestamp that a key was read at detects whether
+    /// it has been written to since.
     ///
-    /// let k = b"k1";
+    /// # Errors
     ///
-    /// tree.insert(k, vec![0]);
-    /// tree.merge(k, vec![1]);
-    /// tree.merge(k, vec![2]);
-    /// assert_eq!(tree.get(k), Ok(Some(IVec::from(vec![0, 1, 2]))));
+    /// Returns `Error::Unsupported` if versions are not
+    /// being kept.
+    pub fn version_of<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Lsn>> {
+        let kept = self.indexes.history.read();
+        let history = kept.as_ref().ok_or_else(versions::disabled)?;
+        history.version_of(key.as_ref())
+    }
+
+    /// Raises the watermark of the versions of this `Tree`,
+    /// collecting those that can no longer be read because
+    /// a later version was written at or before `ts`. Reads
+    /// at timestamps below the watermark fail afterwards. A
+    /// watermark below the current one is ignored.
     ///
-    /// // Replace previously merged data. The merge function will not be called.
-    /// tree.insert(k, vec![3]);
-    /// assert_eq!(tree.get(k), Ok(Some(IVec::from(vec![3]))));
+    /// # Errors
     ///
-    /// // Merges on non-present values will cause the merge function to be called
-    /// // with `old_value == None`. If the merge function returns something (which it
-    /// // does, in this case) a new value will be inserted.
-    /// tree.remove(k);
-    /// tree.merge(k, vec![4]);
-    /// assert_eq!(tree.get(k), Ok(Some(IVec::from(vec![4]))));
-    /// # Ok(()) }
-    /// ```
-    pub fn set_merge_operator(
-        &self,
-        merge_operator: impl MergeOperator + 'static,
-    ) {
-        let mut mo_write = self.merge_operator.write();
-        *mo_write = Some(Box::new(merge_operator));
+    /// Returns `Error::Unsupported` if versions are not
+    /// being kept.
+    pub fn set_version_watermark(&self, ts: Lsn) -> Result<()> {
+        self.indexes.set_version_watermark(ts)
     }
 
     /// Create a double-ended iterator over the tuples of keys and
     /// values in this tree.
     ///
+    /// Items are returned in the lexicographic order of their
+    /// keys' bytes. Trees holding the same items always
+    /// produce the same output, regardless of the order in
+    /// which the items were written, how the tree's nodes
+    /// have been split, merged or consolidated, or whether
+    /// the database was restarted in between, so scans may
+    /// be hashed to compare the contents of trees.
+    ///
+    /// Scans are not snapshots, but writes that happen while
+    /// one is in progress never make it skip or repeat keys.
+    /// See `Iter` for exactly what is guaranteed.
+    ///
     /// # Examples
     ///
     /// ```
@@ -1462,7 +3036,8 @@ impl Tree {
     }
 
     /// Returns the CRC32 of all keys and values
-    /// in this Tree.
+    /// in this Tree, which is equal for trees
+    /// holding the same items.
     ///
     /// This is O(N) and locks the underlying tree
     /// for the duration of the entire scan.
@@ -1478,6 +3053,81 @@ impl Tree {
         Ok(hasher.finalize())
     }
 
+    /// Walks every node of this `Tree`, checking that keys
+    /// are ordered within and across nodes, that the bounds
+    /// of each node agree with its siblings and parent, and
+    /// that every referenced page exists.
+    ///
+    /// This is O(N) and locks the underlying tree for the
+    /// duration of the entire walk. `Db::verify` also checks
+    /// for pages that no tree references.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let db = config.open()?;
+    /// let tree = db.open_tree("verified")?;
+    ///
+    /// for i in 0..100_u32 {
+    ///     tree.insert(i.to_be_bytes(), vec![])?;
+    /// }
+    ///
+    /// let report = tree.verify()?;
+    /// assert!(report.is_ok(), "{:?}", report.violations);
+    /// assert_eq!(report.items, 100);
+    /// # Ok(()) }
+    /// ```
+    pub fn verify(&self) -> Result<VerifyReport> {
+        let _cc = self.concurrency_control.write();
+        let mut report = VerifyReport::default();
+        let _reachable = verify::verify_tree(self, &mut report)?;
+        Ok(report)
+    }
+
+    /// Renders the nodes of this `Tree` one level at a time,
+    /// from the root down to the leaves, with each node's
+    /// page ID, the number of log messages making up its page
+    /// (one more than the length of its delta chain), its
+    /// bounds, its right sibling, and either its separator
+    /// keys and children or its number of items. Keys are
+    /// shown with non-printable bytes escaped. Partial splits
+    /// and merges are shown as they are, rather than being
+    /// completed first, which makes this useful for debugging
+    /// them.
+    ///
+    /// This is O(N) and locks the underlying tree for the
+    /// duration of the entire scan, like `verify`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// db.insert(b"a", b"1")?;
+    ///
+    /// let structure = db.dump_structure()?;
+    /// assert!(structure.contains("items 1"));
+    /// println!("{}", structure);
+    /// # Ok(()) }
+    /// ```
+    pub fn dump_structure(&self) -> Result<String> {
+        let _cc = self.concurrency_control.write();
+        structure::text(self)
+    }
+
+    /// Like `dump_structure`, but renders the nodes as a
+    /// Graphviz DOT graph, with solid edges from index nodes
+    /// to their children, dashed edges to right siblings, and
+    /// the nodes of each level ranked together.
+    pub fn dump_structure_dot(&self) -> Result<String> {
+        let _cc = self.concurrency_control.write();
+        structure::dot(self)
+    }
+
     fn split_node<'g>(
         &self,
         view: &View<'g>,
@@ -1491,7 +3141,11 @@ impl Tree {
         let rhs_lo = rhs.lo.clone();
 
         // install right side
-        let (rhs_pid, rhs_ptr) = self.context.pagecache.allocate(rhs, guard)?;
+        let (rhs_pid, rhs_ptr) = self.context.pagecache.allocate(
+            rhs,
+            self.compress_pages(),
+            guard,
+        )?;
 
         // replace node, pointing next to installed right
         lhs.next = Some(rhs_pid);
@@ -1499,6 +3153,7 @@ impl Tree {
             view.pid,
             view.node_view.0,
             lhs,
+            self.compress_pages(),
             guard,
         )?;
         M.tree_child_split_attempt();
@@ -1532,6 +3187,7 @@ impl Tree {
                 parent_view.pid,
                 parent_view.node_view.0,
                 parent,
+                self.compress_pages(),
                 guard,
             )?;
             if replace.is_ok() {
@@ -1560,8 +3216,11 @@ impl Tree {
 
         let new_root = Node::new_hoisted_root(from, at, to);
 
-        let (new_root_pid, new_root_ptr) =
-            self.context.pagecache.allocate(new_root, guard)?;
+        let (new_root_pid, new_root_ptr) = self.context.pagecache.allocate(
+            new_root,
+            self.compress_pages(),
+            guard,
+        )?;
         debug!("allocated pid {} in root_hoist", new_root_pid);
 
         debug_delay();
@@ -1580,9 +3239,9 @@ impl Tree {
             // 2 threads are at this point, and we don't want
             // to cause roots to diverge between meta and
             // our version.
-            while self.root.compare_and_swap(from, new_root_pid, SeqCst) != from
-            {
-            }
+            while self.root.compare_and_swap(from, new_root_pid, AcqRel)
+                != from
+            {}
 
             Ok(true)
         } else {
@@ -1604,6 +3263,15 @@ impl Tree {
         &self,
         pid: PageId,
         guard: &'g Guard,
+    ) -> Result<Option<View<'g>>> {
+        self.view_for_pid_inner(pid, Repair::All, guard)
+    }
+
+    pub(crate) fn view_for_pid_inner<'g>(
+        &self,
+        pid: PageId,
+        repair: Repair,
+        guard: &'g Guard,
     ) -> Result<Option<View<'g>>> {
         loop {
             let node_view_opt = self.context.pagecache.get(pid, guard)?;
@@ -1611,7 +3279,7 @@ impl Tree {
             if let Some(node_view) = &node_view_opt {
                 let size = node_view.0.log_size();
                 let view = View { node_view: *node_view, pid, size };
-                if view.merging_child.is_some() {
+                if view.merging_child.is_some() && repair != Repair::Nothing {
                     self.merge_node(&view, view.merging_child.unwrap(), guard)?;
                 } else {
                     return Ok(Some(view));
@@ -1622,6 +3290,78 @@ impl Tree {
         }
     }
 
+    // Values that are at least `overflow_threshold` bytes
+    // long are written to their own page before being
+    // linked into a leaf.
+    fn store_value(&self, value: &IVec, guard: &Guard) -> Result<Value> {
+        if value.len() >= self.context.overflow_threshold {
+            let value_pid =
+                self.context.pagecache.allocate_value(value.clone(), guard)?;
+            Ok(Value::Overflow(value_pid))
+        } else {
+            Ok(Value::Inline(value.clone()))
+        }
+    }
+
+    // Records that an operation lost a race and is retrying.
+    pub(crate) fn looped(&self) {
+        M.tree_looped();
+        instrumentation::event(&self.context, InstrumentationEvent::TreeRetry);
+    }
+
+    // Returns `None` if the value lived in an overflow page
+    // that was freed by a concurrent write after its leaf
+    // was read, in which case the leaf should be read again.
+    pub(crate) fn resolve_value(
+        &self,
+        value: &Value,
+        guard: &Guard,
+    ) -> Result<Option<IVec>> {
+        match value {
+            Value::Inline(ivec) => Ok(Some(ivec.clone())),
+            Value::Overflow(value_pid) => Ok(self
+                .context
+                .pagecache
+                .get_value(*value_pid, guard)?
+                .map(|value_view| (*value_view).clone())),
+        }
+    }
+
+    // Releases the overflow page of a value that a write
+    // has just replaced. The write has been applied by then,
+    // so a failure is logged instead of returned, and the
+    // page is left for `Db::verify` to report as orphaned.
+    fn free_replaced_value(&self, value: &Value, guard: &Guard) {
+        if let Err(e) = self.free_value(value, guard) {
+            error!("failed to free the page of a replaced value: {}", e);
+        }
+    }
+
+    // Releases the overflow page of a value that is
+    // no longer referenced by any leaf.
+    fn free_value(&self, value: &Value, guard: &Guard) -> Result<()> {
+        let value_pid = if let Value::Overflow(value_pid) = value {
+            *value_pid
+        } else {
+            return Ok(());
+        };
+
+        while let Some(value_view) =
+            self.context.pagecache.get_value(value_pid, guard)?
+        {
+            if self
+                .context
+                .pagecache
+                .free(value_pid, value_view.0, guard)?
+                .is_ok()
+            {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
     // Returns the traversal path, completing any observed
     // partially complete splits or merges along the way.
     //
@@ -1629,12 +3369,129 @@ impl Tree {
     // high because attempts to split it up have made
     // the inherent complexity of the operation more
     // challenging to understand.
-    #[allow(clippy::cognitive_complexity)]
     pub(crate) fn view_for_key<'g, K>(
         &self,
         key: K,
         guard: &'g Guard,
     ) -> Result<View<'g>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let repair = match self.context.repair_policy {
+            RepairPolicy::Readers | RepairPolicy::Writers => Repair::All,
+            RepairPolicy::Background => Repair::SkipSplits,
+        };
+        self.view_for_key_inner(key, repair, guard)
+    }
+
+    // The traversal used by read-only operations, which
+    // only repairs structure when configured to.
+    pub(crate) fn read_view_for_key<'g, K>(
+        &self,
+        key: K,
+        guard: &'g Guard,
+    ) -> Result<View<'g>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let view = self.view_for_key_inner(key, self.read_repair(), guard)?;
+        self.context.pagecache.record_read(view.node_view.0);
+        Ok(view)
+    }
+
+    // Like `read_view_for_key`, but returns `None` instead
+    // of reading a paged out leaf back from the log if its
+    // filter shows that the key is not in it.
+    pub(crate) fn filtered_read_view_for_key<'g, K>(
+        &self,
+        key: K,
+        guard: &'g Guard,
+    ) -> Result<Option<View<'g>>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let view_opt = self.descend(key, self.read_repair(), true, guard)?;
+        if let Some(view) = &view_opt {
+            self.context.pagecache.record_read(view.node_view.0);
+        }
+        Ok(view_opt)
+    }
+
+    // lets a forward scan that just reached `node` find
+    // the leaves after it already in the cache
+    pub(crate) fn prefetch_after(&self, node: &Node) {
+        let pages = self.context.prefetch_pages;
+        if let (true, Some(next)) = (pages > 0, node.next) {
+            PageCache::prefetch(&self.context.pagecache, next, pages);
+        }
+    }
+
+    pub(crate) fn compress_pages(&self) -> bool {
+        self.context.is_compressed_tree(&self.tree_id)
+    }
+
+    pub(crate) fn read_repair(&self) -> Repair {
+        // a replica's structure only changes along with its
+        // primary's, and a read-only database can't write the
+        // splits and merges, so their readers leave it as it is
+        if self.context.repair_policy == RepairPolicy::Readers
+            && !self.context.pagecache.is_follower()
+            && !self.context.read_only
+        {
+            Repair::All
+        } else {
+            Repair::Nothing
+        }
+    }
+
+    /// Completes any partial splits and merges in this
+    /// tree, one leaf at a time. This is how structure is
+    /// repaired under `RepairPolicy::Background`.
+    pub(crate) fn repair_structure(&self) -> Result<()> {
+        if self.context.pagecache.is_follower() {
+            return Ok(());
+        }
+
+        let mut key = IVec::default();
+
+        loop {
+            let guard = pin();
+            let _ = self.concurrency_control.read(&guard);
+
+            let view = self.view_for_key_inner(&key, Repair::All, &guard)?;
+
+            if view.hi.is_empty() {
+                return Ok(());
+            }
+
+            key = view.hi.clone();
+        }
+    }
+
+    fn view_for_key_inner<'g, K>(
+        &self,
+        key: K,
+        repair: Repair,
+        guard: &'g Guard,
+    ) -> Result<View<'g>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let view = self.descend(key, repair, false, guard)?;
+        Ok(view.expect("only filtered descents stop before the leaf"))
+    }
+
+    // Descends to the leaf responsible for `key`. When
+    // `filtered`, returns `None` as soon as it reaches a
+    // paged out leaf whose filter excludes the key.
+    #[allow(clippy::cognitive_complexity)]
+    fn descend<'g, K>(
+        &self,
+        key: K,
+        repair: Repair,
+        filtered: bool,
+        guard: &'g Guard,
+    ) -> Result<Option<View<'g>>>
     where
         K: AsRef<[u8]>,
     {
@@ -1645,8 +3502,12 @@ impl Tree {
         const MAX_LOOPS: usize = 1_000_000;
 
         let _measure = Measure::new(&M.tree_traverse);
+        let _span = instrumentation::enter(&self.context, Span::TreeDescent);
 
-        let mut cursor = self.root.load(SeqCst);
+        // a new root's page is installed before the Release
+        // half of the hoist publishes its pid, so an Acquire
+        // load is enough to find it
+        let mut cursor = self.root.load(Acquire);
         let mut root_pid = cursor;
         let mut parent_view = None;
         let mut unsplit_parent = None;
@@ -1659,7 +3520,7 @@ impl Tree {
                     line!(),
                     cursor
                 );
-                cursor = self.root.load(SeqCst);
+                cursor = self.root.load(Acquire);
                 root_pid = cursor;
                 parent_view = None;
                 unsplit_parent = None;
@@ -1674,7 +3535,17 @@ impl Tree {
                 return Err(Error::CollectionNotFound(self.tree_id.clone()));
             }
 
-            let node_opt = self.view_for_pid(cursor, guard)?;
+            if filtered
+                && self.context.pagecache.leaf_filter_excludes(
+                    cursor,
+                    key.as_ref(),
+                    guard,
+                )
+            {
+                return Ok(None);
+            }
+
+            let node_opt = self.view_for_pid_inner(cursor, repair, guard)?;
 
             let view = if let Some(view) = node_opt {
                 view
@@ -1682,14 +3553,19 @@ impl Tree {
                 retry!();
             };
 
-            // When we encounter a merge intention, we collaboratively help out
-            if view.merging_child.is_some() {
-                self.merge_node(&view, view.merging_child.unwrap(), guard)?;
-                retry!();
-            } else if view.merging {
-                // we missed the parent merge intention due to a benign race,
-                // so go around again and try to help out if necessary
-                retry!();
+            // When we encounter a merge intention, we collaboratively help out.
+            // Otherwise, a merging node can still be read from, because it
+            // does not accept any new writes.
+            if repair != Repair::Nothing {
+                if view.merging_child.is_some() {
+                    self.merge_node(&view, view.merging_child.unwrap(), guard)?;
+                    retry!();
+                } else if view.merging {
+                    // we missed the parent merge intention due to a benign
+                    // race, so go around again and try to help out if
+                    // necessary
+                    retry!();
+                }
             }
 
             let overshot = key.as_ref() < view.lo.as_ref();
@@ -1701,7 +3577,7 @@ impl Tree {
                 retry!();
             }
 
-            if view.should_split() {
+            if repair != Repair::Nothing && view.should_split(&self.context) {
                 self.split_node(&view, &parent_view, root_pid, guard)?;
                 retry!();
             }
@@ -1712,7 +3588,9 @@ impl Tree {
                     "if our hi bound is not Inf (inity), \
                      we should have a right sibling",
                 );
-                if unsplit_parent.is_none() && parent_view.is_some() {
+                if repair != Repair::All {
+                    // leave the split for somebody else to complete
+                } else if unsplit_parent.is_none() && parent_view.is_some() {
                     unsplit_parent = parent_view.clone();
                 } else if parent_view.is_none() && view.lo.is_empty() {
                     assert!(unsplit_parent.is_none());
@@ -1749,6 +3627,7 @@ impl Tree {
                     unsplit_parent.pid,
                     unsplit_parent.node_view.0,
                     parent,
+                    self.compress_pages(),
                     guard,
                 )?;
                 if replace.is_ok() {
@@ -1763,7 +3642,10 @@ impl Tree {
             // would be merged into a different index, which
             // would add considerable complexity to this already
             // fairly complex implementation.
-            if view.should_merge() && !took_leftmost_branch {
+            if repair != Repair::Nothing
+                && view.should_merge(&self.context)
+                && !took_leftmost_branch
+            {
                 if let Some(ref mut parent) = parent_view {
                     assert!(parent.merging_child.is_none());
                     if parent.can_merge_child() {
@@ -1773,6 +3655,7 @@ impl Tree {
                             parent.pid,
                             parent.node_view.0,
                             frag,
+                            self.compress_pages(),
                             guard,
                         )?;
 
@@ -1792,7 +3675,7 @@ impl Tree {
                 cursor = next.1;
             } else {
                 assert!(!overshot && !undershot);
-                return Ok(view);
+                return Ok(Some(view));
             }
         }
         panic!(
@@ -1829,6 +3712,7 @@ impl Tree {
                 child_pid,
                 child_view.node_view.0,
                 Link::ChildMergeCap,
+                self.compress_pages(),
                 guard,
             )?;
             match install_frag {
@@ -1864,6 +3748,7 @@ impl Tree {
                 parent_view.pid,
                 parent_view.node_view.0,
                 Link::ParentMergeConfirm,
+                self.compress_pages(),
                 guard,
             )?;
             match linked {
@@ -2008,6 +3893,7 @@ impl Tree {
                     cursor_pid,
                     cursor_node.0,
                     replacement,
+                    self.compress_pages(),
                     guard,
                 )?;
                 match replace {
@@ -2125,36 +4011,162 @@ impl Tree {
     ) -> Result<()> {
         let guard = pin();
 
+        // gather every page first, so that they can be
+        // freed with a handful of log messages
+        let mut nodes = vec![];
+        let mut values = vec![];
+
         while let Some(mut pid) = leftmost_chain.pop() {
-            loop {
-                let cursor_view =
-                    if let Some(view) = self.view_for_pid(pid, &guard)? {
-                        view
-                    } else {
-                        trace!("encountered Free node while GC'ing tree");
-                        break;
-                    };
+            while let Some(cursor_view) = self.view_for_pid(pid, &guard)? {
+                if let Some(leaf) = cursor_view.data.leaf_ref() {
+                    for value in &leaf.values {
+                        if let Value::Overflow(value_pid) = *value {
+                            if let Some(value_view) = self
+                                .context
+                                .pagecache
+                                .get_value(value_pid, &guard)?
+                            {
+                                values.push((value_pid, value_view.0));
+                            }
+                        }
+                    }
+                }
 
-                let ret = self.context.pagecache.free(
-                    pid,
-                    cursor_view.node_view.0,
-                    &guard,
-                )?;
+                nodes.push((pid, cursor_view.node_view.0));
 
-                if ret.is_ok() {
-                    let next_pid = if let Some(next_pid) = cursor_view.next {
-                        next_pid
-                    } else {
-                        break;
-                    };
+                if let Some(next_pid) = cursor_view.next {
                     assert_ne!(pid, next_pid);
                     pid = next_pid;
+                } else {
+                    break;
                 }
             }
         }
 
+        // pages that changed while being gathered
+        // are freed individually instead
+        for pid in self.context.pagecache.free_batch(nodes, &guard)? {
+            self.gc_pages_from(pid, &guard)?;
+        }
+
+        for pid in self.context.pagecache.free_batch(values, &guard)? {
+            self.free_value(&Value::Overflow(pid), &guard)?;
+        }
+
         Ok(())
     }
+
+    fn gc_pages_from(&self, mut pid: PageId, guard: &Guard) -> Result<()> {
+        loop {
+            let cursor_view =
+                if let Some(view) = self.view_for_pid(pid, guard)? {
+                    view
+                } else {
+                    trace!("encountered Free node while GC'ing tree");
+                    return Ok(());
+                };
+
+            let ret = self.context.pagecache.free(
+                pid,
+                cursor_view.node_view.0,
+                guard,
+            )?;
+
+            if ret.is_ok() {
+                if let Some(leaf) = cursor_view.data.leaf_ref() {
+                    for value in &leaf.values {
+                        self.free_value(value, guard)?;
+                    }
+                }
+
+                let next_pid = if let Some(next_pid) = cursor_view.next {
+                    next_pid
+                } else {
+                    return Ok(());
+                };
+                assert_ne!(pid, next_pid);
+                pid = next_pid;
+            }
+        }
+    }
+}
+
+/// The length of the prefix that every key between
+/// `lo` and `hi` shares, which is the length of the
+/// prefix that can be omitted from keys stored in a node.
+fn shared_prefix_len(lo: &[u8], hi: &[u8]) -> u16 {
+    let len = lo
+        .iter()
+        .zip(hi.iter())
+        .take_while(|(a, b)| a == b)
+        .take(u16::max_value() as usize)
+        .count();
+
+    u16::try_from(len).unwrap()
+}
+
+/// Appends a key of a dup-sort `Tree` to `encoded`, with its
+/// zero bytes escaped as [0, 255] so that the key can be
+/// terminated by [0, 0], which sorts before any longer key
+/// that it is a prefix of.
+pub(crate) fn escape_dup_key(key: &[u8], encoded: &mut Vec<u8>) {
+    for byte in key {
+        encoded.push(*byte);
+        if *byte == 0 {
+            encoded.push(u8::max_value());
+        }
+    }
+}
+
+impl StaleSnapshot {
+    // Returns the snapshot if it was taken
+    // at most `max_staleness` ago.
+    fn fresh(
+        &self,
+        max_staleness: Duration,
+    ) -> Option<Arc<BTreeMap<IVec, IVec>>> {
+        match &*self.taken.read() {
+            Some((taken_at, items)) if taken_at.elapsed() <= max_staleness => {
+                Some(items.clone())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<'a> PendingValue<'a> {
+    fn store(tree: &'a Tree, value: &IVec, guard: &'a Guard) -> Result<Self> {
+        let peg = if value.len() >= tree.context.overflow_threshold {
+            Some(tree.context.pin_log(guard)?)
+        } else {
+            None
+        };
+        let stored = tree.store_value(value, guard)?;
+
+        Ok(PendingValue { tree, value: stored, peg, linked: false, guard })
+    }
+
+    // Called once the value is linked into its leaf, after
+    // which the write has been applied and can't fail.
+    fn linked(mut self) {
+        self.linked = true;
+        if let Some(peg) = self.peg.take() {
+            if let Err(e) = peg.seal_batch(self.guard) {
+                error!("failed to seal the peg of a linked value: {}", e);
+            }
+        }
+    }
+}
+
+impl Drop for PendingValue<'_> {
+    fn drop(&mut self) {
+        if !self.linked {
+            drop(self.peg.take());
+            if let Err(e) = self.tree.free_value(&self.value, self.guard) {
+                error!("failed to free a value that was never linked: {}", e);
+            }
+        }
+    }
 }
 
 impl Debug for Tree {
@@ -2164,7 +4176,7 @@ impl Debug for Tree {
     ) -> std::result::Result<(), fmt::Error> {
         let guard = pin();
 
-        let mut pid = self.root.load(SeqCst);
+        let mut pid = self.root.load(Acquire);
         let mut left_most = pid;
         let mut level = 0;
 
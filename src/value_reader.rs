@@ -0,0 +1,50 @@
+use std::io::{self, Cursor};
+
+use crate::{pagecache::BlobReader, *};
+
+/// A `std::io::Read` implementation over a single value,
+/// returned by `Tree::get_reader`.
+///
+/// Large values that have been written to their own blob
+/// file and are not currently cached are streamed from disk
+/// instead of being read into memory at once. Their checksum
+/// is verified after the final byte has been read, so a
+/// reader should consume the value until the end before
+/// trusting it. All other values are served from memory.
+#[derive(Debug)]
+pub struct ValueReader(ValueReaderInner);
+
+#[derive(Debug)]
+enum ValueReaderInner {
+    Memory(Cursor<IVec>),
+    Blob(BlobReader),
+}
+
+impl ValueReader {
+    pub(crate) fn from_memory(value: IVec) -> ValueReader {
+        ValueReader(ValueReaderInner::Memory(Cursor::new(value)))
+    }
+
+    pub(crate) fn from_blob(blob_reader: BlobReader) -> ValueReader {
+        ValueReader(ValueReaderInner::Blob(blob_reader))
+    }
+
+    /// Returns the number of bytes that have not been read yet.
+    pub fn remaining(&self) -> u64 {
+        match &self.0 {
+            ValueReaderInner::Memory(cursor) => {
+                cursor.get_ref().len() as u64 - cursor.position()
+            }
+            ValueReaderInner::Blob(blob_reader) => blob_reader.remaining(),
+        }
+    }
+}
+
+impl Read for ValueReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.0 {
+            ValueReaderInner::Memory(cursor) => cursor.read(buf),
+            ValueReaderInner::Blob(blob_reader) => blob_reader.read(buf),
+        }
+    }
+}
@@ -0,0 +1,405 @@
+use crate::{tree::Repair, *};
+
+/// The result of checking the structure of a `Tree` or
+/// `Db` with `Tree::verify` or `Db::verify`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VerifyReport {
+    /// The number of index nodes that were visited.
+    pub index_nodes: usize,
+    /// The number of leaf nodes that were visited.
+    pub leaf_nodes: usize,
+    /// The number of items stored in the visited leaves.
+    pub items: usize,
+    /// The number of values stored in their own page.
+    pub overflow_values: usize,
+    /// The number of nodes that were split without the
+    /// split having been installed in their parent yet.
+    /// These are valid, and will be completed by later
+    /// operations.
+    pub incomplete_splits: usize,
+    /// The number of merges that were started without
+    /// having been completed yet. Like incomplete splits,
+    /// these are valid.
+    pub incomplete_merges: usize,
+    /// Every inconsistency that was found.
+    pub violations: Vec<Violation>,
+}
+
+impl VerifyReport {
+    /// Returns `true` if no violations were found.
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    pub(crate) fn absorb(&mut self, other: VerifyReport) {
+        self.index_nodes += other.index_nodes;
+        self.leaf_nodes += other.leaf_nodes;
+        self.items += other.items;
+        self.overflow_values += other.overflow_values;
+        self.incomplete_splits += other.incomplete_splits;
+        self.incomplete_merges += other.incomplete_merges;
+        self.violations.extend(other.violations);
+    }
+}
+
+/// An inconsistency found by `Tree::verify` or `Db::verify`.
+/// Pages are identified by their page ID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// A page referenced by the tree is free, or does not
+    /// contain a node.
+    MissingPage {
+        /// The missing page.
+        pid: u64,
+    },
+    /// A leaf references a value page that is free, or
+    /// does not contain a value.
+    MissingValue {
+        /// The leaf.
+        pid: u64,
+        /// The missing value page.
+        value_pid: u64,
+    },
+    /// The keys of a node are not in strictly ascending order.
+    UnsortedKeys {
+        /// The node.
+        pid: u64,
+    },
+    /// A node contains a key outside of its bounds.
+    KeyOutOfBounds {
+        /// The node.
+        pid: u64,
+        /// The offending key.
+        key: IVec,
+    },
+    /// An index node is empty, has a different number of
+    /// keys and children, or does not start with its low
+    /// bound.
+    MalformedIndex {
+        /// The index node.
+        pid: u64,
+    },
+    /// Index nodes and leaves were found on the same level.
+    MixedLevel {
+        /// The node that differs from the first node on its level.
+        pid: u64,
+    },
+    /// The first node of a level has a low bound, or the
+    /// last node of a level has a high bound.
+    LevelBoundary {
+        /// The node.
+        pid: u64,
+    },
+    /// The high bound of a node does not match the low
+    /// bound of its right sibling.
+    SiblingBoundaryMismatch {
+        /// The left sibling.
+        left: u64,
+        /// The right sibling.
+        right: u64,
+    },
+    /// The bounds of a node do not lie within the range its
+    /// parent assigned to it.
+    ChildBoundaryMismatch {
+        /// The parent.
+        parent: u64,
+        /// The child.
+        child: u64,
+    },
+    /// A parent points to a node that cannot be reached
+    /// from the leftmost node of its level.
+    UnlinkedChild {
+        /// The parent.
+        parent: u64,
+        /// The child.
+        child: u64,
+    },
+    /// A node was reached more than once.
+    Cycle {
+        /// The node that was reached again.
+        pid: u64,
+    },
+    /// A page is allocated, but not reachable from any tree.
+    /// Only reported by `Db::verify`.
+    OrphanedPage {
+        /// The unreachable page.
+        pid: u64,
+    },
+}
+
+// Walks the tree one level at a time from the root,
+// following sibling links, without repairing any partial
+// splits or merges along the way. Returns the IDs of every
+// node and value page that was reached.
+pub(crate) fn verify_tree(
+    tree: &Tree,
+    report: &mut VerifyReport,
+) -> Result<FastSet8<PageId>> {
+    let guard = pin();
+    let mut reachable = FastSet8::default();
+
+    // the root recorded in the meta page is authoritative,
+    // because other handles to the same tree may not have
+    // observed the latest root hoist yet.
+    let root_pid =
+        tree.context.pagecache.meta_pid_for_name(&tree.tree_id, &guard)?;
+    let mut leftmost = root_pid;
+
+    // the parent and the bounds that the level above
+    // assigned to each node of the current level. The
+    // high bound is `None` when it can't be checked
+    // because the parent is merging one of its children.
+    let mut expected: FastMap8<PageId, (PageId, IVec, Option<IVec>)> =
+        FastMap8::default();
+    let mut merging_children = FastSet8::default();
+
+    loop {
+        let mut pid = leftmost;
+        let mut left: Option<(PageId, IVec)> = None;
+        let mut level_is_index = None;
+        let mut next_leftmost = None;
+        let mut next_expected = FastMap8::default();
+        let mut next_merging_children = FastSet8::default();
+
+        loop {
+            if !reachable.insert(pid) {
+                report.violations.push(Violation::Cycle { pid });
+                break;
+            }
+
+            let view = if let Some(view) =
+                tree.view_for_pid_inner(pid, Repair::Nothing, &guard)?
+            {
+                view
+            } else {
+                report.violations.push(Violation::MissingPage { pid });
+                break;
+            };
+
+            match &left {
+                None if !view.lo.is_empty() => {
+                    report.violations.push(Violation::LevelBoundary { pid })
+                }
+                Some((left_pid, left_hi)) if *left_hi != view.lo => {
+                    report.violations.push(Violation::SiblingBoundaryMismatch {
+                        left: *left_pid,
+                        right: pid,
+                    })
+                }
+                _ => {}
+            }
+
+            match expected.remove(&pid) {
+                Some((parent, lo, hi_opt)) => {
+                    let within_hi = match hi_opt {
+                        None => true,
+                        Some(hi) if hi.is_empty() => true,
+                        Some(hi) => !view.hi.is_empty() && view.hi <= hi,
+                    };
+                    if view.lo != lo || !within_hi {
+                        report.violations.push(
+                            Violation::ChildBoundaryMismatch {
+                                parent,
+                                child: pid,
+                            },
+                        );
+                    }
+                }
+                None if pid != root_pid => report.incomplete_splits += 1,
+                None => {}
+            }
+
+            let is_index = view.data.is_index();
+            match level_is_index {
+                None => level_is_index = Some(is_index),
+                Some(level_kind) if level_kind != is_index => {
+                    report.violations.push(Violation::MixedLevel { pid })
+                }
+                Some(_) => {}
+            }
+
+            verify_keys(pid, &view, report);
+
+            match &view.data {
+                Data::Index(index) => {
+                    report.index_nodes += 1;
+
+                    if index.keys.is_empty()
+                        || index.keys.len() != index.pointers.len()
+                        || view.prefix_decode(&index.keys[0]) != view.lo
+                    {
+                        report
+                            .violations
+                            .push(Violation::MalformedIndex { pid });
+                    }
+
+                    if let Some(merging_child) = view.merging_child {
+                        report.incomplete_merges += 1;
+                        next_merging_children.insert(merging_child);
+                    }
+
+                    if next_leftmost.is_none() {
+                        next_leftmost = index.pointers.first().copied();
+                    }
+
+                    for (i, child) in index.pointers.iter().enumerate() {
+                        let child_lo = index
+                            .keys
+                            .get(i)
+                            .map(|key| view.prefix_decode(key))
+                            .unwrap_or_default();
+                        let child_hi = if view.merging_child.is_some() {
+                            None
+                        } else if let Some(key) = index.keys.get(i + 1) {
+                            Some(view.prefix_decode(key))
+                        } else {
+                            Some(view.hi.clone())
+                        };
+                        next_expected.insert(*child, (pid, child_lo, child_hi));
+                    }
+                }
+                Data::Leaf(leaf) => {
+                    report.leaf_nodes += 1;
+                    report.items += leaf.keys.len();
+
+                    for value in &leaf.values {
+                        if let Value::Overflow(value_pid) = value {
+                            report.overflow_values += 1;
+                            reachable.insert(*value_pid);
+                            if tree
+                                .context
+                                .pagecache
+                                .get_value(*value_pid, &guard)?
+                                .is_none()
+                            {
+                                report.violations.push(
+                                    Violation::MissingValue {
+                                        pid,
+                                        value_pid: *value_pid,
+                                    },
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+
+            left = Some((pid, view.hi.clone()));
+
+            if let Some(next_pid) = view.next {
+                pid = next_pid;
+            } else {
+                if !view.hi.is_empty() {
+                    report.violations.push(Violation::LevelBoundary { pid });
+                }
+                break;
+            }
+        }
+
+        for (child, (parent, _, _)) in expected.drain() {
+            if !merging_children.contains(&child) {
+                report
+                    .violations
+                    .push(Violation::UnlinkedChild { parent, child });
+            }
+        }
+
+        if let Some(next) = next_leftmost {
+            leftmost = next;
+            expected = next_expected;
+            merging_children = next_merging_children;
+        } else {
+            return Ok(reachable);
+        }
+    }
+}
+
+// Keys are stored without the prefix shared by the
+// bounds of their node, which doesn't affect their order.
+fn verify_keys(pid: PageId, node: &Node, report: &mut VerifyReport) {
+    let keys = match &node.data {
+        Data::Index(index) => &index.keys,
+        Data::Leaf(leaf) => &leaf.keys,
+    };
+
+    if keys.windows(2).any(|pair| pair[0] >= pair[1]) {
+        report.violations.push(Violation::UnsortedKeys { pid });
+    }
+
+    for encoded_key in keys {
+        let key = node.prefix_decode(encoded_key);
+        if key < node.lo || (!node.hi.is_empty() && key >= node.hi) {
+            report.violations.push(Violation::KeyOutOfBounds { pid, key });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn detects_corruption() {
+        let db =
+            Config::new().temporary(true).flush_every_ms(None).open().unwrap();
+
+        for i in 0..10_u8 {
+            db.insert([i], vec![]).unwrap();
+        }
+        let intact = db.verify().unwrap();
+        assert!(intact.is_ok(), "{:?}", intact.violations);
+
+        let guard = pin();
+
+        let (orphan_pid, _) = db
+            .context
+            .pagecache
+            .allocate(Node::default(), false, &guard)
+            .unwrap();
+
+        // add a key at the high bound of the leftmost leaf,
+        // retrying if the page moved since it was read
+        let leaf_view = loop {
+            let leaf_view = db.view_for_key(b"", &guard).unwrap();
+            assert!(!leaf_view.hi.is_empty());
+            let mut corrupted = (*leaf_view).clone();
+            if let Data::Leaf(leaf) = &mut corrupted.data {
+                leaf.keys.push(leaf_view.hi.clone());
+                leaf.values.push(Value::Inline(IVec::default()));
+            }
+            if db
+                .context
+                .pagecache
+                .replace(
+                    leaf_view.pid,
+                    leaf_view.node_view.0,
+                    corrupted,
+                    false,
+                    &guard,
+                )
+                .unwrap()
+                .is_ok()
+            {
+                break leaf_view;
+            }
+        };
+
+        let report = db.verify().unwrap();
+        assert!(!report.is_ok());
+        assert!(
+            report
+                .violations
+                .contains(&Violation::OrphanedPage { pid: orphan_pid }),
+            "{:?}",
+            report.violations
+        );
+        assert!(
+            report.violations.contains(&Violation::KeyOutOfBounds {
+                pid: leaf_view.pid,
+                key: leaf_view.hi.clone(),
+            }),
+            "{:?}",
+            report.violations
+        );
+    }
+}
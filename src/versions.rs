@@ -0,0 +1,169 @@
+use std::convert::TryFrom;
+
+use super::*;
+
+// the watermark is stored under the empty key, which no
+// version key can be, because they all contain [0, 0]
+const WATERMARK_KEY: &[u8] = &[];
+
+/// The version history of a `Tree`, enabled with
+/// `Tree::keep_versions`. Each write to the `Tree` adds a
+/// version of the key that it wrote to the history `Tree`,
+/// under the key's dup-sort encoding followed by the
+/// big-endian lsn of the batch that wrote it, so that the
+/// versions of a key are sorted by time. Versions of a value start
+/// with 1, and versions that record a removal are [0].
+pub(crate) struct History {
+    tree: Tree,
+    watermark: AtomicLsn,
+}
+
+fn version_prefix(key: &[u8]) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(key.len() + 10);
+    escape_dup_key(key, &mut prefix);
+    prefix.extend_from_slice(&[0, 0]);
+    prefix
+}
+
+fn version_key(key: &[u8], ts: Lsn) -> Vec<u8> {
+    let mut version_key = version_prefix(key);
+    version_key.extend_from_slice(&ts.to_be_bytes());
+    version_key
+}
+
+fn version_ts(version_key: &[u8]) -> Lsn {
+    let ts = &version_key[version_key.len() - 8..];
+    Lsn::from_be_bytes(<[u8; 8]>::try_from(ts).unwrap())
+}
+
+fn decode_version(version: &[u8]) -> Option<IVec> {
+    if version[0] == 0 { None } else { Some(IVec::from(&version[1..])) }
+}
+
+pub(crate) fn disabled() -> Error {
+    Error::Unsupported(
+        "must keep versions of this Tree by calling \
+         Tree::keep_versions"
+            .to_owned(),
+    )
+}
+
+impl History {
+    pub(crate) fn open(primary: &Tree, tree: &Tree) -> Result<History> {
+        if let Some(stored) = tree.get(WATERMARK_KEY)? {
+            // kept since an earlier run
+            let watermark =
+                Lsn::from_be_bytes(<[u8; 8]>::try_from(&*stored).unwrap());
+            return Ok(History {
+                tree: tree.clone(),
+                watermark: AtomicLsn::new(watermark),
+            });
+        }
+
+        // the current values become the first versions, and
+        // the history starts at the time that they were taken
+        let guard = pin();
+        let peg = primary.context.pin_log(&guard)?;
+        let ts = peg.lsn();
+        for kv_res in primary {
+            let (key, value) = kv_res?;
+            let mut version = vec![1];
+            version.extend_from_slice(&value);
+            let _ = tree.insert(version_key(&key, ts), version)?;
+        }
+        let _ = tree.insert(WATERMARK_KEY, &ts.to_be_bytes())?;
+        peg.seal_batch(&guard)?;
+
+        Ok(History { tree: tree.clone(), watermark: AtomicLsn::new(ts) })
+    }
+
+    /// Adds a version of `key` written by the batch at `ts`,
+    /// and collects the versions of it below the watermark.
+    pub(crate) fn record(
+        &self,
+        ts: Lsn,
+        key: &[u8],
+        new: Option<&[u8]>,
+        guard: &Guard,
+    ) -> Result<()> {
+        let version = if let Some(value) = new {
+            let mut version = Vec::with_capacity(value.len() + 1);
+            version.push(1);
+            version.extend_from_slice(value);
+            version
+        } else {
+            vec![0]
+        };
+        let _ = self.tree.insert_inner(version_key(key, ts), version, guard)?;
+        self.collect_prefix(&version_prefix(key), guard)
+    }
+
+    pub(crate) fn get_at(&self, key: &[u8], ts: Lsn) -> Result<Option<IVec>> {
+        let watermark = self.watermark.load(Acquire);
+        if ts < watermark {
+            return Err(Error::Unsupported(format!(
+                "versions before the watermark {} have been collected, \
+                 so the value at {} is unknown",
+                watermark, ts
+            )));
+        }
+
+        let lo = version_key(key, 0);
+        let hi = version_key(key, ts);
+        match self.tree.range(lo..=hi).next_back() {
+            Some(Ok((_, version))) => Ok(decode_version(&version)),
+            Some(Err(e)) => Err(e),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn version_of(&self, key: &[u8]) -> Result<Option<Lsn>> {
+        let latest = self.tree.scan_prefix(version_prefix(key)).next_back();
+        Ok(latest.transpose()?.map(|(version_key, _)| version_ts(&version_key)))
+    }
+
+    /// Raises the watermark and collects the versions that
+    /// fall below it, for every key.
+    pub(crate) fn set_watermark(&self, ts: Lsn) -> Result<()> {
+        if ts <= self.watermark.load(Acquire) {
+            return Ok(());
+        }
+        let _ = self.tree.insert(WATERMARK_KEY, &ts.to_be_bytes())?;
+        self.watermark.store(ts, Release);
+
+        let guard = pin();
+        let mut last_prefix: Option<Vec<u8>> = None;
+        for key_res in self.tree.range(version_key(&[], 0)..).keys() {
+            let version_key = key_res?;
+            let prefix = &version_key[..version_key.len() - 8];
+            if last_prefix.as_ref().map(AsRef::as_ref) != Some(prefix) {
+                self.collect_prefix(prefix, &guard)?;
+                last_prefix = Some(prefix.to_vec());
+            }
+        }
+        Ok(())
+    }
+
+    // Removes the versions of a key that can't be read at or
+    // after the watermark, which are all of those up to it
+    // except the last, and that last one too if it records a
+    // removal.
+    fn collect_prefix(&self, prefix: &[u8], guard: &Guard) -> Result<()> {
+        let mut hi = prefix.to_vec();
+        hi.extend_from_slice(&self.watermark.load(Acquire).to_be_bytes());
+
+        let mut below = self.tree.range(prefix..=&*hi).rev();
+        match below.next() {
+            Some(Ok((_, version))) if version[0] != 0 => {}
+            Some(Ok((version_key, _))) => {
+                let _ = self.tree.remove_inner(version_key, guard)?;
+            }
+            Some(Err(e)) => return Err(e),
+            None => return Ok(()),
+        }
+        for kv_res in below {
+            let _ = self.tree.remove_inner(kv_res?.0, guard)?;
+        }
+        Ok(())
+    }
+}
@@ -44,3 +44,38 @@ pub fn cleanup(dir: &str) {
         std::fs::remove_dir_all(dir).unwrap();
     }
 }
+
+/// A directory under the system's temporary directory for a
+/// test that opens a database by path, which is removed when
+/// this is dropped, so that it's cleaned up even if the test
+/// fails and never left in the working directory.
+#[allow(dead_code)]
+pub struct TestDir(std::path::PathBuf);
+
+#[allow(dead_code)]
+impl TestDir {
+    /// Starts from an empty directory named after the test,
+    /// and the process, so that test binaries running at
+    /// the same time don't share it.
+    pub fn new(name: &str) -> TestDir {
+        let dir_name = format!("sled_{}_{}", name, std::process::id());
+        let path = std::env::temp_dir().join(dir_name);
+        let _ = std::fs::remove_dir_all(&path);
+        TestDir(path)
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.0
+    }
+
+    /// The path of a file in the directory.
+    pub fn file(&self, name: &str) -> std::path::PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,130 @@
+// Allocation regression thresholds for the hot paths.
+// Run with `cargo test --features measure_allocs --test test_allocations`.
+// The features enabled by `testing` add allocations
+// of their own, so the thresholds only apply without them.
+#![cfg(all(
+    feature = "measure_allocs",
+    not(any(feature = "event_log", feature = "lock_free_delays"))
+))]
+
+use sled::{Config, Db};
+
+const N: usize = 1000;
+
+// average allocations per call, measured at 0.02 for gets,
+// 4.0 for inserts, 6.4 for removes, and 1.2 and 1.4 per
+// item of forward and reverse scans
+const MAX_ALLOCATIONS_PER_GET: f64 = 0.1;
+const MAX_ALLOCATIONS_PER_INSERT: f64 = 5.;
+const MAX_ALLOCATIONS_PER_REMOVE: f64 = 7.5;
+const MAX_ALLOCATIONS_PER_SCANNED_ITEM: f64 = 1.5;
+const MAX_ALLOCATIONS_PER_REVERSE_SCANNED_ITEM: f64 = 1.75;
+
+fn key(i: usize) -> [u8; 8] {
+    (i as u64).to_be_bytes()
+}
+
+fn db() -> Db {
+    // fix the node sizes, which determine how often
+    // nodes are consolidated or split
+    let db = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .max_leaf_items(16)
+        .min_leaf_items(4)
+        .max_index_items(256)
+        .min_index_items(64)
+        .open()
+        .unwrap();
+
+    for i in 0..N {
+        db.insert(key(i), &[1; 16]).unwrap();
+    }
+    for i in 0..N {
+        db.get(key(i)).unwrap();
+    }
+
+    db
+}
+
+// Returns the average number of allocations made by
+// the current thread per call of `f`.
+fn allocations_per_call<F: FnMut(usize)>(calls: usize, mut f: F) -> f64 {
+    let (before, _) = sled::thread_allocations();
+    for i in 0..calls {
+        f(i);
+    }
+    let (after, _) = sled::thread_allocations();
+    (after - before) as f64 / calls as f64
+}
+
+#[test]
+fn get_allocations() {
+    let db = db();
+
+    let allocations = allocations_per_call(N, |i| {
+        assert!(db.get(key(i)).unwrap().is_some());
+    });
+    assert!(
+        allocations <= MAX_ALLOCATIONS_PER_GET,
+        "get made {} allocations per call",
+        allocations
+    );
+
+    let allocations = allocations_per_call(N, |i| {
+        assert!(db.get(key(N + i)).unwrap().is_none());
+    });
+    assert!(
+        allocations <= MAX_ALLOCATIONS_PER_GET,
+        "get of a missing key made {} allocations per call",
+        allocations
+    );
+}
+
+#[test]
+fn insert_allocations() {
+    let db = db();
+
+    let allocations = allocations_per_call(N, |i| {
+        db.insert(key(i), &[2; 16]).unwrap();
+    });
+    assert!(
+        allocations <= MAX_ALLOCATIONS_PER_INSERT,
+        "insert made {} allocations per call",
+        allocations
+    );
+
+    let allocations = allocations_per_call(N, |i| {
+        db.remove(key(i)).unwrap();
+    });
+    assert!(
+        allocations <= MAX_ALLOCATIONS_PER_REMOVE,
+        "remove made {} allocations per call",
+        allocations
+    );
+}
+
+#[test]
+fn scan_allocations() {
+    let db = db();
+
+    let allocations = allocations_per_call(1, |_| {
+        assert_eq!(db.iter().map(Result::unwrap).count(), N);
+    });
+    let per_item = allocations / N as f64;
+    assert!(
+        per_item <= MAX_ALLOCATIONS_PER_SCANNED_ITEM,
+        "scan made {} allocations per item",
+        per_item
+    );
+
+    let allocations = allocations_per_call(1, |_| {
+        assert_eq!(db.iter().rev().map(Result::unwrap).count(), N);
+    });
+    let per_item = allocations / N as f64;
+    assert!(
+        per_item <= MAX_ALLOCATIONS_PER_REVERSE_SCANNED_ITEM,
+        "reverse scan made {} allocations per item",
+        per_item
+    );
+}
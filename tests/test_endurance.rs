@@ -0,0 +1,202 @@
+//! A long-running endurance test that repeatedly runs a mixed
+//! workload against a database, restarting it periodically, while
+//! tracking resident memory, open file descriptors, and the size
+//! of the database on disk. It fails if any of these keep growing
+//! across restarts, which points to slow leaks in epoch-based
+//! reclamation, segment accounting, or file handling.
+//!
+//! This is only built with the `endurance` feature, and is
+//! intended to be run on its own in release mode:
+//!
+//! ```text
+//! cargo test --release --features=endurance --test test_endurance -- --nocapture
+//! ```
+//!
+//! The following environment variables tune the run:
+//!
+//! * `SLED_ENDURANCE_SECS`: total run time, 24 hours by default
+//! * `SLED_ENDURANCE_RESTART_SECS`: time between restarts, 60
+//!   seconds by default
+//! * `SLED_ENDURANCE_WINDOW`: the number of consecutive restarts
+//!   over which a metric may not grow, 10 by default
+#![cfg(all(feature = "endurance", target_os = "linux"))]
+
+use std::{
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering::SeqCst},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use rand::{Rng, thread_rng};
+
+const DB_DIR: &str = "endurance_db";
+const THREADS: usize = 4;
+const KEY_SPACE: u64 = 100_000;
+const MAX_VALUE_LEN: usize = 4096;
+
+// restarts before this many are skipped while
+// caches and the keyspace fill up
+const WARM_UP_RESTARTS: usize = 3;
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    rss_bytes: u64,
+    open_fds: u64,
+    disk_bytes: u64,
+}
+
+fn env_or(name: &str, default: u64) -> u64 {
+    std::env::var(name).ok().map_or(default, |raw| raw.parse().expect(name))
+}
+
+fn rss_bytes() -> u64 {
+    let statm = std::fs::read_to_string("/proc/self/statm").unwrap();
+    let resident_pages: u64 =
+        statm.split_whitespace().nth(1).unwrap().parse().unwrap();
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+    resident_pages * page_size
+}
+
+fn open_fds() -> u64 {
+    std::fs::read_dir("/proc/self/fd").unwrap().count() as u64
+}
+
+fn disk_bytes(dir: &Path) -> u64 {
+    let mut total = 0;
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let entry = entry.unwrap();
+        let metadata = entry.metadata().unwrap();
+        if metadata.is_dir() {
+            total += disk_bytes(&entry.path());
+        } else {
+            total += metadata.len();
+        }
+    }
+    total
+}
+
+fn run_workload(db: &sled::Db, duration: Duration) {
+    let shutdown = Arc::new(AtomicBool::new(false));
+
+    let workers: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let db = db.clone();
+            let shutdown = shutdown.clone();
+            thread::spawn(move || {
+                let mut rng = thread_rng();
+                while !shutdown.load(SeqCst) {
+                    let key = rng.gen_range(0, KEY_SPACE).to_be_bytes();
+                    let value = vec![0xAB; rng.gen_range(0, MAX_VALUE_LEN)];
+
+                    match rng.gen_range(0, 100) {
+                        0..=39 => {
+                            db.get(key).unwrap();
+                        }
+                        40..=69 => {
+                            db.insert(key, value).unwrap();
+                        }
+                        70..=84 => {
+                            db.remove(key).unwrap();
+                        }
+                        85..=94 => {
+                            let old = db.get(key).unwrap();
+                            let _ = db
+                                .compare_and_swap(key, old, Some(value))
+                                .unwrap();
+                        }
+                        _ => {
+                            let _ = db.range(key..).take(16).count();
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+
+    thread::sleep(duration);
+    shutdown.store(true, SeqCst);
+
+    for worker in workers {
+        worker.join().unwrap();
+    }
+}
+
+// Fails if the last `window` samples of a metric were
+// each larger than the one before.
+fn check_growth(
+    samples: &[Sample],
+    window: usize,
+    name: &str,
+    metric: fn(&Sample) -> u64,
+) {
+    if samples.len() < window + 1 {
+        return;
+    }
+
+    let recent: Vec<u64> =
+        samples[samples.len() - window - 1..].iter().map(metric).collect();
+
+    let grew_every_time = recent.windows(2).all(|pair| pair[1] > pair[0]);
+
+    assert!(
+        !grew_every_time,
+        "{} grew across each of the last {} restarts: {:?}",
+        name, window, recent
+    );
+}
+
+#[test]
+fn endurance() {
+    let total =
+        Duration::from_secs(env_or("SLED_ENDURANCE_SECS", 24 * 60 * 60));
+    let restart_every =
+        Duration::from_secs(env_or("SLED_ENDURANCE_RESTART_SECS", 60));
+    let window = env_or("SLED_ENDURANCE_WINDOW", 10) as usize;
+
+    let _ = std::fs::remove_dir_all(DB_DIR);
+
+    let config = sled::Config::new().path(DB_DIR).segment_size(64 * 1024);
+
+    let start = Instant::now();
+    let mut samples = vec![];
+    let mut restarts = 0;
+
+    while start.elapsed() < total {
+        let db = config.open().unwrap();
+        run_workload(&db, restart_every);
+        db.flush().unwrap();
+        drop(db);
+
+        // measure with the database closed, so that
+        // everything it used should have been released
+        let sample = Sample {
+            rss_bytes: rss_bytes(),
+            open_fds: open_fds(),
+            disk_bytes: disk_bytes(Path::new(DB_DIR)),
+        };
+
+        restarts += 1;
+        println!(
+            "restart {} after {:?}: {:?}",
+            restarts,
+            start.elapsed(),
+            sample
+        );
+
+        if restarts <= WARM_UP_RESTARTS {
+            continue;
+        }
+
+        samples.push(sample);
+
+        check_growth(&samples, window, "resident memory", |s| s.rss_bytes);
+        check_growth(&samples, window, "open file descriptors", |s| s.open_fds);
+        check_growth(&samples, window, "disk usage", |s| s.disk_bytes);
+    }
+
+    std::fs::remove_dir_all(DB_DIR).unwrap();
+}
@@ -110,6 +110,39 @@ fn non_contiguous_log_flush() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn read_at_lsn() -> Result<()> {
+    common::setup_logger();
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let db = config.open()?;
+    let log = &db.context.pagecache.log;
+
+    let guard = pin();
+    let mut written = vec![];
+    for i in 0_u8..10 {
+        let data_bytes = IVec::from(vec![i; 64]);
+        let (lsn, _ptr) =
+            log.reserve(REPLACE, PID, &data_bytes, &guard)?.complete()?;
+        written.push((lsn, data_bytes));
+    }
+
+    let (last_lsn, _) = written[written.len() - 1];
+    assert!(log.stable_offset() < last_lsn);
+
+    for (lsn, data_bytes) in &written {
+        let read = log.read_lsn(*lsn)?.expect("message should be readable");
+        assert_eq!(read.into_data().unwrap(), *data_bytes.serialize());
+    }
+
+    // reading made every write stable
+    assert!(log.stable_offset() >= last_lsn);
+
+    // no message begins in the middle of another one
+    assert!(log.read_lsn(written[0].0 + 1)?.is_none());
+
+    Ok(())
+}
+
 #[test]
 fn concurrent_logging() -> Result<()> {
     common::setup_logger();
@@ -265,6 +298,47 @@ fn abort(log: &Log) {
     }
 }
 
+#[test]
+fn recovery_skips_aborted_reservations() -> Result<()> {
+    common::setup_logger();
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let db = config.open()?;
+    let log = &db.context.pagecache.log;
+
+    // a pid that no tree uses, so that only
+    // our own messages are matched below
+    let pid: PageId = 1 << 40;
+
+    let guard = pin();
+    let mut completed = vec![];
+    for i in 0_u8..6 {
+        let res =
+            log.reserve(REPLACE, pid, &IVec::from(vec![i; 32]), &guard)?;
+        if i % 2 == 0 {
+            completed.push(res.complete()?.0);
+        } else if i == 3 {
+            // dropping a reservation aborts it
+            drop(res);
+        } else {
+            let _ = res.abort()?;
+        }
+    }
+    let last_lsn =
+        log.reserve(REPLACE, pid, &IVec::from(b"end"), &guard)?.complete()?.0;
+    completed.push(last_lsn);
+    log.make_stable(last_lsn)?;
+
+    let recovered: Vec<Lsn> = log
+        .iter_from(0)
+        .filter(|&(_, item_pid, ..)| item_pid == pid)
+        .map(|(_, _, lsn, ..)| lsn)
+        .collect();
+
+    assert_eq!(recovered, completed);
+
+    Ok(())
+}
+
 #[test]
 fn log_aborts() {
     let config = Config::new().temporary(true);
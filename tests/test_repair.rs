@@ -0,0 +1,82 @@
+mod common;
+
+use std::sync::Arc;
+use std::thread;
+
+use sled::*;
+
+const N_THREADS: usize = 10;
+const N_PER_THREAD: usize = 100;
+const N: usize = N_THREADS * N_PER_THREAD;
+
+fn kv(i: usize) -> Vec<u8> {
+    let k = [(i >> 16) as u8, (i >> 8) as u8, i as u8];
+    k.to_vec()
+}
+
+#[test]
+fn repair_policies() {
+    common::setup_logger();
+
+    for policy in &[
+        RepairPolicy::Readers,
+        RepairPolicy::Writers,
+        RepairPolicy::Background,
+    ] {
+        let config = Config::new()
+            .temporary(true)
+            .flush_every_ms(None)
+            .repair_policy(*policy)
+            .repair_every_ms(1);
+
+        let t = Arc::new(config.open().unwrap());
+
+        let writers: Vec<_> = (0..N_THREADS)
+            .map(|tn| {
+                let t = t.clone();
+                thread::spawn(move || {
+                    for i in (tn * N_PER_THREAD)..((tn + 1) * N_PER_THREAD) {
+                        let k = kv(i);
+                        t.insert(&k, k.clone()).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        let readers: Vec<_> = (0..N_THREADS)
+            .map(|_| {
+                let t = t.clone();
+                thread::spawn(move || {
+                    for i in 0..N {
+                        let k = kv(i);
+                        if let Some(v) = t.get(&k).unwrap() {
+                            assert_eq!(v, k);
+                        }
+                    }
+                    let mut last = None;
+                    for res in t.iter() {
+                        let (k, v) = res.unwrap();
+                        assert_eq!(k, v);
+                        assert!(last < Some(k.clone()));
+                        last = Some(k);
+                    }
+                })
+            })
+            .collect();
+
+        for handle in writers.into_iter().chain(readers) {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(t.len(), N);
+        for i in 0..N {
+            let k = kv(i);
+            assert_eq!(t.get(&k).unwrap().unwrap(), k);
+            if i % 2 == 0 {
+                t.remove(&k).unwrap();
+            }
+        }
+        assert_eq!(t.iter().count(), N / 2);
+        assert_eq!(t.iter().rev().count(), N / 2);
+    }
+}
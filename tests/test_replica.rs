@@ -0,0 +1,146 @@
+mod common;
+
+use std::thread;
+
+use sled::*;
+
+const N: usize = 1000;
+
+fn kv(i: usize) -> Vec<u8> {
+    let k = [(i >> 16) as u8, (i >> 8) as u8, i as u8];
+    k.to_vec()
+}
+
+#[test]
+fn ship_log_streams_stable_entries() {
+    common::setup_logger();
+
+    let db = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .segment_size(1024 * 1024)
+        .open()
+        .unwrap();
+
+    let from = db.checkpoint().unwrap();
+    let shipper = db.ship_log(from + 1);
+    let consumer = thread::spawn(move || {
+        shipper.collect::<Result<Vec<LogEntry>>>().unwrap()
+    });
+
+    for i in 0..N {
+        db.insert(kv(i), kv(i)).unwrap();
+        if i % 50 == 0 {
+            db.flush().unwrap();
+        }
+    }
+    let to = db.checkpoint().unwrap();
+
+    let expected: Vec<LogEntry> =
+        db.log_entries(from + 1..=to).collect::<Result<_>>().unwrap();
+    assert!(!expected.is_empty());
+
+    // resuming from the middle picks up where that left off
+    let mid = expected.len() / 2;
+    let resumed: Vec<LogEntry> = db
+        .ship_log(expected[mid].lsn)
+        .take(expected.len() - mid)
+        .collect::<Result<_>>()
+        .unwrap();
+    assert_eq!(resumed, &expected[mid..]);
+
+    // shipping ends once the database is dropped
+    drop(db);
+    let shipped = consumer.join().unwrap();
+    assert_eq!(&shipped[..expected.len()], &expected[..]);
+}
+
+#[test]
+fn replica_applies_shipped_entries() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("replica_applies_shipped_entries");
+    let path = dir.path();
+
+    let primary = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .segment_size(1024 * 1024)
+        .open()
+        .unwrap();
+    let replica_config =
+        Config::new().path(path).flush_every_ms(None).replica(true);
+    let mut replica = replica_config.open().unwrap();
+
+    let sync = |replica: &Db| {
+        let from = replica.replication_lsn().unwrap();
+        let to = primary.checkpoint().unwrap();
+        let entries: Vec<LogEntry> =
+            primary.log_entries(from..=to).collect::<Result<_>>().unwrap();
+        replica.apply_log_entries(entries).unwrap();
+    };
+    let assert_same = |replica: &Db| {
+        assert_eq!(replica.tree_names(), primary.tree_names());
+        for name in primary.tree_names() {
+            let expected: Vec<_> = primary
+                .open_tree(&name)
+                .unwrap()
+                .iter()
+                .collect::<Result<_>>()
+                .unwrap();
+            let actual: Vec<_> = replica
+                .open_tree(&name)
+                .unwrap()
+                .iter()
+                .collect::<Result<_>>()
+                .unwrap();
+            assert_eq!(actual, expected);
+        }
+    };
+
+    let other = primary.open_tree(b"other").unwrap();
+    for i in 0..N {
+        primary.insert(kv(i), kv(i)).unwrap();
+        other.insert(kv(i), vec![]).unwrap();
+    }
+    sync(&replica);
+    assert_same(&replica);
+
+    match replica.insert(b"k", b"v") {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected a replica to reject writes, got {:?}", other),
+    }
+
+    // applying the same entries again changes nothing
+    let lsn = replica.replication_lsn();
+    let entries: Vec<LogEntry> =
+        primary.log_entries(..).collect::<Result<_>>().unwrap();
+    replica.apply_log_entries(entries).unwrap();
+    assert_eq!(replica.replication_lsn(), lsn);
+    assert_same(&replica);
+
+    drop(replica);
+    replica = replica_config.open().unwrap();
+    assert_eq!(replica.replication_lsn(), lsn);
+    assert!(replica.insert(b"k", b"v").is_err());
+    assert_same(&replica);
+
+    for i in 0..N / 2 {
+        primary.remove(kv(i)).unwrap();
+    }
+    primary.drop_tree(b"other").unwrap();
+    sync(&replica);
+    assert_same(&replica);
+
+    replica.promote().unwrap();
+    assert_eq!(replica.replication_lsn(), None);
+    replica.insert(b"k", b"v").unwrap();
+
+    drop(replica);
+    replica = Config::new().path(path).open().unwrap();
+    assert_eq!(replica.replication_lsn(), None);
+    assert_eq!(replica.get(b"k").unwrap(), Some(IVec::from(b"v")));
+    replica.insert(b"k2", b"v").unwrap();
+
+    drop(replica);
+}
@@ -0,0 +1,138 @@
+mod common;
+
+use std::thread;
+
+use sled::*;
+
+#[test]
+fn scrub() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("scrub");
+    let path = dir.path();
+
+    let db = Config::new()
+        .path(path)
+        .segment_size(4096)
+        .flush_every_ms(None)
+        .open()
+        .unwrap();
+    for i in 0..500_u32 {
+        db.insert(i.to_be_bytes(), vec![7; 64]).unwrap();
+    }
+
+    // the writes that are still buffered are skipped
+    // rather than synced
+    let unstable = db.scrub().unwrap();
+    db.flush().unwrap();
+
+    let clean = db.scrub().unwrap();
+    assert!(clean.is_ok(), "{:?}", clean);
+    assert!(clean.pages > 0 && clean.bytes > 0, "{:?}", clean);
+    assert!(unstable.bytes < clean.bytes, "{:?} {:?}", unstable, clean);
+
+    assert!(rot_sealed_segments(&db, dir.file("db")) > 0);
+
+    // every page is resident, so all of them are repaired
+    let report = db.scrub().unwrap();
+    assert!(!report.is_ok());
+    assert!(report.corrupt.iter().all(|c| c.repaired), "{:?}", report);
+    assert_eq!(db.last_scrub(), Some(report));
+
+    for i in 0..500_u32 {
+        assert_eq!(db.get(i.to_be_bytes()).unwrap().unwrap(), vec![7; 64]);
+    }
+    assert!(db.scrub().unwrap().is_ok());
+
+    drop(db);
+
+    // scrubs from the background thread
+    let db =
+        Config::new().temporary(true).scrub_every_ms(Some(10)).open().unwrap();
+    db.insert(b"a", b"1").unwrap();
+    let mut waited = 0;
+    while db.last_scrub().is_none() {
+        assert!(waited < 500, "no background scrub after 5 seconds");
+        thread::sleep(std::time::Duration::from_millis(10));
+        waited += 1;
+    }
+    assert!(db.last_scrub().unwrap().is_ok());
+    drop(db);
+
+    assert!(
+        Config::new().temporary(true).scrub_every_ms(Some(0)).open().is_err()
+    );
+}
+
+#[test]
+fn scrub_reports_pages_that_are_not_resident() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("scrub_not_resident");
+    let config = Config::new()
+        .path(dir.path())
+        .segment_size(4096)
+        .cache_capacity(256)
+        .flush_every_ms(None);
+
+    let db = config.open().unwrap();
+    for i in 0..500_u32 {
+        db.insert(i.to_be_bytes(), vec![7; 64]).unwrap();
+    }
+    db.flush().unwrap();
+    drop(db);
+
+    // the cache only fits a leaf or two, so reading every
+    // key pages most of them out again
+    let db = config.open().unwrap();
+    for i in 0..500_u32 {
+        assert_eq!(db.get(i.to_be_bytes()).unwrap().unwrap(), vec![7; 64]);
+    }
+    assert!(rot_sealed_segments(&db, dir.file("db")) > 0);
+
+    let report = db.scrub().unwrap();
+    let lost: Vec<_> = report.corrupt.iter().filter(|c| !c.repaired).collect();
+    assert!(!lost.is_empty(), "{:?}", report);
+
+    // the corrupt copy is all there is of those pages
+    let failures: Vec<Error> =
+        (0..500_u32).filter_map(|i| db.get(i.to_be_bytes()).err()).collect();
+    assert!(!failures.is_empty());
+    for failure in failures {
+        match failure {
+            Error::Corruption { .. } => {}
+            other => panic!("expected Error::Corruption, got {:?}", other),
+        }
+    }
+    assert!(!db.scrub().unwrap().is_ok());
+}
+
+// Flips every bit after the headers of the segments that
+// hold live data, other than the one that is written to
+// next, returning how many were rotted.
+fn rot_sealed_segments<P: AsRef<std::path::Path>>(db: &Db, file: P) -> usize {
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    let segments = db.space_stats().unwrap().segments;
+    let tip = segments.iter().map(|s| s.lsn).max().unwrap();
+    let mut file =
+        std::fs::OpenOptions::new().read(true).write(true).open(file).unwrap();
+    let mut rotted = 0;
+    for segment in segments {
+        if segment.lsn == tip || segment.live_bytes == 0 {
+            continue;
+        }
+        let mut buf = vec![0; 4096 - SEG_HEADER_LEN];
+        let body = segment.offset + SEG_HEADER_LEN as u64;
+        file.seek(SeekFrom::Start(body)).unwrap();
+        file.read_exact(&mut buf).unwrap();
+        for byte in &mut buf {
+            *byte ^= 0xFF;
+        }
+        file.seek(SeekFrom::Start(body)).unwrap();
+        file.write_all(&buf).unwrap();
+        rotted += 1;
+    }
+    file.sync_all().unwrap();
+    rotted
+}
@@ -0,0 +1,230 @@
+// Run with `cargo test --features simulation --test test_simulation`.
+// The simulation runs the IO threadpool's work on the
+// calling thread for every database in the process, so it
+// isn't enabled by `testing`.
+#![cfg(feature = "simulation")]
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use sled::{
+    crash_test::CrashTest,
+    simulation::{Damage, Simulation},
+    Batch,
+};
+
+// Runs a random history of writes, flushes and crashes,
+// checking after every crash that everything flushed before
+// it survived. Returns the final contents of the database
+// and a checksum of its log.
+fn run_history(seed: u64) -> (Vec<(Vec<u8>, Vec<u8>)>, u32) {
+    let simulation = Simulation::new(seed);
+    let config =
+        simulation.config().segment_size(1024).flush_every_ms(Some(50));
+    let mut db = config.open().unwrap();
+
+    // the values written since the last flush may or may not
+    // survive a crash, so only the flushed ones are checked
+    let mut flushed: BTreeMap<Vec<u8>, Option<Vec<u8>>> = BTreeMap::new();
+    let mut unflushed = BTreeMap::new();
+
+    for _ in 0..1000 {
+        let key = simulation.random(64).to_be_bytes().to_vec();
+        match simulation.random(20) {
+            0 => {
+                db.flush().unwrap();
+                flushed.append(&mut unflushed);
+            }
+            1 => {
+                simulation.crash();
+                drop(db);
+                db = config.open().unwrap();
+
+                for (key, value) in flushed.iter_mut() {
+                    let actual =
+                        db.get(key).unwrap().map(|v| v.to_vec());
+                    if unflushed.contains_key(key) {
+                        // a later unflushed write to the key
+                        // may or may not have survived
+                        *value = actual;
+                    } else {
+                        assert_eq!(&actual, value);
+                    }
+                }
+                for (key, _) in std::mem::take(&mut unflushed) {
+                    if !flushed.contains_key(&key) {
+                        let actual =
+                            db.get(&key).unwrap().map(|v| v.to_vec());
+                        let _ = flushed.insert(key, actual);
+                    }
+                }
+            }
+            2..=5 => {
+                db.remove(&key).unwrap();
+                let _ = unflushed.insert(key, None);
+            }
+            _ => {
+                let len = simulation.random(200) as usize;
+                let value = vec![simulation.random(256) as u8; len];
+                db.insert(&key, value.clone()).unwrap();
+                let _ = unflushed.insert(key, Some(value));
+            }
+        }
+
+        let elapsed = Duration::from_millis(simulation.random(20));
+        simulation.tick(&db, elapsed).unwrap();
+    }
+
+    db.flush().unwrap();
+    let contents = db
+        .iter()
+        .map(|res| {
+            let (k, v) = res.unwrap();
+            (k.to_vec(), v.to_vec())
+        })
+        .collect();
+
+    (contents, simulation.log_checksum())
+}
+
+#[test]
+fn histories_replay_from_their_seed() {
+    for seed in 0..4 {
+        assert_eq!(run_history(seed), run_history(seed), "seed {}", seed);
+    }
+}
+
+#[test]
+fn crashes_keep_synced_writes() {
+    let simulation = Simulation::new(7);
+    let config = simulation.config().segment_size(1024);
+
+    let db = config.open().unwrap();
+    for i in 0..100_u32 {
+        db.insert(i.to_be_bytes(), vec![1; 100]).unwrap();
+    }
+    db.flush().unwrap();
+    for i in 100..200_u32 {
+        db.insert(i.to_be_bytes(), vec![2; 100]).unwrap();
+    }
+
+    simulation.crash();
+    assert!(db.insert(b"after", b"crash").is_err() || db.flush().is_err());
+    drop(db);
+
+    let db = config.open().unwrap();
+    for i in 0..100_u32 {
+        let value = db.get(i.to_be_bytes()).unwrap().unwrap();
+        assert_eq!(value, vec![1; 100]);
+    }
+    assert!(db.len() <= 200);
+}
+
+fn crash_test(seed: u64, damage: Damage) {
+    let report = CrashTest::new(seed)
+        .rounds(200)
+        .damage(&[damage])
+        .run(|simulation| {
+            let mut batch = Batch::default();
+            for _ in 0..simulation.random(16) {
+                let key = simulation.random(64).to_be_bytes();
+                if simulation.random(4) == 0 {
+                    batch.remove(&key);
+                } else {
+                    let len = simulation.random(300) as usize;
+                    batch.insert(&key, vec![seed as u8; len]);
+                }
+            }
+            batch
+        })
+        .unwrap();
+
+    assert!(report.is_ok(), "seed {}: {:?}", seed, report.violation);
+    assert!(report.power_losses > 0);
+}
+
+#[test]
+fn recovers_from_truncated_writes() {
+    for seed in 0..8 {
+        crash_test(seed, Damage::Truncate);
+    }
+}
+
+#[test]
+fn recovers_from_flipped_bits() {
+    for seed in 0..8 {
+        crash_test(seed, Damage::BitFlip);
+    }
+}
+
+#[test]
+fn recovers_from_reordered_sectors() {
+    for seed in 0..8 {
+        crash_test(seed, Damage::Reorder);
+    }
+}
+
+#[test]
+fn indexes_survive_power_losses() {
+    // values are indexed by their first byte
+    fn first_byte(value: &[u8]) -> Option<Vec<u8>> {
+        value.first().map(|b| vec![*b])
+    }
+
+    for seed in 0..8 {
+        let simulation = Simulation::new(seed);
+        let config = simulation.config().segment_size(1024);
+        let open = || {
+            let db = config.open().unwrap();
+            let primary = db.open_tree("primary").unwrap();
+            let index_tree = db.open_tree("index").unwrap();
+            let index = primary.add_index(&index_tree, first_byte).unwrap();
+            (db, primary, index)
+        };
+        let (mut db, mut primary, mut index) = open();
+
+        for _ in 0..300 {
+            let crashes = simulation.crashes();
+            if simulation.random(8) == 0 {
+                let at = 1 + simulation.random(4);
+                simulation.power_loss_at(at, Damage::Truncate);
+            }
+
+            let key = simulation.random(32).to_be_bytes();
+            let res = if simulation.random(4) == 0 {
+                primary.remove(key).map(drop)
+            } else {
+                let value = vec![simulation.random(4) as u8; 50];
+                primary.insert(key, value).map(drop)
+            };
+            let res = res.and_then(|()| db.flush().map(drop));
+
+            if simulation.crashes() == crashes {
+                simulation.cancel_power_loss();
+                res.unwrap();
+                continue;
+            }
+
+            drop((db, primary, index));
+            let reopened = open();
+            db = reopened.0;
+            primary = reopened.1;
+            index = reopened.2;
+
+            // every value is found under its index key, and
+            // the index holds no other entries
+            for b in 0..4_u8 {
+                let expected: Vec<_> = primary
+                    .iter()
+                    .map(|res| res.unwrap())
+                    .filter(|(_, v)| v[0] == b)
+                    .collect();
+                let found: Vec<_> =
+                    index.get([b]).map(|res| res.unwrap()).collect();
+                assert_eq!(found, expected, "seed {}", seed);
+            }
+            let entries = index.index_tree().len();
+            assert_eq!(entries, primary.len(), "seed {}", seed);
+        }
+    }
+}
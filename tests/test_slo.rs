@@ -0,0 +1,63 @@
+mod common;
+
+use std::time::Duration;
+
+use sled::*;
+
+const SLO: LatencySlo = LatencySlo {
+    operation: SloOperation::Get,
+    percentile: 99.,
+    threshold: Duration::from_nanos(0),
+    window: Duration::from_millis(10),
+};
+
+#[test]
+fn rejects_invalid_slos() {
+    let db = Config::new().temporary(true).open().unwrap();
+
+    for percentile in &[0., -1., 100.1, std::f64::NAN] {
+        let slo = LatencySlo { percentile: *percentile, ..SLO };
+        assert!(db.register_latency_slo(slo, |_| {}).is_err());
+    }
+
+    let slo = LatencySlo { window: Duration::from_secs(0), ..SLO };
+    assert!(db.register_latency_slo(slo, |_| {}).is_err());
+}
+
+#[cfg(not(feature = "metrics"))]
+#[test]
+fn requires_metrics() {
+    let db = Config::new().temporary(true).open().unwrap();
+
+    if let Err(Error::Unsupported(_)) = db.register_latency_slo(SLO, |_| {}) {
+    } else {
+        panic!("latency SLOs should be unsupported without metrics");
+    }
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn reports_violations() {
+    common::setup_logger();
+
+    let db = Config::new().temporary(true).open().unwrap();
+    db.insert(b"k", b"v").unwrap();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let _monitor = db
+        .register_latency_slo(SLO, move |violation| {
+            let _ = tx.send(*violation);
+        })
+        .unwrap();
+
+    let violation = loop {
+        db.get(b"k").unwrap();
+        if let Ok(violation) = rx.try_recv() {
+            break violation;
+        }
+    };
+
+    assert_eq!(violation.slo, SLO);
+    assert!(violation.operations > 0);
+    assert!(violation.observed > Duration::from_nanos(0));
+}
@@ -26,3 +26,79 @@ fn size_leak() -> sled::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn overwrite_leak() -> sled::Result<()> {
+    common::setup_logger();
+
+    let tree = sled::Config::new()
+        .temporary(true)
+        .segment_size(4096)
+        .flush_every_ms(None)
+        .open()?;
+
+    // overwriting the same keys over and over writes several
+    // megabytes to the log, but segments full of stale pages
+    // must be cleaned and reused instead of growing the file
+    for round in 0..=255_u8 {
+        for key in 0..100_u8 {
+            tree.insert([key], vec![round; 64])?;
+        }
+        tree.flush()?;
+
+        let sz = tree.size_on_disk()?;
+        assert!(
+            sz <= 256 * 1024,
+            "expected system to use less than or equal to \
+             262144 bytes, but actually used {} after {} rounds",
+            sz,
+            round
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+#[cfg(all(target_os = "linux", not(feature = "portable_io")))]
+fn free_segments_return_space() -> sled::Result<()> {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("free_segments_return_space");
+
+    let db = sled::Config::new()
+        .path(dir.path())
+        .segment_size(4096)
+        .flush_every_ms(None)
+        .open()?;
+
+    for key in 0..2000_u32 {
+        db.insert(key.to_be_bytes(), vec![0; 256])?;
+    }
+    db.flush()?;
+
+    // the earliest segments are emptied out while later
+    // ones stay live, so the file can't just be truncated
+    for key in 0..1500_u32 {
+        db.remove(key.to_be_bytes())?;
+    }
+    for _ in 0..10 {
+        for key in 1500..1600_u32 {
+            db.insert(key.to_be_bytes(), vec![1; 8])?;
+        }
+        db.flush()?;
+    }
+
+    let file_len = std::fs::metadata(dir.file("db"))?.len();
+    let sz = db.size_on_disk()?;
+    assert!(
+        sz < file_len,
+        "expected free segments in the middle of the {} byte \
+         log to have been returned to the filesystem, but {} \
+         bytes are used",
+        file_len,
+        sz
+    );
+
+    Ok(())
+}
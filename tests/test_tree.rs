@@ -63,9 +63,10 @@ fn test_varied_compression_ratios() {
         buf
     };
 
+    let dir = common::TestDir::new("compression");
     let tree = sled::Config::default()
         .use_compression(true)
-        .path("compression_db_test")
+        .path(dir.path())
         .open()
         .unwrap();
 
@@ -76,12 +77,72 @@ fn test_varied_compression_ratios() {
     drop(tree);
     let tree = sled::Config::default()
         .use_compression(true)
-        .path("compression_db_test")
+        .path(dir.path())
         .open()
         .unwrap();
     drop(tree);
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn toggle_compression_across_restarts() {
+    use std::io::Read;
+
+    common::setup_logger();
+
+    let dir = common::TestDir::new("toggle_compression_across_restarts");
+    let path = dir.path();
+
+    let open = |use_compression| {
+        Config::new()
+            .path(path)
+            .use_compression(use_compression)
+            .segment_size(4096)
+            .overflow_threshold(64)
+            .open()
+            .unwrap()
+    };
+
+    // small values are stored inline in leaves, medium ones
+    // in their own inline page, and large ones in blobs, as
+    // long as compression doesn't shrink them
+    let value = |round: u8, len: usize| {
+        let mut state = u32::from(round) + 1;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect::<Vec<u8>>()
+    };
+    let lens = [8, 128, 4096];
+
+    for round in 0..4_u8 {
+        let db = open(round % 2 == 0);
+
+        for (i, len) in lens.iter().enumerate() {
+            db.insert([round, i as u8], value(round, *len)).unwrap();
+        }
+
+        // everything written while compression was on or
+        // off remains readable
+        for earlier in 0..=round {
+            for (i, len) in lens.iter().enumerate() {
+                let key = [earlier, i as u8];
+                let expected = value(earlier, *len);
+                assert_eq!(db.get(key).unwrap().unwrap(), expected);
+
+                let mut read = vec![];
+                db.get_reader(key)
+                    .unwrap()
+                    .unwrap()
+                    .read_to_end(&mut read)
+                    .unwrap();
+                assert_eq!(read, expected);
+            }
+        }
+    }
 
-    let _ = std::fs::remove_dir_all("compression_db_test");
 }
 
 #[test]
@@ -380,6 +441,92 @@ fn concurrent_tree_iter() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn scans_during_splits_and_merges() {
+    common::setup_logger();
+
+    const N_INDELIBLE: u64 = 500;
+    const N_SCANS: usize = 200;
+
+    // tiny nodes, so that the writers constantly split and
+    // merge the leaves and index nodes being scanned
+    let db = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .max_leaf_items(6)
+        .min_leaf_items(3)
+        .max_index_items(6)
+        .min_index_items(3)
+        .open()
+        .unwrap();
+
+    // the even keys are present for the whole test, and the
+    // odd ones between them come and go
+    let indelible: Vec<u64> = (0..N_INDELIBLE).map(|i| i * 2).collect();
+    for k in &indelible {
+        db.insert(U64Key::new(*k), vec![]).unwrap();
+    }
+
+    let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let writers: Vec<_> = (0..2_u64)
+        .map(|w| {
+            let db = db.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                let mut round = 0;
+                while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                    let base = (round * 97 + w * 250) % N_INDELIBLE;
+                    let odd =
+                        |i| U64Key::new(((base + i) % N_INDELIBLE) * 2 + 1);
+                    for i in 0..64 {
+                        db.insert(odd(i), vec![]).unwrap();
+                    }
+                    for i in 0..64 {
+                        db.remove(odd(i)).unwrap();
+                    }
+                    round += 1;
+                }
+            })
+        })
+        .collect();
+
+    let read = |k: Result<IVec>| U64Key::from_bytes(&k.unwrap()).unwrap().get();
+    let scanners: Vec<_> = (0..4)
+        .map(|s| {
+            let db = db.clone();
+            let indelible = indelible.clone();
+            thread::spawn(move || {
+                for _ in 0..N_SCANS {
+                    let mut keys: Vec<u64> = if s % 2 == 0 {
+                        db.iter().keys().map(read).collect()
+                    } else {
+                        let mut keys: Vec<u64> =
+                            db.iter().keys().rev().map(read).collect();
+                        assert!(keys.windows(2).all(|w| w[0] > w[1]));
+                        keys.reverse();
+                        keys
+                    };
+
+                    // no key is returned twice, and no key that
+                    // was there for the whole scan is skipped
+                    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+                    keys.retain(|k| k % 2 == 0);
+                    assert_eq!(keys, indelible);
+                }
+            })
+        })
+        .collect();
+
+    for scanner in scanners {
+        scanner.join().unwrap();
+    }
+    done.store(true, std::sync::atomic::Ordering::Relaxed);
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    assert!(db.verify().unwrap().is_ok());
+}
+
 #[test]
 fn concurrent_tree_transactions() -> TransactionResult<()> {
     common::setup_logger();
@@ -502,6 +649,181 @@ fn batch_outside_of_transaction() -> TransactionResult<()> {
     Ok(())
 }
 
+#[test]
+fn optimistic_transactions() -> TransactionResult<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let db = config.open().unwrap();
+    let counters = db.open_tree(b"counters")?;
+    let claims = db.open_tree(b"claims")?;
+
+    const N_THREADS: usize = 8;
+    const N_INCREMENTS: u64 = 100;
+
+    let barrier = Arc::new(Barrier::new(N_THREADS));
+    let mut threads: Vec<std::thread::JoinHandle<TransactionResult<()>>> =
+        vec![];
+    for t in 0..N_THREADS {
+        let db = db.clone();
+        let counters = counters.clone();
+        let claims = claims.clone();
+        let barrier = barrier.clone();
+        let thread = std::thread::spawn(move || {
+            barrier.wait();
+            for _ in 0..N_INCREMENTS {
+                // each value of the counter is claimed once,
+                // unless an increment is lost
+                db.transact(|tx| {
+                    let current = tx
+                        .get(&counters, b"c")?
+                        .map_or(0, |v| u64::from_be_bytes(*array_ref(&v)));
+                    let next = (current + 1).to_be_bytes();
+                    tx.insert(&counters, b"c", &next)?;
+                    tx.insert(&claims, &next, vec![t as u8])?;
+                    Ok(())
+                })?;
+            }
+            Ok(())
+        });
+        threads.push(thread);
+    }
+    for thread in threads.into_iter() {
+        thread.join().unwrap()?;
+    }
+
+    let total = N_THREADS as u64 * N_INCREMENTS;
+    let c = counters.get(b"c")?.unwrap();
+    assert_eq!(u64::from_be_bytes(*array_ref(&c)), total);
+    assert_eq!(claims.len() as u64, total);
+
+    // reads see the transaction's own writes, and nothing
+    // is written when it aborts
+    let res: TransactionResult<(), &str> = db.transact(|tx| {
+        tx.insert(&counters, b"d", b"1")?;
+        assert_eq!(tx.get(&counters, b"d")?, Some(IVec::from(b"1")));
+        tx.remove(&counters, b"c")?;
+        assert_eq!(tx.get(&counters, b"c")?, None);
+        abort("changed my mind")
+    });
+    assert_eq!(res, Err(TransactionError::Abort("changed my mind")));
+    assert_eq!(counters.get(b"d")?, None);
+    assert_eq!(counters.get(b"c")?, Some(c));
+
+    Ok(())
+}
+
+#[test]
+fn optimistic_transactions_with_plain_writers() -> TransactionResult<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let db = config.open().unwrap();
+    let counters = db.open_tree(b"counters")?;
+
+    const N_THREADS: usize = 4;
+    const N_INCREMENTS: u64 = 1000;
+
+    // half of the threads increment the counter in
+    // transactions, and the other half with plain writes
+    // that must not slip in between a commit's validation
+    // and its writes
+    let barrier = Arc::new(Barrier::new(N_THREADS * 2));
+    let mut threads: Vec<std::thread::JoinHandle<TransactionResult<()>>> =
+        vec![];
+    for t in 0..N_THREADS * 2 {
+        let db = db.clone();
+        let counters = counters.clone();
+        let barrier = barrier.clone();
+        let thread = std::thread::spawn(move || {
+            barrier.wait();
+            for _ in 0..N_INCREMENTS {
+                if t % 2 == 0 {
+                    db.transact(|tx| {
+                        let current = tx
+                            .get(&counters, b"c")?
+                            .map_or(0, |v| u64::from_be_bytes(*array_ref(&v)));
+                        let next = (current + 1).to_be_bytes();
+                        tx.insert(&counters, b"c", &next)?;
+                        Ok(())
+                    })?;
+                } else {
+                    let _ = counters.update_and_fetch(b"c", |old| {
+                        let current = old.map_or(0, |v| {
+                            u64::from_be_bytes(*array_ref(&IVec::from(v)))
+                        });
+                        Some((current + 1).to_be_bytes().to_vec())
+                    })?;
+                }
+            }
+            Ok(())
+        });
+        threads.push(thread);
+    }
+    for thread in threads.into_iter() {
+        thread.join().unwrap()?;
+    }
+
+    let total = N_THREADS as u64 * 2 * N_INCREMENTS;
+    let c = counters.get(b"c")?.unwrap();
+    assert_eq!(u64::from_be_bytes(*array_ref(&c)), total);
+
+    Ok(())
+}
+
+#[test]
+fn optimistic_transactions_do_not_sync() -> TransactionResult<()> {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("optimistic_transactions_do_not_sync");
+    let db = Config::new().path(dir.path()).flush_every_ms(None).open()?;
+    db.flush()?;
+
+    // commits are as durable as plain writes, and only
+    // synced by flushes
+    let before = db.io_stats().fsyncs;
+    for i in 0..100_u32 {
+        db.transact(|tx| {
+            tx.insert(&db, &i.to_be_bytes(), b"v")?;
+            Ok(())
+        })?;
+    }
+    assert_eq!(db.io_stats().fsyncs, before);
+    assert_eq!(db.len(), 100);
+
+    Ok(())
+}
+
+#[test]
+fn optimistic_transactions_reject_trees_of_other_dbs() -> Result<()> {
+    let db = Config::new().temporary(true).open()?;
+    let other_db = Config::new().temporary(true).open()?;
+
+    // trees are identified by their names, so these two
+    // share an id
+    let tree = db.open_tree(b"tree")?;
+    let other_tree = other_db.open_tree(b"tree")?;
+
+    let res: TransactionResult<(), ()> = db.transact(|tx| {
+        tx.insert(&tree, b"k", b"v")?;
+        tx.insert(&other_tree, b"k", b"v")?;
+        Ok(())
+    });
+    match res {
+        Err(TransactionError::Storage(Error::Unsupported(_))) => {}
+        other => panic!("expected Error::Unsupported, got {:?}", other),
+    }
+    assert_eq!(tree.get(b"k")?, None);
+    assert_eq!(other_tree.get(b"k")?, None);
+
+    Ok(())
+}
+
+fn array_ref(v: &IVec) -> &[u8; 8] {
+    use std::convert::TryInto;
+    v.as_ref().try_into().unwrap()
+}
+
 #[test]
 fn tree_subdir() {
     let mut parent_path = std::env::temp_dir();
@@ -620,6 +942,51 @@ fn tree_big_keys_iterator() {
     assert_eq!(tree_scan.next(), None);
 }
 
+#[test]
+fn subscribers_and_durability() {
+    common::setup_logger();
+
+    let timeout = std::time::Duration::from_millis(100);
+
+    let db = Config::new().temporary(true).flush_every_ms(None).open().unwrap();
+    let mut eager = db.watch_prefix(b"");
+    let durable_only = db.watch_prefix_durable_only(b"");
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let receiver = thread::spawn(move || {
+        for event in durable_only.take(2) {
+            tx.send(event).unwrap();
+        }
+    });
+
+    let stable = db.context.pagecache.stable_lsn();
+    db.insert(b"k", b"v").unwrap();
+    db.remove(b"k").unwrap();
+
+    // plain subscribers may see events before their writes
+    // are durable, so they could act on writes that are
+    // lost in a crash
+    let insert =
+        Event::Insert { key: IVec::from(b"k"), value: IVec::from(b"v") };
+    let remove = Event::Remove { key: IVec::from(b"k") };
+    assert_eq!(eager.next(), Some(insert.clone()));
+    assert_eq!(eager.next(), Some(remove.clone()));
+    assert_eq!(db.context.pagecache.stable_lsn(), stable);
+
+    // durable-only subscribers wait for the flush
+    assert!(rx.recv_timeout(timeout).is_err());
+    assert!(db.flush().unwrap() > 0);
+    assert_eq!(rx.recv().unwrap(), insert);
+    assert_eq!(rx.recv().unwrap(), remove);
+    receiver.join().unwrap();
+
+    // writes made durable by their caller are delivered
+    // without waiting for anyone else to flush
+    let mut durable_only = db.watch_prefix_durable_only(b"");
+    db.insert_with_durability(b"k", b"v", Durability::Sync).unwrap();
+    assert_eq!(durable_only.next(), Some(insert));
+}
+
 #[test]
 fn tree_subscribers_and_keyspaces() -> Result<()> {
     let config = Config::new().temporary(true).flush_every_ms(None);
@@ -757,6 +1124,59 @@ fn tree_range() {
     assert_eq!(r.next(), None);
 }
 
+#[test]
+fn catalog_recovers_from_meta_journal() {
+    use std::io::Write;
+
+    common::setup_logger();
+
+    let dir = common::TestDir::new("catalog_recovers_from_meta_journal");
+    let path = dir.path();
+    let journal_path = dir.file("meta_journal");
+    let config = || Config::new().path(path).flush_every_ms(None);
+
+    let db = config().open().unwrap();
+    db.open_tree("a").unwrap().insert(b"k", b"a").unwrap();
+    db.flush().unwrap();
+    let journal_with_a = std::fs::read(&journal_path).unwrap();
+
+    db.open_tree("b").unwrap().insert(b"k", b"b").unwrap();
+    db.flush().unwrap();
+    drop(db);
+
+    // a torn record at the end of the journal is ignored
+    let mut journal = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&journal_path)
+        .unwrap();
+    journal.write_all(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13]).unwrap();
+    drop(journal);
+
+    let db = config().open().unwrap();
+    assert!(db.tree_names().contains(&IVec::from(b"b")));
+    drop(db);
+
+    // a change that was made stable in the log before it
+    // could be journaled is kept, and journaled on recovery
+    std::fs::write(&journal_path, &journal_with_a).unwrap();
+
+    let db = config().open().unwrap();
+    let names = db.tree_names();
+    assert!(names.contains(&IVec::from(b"a")));
+    assert!(names.contains(&IVec::from(b"b")));
+    assert_eq!(db.open_tree("b").unwrap().get(b"k").unwrap().unwrap(), b"b");
+    drop(db);
+    assert!(std::fs::read(&journal_path).unwrap().len() > journal_with_a.len());
+
+    // without a journal, it's started off from the log
+    std::fs::remove_file(&journal_path).unwrap();
+
+    let db = config().open().unwrap();
+    assert!(db.tree_names().contains(&IVec::from(b"a")));
+    drop(db);
+    assert!(!std::fs::read(&journal_path).unwrap().is_empty());
+}
+
 #[test]
 fn recover_tree() {
     common::setup_logger();
@@ -787,841 +1207,3106 @@ fn recover_tree() {
 }
 
 #[test]
-fn create_tree() {
+fn overflow_values() {
     common::setup_logger();
 
-    let path = "create_exclusive_db";
-    let _ = std::fs::remove_dir_all(path);
+    let config = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .segment_size(4096)
+        .overflow_threshold(64);
 
-    {
-        let config = Config::new().create_new(true).path(path);
-        config.open().unwrap();
+    // small values stay in their leaf, medium ones get an
+    // overflow page in the log, and large ones get a blob.
+    let value = |i: usize| vec![i as u8; (i % 3) * 1000 + 10];
+
+    let t = config.open().unwrap();
+    for i in 0..N_PER_THREAD {
+        assert_eq!(t.insert(kv(i), value(i)).unwrap(), None);
+    }
+    for i in 0..N_PER_THREAD {
+        assert_eq!(t.get(kv(i)).unwrap().unwrap(), value(i));
+    }
+    for i in 0..N_PER_THREAD {
+        let old = t.insert(kv(i), value(i + 1)).unwrap();
+        assert_eq!(old.unwrap(), value(i));
+    }
+    for (i, res) in t.iter().enumerate() {
+        let (k, v) = res.unwrap();
+        assert_eq!(k, kv(i));
+        assert_eq!(v, value(i + 1));
+    }
+    for (i, res) in t.iter().rev().enumerate() {
+        let (_, v) = res.unwrap();
+        assert_eq!(v, value(N_PER_THREAD - i));
+    }
+    assert_eq!(
+        t.compare_and_swap(kv(0), Some(value(1)), Some(value(2))).unwrap(),
+        Ok(())
+    );
+    drop(t);
+
+    let t = config.open().unwrap();
+    assert_eq!(t.get(kv(0)).unwrap().unwrap(), value(2));
+    for i in 1..N_PER_THREAD {
+        let mut reader = t.get_reader(kv(i)).unwrap().unwrap();
+        assert_eq!(reader.remaining(), value(i + 1).len() as u64);
+        let mut buf = vec![];
+        std::io::Read::read_to_end(&mut reader, &mut buf).unwrap();
+        assert_eq!(buf, value(i + 1));
+        assert_eq!(reader.remaining(), 0);
+    }
+    for i in 0..N_PER_THREAD {
+        assert!(t.remove(kv(i)).unwrap().is_some());
     }
+    assert!(t.get_reader(kv(0)).unwrap().is_none());
+    drop(t);
 
-    let config = Config::new().create_new(true).path(path);
-    config.open().unwrap_err();
-    std::fs::remove_dir_all(path).unwrap();
+    let t = config.open().unwrap();
+    for i in 0..N_PER_THREAD {
+        assert_eq!(t.get(kv(i)), Ok(None));
+    }
 }
 
 #[test]
-fn tree_import_export() -> Result<()> {
+fn failed_writes_free_their_overflow_values() {
     common::setup_logger();
 
-    let config_1 = Config::new().temporary(true);
-    let config_2 = Config::new().temporary(true);
+    let db = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .overflow_threshold(64)
+        .open()
+        .unwrap();
 
-    let db = config_1.open()?;
-    for db_id in 0..N_THREADS {
-        let tree_id = format!("tree_{}", db_id);
-        let tree = db.open_tree(tree_id.as_bytes())?;
-        for i in 0..N_THREADS {
-            let k = kv(i);
-            tree.insert(&k, k.clone()).unwrap();
-        }
-    }
+    let t = db.open_tree("dropped").unwrap();
+    t.insert(kv(0), vec![0; 100]).unwrap();
+    assert!(db.drop_tree(b"dropped").unwrap());
 
-    let checksum_a = db.checksum().unwrap();
+    // a write that fails must not leave its value page behind
+    assert!(t.insert(kv(1), vec![1; 100]).is_err());
 
-    drop(db);
+    let report = db.verify().unwrap();
+    assert!(report.is_ok(), "{:?}", report.violations);
+}
 
-    let exporter = config_1.open()?;
-    let importer = config_2.open()?;
+#[test]
+fn bulk_load() {
+    common::setup_logger();
 
-    let export = exporter.export();
-    importer.import(export);
+    let config = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .segment_size(4096)
+        .overflow_threshold(64);
 
-    drop(exporter);
-    drop(config_1);
-    drop(importer);
+    let value = |i: usize| vec![i as u8; i % 100];
 
-    let db = config_2.open()?;
+    let t = config.open().unwrap();
+    t.bulk_load((0..N).map(|i| (kv(i), value(i)))).unwrap();
 
-    let checksum_b = db.checksum().unwrap();
-    assert_eq!(checksum_a, checksum_b);
+    assert_eq!(t.len(), N);
+    for (i, res) in t.iter().enumerate() {
+        let (k, v) = res.unwrap();
+        assert_eq!(k, kv(i));
+        assert_eq!(v, value(i));
+    }
+    assert_eq!(t.iter().rev().count(), N);
 
-    for db_id in 0..N_THREADS {
-        let tree_id = format!("tree_{}", db_id);
-        let tree = db.open_tree(tree_id.as_bytes())?;
+    // loading is only supported for empty trees
+    assert!(t.bulk_load(vec![(kv(N), value(N))]).is_err());
 
-        for i in 0..N_THREADS {
-            let k = kv(i as usize);
-            assert_eq!(tree.get(&*k).unwrap().unwrap(), k);
-            tree.remove(&*k).unwrap();
+    for i in 0..N {
+        if i % 2 == 0 {
+            assert_eq!(t.remove(kv(i)).unwrap().unwrap(), value(i));
+        } else {
+            t.insert(kv(i), value(i + 1)).unwrap();
         }
     }
+    drop(t);
 
-    let checksum_c = db.checksum().unwrap();
-
-    drop(db);
-
-    let db = config_2.open()?;
-    for db_id in 0..N_THREADS {
-        let tree_id = format!("tree_{}", db_id);
-        let tree = db.open_tree(tree_id.as_bytes())?;
-
-        for i in 0..N_THREADS {
-            let k = kv(i as usize);
-            assert_eq!(tree.get(&*k), Ok(None));
+    let t = config.open().unwrap();
+    assert_eq!(t.len(), N / 2);
+    for i in 0..N {
+        if i % 2 == 0 {
+            assert_eq!(t.get(kv(i)).unwrap(), None);
+        } else {
+            assert_eq!(t.get(kv(i)).unwrap().unwrap(), value(i + 1));
         }
     }
 
-    let checksum_d = db.checksum().unwrap();
-    assert_eq!(checksum_c, checksum_d);
-
-    Ok(())
+    // unsorted input leaves the tree empty and usable
+    let unsorted = t.open_tree("unsorted").unwrap();
+    let pairs = (0..N).map(|i| {
+        if i == N / 2 { (kv(0), value(0)) } else { (kv(i), value(i)) }
+    });
+    assert!(unsorted.bulk_load(pairs).is_err());
+    assert!(unsorted.is_empty());
+    unsorted.insert(kv(0), value(0)).unwrap();
+    assert_eq!(unsorted.len(), 1);
+
+    // leaf bounds are truncated to the shortest separator,
+    // which must still route lookups of the full keys
+    let long_key = |i: usize| {
+        let mut key = kv(i);
+        key.extend_from_slice(&[0xFF; 100]);
+        key
+    };
+    let long = t.open_tree("long").unwrap();
+    long.bulk_load((0..N).map(|i| (long_key(i), kv(i)))).unwrap();
+    let report = long.verify().unwrap();
+    assert!(report.is_ok(), "{:?}", report.violations);
+    for i in 0..N {
+        assert_eq!(long.get(long_key(i)).unwrap().unwrap(), kv(i));
+        assert_eq!(long.get(kv(i)).unwrap(), None);
+        long.insert(kv(i), vec![]).unwrap();
+    }
+    assert_eq!(long.len(), 2 * N);
+    let report = long.verify().unwrap();
+    assert!(report.is_ok(), "{:?}", report.violations);
 }
 
 #[test]
-#[cfg(not(target_os = "fuchsia"))]
-fn quickcheck_tree_matches_btreemap() {
-    let n_tests = if cfg!(windows) { 25 } else { 100 };
+fn verify_structure() {
+    common::setup_logger();
 
-    QuickCheck::new()
-        .gen(StdGen::new(rand::thread_rng(), 1000))
-        .tests(n_tests)
-        .max_tests(n_tests * 10)
-        .quickcheck(
-            prop_tree_matches_btreemap
-                as fn(Vec<Op>, bool, bool, u8, u8) -> bool,
-        );
-}
+    let config = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .segment_size(4096)
+        .overflow_threshold(64);
 
-#[test]
-fn tree_bug_00() {
-    // postmortem:
-    prop_tree_matches_btreemap(vec![Restart], false, false, 0, 0);
-}
+    let db = config.open().unwrap();
+    let t = db.open_tree("verified").unwrap();
 
-#[test]
-fn tree_bug_01() {
-    // postmortem:
-    // this was a bug in the snapshot recovery, where
-    // it led to max_id dropping by 1 after a restart.
-    // postmortem 2:
-    // we were stalling here because we had a new log with stable of
-    // SEG_HEADER_LEN, but when we iterated over it to create a new
-    // snapshot (snapshot every 1 set in Config), we iterated up until
-    // that offset. make_stable requires our stable offset to be >=
-    // the provided one, to deal with 0.
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![32]), 9),
-            Set(Key(vec![195]), 13),
-            Restart,
-            Set(Key(vec![164]), 147),
-        ],
-        true,
-        false,
-        0,
-        0,
+    let report = t.verify().unwrap();
+    assert!(report.is_ok(), "{:?}", report.violations);
+    assert_eq!(report.items, 0);
+
+    for i in 0..N {
+        t.insert(kv(i), vec![i as u8; i % 100]).unwrap();
+    }
+    for i in (0..N).step_by(3) {
+        t.remove(kv(i)).unwrap();
+    }
+
+    let report = t.verify().unwrap();
+    assert!(report.is_ok(), "{:?}", report.violations);
+    assert_eq!(report.items, t.len());
+    assert_eq!(
+        report.overflow_values,
+        t.iter().values().filter(|v| v.as_ref().unwrap().len() >= 64).count()
     );
+    assert!(report.leaf_nodes > 1);
+    assert!(report.index_nodes >= 1);
+
+    let threads: Vec<_> = (0..4)
+        .map(|thread_id| {
+            let t = t.clone();
+            thread::spawn(move || {
+                for i in (thread_id..N).step_by(4) {
+                    t.insert(kv(i), kv(i)).unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let report = db.verify().unwrap();
+    assert!(report.is_ok(), "{:?}", report.violations);
+    assert_eq!(report.items, N);
+
+    // dropping a tree releases all of its pages
+    drop(t);
+    assert!(db.drop_tree(b"verified").unwrap());
+    let report = db.verify().unwrap();
+    assert!(report.is_ok(), "{:?}", report.violations);
+    assert_eq!(report.items, 0);
 }
 
 #[test]
-fn tree_bug_02() {
-    // postmortem:
-    // this was a bug in the way that the `Materializer`
-    // was fed data, possibly out of order, if recover
-    // in the pagecache had to run over log entries
-    // that were later run through the same `Materializer`
-    // then the second time (triggered by a snapshot)
-    // would not pick up on the importance of seeing
-    // the new root set.
-    // portmortem 2: when refactoring iterators, failed
-    // to account for node.hi being empty on the infinity
-    // shard
-    prop_tree_matches_btreemap(
-        vec![
-            Restart,
-            Set(Key(vec![215]), 121),
-            Restart,
-            Set(Key(vec![216]), 203),
-            Scan(Key(vec![210]), 4),
-        ],
-        true,
-        false,
-        0,
-        0,
-    );
+fn long_shared_prefixes() {
+    common::setup_logger();
+
+    let config = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .segment_size(64 * 1024);
+
+    // longer than the 255 bytes that used to be the
+    // limit for prefixes omitted from node keys
+    let key = |i: usize| {
+        let mut key = vec![b'/'; 600];
+        key.extend_from_slice(&(i as u32).to_be_bytes());
+        key
+    };
+
+    let t = config.open().unwrap();
+    for i in 0..N {
+        t.insert(key(i), kv(i)).unwrap();
+    }
+    for i in (0..N).step_by(2) {
+        t.remove(key(i)).unwrap();
+    }
+    drop(t);
+
+    let t = config.open().unwrap();
+    assert_eq!(t.len(), N / 2);
+    for i in 0..N {
+        let expected = if i % 2 == 0 { None } else { Some(kv(i)) };
+        assert_eq!(t.get(key(i)).unwrap().map(|v| v.to_vec()), expected);
+    }
+
+    let odd: Vec<_> = (1..N).step_by(2).map(key).collect();
+    let forward: Vec<_> = t.iter().map(|r| r.unwrap().0.to_vec()).collect();
+    assert_eq!(forward, odd);
+    let mut reverse: Vec<_> =
+        t.range(key(0)..).rev().map(|r| r.unwrap().0.to_vec()).collect();
+    reverse.reverse();
+    assert_eq!(reverse, odd);
 }
 
 #[test]
-fn tree_bug_03() {
-    // postmortem: the tree was not persisting and recovering root hoists
-    // postmortem 2: when refactoring the log storage, we failed to restart
-    // log writing in the proper location.
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![113]), 204),
-            Set(Key(vec![119]), 205),
-            Set(Key(vec![166]), 88),
-            Set(Key(vec![23]), 44),
-            Restart,
-            Set(Key(vec![226]), 192),
-            Set(Key(vec![189]), 186),
-            Restart,
-            Scan(Key(vec![198]), 11),
-        ],
-        true,
-        false,
-        0,
-        0,
+fn deterministic_iteration_order() {
+    use rand::{SeedableRng, seq::SliceRandom};
+
+    common::setup_logger();
+
+    // keys of different lengths that share prefixes, and
+    // values that are both inline and in their own pages
+    let mut items: Vec<(Vec<u8>, Vec<u8>)> = (0..N)
+        .map(|i| {
+            let mut key = kv(i);
+            key.truncate(1 + i % 3);
+            key.extend(vec![b'x'; i % 7]);
+            (key, vec![i as u8; (i * 37) % 300])
+        })
+        .collect();
+    items.sort();
+    items.dedup_by(|a, b| a.0 == b.0);
+    items.push((vec![], b"empty key".to_vec()));
+    items.sort();
+
+    fn scan(tree: &Tree) -> Vec<u8> {
+        let mut out = vec![];
+        for kv_res in tree.iter() {
+            let (k, v) = kv_res.unwrap();
+            out.extend_from_slice(&(k.len() as u64).to_le_bytes());
+            out.extend_from_slice(&k);
+            out.extend_from_slice(&(v.len() as u64).to_le_bytes());
+            out.extend_from_slice(&v);
+        }
+        out
+    }
+
+    let dir = common::TestDir::new("deterministic_iteration_order");
+    let path = dir.path();
+    let config = || {
+        Config::new()
+            .path(path)
+            .flush_every_ms(None)
+            .segment_size(4096)
+            .overflow_threshold(64)
+    };
+    let db = config().open().unwrap();
+
+    let ascending = db.open_tree("ascending").unwrap();
+    for (k, v) in &items {
+        ascending.insert(k, v.clone()).unwrap();
+    }
+
+    let descending = db.open_tree("descending").unwrap();
+    for (k, v) in items.iter().rev() {
+        descending.insert(k, v.clone()).unwrap();
+    }
+
+    // shuffled, with overwrites and removals of other
+    // items interleaved
+    let mut shuffled_items = items.clone();
+    shuffled_items.shuffle(&mut rand_chacha::ChaCha8Rng::seed_from_u64(5));
+    let shuffled = db.open_tree("shuffled").unwrap();
+    for (k, v) in &shuffled_items {
+        shuffled.insert(k, vec![0; 100]).unwrap();
+        let mut extra = k.clone();
+        extra.push(0);
+        if items.binary_search_by(|(key, _)| key.cmp(&extra)).is_err() {
+            shuffled.insert(&extra, v.clone()).unwrap();
+        }
+    }
+    for (k, v) in &shuffled_items {
+        let mut extra = k.clone();
+        extra.push(0);
+        if items.binary_search_by(|(key, _)| key.cmp(&extra)).is_err() {
+            shuffled.remove(&extra).unwrap();
+        }
+        shuffled.insert(k, v.clone()).unwrap();
+    }
+
+    let batched = db.open_tree("batched").unwrap();
+    for chunk in shuffled_items.chunks(64) {
+        let mut batch = Batch::default();
+        for (k, v) in chunk {
+            batch.insert(k.clone(), v.clone());
+        }
+        batched.apply_batch(batch).unwrap();
+    }
+
+    let bulk_loaded = db.open_tree("bulk_loaded").unwrap();
+    bulk_loaded.bulk_load(items.clone()).unwrap();
+
+    let expected = scan(&ascending);
+    let expected_checksum = ascending.checksum().unwrap();
+    let expected_items: Vec<(IVec, IVec)> = items
+        .iter()
+        .map(|(k, v)| (IVec::from(k.clone()), IVec::from(v.clone())))
+        .collect();
+    assert_eq!(
+        ascending.iter().collect::<Result<Vec<_>>>().unwrap(),
+        expected_items
     );
+
+    let names =
+        ["ascending", "descending", "shuffled", "batched", "bulk_loaded"];
+    let check = |db: &Db| {
+        for name in &names {
+            let tree = db.open_tree(name).unwrap();
+            assert!(scan(&tree) == expected, "scan of {} differs", name);
+            assert_eq!(tree.checksum().unwrap(), expected_checksum);
+
+            let mut reversed: Vec<_> =
+                tree.iter().rev().collect::<Result<_>>().unwrap();
+            reversed.reverse();
+            assert_eq!(reversed, expected_items, "reverse scan of {}", name);
+        }
+    };
+
+    check(&db);
+    drop((ascending, descending, shuffled, batched, bulk_loaded));
+    drop(db);
+
+    // after recovery, and with nodes of another size
+    let db = config().open().unwrap();
+    check(&db);
+    drop(db);
+
+    let db = config().max_leaf_items(64).min_leaf_items(16).open().unwrap();
+    for name in &names {
+        let tree = db.open_tree(name).unwrap();
+        for (k, _) in &items {
+            tree.get(k).unwrap();
+        }
+    }
+    check(&db);
+    drop(db);
+
 }
 
 #[test]
-fn tree_bug_04() {
-    // postmortem: pagecache was failing to replace the LogId list
-    // when it encountered a new Update::Compact.
-    // postmortem 2: after refactoring log storage, we were not properly
-    // setting the log tip, and the beginning got clobbered after writing
-    // after a restart.
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![158]), 31),
-            Set(Key(vec![111]), 134),
-            Set(Key(vec![230]), 187),
-            Set(Key(vec![169]), 58),
-            Set(Key(vec![131]), 10),
-            Set(Key(vec![108]), 246),
-            Set(Key(vec![127]), 155),
-            Restart,
-            Set(Key(vec![59]), 119),
-        ],
-        true,
-        false,
-        0,
-        0,
+fn configurable_node_sizes() {
+    common::setup_logger();
+
+    let leaves = |config: Config, value_len: usize| {
+        let t = config.temporary(true).flush_every_ms(None).open().unwrap();
+        for i in 0..N {
+            t.insert(kv(i), vec![0; value_len]).unwrap();
+        }
+        for i in (0..N).step_by(2) {
+            t.remove(kv(i)).unwrap();
+        }
+        // traversals perform the splits and merges that
+        // are due, which the iterator doesn't
+        for i in 0..N {
+            t.get(kv(i)).unwrap();
+        }
+        let report = t.verify().unwrap();
+        assert!(report.is_ok(), "{:?}", report.violations);
+        assert_eq!(report.items, N / 2);
+        report.leaf_nodes
+    };
+
+    let small = leaves(Config::new().max_leaf_items(4).min_leaf_items(1), 1);
+    let large = leaves(
+        Config::new()
+            .max_leaf_items(128)
+            .min_leaf_items(32)
+            .max_index_items(8)
+            .min_index_items(2),
+        1,
     );
+    assert!(small >= N / 2 / 4, "{} leaves", small);
+    assert!(large <= N / 2 / 32 + 1, "{} leaves", large);
+
+    // 100 byte values in nodes of at most 1k
+    let bounded = leaves(
+        Config::new()
+            .max_leaf_items(128)
+            .min_leaf_items(0)
+            .max_node_size(Some(1024)),
+        100,
+    );
+    assert!(bounded >= N / 2 / 10, "{} leaves", bounded);
+
+    for config in vec![
+        Config::new().max_leaf_items(1),
+        Config::new().max_index_items(0),
+        Config::new().max_leaf_items(16).min_leaf_items(9),
+        Config::new().max_index_items(16).min_index_items(9),
+        Config::new().max_node_size(Some(0)),
+    ] {
+        match config.temporary(true).open() {
+            Err(Error::Unsupported(_)) => {}
+            other => panic!("expected an invalid config, got {:?}", other),
+        }
+    }
 }
 
 #[test]
-fn tree_bug_05() {
-    // postmortem: during recovery, the segment accountant was failing to
-    // properly set the file's tip.
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![231]), 107),
-            Set(Key(vec![251]), 42),
-            Set(Key(vec![80]), 81),
-            Set(Key(vec![178]), 130),
-            Set(Key(vec![150]), 232),
-            Restart,
-            Set(Key(vec![98]), 78),
-            Set(Key(vec![0]), 45),
-        ],
-        true,
-        false,
-        0,
-        0,
-    );
+fn incompatible_config_options() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("incompatible_config_options");
+    let path = dir.path();
+
+    for config in vec![
+        Config::new().read_only(true).create_new(true),
+        Config::new().read_only(true).temporary(true),
+        Config::new().in_memory(true).create_new(true),
+        // in-memory databases can't be given a path
+        Config::new().in_memory(true),
+        Config::new().flush_every_ms(Some(0)),
+        Config::new().segment_size(1000),
+        Config::new().compression_factor(23),
+        Config::new().cache_capacity(255),
+        Config::new().inline_value_threshold(1 << 16),
+        Config::new().consolidation_policy(ConsolidationPolicy::Fixed(17)),
+        Config::new().consolidation_policy(ConsolidationPolicy::Adaptive {
+            min_chain: 8,
+            max_chain: 4,
+            max_bytes: 1024,
+            hot_reads: 10,
+        }),
+    ] {
+        match config.path(path).open() {
+            Err(Error::Unsupported(_)) => {}
+            other => panic!("expected an invalid config, got {:?}", other),
+        }
+        // nothing is created for invalid configurations
+        assert!(!path.exists());
+    }
+
+    let db = Config::new().path(path).create_new(true).open().unwrap();
+    db.insert(b"k", b"v").unwrap();
+    drop(db);
+
+    let db = Config::new().path(path).read_only(true).open().unwrap();
+    assert_eq!(db.get(b"k").unwrap().unwrap(), b"v");
+    drop(db);
+
 }
 
 #[test]
-fn tree_bug_06() {
-    // postmortem: after reusing segments, we were failing to checksum reads
-    // performed while iterating over rewritten segment buffers, and using
-    // former garbage data. fix: use the crc that's there for catching torn
-    // writes with high probability, AND zero out buffers.
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![162]), 8),
-            Set(Key(vec![59]), 192),
-            Set(Key(vec![238]), 83),
-            Set(Key(vec![151]), 231),
-            Restart,
-            Set(Key(vec![30]), 206),
-            Set(Key(vec![150]), 146),
-            Set(Key(vec![18]), 34),
-        ],
-        true,
-        false,
-        0,
-        0,
-    );
+fn databases_of_older_versions_are_rejected() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("databases_of_older_versions_are_rejected");
+    let path = dir.path();
+
+    Config::new().path(path).open().unwrap().insert(b"k", b"v").unwrap();
+
+    // 0.31 predates the current on-disk format
+    let conf = std::fs::read(path.join("conf")).unwrap();
+    let params = std::str::from_utf8(&conf[..conf.len() - 4]).unwrap();
+    let mut old: Vec<u8> = params
+        .lines()
+        .map(|line| {
+            if line.starts_with("version: ") {
+                "version: 0.31\n".to_owned()
+            } else {
+                format!("{}\n", line)
+            }
+        })
+        .collect::<String>()
+        .into_bytes();
+    let crc = crc32fast::hash(&old);
+    old.extend_from_slice(&crc.to_le_bytes());
+    std::fs::write(path.join("conf"), old).unwrap();
+
+    match Config::new().path(path).open() {
+        Err(Error::Unsupported(e)) => assert!(e.contains("export"), "{}", e),
+        other => panic!("expected an old database to be rejected: {:?}", other),
+    }
 }
 
 #[test]
-fn tree_bug_07() {
-    // postmortem: the segment accountant was not fully recovered, and thought
-    // that it could reuse a particular segment that wasn't actually empty
-    // yet.
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![135]), 22),
-            Set(Key(vec![41]), 36),
-            Set(Key(vec![101]), 31),
-            Set(Key(vec![111]), 35),
-            Restart,
-            Set(Key(vec![47]), 36),
-            Set(Key(vec![79]), 114),
-            Set(Key(vec![64]), 9),
-            Scan(Key(vec![196]), 25),
-        ],
-        true,
-        false,
-        0,
-        0,
-    );
+#[cfg(not(feature = "portable_io"))]
+fn database_is_locked_while_open() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("database_is_locked_while_open");
+    let config = || Config::new().path(dir.path());
+
+    let db = config().open().unwrap();
+    db.insert(b"k", b"v").unwrap();
+
+    match config().read_only(true).open() {
+        Err(Error::Io(e)) => assert!(
+            e.to_string().contains("already open in another process"),
+            "unexpected error: {}",
+            e
+        ),
+        other => panic!("expected a locking error, got {:?}", other),
+    }
+
+    // with the `testing` feature, opening a database that is
+    // already open for writing blocks until it is closed,
+    // instead of failing
+    let (tx, rx) = std::sync::mpsc::channel();
+    let opener = thread::spawn({
+        let config = config();
+        move || tx.send(config.open()).unwrap()
+    });
+    assert!(rx.recv_timeout(std::time::Duration::from_millis(200)).is_err());
+
+    drop(db);
+
+    let db = rx.recv().unwrap().unwrap();
+    assert_eq!(db.get(b"k").unwrap().unwrap(), b"v");
+    opener.join().unwrap();
 }
 
 #[test]
-fn tree_bug_08() {
-    // postmortem: failed to properly recover the state in the segment
-    // accountant that tracked the previously issued segment.
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![145]), 151),
-            Set(Key(vec![155]), 148),
-            Set(Key(vec![131]), 170),
-            Set(Key(vec![163]), 60),
-            Set(Key(vec![225]), 126),
-            Restart,
-            Set(Key(vec![64]), 237),
-            Set(Key(vec![102]), 205),
-            Restart,
-        ],
-        true,
-        false,
-        0,
-        0,
-    );
+fn flush_every_ms_makes_writes_stable() {
+    common::setup_logger();
+
+    let timeout = std::time::Duration::from_secs(10);
+
+    let db = Config::new().temporary(true).flush_every_ms(None).open().unwrap();
+    let before = db.context.pagecache.stable_lsn();
+    db.insert(b"k", b"v").unwrap();
+
+    // without the background flusher, nothing writes the
+    // buffer until we flush it ourselves
+    thread::sleep(std::time::Duration::from_millis(100));
+    assert_eq!(db.context.pagecache.stable_lsn(), before);
+    assert!(db.flush().unwrap() > 0);
+    assert!(db.context.pagecache.stable_lsn() > before);
+    assert_eq!(db.flush().unwrap(), 0);
+
+    let db =
+        Config::new().temporary(true).flush_every_ms(Some(10)).open().unwrap();
+    let before = db.context.pagecache.stable_lsn();
+    db.insert(b"k", b"v").unwrap();
+
+    let start = std::time::Instant::now();
+    while db.context.pagecache.stable_lsn() == before {
+        assert!(
+            start.elapsed() < timeout,
+            "the background flusher did not flush within {:?}",
+            timeout
+        );
+        thread::sleep(std::time::Duration::from_millis(1));
+    }
+    assert_eq!(db.flush().unwrap(), 0);
 }
 
 #[test]
-fn tree_bug_09() {
-    // postmortem: was failing to load existing snapshots on initialization.
-    // would encounter uninitialized segments at the log tip and overwrite
-    // the first segment (indexed by LSN of 0) in the segment accountant
-    // ordering, skipping over important updates.
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![189]), 36),
-            Set(Key(vec![254]), 194),
-            Set(Key(vec![132]), 50),
-            Set(Key(vec![91]), 221),
-            Set(Key(vec![126]), 6),
-            Set(Key(vec![199]), 183),
-            Set(Key(vec![71]), 125),
-            Scan(Key(vec![67]), 16),
-            Set(Key(vec![190]), 16),
-            Restart,
-        ],
-        true,
-        false,
-        0,
-        0,
-    );
+fn concurrent_durable_writes() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("concurrent_durable_writes");
+    let path = dir.path();
+    let config = || Config::new().path(path).flush_every_ms(None);
+
+    // every insert is flushed before the next one, so threads
+    // keep waiting on each others' writes to be made stable
+    const THREADS: u64 = 16;
+    const WRITES: u64 = 100;
+    {
+        let db = config().open().unwrap();
+        let barrier = Arc::new(Barrier::new(THREADS as usize));
+        let threads: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let db = db.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    for i in 0..WRITES {
+                        let key = (t * WRITES + i).to_be_bytes();
+                        db.insert(key, &key).unwrap();
+                        db.flush().unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    let db = config().open().unwrap();
+    assert_eq!(db.len() as u64, THREADS * WRITES);
+    for k in 0..THREADS * WRITES {
+        let key = k.to_be_bytes();
+        assert_eq!(db.get(key).unwrap(), Some(IVec::from(&key)));
+    }
+
+    drop(db);
 }
 
 #[test]
-fn tree_bug_10() {
-    // postmortem: after reusing a segment, but not completely writing a
-    // segment, we were hitting an old LSN and violating an assert, rather
-    // than just ending.
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![152]), 163),
-            Set(Key(vec![105]), 191),
-            Set(Key(vec![207]), 217),
-            Set(Key(vec![128]), 19),
-            Set(Key(vec![106]), 22),
-            Scan(Key(vec![20]), 24),
-            Set(Key(vec![14]), 150),
-            Set(Key(vec![80]), 43),
-            Set(Key(vec![174]), 134),
-            Set(Key(vec![20]), 150),
-            Set(Key(vec![13]), 171),
-            Restart,
-            Scan(Key(vec![240]), 25),
-            Scan(Key(vec![77]), 37),
-            Set(Key(vec![153]), 232),
-            Del(Key(vec![2])),
-            Set(Key(vec![227]), 169),
-            Get(Key(vec![232])),
-            Cas(Key(vec![247]), 151, 70),
-            Set(Key(vec![78]), 52),
-            Get(Key(vec![16])),
-            Del(Key(vec![78])),
-            Cas(Key(vec![201]), 93, 196),
-            Set(Key(vec![172]), 84),
-        ],
-        true,
-        false,
-        0,
-        0,
-    );
+fn open_with_deadline() {
+    use std::time::Duration;
+
+    common::setup_logger();
+
+    let dir = common::TestDir::new("open_with_deadline");
+    let path = dir.path();
+    let config = || Config::new().path(path).flush_every_ms(None);
+
+    let db = config().open().unwrap();
+    for i in 0_u64..1000 {
+        db.insert(i.to_be_bytes(), vec![0; 64]).unwrap();
+    }
+    db.flush().unwrap();
+    drop(db);
+
+    // giving up as soon as the deadline passes
+    let mut reports = 0;
+    match config().open_with_deadline(Duration::from_millis(0), |_| {
+        reports += 1;
+        false
+    }) {
+        Err(Error::Io(e)) => {
+            assert_eq!(e.kind(), std::io::ErrorKind::TimedOut)
+        }
+        other => panic!("expected a timeout, got {:?}", other),
+    }
+    assert_eq!(reports, 1);
+
+    // waiting as long as it takes
+    let mut last_lsn = 0;
+    let db = config()
+        .open_with_deadline(Duration::from_millis(1), |progress| {
+            assert!(progress.recovered_lsn >= last_lsn);
+            last_lsn = progress.recovered_lsn;
+            true
+        })
+        .unwrap();
+    assert_eq!(db.len(), 1000);
+    drop(db);
+
 }
 
 #[test]
-fn tree_bug_11() {
-    // postmortem: a stall was happening because LSNs and LogIds were being
-    // conflated in calls to make_stable. A higher LogId than any LSN was
-    // being created, then passed in.
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![38]), 148),
-            Set(Key(vec![176]), 175),
-            Set(Key(vec![82]), 88),
-            Set(Key(vec![164]), 85),
-            Set(Key(vec![139]), 74),
-            Set(Key(vec![73]), 23),
-            Cas(Key(vec![34]), 67, 151),
-            Set(Key(vec![115]), 133),
-            Set(Key(vec![249]), 138),
-            Restart,
-            Set(Key(vec![243]), 6),
-        ],
-        true,
-        false,
-        0,
-        0,
-    );
+fn reopen_in_same_process() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("reopen_in_same_process");
+    let path = dir.path();
+
+    // other databases stay busy the whole time
+    let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let others: Vec<_> = (0..4)
+        .map(|_| {
+            let done = done.clone();
+            thread::spawn(move || {
+                let db = Config::new()
+                    .temporary(true)
+                    .flush_every_ms(Some(1))
+                    .open()
+                    .unwrap();
+                let mut i = 0_u64;
+                while !done.load(std::sync::atomic::Ordering::SeqCst) {
+                    db.insert(i.to_be_bytes(), vec![0; 100]).unwrap();
+                    i += 1;
+                }
+            })
+        })
+        .collect();
+
+    for i in 0..50_u64 {
+        let db =
+            Config::new().path(path).flush_every_ms(Some(1)).open().unwrap();
+        let tree = db.open_tree(b"tree").unwrap();
+        tree.insert(i.to_be_bytes(), vec![1; 1000]).unwrap();
+        drop(tree.flush_async());
+        drop(tree);
+        drop(db);
+
+        // a read-only open fails instead of waiting if the
+        // lock is still held, so everything must have been
+        // released by the time the last handle was dropped
+        let db = Config::new().path(path).read_only(true).open().unwrap();
+        let tree = db.open_tree(b"tree").unwrap();
+        assert_eq!(tree.len() as u64, i + 1);
+    }
+
+    done.store(true, std::sync::atomic::Ordering::SeqCst);
+    for other in others {
+        other.join().unwrap();
+    }
+
 }
 
 #[test]
-fn tree_bug_12() {
-    // postmortem: was not checking that a log entry's LSN matches its position
-    // as part of detecting tears / partial rewrites.
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![118]), 156),
-            Set(Key(vec![8]), 63),
-            Set(Key(vec![165]), 110),
-            Set(Key(vec![219]), 108),
-            Set(Key(vec![91]), 61),
-            Set(Key(vec![18]), 98),
-            Scan(Key(vec![73]), 6),
-            Set(Key(vec![240]), 108),
-            Cas(Key(vec![71]), 28, 189),
-            Del(Key(vec![199])),
-            Restart,
-            Set(Key(vec![30]), 140),
-            Scan(Key(vec![118]), 13),
-            Get(Key(vec![180])),
-            Cas(Key(vec![115]), 151, 116),
-            Restart,
-            Set(Key(vec![31]), 95),
-            Cas(Key(vec![79]), 153, 225),
-            Set(Key(vec![34]), 161),
-            Get(Key(vec![213])),
-            Set(Key(vec![237]), 215),
-            Del(Key(vec![52])),
-            Set(Key(vec![56]), 78),
-            Scan(Key(vec![141]), 2),
-            Cas(Key(vec![228]), 114, 170),
-            Get(Key(vec![231])),
-            Get(Key(vec![223])),
-            Del(Key(vec![167])),
-            Restart,
-            Scan(Key(vec![240]), 31),
-            Del(Key(vec![54])),
-            Del(Key(vec![2])),
-            Set(Key(vec![117]), 165),
-            Set(Key(vec![223]), 50),
-            Scan(Key(vec![69]), 4),
-            Get(Key(vec![156])),
-            Set(Key(vec![214]), 72),
-        ],
-        true,
-        false,
-        0,
-        0,
+fn durability_levels() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("durability_levels");
+    let path = dir.path();
+    let config = || Config::new().path(path).flush_every_ms(None);
+
+    let db = config().open().unwrap();
+    let before = db.context.pagecache.stable_lsn();
+    db.insert_with_durability(b"buffered", b"", Durability::Buffered).unwrap();
+    assert_eq!(db.context.pagecache.stable_lsn(), before);
+
+    for (key, durability) in &[
+        (b"data_sync", Durability::DataSync),
+        (b"sync_all_", Durability::Sync),
+    ] {
+        let before = db.context.pagecache.stable_lsn();
+        db.insert_with_durability(key, b"", *durability).unwrap();
+        assert!(db.context.pagecache.stable_lsn() > before);
+        // nothing is left for a flush to write
+        assert_eq!(db.flush().unwrap(), 0);
+    }
+
+    let before = db.context.pagecache.stable_lsn();
+    assert_eq!(
+        db.remove_with_durability(b"buffered", Durability::Sync).unwrap(),
+        Some(IVec::from(b""))
     );
+    assert!(db.context.pagecache.stable_lsn() > before);
+    drop(db);
+
+    let db = config().open().unwrap();
+    assert_eq!(db.get(b"buffered").unwrap(), None);
+    assert!(db.contains_key(b"data_sync").unwrap());
+    assert!(db.contains_key(b"sync_all_").unwrap());
+
+    drop(db);
 }
 
 #[test]
-fn tree_bug_13() {
-    // postmortem: failed root hoists were being improperly recovered before the
-    // following free was done on their page, but we treated the written node as
-    // if it were a successful completed root hoist.
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![42]), 10),
-            Set(Key(vec![137]), 220),
-            Set(Key(vec![183]), 129),
-            Set(Key(vec![91]), 145),
-            Set(Key(vec![126]), 26),
-            Set(Key(vec![255]), 67),
-            Set(Key(vec![69]), 18),
-            Restart,
-            Set(Key(vec![24]), 92),
-            Set(Key(vec![193]), 17),
-            Set(Key(vec![3]), 143),
-            Cas(Key(vec![50]), 13, 84),
-            Restart,
-            Set(Key(vec![191]), 116),
-            Restart,
-            Del(Key(vec![165])),
-        ],
-        true,
-        false,
-        0,
-        0,
-    );
+#[cfg(all(target_os = "linux", not(feature = "io_uring")))]
+fn direct_io_log() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("direct_io_log");
+    let path = dir.path();
+    let config = |direct| {
+        Config::new()
+            .path(path)
+            .segment_size(64 * 1024)
+            .flush_every_ms(None)
+            .use_direct_io(direct)
+    };
+
+    for (round, &direct) in [true, true, false, true].iter().enumerate() {
+        let db = config(direct).open().unwrap();
+
+        for i in 0..round * 500 {
+            let key = (i as u64).to_be_bytes();
+            assert_eq!(db.get(key).unwrap(), Some(IVec::from(&key)));
+        }
+
+        for i in round * 500..(round + 1) * 500 {
+            let key = (i as u64).to_be_bytes();
+            db.insert(key, &key).unwrap();
+            if i % 50 == 0 {
+                db.flush().unwrap();
+                if direct {
+                    // every flush ends on a block boundary
+                    let stable = db.context.pagecache.stable_lsn();
+                    assert_eq!((stable + 1) % 4096, 0);
+                }
+            }
+        }
+
+        db.flush().unwrap();
+    }
+
 }
 
 #[test]
-fn tree_bug_14() {
-    // postmortem: after adding prefix compression, we were not
-    // handling re-inserts and deletions properly
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![107]), 234),
-            Set(Key(vec![7]), 245),
-            Set(Key(vec![40]), 77),
-            Set(Key(vec![171]), 244),
-            Set(Key(vec![173]), 16),
-            Set(Key(vec![171]), 176),
-            Scan(Key(vec![93]), 33),
-        ],
-        true,
-        false,
-        0,
-        0,
-    );
-}
+fn page_in_fragments() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("page_in_fragments");
+    let path = dir.path();
+    let config = || Config::new().path(path).cache_capacity(256);
+
+    let db = config().open().unwrap();
+    // overwriting keys appends links to the same
+    // few pages, so each page has several fragments
+    for round in 0_u64..8 {
+        for i in 0_u64..200 {
+            db.insert(i.to_be_bytes(), &round.to_be_bytes()).unwrap();
+        }
+    }
+    drop(db);
+
+    for _ in 0..2 {
+        let db = config().open().unwrap();
+        for i in 0_u64..200 {
+            assert_eq!(
+                db.get(i.to_be_bytes()).unwrap(),
+                Some(IVec::from(&7_u64.to_be_bytes()))
+            );
+        }
+    }
 
-#[test]
-fn tree_bug_15() {
-    // postmortem: was not sorting keys properly when binary searching for them
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![102]), 165),
-            Set(Key(vec![91]), 191),
-            Set(Key(vec![141]), 228),
-            Set(Key(vec![188]), 124),
-            Del(Key(vec![141])),
-            Scan(Key(vec![101]), 26),
-        ],
-        true,
-        false,
-        0,
-        0,
-    );
 }
 
 #[test]
-fn tree_bug_16() {
-    // postmortem: the test merge function was not properly adding numbers.
-    prop_tree_matches_btreemap(
-        vec![Merge(Key(vec![247]), 162), Scan(Key(vec![209]), 31)],
-        false,
-        false,
-        0,
-        0,
-    );
+fn concurrent_page_faults() {
+    common::setup_logger();
+
+    // with a tiny cache nearly every read has to page its
+    // node back in, and threads reading the same keys race
+    // to install the page they read from the log
+    let db = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .cache_capacity(256)
+        .open()
+        .unwrap();
+
+    for i in 0..N {
+        db.insert(kv(i), kv(i)).unwrap();
+    }
+    db.flush().unwrap();
+
+    let barrier = Arc::new(Barrier::new(N_THREADS));
+    let threads: Vec<_> = (0..N_THREADS)
+        .map(|t| {
+            let db = db.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                for i in 0..N {
+                    assert_eq!(db.get(kv(i)).unwrap(), Some(IVec::from(kv(i))));
+                    // writers link onto pages while they are
+                    // being paged in and out by the readers
+                    if t % 2 == 0 && i % N_THREADS == t {
+                        db.insert(kv(i), kv(i)).unwrap();
+                    }
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert_eq!(db.len(), N);
+    assert!(db.verify().unwrap().is_ok());
 }
 
 #[test]
-fn tree_bug_17() {
-    // postmortem: we were creating a copy of a node leaf during iteration
-    // before accidentally putting it into a PinnedValue, despite the
-    // fact that it was not actually part of the node's actual memory!
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![194, 215, 103, 0, 138, 11, 248, 131]), 70),
-            Scan(Key(vec![]), 30),
-        ],
-        false,
-        false,
-        0,
-        0,
-    );
+fn get_stale_within_bound() {
+    use std::time::Duration;
+
+    common::setup_logger();
+
+    let db = Config::new().temporary(true).open().unwrap();
+    let minute = Duration::from_secs(60);
+    let now = Duration::from_secs(0);
+
+    db.insert(b"a", b"1").unwrap();
+    assert_eq!(db.get_stale(b"a", minute).unwrap(), Some(IVec::from(b"1")));
+
+    // served from the copy taken by the first read
+    db.insert(b"a", b"2").unwrap();
+    db.insert(b"b", b"1").unwrap();
+    assert_eq!(db.get_stale(b"a", minute).unwrap(), Some(IVec::from(b"1")));
+    assert_eq!(db.get_stale(b"b", minute).unwrap(), None);
+
+    // a tighter bound takes a new copy
+    assert_eq!(db.get_stale(b"a", now).unwrap(), Some(IVec::from(b"2")));
+    assert_eq!(db.get_stale(b"b", minute).unwrap(), Some(IVec::from(b"1")));
+
+    db.remove(b"b").unwrap();
+    assert_eq!(db.get_stale(b"b", minute).unwrap(), Some(IVec::from(b"1")));
+    assert_eq!(db.get_stale(b"b", now).unwrap(), None);
 }
 
 #[test]
-fn tree_bug_18() {
-    // postmortem: when implementing get_gt and get_lt, there were some
-    // issues with getting order comparisons correct.
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![]), 19),
-            Set(Key(vec![78]), 98),
-            Set(Key(vec![255]), 224),
-            Set(Key(vec![]), 131),
-            Get(Key(vec![255])),
-            GetGt(Key(vec![89])),
-        ],
-        false,
-        false,
-        0,
-        0,
-    );
+fn drop_large_tree() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("drop_large_tree");
+    let path = dir.path();
+    // a small segment size splits the freed pages
+    // across many batches
+    let config =
+        || Config::new().path(path).segment_size(1024).overflow_threshold(64);
+
+    let db = config().open().unwrap();
+    let big = db.open_tree(b"big").unwrap();
+    for i in 0..5_000_u32 {
+        let value = if i % 5 == 0 { vec![7; 100] } else { vec![] };
+        big.insert(i.to_be_bytes(), value).unwrap();
+        db.insert(i.to_be_bytes(), b"kept").unwrap();
+    }
+    db.flush().unwrap();
+
+    assert!(db.drop_tree(b"big").unwrap());
+    db.flush().unwrap();
+    drop(big);
+    drop(db);
+
+    let db = config().open().unwrap();
+    assert_eq!(db.tree_names(), vec![IVec::from(b"__sled__default")]);
+    assert_eq!(db.len(), 5_000);
+
+    // the freed pages are reused by a new tree
+    let big = db.open_tree(b"big").unwrap();
+    assert!(big.is_empty());
+    for i in 0..5_000_u32 {
+        big.insert(i.to_be_bytes(), vec![8; 100]).unwrap();
+    }
+    db.flush().unwrap();
+    drop(big);
+    drop(db);
+
+    let db = config().open().unwrap();
+    let big = db.open_tree(b"big").unwrap();
+    assert_eq!(big.len(), 5_000);
+    assert!(big.iter().values().all(|value| value.unwrap() == vec![8; 100]));
+    assert_eq!(db.get(4_999_u32.to_be_bytes()).unwrap().unwrap(), b"kept");
+
+    drop(big);
+    drop(db);
 }
 
 #[test]
-fn tree_bug_19() {
-    // postmortem: we were not seeking properly to the next node
-    // when we hit a half-split child and were using get_lt
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![]), 138),
-            Set(Key(vec![68]), 113),
-            Set(Key(vec![155]), 73),
-            Set(Key(vec![50]), 220),
-            Set(Key(vec![]), 247),
-            GetLt(Key(vec![100])),
-        ],
-        false,
-        false,
-        0,
-        0,
+fn user_version_survives_restart() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("user_version_survives_restart");
+    let path = dir.path();
+
+    let db = Config::new().path(path).open().unwrap();
+    assert_eq!(db.get_user_version().unwrap(), 0);
+    db.set_user_version(7).unwrap();
+    db.open_tree(b"other").unwrap().insert(b"k", b"v").unwrap();
+    db.set_user_version(u32::max_value()).unwrap();
+    assert_eq!(db.get_user_version().unwrap(), u32::max_value());
+    drop(db);
+
+    let db = Config::new().path(path).open().unwrap();
+    assert_eq!(db.get_user_version().unwrap(), u32::max_value());
+    // trees created while the version was set are still there
+    assert_eq!(
+        db.open_tree(b"other").unwrap().get(b"k").unwrap().unwrap(),
+        b"v"
     );
+    drop(db);
+
+    let db = Config::new().path(path).read_only(true).open().unwrap();
+    assert_eq!(db.get_user_version().unwrap(), u32::max_value());
+    match db.set_user_version(8) {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected read-only error, got {:?}", other),
+    }
+    drop(db);
+
 }
 
 #[test]
-fn tree_bug_20() {
-    // postmortem: we were not seeking forward during get_gt
-    // if path_for_key reached a leaf that didn't include
-    // a key for our
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![]), 10),
-            Set(Key(vec![56]), 42),
-            Set(Key(vec![138]), 27),
-            Set(Key(vec![155]), 73),
-            Set(Key(vec![]), 251),
-            GetGt(Key(vec![94])),
-        ],
-        false,
-        false,
-        0,
-        0,
-    );
+fn in_memory_mode() {
+    common::setup_logger();
+
+    let config = Config::new().in_memory(true);
+    let db = config.open().unwrap();
+    let path = config.get_path();
+
+    for i in 0..100_u32 {
+        db.insert(i.to_be_bytes(), vec![0; 1024]).unwrap();
+    }
+    db.flush().unwrap();
+    for i in 0..100_u32 {
+        assert_eq!(db.get(i.to_be_bytes()).unwrap().unwrap().len(), 1024);
+    }
+
+    // nothing is written to disk until a value is too
+    // large for the log and has to be stored as a blob
+    assert!(!path.exists());
+    assert!(db.size_on_disk().unwrap() >= 100 * 1024);
+
+    db.insert(b"blob", vec![1; 1024 * 1024]).unwrap();
+    db.flush().unwrap();
+    assert_eq!(db.get(b"blob").unwrap().unwrap().len(), 1024 * 1024);
+    assert!(path.join("blobs").exists());
+    assert!(!path.join("db").exists());
+    assert!(!path.join("conf").exists());
+    assert!(std::fs::read_dir(&path).unwrap().all(|entry| {
+        !entry.unwrap().file_name().to_string_lossy().starts_with("snap.")
+    }));
+
+    drop(db);
+    drop(config);
+    assert!(!path.exists());
 }
 
 #[test]
-fn tree_bug_21() {
-    // postmortem: more split woes while implementing get_lt
-    // postmortem 2: failed to properly account for node hi key
-    // being empty in the view predecessor function
-    // postmortem 3: when rewriting Iter, failed to account for
-    // direction of iteration
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![176]), 163),
-            Set(Key(vec![]), 229),
-            Set(Key(vec![169]), 121),
-            Set(Key(vec![]), 58),
-            GetLt(Key(vec![176])),
-        ],
-        false,
-        false,
-        0,
-        0,
-    );
+fn dup_sort_mode() {
+    common::setup_logger();
+
+    let db = Config::new().temporary(true).open().unwrap();
+    let tree = db.open_tree("index").unwrap();
+
+    match tree.insert_dup(b"k", b"v") {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected dup-sort to be disabled, got {:?}", other),
+    }
+    assert!(tree.scan_dups(b"k").next().unwrap().is_err());
+
+    tree.set_dup_sort(true);
+
+    // keys that are prefixes of each other or contain zero
+    // bytes must not see each other's values
+    let keys: Vec<&[u8]> =
+        vec![b"", b"\0", b"\0\0", b"\0\xff", b"a", b"a\0", b"ab", b"b"];
+    let values: Vec<&[u8]> = vec![b"", b"\0", b"\0\0", b"x", b"xy", b"y"];
+
+    for key in &keys {
+        for value in values.iter().rev() {
+            assert!(tree.insert_dup(key, value).unwrap());
+            assert!(!tree.insert_dup(key, value).unwrap());
+        }
+    }
+
+    let expected: Vec<IVec> = values.iter().map(|v| IVec::from(*v)).collect();
+    for key in &keys {
+        let dups: Vec<IVec> =
+            tree.scan_dups(key).collect::<sled::Result<_>>().unwrap();
+        assert_eq!(dups, expected);
+
+        let mut rev: Vec<IVec> =
+            tree.scan_dups(key).rev().collect::<sled::Result<_>>().unwrap();
+        rev.reverse();
+        assert_eq!(rev, expected);
+    }
+    assert_eq!(tree.scan_dups(b"c").count(), 0);
+
+    assert!(tree.remove_dup(b"a", b"xy").unwrap());
+    assert!(!tree.remove_dup(b"a", b"xy").unwrap());
+    assert_eq!(tree.scan_dups(b"a").count(), values.len() - 1);
+    assert_eq!(tree.scan_dups(b"ab").count(), values.len());
 }
 
 #[test]
-fn tree_bug_22() {
-    // postmortem: inclusivity wasn't being properly flipped off after
-    // the first result during iteration
-    // postmortem 2: failed to properly check bounds while iterating
-    prop_tree_matches_btreemap(
-        vec![
-            Merge(Key(vec![]), 155),
-            Merge(Key(vec![56]), 251),
-            Scan(Key(vec![]), 2),
-        ],
-        false,
-        false,
-        0,
-        0,
+fn secondary_indexes() {
+    common::setup_logger();
+
+    fn keys(iter: IndexIter) -> Vec<IVec> {
+        iter.keys().collect::<sled::Result<_>>().unwrap()
+    }
+
+    // values are indexed by their first byte, and values
+    // starting with 0 are not indexed
+    fn first_byte(value: &[u8]) -> Option<Vec<u8>> {
+        match value.first() {
+            Some(0) | None => None,
+            Some(b) => Some(vec![*b]),
+        }
+    }
+
+    let db = Config::new().temporary(true).open().unwrap();
+    let primary = db.open_tree("primary").unwrap();
+    let index_tree = db.open_tree("index").unwrap();
+
+    // an index declared over existing data is filled from it
+    primary.insert(b"a", b"x1").unwrap();
+    primary.insert(b"b", b"\0").unwrap();
+    let index = primary.add_index(&index_tree, first_byte).unwrap();
+    assert_eq!(keys(index.get(b"x")), vec![IVec::from(b"a")]);
+
+    match primary.add_index(&index_tree, first_byte) {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected a duplicate index error, got {:?}", other),
+    }
+    assert!(primary.add_index(&primary, first_byte).is_err());
+
+    // every kind of write moves the index entries
+    primary.insert(b"b", b"x2").unwrap();
+    primary.insert(b"c", b"y1").unwrap();
+    assert_eq!(keys(index.get(b"x")), vec![IVec::from(b"a"), IVec::from(b"b")]);
+
+    primary.compare_and_swap(b"a", Some(b"x1"), Some(b"y2")).unwrap().unwrap();
+    assert_eq!(keys(index.get(b"x")), vec![IVec::from(b"b")]);
+
+    let mut batch = Batch::default();
+    batch.insert(b"d", b"z1");
+    batch.remove(b"c");
+    primary.apply_batch(batch).unwrap();
+    assert_eq!(keys(index.get(b"y")), vec![IVec::from(b"a")]);
+
+    let res: TransactionResult<()> = primary.transaction(|tx| {
+        tx.insert(b"e", b"z2")?;
+        tx.remove(b"b")?;
+        Ok(())
+    });
+    res.unwrap();
+    assert_eq!(index.get(b"x").count(), 0);
+
+    primary.set_merge_operator(|_k, _old: Option<&[u8]>, new: &[u8]| {
+        Some(new.to_vec())
+    });
+    primary.merge(b"f", b"x3").unwrap();
+    assert_eq!(keys(index.get(b"x")), vec![IVec::from(b"f")]);
+
+    // lookups by range and prefix are ordered by index key
+    let all: Vec<(IVec, IVec)> =
+        index.range::<&[u8], _>(..).collect::<sled::Result<_>>().unwrap();
+    let expected: Vec<(IVec, IVec)> = vec![
+        (b"f".into(), b"x3".into()),
+        (b"a".into(), b"y2".into()),
+        (b"d".into(), b"z1".into()),
+        (b"e".into(), b"z2".into()),
+    ];
+    assert_eq!(all, expected);
+    assert_eq!(
+        keys(index.range::<&[u8], _>(&b"x"[..]..&b"z"[..])),
+        vec![IVec::from(b"f"), IVec::from(b"a")]
+    );
+    assert_eq!(
+        keys(index.range::<&[u8], _>((
+            std::ops::Bound::Excluded(&b"x"[..]),
+            std::ops::Bound::Included(&b"z"[..]),
+        ))),
+        vec![IVec::from(b"a"), IVec::from(b"d"), IVec::from(b"e")]
     );
+    let last = index.scan_prefix(b"z").next_back().unwrap().unwrap();
+    assert_eq!(last, (IVec::from(b"e"), IVec::from(b"z2")));
+
+    // writes made while the index isn't declared are caught
+    // up with by a rebuild
+    index_tree.clear().unwrap();
+    assert_eq!(index.get(b"z").count(), 0);
+    index.rebuild().unwrap();
+    assert_eq!(keys(index.get(b"z")), vec![IVec::from(b"d"), IVec::from(b"e")]);
+
+    assert!(primary.bulk_load(vec![(b"g", b"x4".to_vec())]).is_err());
 }
 
 #[test]
-fn tree_bug_23() {
-    // postmortem: when rewriting CRC handling code, mis-sized the blob crc
-    prop_tree_matches_btreemap(
-        vec![Set(Key(vec![6; 5120]), 92), Restart, Scan(Key(vec![]), 35)],
-        false,
-        false,
-        0,
-        0,
-    );
+fn versioned_reads() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("versioned_reads");
+    let path = dir.path();
+    let config = || Config::new().path(path).flush_every_ms(None);
+
+    let db = config().open().unwrap();
+    let tree = db.open_tree("tree").unwrap();
+    let history = db.open_tree("history").unwrap();
+
+    match tree.get_at(b"k", 0) {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected versions to be disabled, got {:?}", other),
+    }
+
+    // values from before versions were kept are the first
+    tree.insert(b"a", b"a0").unwrap();
+    let before = db.checkpoint().unwrap();
+    tree.keep_versions(&history).unwrap();
+    assert!(tree.keep_versions(&history).is_err());
+    assert!(tree.get_at(b"a", before).is_err());
+
+    let mut times = vec![];
+    for i in 0..10_u8 {
+        tree.insert(b"a", vec![i]).unwrap();
+        if i % 3 == 0 {
+            tree.remove(b"b").unwrap();
+        } else {
+            let mut batch = Batch::default();
+            batch.insert(b"b", vec![i]);
+            tree.apply_batch(batch).unwrap();
+        }
+        times.push(db.checkpoint().unwrap());
+    }
+
+    let at = |key: &[u8], ts: i64| {
+        tree.get_at(key, ts).unwrap().map(|v| v.to_vec())
+    };
+    for (i, ts) in times.iter().enumerate() {
+        let i = i as u8;
+        assert_eq!(at(b"a", *ts), Some(vec![i]));
+        let b = if i % 3 == 0 { None } else { Some(vec![i]) };
+        assert_eq!(at(b"b", *ts), b);
+    }
+    assert_eq!(at(b"c", times[9]), None);
+
+    // a write after a read is detected by its version
+    let read_at = times[9];
+    assert!(tree.version_of(b"a").unwrap().unwrap() < read_at);
+    tree.insert(b"a", b"a10").unwrap();
+    assert!(tree.version_of(b"a").unwrap().unwrap() > read_at);
+    assert_eq!(tree.version_of(b"c").unwrap(), None);
+
+    // raising the watermark collects the versions that can
+    // no longer be read
+    let versions = history.len();
+    tree.set_version_watermark(times[5]).unwrap();
+    assert!(history.len() < versions);
+    assert!(tree.get_at(b"a", times[4]).is_err());
+    assert_eq!(at(b"a", times[5]), Some(vec![5]));
+    assert_eq!(at(b"a", times[9]), Some(vec![9]));
+
+    // the history and its watermark survive a restart
+    drop((tree, history));
+    drop(db);
+    let db = config().open().unwrap();
+    let tree = db.open_tree("tree").unwrap();
+    tree.keep_versions(&db.open_tree("history").unwrap()).unwrap();
+    assert!(tree.get_at(b"a", times[4]).is_err());
+    assert_eq!(tree.get_at(b"b", times[8]).unwrap(), Some(vec![8].into()));
+    assert_eq!(tree.get_at(b"b", times[9]).unwrap(), None);
+
+    tree.insert(b"b", b"b10").unwrap();
+    let now = db.checkpoint().unwrap();
+    assert_eq!(tree.get_at(b"b", now).unwrap(), Some(IVec::from(b"b10")));
+
+    drop(tree);
+    drop(db);
 }
 
 #[test]
-fn tree_bug_24() {
-    // postmortem: get_gt diverged with the Iter impl
-    prop_tree_matches_btreemap(
-        vec![
-            Merge(Key(vec![]), 193),
-            Del(Key(vec![])),
-            Del(Key(vec![])),
-            Set(Key(vec![]), 55),
-            Set(Key(vec![]), 212),
-            Merge(Key(vec![]), 236),
-            Del(Key(vec![])),
-            Set(Key(vec![]), 192),
-            Del(Key(vec![])),
-            Set(Key(vec![94]), 115),
-            Merge(Key(vec![62]), 34),
-            GetGt(Key(vec![])),
-        ],
-        false,
-        false,
-        0,
-        0,
-    );
+fn read_snapshots_see_a_frozen_view() {
+    use rand::{Rng, SeedableRng};
+    use std::collections::BTreeMap;
+
+    common::setup_logger();
+
+    type Model = BTreeMap<Vec<u8>, Vec<u8>>;
+
+    fn check(snapshot: &ReadSnapshot, model: &Model) {
+        fn items<I>(iter: I) -> Vec<(Vec<u8>, Vec<u8>)>
+        where
+            I: Iterator<Item = sled::Result<(IVec, IVec)>>,
+        {
+            iter.map(|r| {
+                let (k, v) = r.unwrap();
+                (k.to_vec(), v.to_vec())
+            })
+            .collect()
+        }
+
+        let expected: Vec<_> =
+            model.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+
+        assert_eq!(items(snapshot.iter()), expected);
+
+        let mut reverse = items(snapshot.iter().rev());
+        reverse.reverse();
+        assert_eq!(reverse, expected);
+
+        // alternating ends meet in the middle without
+        // skipping or repeating an item
+        let mut iter = snapshot.iter();
+        let (mut front, mut back) = (vec![], vec![]);
+        loop {
+            match iter.next() {
+                Some(kv) => front.push(kv.unwrap().0.to_vec()),
+                None => break,
+            }
+            match iter.next_back() {
+                Some(kv) => back.push(kv.unwrap().0.to_vec()),
+                None => break,
+            }
+        }
+        back.reverse();
+        front.extend(back);
+        let keys: Vec<Vec<u8>> = model.keys().cloned().collect();
+        assert_eq!(front, keys);
+
+        let lo = vec![16_u8];
+        let hi = vec![48_u8];
+        let expected_range: Vec<_> = model
+            .range(lo.clone()..hi.clone())
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        assert_eq!(items(snapshot.range(lo..hi)), expected_range);
+
+        for key in 0..64_u8 {
+            let got = snapshot.get([key]).unwrap().map(|v| v.to_vec());
+            assert_eq!(got.as_ref(), model.get(&vec![key]));
+        }
+    }
+
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(7);
+    let db = Config::new().temporary(true).open().unwrap();
+    let mut model = Model::new();
+    let mut snapshots: Vec<(ReadSnapshot, Model)> = vec![];
+
+    for round in 0..2000 {
+        let key = vec![rng.gen_range(0, 64_u8)];
+        match rng.gen_range(0, 10) {
+            0 => {
+                db.remove(&key).unwrap();
+                let _ = model.remove(&key);
+            }
+            1 => {
+                let mut batch = Batch::default();
+                for _ in 0..4 {
+                    let key = vec![rng.gen_range(0, 64_u8)];
+                    batch.insert(key.clone(), vec![round as u8]);
+                    let _ = model.insert(key, vec![round as u8]);
+                }
+                db.apply_batch(batch).unwrap();
+            }
+            2 => {
+                let new = vec![round as u8];
+                let old = model.get(&key).cloned();
+                db.compare_and_swap(&key, old, Some(new.clone()))
+                    .unwrap()
+                    .unwrap();
+                let _ = model.insert(key, new);
+            }
+            _ => {
+                db.insert(&key, vec![round as u8; 3]).unwrap();
+                let _ = model.insert(key, vec![round as u8; 3]);
+            }
+        }
+
+        if round % 100 == 0 {
+            snapshots.push((db.snapshot(), model.clone()));
+        }
+        if round % 250 == 0 {
+            for (snapshot, frozen) in &snapshots {
+                check(snapshot, frozen);
+            }
+        }
+    }
+
+    for (snapshot, frozen) in &snapshots {
+        check(snapshot, frozen);
+    }
+
+    // trees opened after the snapshot aren't in it, and
+    // prefix scans and loads are seen as of the snapshot
+    let snapshot = db.snapshot();
+    let loaded = db.open_tree("loaded").unwrap();
+    assert!(snapshot.open_tree("loaded").is_none());
+
+    let snapshot = db.snapshot();
+    loaded.bulk_load(vec![(b"a1", b"x"), (b"a2", b"y")]).unwrap();
+    let loaded_then = snapshot.open_tree("loaded").unwrap();
+    assert!(loaded_then.is_empty());
+    assert_eq!(loaded.scan_prefix(b"a").count(), 2);
+    assert_eq!(loaded_then.scan_prefix(b"a").count(), 0);
 }
 
 #[test]
-fn tree_bug_25() {
-    // postmortem: was not accounting for merges when traversing
-    // the frag chain and a Del was encountered
-    prop_tree_matches_btreemap(
-        vec![Del(Key(vec![])), Merge(Key(vec![]), 84), Get(Key(vec![]))],
-        false,
-        false,
-        0,
-        0,
-    );
+fn read_snapshot_scans_with_concurrent_writers() {
+    common::setup_logger();
+
+    let db = Config::new().temporary(true).open().unwrap();
+    for i in 0..N as u32 {
+        db.insert(i.to_be_bytes(), vec![0; 8]).unwrap();
+    }
+
+    let snapshot = db.snapshot();
+    let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+    let writers: Vec<_> = (0..4_u32)
+        .map(|t| {
+            let db = db.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let mut i = t;
+                while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                    let key = (i % (2 * N as u32)).to_be_bytes();
+                    if i % 3 == 0 {
+                        db.remove(key).unwrap();
+                    } else {
+                        db.insert(key, vec![1; 8]).unwrap();
+                    }
+                    i += 4;
+                }
+            })
+        })
+        .collect();
+
+    for _ in 0..5 {
+        let mut expected = 0_u32;
+        for kv_res in snapshot.iter() {
+            let (k, v) = kv_res.unwrap();
+            assert_eq!(&*k, &expected.to_be_bytes());
+            assert_eq!(&*v, &[0; 8]);
+            expected += 1;
+        }
+        assert_eq!(expected, N as u32);
+    }
+
+    stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    for writer in writers {
+        writer.join().unwrap();
+    }
 }
 
 #[test]
-fn tree_bug_26() {
-    // postmortem:
-    prop_tree_matches_btreemap(
-        vec![
-            Merge(Key(vec![]), 194),
-            Merge(Key(vec![62]), 114),
-            Merge(Key(vec![80]), 202),
-            Merge(Key(vec![]), 169),
-            Set(Key(vec![]), 197),
-            Del(Key(vec![])),
-            Del(Key(vec![])),
-            Set(Key(vec![]), 215),
-            Set(Key(vec![]), 164),
-            Merge(Key(vec![]), 150),
-            GetGt(Key(vec![])),
-            GetLt(Key(vec![80])),
-        ],
-        false,
-        false,
-        0,
-        0,
+fn write_once_keys() {
+    fn read_only<T>(key: &[u8]) -> sled::Result<T> {
+        Err(Error::ReadOnlyKey(key.into()))
+    }
+
+    common::setup_logger();
+
+    let db =
+        Config::new().temporary(true).overflow_threshold(64).open().unwrap();
+    let tree = db.open_tree("objects").unwrap();
+    tree.set_merge_operator(|_k, _old, new| Some(new.to_vec()));
+    tree.set_write_once(true);
+
+    assert_eq!(tree.insert(b"a", b"1").unwrap(), None);
+    assert_eq!(tree.insert(b"a", b"2"), read_only(b"a"));
+    assert_eq!(tree.insert(b"a", vec![2; 128]), read_only(b"a"));
+    assert_eq!(tree.remove(b"a"), read_only(b"a"));
+    assert_eq!(tree.merge(b"a", b"2"), read_only(b"a"));
+    assert_eq!(
+        tree.update_and_fetch(b"a", |_| Some(b"2".to_vec())),
+        read_only(b"a")
+    );
+    assert_eq!(
+        tree.compare_and_swap(b"a", Some(b"1"), Some(b"2")),
+        read_only(b"a")
+    );
+
+    // unique creation fails as usual when the key exists
+    let cas = tree.compare_and_swap(b"a", None as Option<&[u8]>, Some(b"2"));
+    assert!(cas.unwrap().is_err());
+    assert_eq!(
+        tree.compare_and_swap(b"b", None as Option<&[u8]>, Some(b"1")),
+        Ok(Ok(()))
+    );
+
+    // removing a missing key writes nothing
+    assert_eq!(tree.remove(b"c"), Ok(None));
+
+    // batches are rejected as a whole
+    let mut batch = Batch::default();
+    batch.insert(b"c", b"1");
+    batch.insert(b"b", b"2");
+    assert_eq!(tree.apply_batch(batch), read_only(b"b"));
+    assert_eq!(tree.get(b"c").unwrap(), None);
+
+    let res: TransactionResult<()> = tree.transaction(|tree| {
+        tree.insert(b"d", b"1")?;
+        tree.insert(b"d", b"2")?;
+        Ok(())
+    });
+    assert_eq!(
+        res,
+        Err(TransactionError::Storage(Error::ReadOnlyKey(b"d".into())))
     );
+    assert_eq!(tree.get(b"d").unwrap(), None);
+
+    assert_eq!(tree.get(b"a").unwrap(), Some(IVec::from(b"1")));
+    assert_eq!(tree.get(b"b").unwrap(), Some(IVec::from(b"1")));
+
+    tree.set_write_once(false);
+    assert_eq!(tree.insert(b"a", b"2").unwrap(), Some(IVec::from(b"1")));
+    assert_eq!(tree.remove(b"a").unwrap(), Some(IVec::from(b"2")));
 }
 
 #[test]
-fn tree_bug_27() {
-    // postmortem: was not accounting for the fact that deletions reduce the
-    // chances of being able to split successfully.
-    prop_tree_matches_btreemap(
-        vec![
-            Del(Key(vec![])),
-            Merge(
-                Key(vec![
-                    74, 117, 68, 37, 89, 16, 84, 130, 133, 78, 74, 59, 44, 109,
-                    34, 5, 36, 74, 131, 100, 79, 86, 87, 107, 87, 27, 1, 85,
-                    53, 112, 89, 75, 67, 78, 58, 121, 0, 105, 8, 117, 79, 40,
-                    94, 123, 83, 72, 78, 23, 23, 35, 50, 77, 59, 75, 54, 92,
-                    89, 12, 27, 48, 64, 21, 42, 97, 45, 28, 122, 13, 4, 32, 51,
-                    25, 26, 18, 65, 12, 54, 104, 106, 80, 75, 91, 111, 9, 5,
-                    130, 43, 40, 3, 72, 0, 58, 92, 64, 112, 97, 75, 130, 11,
-                    135, 19, 107, 40, 17, 25, 49, 48, 119, 82, 54, 35, 113, 91,
-                    68, 12, 118, 123, 62, 108, 88, 67, 43, 33, 119, 132, 124,
-                    1, 62, 133, 110, 25, 62, 129, 117, 117, 107, 123, 94, 127,
-                    80, 0, 116, 101, 9, 9, 54, 134, 70, 66, 79, 50, 124, 115,
-                    85, 42, 120, 24, 15, 81, 100, 72, 71, 40, 58, 22, 6, 34,
-                    54, 69, 110, 18, 74, 111, 80, 52, 90, 44, 4, 29, 84, 95,
-                    21, 25, 10, 10, 60, 18, 78, 23, 21, 114, 92, 96, 17, 127,
-                    53, 86, 2, 60, 104, 8, 132, 44, 115, 6, 25, 80, 46, 12, 20,
-                    44, 67, 136, 127, 50, 55, 70, 41, 90, 16, 10, 44, 32, 24,
-                    106, 13, 104,
-                ]),
-                219,
-            ),
-            Merge(Key(vec![]), 71),
-            Del(Key(vec![])),
-            Set(Key(vec![0]), 146),
-            Merge(Key(vec![13]), 155),
-            Merge(Key(vec![]), 14),
-            Del(Key(vec![])),
-            Set(Key(vec![]), 150),
-            Set(
-                Key(vec![
-                    13, 8, 3, 6, 9, 14, 3, 13, 7, 12, 13, 7, 13, 13, 1, 13, 5,
-                    4, 3, 2, 6, 16, 17, 10, 0, 16, 12, 0, 16, 1, 0, 15, 15, 4,
-                    1, 6, 9, 9, 11, 16, 7, 6, 10, 1, 11, 10, 4, 9, 9, 14, 4,
-                    12, 16, 10, 15, 2, 1, 8, 4,
-                ]),
-                247,
-            ),
-            Del(Key(vec![154])),
-            Del(Key(vec![])),
-            Del(Key(vec![
-                0, 24, 24, 31, 40, 23, 10, 30, 16, 41, 30, 23, 14, 25, 21, 19,
-                18, 7, 17, 41, 11, 5, 14, 42, 11, 22, 4, 8, 4, 38, 33, 31, 3,
-                30, 40, 22, 40, 39, 5, 40, 1, 41, 11, 26, 25, 33, 12, 38, 4,
-                35, 30, 42, 19, 26, 23, 22, 39, 18, 29, 4, 1, 24, 14, 38, 0,
-                36, 27, 11, 27, 34, 16, 15, 38, 0, 20, 37, 22, 31, 12, 26, 16,
-                4, 22, 25, 4, 34, 4, 33, 37, 28, 18, 4, 41, 15, 8, 16, 27, 3,
-                20, 26, 40, 31, 15, 15, 17, 15, 5, 13, 22, 37, 7, 13, 35, 14,
-                6, 28, 21, 26, 13, 35, 1, 10, 8, 34, 23, 27, 29, 8, 14, 42, 36,
-                31, 34, 12, 31, 24, 5, 8, 11, 36, 29, 24, 38, 8, 12, 18, 22,
-                36, 21, 28, 11, 24, 0, 41, 37, 39, 42, 25, 13, 41, 27, 8, 24,
-                22, 30, 17, 2, 4, 20, 33, 5, 24, 33, 6, 29, 5, 0, 17, 9, 20,
-                26, 15, 23, 22, 16, 23, 16, 1, 20, 0, 28, 16, 34, 30, 19, 5,
-                36, 40, 28, 6, 39,
-            ])),
-            Merge(Key(vec![]), 50),
-        ],
-        false,
-        false,
-        0,
-        0,
-    );
+fn content_addressed_blobs() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("content_addressed_blobs");
+    let path = dir.path();
+    let config = || Config::new().path(path).overflow_threshold(64);
+
+    let big = vec![1; 1024];
+    let (small_hash, big_hash) = {
+        let db = config().open().unwrap();
+
+        // looking blobs up doesn't create their tree
+        assert_eq!(db.get_blob(&[0; 32]).unwrap(), None);
+        assert!(!db.release_blob(&[0; 32]).unwrap());
+        assert_eq!(db.tree_names(), vec![IVec::from(b"__sled__default")]);
+
+        let small_hash = db.put_blob(b"small").unwrap();
+        let big_hash = db.put_blob(&big).unwrap();
+        assert_ne!(small_hash, big_hash);
+        assert_eq!(db.put_blob(&big).unwrap(), big_hash);
+        assert_eq!(db.put_blob(&big).unwrap(), big_hash);
+
+        // contents and reference counts of the two blobs
+        assert_eq!(db.open_tree("__sled__blobs").unwrap().len(), 4);
+
+        (small_hash, big_hash)
+    };
+
+    let db = config().open().unwrap();
+    assert_eq!(db.get_blob(&small_hash).unwrap(), Some(IVec::from(b"small")));
+    assert_eq!(db.get_blob(&big_hash).unwrap(), Some(IVec::from(big.clone())));
+
+    assert!(db.release_blob(&small_hash).unwrap());
+    assert_eq!(db.get_blob(&small_hash).unwrap(), None);
+    assert!(!db.release_blob(&small_hash).unwrap());
+
+    for _ in 0..2 {
+        assert!(db.release_blob(&big_hash).unwrap());
+        assert_eq!(
+            db.get_blob(&big_hash).unwrap(),
+            Some(IVec::from(big.clone()))
+        );
+    }
+    assert!(db.release_blob(&big_hash).unwrap());
+    assert_eq!(db.get_blob(&big_hash).unwrap(), None);
+    assert!(db.open_tree("__sled__blobs").unwrap().is_empty());
+    assert!(db.drop_tree(b"__sled__blobs").is_err());
+
+    drop(db);
 }
 
 #[test]
-fn tree_bug_28() {
-    // postmortem:
-    prop_tree_matches_btreemap(
-        vec![
-            Del(Key(vec![])),
-            Set(Key(vec![]), 65),
-            Del(Key(vec![])),
-            Del(Key(vec![])),
-            Merge(Key(vec![]), 50),
-            Merge(Key(vec![]), 2),
-            Del(Key(vec![197])),
-            Merge(Key(vec![5]), 146),
-            Set(Key(vec![222]), 224),
-            Merge(Key(vec![149]), 60),
-            Scan(Key(vec![178]), 18),
-        ],
-        false,
-        false,
-        0,
-        0,
-    );
+fn consolidation_policies() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("consolidation_policies");
+    let path = dir.path();
+
+    for policy in vec![
+        ConsolidationPolicy::Fixed(2),
+        ConsolidationPolicy::Fixed(16),
+        // consolidates the hot half of the keys early
+        ConsolidationPolicy::Adaptive {
+            min_chain: 4,
+            max_chain: 16,
+            max_bytes: 64 * 1024,
+            hot_reads: 10,
+        },
+        // consolidates on size alone
+        ConsolidationPolicy::Adaptive {
+            min_chain: 2,
+            max_chain: 16,
+            max_bytes: 100,
+            hot_reads: u32::max_value(),
+        },
+    ] {
+        let _ = std::fs::remove_dir_all(path);
+        let config = || {
+            Config::new()
+                .path(path)
+                .cache_capacity(256)
+                .max_leaf_items(64)
+                .min_leaf_items(16)
+                .consolidation_policy(policy)
+        };
+
+        {
+            let db = config().open().unwrap();
+            for i in 0..1000_u32 {
+                db.insert(i.to_be_bytes(), &i.to_le_bytes()).unwrap();
+                if i % 2 == 0 {
+                    assert!(db.get((i / 2).to_be_bytes()).unwrap().is_some());
+                }
+            }
+            for i in (0..1000_u32).step_by(3) {
+                db.remove(i.to_be_bytes()).unwrap();
+            }
+        }
+
+        let db = config().open().unwrap();
+        for i in 0..1000_u32 {
+            let expected = if i % 3 == 0 {
+                None
+            } else {
+                Some(IVec::from(&i.to_le_bytes()))
+            };
+            assert_eq!(
+                db.get(i.to_be_bytes()).unwrap(),
+                expected,
+                "{:?}",
+                policy
+            );
+        }
+        assert!(db.verify().unwrap().is_ok());
+    }
+
+}
+
+#[derive(Default)]
+struct Concatenate {
+    recovered: Arc<std::sync::Mutex<Vec<(u64, Vec<u8>)>>>,
+}
+
+impl Materializer for Concatenate {
+    fn merge(&self, page: &[u8], frags: &[&[u8]]) -> Vec<u8> {
+        let mut ret = page.to_vec();
+        for frag in frags {
+            ret.extend_from_slice(frag);
+        }
+        ret
+    }
+
+    fn recover(&self, pid: u64, page: &[u8]) {
+        self.recovered.lock().unwrap().push((pid, page.to_vec()));
+    }
 }
 
 #[test]
-fn tree_bug_29() {
-    // postmortem: tree merge and split thresholds caused an infinite
-    // loop while performing updates
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![]), 142),
-            Merge(
-                Key(vec![
-                    45, 47, 6, 67, 16, 12, 62, 35, 69, 80, 49, 61, 29, 82, 9,
-                    47, 25, 78, 47, 64, 29, 74, 45, 0, 37, 44, 21, 82, 55, 44,
-                    31, 60, 86, 18, 45, 67, 55, 21, 35, 46, 25, 51, 5, 32, 33,
-                    36, 1, 81, 28, 28, 79, 76, 80, 89, 80, 62, 8, 85, 50, 15,
-                    4, 11, 76, 72, 73, 47, 30, 50, 85, 67, 84, 13, 82, 84, 78,
-                    70, 42, 83, 8, 7, 50, 77, 85, 37, 47, 82, 86, 46, 30, 27,
-                    5, 39, 70, 26, 59, 16, 6, 34, 56, 40, 40, 67, 16, 61, 63,
-                    56, 64, 31, 15, 81, 84, 19, 61, 66, 3, 7, 40, 56, 13, 40,
-                    64, 50, 88, 47, 88, 50, 63, 65, 79, 62, 1, 44, 59, 27, 12,
-                    60, 3, 36, 89, 45, 18, 4, 68, 48, 61, 30, 48, 26, 84, 49,
-                    3, 74, 51, 53, 30, 57, 50, 35, 74, 59, 30, 73, 19, 30, 82,
-                    78, 3, 5, 62, 17, 48, 29, 67, 52, 45, 61, 74, 52, 29, 61,
+fn materialized_pages() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("materialized_pages");
+    let path = dir.path();
+    let config = || Config::new().path(path).cache_capacity(256);
+
+    let mut expected = vec![];
+    let pids: Vec<u64> = {
+        let db = config().materializer(Concatenate::default()).open().unwrap();
+
+        let pids: Vec<u64> =
+            (0..10_u8).map(|i| db.allocate_page(vec![i]).unwrap()).collect();
+
+        // link enough fragments to consolidate each page a few
+        // times, while the tiny cache pages them out in between
+        for (i, pid) in pids.iter().enumerate() {
+            let mut page = vec![i as u8];
+            for j in 0..20_u8 {
+                db.link_page(*pid, vec![j]).unwrap();
+                page.push(j);
+            }
+            expected.push(page);
+        }
+
+        for (pid, page) in pids.iter().zip(&expected) {
+            assert_eq!(db.get_page(*pid).unwrap(), Some(IVec::from(&**page)));
+        }
+
+        assert!(db.verify().unwrap().is_ok());
+
+        pids
+    };
+
+    // the pages can't be read back in without a materializer
+    match config().open() {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected Unsupported, got {:?}", other.map(|_| ())),
+    }
+
+    let materializer = Concatenate::default();
+    let recovered = materializer.recovered.clone();
+    let db = config().materializer(materializer).open().unwrap();
+
+    let mut recovered = recovered.lock().unwrap().clone();
+    recovered.sort();
+    let mut expected_recovered: Vec<(u64, Vec<u8>)> =
+        pids.iter().cloned().zip(expected.iter().cloned()).collect();
+    expected_recovered.sort();
+    assert_eq!(recovered, expected_recovered);
+
+    // pages are not trees, and trees are not pages
+    assert!(db.drop_tree(b"__sled__pages").is_err());
+    assert!(db.link_page(u64::max_value(), vec![0]).is_err());
+
+    assert!(db.free_page(pids[0]).unwrap());
+    assert!(!db.free_page(pids[0]).unwrap());
+    assert_eq!(db.get_page(pids[0]).unwrap(), None);
+    assert!(db.link_page(pids[0], vec![0]).is_err());
+    assert_eq!(db.get_page(pids[1]).unwrap(), Some(IVec::from(&*expected[1])));
+
+    assert!(db.verify().unwrap().is_ok());
+
+    drop(db);
+}
+
+#[test]
+fn freed_page_ids_are_reused() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("freed_page_ids_are_reused");
+    let path = dir.path();
+    let config = || {
+        Config::new()
+            .path(path)
+            .flush_every_ms(None)
+            .materializer(Concatenate::default())
+    };
+
+    // each page holds its own id, so that a page whose id was
+    // handed out twice would not read back correctly
+    let allocate = |db: &sled::Db| {
+        let pid = db.allocate_page(vec![]).unwrap();
+        db.link_page(pid, pid.to_be_bytes().to_vec()).unwrap();
+        pid
+    };
+
+    let (mut live, mut freed) = {
+        let db = config().open().unwrap();
+        let pids: Vec<u64> = (0..20).map(|_| allocate(&db)).collect();
+        let (freed, live): (Vec<u64>, Vec<u64>) =
+            pids.into_iter().partition(|pid| pid % 2 == 0);
+        for pid in &freed {
+            assert!(db.free_page(*pid).unwrap());
+        }
+        db.flush().unwrap();
+        (live, freed)
+    };
+
+    // every restart happens between the frees and the reuse
+    // of their ids. the ids also go to the nodes of the tree
+    // that records the pages, which may take a few of them.
+    for _ in 0..5 {
+        let db = config().open().unwrap();
+
+        let reused: Vec<u64> =
+            (0..freed.len()).map(|_| allocate(&db)).collect();
+        assert!(
+            reused.iter().filter(|pid| freed.contains(pid)).count()
+                >= freed.len() / 2
+        );
+
+        for pid in live.iter().chain(&reused) {
+            assert_eq!(
+                db.get_page(*pid).unwrap(),
+                Some(IVec::from(&pid.to_be_bytes())),
+            );
+        }
+
+        for pid in &live {
+            assert!(db.free_page(*pid).unwrap());
+        }
+        db.flush().unwrap();
+        assert!(db.verify().unwrap().is_ok());
+
+        freed = live;
+        live = reused;
+    }
+
+    let db = config().open().unwrap();
+    for pid in &live {
+        assert_eq!(
+            db.get_page(*pid).unwrap(),
+            Some(IVec::from(&pid.to_be_bytes())),
+        );
+    }
+
+    drop(db);
+}
+
+#[test]
+fn recovery_stops_at_torn_write() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("recovery_stops_at_torn_write");
+    let path = dir.path();
+    let config = || Config::new().path(path).flush_every_ms(None);
+
+    {
+        let db = config().open().unwrap();
+        for i in 0..10_u8 {
+            db.insert(&[i], &[i]).unwrap();
+        }
+        db.flush().unwrap();
+        db.insert(b"torn", vec![7; 100]).unwrap();
+        db.flush().unwrap();
+    }
+
+    // recover from the log alone, and tear the last insert
+    // by flipping a byte in the middle of its value, as if
+    // power was lost while writing it
+    for entry in std::fs::read_dir(path).unwrap() {
+        let entry = entry.unwrap().path();
+        if entry.file_name().unwrap().to_string_lossy().starts_with("snap.") {
+            std::fs::remove_file(entry).unwrap();
+        }
+    }
+    let file = dir.file("db");
+    let mut bytes = std::fs::read(&file).unwrap();
+    let value_start =
+        bytes.windows(100).rposition(|w| w == &[7; 100][..]).unwrap();
+    bytes[value_start + 50] ^= 0xFF;
+    std::fs::write(&file, &bytes).unwrap();
+
+    {
+        let db = config().open().unwrap();
+        for i in 0..10_u8 {
+            assert_eq!(db.get(&[i]).unwrap(), Some(IVec::from(&[i])));
+        }
+        assert_eq!(db.get(b"torn").unwrap(), None);
+
+        // new writes replace the torn tail of the log
+        db.insert(b"after", b"recovery").unwrap();
+        db.flush().unwrap();
+    }
+
+    let db = config().open().unwrap();
+    assert_eq!(db.len(), 11);
+    assert_eq!(db.get(b"after").unwrap(), Some(IVec::from(b"recovery")));
+    assert_eq!(db.get(b"torn").unwrap(), None);
+
+    drop(db);
+}
+
+#[test]
+fn changes_between_checkpoints() {
+    common::setup_logger();
+
+    let config = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .segment_size(1024 * 1024)
+        .overflow_threshold(64);
+
+    type Items = std::collections::BTreeMap<(IVec, IVec), IVec>;
+
+    fn items(db: &Db) -> Items {
+        let mut items = Items::new();
+        for name in db.tree_names() {
+            let tree = db.open_tree(&name).unwrap();
+            for kv_res in tree.iter() {
+                let (k, v) = kv_res.unwrap();
+                items.insert((name.clone(), k), v);
+            }
+        }
+        items
+    }
+
+    fn expected_changes(before: &Items, after: &Items) -> Vec<Change> {
+        let keys: std::collections::BTreeSet<_> =
+            before.keys().chain(after.keys()).collect();
+        keys.into_iter()
+            .filter(|k| before.get(k) != after.get(k))
+            .map(|(tree, key)| Change {
+                tree: tree.clone(),
+                key: key.clone(),
+                before: before.get(&(tree.clone(), key.clone())).cloned(),
+                after: after.get(&(tree.clone(), key.clone())).cloned(),
+            })
+            .collect()
+    }
+
+    let db = config.open().unwrap();
+    let empty = db.checkpoint().unwrap();
+    let items_at_empty = items(&db);
+
+    let dropped = db.open_tree("dropped").unwrap();
+    for i in 0..N {
+        db.insert(kv(i), vec![i as u8; i % 100]).unwrap();
+        dropped.insert(kv(i), kv(i)).unwrap();
+    }
+    let first = db.checkpoint().unwrap();
+    let items_at_first = items(&db);
+
+    for i in (0..N).step_by(3) {
+        db.remove(kv(i)).unwrap();
+    }
+    drop(dropped);
+    drop(db);
+
+    let db = config.open().unwrap();
+    db.drop_tree(b"dropped").unwrap();
+    let t = db.open_tree("created").unwrap();
+    for i in (0..N).step_by(2) {
+        db.insert(kv(i), vec![i as u8; 200]).unwrap();
+        t.insert(kv(i), vec![]).unwrap();
+    }
+    // a temporary change that isn't visible at either checkpoint
+    db.insert(b"temporary", b"").unwrap();
+    db.remove(b"temporary").unwrap();
+    let second = db.checkpoint().unwrap();
+    let items_at_second = items(&db);
+
+    assert_eq!(
+        db.changes_between(empty, first).unwrap(),
+        expected_changes(&items_at_empty, &items_at_first)
+    );
+    assert_eq!(
+        db.changes_between(first, second).unwrap(),
+        expected_changes(&items_at_first, &items_at_second)
+    );
+    assert_eq!(
+        db.changes_between(empty, second).unwrap(),
+        expected_changes(&items_at_empty, &items_at_second)
+    );
+    assert_eq!(db.changes_between(second, second).unwrap(), vec![]);
+
+    match db.changes_between(second, first) {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected lsns out of order to fail, got {:?}", other),
+    }
+}
+
+#[test]
+fn log_entries_filtered_by_lsn_and_page() {
+    common::setup_logger();
+
+    let db = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .segment_size(4096)
+        .open()
+        .unwrap();
+
+    for i in 0..N {
+        db.insert(kv(i), kv(i)).unwrap();
+    }
+    let first = db.checkpoint().unwrap();
+
+    for i in 0..N {
+        db.insert(kv(i), vec![]).unwrap();
+    }
+    db.remove(kv(0)).unwrap();
+    let second = db.checkpoint().unwrap();
+
+    // written after the second checkpoint, so never yielded
+    db.insert(kv(0), vec![]).unwrap();
+
+    let all: Vec<LogEntry> =
+        db.log_entries(..).collect::<Result<_>>().unwrap();
+    assert!(!all.is_empty());
+    assert!(all.windows(2).all(|w| w[0].lsn <= w[1].lsn));
+    assert!(all.iter().all(|e| e.lsn <= second));
+    assert!(
+        all.iter()
+            .all(|e| (e.kind == LogEntryKind::Free) == e.payload.is_empty())
+    );
+
+    let between: Vec<LogEntry> =
+        db.log_entries(first + 1..=second).collect::<Result<_>>().unwrap();
+    let expected: Vec<LogEntry> =
+        all.iter().filter(|e| e.lsn > first).cloned().collect();
+    assert!(!between.is_empty());
+    assert_eq!(between, expected);
+
+    let pid = between[0].pid;
+    let page: Vec<LogEntry> =
+        db.log_entries(..).page(pid).collect::<Result<_>>().unwrap();
+    let expected: Vec<LogEntry> =
+        all.iter().filter(|e| e.pid == pid).cloned().collect();
+    assert_eq!(page, expected);
+}
+
+#[test]
+fn backup_to_while_writing() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("backup_to_while_writing");
+    let path = dir.path();
+
+    let db = Config::new()
+        .temporary(true)
+        .segment_size(4096)
+        .flush_every_ms(Some(1))
+        .open()
+        .unwrap();
+
+    db.insert(b"big", vec![7; 16 * 1024]).unwrap();
+    for i in 0..N {
+        db.insert(kv(i), vec![0]).unwrap();
+    }
+
+    // every round overwrites the keys in order, so any
+    // consistent cut sees a prefix of them one round ahead
+    let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let writer = thread::spawn({
+        let db = db.clone();
+        let stop = stop.clone();
+        move || {
+            let mut round = 1_u8;
+            while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                for i in 0..N {
+                    db.insert(kv(i), vec![round]).unwrap();
+                }
+                round = round.wrapping_add(1);
+            }
+        }
+    });
+
+    thread::sleep(std::time::Duration::from_millis(50));
+    let cut = db.backup_to(path).unwrap();
+    assert!(cut <= db.checkpoint().unwrap());
+
+    match db.backup_to(path) {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected an existing backup to be kept, got {:?}", other),
+    }
+
+    stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    writer.join().unwrap();
+    drop(db);
+
+    let backup =
+        Config::new().path(path).segment_size(4096).open().unwrap();
+    assert_eq!(backup.get(b"big").unwrap().unwrap().len(), 16 * 1024);
+
+    let first = backup.get(kv(0)).unwrap().unwrap()[0];
+    let mut behind = false;
+    for i in 0..N {
+        let round = backup.get(kv(i)).unwrap().unwrap()[0];
+        if round == first {
+            assert!(!behind, "key {} is out of order", i);
+        } else {
+            assert_eq!(round, first.wrapping_sub(1), "key {}", i);
+            behind = true;
+        }
+    }
+
+    drop(backup);
+}
+
+#[test]
+fn export_and_import_dump() {
+    common::setup_logger();
+
+    let old = Config::new().temporary(true).open().unwrap();
+    let other = old.open_tree(b"other").unwrap();
+    for i in 0..N {
+        old.insert(kv(i), kv(i)).unwrap();
+        other.insert(kv(i), vec![1; i]).unwrap();
+    }
+
+    let mut dump = vec![];
+    let exported = old.export_to(&mut dump).unwrap();
+    assert_eq!(exported, 2 * N as u64);
+
+    let new = Config::new().temporary(true).open().unwrap();
+    assert_eq!(new.import_from(&dump[..]).unwrap(), exported);
+    assert_eq!(old.checksum().unwrap(), new.checksum().unwrap());
+
+    // importing the same keys again would overwrite them
+    match new.import_from(&dump[..]) {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected Unsupported, got {:?}", other),
+    }
+
+    let is_rejected = |bytes: &[u8]| {
+        let db = Config::new().temporary(true).open().unwrap();
+        match db.import_from(bytes) {
+            Err(Error::Unsupported(_)) => true,
+            other => panic!("expected Unsupported, got {:?}", other),
+        }
+    };
+
+    let mut flipped = dump.clone();
+    let middle = flipped.len() / 2;
+    flipped[middle] ^= 1;
+    assert!(is_rejected(&flipped));
+
+    assert!(is_rejected(&dump[..dump.len() - 1]));
+    assert!(is_rejected(&dump[..middle]));
+
+    let mut future = dump.clone();
+    future[8] = 2;
+    assert!(is_rejected(&future));
+}
+
+#[test]
+fn recover_until_rolls_back() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("recover_until_rolls_back");
+    let path = dir.path();
+    let config = || Config::new().path(path).flush_every_ms(None);
+
+    let db = config().open().unwrap();
+    for i in 0..N {
+        db.insert(kv(i), kv(i)).unwrap();
+    }
+    let good = db.checkpoint().unwrap();
+    let checksum = db.checksum().unwrap();
+
+    // the mistake that is rolled back
+    for i in 0..N {
+        db.remove(kv(i)).unwrap();
+    }
+    db.open_tree(b"mistake").unwrap().insert(b"k", b"v").unwrap();
+    db.flush().unwrap();
+    drop(db);
+
+    // the snapshot written on this restart is past `good`
+    let db = config().open().unwrap();
+    assert!(db.is_empty());
+    drop(db);
+
+    let db = config().recover_until(Some(good)).open().unwrap();
+    assert_eq!(db.checksum().unwrap(), checksum);
+    assert!(!db.tree_names().contains(&IVec::from(b"mistake")));
+    db.insert(b"after", b"rollback").unwrap();
+    db.flush().unwrap();
+    drop(db);
+
+    // the rolled back writes stay gone without recover_until
+    let db = config().open().unwrap();
+    assert_eq!(db.len(), N + 1);
+    assert_eq!(db.get(kv(N - 1)).unwrap().unwrap(), kv(N - 1));
+    assert_eq!(db.get(b"after").unwrap().unwrap(), b"rollback");
+    assert!(!db.tree_names().contains(&IVec::from(b"mistake")));
+    drop(db);
+
+    std::fs::remove_dir_all(path).unwrap();
+
+    // once the segments holding the state at an lsn have
+    // been reused, it can't be recovered to
+    let config = || {
+        Config::new()
+            .path(path)
+            .segment_size(4096)
+            .flush_every_ms(None)
+    };
+
+    let db = config().open().unwrap();
+    for i in 0..N {
+        db.insert(kv(i), vec![0; 64]).unwrap();
+    }
+    let reused = db.checkpoint().unwrap();
+    for round in 1..10 {
+        for i in 0..N {
+            db.insert(kv(i), vec![round; 64]).unwrap();
+        }
+        db.flush().unwrap();
+    }
+    drop(db);
+
+    match config().recover_until(Some(reused)).open() {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected Unsupported, got {:?}", other),
+    }
+
+    // and nothing was changed by trying
+    let db = config().open().unwrap();
+    assert_eq!(db.get(kv(0)).unwrap().unwrap(), vec![9; 64]);
+    drop(db);
+
+}
+
+#[test]
+fn periodic_snapshots() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("periodic_snapshots");
+    let path = dir.path();
+    let config = || {
+        Config::new().path(path).flush_every_ms(Some(1)).snapshot_after_ops(10)
+    };
+
+    let snapshots = || -> Vec<std::path::PathBuf> {
+        let mut snapshots: Vec<_> = std::fs::read_dir(path)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|entry| {
+                let name = entry.file_name().unwrap().to_string_lossy();
+                name.starts_with("snap.") && !name.ends_with(".generating")
+            })
+            .collect();
+        snapshots.sort();
+        snapshots
+    };
+
+    let db = config().open().unwrap();
+    let first = snapshots();
+
+    for i in 0..N {
+        db.insert(kv(i), kv(i)).unwrap();
+    }
+
+    // the flush thread replaces the snapshot written by
+    // recovery with a newer one
+    let start = std::time::Instant::now();
+    while snapshots() == first {
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+
+    for i in 0..N {
+        db.insert(kv(i), kv(i + 1)).unwrap();
+    }
+    let checksum = db.checksum().unwrap();
+    drop(db);
+
+    // only the newest snapshot is kept around
+    let db = config().open().unwrap();
+    assert_eq!(db.checksum().unwrap(), checksum);
+    drop(db);
+    assert_eq!(snapshots().len(), 1);
+
+    // a corrupt snapshot falls back to a full scan of the log
+    let newest = snapshots().pop().unwrap();
+    let mut bytes = std::fs::read(&newest).unwrap();
+    bytes[0] ^= 0xFF;
+    std::fs::write(&newest, bytes).unwrap();
+
+    let db = config().open().unwrap();
+    assert_eq!(db.checksum().unwrap(), checksum);
+    assert!(db.verify().unwrap().is_ok());
+    drop(db);
+
+}
+
+#[test]
+fn changes_between_reclaimed_segments() {
+    common::setup_logger();
+
+    let db = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .segment_size(256)
+        .open()
+        .unwrap();
+
+    let from = db.checkpoint().unwrap();
+    for i in 0..N * 10 {
+        db.insert(kv(i % 10), kv(i)).unwrap();
+    }
+    db.flush().unwrap();
+    assert!(db.context.pagecache.attempt_gc().is_ok());
+    let to = db.checkpoint().unwrap();
+
+    match db.changes_between(from, to) {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!(
+            "expected reclaimed segments to be unsupported, got {:?}",
+            other
+        ),
+    }
+}
+
+#[test]
+fn create_tree() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("create_exclusive");
+    let path = dir.path();
+
+    {
+        let config = Config::new().create_new(true).path(path);
+        config.open().unwrap();
+    }
+
+    let config = Config::new().create_new(true).path(path);
+    config.open().unwrap_err();
+}
+
+#[test]
+fn tree_import_export() -> Result<()> {
+    common::setup_logger();
+
+    let config_1 = Config::new().temporary(true);
+    let config_2 = Config::new().temporary(true);
+
+    let db = config_1.open()?;
+    for db_id in 0..N_THREADS {
+        let tree_id = format!("tree_{}", db_id);
+        let tree = db.open_tree(tree_id.as_bytes())?;
+        for i in 0..N_THREADS {
+            let k = kv(i);
+            tree.insert(&k, k.clone()).unwrap();
+        }
+    }
+
+    let checksum_a = db.checksum().unwrap();
+
+    drop(db);
+
+    let exporter = config_1.open()?;
+    let importer = config_2.open()?;
+
+    let export = exporter.export();
+    importer.import(export);
+
+    drop(exporter);
+    drop(config_1);
+    drop(importer);
+
+    let db = config_2.open()?;
+
+    let checksum_b = db.checksum().unwrap();
+    assert_eq!(checksum_a, checksum_b);
+
+    for db_id in 0..N_THREADS {
+        let tree_id = format!("tree_{}", db_id);
+        let tree = db.open_tree(tree_id.as_bytes())?;
+
+        for i in 0..N_THREADS {
+            let k = kv(i as usize);
+            assert_eq!(tree.get(&*k).unwrap().unwrap(), k);
+            tree.remove(&*k).unwrap();
+        }
+    }
+
+    let checksum_c = db.checksum().unwrap();
+
+    drop(db);
+
+    let db = config_2.open()?;
+    for db_id in 0..N_THREADS {
+        let tree_id = format!("tree_{}", db_id);
+        let tree = db.open_tree(tree_id.as_bytes())?;
+
+        for i in 0..N_THREADS {
+            let k = kv(i as usize);
+            assert_eq!(tree.get(&*k), Ok(None));
+        }
+    }
+
+    let checksum_d = db.checksum().unwrap();
+    assert_eq!(checksum_c, checksum_d);
+
+    Ok(())
+}
+
+#[test]
+#[cfg(not(target_os = "fuchsia"))]
+fn quickcheck_tree_matches_btreemap() {
+    let n_tests = if cfg!(windows) { 25 } else { 100 };
+
+    QuickCheck::new()
+        .gen(StdGen::new(rand::thread_rng(), 1000))
+        .tests(n_tests)
+        .max_tests(n_tests * 10)
+        .quickcheck(
+            prop_tree_matches_btreemap
+                as fn(Vec<Op>, bool, bool, u8, u8) -> bool,
+        );
+}
+
+#[test]
+fn tree_bug_00() {
+    // postmortem:
+    prop_tree_matches_btreemap(vec![Restart], false, false, 0, 0);
+}
+
+#[test]
+fn tree_bug_01() {
+    // postmortem:
+    // this was a bug in the snapshot recovery, where
+    // it led to max_id dropping by 1 after a restart.
+    // postmortem 2:
+    // we were stalling here because we had a new log with stable of
+    // SEG_HEADER_LEN, but when we iterated over it to create a new
+    // snapshot (snapshot every 1 set in Config), we iterated up until
+    // that offset. make_stable requires our stable offset to be >=
+    // the provided one, to deal with 0.
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![32]), 9),
+            Set(Key(vec![195]), 13),
+            Restart,
+            Set(Key(vec![164]), 147),
+        ],
+        true,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_02() {
+    // postmortem:
+    // this was a bug in the way that the `Materializer`
+    // was fed data, possibly out of order, if recover
+    // in the pagecache had to run over log entries
+    // that were later run through the same `Materializer`
+    // then the second time (triggered by a snapshot)
+    // would not pick up on the importance of seeing
+    // the new root set.
+    // portmortem 2: when refactoring iterators, failed
+    // to account for node.hi being empty on the infinity
+    // shard
+    prop_tree_matches_btreemap(
+        vec![
+            Restart,
+            Set(Key(vec![215]), 121),
+            Restart,
+            Set(Key(vec![216]), 203),
+            Scan(Key(vec![210]), 4),
+        ],
+        true,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_03() {
+    // postmortem: the tree was not persisting and recovering root hoists
+    // postmortem 2: when refactoring the log storage, we failed to restart
+    // log writing in the proper location.
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![113]), 204),
+            Set(Key(vec![119]), 205),
+            Set(Key(vec![166]), 88),
+            Set(Key(vec![23]), 44),
+            Restart,
+            Set(Key(vec![226]), 192),
+            Set(Key(vec![189]), 186),
+            Restart,
+            Scan(Key(vec![198]), 11),
+        ],
+        true,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_04() {
+    // postmortem: pagecache was failing to replace the LogId list
+    // when it encountered a new Update::Compact.
+    // postmortem 2: after refactoring log storage, we were not properly
+    // setting the log tip, and the beginning got clobbered after writing
+    // after a restart.
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![158]), 31),
+            Set(Key(vec![111]), 134),
+            Set(Key(vec![230]), 187),
+            Set(Key(vec![169]), 58),
+            Set(Key(vec![131]), 10),
+            Set(Key(vec![108]), 246),
+            Set(Key(vec![127]), 155),
+            Restart,
+            Set(Key(vec![59]), 119),
+        ],
+        true,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_05() {
+    // postmortem: during recovery, the segment accountant was failing to
+    // properly set the file's tip.
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![231]), 107),
+            Set(Key(vec![251]), 42),
+            Set(Key(vec![80]), 81),
+            Set(Key(vec![178]), 130),
+            Set(Key(vec![150]), 232),
+            Restart,
+            Set(Key(vec![98]), 78),
+            Set(Key(vec![0]), 45),
+        ],
+        true,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_06() {
+    // postmortem: after reusing segments, we were failing to checksum reads
+    // performed while iterating over rewritten segment buffers, and using
+    // former garbage data. fix: use the crc that's there for catching torn
+    // writes with high probability, AND zero out buffers.
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![162]), 8),
+            Set(Key(vec![59]), 192),
+            Set(Key(vec![238]), 83),
+            Set(Key(vec![151]), 231),
+            Restart,
+            Set(Key(vec![30]), 206),
+            Set(Key(vec![150]), 146),
+            Set(Key(vec![18]), 34),
+        ],
+        true,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_07() {
+    // postmortem: the segment accountant was not fully recovered, and thought
+    // that it could reuse a particular segment that wasn't actually empty
+    // yet.
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![135]), 22),
+            Set(Key(vec![41]), 36),
+            Set(Key(vec![101]), 31),
+            Set(Key(vec![111]), 35),
+            Restart,
+            Set(Key(vec![47]), 36),
+            Set(Key(vec![79]), 114),
+            Set(Key(vec![64]), 9),
+            Scan(Key(vec![196]), 25),
+        ],
+        true,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_08() {
+    // postmortem: failed to properly recover the state in the segment
+    // accountant that tracked the previously issued segment.
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![145]), 151),
+            Set(Key(vec![155]), 148),
+            Set(Key(vec![131]), 170),
+            Set(Key(vec![163]), 60),
+            Set(Key(vec![225]), 126),
+            Restart,
+            Set(Key(vec![64]), 237),
+            Set(Key(vec![102]), 205),
+            Restart,
+        ],
+        true,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_09() {
+    // postmortem: was failing to load existing snapshots on initialization.
+    // would encounter uninitialized segments at the log tip and overwrite
+    // the first segment (indexed by LSN of 0) in the segment accountant
+    // ordering, skipping over important updates.
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![189]), 36),
+            Set(Key(vec![254]), 194),
+            Set(Key(vec![132]), 50),
+            Set(Key(vec![91]), 221),
+            Set(Key(vec![126]), 6),
+            Set(Key(vec![199]), 183),
+            Set(Key(vec![71]), 125),
+            Scan(Key(vec![67]), 16),
+            Set(Key(vec![190]), 16),
+            Restart,
+        ],
+        true,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_10() {
+    // postmortem: after reusing a segment, but not completely writing a
+    // segment, we were hitting an old LSN and violating an assert, rather
+    // than just ending.
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![152]), 163),
+            Set(Key(vec![105]), 191),
+            Set(Key(vec![207]), 217),
+            Set(Key(vec![128]), 19),
+            Set(Key(vec![106]), 22),
+            Scan(Key(vec![20]), 24),
+            Set(Key(vec![14]), 150),
+            Set(Key(vec![80]), 43),
+            Set(Key(vec![174]), 134),
+            Set(Key(vec![20]), 150),
+            Set(Key(vec![13]), 171),
+            Restart,
+            Scan(Key(vec![240]), 25),
+            Scan(Key(vec![77]), 37),
+            Set(Key(vec![153]), 232),
+            Del(Key(vec![2])),
+            Set(Key(vec![227]), 169),
+            Get(Key(vec![232])),
+            Cas(Key(vec![247]), 151, 70),
+            Set(Key(vec![78]), 52),
+            Get(Key(vec![16])),
+            Del(Key(vec![78])),
+            Cas(Key(vec![201]), 93, 196),
+            Set(Key(vec![172]), 84),
+        ],
+        true,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_11() {
+    // postmortem: a stall was happening because LSNs and LogIds were being
+    // conflated in calls to make_stable. A higher LogId than any LSN was
+    // being created, then passed in.
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![38]), 148),
+            Set(Key(vec![176]), 175),
+            Set(Key(vec![82]), 88),
+            Set(Key(vec![164]), 85),
+            Set(Key(vec![139]), 74),
+            Set(Key(vec![73]), 23),
+            Cas(Key(vec![34]), 67, 151),
+            Set(Key(vec![115]), 133),
+            Set(Key(vec![249]), 138),
+            Restart,
+            Set(Key(vec![243]), 6),
+        ],
+        true,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_12() {
+    // postmortem: was not checking that a log entry's LSN matches its position
+    // as part of detecting tears / partial rewrites.
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![118]), 156),
+            Set(Key(vec![8]), 63),
+            Set(Key(vec![165]), 110),
+            Set(Key(vec![219]), 108),
+            Set(Key(vec![91]), 61),
+            Set(Key(vec![18]), 98),
+            Scan(Key(vec![73]), 6),
+            Set(Key(vec![240]), 108),
+            Cas(Key(vec![71]), 28, 189),
+            Del(Key(vec![199])),
+            Restart,
+            Set(Key(vec![30]), 140),
+            Scan(Key(vec![118]), 13),
+            Get(Key(vec![180])),
+            Cas(Key(vec![115]), 151, 116),
+            Restart,
+            Set(Key(vec![31]), 95),
+            Cas(Key(vec![79]), 153, 225),
+            Set(Key(vec![34]), 161),
+            Get(Key(vec![213])),
+            Set(Key(vec![237]), 215),
+            Del(Key(vec![52])),
+            Set(Key(vec![56]), 78),
+            Scan(Key(vec![141]), 2),
+            Cas(Key(vec![228]), 114, 170),
+            Get(Key(vec![231])),
+            Get(Key(vec![223])),
+            Del(Key(vec![167])),
+            Restart,
+            Scan(Key(vec![240]), 31),
+            Del(Key(vec![54])),
+            Del(Key(vec![2])),
+            Set(Key(vec![117]), 165),
+            Set(Key(vec![223]), 50),
+            Scan(Key(vec![69]), 4),
+            Get(Key(vec![156])),
+            Set(Key(vec![214]), 72),
+        ],
+        true,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_13() {
+    // postmortem: failed root hoists were being improperly recovered before the
+    // following free was done on their page, but we treated the written node as
+    // if it were a successful completed root hoist.
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![42]), 10),
+            Set(Key(vec![137]), 220),
+            Set(Key(vec![183]), 129),
+            Set(Key(vec![91]), 145),
+            Set(Key(vec![126]), 26),
+            Set(Key(vec![255]), 67),
+            Set(Key(vec![69]), 18),
+            Restart,
+            Set(Key(vec![24]), 92),
+            Set(Key(vec![193]), 17),
+            Set(Key(vec![3]), 143),
+            Cas(Key(vec![50]), 13, 84),
+            Restart,
+            Set(Key(vec![191]), 116),
+            Restart,
+            Del(Key(vec![165])),
+        ],
+        true,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_14() {
+    // postmortem: after adding prefix compression, we were not
+    // handling re-inserts and deletions properly
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![107]), 234),
+            Set(Key(vec![7]), 245),
+            Set(Key(vec![40]), 77),
+            Set(Key(vec![171]), 244),
+            Set(Key(vec![173]), 16),
+            Set(Key(vec![171]), 176),
+            Scan(Key(vec![93]), 33),
+        ],
+        true,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_15() {
+    // postmortem: was not sorting keys properly when binary searching for them
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![102]), 165),
+            Set(Key(vec![91]), 191),
+            Set(Key(vec![141]), 228),
+            Set(Key(vec![188]), 124),
+            Del(Key(vec![141])),
+            Scan(Key(vec![101]), 26),
+        ],
+        true,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_16() {
+    // postmortem: the test merge function was not properly adding numbers.
+    prop_tree_matches_btreemap(
+        vec![Merge(Key(vec![247]), 162), Scan(Key(vec![209]), 31)],
+        false,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_17() {
+    // postmortem: we were creating a copy of a node leaf during iteration
+    // before accidentally putting it into a PinnedValue, despite the
+    // fact that it was not actually part of the node's actual memory!
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![194, 215, 103, 0, 138, 11, 248, 131]), 70),
+            Scan(Key(vec![]), 30),
+        ],
+        false,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_18() {
+    // postmortem: when implementing get_gt and get_lt, there were some
+    // issues with getting order comparisons correct.
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![]), 19),
+            Set(Key(vec![78]), 98),
+            Set(Key(vec![255]), 224),
+            Set(Key(vec![]), 131),
+            Get(Key(vec![255])),
+            GetGt(Key(vec![89])),
+        ],
+        false,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_19() {
+    // postmortem: we were not seeking properly to the next node
+    // when we hit a half-split child and were using get_lt
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![]), 138),
+            Set(Key(vec![68]), 113),
+            Set(Key(vec![155]), 73),
+            Set(Key(vec![50]), 220),
+            Set(Key(vec![]), 247),
+            GetLt(Key(vec![100])),
+        ],
+        false,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_20() {
+    // postmortem: we were not seeking forward during get_gt
+    // if path_for_key reached a leaf that didn't include
+    // a key for our
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![]), 10),
+            Set(Key(vec![56]), 42),
+            Set(Key(vec![138]), 27),
+            Set(Key(vec![155]), 73),
+            Set(Key(vec![]), 251),
+            GetGt(Key(vec![94])),
+        ],
+        false,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_21() {
+    // postmortem: more split woes while implementing get_lt
+    // postmortem 2: failed to properly account for node hi key
+    // being empty in the view predecessor function
+    // postmortem 3: when rewriting Iter, failed to account for
+    // direction of iteration
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![176]), 163),
+            Set(Key(vec![]), 229),
+            Set(Key(vec![169]), 121),
+            Set(Key(vec![]), 58),
+            GetLt(Key(vec![176])),
+        ],
+        false,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_22() {
+    // postmortem: inclusivity wasn't being properly flipped off after
+    // the first result during iteration
+    // postmortem 2: failed to properly check bounds while iterating
+    prop_tree_matches_btreemap(
+        vec![
+            Merge(Key(vec![]), 155),
+            Merge(Key(vec![56]), 251),
+            Scan(Key(vec![]), 2),
+        ],
+        false,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_23() {
+    // postmortem: when rewriting CRC handling code, mis-sized the blob crc
+    prop_tree_matches_btreemap(
+        vec![Set(Key(vec![6; 5120]), 92), Restart, Scan(Key(vec![]), 35)],
+        false,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_24() {
+    // postmortem: get_gt diverged with the Iter impl
+    prop_tree_matches_btreemap(
+        vec![
+            Merge(Key(vec![]), 193),
+            Del(Key(vec![])),
+            Del(Key(vec![])),
+            Set(Key(vec![]), 55),
+            Set(Key(vec![]), 212),
+            Merge(Key(vec![]), 236),
+            Del(Key(vec![])),
+            Set(Key(vec![]), 192),
+            Del(Key(vec![])),
+            Set(Key(vec![94]), 115),
+            Merge(Key(vec![62]), 34),
+            GetGt(Key(vec![])),
+        ],
+        false,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_25() {
+    // postmortem: was not accounting for merges when traversing
+    // the frag chain and a Del was encountered
+    prop_tree_matches_btreemap(
+        vec![Del(Key(vec![])), Merge(Key(vec![]), 84), Get(Key(vec![]))],
+        false,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_26() {
+    // postmortem:
+    prop_tree_matches_btreemap(
+        vec![
+            Merge(Key(vec![]), 194),
+            Merge(Key(vec![62]), 114),
+            Merge(Key(vec![80]), 202),
+            Merge(Key(vec![]), 169),
+            Set(Key(vec![]), 197),
+            Del(Key(vec![])),
+            Del(Key(vec![])),
+            Set(Key(vec![]), 215),
+            Set(Key(vec![]), 164),
+            Merge(Key(vec![]), 150),
+            GetGt(Key(vec![])),
+            GetLt(Key(vec![80])),
+        ],
+        false,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_27() {
+    // postmortem: was not accounting for the fact that deletions reduce the
+    // chances of being able to split successfully.
+    prop_tree_matches_btreemap(
+        vec![
+            Del(Key(vec![])),
+            Merge(
+                Key(vec![
+                    74, 117, 68, 37, 89, 16, 84, 130, 133, 78, 74, 59, 44, 109,
+                    34, 5, 36, 74, 131, 100, 79, 86, 87, 107, 87, 27, 1, 85,
+                    53, 112, 89, 75, 67, 78, 58, 121, 0, 105, 8, 117, 79, 40,
+                    94, 123, 83, 72, 78, 23, 23, 35, 50, 77, 59, 75, 54, 92,
+                    89, 12, 27, 48, 64, 21, 42, 97, 45, 28, 122, 13, 4, 32, 51,
+                    25, 26, 18, 65, 12, 54, 104, 106, 80, 75, 91, 111, 9, 5,
+                    130, 43, 40, 3, 72, 0, 58, 92, 64, 112, 97, 75, 130, 11,
+                    135, 19, 107, 40, 17, 25, 49, 48, 119, 82, 54, 35, 113, 91,
+                    68, 12, 118, 123, 62, 108, 88, 67, 43, 33, 119, 132, 124,
+                    1, 62, 133, 110, 25, 62, 129, 117, 117, 107, 123, 94, 127,
+                    80, 0, 116, 101, 9, 9, 54, 134, 70, 66, 79, 50, 124, 115,
+                    85, 42, 120, 24, 15, 81, 100, 72, 71, 40, 58, 22, 6, 34,
+                    54, 69, 110, 18, 74, 111, 80, 52, 90, 44, 4, 29, 84, 95,
+                    21, 25, 10, 10, 60, 18, 78, 23, 21, 114, 92, 96, 17, 127,
+                    53, 86, 2, 60, 104, 8, 132, 44, 115, 6, 25, 80, 46, 12, 20,
+                    44, 67, 136, 127, 50, 55, 70, 41, 90, 16, 10, 44, 32, 24,
+                    106, 13, 104,
+                ]),
+                219,
+            ),
+            Merge(Key(vec![]), 71),
+            Del(Key(vec![])),
+            Set(Key(vec![0]), 146),
+            Merge(Key(vec![13]), 155),
+            Merge(Key(vec![]), 14),
+            Del(Key(vec![])),
+            Set(Key(vec![]), 150),
+            Set(
+                Key(vec![
+                    13, 8, 3, 6, 9, 14, 3, 13, 7, 12, 13, 7, 13, 13, 1, 13, 5,
+                    4, 3, 2, 6, 16, 17, 10, 0, 16, 12, 0, 16, 1, 0, 15, 15, 4,
+                    1, 6, 9, 9, 11, 16, 7, 6, 10, 1, 11, 10, 4, 9, 9, 14, 4,
+                    12, 16, 10, 15, 2, 1, 8, 4,
+                ]),
+                247,
+            ),
+            Del(Key(vec![154])),
+            Del(Key(vec![])),
+            Del(Key(vec![
+                0, 24, 24, 31, 40, 23, 10, 30, 16, 41, 30, 23, 14, 25, 21, 19,
+                18, 7, 17, 41, 11, 5, 14, 42, 11, 22, 4, 8, 4, 38, 33, 31, 3,
+                30, 40, 22, 40, 39, 5, 40, 1, 41, 11, 26, 25, 33, 12, 38, 4,
+                35, 30, 42, 19, 26, 23, 22, 39, 18, 29, 4, 1, 24, 14, 38, 0,
+                36, 27, 11, 27, 34, 16, 15, 38, 0, 20, 37, 22, 31, 12, 26, 16,
+                4, 22, 25, 4, 34, 4, 33, 37, 28, 18, 4, 41, 15, 8, 16, 27, 3,
+                20, 26, 40, 31, 15, 15, 17, 15, 5, 13, 22, 37, 7, 13, 35, 14,
+                6, 28, 21, 26, 13, 35, 1, 10, 8, 34, 23, 27, 29, 8, 14, 42, 36,
+                31, 34, 12, 31, 24, 5, 8, 11, 36, 29, 24, 38, 8, 12, 18, 22,
+                36, 21, 28, 11, 24, 0, 41, 37, 39, 42, 25, 13, 41, 27, 8, 24,
+                22, 30, 17, 2, 4, 20, 33, 5, 24, 33, 6, 29, 5, 0, 17, 9, 20,
+                26, 15, 23, 22, 16, 23, 16, 1, 20, 0, 28, 16, 34, 30, 19, 5,
+                36, 40, 28, 6, 39,
+            ])),
+            Merge(Key(vec![]), 50),
+        ],
+        false,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_28() {
+    // postmortem:
+    prop_tree_matches_btreemap(
+        vec![
+            Del(Key(vec![])),
+            Set(Key(vec![]), 65),
+            Del(Key(vec![])),
+            Del(Key(vec![])),
+            Merge(Key(vec![]), 50),
+            Merge(Key(vec![]), 2),
+            Del(Key(vec![197])),
+            Merge(Key(vec![5]), 146),
+            Set(Key(vec![222]), 224),
+            Merge(Key(vec![149]), 60),
+            Scan(Key(vec![178]), 18),
+        ],
+        false,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_29() {
+    // postmortem: tree merge and split thresholds caused an infinite
+    // loop while performing updates
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![]), 142),
+            Merge(
+                Key(vec![
+                    45, 47, 6, 67, 16, 12, 62, 35, 69, 80, 49, 61, 29, 82, 9,
+                    47, 25, 78, 47, 64, 29, 74, 45, 0, 37, 44, 21, 82, 55, 44,
+                    31, 60, 86, 18, 45, 67, 55, 21, 35, 46, 25, 51, 5, 32, 33,
+                    36, 1, 81, 28, 28, 79, 76, 80, 89, 80, 62, 8, 85, 50, 15,
+                    4, 11, 76, 72, 73, 47, 30, 50, 85, 67, 84, 13, 82, 84, 78,
+                    70, 42, 83, 8, 7, 50, 77, 85, 37, 47, 82, 86, 46, 30, 27,
+                    5, 39, 70, 26, 59, 16, 6, 34, 56, 40, 40, 67, 16, 61, 63,
+                    56, 64, 31, 15, 81, 84, 19, 61, 66, 3, 7, 40, 56, 13, 40,
+                    64, 50, 88, 47, 88, 50, 63, 65, 79, 62, 1, 44, 59, 27, 12,
+                    60, 3, 36, 89, 45, 18, 4, 68, 48, 61, 30, 48, 26, 84, 49,
+                    3, 74, 51, 53, 30, 57, 50, 35, 74, 59, 30, 73, 19, 30, 82,
+                    78, 3, 5, 62, 17, 48, 29, 67, 52, 45, 61, 74, 52, 29, 61,
                     63, 11, 89, 76, 34, 8, 50, 75, 42, 12, 5, 55, 0, 59, 44,
                     68, 26, 76, 37, 50, 53, 73, 53, 76, 57, 40, 30, 52, 0, 41,
                     21, 8, 79, 79, 38, 37, 50, 56, 43, 9, 85, 21, 60, 64, 13,
@@ -2226,55 +4911,1024 @@ fn tree_bug_39() {
 }
 
 #[test]
-fn tree_bug_40() {
-    // postmortem: deletions of non-existant keys were
-    // being persisted despite being unneccessary.
-    prop_tree_matches_btreemap(
-        vec![Del(Key(vec![99; 111222333]))],
-        false,
-        false,
-        0,
-        0,
+fn tree_bug_40() {
+    // postmortem: deletions of non-existant keys were
+    // being persisted despite being unneccessary.
+    prop_tree_matches_btreemap(
+        vec![Del(Key(vec![99; 111222333]))],
+        false,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_41() {
+    // postmortem: indexing of values during
+    // iteration was incorrect.
+    prop_tree_matches_btreemap(
+        vec![
+            Set(Key(vec![]), 131),
+            Set(Key(vec![17; 1]), 214),
+            Set(Key(vec![4; 1]), 202),
+            Set(Key(vec![24; 1]), 79),
+            Set(Key(vec![26; 1]), 235),
+            Scan(Key(vec![]), 19),
+        ],
+        false,
+        false,
+        0,
+        0,
+    );
+}
+
+#[test]
+fn tree_bug_42() {
+    // postmortem: during refactoring, accidentally
+    // messed up the index selection for merge destinations.
+    for _ in 0..100 {
+        prop_tree_matches_btreemap(
+            vec![
+                Merge(Key(vec![]), 112),
+                Set(Key(vec![110; 1]), 153),
+                Set(Key(vec![15; 1]), 100),
+                Del(Key(vec![110; 1])),
+                GetLt(Key(vec![148; 1])),
+            ],
+            false,
+            false,
+            0,
+            0,
+        );
+    }
+}
+
+#[test]
+fn compressed_trees() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("compressed_trees");
+    let path = dir.path();
+
+    let config = || {
+        Config::new()
+            .path(path)
+            .cache_capacity(256)
+            .max_leaf_items(64)
+            .min_leaf_items(16)
+    };
+
+    let check = |db: &sled::Db| {
+        let tree = db.open_tree("compressed").unwrap();
+        for i in 0..1000_u32 {
+            let expected =
+                if i % 3 == 0 { None } else { Some(IVec::from(&[7; 64][..])) };
+            assert_eq!(tree.get(i.to_be_bytes()).unwrap(), expected);
+            assert_eq!(db.get(i.to_be_bytes()).unwrap(), expected);
+        }
+        assert!(db.verify().unwrap().is_ok());
+    };
+
+    {
+        let db = config().compress_tree("compressed").open().unwrap();
+        let tree = db.open_tree("compressed").unwrap();
+        for i in 0..1000_u32 {
+            tree.insert(i.to_be_bytes(), &[7; 64][..]).unwrap();
+            db.insert(i.to_be_bytes(), &[7; 64][..]).unwrap();
+        }
+        for i in (0..1000_u32).step_by(3) {
+            tree.remove(i.to_be_bytes()).unwrap();
+            db.remove(i.to_be_bytes()).unwrap();
+        }
+    }
+
+    // pages written compressed stay readable once the
+    // tree is no longer configured for compression, and
+    // the other way around
+    check(&config().compress_tree("compressed").open().unwrap());
+    check(&config().open().unwrap());
+    check(&config().compress_tree("__sled__default").open().unwrap());
+
+}
+
+#[test]
+fn prefetching_scans() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("prefetching_scans");
+    let path = dir.path();
+
+    let config = || {
+        Config::new()
+            .path(path)
+            .cache_capacity(256)
+            .max_leaf_items(16)
+            .min_leaf_items(4)
+            .prefetch_pages(4)
+    };
+
+    {
+        let db = config().open().unwrap();
+        for i in 0..2000_u32 {
+            db.insert(i.to_be_bytes(), &i.to_le_bytes()).unwrap();
+        }
+    }
+
+    let db = config().open().unwrap();
+    for _ in 0..2 {
+        let keys: Vec<u32> = db
+            .iter()
+            .map(|res| {
+                let (k, v) = res.unwrap();
+                let mut key = [0; 4];
+                let mut value = [0; 4];
+                key.copy_from_slice(&k);
+                value.copy_from_slice(&v);
+                assert_eq!(u32::from_be_bytes(key), u32::from_le_bytes(value));
+                u32::from_be_bytes(key)
+            })
+            .collect();
+        assert_eq!(keys, (0..2000).collect::<Vec<_>>());
+    }
+
+    // scans that change direction only prefetch forward
+    let mut iter = db.range(500_u32.to_be_bytes()..1500_u32.to_be_bytes());
+    for _ in 0..100 {
+        assert!(iter.next().is_some());
+        assert!(iter.next_back().is_some());
+    }
+    assert_eq!(iter.count(), 800);
+
+    assert!(db.verify().unwrap().is_ok());
+    drop(db);
+}
+
+#[test]
+fn memory_usage() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("memory_usage");
+    let path = dir.path();
+
+    let config = |cache_capacity| {
+        Config::new().path(path).cache_capacity(cache_capacity)
+    };
+
+    {
+        let db = config(16 * 1024 * 1024).open().unwrap();
+        let empty = db.memory_usage();
+        assert!(empty.page_table > 0);
+        assert!(empty.io_buffer > 0);
+
+        for i in 0..1000_u32 {
+            db.insert(i.to_be_bytes(), vec![0; 1024]).unwrap();
+        }
+
+        let full = db.memory_usage();
+        assert!(full.resident_pages >= 1000 * 1024);
+        assert!(full.delta_chains > empty.delta_chains);
+        assert!(full.lru > 0);
+        assert_eq!(
+            full.total(),
+            full.resident_pages
+                + full.delta_chains
+                + full.page_table
+                + full.lru
+                + full.io_buffer
+        );
+    }
+
+    // a small cache keeps fewer pages resident after
+    // reading everything back in
+    let db = config(64 * 1024).open().unwrap();
+    for i in 0..1000_u32 {
+        assert_eq!(db.get(i.to_be_bytes()).unwrap().unwrap().len(), 1024);
+    }
+    assert!(db.memory_usage().resident_pages < 500 * 1024);
+
+    drop(db);
+}
+
+#[test]
+fn dirty_pages() {
+    common::setup_logger();
+
+    let db = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .materializer(Concatenate::default())
+        .open()
+        .unwrap();
+
+    db.flush().unwrap();
+    assert!(db.dirty_pages().is_empty());
+    assert_eq!(db.flush_dirty().unwrap(), 0);
+
+    let a = db.allocate_page(vec![0]).unwrap();
+    let b = db.allocate_page(vec![1]).unwrap();
+    db.link_page(a, vec![2]).unwrap();
+
+    let dirty = db.dirty_pages();
+    assert!(dirty.contains(&a) && dirty.contains(&b));
+    assert!(dirty.windows(2).all(|w| w[0] < w[1]));
+
+    // a later write to another page leaves it dirty
+    db.flush_page(a).unwrap();
+    assert!(!db.dirty_pages().contains(&a));
+    db.link_page(b, vec![3]).unwrap();
+    assert!(db.dirty_pages().contains(&b));
+    assert_eq!(db.flush_page(a).unwrap(), 0);
+
+    db.insert(b"k", b"v").unwrap();
+    assert!(!db.dirty_pages().is_empty());
+    assert!(db.flush_dirty().unwrap() > 0);
+    assert!(db.dirty_pages().is_empty());
+
+    db.link_page(a, vec![4]).unwrap();
+    db.flush().unwrap();
+    assert!(db.dirty_pages().is_empty());
+    assert_eq!(db.get_page(a).unwrap(), Some(IVec::from(vec![0, 2, 4])));
+}
+
+#[test]
+fn pinned_reads() {
+    common::setup_logger();
+
+    let db = Config::new()
+        .temporary(true)
+        .cache_capacity(256)
+        .overflow_threshold(64)
+        .open()
+        .unwrap();
+
+    for i in 0..100_u32 {
+        // every other value is stored in its own page
+        let len = if i % 2 == 0 { 8 } else { 128 };
+        db.insert(i.to_be_bytes(), vec![i as u8; len]).unwrap();
+    }
+
+    let pinned: Vec<PinnedValue> = (0..100_u32)
+        .map(|i| db.get_pinned(i.to_be_bytes()).unwrap().unwrap())
+        .collect();
+
+    // overwriting and removing the keys, which pages the
+    // old versions out of the tiny cache and frees the
+    // overflow pages, leaves the pinned values intact
+    for i in 0..100_u32 {
+        if i % 3 == 0 {
+            db.remove(i.to_be_bytes()).unwrap();
+        } else {
+            db.insert(i.to_be_bytes(), vec![0xFF; 256]).unwrap();
+        }
+    }
+
+    for (i, value) in pinned.iter().enumerate() {
+        let len = if i % 2 == 0 { 8 } else { 128 };
+        assert_eq!(*value, vec![i as u8; len]);
+        assert_eq!(value.to_owned(), IVec::from(vec![i as u8; len]));
+    }
+    drop(pinned);
+
+    for i in 0..100_u32 {
+        let pinned = db.get_pinned(i.to_be_bytes()).unwrap();
+        assert_eq!(
+            pinned.map(|value| value.to_owned()),
+            db.get(i.to_be_bytes()).unwrap()
+        );
+    }
+}
+
+#[cfg(feature = "async")]
+fn poll_once<F: std::future::Future>(
+    future: &mut std::pin::Pin<Box<F>>,
+) -> std::task::Poll<F::Output> {
+    use std::task::{Context, RawWaker, RawWakerVTable, Waker};
+
+    fn raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            raw_waker()
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable =
+            RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+
+    let waker = unsafe { Waker::from_raw(raw_waker()) };
+    future.as_mut().poll(&mut Context::from_waker(&waker))
+}
+
+#[cfg(feature = "async")]
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    loop {
+        if let std::task::Poll::Ready(output) = poll_once(&mut future) {
+            return output;
+        }
+        thread::yield_now();
+    }
+}
+
+#[test]
+#[cfg(feature = "async")]
+fn async_reads() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("async_reads");
+    let path = dir.path();
+    let config = || Config::new().path(path).cache_capacity(256);
+
+    {
+        let db = config().open().unwrap();
+        for i in 0..1000_u32 {
+            db.insert(i.to_be_bytes(), i.to_le_bytes().to_vec()).unwrap();
+        }
+        db.insert(b"overflow", vec![7; 100_000]).unwrap();
+        db.flush().unwrap();
+    }
+
+    // after a restart nothing is resident, so these reads
+    // page in on the threadpool
+    let db = config().open().unwrap();
+    for i in (0..1000_u32).step_by(7) {
+        let value = block_on(db.get_async(i.to_be_bytes())).unwrap();
+        assert_eq!(value.unwrap(), i.to_le_bytes());
+    }
+    assert_eq!(block_on(db.get_async(b"missing")).unwrap(), None);
+    assert_eq!(
+        block_on(db.get_async(b"overflow")).unwrap().unwrap(),
+        vec![7; 100_000]
+    );
+
+    // async iteration matches sync iteration across leaves
+    let start = 10_u32.to_be_bytes();
+    let expected: Vec<(IVec, IVec)> =
+        db.range(start..).collect::<Result<_>>().unwrap();
+    let mut iter = db.range(start..).into_async();
+    let mut actual = vec![];
+    while let Some(item) = block_on(iter.next()) {
+        actual.push(item.unwrap());
+    }
+    assert_eq!(actual, expected);
+    assert!(block_on(iter.next()).is_none());
+
+    drop(db);
+
+    // with everything resident, reads are ready right away
+    let db = Config::new().temporary(true).open().unwrap();
+    db.insert(b"k", b"v").unwrap();
+    db.insert(b"overflow", vec![7; 100_000]).unwrap();
+    let mut get = Box::pin(db.get_async(b"k"));
+    match poll_once(&mut get) {
+        std::task::Poll::Ready(value) => {
+            assert_eq!(value.unwrap().unwrap(), b"v")
+        }
+        std::task::Poll::Pending => panic!("expected a resident read"),
+    }
+    let mut get = Box::pin(db.get_async(b"overflow"));
+    assert!(poll_once(&mut get).is_ready());
+}
+
+#[cfg(feature = "instrumentation")]
+#[test]
+fn instrumentation_spans_nest() {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Mutex;
+    use std::thread::ThreadId;
+
+    #[derive(Default)]
+    struct Records {
+        entered: Mutex<HashSet<Span>>,
+        stacks: Mutex<HashMap<ThreadId, Vec<Span>>>,
+    }
+
+    struct Recorder(Arc<Records>);
+
+    impl Instrumentation for Recorder {
+        fn enter(&self, span: Span) {
+            self.0.entered.lock().unwrap().insert(span);
+            let mut stacks = self.0.stacks.lock().unwrap();
+            stacks.entry(thread::current().id()).or_default().push(span);
+        }
+
+        fn exit(&self, span: Span) {
+            let mut stacks = self.0.stacks.lock().unwrap();
+            let stack = stacks.get_mut(&thread::current().id()).unwrap();
+            assert_eq!(stack.pop(), Some(span));
+        }
+    }
+
+    let records = Arc::new(Records::default());
+
+    let db = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .instrumentation(Recorder(records.clone()))
+        .open()
+        .unwrap();
+
+    db.insert(b"a", b"1").unwrap();
+    assert_eq!(db.get(b"a").unwrap().unwrap(), b"1");
+    db.flush().unwrap();
+
+    drop(db);
+
+    let entered = records.entered.lock().unwrap();
+    for span in &[
+        Span::RecoverSnapshot,
+        Span::RecoverSegments,
+        Span::RecoverPages,
+        Span::TreeDescent,
+        Span::LogReservation,
+        Span::Flush,
+    ] {
+        assert!(entered.contains(span), "{:?} was never entered", span);
+    }
+
+    let stacks = records.stacks.lock().unwrap();
+    assert!(stacks.values().all(Vec::is_empty), "{:?}", *stacks);
+}
+
+#[test]
+fn dump_structure_shows_every_node() {
+    let db = Config::new().temporary(true).open().unwrap();
+
+    for i in 0..100_u8 {
+        db.insert([i], vec![]).unwrap();
+    }
+
+    let report = db.verify().unwrap();
+    assert!(report.index_nodes > 0, "{:?}", report);
+
+    let text = db.dump_structure().unwrap();
+    let nodes: Vec<&str> = text
+        .lines()
+        .filter(|line| {
+            line.starts_with("  index ") || line.starts_with("  leaf ")
+        })
+        .collect();
+    assert_eq!(nodes.len(), report.index_nodes + report.leaf_nodes, "{}", text);
+
+    let items: usize = nodes
+        .iter()
+        .filter_map(|line| line.split(" items ").nth(1))
+        .map(|items| items.parse::<usize>().unwrap())
+        .sum();
+    assert_eq!(items, 100, "{}", text);
+    // non-printable bytes are escaped
+    assert!(text.contains("lo \"\\x0"), "{}", text);
+
+    let dot = db.dump_structure_dot().unwrap();
+    assert!(dot.starts_with("digraph \"__sled__default\" {"), "{}", dot);
+    assert!(dot.ends_with("}\n"), "{}", dot);
+    assert_eq!(
+        dot.matches("\\nchain ").count(),
+        report.index_nodes + report.leaf_nodes,
+        "{}",
+        dot
     );
+    assert!(dot.contains("[style=dashed]"), "{}", dot);
 }
 
 #[test]
-fn tree_bug_41() {
-    // postmortem: indexing of values during
-    // iteration was incorrect.
-    prop_tree_matches_btreemap(
-        vec![
-            Set(Key(vec![]), 131),
-            Set(Key(vec![17; 1]), 214),
-            Set(Key(vec![4; 1]), 202),
-            Set(Key(vec![24; 1]), 79),
-            Set(Key(vec![26; 1]), 235),
-            Scan(Key(vec![]), 19),
-        ],
-        false,
-        false,
-        0,
-        0,
+fn space_stats() {
+    common::setup_logger();
+
+    let db = Config::new()
+        .temporary(true)
+        .segment_size(256 * 1024)
+        .open()
+        .unwrap();
+
+    for i in 0..1000_u32 {
+        db.insert(i.to_be_bytes(), vec![0; 1024]).unwrap();
+    }
+    // overwrite everything to leave dead bytes behind
+    for i in 0..1000_u32 {
+        db.insert(i.to_be_bytes(), vec![1; 1024]).unwrap();
+    }
+    db.flush().unwrap();
+
+    let stats = db.space_stats().unwrap();
+    assert!(stats.log_bytes > 0, "{:?}", stats);
+    assert!(stats.live_bytes >= 1000 * 1024, "{:?}", stats);
+    assert!(stats.dead_bytes > 0, "{:?}", stats);
+    assert!(stats.segments.len() > 1, "{:?}", stats);
+    assert_eq!(
+        stats.live_bytes,
+        stats.segments.iter().map(|s| s.live_bytes).sum::<u64>()
+    );
+    assert_eq!(
+        stats.dead_bytes,
+        stats.segments.iter().map(|s| s.dead_bytes).sum::<u64>()
     );
+    assert!(
+        stats.segments.windows(2).all(|w| w[0].offset < w[1].offset),
+        "{:?}",
+        stats
+    );
+    assert!(
+        stats
+            .segments
+            .iter()
+            .any(|s| s.state == sled::SegmentState::Active),
+        "{:?}",
+        stats
+    );
+    assert!(stats.space_amplification > 0., "{:?}", stats);
+    assert!(stats.write_amplification >= 1., "{:?}", stats);
 }
 
 #[test]
-fn tree_bug_42() {
-    // postmortem: during refactoring, accidentally
-    // messed up the index selection for merge destinations.
-    for _ in 0..100 {
-        prop_tree_matches_btreemap(
-            vec![
-                Merge(Key(vec![]), 112),
-                Set(Key(vec![110; 1]), 153),
-                Set(Key(vec![15; 1]), 100),
-                Del(Key(vec![110; 1])),
-                GetLt(Key(vec![148; 1])),
-            ],
-            false,
-            false,
-            0,
-            0,
+fn compact() {
+    common::setup_logger();
+
+    let db = Config::new()
+        .temporary(true)
+        .segment_size(256 * 1024)
+        .open()
+        .unwrap();
+
+    for i in 0..1000_u32 {
+        db.insert(i.to_be_bytes(), vec![0; 1024]).unwrap();
+    }
+    for i in 0..1000_u32 {
+        db.insert(i.to_be_bytes(), vec![1; 1024]).unwrap();
+    }
+    db.flush().unwrap();
+
+    let before = db.space_stats().unwrap();
+    let inactive = before
+        .segments
+        .iter()
+        .find(|s| s.state == sled::SegmentState::Inactive)
+        .copied()
+        .unwrap();
+
+    let stats = db.compact_segment(inactive.offset).unwrap();
+    assert_eq!(stats.segments_drained, 1, "{:?}", stats);
+    assert!(stats.pages_relocated > 0, "{:?}", stats);
+    assert!(stats.bytes_relocated > 0, "{:?}", stats);
+
+    let stats = db.compact().unwrap();
+    assert!(stats.segments_drained > 0, "{:?}", stats);
+
+    // every segment that was inactive is now draining until
+    // the segments its pages moved to fill up, or free
+    let after = db.space_stats().unwrap();
+    for segment in &before.segments {
+        if segment.state == sled::SegmentState::Inactive {
+            let now = after.segments.iter().find(|s| s.lsn == segment.lsn);
+            assert!(
+                now.map_or(true, |s| s.state == sled::SegmentState::Draining),
+                "{:?} {:?}",
+                before,
+                after
+            );
+        }
+    }
+
+    for i in 0..1000_u32 {
+        assert_eq!(db.get(i.to_be_bytes()).unwrap().unwrap(), vec![1; 1024]);
+    }
+}
+
+#[test]
+fn leaf_filters() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("leaf_filters");
+    let path = dir.path();
+
+    let config = || {
+        Config::new()
+            .path(path)
+            .cache_capacity(256)
+            .max_leaf_items(16)
+            .min_leaf_items(4)
+            .leaf_filter_bits_per_key(10)
+    };
+
+    let check = |db: &sled::Db, present: &dyn Fn(u32) -> bool| {
+        for i in 0..2000_u32 {
+            let value = db.get(i.to_be_bytes()).unwrap();
+            if present(i) {
+                assert_eq!(value.unwrap(), i.to_le_bytes());
+            } else {
+                assert_eq!(value, None, "read a value for key {}", i);
+            }
+        }
+    };
+
+    {
+        let db = config().open().unwrap();
+        for i in (0..2000_u32).step_by(2) {
+            db.insert(i.to_be_bytes(), &i.to_le_bytes()).unwrap();
+        }
+        check(&db, &|i| i % 2 == 0);
+        assert!(db.memory_usage().leaf_filters > 0);
+    }
+
+    // the filters are written on shutdown and read back
+    let db = config().open().unwrap();
+    assert!(db.memory_usage().leaf_filters > 0);
+    check(&db, &|i| i % 2 == 0);
+
+    // writing to a leaf invalidates its filter
+    for i in (1..2000_u32).step_by(10) {
+        db.insert(i.to_be_bytes(), &i.to_le_bytes()).unwrap();
+    }
+    for i in (0..2000_u32).step_by(10) {
+        db.remove(i.to_be_bytes()).unwrap();
+    }
+    let present = |i: u32| (i % 2 == 0 && i % 10 != 0) || i % 10 == 1;
+    check(&db, &present);
+
+    drop(db);
+    let db = config().open().unwrap();
+    check(&db, &present);
+
+    assert!(db.verify().unwrap().is_ok());
+    drop(db);
+}
+
+#[test]
+fn warm_cache() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("warm_cache");
+    let path = dir.path();
+
+    let config = |pages| Config::new().path(path).warm_cache_pages(pages);
+
+    {
+        let db = config(10_000).open().unwrap();
+        for i in 0..2000_u32 {
+            db.insert(i.to_be_bytes(), vec![1; 64]).unwrap();
+        }
+    }
+
+    // without the option, nothing is warmed up on open, and
+    // the pages recorded on the last shutdown are left alone
+    let db = config(0).open().unwrap();
+    let cold = db.memory_usage().resident_pages;
+    assert!(db.warm_cache().unwrap() > 0);
+    assert!(db.memory_usage().resident_pages > cold);
+    assert_eq!(db.warm_cache().unwrap(), 0);
+    drop(db);
+
+    // with it, the pages are read back in the background
+    let db = config(10_000).open().unwrap();
+    let _ = db.warm_cache().unwrap();
+    assert_eq!(db.warm_cache().unwrap(), 0);
+    for i in 0..2000_u32 {
+        assert_eq!(db.get(i.to_be_bytes()).unwrap().unwrap(), vec![1; 64]);
+    }
+
+    assert!(db.verify().unwrap().is_ok());
+    drop(db);
+}
+
+#[test]
+fn throttled_maintenance() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("throttled_maintenance");
+    let path = dir.path();
+
+    let config = || {
+        Config::new()
+            .path(path)
+            .flush_every_ms(Some(1))
+            .background_bytes_per_sec(Some(1024))
+            .background_ops_per_sec(Some(10))
+    };
+
+    {
+        let db = config().open().unwrap();
+        for round in 0..5_u8 {
+            for i in 0..1000_u32 {
+                db.insert(i.to_be_bytes(), vec![round; 64]).unwrap();
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        for i in 0..1000_u32 {
+            assert_eq!(db.get(i.to_be_bytes()).unwrap().unwrap(), vec![4; 64]);
+        }
+    }
+
+    // flushing on shutdown is never throttled
+    let db = config().open().unwrap();
+    for i in 0..1000_u32 {
+        assert_eq!(db.get(i.to_be_bytes()).unwrap().unwrap(), vec![4; 64]);
+    }
+    assert!(db.verify().unwrap().is_ok());
+    drop(db);
+
+    assert!(config().background_ops_per_sec(Some(0)).open().is_err());
+}
+
+#[test]
+fn io_stats() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("io_stats");
+    let path = dir.path();
+
+    let config = || Config::new().path(path).cache_capacity(256);
+
+    {
+        let db = config().open().unwrap();
+        // overwritten, so that compaction has segments to drain
+        for value in 0..2_u8 {
+            for i in 0..1000_u32 {
+                db.insert(i.to_be_bytes(), vec![value; 1024]).unwrap();
+            }
+        }
+        db.flush().unwrap();
+
+        let before = db.io_stats();
+        assert!(before.log_bytes_written >= 2000 * 1024);
+        assert!(before.fsyncs > 0);
+
+        let compaction = db.compact().unwrap();
+        let after = db.io_stats();
+        assert!(compaction.pages_relocated > 0, "{:?}", compaction);
+        assert!(
+            after.gc_relocated_pages - before.gc_relocated_pages
+                >= compaction.pages_relocated as u64
+        );
+        assert!(
+            after.gc_relocated_bytes - before.gc_relocated_bytes
+                >= compaction.bytes_relocated
         );
+        assert!(
+            after.log_bytes_written - before.log_bytes_written
+                >= compaction.bytes_relocated
+        );
+    }
+
+    // nothing is resident after a restart
+    let db = config().open().unwrap();
+    let before = db.io_stats();
+    for i in 0..1000_u32 {
+        assert_eq!(db.get(i.to_be_bytes()).unwrap().unwrap(), vec![1; 1024]);
+    }
+    let after = db.io_stats();
+    assert!(after.page_ins > before.page_ins);
+    assert!(after.page_in_bytes > before.page_in_bytes);
+
+    drop(db);
+}
+
+#[test]
+fn get_or_insert_with_and_remove_if() {
+    common::setup_logger();
+
+    const N_THREADS: usize = 8;
+    const N_KEYS: u8 = 100;
+
+    let db = Config::new().temporary(true).open().unwrap();
+
+    // every thread races to insert its own id for each key,
+    // and all of them must see the one that won
+    let barrier = Arc::new(Barrier::new(N_THREADS));
+    let threads: Vec<_> = (0..N_THREADS as u8)
+        .map(|t| {
+            let db = db.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                (0..N_KEYS)
+                    .map(|k| db.get_or_insert_with([k], || vec![t]).unwrap())
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    let seen: Vec<Vec<IVec>> =
+        threads.into_iter().map(|t| t.join().unwrap()).collect();
+    for k in 0..N_KEYS {
+        let winner = db.get([k]).unwrap().unwrap();
+        assert!(seen.iter().all(|s| s[k as usize] == winner));
+    }
+
+    // only one of the threads removes each key, and only
+    // those that it checks the value of
+    let barrier = Arc::new(Barrier::new(N_THREADS));
+    let threads: Vec<_> = (0..N_THREADS)
+        .map(|_| {
+            let db = db.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                (0..N_KEYS)
+                    .filter(|k| {
+                        db.remove_if([*k], |_| k % 2 == 0).unwrap().is_some()
+                    })
+                    .count()
+            })
+        })
+        .collect();
+    let removed: usize = threads.into_iter().map(|t| t.join().unwrap()).sum();
+    assert_eq!(removed, N_KEYS as usize / 2);
+    assert_eq!(db.len(), N_KEYS as usize / 2);
+
+    assert_eq!(db.remove_if([1], |v| v == [N_THREADS as u8]).unwrap(), None);
+    assert!(db.contains_key([1]).unwrap());
+}
+
+#[test]
+fn tree_collection_traits() {
+    common::setup_logger();
+
+    let mut tree: Tree =
+        (0..10_u32).map(|i| (i.to_be_bytes().to_vec(), vec![0])).collect();
+    assert_eq!(tree.len(), 10);
+
+    // spans several batches, and overwrites what's there
+    tree.extend((5..3000_u32).map(|i| (i.to_be_bytes().to_vec(), vec![1])));
+    assert_eq!(tree.len(), 3000);
+    for (i, kv) in (&tree).into_iter().enumerate() {
+        let (k, v) = kv.unwrap();
+        assert_eq!(k, (i as u32).to_be_bytes().to_vec());
+        assert_eq!(v, vec![if i < 5 { 0 } else { 1 }]);
+    }
+    assert_eq!(tree.clone().into_iter().count(), 3000);
+    assert!(tree.verify().unwrap().is_ok());
+
+    let mut db: Db = vec![(b"a", b"1"), (b"b", b"2")].into_iter().collect();
+    db.extend(vec![(b"c", b"3")]);
+    let pairs: Vec<(IVec, IVec)> =
+        (&db).into_iter().map(|kv| kv.unwrap()).collect();
+    assert_eq!(
+        pairs,
+        vec![
+            (IVec::from(b"a"), IVec::from(b"1")),
+            (IVec::from(b"b"), IVec::from(b"2")),
+            (IVec::from(b"c"), IVec::from(b"3")),
+        ]
+    );
+    assert_eq!(db.into_iter().count(), 3);
+}
+
+#[test]
+fn generate_id_across_restarts() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("generate_id_across_restarts");
+    let path = dir.path();
+    let config = || {
+        Config::new().path(path).idgen_persist_interval(16).flush_every_ms(None)
+    };
+
+    let mut last = None;
+    for _ in 0..3 {
+        let db = config().open().unwrap();
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    (0..1000)
+                        .map(|_| db.generate_id().unwrap())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let mut ids: Vec<u64> =
+            threads.into_iter().flat_map(|t| t.join().unwrap()).collect();
+
+        // each thread sees its ids increase, no id is given
+        // out twice, and ids keep increasing after a restart
+        for thread_ids in ids.chunks(1000) {
+            assert!(thread_ids.windows(2).all(|w| w[0] < w[1]));
+        }
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), 4000);
+        if let Some(last) = last {
+            assert!(ids[0] > last);
+        }
+        last = ids.last().cloned();
+    }
+
+}
+
+#[test]
+fn int_key_ranges() {
+    common::setup_logger();
+
+    let db = Config::new().temporary(true).open().unwrap();
+    for i in -1000..1000_i64 {
+        db.insert(I64Key::new(i), vec![]).unwrap();
+    }
+
+    let read = |k: Result<IVec>| I64Key::from_bytes(&k.unwrap()).unwrap().get();
+    let ints: Vec<i64> =
+        db.range(I64Key::new(-10)..I64Key::new(10)).keys().map(read).collect();
+    assert_eq!(ints, (-10..10).collect::<Vec<_>>());
+    let ints: Vec<i64> =
+        db.range(..=I64Key::new(-998)).keys().map(read).collect();
+    assert_eq!(ints, vec![-1000, -999, -998]);
+
+    // (shard, name, seq) tuples, scanned by shard and name
+    let tuples = db.open_tree("tuples").unwrap();
+    for shard in 0..4 {
+        for name in &["a", "a\0", "ab"] {
+            for seq in &[u64::max_value(), 0, 1 << 32] {
+                let key =
+                    KeyBuilder::new().u64(shard).bytes(name).u64(*seq).build();
+                tuples.insert(key, vec![]).unwrap();
+            }
+        }
+    }
+    let prefix = KeyBuilder::new().u64(2).bytes("a").build();
+    let seqs: Vec<u64> = tuples
+        .scan_prefix(prefix)
+        .keys()
+        .map(|k| {
+            let k = k.unwrap();
+            let mut reader = KeyReader::new(&k);
+            assert_eq!(reader.u64(), Some(2));
+            assert_eq!(reader.bytes(), Some(b"a".to_vec()));
+            reader.u64().unwrap()
+        })
+        .collect();
+    assert_eq!(seqs, vec![0, 1 << 32, u64::max_value()]);
+
+    let names: Vec<Vec<u8>> = tuples
+        .scan_prefix(KeyBuilder::new().u64(3).build())
+        .keys()
+        .map(|k| {
+            let k = k.unwrap();
+            let mut reader = KeyReader::new(&k);
+            let _ = reader.u64().unwrap();
+            reader.bytes().unwrap()
+        })
+        .collect();
+    let mut expected: Vec<Vec<u8>> = vec![];
+    for name in &["a", "a\0", "ab"] {
+        expected.extend(vec![name.as_bytes().to_vec(); 3]);
+    }
+    assert_eq!(names, expected);
+}
+
+#[test]
+fn packed_values() {
+    common::setup_logger();
+
+    let dir = common::TestDir::new("packed_values");
+    let path = dir.path();
+
+    let value = |i: u32| vec![i as u8; (i % 300) as usize];
+
+    for &threshold in &[0, 128, 300] {
+        let config = || {
+            Config::new()
+                .path(path)
+                .cache_capacity(256)
+                .max_leaf_items(16)
+                .min_leaf_items(4)
+                .overflow_threshold(256)
+                .inline_value_threshold(threshold)
+        };
+
+        let db = config().open().unwrap();
+        for i in 0..1000_u32 {
+            db.insert(i.to_be_bytes(), value(i)).unwrap();
+        }
+        for i in (0..1000_u32).step_by(3) {
+            db.remove(i.to_be_bytes()).unwrap();
+        }
+
+        let check = |db: &sled::Db| {
+            for i in 0..1000_u32 {
+                let read = db.get(i.to_be_bytes()).unwrap();
+                if i % 3 == 0 {
+                    assert_eq!(read, None);
+                } else {
+                    assert_eq!(read.unwrap(), value(i));
+                }
+            }
+            let values: Vec<_> = db.iter().values().map(Result::unwrap).collect();
+            assert_eq!(values.len(), 666);
+        };
+        check(&db);
+
+        // writing to a value that was read leaves its leaf
+        // and the values packed next to it alone
+        let mut read = db.get(100_u32.to_be_bytes()).unwrap().unwrap();
+        for byte in read.iter_mut() {
+            *byte = 0;
+        }
+        check(&db);
+
+        drop(db);
+        let db = config().open().unwrap();
+        check(&db);
+        assert!(db.verify().unwrap().is_ok());
+        drop(db);
+        std::fs::remove_dir_all(path).unwrap();
     }
 }
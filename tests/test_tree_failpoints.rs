@@ -2,12 +2,11 @@
 mod common;
 
 use std::collections::{BTreeMap, HashSet};
-use std::sync::Mutex;
 
 use quickcheck::{Arbitrary, Gen, QuickCheck, StdGen};
 use rand::{seq::SliceRandom, Rng};
 
-use sled::*;
+use sled::{fail::FaultPlan, *};
 
 #[derive(Debug, Clone)]
 enum Op {
@@ -43,6 +42,7 @@ impl Arbitrary for Op {
             "write_blob write crc",
             "write_blob write kind_byte",
             "write_blob write buf",
+            "read page",
         ];
 
         if g.gen_bool(1. / 30.) {
@@ -81,10 +81,6 @@ fn v(b: &[u8]) -> u16 {
     (u16::from(b[0]) << 8) + u16::from(b[1])
 }
 
-fn tear_down_failpoints() {
-    sled::fail::reset();
-}
-
 #[derive(Debug)]
 struct ReferenceEntry {
     values: Vec<Option<u16>>,
@@ -92,20 +88,10 @@ struct ReferenceEntry {
 }
 
 fn prop_tree_crashes_nicely(ops: Vec<Op>, flusher: bool) -> bool {
-    // forces quickcheck to run one thread at a time
-    static M: Lazy<Mutex<()>, fn() -> Mutex<()>> = Lazy::new(|| Mutex::new(()));
-
-    let _lock = M.lock().expect("our test lock should not be poisoned");
-
-    // clear all failpoints that may be left over from the last run
-    tear_down_failpoints();
-
     let res = std::panic::catch_unwind(|| {
         run_tree_crashes_nicely(ops.clone(), flusher)
     });
 
-    tear_down_failpoints();
-
     match res {
         Err(e) => {
             println!(
@@ -128,8 +114,11 @@ fn run_tree_crashes_nicely(ops: Vec<Op>, flusher: bool) -> bool {
 
     let segment_size = 256;
 
+    let fault_plan = FaultPlan::new(0);
+
     let config = Config::new()
         .temporary(true)
+        .fault_plan(fault_plan.clone())
         .flush_every_ms(if flusher { Some(1) } else { None })
         .cache_capacity(256)
         .idgen_persist_interval(1)
@@ -235,7 +224,7 @@ fn run_tree_crashes_nicely(ops: Vec<Op>, flusher: bool) -> bool {
             match $e {
                 Ok(thing) => thing,
                 Err(Error::FailPoint) => {
-                    tear_down_failpoints();
+                    fault_plan.reset();
                     crash_counter += 1;
                     restart!();
                     continue;
@@ -333,7 +322,7 @@ fn run_tree_crashes_nicely(ops: Vec<Op>, flusher: bool) -> bool {
             }
             FailPoint(fp) => {
                 fail_points.insert(fp);
-                sled::fail::set(&*fp);
+                fault_plan.set(fp);
             }
         }
     }
@@ -1363,3 +1352,56 @@ fn failpoints_bug_30() {
         false,
     ));
 }
+
+#[test]
+fn fault_plan_fails_at_nth_hit() {
+    let fault_plan = FaultPlan::new(0);
+    let db = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .fault_plan(fault_plan.clone())
+        .open()
+        .unwrap();
+
+    fault_plan.set_at("buffer write", 3);
+
+    db.insert(b"a", b"1").unwrap();
+    db.flush().unwrap();
+    db.insert(b"b", b"2").unwrap();
+    db.flush().unwrap();
+    db.insert(b"c", b"3").unwrap();
+    assert_eq!(db.flush(), Err(Error::FailPoint));
+}
+
+#[test]
+fn fault_plan_is_deterministic() {
+    // the number of successful flushes before the first
+    // injected failure
+    fn flushes_before_failure(seed: u64) -> usize {
+        let fault_plan = FaultPlan::new(seed);
+        let db = Config::new()
+            .temporary(true)
+            .flush_every_ms(None)
+            .fault_plan(fault_plan.clone())
+            .open()
+            .unwrap();
+
+        fault_plan.set_probability("buffer write", 0.2);
+
+        for i in 0..1000_u32 {
+            db.insert(i.to_be_bytes(), vec![]).unwrap();
+            if db.flush().is_err() {
+                return i as usize;
+            }
+        }
+        panic!("the failpoint never failed");
+    }
+
+    // databases with their own plans can run concurrently
+    let threads: Vec<_> = (0..4)
+        .map(|_| std::thread::spawn(|| flushes_before_failure(7)))
+        .collect();
+    let results: Vec<usize> =
+        threads.into_iter().map(|t| t.join().unwrap()).collect();
+    assert!(results.iter().all(|r| *r == results[0]), "{:?}", results);
+}